@@ -23,7 +23,7 @@ async fn create_hypercore(page_size: usize) -> Result<Hypercore, HypercoreError>
     use hypercore::StorageTraits;
 
     let storage = Storage::open(
-        |_| {
+        move |_| {
             Box::pin(async move {
                 Ok(Box::new(RandomAccessMemory::new(page_size)) as Box<dyn StorageTraits + Send>)
             })
@@ -42,7 +42,7 @@ async fn create_hypercore(page_size: usize) -> Result<Hypercore, HypercoreError>
     use hypercore::StorageTraits;
 
     let storage = Storage::open(
-        |_| {
+        move |_| {
             Box::pin(async move {
                 Ok(Box::new(RandomAccessMemory::new(page_size)) as Box<dyn StorageTraits + Send>)
             })
@@ -0,0 +1,80 @@
+use async_std::net::{TcpListener, TcpStream};
+use hypercore::replication::Replicator;
+use hypercore::{
+    discovery_key, generate_signing_key, HypercoreBuilder, HypercoreError, KeyEncoding,
+    PartialKeypair, Storage, VerifyingKey,
+};
+
+/// Example on how to replicate a hypercore between two processes over TCP. Start the origin,
+/// which appends a few values and prints the public key of its core:
+///
+///   cargo run --example replicate_tcp -- serve 127.0.0.1:4000
+///
+/// then replicate it from another terminal:
+///
+///   cargo run --example replicate_tcp -- connect 127.0.0.1:4000 <public key>
+#[async_std::main]
+async fn main() -> Result<(), HypercoreError> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args[..] {
+        ["serve", address] => serve(address).await,
+        ["connect", address, key] => connect(address, key).await,
+        _ => {
+            eprintln!("Usage: replicate_tcp serve <address> | connect <address> <public key>");
+            Ok(())
+        }
+    }
+}
+
+/// Create a core, append to it and serve it to every peer that connects.
+async fn serve(address: &str) -> Result<(), HypercoreError> {
+    let mut hypercore = HypercoreBuilder::new(Storage::new_memory().await?)
+        .build()
+        .await?;
+    let batch: &[&[u8]] = &[b"Hello, ", b"from ", b"replicated ", b"hypercore!"];
+    hypercore.append_batch(batch).await?;
+    println!("Serving {}", hypercore.key_pair().public.to_hex());
+
+    let mut replicator = Replicator::new();
+    replicator.add_core(hypercore)?;
+    // The static key identifies this peer in the handshake, it is not the key of the core
+    let static_key = generate_signing_key();
+    let listener = TcpListener::bind(address).await?;
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        match replicator.serve(stream, &static_key).await {
+            Ok(()) => println!("Replicated with {peer}"),
+            Err(err) => eprintln!("Replication with {peer} failed: {err}"),
+        }
+    }
+}
+
+/// Create an empty core with the public key of the served one, and replicate it.
+async fn connect(address: &str, key: &str) -> Result<(), HypercoreError> {
+    let public_key = VerifyingKey::parse_key(key)?;
+    let hypercore = HypercoreBuilder::new(Storage::new_memory().await?)
+        .key_pair(PartialKeypair {
+            public: public_key,
+            secret: None,
+        })
+        .build()
+        .await?;
+    let mut replicator = Replicator::new();
+    replicator.add_core(hypercore)?;
+
+    let stream = TcpStream::connect(address).await?;
+    replicator.connect(stream, &generate_signing_key()).await?;
+
+    // Print the replicated values, converting binary back to string
+    let hypercore = replicator
+        .core_mut(&discovery_key(&public_key))
+        .expect("Core was added");
+    let mut values = String::new();
+    for index in 0..hypercore.info().length {
+        let value = hypercore.get(index).await?.expect("Value was replicated");
+        values.push_str(&String::from_utf8_lossy(&value));
+    }
+    println!("{values}"); // prints "Hello, from replicated hypercore!"
+    Ok(())
+}
@@ -0,0 +1,154 @@
+//! Restricted ranges of blocks, which peers serving the core only send to the peers listed as
+//! their readers, see [`crate::Hypercore::restrict`]. Mixes public and private blocks in one
+//! core: the tree and its signatures stay public, only the values of restricted blocks are
+//! withheld.
+//!
+//! A restriction is stored in the user data under `acl/<start>` as the end of the range, a
+//! little-endian u64, followed by the 32 byte static public keys of its readers. User data is
+//! local, so every serving peer keeps its own list. A malformed entry restricts every block
+//! from its start to every peer, so a corrupt list fails closed.
+use std::ops::Range;
+
+use crate::HypercoreError;
+
+/// Prefix of the user data keys of restrictions.
+pub(crate) const ACL_KEY_PREFIX: &str = "acl/";
+
+/// Blocks only served to the given readers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Restriction {
+    /// The restricted blocks
+    pub range: Range<u64>,
+    /// Static public keys of the peers the blocks are served to
+    pub readers: Vec<[u8; 32]>,
+}
+
+impl Restriction {
+    /// Can the peer with the given static public key read the block.
+    pub fn allows(&self, index: u64, peer: &[u8; 32]) -> bool {
+        !self.range.contains(&index) || self.readers.contains(peer)
+    }
+
+    /// User data key and value of the restriction.
+    pub(crate) fn encode(&self) -> (String, Vec<u8>) {
+        let mut value = Vec::with_capacity(8 + self.readers.len() * 32);
+        value.extend(self.range.end.to_le_bytes());
+        for reader in &self.readers {
+            value.extend(reader);
+        }
+        (format!("{ACL_KEY_PREFIX}{}", self.range.start), value)
+    }
+
+    /// Restriction of a user data entry, `None` if the key isn't one of a restriction.
+    pub(crate) fn decode(key: &str, value: &[u8]) -> Option<Self> {
+        let start = key.strip_prefix(ACL_KEY_PREFIX)?;
+        Some(match (start.parse(), decode_value(value)) {
+            (Ok(start), Ok((end, readers))) => Restriction {
+                range: start..end,
+                readers,
+            },
+            (start, _) => {
+                tracing::warn!("Malformed restriction {key}, restricting to no readers");
+                Restriction {
+                    range: start.unwrap_or(0)..u64::MAX,
+                    readers: vec![],
+                }
+            }
+        })
+    }
+}
+
+/// The parts of the range readable by the peer under the restrictions, split only at the bounds
+/// of the restrictions inside it.
+pub(crate) fn readable_ranges(
+    restrictions: &[Restriction],
+    range: Range<u64>,
+    peer: &[u8; 32],
+) -> Vec<Range<u64>> {
+    let mut bounds = vec![range.start, range.end];
+    for restriction in restrictions {
+        for bound in [restriction.range.start, restriction.range.end] {
+            if range.start < bound && bound < range.end {
+                bounds.push(bound);
+            }
+        }
+    }
+    bounds.sort_unstable();
+    bounds.dedup();
+    let mut ranges: Vec<Range<u64>> = vec![];
+    for part in bounds.windows(2) {
+        // No bound inside the part, so its first block is readable like all of it
+        if !restrictions
+            .iter()
+            .all(|restriction| restriction.allows(part[0], peer))
+        {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(last) if last.end == part[0] => last.end = part[1],
+            _ => ranges.push(part[0]..part[1]),
+        }
+    }
+    ranges
+}
+
+fn decode_value(value: &[u8]) -> Result<(u64, Vec<[u8; 32]>), HypercoreError> {
+    let invalid = || HypercoreError::InvalidOperation {
+        context: "Invalid restriction".to_string(),
+    };
+    if value.len() < 8 || !(value.len() - 8).is_multiple_of(32) {
+        return Err(invalid());
+    }
+    let end = u64::from_le_bytes(value[..8].try_into().map_err(|_| invalid())?);
+    let readers = value[8..]
+        .chunks_exact(32)
+        .map(|reader| reader.try_into().expect("Should be 32 bytes"))
+        .collect();
+    Ok((end, readers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::create_hypercore_with_data;
+
+    #[async_std::test]
+    async fn restrictions_fail_closed() -> Result<(), HypercoreError> {
+        let mut core = create_hypercore_with_data(10).await?;
+        let (reader, other) = ([1; 32], [2; 32]);
+        assert!(core.restrict(3..3, &[reader]).await.is_err());
+        core.restrict(2..5, &[reader]).await?;
+        core.restrict(4..6, &[reader, other]).await?;
+        assert_eq!(core.restrictions().len(), 2);
+        assert!(core.is_readable_by(1, &other));
+        assert!(!core.is_readable_by(2, &other));
+        assert!(core.is_readable_by(4, &reader));
+        assert!(!core.is_readable_by(4, &other));
+        assert!(core.is_readable_by(5, &other));
+        assert_eq!(core.readable_ranges(0..10, &other), [0..2, 5..10]);
+        assert_eq!(core.readable_ranges(0..10, &reader), vec![0..10]);
+        assert_eq!(core.readable_ranges(3..5, &other), []);
+
+        // Replacing and removing restrictions
+        core.restrict(2..3, &[reader]).await?;
+        assert!(core.is_readable_by(4, &other));
+        assert!(core.unrestrict(2).await?);
+        assert!(!core.unrestrict(2).await?);
+        assert_eq!(
+            core.restrictions(),
+            [Restriction {
+                range: 4..6,
+                readers: vec![reader, other],
+            }]
+        );
+
+        // Malformed entries restrict to nobody
+        core.set_user_data("acl/7", Some(b"oops")).await?;
+        assert!(!core.is_readable_by(8, &reader));
+        assert_eq!(core.readable_ranges(0..10, &reader), vec![0..7]);
+        assert!(core.is_readable_by(6, &reader));
+        core.set_user_data("acl/oops", Some(&[0; 8])).await?;
+        assert!(!core.is_readable_by(0, &reader));
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+use compact_encoding::{CompactEncoding, State};
+use futures::future::Either;
+use intmap::IntMap;
+use std::collections::BTreeMap;
+
+use crate::common::{HypercoreError, Store, StoreInfo, StoreInfoInstruction, StoreInfoType};
+
+/// Name of the auxiliary store of the annotations, see [`crate::Storage::aux`].
+pub(crate) const ANNOTATION_STORE: &str = "annotations";
+
+/// Annotation store. Local-only metadata of blocks, such as labels or moderation flags, kept
+/// outside of the log so that annotating never changes the signed data.
+///
+/// Stored as a log of records, each prefixed with its byte length: block index, key and an
+/// optional value, where a missing value removes the annotation. Everything is loaded to
+/// memory on open. A record torn by a crash while writing is ignored, and overwritten by the
+/// next record.
+#[derive(Debug)]
+pub(crate) struct AnnotationStore {
+    annotations: IntMap<BTreeMap<String, Vec<u8>>>,
+    length: u64,
+    /// Torn bytes at the end of the store that need to be truncated before the next write
+    torn: bool,
+}
+
+impl AnnotationStore {
+    pub(crate) fn open(
+        info: Option<StoreInfo>,
+    ) -> Result<Either<StoreInfoInstruction, Self>, HypercoreError> {
+        match info {
+            None => Ok(Either::Left(StoreInfoInstruction::new_size(
+                Store::aux(ANNOTATION_STORE),
+                0,
+            ))),
+            Some(info) => {
+                if info.info_type == StoreInfoType::Size {
+                    let length = info.length.unwrap();
+                    if length == 0 {
+                        return Ok(Either::Right(Self {
+                            annotations: IntMap::new(),
+                            length: 0,
+                            torn: false,
+                        }));
+                    }
+                    return Ok(Either::Left(StoreInfoInstruction::new_content(
+                        Store::aux(ANNOTATION_STORE),
+                        0,
+                        length,
+                    )));
+                }
+                let data = info.data.expect("Did not receive annotation store content");
+                let mut store = Self {
+                    annotations: IntMap::new(),
+                    length: 0,
+                    torn: false,
+                };
+                let mut state = State::from_buffer(&data);
+                while state.start() < state.end() {
+                    let Ok(record_length) = state.decode_u64_var(&data) else {
+                        break;
+                    };
+                    let record_end = state.start() + record_length as usize;
+                    if record_end > data.len() {
+                        break;
+                    }
+                    let (index, key, value) = decode_record(&data[state.start()..record_end])?;
+                    store.apply(index, key, value);
+                    state.set_start(record_end)?;
+                    store.length = record_end as u64;
+                }
+                store.torn = store.length < data.len() as u64;
+                Ok(Either::Right(store))
+            }
+        }
+    }
+
+    /// Annotations of the block at `index`.
+    pub(crate) fn get(&self, index: u64) -> Option<&BTreeMap<String, Vec<u8>>> {
+        self.annotations.get(index)
+    }
+
+    /// Sets the annotation `key` of the block at `index`, or removes it if `value` is `None`.
+    pub(crate) fn put(
+        &mut self,
+        index: u64,
+        key: &str,
+        value: Option<&[u8]>,
+    ) -> Result<Vec<StoreInfo>, HypercoreError> {
+        let record = encode_record(index, key, value)?;
+        let mut state = State::new();
+        state.preencode(&(record.len() as u64))?;
+        state.add_end(record.len())?;
+        let mut buffer = state.create_buffer();
+        state.encode(&(record.len() as u64), &mut buffer)?;
+        state.set_slice_to_buffer(&record, &mut buffer)?;
+
+        let mut infos = Vec::with_capacity(2);
+        if self.torn {
+            infos.push(StoreInfo::new_truncate(
+                Store::aux(ANNOTATION_STORE),
+                self.length,
+            ));
+            self.torn = false;
+        }
+        infos.push(StoreInfo::new_content(
+            Store::aux(ANNOTATION_STORE),
+            self.length,
+            &buffer,
+        ));
+        self.length += buffer.len() as u64;
+        self.apply(index, key.to_string(), value.map(|value| value.to_vec()));
+        Ok(infos)
+    }
+
+    /// Removes the annotations of the blocks from `length` on, as they were truncated away.
+    pub(crate) fn truncate(&mut self, length: u64) -> Result<Vec<StoreInfo>, HypercoreError> {
+        let removed: Vec<(u64, String)> = self
+            .annotations
+            .iter()
+            .filter(|(index, _)| **index >= length)
+            .flat_map(|(index, annotations)| annotations.keys().map(|key| (*index, key.clone())))
+            .collect();
+        let mut infos = vec![];
+        for (index, key) in removed {
+            infos.extend(self.put(index, &key, None)?);
+        }
+        Ok(infos)
+    }
+
+    fn apply(&mut self, index: u64, key: String, value: Option<Vec<u8>>) {
+        match value {
+            Some(value) => {
+                if let Some(annotations) = self.annotations.get_mut(index) {
+                    annotations.insert(key, value);
+                } else {
+                    self.annotations
+                        .insert(index, BTreeMap::from([(key, value)]));
+                }
+            }
+            None => {
+                if let Some(annotations) = self.annotations.get_mut(index) {
+                    annotations.remove(&key);
+                    if annotations.is_empty() {
+                        self.annotations.remove(index);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn encode_record(index: u64, key: &str, value: Option<&[u8]>) -> Result<Box<[u8]>, HypercoreError> {
+    let mut state = State::new();
+    state.preencode(&index)?;
+    state.preencode_str(key)?;
+    state.add_end(1)?; // Flags
+    if let Some(value) = value {
+        state.preencode_buffer(value)?;
+    }
+    let mut buffer = state.create_buffer();
+    state.encode(&index, &mut buffer)?;
+    state.encode_str(key, &mut buffer)?;
+    state.set_byte_to_buffer(value.is_some() as u8, &mut buffer)?;
+    if let Some(value) = value {
+        state.encode_buffer(value, &mut buffer)?;
+    }
+    Ok(buffer)
+}
+
+fn decode_record(buffer: &[u8]) -> Result<(u64, String, Option<Vec<u8>>), HypercoreError> {
+    let mut state = State::from_buffer(buffer);
+    let index: u64 = state.decode(buffer)?;
+    let key: String = state.decode(buffer)?;
+    let flags = state.decode_u8(buffer)?;
+    let value: Option<Vec<u8>> = if flags & 1 != 0 {
+        Some(state.decode(buffer)?)
+    } else {
+        None
+    };
+    Ok((index, key, value))
+}
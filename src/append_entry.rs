@@ -0,0 +1,42 @@
+//! Appends shipped as they are, see [`AppendEntry`]. Catching up through tree upgrades takes a
+//! request and a proof of the new roots per append. A follower that is at most a few blocks
+//! behind can instead take the blocks with the writer's signature of the new length, hash the
+//! tree itself and check the signature, which for a hot feed is a single small message per
+//! append.
+use crate::encoding::{CompactEncoding, EncodingError, HypercoreState};
+
+/// Blocks appended to a core after a length, with the signature of the core at the length
+/// they bring it to, see [`crate::Hypercore::append_entry`] and
+/// [`crate::Hypercore::apply_append_entry`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppendEntry {
+    /// Fork of the core
+    pub fork: u64,
+    /// Length of the core before the blocks
+    pub start: u64,
+    /// The blocks as stored, i.e. encrypted for encrypted cores
+    pub values: Vec<Vec<u8>>,
+    /// Signature of the core at length `start + values.len()`
+    pub signature: Vec<u8>,
+}
+
+impl AppendEntry {
+    /// Length of the core after the blocks.
+    pub fn end(&self) -> u64 {
+        self.start + self.values.len() as u64
+    }
+
+    /// Encode the entry.
+    pub fn encode(&self) -> Result<Vec<u8>, EncodingError> {
+        let mut state = HypercoreState::new();
+        state.preencode(self)?;
+        let mut buffer = state.create_buffer();
+        state.encode(self, &mut buffer)?;
+        Ok(buffer.to_vec())
+    }
+
+    /// Decode an entry encoded with [`AppendEntry::encode`].
+    pub fn decode(buffer: &[u8]) -> Result<Self, EncodingError> {
+        HypercoreState::from_buffer(buffer).decode(buffer)
+    }
+}
@@ -0,0 +1,124 @@
+//! Queue funneling appends from many tasks to the single writer of a hypercore, so that
+//! producers don't each need to lock the hypercore.
+use futures::channel::{mpsc, oneshot};
+use futures::{SinkExt, StreamExt};
+
+use crate::{AppendOutcome, Hypercore, HypercoreError};
+
+type Request = (
+    Vec<Vec<u8>>,
+    oneshot::Sender<Result<AppendOutcome, HypercoreError>>,
+);
+
+/// Create an append queue holding at most `capacity` waiting batches. The [`Appender`] handles
+/// submit batches and the [`AppendQueue`] appends them to the hypercore, in the order they
+/// were submitted.
+pub fn append_queue(capacity: usize) -> (Appender, AppendQueue) {
+    let (sender, receiver) = mpsc::channel(capacity);
+    (Appender { sender }, AppendQueue { receiver })
+}
+
+/// Handle submitting appends to an [`AppendQueue`], cloned for every producer.
+#[derive(Debug, Clone)]
+pub struct Appender {
+    sender: mpsc::Sender<Request>,
+}
+
+impl Appender {
+    /// Append a data slice, see [`Appender::append_batch`].
+    pub async fn append(&self, data: &[u8]) -> Result<AppendOutcome, HypercoreError> {
+        self.append_batch(&[data]).await
+    }
+
+    /// Append a batch of data slices, waiting for room in the queue if it is full. Resolves
+    /// to the outcome of the append once the queue has run it: the batch got the indices
+    /// right before `length`.
+    pub async fn append_batch<A: AsRef<[u8]>, B: AsRef<[A]>>(
+        &self,
+        batch: B,
+    ) -> Result<AppendOutcome, HypercoreError> {
+        let batch = batch
+            .as_ref()
+            .iter()
+            .map(|data| data.as_ref().to_vec())
+            .collect();
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .clone()
+            .send((batch, sender))
+            .await
+            .map_err(|_| closed())?;
+        receiver.await.map_err(|_| closed())?
+    }
+}
+
+/// Receiving end of the append queue, run by the task owning the hypercore.
+#[derive(Debug)]
+pub struct AppendQueue {
+    receiver: mpsc::Receiver<Request>,
+}
+
+impl AppendQueue {
+    /// Append the submitted batches to the hypercore until all [`Appender`] handles are
+    /// dropped. A failed append fails only the batch that caused it.
+    pub async fn run(mut self, hypercore: &mut Hypercore) {
+        while let Some((batch, sender)) = self.receiver.next().await {
+            let _ = sender.send(hypercore.append_batch(&batch).await);
+        }
+    }
+}
+
+fn closed() -> HypercoreError {
+    HypercoreError::InvalidOperation {
+        context: "Append queue was dropped".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::create_hypercore_with_data;
+
+    #[async_std::test]
+    async fn append_queue_serializes_producers() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(0).await?;
+        let (appender, queue) = append_queue(2);
+        let producers: Vec<_> = (0..4)
+            .map(|producer| {
+                let appender = appender.clone();
+                async move {
+                    let mut outcomes = vec![];
+                    for i in 0..5 {
+                        let value = format!("{producer}:{i}");
+                        outcomes.push((value.clone(), appender.append(value.as_bytes()).await?));
+                    }
+                    Ok::<_, HypercoreError>(outcomes)
+                }
+            })
+            .collect();
+        drop(appender);
+
+        let (_, results) = futures::join!(
+            queue.run(&mut hypercore),
+            futures::future::join_all(producers)
+        );
+        let mut lengths = vec![];
+        for outcomes in results {
+            let mut previous = 0;
+            for (value, outcome) in outcomes? {
+                // Appends of one producer keep their order
+                assert!(outcome.length > previous);
+                previous = outcome.length;
+                assert_eq!(
+                    hypercore.get(outcome.length - 1).await?.unwrap(),
+                    value.into_bytes()
+                );
+                lengths.push(outcome.length);
+            }
+        }
+        lengths.sort();
+        assert_eq!(lengths, (1..=20).collect::<Vec<_>>());
+        assert_eq!(hypercore.info().length, 20);
+        Ok(())
+    }
+}
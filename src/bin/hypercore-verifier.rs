@@ -0,0 +1,7 @@
+//! Verifier co-process, see [`hypercore::verifier`]. Serves framed verification requests on
+//! stdin, writing the responses to stdout, until stdin is closed.
+use std::io;
+
+fn main() -> io::Result<()> {
+    hypercore::verifier::serve(io::stdin().lock(), io::stdout().lock())
+}
@@ -1,12 +1,11 @@
-use super::fixed::{FixedBitfield, FIXED_BITFIELD_BITS_LENGTH, FIXED_BITFIELD_LENGTH};
+use super::fixed::FixedBitfield;
+use super::BitfieldFormat;
 use crate::{
     common::{BitfieldUpdate, StoreInfo, StoreInfoInstruction, StoreInfoType},
     Store,
 };
 use futures::future::Either;
-use std::{cell::RefCell, convert::TryInto};
-
-const DYNAMIC_BITFIELD_PAGE_SIZE: usize = 32768;
+use std::convert::TryInto;
 
 /// Dynamic sized bitfield, uses a map of `FixedBitfield` elements.
 /// See:
@@ -14,13 +13,19 @@ const DYNAMIC_BITFIELD_PAGE_SIZE: usize = 32768;
 /// for reference.
 #[derive(Debug)]
 pub(crate) struct DynamicBitfield {
-    pages: intmap::IntMap<RefCell<FixedBitfield>>,
+    format: BitfieldFormat,
+    /// Number of bits in a page
+    page_bits: u64,
+    pages: intmap::IntMap<FixedBitfield>,
     biggest_page_index: u64,
     unflushed: Vec<u64>,
 }
 
 impl DynamicBitfield {
-    pub(crate) fn open(info: Option<StoreInfo>) -> Either<StoreInfoInstruction, Self> {
+    pub(crate) fn open(
+        format: BitfieldFormat,
+        info: Option<StoreInfo>,
+    ) -> Either<StoreInfoInstruction, Self> {
         match info {
             None => Either::Left(StoreInfoInstruction::new_size(Store::Bitfield, 0)),
             Some(info) => {
@@ -35,43 +40,40 @@ impl DynamicBitfield {
                     ));
                 }
                 let data = info.data.expect("Did not receive bitfield store content");
-                let resumed = data.len() >= 4;
+                let page_size = format.page_size();
+                let mut pages: intmap::IntMap<FixedBitfield> = intmap::IntMap::new();
                 let mut biggest_page_index = 0;
-                if resumed {
-                    let mut pages: intmap::IntMap<RefCell<FixedBitfield>> = intmap::IntMap::new();
-                    let mut data_index = 0;
-                    while data_index < data.len() {
-                        let parent_index: u64 = (data_index / FIXED_BITFIELD_LENGTH) as u64;
-                        pages.insert(
-                            parent_index,
-                            RefCell::new(FixedBitfield::from_data(data_index, &data)),
-                        );
-                        if parent_index > biggest_page_index {
-                            biggest_page_index = parent_index;
-                        }
-                        data_index += FIXED_BITFIELD_LENGTH;
-                    }
-                    Either::Right(Self {
-                        pages,
-                        unflushed: vec![],
-                        biggest_page_index,
-                    })
-                } else {
-                    Either::Right(Self {
-                        pages: intmap::IntMap::new(),
-                        unflushed: vec![],
-                        biggest_page_index,
-                    })
+                let mut data_index = 0;
+                while data_index + 4 <= data.len() {
+                    let page_index = (data_index / page_size) as u64;
+                    pages.insert(
+                        page_index,
+                        FixedBitfield::from_data(page_size, data_index, &data),
+                    );
+                    biggest_page_index = page_index;
+                    data_index += page_size;
                 }
+                Either::Right(Self {
+                    format,
+                    page_bits: format.page_bits(),
+                    pages,
+                    unflushed: vec![],
+                    biggest_page_index,
+                })
             }
         }
     }
 
+    /// Format of the bitfield store.
+    pub(crate) fn format(&self) -> BitfieldFormat {
+        self.format
+    }
+
     /// Flushes pending changes, returns info slices to write to storage.
     pub(crate) fn flush(&mut self) -> Box<[StoreInfo]> {
         let mut infos_to_flush: Vec<StoreInfo> = Vec::with_capacity(self.unflushed.len());
         for unflushed_id in &self.unflushed {
-            let mut p = self.pages.get_mut(*unflushed_id).unwrap().borrow_mut();
+            let p = self.pages.get_mut(*unflushed_id).unwrap();
             let data = p.to_bytes();
             infos_to_flush.push(StoreInfo::new_content(
                 Store::Bitfield,
@@ -85,25 +87,26 @@ impl DynamicBitfield {
     }
 
     pub(crate) fn get(&self, index: u64) -> bool {
-        let j = index & (DYNAMIC_BITFIELD_PAGE_SIZE as u64 - 1);
-        let i = (index - j) / DYNAMIC_BITFIELD_PAGE_SIZE as u64;
+        let j = index & (self.page_bits - 1);
+        let i = (index - j) / self.page_bits;
 
         if !self.pages.contains_key(i) {
             false
         } else {
-            let p = self.pages.get(i).unwrap().borrow();
+            let p = self.pages.get(i).unwrap();
             p.get(j.try_into().expect("Index should have fit into u32"))
         }
     }
 
     #[allow(dead_code)]
     pub(crate) fn set(&mut self, index: u64, value: bool) -> bool {
-        let j = index & (DYNAMIC_BITFIELD_PAGE_SIZE as u64 - 1);
-        let i = (index - j) / DYNAMIC_BITFIELD_PAGE_SIZE as u64;
+        let j = index & (self.page_bits - 1);
+        let i = (index - j) / self.page_bits;
 
         if !self.pages.contains_key(i) {
             if value {
-                self.pages.insert(i, RefCell::new(FixedBitfield::new()));
+                self.pages
+                    .insert(i, FixedBitfield::new(self.format.page_size()));
                 if i > self.biggest_page_index {
                     self.biggest_page_index = i;
                 }
@@ -113,7 +116,7 @@ impl DynamicBitfield {
             }
         }
 
-        let mut p = self.pages.get_mut(i).unwrap().borrow_mut();
+        let p = self.pages.get_mut(i).unwrap();
         let changed: bool = p.set(j.try_into().expect("Index should have fit into u32"), value);
 
         if changed && !p.dirty {
@@ -132,20 +135,21 @@ impl DynamicBitfield {
     }
 
     pub(crate) fn set_range(&mut self, start: u64, length: u64, value: bool) {
-        let mut j = start & (DYNAMIC_BITFIELD_PAGE_SIZE as u64 - 1);
-        let mut i = (start - j) / (DYNAMIC_BITFIELD_PAGE_SIZE as u64);
+        let mut j = start & (self.page_bits - 1);
+        let mut i = (start - j) / self.page_bits;
         let mut length = length;
 
         while length > 0 {
             if !self.pages.contains_key(i) {
-                self.pages.insert(i, RefCell::new(FixedBitfield::new()));
+                self.pages
+                    .insert(i, FixedBitfield::new(self.format.page_size()));
                 if i > self.biggest_page_index {
                     self.biggest_page_index = i;
                 }
             }
-            let mut p = self.pages.get_mut(i).unwrap().borrow_mut();
+            let p = self.pages.get_mut(i).unwrap();
 
-            let end = std::cmp::min(j + length, DYNAMIC_BITFIELD_PAGE_SIZE as u64);
+            let end = std::cmp::min(j + length, self.page_bits);
 
             let range_start: u32 = j
                 .try_into()
@@ -168,8 +172,8 @@ impl DynamicBitfield {
 
     /// Finds the first index of the value after given position. Returns None if not found.
     pub(crate) fn index_of(&self, value: bool, position: u64) -> Option<u64> {
-        let first_index = position & (DYNAMIC_BITFIELD_PAGE_SIZE as u64 - 1);
-        let first_page = (position - first_index) / (DYNAMIC_BITFIELD_PAGE_SIZE as u64);
+        let first_index = position & (self.page_bits - 1);
+        let first_page = (position - first_index) / self.page_bits;
 
         if value {
             // For finding the first positive value, we only care about pages that are set,
@@ -177,8 +181,8 @@ impl DynamicBitfield {
 
             // To keep the common case fast, first try the same page as the position
             if let Some(p) = self.pages.get(first_page) {
-                if let Some(index) = p.borrow().index_of(value, first_index as u32) {
-                    return Some(first_page * DYNAMIC_BITFIELD_PAGE_SIZE as u64 + index as u64);
+                if let Some(index) = p.index_of(value, first_index as u32) {
+                    return Some(first_page * self.page_bits + index as u64);
                 };
             }
 
@@ -188,8 +192,8 @@ impl DynamicBitfield {
             keys.sort();
             for key in keys {
                 if let Some(p) = self.pages.get(*key) {
-                    if let Some(index) = p.borrow().index_of(value, 0) {
-                        return Some(key * DYNAMIC_BITFIELD_PAGE_SIZE as u64 + index as u64);
+                    if let Some(index) = p.index_of(value, 0) {
+                        return Some(key * self.page_bits + index as u64);
                     };
                 }
             }
@@ -200,11 +204,11 @@ impl DynamicBitfield {
             let mut j = first_index as u32;
             while i == first_page || i <= self.biggest_page_index {
                 if let Some(p) = self.pages.get(i) {
-                    if let Some(index) = p.borrow().index_of(value, j) {
-                        return Some(i * DYNAMIC_BITFIELD_PAGE_SIZE as u64 + index as u64);
+                    if let Some(index) = p.index_of(value, j) {
+                        return Some(i * self.page_bits + index as u64);
                     };
                 } else {
-                    return Some(i * DYNAMIC_BITFIELD_PAGE_SIZE as u64 + j as u64);
+                    return Some(i * self.page_bits + j as u64);
                 }
                 i += 1;
                 j = 0; // We start at the beginning of each page
@@ -215,8 +219,8 @@ impl DynamicBitfield {
 
     /// Finds the last index of the value before given position. Returns None if not found.
     pub(crate) fn last_index_of(&self, value: bool, position: u64) -> Option<u64> {
-        let last_index = position & (DYNAMIC_BITFIELD_PAGE_SIZE as u64 - 1);
-        let last_page = (position - last_index) / (DYNAMIC_BITFIELD_PAGE_SIZE as u64);
+        let last_index = position & (self.page_bits - 1);
+        let last_page = (position - last_index) / self.page_bits;
 
         if value {
             // For finding the last positive value, we only care about pages that are set,
@@ -224,8 +228,8 @@ impl DynamicBitfield {
 
             // To keep the common case fast, first try the same page as the position
             if let Some(p) = self.pages.get(last_page) {
-                if let Some(index) = p.borrow().last_index_of(value, last_index as u32) {
-                    return Some(last_page * DYNAMIC_BITFIELD_PAGE_SIZE as u64 + index as u64);
+                if let Some(index) = p.last_index_of(value, last_index as u32) {
+                    return Some(last_page * self.page_bits + index as u64);
                 };
             }
 
@@ -237,11 +241,8 @@ impl DynamicBitfield {
 
             for key in keys {
                 if let Some(p) = self.pages.get(*key) {
-                    if let Some(index) = p
-                        .borrow()
-                        .last_index_of(value, FIXED_BITFIELD_BITS_LENGTH as u32 - 1)
-                    {
-                        return Some(key * DYNAMIC_BITFIELD_PAGE_SIZE as u64 + index as u64);
+                    if let Some(index) = p.last_index_of(value, (self.page_bits - 1) as u32) {
+                        return Some(key * self.page_bits + index as u64);
                     };
                 }
             }
@@ -252,14 +253,14 @@ impl DynamicBitfield {
             let mut j = last_index as u32;
             while i == last_page || i == 0 {
                 if let Some(p) = self.pages.get(i) {
-                    if let Some(index) = p.borrow().last_index_of(value, j) {
-                        return Some(i * DYNAMIC_BITFIELD_PAGE_SIZE as u64 + index as u64);
+                    if let Some(index) = p.last_index_of(value, j) {
+                        return Some(i * self.page_bits + index as u64);
                     };
                 } else {
-                    return Some(i * DYNAMIC_BITFIELD_PAGE_SIZE as u64 + j as u64);
+                    return Some(i * self.page_bits + j as u64);
                 }
                 i -= 1;
-                j = FIXED_BITFIELD_BITS_LENGTH as u32 - 1; // We start at end of each page
+                j = (self.page_bits - 1) as u32; // We start at end of each page
             }
         }
 
@@ -278,7 +279,10 @@ mod tests {
     }
 
     fn get_dynamic_bitfield() -> DynamicBitfield {
-        match DynamicBitfield::open(Some(StoreInfo::new_content(Store::Bitfield, 0, &[]))) {
+        match DynamicBitfield::open(
+            BitfieldFormat::default(),
+            Some(StoreInfo::new_content(Store::Bitfield, 0, &[])),
+        ) {
             Either::Left(_) => panic!("Could not open bitfield"),
             Either::Right(bitfield) => bitfield,
         }
@@ -400,4 +404,39 @@ mod tests {
         assert_value_range(&bitfield, 10000020, 30, true);
         assert_value_range(&bitfield, 10000050, 9, false);
     }
+
+    #[test]
+    fn bitfield_dynamic_reopen_with_page_sizes() {
+        for page_size in [1024, 4096, 65536] {
+            let format = BitfieldFormat::new(page_size).unwrap();
+            let open = |data: &[u8]| match DynamicBitfield::open(
+                format,
+                Some(StoreInfo::new_content(Store::Bitfield, 0, data)),
+            ) {
+                Either::Left(_) => panic!("Could not open bitfield"),
+                Either::Right(bitfield) => bitfield,
+            };
+            let mut bitfield = open(&[]);
+            bitfield.set_range(5, 1_000_000, true);
+            bitfield.set(2_000_000, true);
+            let mut data = vec![];
+            for info in bitfield.flush().iter() {
+                let bytes = info.data.as_ref().unwrap();
+                assert_eq!(bytes.len(), page_size);
+                let start = info.index as usize;
+                if data.len() < start + bytes.len() {
+                    data.resize(start + bytes.len(), 0);
+                }
+                data[start..start + bytes.len()].copy_from_slice(bytes);
+            }
+
+            let bitfield = open(&data);
+            assert_eq!(bitfield.format(), format);
+            assert_eq!(bitfield.index_of(true, 0), Some(5));
+            assert_eq!(bitfield.index_of(false, 5), Some(1_000_005));
+            assert_eq!(bitfield.index_of(true, 1_000_005), Some(2_000_000));
+            assert_eq!(bitfield.last_index_of(true, 1_999_999), Some(1_000_004));
+            assert_value_range(&bitfield, 500_000, 100, true);
+        }
+    }
 }
@@ -1,12 +1,10 @@
-pub(crate) const FIXED_BITFIELD_LENGTH: usize = 1024;
-pub(crate) const FIXED_BITFIELD_BYTES_LENGTH: usize = FIXED_BITFIELD_LENGTH * 4;
-pub(crate) const FIXED_BITFIELD_BITS_LENGTH: usize = FIXED_BITFIELD_BYTES_LENGTH * 8;
 // u32 has 4 bytes and a byte has 8 bits
 const FIXED_BITFIELD_BITS_PER_ELEM: u32 = 4 * 8;
 
 use std::convert::TryInto;
 
-/// Fixed size bitfield
+/// Fixed size bitfield, one page of the bitfield store. The size is given in bytes and must be
+/// a multiple of 4.
 /// see:
 /// https://github.com/holepunchto/bits-to-bytes/blob/main/index.js
 /// for implementations.
@@ -15,29 +13,28 @@ use std::convert::TryInto;
 #[derive(Debug)]
 pub(crate) struct FixedBitfield {
     pub(crate) dirty: bool,
-    bitfield: [u32; FIXED_BITFIELD_LENGTH],
+    bitfield: Box<[u32]>,
 }
 
 impl FixedBitfield {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(page_size: usize) -> Self {
         Self {
             dirty: false,
-            bitfield: [0; FIXED_BITFIELD_LENGTH],
+            bitfield: vec![0; page_size / 4].into_boxed_slice(),
         }
     }
 
-    pub(crate) fn from_data(data_index: usize, data: &[u8]) -> Self {
-        let mut bitfield = [0; FIXED_BITFIELD_LENGTH];
-        if data.len() >= data_index + 4 {
-            let mut i = data_index;
-            let limit = std::cmp::min(data_index + FIXED_BITFIELD_BYTES_LENGTH, data.len()) - 4;
-            while i <= limit {
-                let value: u32 = (data[i] as u32)
-                    | ((data[i + 1] as u32) << 8)
-                    | ((data[i + 2] as u32) << 16)
-                    | ((data[i + 3] as u32) << 24);
-                bitfield[i / 4] = value;
-                i += 4;
+    /// Reads the page starting at `data_index`. Missing bytes at the end of the data are
+    /// read as zeros.
+    pub(crate) fn from_data(page_size: usize, data_index: usize, data: &[u8]) -> Self {
+        let mut bitfield = vec![0; page_size / 4].into_boxed_slice();
+        let end = std::cmp::min(data_index + page_size, data.len());
+        if end > data_index {
+            for (elem, bytes) in bitfield
+                .iter_mut()
+                .zip(data[data_index..end].chunks_exact(4))
+            {
+                *elem = u32::from_le_bytes(bytes.try_into().expect("Chunk should be 4 bytes"));
             }
         }
         Self {
@@ -47,17 +44,15 @@ impl FixedBitfield {
     }
 
     pub(crate) fn to_bytes(&self) -> Box<[u8]> {
-        let mut data: [u8; FIXED_BITFIELD_BYTES_LENGTH] = [0; FIXED_BITFIELD_BYTES_LENGTH];
-        let mut i = 0;
-        for elem in self.bitfield {
-            let bytes = &elem.to_le_bytes();
-            data[i] = bytes[0];
-            data[i + 1] = bytes[1];
-            data[i + 2] = bytes[2];
-            data[i + 3] = bytes[3];
-            i += 4;
-        }
-        data.into()
+        self.bitfield
+            .iter()
+            .flat_map(|elem| elem.to_le_bytes())
+            .collect()
+    }
+
+    /// Number of bits in the page.
+    pub(crate) fn bits_length(&self) -> u32 {
+        self.bitfield.len() as u32 * FIXED_BITFIELD_BITS_PER_ELEM
     }
 
     pub(crate) fn get(&self, index: u32) -> bool {
@@ -133,7 +128,7 @@ impl FixedBitfield {
 
     /// Finds the first index of the value after given position. Returns None if not found.
     pub(crate) fn index_of(&self, value: bool, position: u32) -> Option<u32> {
-        (position..FIXED_BITFIELD_BITS_LENGTH as u32).find(|&i| self.get(i) == value)
+        (position..self.bits_length()).find(|&i| self.get(i) == value)
     }
 
     /// Finds the last index of the value before given position. Returns None if not found.
@@ -146,6 +141,8 @@ impl FixedBitfield {
 mod tests {
     use super::*;
 
+    const PAGE_SIZE: usize = 4096;
+
     fn assert_value_range(bitfield: &FixedBitfield, start: u32, length: u32, value: bool) {
         for i in start..start + length {
             assert_eq!(bitfield.get(i), value);
@@ -154,7 +151,7 @@ mod tests {
 
     #[test]
     fn bitfield_fixed_get_and_set() {
-        let mut bitfield = FixedBitfield::new();
+        let mut bitfield = FixedBitfield::new(PAGE_SIZE);
         assert_value_range(&bitfield, 0, 9, false);
         assert_eq!(bitfield.index_of(true, 0), None);
         assert_eq!(bitfield.index_of(false, 0), Some(0));
@@ -191,9 +188,30 @@ mod tests {
         assert_eq!(bitfield.last_index_of(true, 32766), Some(32));
     }
 
+    #[test]
+    fn bitfield_fixed_bytes_round_trip() {
+        for page_size in [1024, 4096, 65536] {
+            let mut bitfield = FixedBitfield::new(page_size);
+            let last = page_size as u32 * 8 - 1;
+            bitfield.set(1, true);
+            bitfield.set(last, true);
+            let mut data = vec![0xff; 8];
+            data.extend_from_slice(&bitfield.to_bytes());
+            assert_eq!(data.len(), page_size + 8);
+            let read = FixedBitfield::from_data(page_size, 8, &data);
+            assert_eq!(read.index_of(true, 0), Some(1));
+            assert_eq!(read.index_of(true, 2), Some(last));
+            assert_eq!(read.bits_length(), last + 1);
+
+            // Truncated pages are padded with zeros
+            let read = FixedBitfield::from_data(page_size, 8, &data[..16]);
+            assert_eq!(read.last_index_of(true, last), Some(1));
+        }
+    }
+
     #[test]
     fn bitfield_fixed_set_range() {
-        let mut bitfield = FixedBitfield::new();
+        let mut bitfield = FixedBitfield::new(PAGE_SIZE);
         bitfield.set_range(0, 2, true);
         assert_value_range(&bitfield, 0, 2, true);
         assert_value_range(&bitfield, 3, 61, false);
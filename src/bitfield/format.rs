@@ -0,0 +1,64 @@
+use crate::HypercoreError;
+
+/// On-disk format of the bitfield store: the store is a sequence of pages of `page_size`
+/// bytes, each holding the have-bits of `page_size * 8` blocks.
+///
+/// The format is recorded in the oplog header when it differs from the default, so a core
+/// always reopens with the page size it was created with, whatever the defaults of the
+/// opening version are. Cores without a recorded format use the default, which matches the
+/// Javascript implementation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitfieldFormat {
+    page_size: usize,
+}
+
+impl BitfieldFormat {
+    /// Version of the format written to the oplog header.
+    pub const VERSION: u8 = 1;
+    /// Default page size in bytes.
+    pub const DEFAULT_PAGE_SIZE: usize = 4096;
+    /// Smallest supported page size in bytes.
+    pub const MIN_PAGE_SIZE: usize = 1024;
+    /// Biggest supported page size in bytes.
+    pub const MAX_PAGE_SIZE: usize = 64 * 1024;
+
+    /// Format with the given page size in bytes, which must be a power of two between
+    /// [`BitfieldFormat::MIN_PAGE_SIZE`] and [`BitfieldFormat::MAX_PAGE_SIZE`].
+    pub fn new(page_size: usize) -> Result<Self, HypercoreError> {
+        if !page_size.is_power_of_two()
+            || !(Self::MIN_PAGE_SIZE..=Self::MAX_PAGE_SIZE).contains(&page_size)
+        {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Bitfield page size {page_size} should be a power of two between {} and {}",
+                    Self::MIN_PAGE_SIZE,
+                    Self::MAX_PAGE_SIZE
+                ),
+            });
+        }
+        Ok(Self { page_size })
+    }
+
+    /// Page size in bytes.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Is this the default format, which isn't recorded in the oplog header.
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Number of blocks a page covers.
+    pub(crate) fn page_bits(&self) -> u64 {
+        self.page_size as u64 * 8
+    }
+}
+
+impl Default for BitfieldFormat {
+    fn default() -> Self {
+        Self {
+            page_size: Self::DEFAULT_PAGE_SIZE,
+        }
+    }
+}
@@ -1,4 +1,6 @@
 mod dynamic;
 mod fixed;
+mod format;
 
 pub(crate) use dynamic::DynamicBitfield as Bitfield;
+pub use format::BitfieldFormat;
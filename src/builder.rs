@@ -1,11 +1,15 @@
 use std::fmt::Debug;
+use std::sync::Arc;
 #[cfg(feature = "cache")]
 use std::time::Duration;
 use tracing::instrument;
 
 #[cfg(feature = "cache")]
 use crate::common::cache::CacheOptions;
-use crate::{core::HypercoreOptions, Hypercore, HypercoreError, PartialKeypair, Storage};
+use crate::{
+    core::HypercoreOptions, AppendPolicy, BitfieldFormat, Hypercore, HypercoreError, Limits,
+    PartialKeypair, Quota, Rng, Signer, SignerKey, Storage,
+};
 
 /// Build CacheOptions.
 #[cfg(feature = "cache")]
@@ -72,12 +76,90 @@ impl HypercoreBuilder {
         self
     }
 
+    /// Sign the core with the given signer instead of the secret key of the key pair, e.g. a
+    /// secp256k1 key with the `schnorr` feature. Its scheme and public key are recorded when
+    /// creating the core, and must match when opening it. The signer itself isn't stored:
+    /// without it, the core is opened read-only.
+    pub fn signer(mut self, signer: Arc<dyn Signer>) -> Self {
+        self.options.signer = Some(signer);
+        self
+    }
+
+    /// Verify the core with the given signer key, for readers of a core created with a
+    /// [`HypercoreBuilder::signer`]. Without it, the core is verified with the public key of
+    /// the key pair.
+    pub fn signer_key(mut self, signer_key: SignerKey) -> Self {
+        self.options.signer_key = Some(signer_key);
+        self
+    }
+
     /// Set open.
     pub fn open(mut self, open: bool) -> Self {
         self.options.open = open;
         self
     }
 
+    /// Read tree nodes from storage in groups of 102 nodes (one 4KiB page) instead of one by
+    /// one. Cuts the amount of random reads when creating proofs on slow disks. Does not change
+    /// the on-disk format.
+    pub fn tree_page_reads(mut self, tree_page_reads: bool) -> Self {
+        self.options.tree_page_reads = tree_page_reads;
+        self
+    }
+
+    /// Set the limits of proofs, batches and block sizes.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.options.limits = limits;
+        self
+    }
+
+    /// Set the policy deciding whether appends are allowed, e.g. to enforce per-user quotas.
+    pub fn append_policy(mut self, append_policy: Arc<dyn AppendPolicy>) -> Self {
+        self.options.append_policy = Some(append_policy);
+        self
+    }
+
+    /// Cap the length and byte length of the core, a shorthand for an
+    /// [`HypercoreBuilder::append_policy`] of a [`Quota`].
+    pub fn quota(self, quota: Quota) -> Self {
+        self.append_policy(Arc::new(quota))
+    }
+
+    /// Set the on-disk format of the bitfield store, e.g. bigger pages for cores with many
+    /// blocks. Only used when creating a core: existing cores are opened with the format they
+    /// were created with.
+    pub fn bitfield_format(mut self, bitfield_format: BitfieldFormat) -> Self {
+        self.options.bitfield_format = bitfield_format;
+        self
+    }
+
+    /// Set the source of randomness used to generate the key pair, if none is set, to pick
+    /// blocks in [`Hypercore::scrub_random`] and for the nonces of encrypted blocks. Defaults
+    /// to [`crate::OsRandom`]; use a [`crate::SeededRng`] for reproducible tests.
+    pub fn rng(mut self, rng: Arc<dyn Rng>) -> Self {
+        self.options.rng = rng;
+        self
+    }
+
+    /// Encrypt the values of appended blocks with XChaCha20-Poly1305, and decrypt them in
+    /// [`Hypercore::get`]. Blocks are hashed, stored and replicated encrypted, so peers
+    /// without the key still verify and serve them. Byte lengths, byte ranges and limits count
+    /// the encrypted blocks, [`crate::ENCRYPTION_OVERHEAD`] bytes bigger than their values. The
+    /// key isn't stored: give it whenever the core is opened.
+    #[cfg(feature = "encryption")]
+    pub fn encryption_key(mut self, encryption_key: [u8; 32]) -> Self {
+        self.options.encryption_key = Some(encryption_key);
+        self
+    }
+
+    /// Set the number of threads hashing the blocks of big appended batches. Defaults to the
+    /// global rayon thread pool, 1 hashes on the appending thread.
+    #[cfg(feature = "parallel")]
+    pub fn hash_threads(mut self, threads: usize) -> Self {
+        self.options.hash_threads = Some(threads);
+        self
+    }
+
     /// Set node cache options.
     #[cfg(feature = "cache")]
     pub fn node_cache_options(mut self, builder: CacheOptionsBuilder) -> Self {
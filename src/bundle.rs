@@ -0,0 +1,136 @@
+//! Bundles of many small records in one block, e.g. reactions, so that the signature and tree
+//! nodes of a block are paid once per bundle instead of once per record.
+//!
+//! A bundle starts with the number of records as a little-endian u32, followed by an offset
+//! table with the end offset of every record in the data as little-endian u32s, followed by
+//! the data of the records.
+use crate::HypercoreError;
+
+/// Byte size of the count and of every offset.
+const OFFSET_SIZE: usize = 4;
+
+/// Address of a record in a bundle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RecordId {
+    /// Index of the block of the bundle
+    pub index: u64,
+    /// Position of the record in the bundle
+    pub record: u32,
+}
+
+/// Pack records into the value of one block.
+pub fn encode_bundle<A: AsRef<[u8]>>(records: &[A]) -> Result<Vec<u8>, HypercoreError> {
+    let data_length: usize = records.iter().map(|record| record.as_ref().len()).sum();
+    let table_length = OFFSET_SIZE * (records.len() + 1);
+    if records.len() > u32::MAX as usize || data_length > u32::MAX as usize {
+        return Err(HypercoreError::BadArgument {
+            context: format!(
+                "Bundle of {} records and {data_length} bytes is too big",
+                records.len()
+            ),
+        });
+    }
+    let mut bundle = Vec::with_capacity(table_length + data_length);
+    bundle.extend((records.len() as u32).to_le_bytes());
+    let mut end: u32 = 0;
+    for record in records {
+        end += record.as_ref().len() as u32;
+        bundle.extend(end.to_le_bytes());
+    }
+    for record in records {
+        bundle.extend(record.as_ref());
+    }
+    Ok(bundle)
+}
+
+/// Unpack all records of a bundle.
+pub fn decode_bundle(bundle: &[u8]) -> Result<Vec<&[u8]>, HypercoreError> {
+    let count = bundle_count(bundle)?;
+    (0..count)
+        .map(|record| bundle_record(bundle, record).map(|record| record.expect("Record exists")))
+        .collect()
+}
+
+/// Number of records in a bundle.
+pub fn bundle_count(bundle: &[u8]) -> Result<u32, HypercoreError> {
+    let count = read_u32(bundle, 0)?;
+    if (count as usize + 1) * OFFSET_SIZE > bundle.len() {
+        return Err(invalid_bundle("offset table exceeds the bundle"));
+    }
+    Ok(count)
+}
+
+/// Record at the given position of a bundle, `None` if the bundle has fewer records. Only the
+/// offsets of the record are read, not the whole table.
+pub fn bundle_record(bundle: &[u8], record: u32) -> Result<Option<&[u8]>, HypercoreError> {
+    let count = bundle_count(bundle)?;
+    if record >= count {
+        return Ok(None);
+    }
+    let data_start = (count as usize + 1) * OFFSET_SIZE;
+    let start = match record {
+        0 => 0,
+        _ => read_u32(bundle, record as usize * OFFSET_SIZE)? as usize,
+    };
+    let end = read_u32(bundle, (record as usize + 1) * OFFSET_SIZE)? as usize;
+    if start > end || data_start + end > bundle.len() {
+        return Err(invalid_bundle("record offsets out of bounds"));
+    }
+    Ok(Some(&bundle[data_start + start..data_start + end]))
+}
+
+fn read_u32(bundle: &[u8], position: usize) -> Result<u32, HypercoreError> {
+    bundle
+        .get(position..position + OFFSET_SIZE)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().expect("Offset should be 4 bytes")))
+        .ok_or_else(|| invalid_bundle("truncated offset table"))
+}
+
+fn invalid_bundle(reason: &str) -> HypercoreError {
+    HypercoreError::BadArgument {
+        context: format!("Invalid bundle, {reason}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::create_hypercore_with_data;
+
+    #[async_std::test]
+    async fn bundles_pack_records_into_one_block() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(1).await?;
+        let reactions: Vec<Vec<u8>> = (0..100).map(|i| format!("+{i}").into_bytes()).collect();
+        let ids = hypercore.append_bundle(&reactions).await?;
+        assert_eq!(hypercore.info().length, 2);
+        assert_eq!(ids.len(), 100);
+        assert_eq!(
+            ids[42],
+            RecordId {
+                index: 1,
+                record: 42
+            }
+        );
+
+        assert_eq!(hypercore.get_record(ids[42]).await?.unwrap(), b"+42");
+        assert_eq!(
+            hypercore
+                .get_record(RecordId {
+                    index: 1,
+                    record: 100
+                })
+                .await?,
+            None
+        );
+        assert_eq!(hypercore.get_bundle(1).await?.unwrap(), reactions);
+        // Blocks that aren't bundles are reported
+        assert!(hypercore.get_bundle(0).await.is_err());
+
+        let empty = encode_bundle::<&[u8]>(&[])?;
+        assert_eq!(decode_bundle(&empty)?, Vec::<&[u8]>::new());
+        let mut truncated = encode_bundle(&[b"abc"])?;
+        truncated.pop();
+        assert!(bundle_record(&truncated, 0).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+//! Disk cache of remote cores, for nodes that serve many feeds they don't permanently host.
+use random_access_disk::RandomAccessDisk;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::instrument;
+
+use crate::common::{decode_hex, encode_hex};
+use crate::{
+    storage::map_random_access_err, Clock, Hypercore, HypercoreBuilder, HypercoreError,
+    PartialKeypair, SettingValue, Settings, Storage, SystemClock, VerifyingKey,
+};
+
+const LAST_USED: &str = "last_used";
+/// Default time that writes wait for others to share their sync, see
+/// [`CacheStore::set_commit_window`].
+const DEFAULT_COMMIT_WINDOW: Duration = Duration::from_millis(10);
+
+/// Store of read-only cores of other writers that evicts the least recently used cores, as a
+/// whole, when their total size on disk exceeds a budget.
+///
+/// Each core is stored in a directory named after its hex encoded public key under the root
+/// directory. The order of use is kept in the `cache-index` file so that it survives
+/// restarts. The most recently used core is never evicted, even if it alone exceeds the
+/// budget.
+///
+/// Writes to the cores are not synced to disk one by one. Instead, cores with writes are
+/// marked with [`CacheStore::mark_written`] and synced together by [`CacheStore::commit`], a
+/// group commit, so that many cores appending at the same time share the cost of a sync
+/// window instead of paying for one sync per write.
+#[derive(Debug)]
+pub struct CacheStore {
+    root: PathBuf,
+    budget: u64,
+    index: Settings,
+    cores: HashMap<[u8; 32], Hypercore>,
+    clock: u64,
+    commit_window: Duration,
+    time: Arc<dyn Clock>,
+    uncommitted: HashSet<[u8; 32]>,
+    uncommitted_since: Option<Instant>,
+}
+
+impl CacheStore {
+    /// Open the cache in the given root directory, with a budget in bytes.
+    #[instrument(err)]
+    pub async fn open(root: &Path, budget: u64) -> Result<Self, HypercoreError> {
+        std::fs::create_dir_all(root)?;
+        let storage = RandomAccessDisk::open(root.join("cache-index"))
+            .await
+            .map_err(map_random_access_err)?;
+        let index = Settings::open(Box::new(storage)).await?;
+        let clock = index
+            .iter()
+            .filter_map(|(_, value)| match value {
+                SettingValue::U64(last_used) => Some(*last_used),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        Ok(Self {
+            root: root.to_path_buf(),
+            budget,
+            index,
+            cores: HashMap::new(),
+            clock,
+            commit_window: DEFAULT_COMMIT_WINDOW,
+            time: Arc::new(SystemClock),
+            uncommitted: HashSet::new(),
+            uncommitted_since: None,
+        })
+    }
+
+    /// Budget in bytes.
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// Time that the first write after a commit waits for writes to other cores before
+    /// [`CacheStore::commit_if_due`] syncs them together.
+    pub fn commit_window(&self) -> Duration {
+        self.commit_window
+    }
+
+    /// Set the commit window. Longer windows coalesce more syncs at the cost of a longer time
+    /// until writes are durable.
+    pub fn set_commit_window(&mut self, window: Duration) {
+        self.commit_window = window;
+    }
+
+    /// Set the clock timing the commit window, e.g. a [`crate::ManualClock`] in tests.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.time = clock;
+    }
+
+    /// Get the core with the given public key, creating it if it isn't cached. Marks the core
+    /// as the most recently used, and evicts other cores if a new core was opened and the
+    /// budget is exceeded. As cores grow when blocks are downloaded, call
+    /// [`CacheStore::evict`] after downloading to keep within the budget.
+    #[instrument(err, skip(self))]
+    pub async fn get(
+        &mut self,
+        public_key: &VerifyingKey,
+    ) -> Result<&mut Hypercore, HypercoreError> {
+        let key = public_key.to_bytes();
+        self.touch(public_key).await?;
+        if !self.cores.contains_key(&key) {
+            let storage = Storage::new_disk(&self.core_dir(public_key), false).await?;
+            let core = HypercoreBuilder::new(storage)
+                .key_pair(PartialKeypair {
+                    public: *public_key,
+                    secret: None,
+                })
+                .build()
+                .await?;
+            self.cores.insert(key, core);
+            self.evict().await?;
+        }
+        Ok(self.cores.get_mut(&key).expect("Core should be open"))
+    }
+
+    /// Public keys of the cached cores, from the least to the most recently used.
+    pub fn keys(&self) -> Vec<VerifyingKey> {
+        self.entries()
+            .into_iter()
+            .map(|(public_key, _)| public_key)
+            .collect()
+    }
+
+    /// Total byte length of the files of all cached cores.
+    pub fn disk_usage(&self) -> Result<u64, HypercoreError> {
+        self.entries()
+            .iter()
+            .map(|(public_key, _)| dir_size(&self.core_dir(public_key)))
+            .sum()
+    }
+
+    /// Mark the core with the given public key as having writes that are not yet synced to
+    /// disk, e.g. after applying a proof to it.
+    pub fn mark_written(&mut self, public_key: &VerifyingKey) {
+        if self.uncommitted.insert(public_key.to_bytes()) && self.uncommitted_since.is_none() {
+            self.uncommitted_since = Some(self.time.now());
+        }
+    }
+
+    /// Number of cores with writes that are not yet synced.
+    pub fn uncommitted(&self) -> usize {
+        self.uncommitted.len()
+    }
+
+    /// When the pending writes are due to be committed, `None` if there are none. Lets the
+    /// caller sleep until then before calling [`CacheStore::commit_if_due`].
+    pub fn commit_deadline(&self) -> Option<Instant> {
+        self.uncommitted_since
+            .map(|since| since + self.commit_window)
+    }
+
+    /// Commit if the commit window of the oldest pending write has passed. Returns the number
+    /// of synced cores.
+    #[instrument(err, skip(self))]
+    pub async fn commit_if_due(&mut self) -> Result<usize, HypercoreError> {
+        match self.commit_deadline() {
+            Some(deadline) if self.time.now() >= deadline => self.commit().await,
+            _ => Ok(0),
+        }
+    }
+
+    /// Sync all cores marked with [`CacheStore::mark_written`] to disk, in one go. Returns
+    /// the number of synced cores.
+    #[instrument(err, skip(self))]
+    pub async fn commit(&mut self) -> Result<usize, HypercoreError> {
+        let mut synced = 0;
+        let keys: Vec<[u8; 32]> = self.uncommitted.iter().copied().collect();
+        for key in keys {
+            // Cores that fail to sync stay marked, to be retried on the next commit
+            if let Some(core) = self.cores.get_mut(&key) {
+                core.sync().await?;
+                synced += 1;
+            }
+            self.uncommitted.remove(&key);
+        }
+        self.uncommitted_since = None;
+        Ok(synced)
+    }
+
+    /// Remove least recently used cores until the disk usage is within the budget. Returns the
+    /// public keys of the removed cores.
+    #[instrument(err, skip(self))]
+    pub async fn evict(&mut self) -> Result<Vec<VerifyingKey>, HypercoreError> {
+        let mut entries = self.entries();
+        // The most recently used core is kept
+        entries.pop();
+        let mut usage = self.disk_usage()?;
+        let mut evicted = vec![];
+        for (public_key, _) in entries {
+            if usage <= self.budget {
+                break;
+            }
+            usage -= self.remove_core(&public_key).await?;
+            evicted.push(public_key);
+        }
+        Ok(evicted)
+    }
+
+    /// Remove a core from the cache, returns true if it was cached.
+    #[instrument(err, skip(self))]
+    pub async fn remove(&mut self, public_key: &VerifyingKey) -> Result<bool, HypercoreError> {
+        let cached = self
+            .index
+            .get(&Settings::core_key(public_key, LAST_USED))
+            .is_some();
+        self.remove_core(public_key).await?;
+        Ok(cached)
+    }
+
+    /// Removes the core and returns the number of bytes freed.
+    async fn remove_core(&mut self, public_key: &VerifyingKey) -> Result<u64, HypercoreError> {
+        self.uncommitted.remove(&public_key.to_bytes());
+        let dir = self.core_dir(public_key);
+        let size = dir_size(&dir)?;
+        if let Some(core) = self.cores.remove(&public_key.to_bytes()) {
+            core.destroy(false).await?;
+        }
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        self.index
+            .remove(&Settings::core_key(public_key, LAST_USED))
+            .await?;
+        Ok(size)
+    }
+
+    async fn touch(&mut self, public_key: &VerifyingKey) -> Result<(), HypercoreError> {
+        self.clock += 1;
+        self.index
+            .set(&Settings::core_key(public_key, LAST_USED), self.clock)
+            .await
+    }
+
+    /// Cached cores and when they were last used, from the least to the most recently used.
+    fn entries(&self) -> Vec<(VerifyingKey, u64)> {
+        let mut entries: Vec<(VerifyingKey, u64)> = self
+            .index
+            .iter()
+            .filter_map(|(key, value)| match value {
+                SettingValue::U64(last_used) => Some((parse_index_key(key)?, *last_used)),
+                _ => None,
+            })
+            .collect();
+        entries.sort_by_key(|(_, last_used)| *last_used);
+        entries
+    }
+
+    fn core_dir(&self, public_key: &VerifyingKey) -> PathBuf {
+        self.root.join(encode_hex(public_key.as_bytes()))
+    }
+}
+
+fn parse_index_key(key: &str) -> Option<VerifyingKey> {
+    let hex = key
+        .strip_prefix("core/")?
+        .strip_suffix(&format!("/{LAST_USED}"))?;
+    VerifyingKey::from_bytes(&decode_hex(hex)?).ok()
+}
+
+fn dir_size(dir: &Path) -> Result<u64, HypercoreError> {
+    if !dir.exists() {
+        return Ok(0);
+    }
+    let mut size = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let metadata = entry?.metadata()?;
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
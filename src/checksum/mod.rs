@@ -0,0 +1,101 @@
+use futures::future::Either;
+
+use crate::common::{Store, StoreInfo, StoreInfoInstruction};
+
+/// Name of the auxiliary store of the checksums, see [`crate::Storage::aux`].
+pub(crate) const CHECKSUM_STORE: &str = "checksum";
+
+/// Byte size of one stored checksum.
+pub(crate) const CHECKSUM_SIZE: u64 = 4;
+
+/// Checksum store. Holds a CRC32 of every block at `index * CHECKSUM_SIZE`, independent of the
+/// tree, so that blocks can be checked for corruption without hashing them with BLAKE2b. A
+/// stored zero means that the checksum is not known, e.g. for blocks written before the
+/// checksum store existed.
+#[derive(Debug, Default)]
+pub(crate) struct ChecksumStore {}
+
+impl ChecksumStore {
+    /// Checksum of a block, never zero.
+    pub(crate) fn checksum(value: &[u8]) -> u32 {
+        match crc32fast::hash(value) {
+            0 => 1,
+            checksum => checksum,
+        }
+    }
+
+    /// Stores checksums of consecutive blocks starting at `index`.
+    pub(crate) fn put_batch<A: AsRef<[u8]>, B: AsRef<[A]>>(
+        &self,
+        batch: B,
+        index: u64,
+    ) -> StoreInfo {
+        let checksums: Vec<u32> = batch
+            .as_ref()
+            .iter()
+            .map(|value| Self::checksum(value.as_ref()))
+            .collect();
+        self.put_checksums(&checksums, index)
+    }
+
+    /// Stores the checksum of the block at `index`.
+    pub(crate) fn put(&self, value: &[u8], index: u64) -> StoreInfo {
+        self.put_checksums(&[Self::checksum(value)], index)
+    }
+
+    /// Stores already calculated checksums of consecutive blocks starting at `index`.
+    pub(crate) fn put_checksums(&self, checksums: &[u32], index: u64) -> StoreInfo {
+        let mut buffer: Vec<u8> = Vec::with_capacity(checksums.len() * CHECKSUM_SIZE as usize);
+        for checksum in checksums {
+            buffer.extend_from_slice(&checksum.to_le_bytes());
+        }
+        StoreInfo::new_content(Store::aux(CHECKSUM_STORE), index * CHECKSUM_SIZE, &buffer)
+    }
+
+    /// Reads stored checksums of `length` blocks starting at `index`. Checksums missing from
+    /// the store are returned as zero.
+    pub(crate) fn read(
+        &self,
+        index: u64,
+        length: u64,
+        info: Option<StoreInfo>,
+    ) -> Either<StoreInfoInstruction, Vec<u32>> {
+        if let Some(info) = info {
+            let mut checksums: Vec<u32> = vec![0; length as usize];
+            if let Some(data) = info.data {
+                for (i, bytes) in data.chunks_exact(CHECKSUM_SIZE as usize).enumerate() {
+                    checksums[i] = u32::from_le_bytes(bytes.try_into().unwrap());
+                }
+            }
+            Either::Right(checksums)
+        } else {
+            Either::Left(StoreInfoInstruction::new_content_allow_miss(
+                Store::aux(CHECKSUM_STORE),
+                index * CHECKSUM_SIZE,
+                length * CHECKSUM_SIZE,
+            ))
+        }
+    }
+
+    /// Clears checksums of `length` blocks starting at `index`, given the current byte length
+    /// of the store.
+    pub(crate) fn clear(&self, index: u64, length: u64, store_length: u64) -> Option<StoreInfo> {
+        let start = index * CHECKSUM_SIZE;
+        if start >= store_length {
+            return None;
+        }
+        let length = std::cmp::min(length * CHECKSUM_SIZE, store_length - start);
+        Some(StoreInfo::new_delete(
+            Store::aux(CHECKSUM_STORE),
+            start,
+            length,
+        ))
+    }
+
+    /// Truncates the checksums to those of the first `length` blocks, given the current byte
+    /// length of the store.
+    pub(crate) fn truncate(&self, length: u64, store_length: u64) -> Option<StoreInfo> {
+        let end = length * CHECKSUM_SIZE;
+        (end < store_length).then(|| StoreInfo::new_truncate(Store::aux(CHECKSUM_STORE), end))
+    }
+}
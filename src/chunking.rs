@@ -0,0 +1,145 @@
+//! Payload size statistics and block size suggestions for splitting payloads into blocks.
+
+/// Smallest suggested block size.
+pub(crate) const MIN_BLOCK_SIZE: usize = 1024;
+
+/// Largest suggested block size, and the suggestion when nothing has been appended yet.
+pub(crate) const MAX_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Byte size of a tree node in a proof: 32 byte hash and 8 byte index.
+const PROOF_NODE_SIZE: usize = 40;
+
+/// Proofs may take at most 1/`PROOF_OVERHEAD_RATIO` of the block size.
+const PROOF_OVERHEAD_RATIO: usize = 16;
+
+/// How to split a stream of payloads into blocks in [`crate::Hypercore::append_stream`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chunking {
+    /// Every payload is a block of its own
+    Payload,
+    /// Payloads are concatenated and split into blocks of the given byte size
+    Fixed(usize),
+    /// Like [`Chunking::Fixed`], with the size of
+    /// [`crate::Hypercore::suggested_chunking`]
+    Auto,
+}
+
+/// Distribution of the sizes of appended payloads. Sizes are counted in power of two buckets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadStats {
+    count: u64,
+    byte_length: u64,
+    buckets: [u64; 66],
+}
+
+impl Default for PayloadStats {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            byte_length: 0,
+            buckets: [0; 66],
+        }
+    }
+}
+
+impl PayloadStats {
+    /// Record an appended payload of the given byte size.
+    pub fn record(&mut self, size: usize) {
+        self.count += 1;
+        self.byte_length += size as u64;
+        self.buckets[bucket(size)] += 1;
+    }
+
+    /// Number of recorded payloads.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Total byte size of recorded payloads.
+    pub fn byte_length(&self) -> u64 {
+        self.byte_length
+    }
+
+    /// Mean payload size, `None` if nothing has been recorded.
+    pub fn mean(&self) -> Option<u64> {
+        (self.count > 0).then(|| self.byte_length / self.count)
+    }
+
+    /// Upper bound of the size below which the given share of payloads fall, e.g. `0.5` for
+    /// the median. Rounded up to a power of two, `None` if nothing has been recorded.
+    pub fn percentile(&self, share: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let target = ((self.count as f64 * share.clamp(0.0, 1.0)).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Some(if i == 0 { 0 } else { 1u64 << (i - 1) });
+            }
+        }
+        unreachable!("Bucket counts should add up to count")
+    }
+
+    /// Suggested block size for payloads like the recorded ones. Blocks as big as the median
+    /// payload keep requests at payload granularity, but blocks must be big enough that the
+    /// merkle proof of a block is small compared to it. Always a power of two between 1 KiB
+    /// and 64 KiB, 64 KiB if nothing has been recorded.
+    pub fn suggested_block_size(&self) -> usize {
+        let Some(median) = self.percentile(0.5) else {
+            return MAX_BLOCK_SIZE;
+        };
+        let median = median.max(1);
+        // A proof has about one node per level of the tree
+        let blocks = (self.byte_length / median).max(1);
+        let proof_size = (64 - blocks.leading_zeros() as usize + 1) * PROOF_NODE_SIZE;
+        let min_size = (proof_size * PROOF_OVERHEAD_RATIO).next_power_of_two();
+        (median as usize)
+            .max(min_size)
+            .clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE)
+    }
+}
+
+/// Index of the bucket of sizes in `(2^(i-2), 2^(i-1)]`, 0 for empty payloads.
+fn bucket(size: usize) -> usize {
+    if size == 0 {
+        0
+    } else {
+        (usize::BITS - (size - 1).leading_zeros()) as usize + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_stats_suggest_block_size() {
+        let mut stats = PayloadStats::default();
+        assert_eq!(stats.mean(), None);
+        assert_eq!(stats.suggested_block_size(), MAX_BLOCK_SIZE);
+
+        // Tiny payloads are packed into blocks big enough for the proof overhead
+        for _ in 0..1000 {
+            stats.record(100);
+        }
+        assert_eq!(stats.mean(), Some(100));
+        assert_eq!(stats.percentile(0.5), Some(128));
+        assert_eq!(stats.suggested_block_size(), 8192);
+
+        // Medium payloads get blocks of their own
+        let mut stats = PayloadStats::default();
+        for size in [10_000, 12_000, 30_000] {
+            stats.record(size);
+        }
+        assert_eq!(stats.percentile(0.5), Some(16384));
+        assert_eq!(stats.percentile(1.0), Some(32768));
+        assert_eq!(stats.suggested_block_size(), 16384);
+
+        // Huge payloads are split
+        let mut stats = PayloadStats::default();
+        stats.record(10 * 1024 * 1024);
+        assert_eq!(stats.suggested_block_size(), MAX_BLOCK_SIZE);
+    }
+}
@@ -48,6 +48,26 @@ pub enum HypercoreError {
         /// Context for the error
         context: String,
     },
+    /// A configured limit was exceeded, see [`crate::Limits`]
+    #[error("Limit exceeded. {context}")]
+    LimitExceeded {
+        /// Context for the error
+        context: String,
+    },
+    /// An append was refused by the append policy of the core, see [`crate::AppendPolicy`]
+    #[error("Quota exceeded. {context}")]
+    QuotaExceeded {
+        /// Context for the error
+        context: String,
+    },
+    /// Store files were modified by another process, see [`crate::Storage::set_file_watch`].
+    /// The state in memory no longer matches them: reopen the hypercore.
+    #[error("Stores modified by another process: {}.",
+          .stores.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+    ExternallyModified {
+        /// Stores whose files changed
+        stores: Vec<Store>,
+    },
     /// Unexpected IO error occured
     #[error("Unrecoverable input/output error occured.{}",
           .context.as_ref().map_or_else(String::new, |ctx| format!(" {ctx}.")))]
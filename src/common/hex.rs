@@ -0,0 +1,37 @@
+//! Hex encoding of keys and ids in file names, setting keys and nostr events.
+
+/// Encode as lowercase hex.
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Decode exactly `N` bytes from hex of either case, `None` on any other input.
+pub(crate) fn decode_hex<const N: usize>(encoded: &str) -> Option<[u8; N]> {
+    // Checking the digits first also rules out the signs `from_str_radix` accepts
+    if encoded.len() != 2 * N || !encoded.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&encoded[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_round_trips_and_rejects_malformed_input() {
+        let bytes = [0x00, 0x7f, 0xab, 0xff];
+        assert_eq!(encode_hex(&bytes), "007fabff");
+        assert_eq!(decode_hex::<4>("007fabff"), Some(bytes));
+        assert_eq!(decode_hex::<4>("007FABFF"), Some(bytes));
+        assert_eq!(decode_hex::<4>("007fab"), None);
+        assert_eq!(decode_hex::<4>("007fabff00"), None);
+        assert_eq!(decode_hex::<4>("007fabfg"), None);
+        assert_eq!(decode_hex::<2>("+f+f"), None);
+        assert_eq!(decode_hex::<2>("éé"), None);
+    }
+}
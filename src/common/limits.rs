@@ -0,0 +1,78 @@
+use super::{HypercoreError, Proof};
+
+/// Maximum sizes accepted when creating and verifying proofs and when appending, so that
+/// hostile peers can't force pathological memory or CPU use. Exceeding them fails with
+/// [`HypercoreError::LimitExceeded`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of tree nodes in a single proof, summed over all of its parts
+    pub max_proof_nodes: usize,
+    /// Maximum number of blocks appended in a single batch
+    pub max_batch_length: usize,
+    /// Maximum byte size of a single block
+    pub max_value_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            // A proof needs about one node per tree level per part, and a full upgrade two
+            // per level
+            max_proof_nodes: 1024,
+            max_batch_length: 64 * 1024,
+            max_value_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl Limits {
+    /// Check the node count and value size of a proof.
+    pub(crate) fn check_proof(&self, proof: &Proof) -> Result<(), HypercoreError> {
+        let mut nodes: usize = 0;
+        if let Some(block) = proof.block.as_ref() {
+            self.check_value_size(block.value.len())?;
+            nodes += block.nodes.len();
+        }
+        if let Some(hash) = proof.hash.as_ref() {
+            nodes += hash.nodes.len();
+        }
+        if let Some(seek) = proof.seek.as_ref() {
+            nodes += seek.nodes.len();
+        }
+        if let Some(upgrade) = proof.upgrade.as_ref() {
+            nodes += upgrade.nodes.len() + upgrade.additional_nodes.len();
+        }
+        if nodes > self.max_proof_nodes {
+            return Err(HypercoreError::LimitExceeded {
+                context: format!(
+                    "Proof has {nodes} nodes, maximum is {}",
+                    self.max_proof_nodes
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Check the length of a batch of blocks.
+    pub(crate) fn check_batch_length(&self, length: usize) -> Result<(), HypercoreError> {
+        if length > self.max_batch_length {
+            return Err(HypercoreError::LimitExceeded {
+                context: format!(
+                    "Batch has {length} blocks, maximum is {}",
+                    self.max_batch_length
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Check the byte size of a block.
+    pub(crate) fn check_value_size(&self, size: usize) -> Result<(), HypercoreError> {
+        if size > self.max_value_size {
+            return Err(HypercoreError::LimitExceeded {
+                context: format!("Block has {size} bytes, maximum is {}", self.max_value_size),
+            });
+        }
+        Ok(())
+    }
+}
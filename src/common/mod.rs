@@ -1,17 +1,31 @@
 #[cfg(feature = "cache")]
 pub(crate) mod cache;
 mod error;
+mod hex;
+mod limits;
 mod node;
 mod peer;
+mod progress;
+mod quota;
+mod retry;
+mod sources;
 mod store;
 
 pub use self::error::HypercoreError;
+pub(crate) use self::hex::{decode_hex, encode_hex};
+pub use self::limits::Limits;
 pub use self::node::Node;
 pub(crate) use self::node::NodeByteRange;
 pub(crate) use self::peer::ValuelessProof;
 pub use self::peer::{
-    DataBlock, DataHash, DataSeek, DataUpgrade, Proof, RequestBlock, RequestSeek, RequestUpgrade,
+    ByteRangePlan, DataBlock, DataHash, DataSeek, DataUpgrade, Proof, RequestBlock, RequestSeek,
+    RequestUpgrade,
 };
+pub use self::progress::Progress;
+pub(crate) use self::quota::check_append;
+pub use self::quota::{AppendGrowth, AppendPolicy, Quota};
+pub use self::retry::{Backoff, RetryPolicy};
+pub use self::sources::{Clock, ManualClock, OsRandom, Rng, SeededRng, SystemClock};
 pub use self::store::Store;
 pub(crate) use self::store::{StoreInfo, StoreInfoInstruction, StoreInfoType};
 
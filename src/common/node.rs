@@ -58,7 +58,7 @@ impl Node {
     pub fn new_blank(index: u64) -> Self {
         Self {
             index,
-            hash: vec![0, 32],
+            hash: vec![0; 32],
             length: 0,
             parent: 0,
             data: None,
@@ -115,7 +115,7 @@ impl Display for Node {
 
 impl PartialOrd for Node {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.index.cmp(&other.index))
+        Some(self.cmp(other))
     }
 }
 
@@ -29,6 +29,26 @@ pub struct RequestUpgrade {
     pub length: u64,
 }
 
+#[derive(Debug, Clone, Default, PartialEq)]
+/// Requests needed to download a byte range, see [`crate::Hypercore::plan_byte_range`]
+pub struct ByteRangePlan {
+    /// Seek to locate a block whose index can't be determined locally. Send it together with
+    /// `hash` in a request of its own, and plan again once its proof has been applied.
+    pub seek: Option<RequestSeek>,
+    /// Hash request that anchors `seek` to the deepest locally known tree node containing the
+    /// byte offset. Its index is a tree node index, not a block index.
+    pub hash: Option<RequestBlock>,
+    /// Missing blocks overlapping the byte range, in order
+    pub blocks: Vec<RequestBlock>,
+}
+
+impl ByteRangePlan {
+    /// Nothing left to request, the whole byte range is stored locally
+    pub fn is_empty(&self) -> bool {
+        self.seek.is_none() && self.hash.is_none() && self.blocks.is_empty()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 /// Proof generated from corresponding requests
 pub struct Proof {
@@ -0,0 +1,103 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Progress of a long running operation, such as creating a hypercore from a big dataset.
+///
+/// The handle is cheap to clone: pass a clone to the operation and poll the other one, e.g. to
+/// render a progress bar. Units of `completed` and `total` depend on the operation, usually they
+/// are blocks.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    inner: Arc<ProgressInner>,
+}
+
+#[derive(Debug)]
+struct ProgressInner {
+    total: AtomicU64,
+    completed: AtomicU64,
+    bytes: AtomicU64,
+    finished: AtomicBool,
+    started: Instant,
+}
+
+impl Progress {
+    /// Create a new progress handle expecting `total` units of work. Use 0 if the total is not
+    /// known.
+    pub fn new(total: u64) -> Self {
+        Self {
+            inner: Arc::new(ProgressInner {
+                total: AtomicU64::new(total),
+                completed: AtomicU64::new(0),
+                bytes: AtomicU64::new(0),
+                finished: AtomicBool::new(false),
+                started: Instant::now(),
+            }),
+        }
+    }
+
+    /// Total units of work, 0 if not known.
+    pub fn total(&self) -> u64 {
+        self.inner.total.load(Ordering::Relaxed)
+    }
+
+    /// Completed units of work.
+    pub fn completed(&self) -> u64 {
+        self.inner.completed.load(Ordering::Relaxed)
+    }
+
+    /// Bytes processed so far.
+    pub fn bytes(&self) -> u64 {
+        self.inner.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Has the operation finished.
+    pub fn is_finished(&self) -> bool {
+        self.inner.finished.load(Ordering::Relaxed)
+    }
+
+    /// Time since the progress handle was created.
+    pub fn elapsed(&self) -> Duration {
+        self.inner.started.elapsed()
+    }
+
+    /// Average bytes processed per second since the progress handle was created.
+    pub fn bytes_per_second(&self) -> f64 {
+        let elapsed = self.elapsed().as_secs_f64();
+        if elapsed == 0.0 {
+            return 0.0;
+        }
+        self.bytes() as f64 / elapsed
+    }
+
+    /// Estimated time left based on the average speed so far, `None` if the total is not known
+    /// or nothing has been completed yet.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.is_finished() {
+            return Some(Duration::ZERO);
+        }
+        let total = self.total();
+        let completed = self.completed();
+        if total == 0 || completed == 0 {
+            return None;
+        }
+        let remaining = total.saturating_sub(completed);
+        Some(self.elapsed().mul_f64(remaining as f64 / completed as f64))
+    }
+
+    /// Set the total units of work, e.g. once it is known.
+    pub fn set_total(&self, total: u64) {
+        self.inner.total.store(total, Ordering::Relaxed);
+    }
+
+    /// Mark `completed` more units of work, processing `bytes` bytes, as done.
+    pub(crate) fn advance(&self, completed: u64, bytes: u64) {
+        self.inner.completed.fetch_add(completed, Ordering::Relaxed);
+        self.inner.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Mark the operation finished.
+    pub(crate) fn finish(&self) {
+        self.inner.finished.store(true, Ordering::Relaxed);
+    }
+}
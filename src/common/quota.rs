@@ -0,0 +1,63 @@
+use std::fmt::Debug;
+
+use super::HypercoreError;
+
+/// Size of a writable core before an append and how much the append grows it, see
+/// [`AppendPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppendGrowth {
+    /// Length of the core before the append
+    pub length: u64,
+    /// Byte length of the core before the append
+    pub byte_length: u64,
+    /// Number of appended blocks
+    pub blocks: u64,
+    /// Byte length of the appended blocks
+    pub bytes: u64,
+}
+
+/// Decides whether appends to a writable core are allowed, e.g. to enforce per-user feed size
+/// limits in hosted deployments. Refused appends fail with [`HypercoreError::QuotaExceeded`]
+/// before anything is written.
+pub trait AppendPolicy: Debug + Send + Sync {
+    /// Check an append, returning why it is refused if it is.
+    fn check_append(&self, growth: &AppendGrowth) -> Result<(), String>;
+}
+
+/// Policy capping the length and byte length of a core.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Quota {
+    /// Maximum number of blocks, `None` for no cap
+    pub max_length: Option<u64>,
+    /// Maximum number of bytes, `None` for no cap
+    pub max_byte_length: Option<u64>,
+}
+
+impl AppendPolicy for Quota {
+    fn check_append(&self, growth: &AppendGrowth) -> Result<(), String> {
+        let length = growth.length.saturating_add(growth.blocks);
+        if let Some(max_length) = self.max_length.filter(|max| length > *max) {
+            return Err(format!(
+                "Length would be {length} blocks, maximum is {max_length}"
+            ));
+        }
+        let byte_length = growth.byte_length.saturating_add(growth.bytes);
+        if let Some(max_byte_length) = self.max_byte_length.filter(|max| byte_length > *max) {
+            return Err(format!(
+                "Byte length would be {byte_length} bytes, maximum is {max_byte_length}"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Check an append against an optional policy.
+pub(crate) fn check_append(
+    policy: Option<&dyn AppendPolicy>,
+    growth: AppendGrowth,
+) -> Result<(), HypercoreError> {
+    match policy.map(|policy| policy.check_append(&growth)) {
+        Some(Err(context)) => Err(HypercoreError::QuotaExceeded { context }),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,128 @@
+//! Retry policy shared by the parts that retry over the network: read repair, the replicator
+//! and nostr tunnels. Applications configure it once and pass the same policy to each.
+use std::time::Duration;
+
+use super::Rng;
+
+/// How the delay between attempts grows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    /// Every retry waits the initial delay
+    Constant,
+    /// Retry `n` waits `n` times the initial delay
+    Linear,
+    /// Retry `n` waits `2^(n-1)` times the initial delay
+    Exponential,
+}
+
+/// How often and how soon failed operations are retried.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts in total, the first one included, before giving up
+    pub max_attempts: u32,
+    /// Delay before the first retry
+    pub initial_delay: Duration,
+    /// Upper bound of the delay before any retry
+    pub max_delay: Duration,
+    /// Growth of the delay
+    pub backoff: Backoff,
+    /// Share of the delay, between 0 and 1, randomly taken off each delay, so that peers
+    /// failing together don't retry together
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            backoff: Backoff::Exponential,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Policy that never retries.
+    pub fn never() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Can another attempt follow the given number of failed ones.
+    pub fn allows(&self, failed: u32) -> bool {
+        failed < self.max_attempts
+    }
+
+    /// Delay before the attempt following the given number of failed ones, `None` if out of
+    /// attempts.
+    pub fn delay(&self, failed: u32, rng: &dyn Rng) -> Option<Duration> {
+        if failed == 0 {
+            return Some(Duration::ZERO);
+        }
+        if !self.allows(failed) {
+            return None;
+        }
+        let factor = match self.backoff {
+            Backoff::Constant => 1,
+            Backoff::Linear => failed,
+            Backoff::Exponential => 1u32.checked_shl(failed - 1).unwrap_or(u32::MAX),
+        };
+        let delay = self
+            .initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return Some(delay);
+        }
+        let random = rng.next_u64() as f64 / u64::MAX as f64;
+        Some(delay.mul_f64(1.0 - jitter * random))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SeededRng;
+
+    #[test]
+    fn retry_delays_back_off() {
+        let rng = SeededRng::new(1);
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            backoff: Backoff::Exponential,
+            jitter: 0.0,
+        };
+        let delays: Vec<Option<Duration>> = (0..6).map(|n| policy.delay(n, &rng)).collect();
+        assert_eq!(
+            delays,
+            [0, 100, 200, 400, 500]
+                .map(|millis| Some(Duration::from_millis(millis)))
+                .into_iter()
+                .chain([None])
+                .collect::<Vec<_>>()
+        );
+        let linear = RetryPolicy {
+            backoff: Backoff::Linear,
+            ..policy
+        };
+        assert_eq!(linear.delay(3, &rng), Some(Duration::from_millis(300)));
+        assert_eq!(RetryPolicy::never().delay(1, &rng), None);
+
+        // Jitter only shortens delays
+        let jittered = RetryPolicy {
+            jitter: 0.5,
+            ..policy
+        };
+        for _ in 0..100 {
+            let delay = jittered.delay(2, &rng).unwrap();
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+    }
+}
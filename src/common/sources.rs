@@ -0,0 +1,180 @@
+//! Sources of time and randomness. Defaults read the OS, tests and simulations can swap in a
+//! [`ManualClock`] and a [`SeededRng`] to make runs reproducible.
+use rand::rngs::{OsRng, StdRng};
+use rand::{RngCore, SeedableRng};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Source of the current time.
+pub trait Clock: Debug + Send + Sync {
+    /// Current time.
+    fn now(&self) -> Instant;
+
+    /// Current wall-clock time since the Unix epoch, for timestamps sent to others, e.g. of
+    /// nostr events.
+    fn unix_time(&self) -> Duration;
+}
+
+/// Clock reading the monotonic OS clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn unix_time(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+}
+
+/// Clock that only moves when advanced. Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    start: Instant,
+    unix_start: Duration,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    /// Create a clock standing still at the time of creation.
+    pub fn new() -> Self {
+        Self::at_unix_time(SystemClock.unix_time())
+    }
+
+    /// Create a clock standing still at the given wall-clock time since the Unix epoch.
+    pub fn at_unix_time(unix_time: Duration) -> Self {
+        Self {
+            start: Instant::now(),
+            unix_start: unix_time,
+            elapsed: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    /// Move the clock forward.
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().expect("Clock lock poisoned") += duration;
+    }
+
+    /// Time the clock has been advanced since its creation.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().expect("Clock lock poisoned")
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn unix_time(&self) -> Duration {
+        self.unix_start + self.elapsed()
+    }
+}
+
+/// Source of randomness, used for key pairs and random sampling.
+pub trait Rng: Debug + Send + Sync {
+    /// Fill `dest` with random bytes.
+    fn fill_bytes(&self, dest: &mut [u8]);
+
+    /// Random u64.
+    fn next_u64(&self) -> u64 {
+        let mut bytes = [0; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    /// Uniformly random u64 below `bound`, which must not be 0.
+    fn below(&self, bound: u64) -> u64 {
+        assert!(bound > 0, "Bound should not be 0");
+        // Reject the top values that would make the modulo biased
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next_u64();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+}
+
+/// Cryptographically secure randomness of the OS.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsRandom;
+
+impl Rng for OsRandom {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        OsRng.fill_bytes(dest);
+    }
+}
+
+/// Deterministic randomness from a seed, for tests and simulations only: key pairs generated
+/// from it are as predictable as the seed. Clones share the same stream.
+#[derive(Debug, Clone)]
+pub struct SeededRng {
+    inner: Arc<Mutex<StdRng>>,
+}
+
+impl SeededRng {
+    /// Create a random stream from a seed.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(StdRng::seed_from_u64(seed))),
+        }
+    }
+}
+
+impl Rng for SeededRng {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        self.inner
+            .lock()
+            .expect("Rng lock poisoned")
+            .fill_bytes(dest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HypercoreBuilder, HypercoreError, Storage};
+
+    #[async_std::test]
+    async fn seeded_sources_are_reproducible() -> Result<(), HypercoreError> {
+        let mut public_keys = vec![];
+        for seed in [7, 7, 8] {
+            let hypercore = HypercoreBuilder::new(Storage::new_memory().await?)
+                .rng(Arc::new(SeededRng::new(seed)))
+                .build()
+                .await?;
+            public_keys.push(hypercore.key_pair().public);
+        }
+        assert_eq!(public_keys[0], public_keys[1]);
+        assert_ne!(public_keys[0], public_keys[2]);
+
+        let rng = SeededRng::new(1);
+        let values: Vec<u64> = (0..100).map(|_| rng.below(10)).collect();
+        assert!(values.iter().all(|value| *value < 10));
+        let rng = SeededRng::new(1);
+        assert_eq!(values, (0..100).map(|_| rng.below(10)).collect::<Vec<_>>());
+
+        let clock = ManualClock::new();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+        clock.clone().advance(Duration::from_secs(5));
+        assert_eq!(clock.now(), start + Duration::from_secs(5));
+        let clock = ManualClock::at_unix_time(Duration::from_secs(1_700_000_000));
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(clock.unix_time(), Duration::from_secs(1_700_000_005));
+        Ok(())
+    }
+}
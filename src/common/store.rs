@@ -1,5 +1,7 @@
-/// The types of stores that can be created.
+/// The types of stores that can be created. Extensions of the core, like the checksums of the
+/// blocks, keep their data in named [`Store::Aux`] stores, and more kinds of stores may be added.
 #[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub enum Store {
     /// Tree
     Tree,
@@ -9,6 +11,15 @@ pub enum Store {
     Bitfield,
     /// Oplog
     Oplog,
+    /// Auxiliary store of an extension, e.g. an index, by name, see [`crate::Storage::aux`]
+    Aux(String),
+}
+
+impl Store {
+    /// Auxiliary store with the given name.
+    pub(crate) fn aux(name: &str) -> Self {
+        Store::Aux(name.to_string())
+    }
 }
 
 impl std::fmt::Display for Store {
@@ -18,6 +29,7 @@ impl std::fmt::Display for Store {
             Store::Data => write!(f, "data"),
             Store::Bitfield => write!(f, "bitfield"),
             Store::Oplog => write!(f, "oplog"),
+            Store::Aux(name) => write!(f, "aux-{name}"),
         }
     }
 }
@@ -110,6 +122,8 @@ pub(crate) struct StoreInfoInstruction {
     pub(crate) index: u64,
     pub(crate) length: Option<u64>,
     pub(crate) allow_miss: bool,
+    /// When reading past the end of the store, return only what is there instead of missing.
+    pub(crate) allow_partial: bool,
 }
 
 impl StoreInfoInstruction {
@@ -120,6 +134,7 @@ impl StoreInfoInstruction {
             index,
             length: Some(length),
             allow_miss: false,
+            allow_partial: false,
         }
     }
 
@@ -130,6 +145,18 @@ impl StoreInfoInstruction {
             index,
             length: Some(length),
             allow_miss: true,
+            allow_partial: false,
+        }
+    }
+
+    pub(crate) fn new_content_allow_partial(store: Store, index: u64, length: u64) -> Self {
+        Self {
+            store,
+            info_type: StoreInfoType::Content,
+            index,
+            length: Some(length),
+            allow_miss: true,
+            allow_partial: true,
         }
     }
 
@@ -140,6 +167,7 @@ impl StoreInfoInstruction {
             index: 0,
             length: None,
             allow_miss: false,
+            allow_partial: false,
         }
     }
 
@@ -150,6 +178,7 @@ impl StoreInfoInstruction {
             index,
             length: None,
             allow_miss: false,
+            allow_partial: false,
         }
     }
 }
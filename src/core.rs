@@ -1,27 +1,74 @@
 //! Hypercore's main abstraction. Exposes an append-only, secure log structure.
-use ed25519_dalek::Signature;
+use ed25519_dalek::{Signature, VerifyingKey};
 use futures::future::Either;
+use futures::stream::{self, Stream, StreamExt};
+use std::collections::BTreeSet;
 use std::convert::TryFrom;
 use std::fmt::Debug;
+use std::ops::Range;
+use std::sync::Arc;
 use tracing::instrument;
 
 #[cfg(feature = "cache")]
 use crate::common::cache::CacheOptions;
+#[cfg(feature = "cache")]
+use crate::encoding::{CompactEncoding, HypercoreState};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::verify_pool::{VerifiedProof, VerifyJob};
 use crate::{
-    bitfield::Bitfield,
-    common::{BitfieldUpdate, HypercoreError, NodeByteRange, Proof, StoreInfo, ValuelessProof},
-    crypto::{generate_signing_key, PartialKeypair},
+    acl::{readable_ranges, Restriction, ACL_KEY_PREFIX},
+    annotation::{AnnotationStore, ANNOTATION_STORE},
+    append_entry::AppendEntry,
+    bitfield::{Bitfield, BitfieldFormat},
+    bundle::{bundle_record, decode_bundle, encode_bundle, RecordId},
+    checksum::{ChecksumStore, CHECKSUM_SIZE, CHECKSUM_STORE},
+    chunking::{Chunking, PayloadStats},
+    common::{
+        check_append, AppendGrowth, AppendPolicy, BitfieldUpdate, ByteRangePlan, HypercoreError,
+        Limits, NodeByteRange, OsRandom, Progress, Proof, Rng, Store, StoreInfo,
+        StoreInfoInstruction, ValuelessProof,
+    },
+    crypto::{
+        generate_signing_key_with, Hash, PartialKeypair, SignatureVerifier, Signer, SignerKey,
+    },
     data::BlockStore,
-    oplog::{Header, Oplog, MAX_OPLOG_ENTRIES_BYTE_SIZE},
-    storage::Storage,
-    tree::{MerkleTree, MerkleTreeChangeset},
-    RequestBlock, RequestSeek, RequestUpgrade,
+    download::{DownloadProgress, DownloadStore, WantedRange, DOWNLOAD_STORE},
+    inclusion::{root_indices, Checkpoint, ConsistencyProof, InclusionProof, SignedHead},
+    oplog::{Header, Oplog},
+    receipt::PinReceipt,
+    record::FieldDisclosure,
+    storage::{AuxStore, SlowIoWatchdog, Storage},
+    tree::{verify_upgrade, LeafHasher, LocalSeek, MerkleTree, MerkleTreeChangeset},
+    Node, RequestBlock, RequestSeek, RequestUpgrade,
 };
 
-#[derive(Debug)]
+/// Byte size of data buffered before writing it to the block store when appending from an
+/// iterator.
+const APPEND_ITER_WRITE_BYTE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Number of blocks whose leaf nodes are read at once when checking the bitfield on open.
+const REPAIR_LEAF_BATCH_LENGTH: u64 = 16 * 1024;
+
+/// Number of block checksums read at once when scrubbing.
+const SCRUB_CHECKSUM_BATCH_LENGTH: u64 = 1024;
+
+/// Index and locally present value of a block yielded by [`Hypercore::diff`].
+type DiffEntry = (u64, Option<Vec<u8>>);
+
 pub(crate) struct HypercoreOptions {
     pub(crate) key_pair: Option<PartialKeypair>,
+    pub(crate) signer: Option<Arc<dyn Signer>>,
+    pub(crate) signer_key: Option<SignerKey>,
     pub(crate) open: bool,
+    pub(crate) tree_page_reads: bool,
+    pub(crate) limits: Limits,
+    pub(crate) rng: Arc<dyn Rng>,
+    pub(crate) append_policy: Option<Arc<dyn AppendPolicy>>,
+    pub(crate) bitfield_format: BitfieldFormat,
+    #[cfg(feature = "encryption")]
+    pub(crate) encryption_key: Option<[u8; 32]>,
+    #[cfg(feature = "parallel")]
+    pub(crate) hash_threads: Option<usize>,
     #[cfg(feature = "cache")]
     pub(crate) node_cache_options: Option<CacheOptions>,
 }
@@ -30,24 +77,75 @@ impl HypercoreOptions {
     pub(crate) fn new() -> Self {
         Self {
             key_pair: None,
+            signer: None,
+            signer_key: None,
             open: false,
+            tree_page_reads: false,
+            limits: Limits::default(),
+            rng: Arc::new(OsRandom),
+            append_policy: None,
+            bitfield_format: BitfieldFormat::default(),
+            #[cfg(feature = "encryption")]
+            encryption_key: None,
+            #[cfg(feature = "parallel")]
+            hash_threads: None,
             #[cfg(feature = "cache")]
             node_cache_options: None,
         }
     }
 }
 
+impl Debug for HypercoreOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("HypercoreOptions");
+        debug
+            .field("key_pair", &self.key_pair)
+            .field("signer", &self.signer)
+            .field("signer_key", &self.signer_key)
+            .field("open", &self.open)
+            .field("tree_page_reads", &self.tree_page_reads)
+            .field("limits", &self.limits)
+            .field("rng", &self.rng)
+            .field("append_policy", &self.append_policy)
+            .field("bitfield_format", &self.bitfield_format);
+        // Like BlockEncryption, never print the key
+        #[cfg(feature = "encryption")]
+        debug.field("encryption_key", &self.encryption_key.map(|_| "<redacted>"));
+        #[cfg(feature = "parallel")]
+        debug.field("hash_threads", &self.hash_threads);
+        #[cfg(feature = "cache")]
+        debug.field("node_cache_options", &self.node_cache_options);
+        debug.finish()
+    }
+}
+
 /// Hypercore is an append-only log structure.
 #[derive(Debug)]
 pub struct Hypercore {
     pub(crate) key_pair: PartialKeypair,
+    /// Signer of the tree, `None` for read-only hypercores
+    signer: Option<Arc<dyn Signer>>,
+    verifier: SignatureVerifier,
     pub(crate) storage: Storage,
     pub(crate) oplog: Oplog,
     pub(crate) tree: MerkleTree,
     pub(crate) block_store: BlockStore,
+    pub(crate) checksum_store: ChecksumStore,
     pub(crate) bitfield: Bitfield,
+    pub(crate) download_store: DownloadStore,
+    pub(crate) annotation_store: AnnotationStore,
+    payload_stats: PayloadStats,
+    limits: Limits,
+    rng: Arc<dyn Rng>,
+    append_policy: Option<Arc<dyn AppendPolicy>>,
+    #[cfg(feature = "encryption")]
+    encryption: Option<crate::crypto::BlockEncryption>,
+    leaf_hasher: LeafHasher,
     skip_flush_count: u8, // autoFlush in Javascript
     header: Header,
+    /// Restrictions of the user data, decoded once and refreshed when it changes
+    restrictions: Vec<Restriction>,
+    recovery: RecoveryReport,
     #[cfg(feature = "replication")]
     events: crate::replication::events::Events,
 }
@@ -61,6 +159,57 @@ pub struct AppendOutcome {
     pub byte_length: u64,
 }
 
+/// Result of [`Hypercore::simulate_append`]: the state the hypercore would be in after the
+/// append.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulatedAppend {
+    /// Length of the hypercore after append
+    pub length: u64,
+    /// Byte length of the hypercore after append
+    pub byte_length: u64,
+    /// Fork of the hypercore
+    pub fork: u64,
+    /// Tree hash after append, i.e. the hash of the roots
+    pub hash: [u8; 32],
+    /// Payload the append signs, the tree hash with the length and fork
+    pub signable: Vec<u8>,
+}
+
+/// Outcome of [`Hypercore::hint_prefetch`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrefetchOutcome {
+    /// Blocks present locally that were read ahead
+    pub warmed: u64,
+    /// Missing blocks requested from peers
+    pub requested: u64,
+}
+
+/// What opening a hypercore recovered or discarded of state left behind by a crash, see
+/// [`Hypercore::recovery_report`]. Opening repairs these silently, applications can log or
+/// alert on them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Oplog header slot the header was read from, 0 or 1. `None` if the hypercore was
+    /// created, not opened.
+    pub header_slot: Option<u8>,
+    /// Oplog entries replayed into the tree and bitfield, written but not yet flushed
+    pub replayed_entries: u64,
+    /// Oplog entries of an unfinished atomic batch that were dropped
+    pub discarded_entries: u64,
+    /// Bytes at the end of the oplog that weren't a valid entry, e.g. one torn by a crash
+    pub discarded_bytes: u64,
+    /// Blocks whose bits were cleared from the bitfield as their tree nodes or data were lost,
+    /// checked only when data was written after the last flush
+    pub cleared_blocks: Vec<Range<u64>>,
+}
+
+impl RecoveryReport {
+    /// Was anything discarded or repaired. Replayed entries are part of a regular open.
+    pub fn is_clean(&self) -> bool {
+        self.discarded_entries == 0 && self.discarded_bytes == 0 && self.cleared_blocks.is_empty()
+    }
+}
+
 /// Info about the hypercore
 #[derive(Debug, PartialEq)]
 pub struct Info {
@@ -77,9 +226,79 @@ pub struct Info {
     pub writeable: bool,
 }
 
+/// Read transaction pinning the fork and length of a hypercore, see [`Hypercore::read_txn`].
+#[derive(Debug)]
+pub struct ReadTxn<'a> {
+    core: &'a Hypercore,
+    fork: u64,
+    length: u64,
+}
+
+impl ReadTxn<'_> {
+    /// Pinned fork.
+    pub fn fork(&self) -> u64 {
+        self.fork
+    }
+
+    /// Pinned length.
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// Read value at given index of the pinned state, see [`Hypercore::get_pinned`].
+    pub async fn get(&self, index: u64) -> Result<Option<Vec<u8>>, HypercoreError> {
+        self.core.get_pinned(self.fork, self.length, index).await
+    }
+}
+
 impl Hypercore {
+    /// Creates a new hypercore in the storage, or opens the one in it, signed by the given
+    /// signer, so the secret key never has to be in memory. Same as
+    /// [`HypercoreBuilder::signer`](crate::HypercoreBuilder::signer) with the defaults otherwise.
+    pub async fn new_with_signer(
+        storage: Storage,
+        signer: Arc<dyn Signer>,
+    ) -> Result<Hypercore, HypercoreError> {
+        let mut options = HypercoreOptions::new();
+        options.signer = Some(signer);
+        Hypercore::new(storage, options).await
+    }
+
+    /// Opens the hypercore with the given public key in the storage, or creates an empty one
+    /// to download it into, without any secret key: a follower of a core written elsewhere.
+    /// Appends fail with [`HypercoreError::NotWritable`], while proofs of peers are verified
+    /// and applied as usual. A secret key already in the storage stays stored, but isn't used.
+    /// Fails if the storage holds a hypercore with another public key.
+    pub async fn open_read_only(
+        storage: Storage,
+        public_key: VerifyingKey,
+    ) -> Result<Hypercore, HypercoreError> {
+        let mut options = HypercoreOptions::new();
+        options.key_pair = Some(PartialKeypair {
+            public: public_key,
+            secret: None,
+        });
+        let mut hypercore = Hypercore::new(storage, options).await?;
+        if hypercore.key_pair.public != public_key {
+            return Err(HypercoreError::BadArgument {
+                context: "Storage holds a hypercore with another public key".to_string(),
+            });
+        }
+        hypercore.key_pair.secret = None;
+        hypercore.signer = None;
+        Ok(hypercore)
+    }
+
     /// Creates/opens new hypercore using given storage and options
     pub(crate) async fn new(
+        storage: Storage,
+        options: HypercoreOptions,
+    ) -> Result<Hypercore, HypercoreError> {
+        // Opening holds much state across awaits, keep it off the stack of the callers
+        Box::pin(Self::open(storage, options)).await
+    }
+
+    async fn open(
         mut storage: Storage,
         mut options: HypercoreOptions,
     ) -> Result<Hypercore, HypercoreError> {
@@ -93,7 +312,7 @@ impl Hypercore {
             None
         } else {
             Some(options.key_pair.take().unwrap_or_else(|| {
-                let signing_key = generate_signing_key();
+                let signing_key = generate_signing_key_with(options.rng.as_ref());
                 PartialKeypair {
                     public: signing_key.verifying_key(),
                     secret: Some(signing_key),
@@ -101,25 +320,58 @@ impl Hypercore {
             }))
         };
 
+        let signer_key = match (&options.signer, options.signer_key) {
+            (Some(signer), Some(key)) if signer.key() != key => {
+                return Err(HypercoreError::BadArgument {
+                    context: "Signer does not match the signer key".to_string(),
+                });
+            }
+            (Some(signer), _) => Some(signer.key()),
+            (None, key) => key,
+        };
+
         // Open/create oplog
-        let mut oplog_open_outcome = match Oplog::open(&key_pair, None)? {
-            Either::Right(value) => value,
-            Either::Left(instruction) => {
-                let info = storage.read_info(instruction).await?;
-                match Oplog::open(&key_pair, Some(info))? {
-                    Either::Right(value) => value,
-                    Either::Left(_) => {
-                        return Err(HypercoreError::InvalidOperation {
-                            context: "Could not open oplog".to_string(),
-                        });
+        let mut oplog_open_outcome =
+            match Oplog::open(&key_pair, options.bitfield_format, signer_key, None)? {
+                Either::Right(value) => value,
+                Either::Left(instruction) => {
+                    let info = storage.read_info(instruction).await?;
+                    match Oplog::open(&key_pair, options.bitfield_format, signer_key, Some(info))? {
+                        Either::Right(value) => value,
+                        Either::Left(_) => {
+                            return Err(HypercoreError::InvalidOperation {
+                                context: "Could not open oplog".to_string(),
+                            });
+                        }
                     }
                 }
-            }
-        };
+            };
         storage
             .flush_infos(&oplog_open_outcome.infos_to_flush)
             .await?;
 
+        // The signer is recorded in the manifest when creating, and selected when opening
+        let manifest_signer_key = oplog_open_outcome.header.manifest.signer.key();
+        if signer_key.is_some_and(|key| key != manifest_signer_key) {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Hypercore is signed by a different {} key",
+                    manifest_signer_key.scheme
+                ),
+            });
+        }
+        let verifier = SignatureVerifier::new(&manifest_signer_key)?;
+        let signer = match (
+            options.signer.take(),
+            &oplog_open_outcome.header.key_pair.secret,
+        ) {
+            (Some(signer), _) => Some(signer),
+            (None, Some(secret)) if secret.key() == manifest_signer_key => {
+                Some(Arc::new(secret.clone()) as Arc<dyn Signer>)
+            }
+            (None, _) => None,
+        };
+
         // Open/create tree
         let mut tree = match MerkleTree::open(
             &oplog_open_outcome.header.tree,
@@ -145,20 +397,30 @@ impl Hypercore {
                 }
             }
         };
+        tree.page_reads = options.tree_page_reads;
 
         // Create block store instance
         let block_store = BlockStore::default();
 
-        // Open bitfield
-        let mut bitfield = match Bitfield::open(None) {
+        // Checksums, wanted ranges and annotations are kept in auxiliary stores
+        for name in [CHECKSUM_STORE, DOWNLOAD_STORE, ANNOTATION_STORE] {
+            storage.aux(name).await?;
+        }
+
+        // Create checksum store instance
+        let checksum_store = ChecksumStore::default();
+
+        // Open bitfield with the format it was created with
+        let bitfield_format = oplog_open_outcome.header.bitfield_format;
+        let mut bitfield = match Bitfield::open(bitfield_format, None) {
             Either::Right(value) => value,
             Either::Left(instruction) => {
                 let info = storage.read_info(instruction).await?;
-                match Bitfield::open(Some(info)) {
+                match Bitfield::open(bitfield_format, Some(info)) {
                     Either::Right(value) => value,
                     Either::Left(instruction) => {
                         let info = storage.read_info(instruction).await?;
-                        match Bitfield::open(Some(info)) {
+                        match Bitfield::open(bitfield_format, Some(info)) {
                             Either::Right(value) => value,
                             Either::Left(_) => {
                                 return Err(HypercoreError::InvalidOperation {
@@ -171,9 +433,74 @@ impl Hypercore {
             }
         };
 
+        // Open download store
+        let download_store = match DownloadStore::open(None)? {
+            Either::Right(value) => value,
+            Either::Left(instruction) => {
+                let info = storage.read_info(instruction).await?;
+                match DownloadStore::open(Some(info))? {
+                    Either::Right(value) => value,
+                    Either::Left(instruction) => {
+                        let info = storage.read_info(instruction).await?;
+                        match DownloadStore::open(Some(info))? {
+                            Either::Right(value) => value,
+                            Either::Left(_) => {
+                                return Err(HypercoreError::InvalidOperation {
+                                    context: "Could not open download store".to_string(),
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        };
+
+        // Open annotation store
+        let annotation_store = match AnnotationStore::open(None)? {
+            Either::Right(value) => value,
+            Either::Left(instruction) => {
+                let info = storage.read_info(instruction).await?;
+                match AnnotationStore::open(Some(info))? {
+                    Either::Right(value) => value,
+                    Either::Left(instruction) => {
+                        let info = storage.read_info(instruction).await?;
+                        match AnnotationStore::open(Some(info))? {
+                            Either::Right(value) => value,
+                            Either::Left(_) => {
+                                return Err(HypercoreError::InvalidOperation {
+                                    context: "Could not open annotation store".to_string(),
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        };
+
+        let mut recovery = RecoveryReport {
+            header_slot: oplog_open_outcome.header_slot,
+            replayed_entries: 0,
+            discarded_entries: oplog_open_outcome.discarded_entries,
+            discarded_bytes: oplog_open_outcome.discarded_bytes,
+            cleared_blocks: vec![],
+        };
+        if recovery.discarded_entries > 0 || recovery.discarded_bytes > 0 {
+            tracing::warn!(
+                "Discarded {} unfinished oplog entries and {} trailing bytes, the core was not closed cleanly",
+                recovery.discarded_entries,
+                recovery.discarded_bytes
+            );
+        }
+
         // Process entries stored only to the oplog and not yet flushed into bitfield or tree
         if let Some(entries) = oplog_open_outcome.entries {
+            recovery.replayed_entries = entries.len() as u64;
             for entry in entries.iter() {
+                if let Some((key, value)) = &entry.user_data {
+                    oplog_open_outcome
+                        .header
+                        .set_user_data(key, value.as_deref());
+                }
                 for node in &entry.tree_nodes {
                     tree.add_node(node.clone());
                 }
@@ -239,19 +566,191 @@ impl Hypercore {
         let oplog = oplog_open_outcome.oplog;
         let header = oplog_open_outcome.header;
         let key_pair = header.key_pair.clone();
+        #[cfg(feature = "encryption")]
+        let encryption = options
+            .encryption_key
+            .map(|key| crate::crypto::BlockEncryption::new(&key, &key_pair.public));
 
-        Ok(Hypercore {
+        let mut hypercore = Hypercore {
             key_pair,
+            signer,
+            verifier,
             storage,
             oplog,
             tree,
             block_store,
+            checksum_store,
             bitfield,
+            download_store,
+            annotation_store,
+            payload_stats: PayloadStats::default(),
+            limits: options.limits,
+            rng: options.rng,
+            append_policy: options.append_policy,
+            #[cfg(feature = "encryption")]
+            encryption,
+            #[cfg(feature = "parallel")]
+            leaf_hasher: LeafHasher::new(options.hash_threads)?,
+            #[cfg(not(feature = "parallel"))]
+            leaf_hasher: LeafHasher::default(),
             header,
+            restrictions: vec![],
             skip_flush_count: 0,
+            recovery,
             #[cfg(feature = "replication")]
             events: crate::replication::events::Events::new(),
-        })
+        };
+        hypercore.refresh_restrictions();
+        // Only data written after the last flush can be missing under set bits
+        if hypercore.header.dirty {
+            hypercore.recovery.cleared_blocks = hypercore.repair_bitfield().await?;
+        }
+        Ok(hypercore)
+    }
+
+    /// What opening the hypercore recovered or discarded, e.g. oplog entries torn by a crash
+    /// or bitfield bits of lost data.
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery
+    }
+
+    /// Cross-checks every set bit of the bitfield with the tree and the data store, clearing
+    /// the bits of blocks past the tree, without a leaf node, or whose byte range ends past the
+    /// data store. The oplog, and with it the bitfield, can outlive a data write that was lost
+    /// in a crash, and reads of those blocks would then return garbage. Runs only when the
+    /// oplog header is dirty, as the check reads the whole tree. Returns the ranges of blocks
+    /// cleared.
+    async fn repair_bitfield(&mut self) -> Result<Vec<Range<u64>>, HypercoreError> {
+        let mut lost: Vec<Range<u64>> = vec![];
+        if let Some(start) = self.bitfield.index_of(true, self.tree.length) {
+            let last = self
+                .bitfield
+                .last_index_of(true, u64::MAX)
+                .expect("A bit past the tree should be set");
+            lost.push(start..last + 1);
+        }
+        for present in self.bitfield_snapshot() {
+            let mut start = present.start;
+            while start < present.end {
+                let blocks = start..present.end.min(start + REPAIR_LEAF_BATCH_LENGTH);
+                let info = self
+                    .storage
+                    .read_info(MerkleTree::leaves_instruction(&blocks))
+                    .await?;
+                for block in self.tree.missing_leaves(blocks.clone(), &info) {
+                    match lost.last_mut() {
+                        Some(range) if range.end == block => range.end += 1,
+                        _ => lost.push(block..block + 1),
+                    }
+                }
+                start = blocks.end;
+            }
+        }
+        for blocks in &lost {
+            self.clear_lost_blocks(blocks).await?;
+        }
+
+        // Byte ranges grow with the index, so binary search the first set bit whose data
+        // doesn't fit into the data store, all set bits from it on are lost
+        if let (Some(first), Some(last)) = (
+            self.bitfield.index_of(true, 0),
+            self.bitfield.last_index_of(true, u64::MAX),
+        ) {
+            let data_length = self
+                .storage
+                .read_info(StoreInfoInstruction::new_size(Store::Data, 0))
+                .await?
+                .length
+                .unwrap_or(0);
+            let (mut low, mut high) = (first, last + 1);
+            while low < high {
+                let middle = low + (high - low) / 2;
+                let block = match self.bitfield.index_of(true, middle) {
+                    Some(block) if block < high => block,
+                    _ => {
+                        high = middle;
+                        continue;
+                    }
+                };
+                if self.fits_data_store(block, data_length).await? {
+                    low = block + 1;
+                } else {
+                    high = block;
+                }
+            }
+            if let Some(start) = self.bitfield.index_of(true, low) {
+                let blocks = start..last + 1;
+                self.clear_lost_blocks(&blocks).await?;
+                lost.push(blocks);
+            }
+        }
+        lost.sort_by_key(|blocks| blocks.start);
+
+        if !lost.is_empty() {
+            let count: u64 = lost.iter().map(|blocks| blocks.end - blocks.start).sum();
+            tracing::warn!(
+                "Cleared {count} blocks in {lost:?} that have no tree nodes or data, the core was not closed cleanly"
+            );
+        }
+        Ok(lost)
+    }
+
+    /// Does the byte range of the block end within the data store.
+    async fn fits_data_store(&self, index: u64, data_length: u64) -> Result<bool, HypercoreError> {
+        match self.byte_range(index, None).await {
+            Ok(range) => Ok(range.index + range.length <= data_length),
+            Err(err @ HypercoreError::IO { .. }) => Err(err),
+            // Missing tree nodes make the block as bogus as missing data
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Clears the bits of blocks found lost by [`Hypercore::repair_bitfield`].
+    async fn clear_lost_blocks(&mut self, blocks: &Range<u64>) -> Result<(), HypercoreError> {
+        let infos_to_flush = self.oplog.clear(blocks.start, blocks.end)?;
+        self.storage.flush_infos(&infos_to_flush).await?;
+        self.bitfield
+            .set_range(blocks.start, blocks.end - blocks.start, false);
+        if blocks.start < self.header.hints.contiguous_length {
+            self.header.hints.contiguous_length = blocks.start;
+        }
+        Ok(())
+    }
+
+    /// Creates a new hypercore from an iterator of blocks. Produces the same tree, data and
+    /// bitfield stores as appending the blocks one by one, but buffers data into large sequential
+    /// writes and hashes and signs the tree only once, which makes initial publishing of big
+    /// datasets considerably faster.
+    #[instrument(err, skip_all)]
+    pub async fn create_from_iter<A: AsRef<[u8]>, I: IntoIterator<Item = A>>(
+        storage: Storage,
+        key_pair: PartialKeypair,
+        blocks: I,
+    ) -> Result<Hypercore, HypercoreError> {
+        Self::create_from_iter_with_progress(storage, key_pair, blocks, &Progress::new(0)).await
+    }
+
+    /// Same as [`Hypercore::create_from_iter`], but reports appended blocks and bytes to the
+    /// given `progress`, which is marked finished once the hypercore has been created.
+    #[instrument(err, skip_all)]
+    pub async fn create_from_iter_with_progress<A: AsRef<[u8]>, I: IntoIterator<Item = A>>(
+        storage: Storage,
+        key_pair: PartialKeypair,
+        blocks: I,
+        progress: &Progress,
+    ) -> Result<Hypercore, HypercoreError> {
+        let mut options = HypercoreOptions::new();
+        options.key_pair = Some(key_pair);
+        let mut hypercore = Hypercore::new(storage, options).await?;
+        if hypercore.tree.length > 0 {
+            return Err(HypercoreError::BadArgument {
+                context: "Can not create hypercore from iterator into non-empty storage"
+                    .to_string(),
+            });
+        }
+        hypercore.append_iter(blocks, progress).await?;
+        progress.finish();
+        Ok(hypercore)
     }
 
     /// Gets basic info about the Hypercore
@@ -261,7 +760,7 @@ impl Hypercore {
             byte_length: self.tree.byte_length,
             contiguous_length: self.header.hints.contiguous_length,
             fork: self.tree.fork,
-            writeable: self.key_pair.secret.is_some(),
+            writeable: self.signer.is_some(),
         }
     }
 
@@ -277,86 +776,831 @@ impl Hypercore {
         &mut self,
         batch: B,
     ) -> Result<AppendOutcome, HypercoreError> {
-        let secret_key = match &self.key_pair.secret {
-            Some(key) => key,
+        let sizes: Vec<usize> = batch
+            .as_ref()
+            .iter()
+            .map(|data| data.as_ref().len())
+            .collect();
+        let outcome = self.append_blocks(batch).await?;
+        for size in sizes {
+            self.payload_stats.record(size);
+        }
+        Ok(outcome)
+    }
+
+    /// Compute the length, byte length, tree hash and signature payload that appending the
+    /// batch would result in, without writing anything. Lets a writer publish the hash before
+    /// the append, e.g. in a nostr event, or take part in a two-phase commit. Fails like the
+    /// append would on exceeded limits or quota, but works on read-only hypercores too. Fails
+    /// on encrypted hypercores, whose blocks are encrypted with random nonces.
+    pub fn simulate_append<A: AsRef<[u8]>, B: AsRef<[A]>>(
+        &self,
+        batch: B,
+    ) -> Result<SimulatedAppend, HypercoreError> {
+        #[cfg(feature = "encryption")]
+        if self.encryption.is_some() {
+            return Err(HypercoreError::InvalidOperation {
+                context: "Can not simulate appends to an encrypted hypercore".to_string(),
+            });
+        }
+        self.limits.check_batch_length(batch.as_ref().len())?;
+        let mut changeset = self.tree.changeset();
+        for data in batch.as_ref().iter() {
+            self.limits.check_value_size(data.as_ref().len())?;
+            changeset.append(data.as_ref());
+        }
+        check_append(
+            self.append_policy.as_deref(),
+            AppendGrowth {
+                length: self.tree.length,
+                byte_length: self.tree.byte_length,
+                blocks: changeset.batch_length,
+                bytes: changeset.byte_length - self.tree.byte_length,
+            },
+        )?;
+        let hash = changeset.hash();
+        Ok(SimulatedAppend {
+            length: changeset.length,
+            byte_length: changeset.byte_length,
+            fork: changeset.fork,
+            signable: changeset.signable(&hash).into_vec(),
+            hash: hash[..]
+                .try_into()
+                .expect("BLAKE2b-256 hash should be 32 bytes"),
+        })
+    }
+
+    /// Appends a stream of payloads, split into blocks according to `chunking`. Payloads are
+    /// appended in batches of up to `APPEND_ITER_WRITE_BYTE_SIZE` bytes, each as a signed
+    /// upgrade.
+    #[instrument(err, skip_all)]
+    pub async fn append_stream<A: AsRef<[u8]>, S: Stream<Item = A> + Unpin>(
+        &mut self,
+        mut payloads: S,
+        chunking: Chunking,
+    ) -> Result<AppendOutcome, HypercoreError> {
+        if self.signer.is_none() {
+            return Err(HypercoreError::NotWritable);
+        }
+        let block_size = match chunking {
+            Chunking::Payload => None,
+            Chunking::Fixed(0) => {
+                return Err(HypercoreError::BadArgument {
+                    context: "Block size must be greater than zero".to_string(),
+                });
+            }
+            Chunking::Fixed(block_size) => Some(block_size),
+            Chunking::Auto => Some(self.payload_stats.suggested_block_size()),
+        };
+        let mut blocks: Vec<Vec<u8>> = Vec::new();
+        let mut blocks_byte_length: usize = 0;
+        let mut partial: Vec<u8> = Vec::new();
+        while let Some(payload) = payloads.next().await {
+            let payload = payload.as_ref();
+            self.payload_stats.record(payload.len());
+            let Some(block_size) = block_size else {
+                blocks_byte_length += payload.len();
+                blocks.push(payload.to_vec());
+                if self.stream_batch_full(&blocks, blocks_byte_length) {
+                    self.append_blocks(&blocks).await?;
+                    blocks.clear();
+                    blocks_byte_length = 0;
+                }
+                continue;
+            };
+            let mut offset = 0;
+            while offset < payload.len() {
+                let end = payload.len().min(offset + block_size - partial.len());
+                if partial.is_empty() && end - offset == block_size {
+                    blocks.push(payload[offset..end].to_vec());
+                } else {
+                    partial.extend_from_slice(&payload[offset..end]);
+                    if partial.len() < block_size {
+                        break;
+                    }
+                    blocks.push(std::mem::take(&mut partial));
+                }
+                offset = end;
+                blocks_byte_length += block_size;
+                if self.stream_batch_full(&blocks, blocks_byte_length) {
+                    self.append_blocks(&blocks).await?;
+                    blocks.clear();
+                    blocks_byte_length = 0;
+                }
+            }
+        }
+        if !partial.is_empty() {
+            blocks.push(partial);
+        }
+        self.append_blocks(&blocks).await
+    }
+
+    /// Whether the blocks buffered by [`Hypercore::append_stream`] should be appended before
+    /// buffering more.
+    fn stream_batch_full(&self, blocks: &[Vec<u8>], byte_length: usize) -> bool {
+        byte_length >= APPEND_ITER_WRITE_BYTE_SIZE || blocks.len() >= self.limits.max_batch_length
+    }
+
+    async fn append_blocks<A: AsRef<[u8]>, B: AsRef<[A]>>(
+        &mut self,
+        batch: B,
+    ) -> Result<AppendOutcome, HypercoreError> {
+        #[cfg(feature = "encryption")]
+        if let Some(encryption) = &self.encryption {
+            let start = self.tree.length;
+            let blocks: Vec<Vec<u8>> = batch
+                .as_ref()
+                .iter()
+                .enumerate()
+                .map(|(i, value)| {
+                    encryption.encrypt(start + i as u64, value.as_ref(), self.rng.as_ref())
+                })
+                .collect();
+            return Box::pin(self.append_stored_blocks(&blocks)).await;
+        }
+        Box::pin(self.append_stored_blocks(batch)).await
+    }
+
+    /// Appends blocks as they are to be stored, i.e. encrypted for encrypted cores.
+    async fn append_stored_blocks<A: AsRef<[u8]>, B: AsRef<[A]>>(
+        &mut self,
+        batch: B,
+    ) -> Result<AppendOutcome, HypercoreError> {
+        let signer = match &self.signer {
+            Some(signer) => signer.clone(),
             None => return Err(HypercoreError::NotWritable),
         };
 
+        self.limits.check_batch_length(batch.as_ref().len())?;
+        let mut bytes: u64 = 0;
+        for data in batch.as_ref().iter() {
+            self.limits.check_value_size(data.as_ref().len())?;
+            bytes += data.as_ref().len() as u64;
+        }
+        check_append(
+            self.append_policy.as_deref(),
+            AppendGrowth {
+                length: self.tree.length,
+                byte_length: self.tree.byte_length,
+                blocks: batch.as_ref().len() as u64,
+                bytes,
+            },
+        )?;
+
         if !batch.as_ref().is_empty() {
             // Create a changeset for the tree
             let mut changeset = self.tree.changeset();
+            let blocks: Vec<&[u8]> = batch.as_ref().iter().map(|data| data.as_ref()).collect();
             let mut batch_length: usize = 0;
-            for data in batch.as_ref().iter() {
-                batch_length += changeset.append(data.as_ref());
+            for (data, hash) in blocks.iter().zip(self.leaf_hasher.hash_leaves(&blocks)) {
+                changeset.append_hash(hash.to_vec(), data.len() as u64);
+                batch_length += data.len();
             }
-            changeset.hash_and_sign(secret_key);
+            changeset.hash_and_sign(signer.as_ref()).await?;
+            Box::pin(self.commit_append(changeset, &blocks, batch_length)).await?;
+        }
 
-            // Write the received data to the block store
-            let info =
-                self.block_store
-                    .append_batch(batch.as_ref(), batch_length, self.tree.byte_length);
-            self.storage.flush_info(info).await?;
+        // Return the new value
+        Ok(AppendOutcome {
+            length: self.tree.length,
+            byte_length: self.tree.byte_length,
+        })
+    }
 
-            // Append the changeset to the Oplog
-            let bitfield_update = BitfieldUpdate {
-                drop: false,
-                start: changeset.ancestors,
-                length: changeset.batch_length,
-            };
-            let outcome = self.oplog.append_changeset(
-                &changeset,
-                Some(bitfield_update.clone()),
-                false,
-                &self.header,
-            )?;
-            self.storage.flush_infos(&outcome.infos_to_flush).await?;
-            self.header = outcome.header;
+    /// Writes the blocks of a signed changeset appending them and commits it.
+    async fn commit_append(
+        &mut self,
+        changeset: MerkleTreeChangeset,
+        batch: &[&[u8]],
+        batch_length: usize,
+    ) -> Result<(), HypercoreError> {
+        // Write the received data to the block store
+        self.mark_dirty().await?;
+        let info = self
+            .block_store
+            .append_batch(batch, batch_length, self.tree.byte_length);
+        self.storage.flush_info(info).await?;
+        let info = self.checksum_store.put_batch(batch, changeset.ancestors);
+        self.storage.flush_info(info).await?;
 
-            // Write to bitfield
-            self.bitfield.update(&bitfield_update);
+        // Append the changeset to the Oplog
+        let bitfield_update = BitfieldUpdate {
+            drop: false,
+            start: changeset.ancestors,
+            length: changeset.batch_length,
+        };
+        let outcome = self.oplog.append_changeset(
+            &changeset,
+            Some(bitfield_update.clone()),
+            false,
+            &self.header,
+        )?;
+        self.storage.flush_infos(&outcome.infos_to_flush).await?;
+        self.header = outcome.header;
 
-            // Contiguous length is known only now
-            update_contiguous_length(&mut self.header, &self.bitfield, &bitfield_update);
+        // Write to bitfield
+        self.bitfield.update(&bitfield_update);
 
-            // Commit changeset to in-memory tree
-            self.tree.commit(changeset)?;
+        // Contiguous length is known only now
+        update_contiguous_length(&mut self.header, &self.bitfield, &bitfield_update);
 
-            // Now ready to flush
-            if self.should_flush_bitfield_and_tree_and_oplog() {
-                self.flush_bitfield_and_tree_and_oplog(false).await?;
-            }
+        // Commit changeset to in-memory tree
+        self.tree.commit(changeset)?;
 
-            #[cfg(feature = "replication")]
-            {
-                let _ = self.events.send(crate::replication::events::DataUpgrade {});
-                let _ = self
-                    .events
-                    .send(crate::replication::events::Have::from(&bitfield_update));
+        // Now ready to flush
+        if self.should_flush_bitfield_and_tree_and_oplog() {
+            self.flush_bitfield_and_tree_and_oplog(false).await?;
+        }
+
+        #[cfg(feature = "replication")]
+        {
+            let _ = self.events.send(crate::replication::events::DataUpgrade {});
+            let _ = self
+                .events
+                .send(crate::replication::events::Have::from(&bitfield_update));
+        }
+        Ok(())
+    }
+
+    /// Appends all blocks of given iterator as one signed upgrade. Data is written in chunks of
+    /// `APPEND_ITER_WRITE_BYTE_SIZE` and tree nodes are flushed as they are created, so memory use
+    /// stays bounded regardless of the amount of blocks.
+    async fn append_iter<A: AsRef<[u8]>, I: IntoIterator<Item = A>>(
+        &mut self,
+        blocks: I,
+        progress: &Progress,
+    ) -> Result<AppendOutcome, HypercoreError> {
+        let signer = match &self.signer {
+            Some(signer) => signer.clone(),
+            None => return Err(HypercoreError::NotWritable),
+        };
+
+        let mut changeset = self.tree.changeset();
+        let mut buffer: Vec<u8> = Vec::with_capacity(APPEND_ITER_WRITE_BYTE_SIZE);
+        let mut buffer_offset = self.tree.byte_length;
+        let mut checksums: Vec<u32> = Vec::new();
+        let mut checksums_index = self.tree.length;
+        for block in blocks {
+            let block = block.as_ref();
+            self.limits.check_value_size(block.len())?;
+            check_append(
+                self.append_policy.as_deref(),
+                AppendGrowth {
+                    length: changeset.length,
+                    byte_length: changeset.byte_length,
+                    blocks: 1,
+                    bytes: block.len() as u64,
+                },
+            )?;
+            changeset.append(block);
+            buffer.extend_from_slice(block);
+            checksums.push(ChecksumStore::checksum(block));
+            self.payload_stats.record(block.len());
+            progress.advance(1, block.len() as u64);
+            if buffer.len() >= APPEND_ITER_WRITE_BYTE_SIZE {
+                self.flush_append_iter_chunk(
+                    &mut changeset,
+                    &buffer,
+                    buffer_offset,
+                    &checksums,
+                    checksums_index,
+                )
+                .await?;
+                buffer_offset += buffer.len() as u64;
+                buffer.clear();
+                checksums_index += checksums.len() as u64;
+                checksums.clear();
             }
         }
+        if changeset.batch_length == 0 {
+            return Ok(AppendOutcome {
+                length: self.tree.length,
+                byte_length: self.tree.byte_length,
+            });
+        }
+        self.flush_append_iter_chunk(
+            &mut changeset,
+            &buffer,
+            buffer_offset,
+            &checksums,
+            checksums_index,
+        )
+        .await?;
+
+        // Only now sign the whole upgrade and write the resulting header
+        changeset.hash_and_sign(signer.as_ref()).await?;
+        let bitfield_update = BitfieldUpdate {
+            drop: false,
+            start: changeset.ancestors,
+            length: changeset.batch_length,
+        };
+        self.oplog.update_header_with_changeset(
+            &changeset,
+            Some(bitfield_update.clone()),
+            &mut self.header,
+        )?;
+        self.bitfield.update(&bitfield_update);
+        update_contiguous_length(&mut self.header, &self.bitfield, &bitfield_update);
+        self.tree.commit(changeset)?;
+        self.flush_bitfield_and_tree_and_oplog(false).await?;
+
+        #[cfg(feature = "replication")]
+        {
+            let _ = self.events.send(crate::replication::events::DataUpgrade {});
+            let _ = self
+                .events
+                .send(crate::replication::events::Have::from(&bitfield_update));
+        }
 
-        // Return the new value
         Ok(AppendOutcome {
             length: self.tree.length,
             byte_length: self.tree.byte_length,
         })
     }
 
-    #[cfg(feature = "replication")]
-    /// Subscribe to core events relevant to replication
+    async fn flush_append_iter_chunk(
+        &mut self,
+        changeset: &mut MerkleTreeChangeset,
+        buffer: &[u8],
+        buffer_offset: u64,
+        checksums: &[u32],
+        checksums_index: u64,
+    ) -> Result<(), HypercoreError> {
+        if !buffer.is_empty() {
+            self.mark_dirty().await?;
+            let info = self.block_store.put(buffer, buffer_offset);
+            self.storage.flush_info(info).await?;
+        }
+        if !checksums.is_empty() {
+            let info = self
+                .checksum_store
+                .put_checksums(checksums, checksums_index);
+            self.storage.flush_info(info).await?;
+        }
+        for node in changeset.nodes.drain(..) {
+            self.tree.add_node(node);
+        }
+        let infos = self.tree.flush();
+        self.storage.flush_infos(&infos).await?;
+        Ok(())
+    }
+
+    /// Replace the policy deciding whether appends are allowed, e.g. when the quota of the
+    /// owning user changes. `None` allows all appends.
+    pub fn set_append_policy(&mut self, append_policy: Option<Arc<dyn AppendPolicy>>) {
+        self.append_policy = append_policy;
+    }
+
+    /// On-disk format of the bitfield store, see [`HypercoreBuilder::bitfield_format`].
+    ///
+    /// [`HypercoreBuilder::bitfield_format`]: crate::HypercoreBuilder::bitfield_format
+    pub fn bitfield_format(&self) -> BitfieldFormat {
+        self.bitfield.format()
+    }
+
+    /// Measure the latency of every storage IO with the given watchdog, or stop measuring with
+    /// `None`. See [`Storage::set_slow_io_watchdog`].
+    pub fn set_slow_io_watchdog(&mut self, watchdog: Option<SlowIoWatchdog>) {
+        self.storage.set_slow_io_watchdog(watchdog);
+    }
+
+    /// The watchdog measuring storage IO latency, if any.
+    pub fn slow_io_watchdog(&self) -> Option<&SlowIoWatchdog> {
+        self.storage.slow_io_watchdog()
+    }
+
+    /// Watch the store files for changes made by another process, or stop watching. See
+    /// [`Storage::set_file_watch`].
+    #[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+    pub fn set_file_watch(&mut self, watch: bool) -> Result<(), HypercoreError> {
+        self.storage.set_file_watch(watch)
+    }
+
+    /// Check the watched store files for changes made by another process, see
+    /// [`Storage::set_file_watch`]. Fails with [`HypercoreError::ExternallyModified`] if there
+    /// are any, after dropping the cached tree nodes: the hypercore then has to be reopened,
+    /// and writes fail until it is.
+    pub fn check_files(&self) -> Result<(), HypercoreError> {
+        let result = self.storage.check_files();
+        #[cfg(feature = "cache")]
+        if result.is_err() {
+            self.tree.clear_node_cache();
+        }
+        result
+    }
+
+    /// Auxiliary store of an extension with the given name, created on first use. See
+    /// [`Storage::aux`].
+    pub async fn aux(&mut self, name: &str) -> Result<AuxStore<'_>, HypercoreError> {
+        self.storage.aux(name).await
+    }
+
+    /// Delete the auxiliary store with the given name, see [`Storage::remove_aux`].
+    pub async fn remove_aux(&mut self, name: &str) -> Result<(), HypercoreError> {
+        self.storage.remove_aux(name).await
+    }
+
+    /// Value of the user data under the given key, see [`Hypercore::set_user_data`].
+    pub fn get_user_data(&self, key: &str) -> Option<&[u8]> {
+        self.header.user_data(key)
+    }
+
+    /// Persist a small key/value pair in the oplog header, e.g. application metadata of the
+    /// core, or remove the key with `None`. An empty value removes the key too, as the
+    /// Javascript implementation can't tell it from a removal. User data is local, it isn't
+    /// replicated.
+    #[instrument(err, skip(self, value))]
+    pub async fn set_user_data(
+        &mut self,
+        key: &str,
+        value: Option<&[u8]>,
+    ) -> Result<(), HypercoreError> {
+        let value = value.filter(|value| !value.is_empty());
+        let infos_to_flush = self.oplog.set_user_data(key, value)?;
+        self.storage.flush_infos(&infos_to_flush).await?;
+        self.header.set_user_data(key, value);
+        if key.starts_with(ACL_KEY_PREFIX) {
+            self.refresh_restrictions();
+        }
+        if self.should_flush_bitfield_and_tree_and_oplog() {
+            self.flush_bitfield_and_tree_and_oplog(false).await?;
+        }
+        Ok(())
+    }
+
+    /// All user data, sorted by key.
+    pub fn user_data(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.header
+            .user_data
+            .iter()
+            .map(|(key, value)| (key.as_str(), &value[..]))
+    }
+
+    /// Only serve the blocks of the range to the peers with the given static public keys, see
+    /// [`crate::Restriction`]. Replaces a restriction starting at the same block. Restrictions
+    /// are stored in the user data, they aren't replicated.
+    pub async fn restrict(
+        &mut self,
+        range: Range<u64>,
+        readers: &[[u8; 32]],
+    ) -> Result<(), HypercoreError> {
+        if range.is_empty() {
+            return Err(HypercoreError::BadArgument {
+                context: format!("Can not restrict empty range {range:?}"),
+            });
+        }
+        let (key, value) = Restriction {
+            range,
+            readers: readers.to_vec(),
+        }
+        .encode();
+        self.set_user_data(&key, Some(&value)).await
+    }
+
+    /// Remove the restriction starting at the given block. Returns false if there was none.
+    pub async fn unrestrict(&mut self, start: u64) -> Result<bool, HypercoreError> {
+        let key = format!("{ACL_KEY_PREFIX}{start}");
+        if self.get_user_data(&key).is_none() {
+            return Ok(false);
+        }
+        self.set_user_data(&key, None).await?;
+        Ok(true)
+    }
+
+    /// The restrictions of the blocks of the core, see [`Hypercore::restrict`].
+    pub fn restrictions(&self) -> &[Restriction] {
+        &self.restrictions
+    }
+
+    fn refresh_restrictions(&mut self) {
+        self.restrictions = self
+            .user_data()
+            .filter_map(|(key, value)| Restriction::decode(key, value))
+            .collect();
+    }
+
+    /// Can the peer with the given static public key be sent the block, i.e. does every
+    /// restriction of the block list it as a reader.
+    pub fn is_readable_by(&self, index: u64, peer: &[u8; 32]) -> bool {
+        self.restrictions
+            .iter()
+            .all(|restriction| restriction.allows(index, peer))
+    }
+
+    /// The parts of the range the peer with the given static public key can be sent, see
+    /// [`Hypercore::is_readable_by`]. Splits the range only at the bounds of restrictions.
+    pub fn readable_ranges(&self, range: Range<u64>, peer: &[u8; 32]) -> Vec<Range<u64>> {
+        readable_ranges(&self.restrictions, range, peer)
+    }
+
+    /// Close the hypercore and delete its stores, see [`Storage::destroy`]. With `shred`, the
+    /// key pair is overwritten before it is deleted.
+    pub async fn destroy(self, shred: bool) -> Result<(), HypercoreError> {
+        self.storage.destroy(shred).await
+    }
+
+    /// Sizes of the payloads appended locally since the hypercore was opened.
+    pub fn payload_stats(&self) -> &PayloadStats {
+        &self.payload_stats
+    }
+
+    /// Chunking suggested for [`Hypercore::append_stream`] by the sizes of the payloads
+    /// appended so far, see [`PayloadStats::suggested_block_size`].
+    pub fn suggested_chunking(&self) -> Chunking {
+        Chunking::Fixed(self.payload_stats.suggested_block_size())
+    }
+
+    #[cfg(feature = "replication")]
+    /// Subscribe to core events relevant to replication
     pub fn event_subscribe(&self) -> async_broadcast::Receiver<crate::replication::events::Event> {
         self.events.channel.new_receiver()
     }
 
+    #[cfg(feature = "replication")]
+    /// Request the block at the given `index` from peers. Returns a receiver that gets a message
+    /// when the block has been stored locally, or `None` if the block is already present.
+    /// Concurrent requests for the same block share a single
+    /// [`Event::Get`](crate::replication::Event::Get).
+    pub fn request_block(&mut self, index: u64) -> Option<async_broadcast::Receiver<()>> {
+        if self.bitfield.get(index) {
+            return None;
+        }
+        Some(self.events.send_on_get(index))
+    }
+
+    /// Hint that the blocks at the given indices will be read soon, e.g. the next page of a
+    /// timeline, to hide the latency of reading them. Blocks present locally are read ahead:
+    /// their tree nodes go to the node cache, if enabled, and their data into the page cache
+    /// of the OS for disk storage. With `fetch`, missing blocks are requested from peers, see
+    /// [`Hypercore::request_block`], without waiting for them. Fetching needs the
+    /// `replication` feature, without it `fetch` fails with an invalid operation.
+    #[instrument(err, skip(self, indices))]
+    pub async fn hint_prefetch(
+        &mut self,
+        indices: impl IntoIterator<Item = u64>,
+        fetch: bool,
+    ) -> Result<PrefetchOutcome, HypercoreError> {
+        #[cfg(not(feature = "replication"))]
+        if fetch {
+            return Err(HypercoreError::InvalidOperation {
+                context: "Fetching missing blocks requires the replication feature".to_string(),
+            });
+        }
+        let indices: BTreeSet<u64> = indices.into_iter().collect();
+        let mut outcome = PrefetchOutcome::default();
+        for index in indices {
+            if self.bitfield.get(index) {
+                if index < self.tree.length {
+                    self.get_stored(index).await?;
+                    outcome.warmed += 1;
+                }
+            } else if fetch {
+                #[cfg(feature = "replication")]
+                {
+                    self.request_block(index);
+                    outcome.requested += 1;
+                }
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Mark blocks from `start` (inclusive) to `end` (exclusive) as wanted with the given
+    /// `priority`, or change the priority of an already wanted range. Wanted ranges are
+    /// persisted, so a download interrupted by a restart can be resumed from
+    /// [`download_progress`](Self::download_progress). Ranges are removed once all of their
+    /// blocks have been stored.
+    #[instrument(err, skip(self))]
+    pub async fn want(
+        &mut self,
+        start: u64,
+        end: u64,
+        priority: u64,
+    ) -> Result<(), HypercoreError> {
+        if start >= end {
+            return Err(HypercoreError::BadArgument {
+                context: format!("Wanted range start {start} must be smaller than end {end}"),
+            });
+        }
+        let infos = self.download_store.want(WantedRange {
+            start,
+            end,
+            priority,
+        })?;
+        self.storage.flush_infos(&infos).await?;
+        Ok(())
+    }
+
+    /// Stop wanting the range from `start` to `end`, returns true if it was wanted.
+    #[instrument(err, skip(self))]
+    pub async fn unwant(&mut self, start: u64, end: u64) -> Result<bool, HypercoreError> {
+        match self
+            .download_store
+            .remove(|wanted| wanted.start == start && wanted.end == end)?
+        {
+            Some(infos) => {
+                self.storage.flush_infos(&infos).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Progress of the wanted ranges, most urgent first.
+    pub fn download_progress(&self) -> Vec<DownloadProgress> {
+        self.download_store
+            .ranges()
+            .iter()
+            .map(|range| {
+                let mut downloaded: u64 = 0;
+                let mut next: Option<u64> = None;
+                let mut position = range.start;
+                while position < range.end {
+                    let missing = self
+                        .bitfield
+                        .index_of(false, position)
+                        .unwrap_or(range.end)
+                        .min(range.end);
+                    downloaded += missing - position;
+                    if missing == range.end {
+                        break;
+                    }
+                    if next.is_none() {
+                        next = Some(missing);
+                    }
+                    position = self
+                        .bitfield
+                        .index_of(true, missing)
+                        .unwrap_or(range.end)
+                        .min(range.end);
+                }
+                DownloadProgress {
+                    range: *range,
+                    downloaded,
+                    next,
+                }
+            })
+            .collect()
+    }
+
+    /// Attach local-only metadata, such as a label or a moderation flag, to the block at
+    /// `index`. Annotations are never replicated and don't change the log. An existing value
+    /// of the same `key` is replaced.
+    #[instrument(err, skip(self, value))]
+    pub async fn annotate(
+        &mut self,
+        index: u64,
+        key: &str,
+        value: &[u8],
+    ) -> Result<(), HypercoreError> {
+        self.put_annotation(index, key, Some(value)).await
+    }
+
+    /// Remove the annotation `key` of the block at `index`, returns true if it existed.
+    #[instrument(err, skip(self))]
+    pub async fn remove_annotation(
+        &mut self,
+        index: u64,
+        key: &str,
+    ) -> Result<bool, HypercoreError> {
+        let exists = self
+            .annotation_store
+            .get(index)
+            .is_some_and(|annotations| annotations.contains_key(key));
+        if exists {
+            self.put_annotation(index, key, None).await?;
+        }
+        Ok(exists)
+    }
+
+    /// Annotations of the block at `index` in key order.
+    pub fn annotations(&self, index: u64) -> Vec<(&str, &[u8])> {
+        self.annotation_store
+            .get(index)
+            .map(|annotations| {
+                annotations
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_slice()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    async fn put_annotation(
+        &mut self,
+        index: u64,
+        key: &str,
+        value: Option<&[u8]>,
+    ) -> Result<(), HypercoreError> {
+        if index >= self.tree.length {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Can not annotate block {index}, hypercore length is {}",
+                    self.tree.length
+                ),
+            });
+        }
+        let infos = self.annotation_store.put(index, key, value)?;
+        self.storage.flush_infos(&infos).await
+    }
+
     /// Check if core has the block at the given `index` locally
     #[instrument(ret, skip(self))]
     pub fn has(&self, index: u64) -> bool {
         self.bitfield.get(index)
     }
 
+    /// Iterate lazily over the blocks present locally, yielding the index and the byte range of
+    /// each block in the data store. Present blocks are found from the bitfield as the stream
+    /// is polled, so no list of indexes is built up front.
+    pub fn iter_available(
+        &mut self,
+    ) -> impl Stream<Item = Result<(u64, Range<u64>), HypercoreError>> + '_ {
+        stream::try_unfold((self, 0), |(core, position)| async move {
+            let index = match core.bitfield.index_of(true, position) {
+                Some(index) if index < core.tree.length => index,
+                _ => return Ok(None),
+            };
+            let byte_range = core.byte_range(index, None).await?;
+            let byte_range = byte_range.index..byte_range.index + byte_range.length;
+            Ok(Some(((index, byte_range), (core, index + 1))))
+        })
+    }
+
+    /// Ranges of blocks present locally, in order. Built from the bitfield a run of present
+    /// blocks at a time.
+    pub fn bitfield_snapshot(&self) -> Vec<Range<u64>> {
+        let mut ranges = vec![];
+        let mut position = 0;
+        while let Some(start) = self.bitfield.index_of(true, position) {
+            if start >= self.tree.length {
+                break;
+            }
+            let end = self
+                .bitfield
+                .index_of(false, start)
+                .unwrap_or(self.tree.length)
+                .min(self.tree.length);
+            ranges.push(start..end);
+            position = end;
+        }
+        ranges
+    }
+
+    /// Changes since a [`Checkpoint`] of the core, e.g. the one the application last looked
+    /// at, up to `to_length`. Yields the index of every block appended in between, with its
+    /// value if present locally. Layers built on top of a core, such as key-value stores, can
+    /// decode the values into key level changes.
+    ///
+    /// Fails if the core diverged from the checkpoint, i.e. it is on another fork or its tree
+    /// at the length of the checkpoint has another hash: the blocks before the checkpoint
+    /// changed too, so the application has to start over from an empty state.
+    pub async fn diff(
+        &mut self,
+        from: &Checkpoint,
+        to_length: u64,
+    ) -> Result<impl Stream<Item = Result<DiffEntry, HypercoreError>> + '_, HypercoreError> {
+        let from_length = from.length;
+        if from_length > to_length || to_length > self.tree.length {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Invalid diff from length {from_length} to length {to_length}, hypercore length is {}",
+                    self.tree.length
+                ),
+            });
+        }
+        self.checkpoint_roots(from).await?;
+        Ok(stream::try_unfold(
+            (self, from_length),
+            move |(core, index)| async move {
+                if index >= to_length {
+                    return Ok(None);
+                }
+                // Missing blocks are not requested from peers, as get would
+                let value = if core.bitfield.get(index) {
+                    core.get(index).await?
+                } else {
+                    None
+                };
+                Ok(Some(((index, value), (core, index + 1))))
+            },
+        ))
+    }
+
     /// Read value at given index, if any.
     #[instrument(err, skip(self))]
-    pub async fn get(&mut self, index: u64) -> Result<Option<Vec<u8>>, HypercoreError> {
+    pub async fn get(&self, index: u64) -> Result<Option<Vec<u8>>, HypercoreError> {
+        let value = Box::pin(self.get_stored(index)).await?;
+        #[cfg(feature = "encryption")]
+        if let (Some(encryption), Some(block)) = (&self.encryption, &value) {
+            return Ok(Some(encryption.decrypt(index, block)?));
+        }
+        Ok(value)
+    }
+
+    /// Read the block at given index as stored and hashed into the tree, i.e. encrypted for
+    /// encrypted cores.
+    async fn get_stored(&self, index: u64) -> Result<Option<Vec<u8>>, HypercoreError> {
         if !self.bitfield.get(index) {
             #[cfg(feature = "replication")]
             // if not in this core, emit Event::Get(index)
@@ -387,6 +1631,61 @@ impl Hypercore {
         Ok(Some(data.to_vec()))
     }
 
+    /// Append many small records as one block, see [`encode_bundle`](crate::encode_bundle).
+    /// Returns the ids of the records, for [`Hypercore::get_record`].
+    #[instrument(err, skip_all)]
+    pub async fn append_bundle<A: AsRef<[u8]>>(
+        &mut self,
+        records: &[A],
+    ) -> Result<Vec<RecordId>, HypercoreError> {
+        let bundle = encode_bundle(records)?;
+        let index = self.append(&bundle).await?.length - 1;
+        Ok((0..records.len() as u32)
+            .map(|record| RecordId { index, record })
+            .collect())
+    }
+
+    /// Read a record of a bundle appended with [`Hypercore::append_bundle`]. Returns `None` if
+    /// the block is missing or the bundle has no such record.
+    #[instrument(err, skip(self))]
+    pub async fn get_record(&self, id: RecordId) -> Result<Option<Vec<u8>>, HypercoreError> {
+        let Some(bundle) = self.get(id.index).await? else {
+            return Ok(None);
+        };
+        Ok(bundle_record(&bundle, id.record)?.map(|record| record.to_vec()))
+    }
+
+    /// Read all records of a bundle appended with [`Hypercore::append_bundle`], if the block
+    /// is present.
+    #[instrument(err, skip(self))]
+    pub async fn get_bundle(&self, index: u64) -> Result<Option<Vec<Vec<u8>>>, HypercoreError> {
+        let Some(bundle) = self.get(index).await? else {
+            return Ok(None);
+        };
+        Ok(Some(
+            decode_bundle(&bundle)?
+                .into_iter()
+                .map(|record| record.to_vec())
+                .collect(),
+        ))
+    }
+
+    /// Sync all storage files to disk, so that everything written so far survives a crash of
+    /// the machine. Appends and applied proofs are only written, not synced, so that many of
+    /// them can share one sync; see [`crate::CacheStore::commit`] for syncing many cores at once.
+    #[instrument(err, skip(self))]
+    pub async fn sync(&mut self) -> Result<(), HypercoreError> {
+        self.storage.sync_all().await
+    }
+
+    /// Write the pending bitfield and tree changes to their stores and rewrite the oplog with
+    /// just the header. This happens on its own every few writes, or sooner when the oplog
+    /// grows big; flushing before closing makes the next open skip replaying the oplog.
+    #[instrument(err, skip(self))]
+    pub async fn flush(&mut self) -> Result<(), HypercoreError> {
+        self.flush_bitfield_and_tree_and_oplog(false).await
+    }
+
     /// Clear data for entries between start and end (exclusive) indexes.
     #[instrument(err, skip(self))]
     pub async fn clear(&mut self, start: u64, end: u64) -> Result<(), HypercoreError> {
@@ -401,6 +1700,15 @@ impl Hypercore {
         // Set bitfield
         self.bitfield.set_range(start, end - start, false);
 
+        // Clear checksums
+        let checksum_store_length = self.checksum_store_length().await?;
+        if let Some(info_to_flush) =
+            self.checksum_store
+                .clear(start, end - start, checksum_store_length)
+        {
+            self.storage.flush_info(info_to_flush).await?;
+        }
+
         // Set contiguous length
         if start < self.header.hints.contiguous_length {
             self.header.hints.contiguous_length = start;
@@ -453,337 +1761,1175 @@ impl Hypercore {
         Ok(())
     }
 
-    /// Access the key pair.
-    pub fn key_pair(&self) -> &PartialKeypair {
-        &self.key_pair
-    }
-
-    /// Create a proof for given request
-    #[instrument(err, skip_all)]
-    pub async fn create_proof(
-        &mut self,
-        block: Option<RequestBlock>,
-        hash: Option<RequestBlock>,
-        seek: Option<RequestSeek>,
-        upgrade: Option<RequestUpgrade>,
-    ) -> Result<Option<Proof>, HypercoreError> {
-        let valueless_proof = self
-            .create_valueless_proof(block, hash, seek, upgrade)
-            .await?;
-        let value: Option<Vec<u8>> = if let Some(block) = valueless_proof.block.as_ref() {
-            let value = self.get(block.index).await?;
-            if value.is_none() {
-                // The data value requested in the proof can not be read, we return None here
-                // and let the party requesting figure out what to do.
-                return Ok(None);
-            }
-            value
-        } else {
-            None
+    /// Truncates the hypercore to `new_length` blocks and moves it to the given fork, like
+    /// `truncate` of the Javascript implementation. The truncation is a signed upgrade, so
+    /// peers replicate the new fork. Truncating to the current length only bumps the fork.
+    #[instrument(err, skip(self))]
+    pub async fn truncate(&mut self, new_length: u64, fork: u64) -> Result<(), HypercoreError> {
+        let signer = match &self.signer {
+            Some(signer) => signer.clone(),
+            None => return Err(HypercoreError::NotWritable),
         };
-        Ok(Some(valueless_proof.into_proof(value)))
-    }
-
-    /// Verify and apply proof received from peer, returns true if changed, false if not
-    /// possible to apply.
-    #[instrument(skip_all)]
-    pub async fn verify_and_apply_proof(&mut self, proof: &Proof) -> Result<bool, HypercoreError> {
-        if proof.fork != self.tree.fork {
-            return Ok(false);
+        if new_length > self.tree.length {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Can not truncate to {new_length}, length is {}",
+                    self.tree.length
+                ),
+            });
         }
-        let changeset = self.verify_proof(proof).await?;
-        if !self.tree.commitable(&changeset) {
-            return Ok(false);
+        if fork < self.tree.fork {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Can not truncate to fork {fork}, fork is {}",
+                    self.tree.fork
+                ),
+            });
         }
 
-        // In javascript there's _verifyExclusive and _verifyShared based on changeset.upgraded, but
-        // here we do only one. _verifyShared groups together many subsequent changesets into a single
-        // oplog push, and then flushes in the end only for the whole group.
-        let bitfield_update: Option<BitfieldUpdate> = if let Some(block) = &proof.block.as_ref() {
-            let byte_offset =
-                match self
-                    .tree
-                    .byte_offset_in_changeset(block.index, &changeset, None)?
-                {
+        let mut changeset = match self.tree.truncate(new_length, fork, None)? {
+            Either::Right(value) => value,
+            Either::Left(instructions) => {
+                let infos = self.storage.read_infos(&instructions).await?;
+                match self.tree.truncate(new_length, fork, Some(&infos))? {
                     Either::Right(value) => value,
-                    Either::Left(instructions) => {
-                        let infos = self.storage.read_infos_to_vec(&instructions).await?;
-                        match self.tree.byte_offset_in_changeset(
-                            block.index,
-                            &changeset,
-                            Some(&infos),
-                        )? {
-                            Either::Right(value) => value,
-                            Either::Left(_) => {
-                                return Err(HypercoreError::InvalidOperation {
-                                    context: format!(
-                                        "Could not read offset for index {} from tree",
-                                        block.index
-                                    ),
-                                });
-                            }
-                        }
+                    Either::Left(_) => {
+                        return Err(HypercoreError::InvalidOperation {
+                            context: format!("Could not truncate tree to length {new_length}"),
+                        });
                     }
-                };
-
-            // Write the value to the block store
-            let info_to_flush = self.block_store.put(&block.value, byte_offset);
-            self.storage.flush_info(info_to_flush).await?;
-
-            // Return a bitfield update for the given value
-            Some(BitfieldUpdate {
-                drop: false,
-                start: block.index,
-                length: 1,
-            })
-        } else {
-            // Only from DataBlock can there be changes to the bitfield
-            None
+                }
+            }
         };
+        changeset.hash_and_sign(signer.as_ref()).await?;
 
-        // Append the changeset to the Oplog
+        // Append the truncation to the Oplog, dropping the truncated blocks
+        let bitfield_update = BitfieldUpdate {
+            drop: true,
+            start: new_length,
+            length: self.tree.length - new_length,
+        };
         let outcome = self.oplog.append_changeset(
             &changeset,
-            bitfield_update.clone(),
+            Some(bitfield_update.clone()),
             false,
             &self.header,
         )?;
         self.storage.flush_infos(&outcome.infos_to_flush).await?;
         self.header = outcome.header;
 
-        if let Some(bitfield_update) = &bitfield_update {
-            // Write to bitfield
-            self.bitfield.update(bitfield_update);
-
-            // Contiguous length is known only now
-            update_contiguous_length(&mut self.header, &self.bitfield, bitfield_update);
+        self.bitfield.update(&bitfield_update);
+        if new_length < self.header.hints.contiguous_length {
+            self.header.hints.contiguous_length = new_length;
         }
 
-        // Commit changeset to in-memory tree
-        self.tree.commit(changeset)?;
+        // Remove the data and checksums of the truncated blocks
+        let byte_length = changeset.byte_length;
+        self.storage
+            .flush_info(self.block_store.truncate(byte_length))
+            .await?;
+        let checksum_store_length = self.checksum_store_length().await?;
+        if let Some(info) = self
+            .checksum_store
+            .truncate(new_length, checksum_store_length)
+        {
+            self.storage.flush_info(info).await?;
+        }
 
-        // Now ready to flush
-        if self.should_flush_bitfield_and_tree_and_oplog() {
-            self.flush_bitfield_and_tree_and_oplog(false).await?;
+        // Annotations and wanted ranges of the truncated blocks go with them
+        let infos = self.annotation_store.truncate(new_length)?;
+        self.storage.flush_infos(&infos).await?;
+        if let Some(infos) = self.download_store.truncate(new_length)? {
+            self.storage.flush_infos(&infos).await?;
         }
 
+        // Commit changeset to in-memory tree and flush right away, as the tree treats
+        // unflushed nodes past the truncation as deleted until then
+        self.tree.commit(changeset)?;
+        self.flush_bitfield_and_tree_and_oplog(false).await?;
+
         #[cfg(feature = "replication")]
         {
-            if proof.upgrade.is_some() {
-                // Notify replicator if we receieved an upgrade
-                let _ = self.events.send(crate::replication::events::DataUpgrade {});
-            }
-
-            // Notify replicator if we receieved a bitfield update
-            if let Some(ref bitfield) = bitfield_update {
-                let _ = self
-                    .events
-                    .send(crate::replication::events::Have::from(bitfield));
-            }
+            let _ = self.events.send(crate::replication::events::DataUpgrade {});
+            let _ = self
+                .events
+                .send(crate::replication::events::Have::from(&bitfield_update));
         }
-        Ok(true)
+        Ok(())
     }
 
-    /// Used to fill the nodes field of a `RequestBlock` during
-    /// synchronization.
+    /// Checks all locally stored blocks for corruption. Every block is first compared against
+    /// its stored CRC32 checksum, and only if that does not match, or is not known, hashed and
+    /// verified against the tree. Missing or wrong checksums of valid blocks are rewritten.
+    /// Returns the indices of the blocks that failed tree verification; clear them to have
+    /// them downloaded again.
     #[instrument(err, skip(self))]
-    pub async fn missing_nodes(&mut self, index: u64) -> Result<u64, HypercoreError> {
-        self.missing_nodes_from_merkle_tree_index(index * 2).await
+    pub async fn scrub(&mut self) -> Result<Vec<u64>, HypercoreError> {
+        self.scrub_blocks(None).await
     }
 
-    /// Get missing nodes using a merkle tree index. Advanced variant of missing_nodex
-    /// that allow for special cases of searching directly from the merkle tree.
-    #[instrument(err, skip(self))]
-    pub async fn missing_nodes_from_merkle_tree_index(
+    /// Same as [`Hypercore::scrub`], but reports checked blocks and bytes to the given
+    /// `progress`, which is marked finished once done.
+    #[instrument(err, skip_all)]
+    pub async fn scrub_with_progress(
         &mut self,
-        merkle_tree_index: u64,
-    ) -> Result<u64, HypercoreError> {
-        match self.tree.missing_nodes(merkle_tree_index, None)? {
-            Either::Right(value) => Ok(value),
-            Either::Left(instructions) => {
-                let mut instructions = instructions;
-                let mut infos: Vec<StoreInfo> = vec![];
-                loop {
-                    infos.extend(self.storage.read_infos_to_vec(&instructions).await?);
-                    match self.tree.missing_nodes(merkle_tree_index, Some(&infos))? {
-                        Either::Right(value) => {
-                            return Ok(value);
-                        }
-                        Either::Left(new_instructions) => {
-                            instructions = new_instructions;
-                        }
+        progress: &Progress,
+    ) -> Result<Vec<u64>, HypercoreError> {
+        let corrupt = self.scrub_blocks(Some(progress)).await?;
+        progress.finish();
+        Ok(corrupt)
+    }
+
+    async fn scrub_blocks(
+        &mut self,
+        progress: Option<&Progress>,
+    ) -> Result<Vec<u64>, HypercoreError> {
+        let length = self.tree.length;
+        if let Some(progress) = progress {
+            progress.set_total(length);
+        }
+        let stored_checksums_length = self.checksum_store_length().await? / CHECKSUM_SIZE;
+        let mut corrupt: Vec<u64> = Vec::new();
+        let mut batch_start: u64 = 0;
+        while batch_start < length {
+            let batch_length = std::cmp::min(SCRUB_CHECKSUM_BATCH_LENGTH, length - batch_start);
+            let stored_length = std::cmp::min(
+                batch_length,
+                stored_checksums_length.saturating_sub(batch_start),
+            );
+            let mut checksums = if stored_length > 0 {
+                self.read_checksums(batch_start, stored_length).await?
+            } else {
+                vec![]
+            };
+            checksums.resize(batch_length as usize, 0);
+
+            for (i, stored_checksum) in checksums.into_iter().enumerate() {
+                let index = batch_start + i as u64;
+                let Some(value) = self.get_stored(index).await? else {
+                    if let Some(progress) = progress {
+                        progress.advance(1, 0);
+                    }
+                    continue;
+                };
+                if stored_checksum != ChecksumStore::checksum(&value) {
+                    // Escalate to verifying the block against the tree
+                    let node = self.tree_node(index * 2).await?;
+                    if Hash::data(&value).as_bytes() == node.hash {
+                        let info_to_flush = self.checksum_store.put(&value, index);
+                        self.storage.flush_info(info_to_flush).await?;
+                    } else {
+                        corrupt.push(index);
                     }
                 }
+                if let Some(progress) = progress {
+                    progress.advance(1, value.len() as u64);
+                }
             }
+            batch_start += batch_length;
         }
+        Ok(corrupt)
     }
 
-    /// Makes the hypercore read-only by deleting the secret key. Returns true if the
-    /// hypercore was changed, false if the hypercore was already read-only. This is useful
-    /// in scenarios where a hypercore should be made immutable after initial values have
-    /// been stored.
-    #[instrument(err, skip_all)]
-    pub async fn make_read_only(&mut self) -> Result<bool, HypercoreError> {
-        if self.key_pair.secret.is_some() {
-            self.key_pair.secret = None;
-            self.header.key_pair.secret = None;
-            // Need to flush clearing traces to make sure both oplog slots are cleared
-            self.flush_bitfield_and_tree_and_oplog(true).await?;
-            Ok(true)
-        } else {
-            Ok(false)
+    /// Verifies `count` randomly picked locally stored blocks against the tree. Unlike
+    /// [`Hypercore::scrub`] this is cheap enough to run periodically, e.g. with
+    /// `SharedCore::scrubber`. Corrupt blocks are cleared so that they get downloaded again,
+    /// and reported with a `Corrupt` event. Returns the indices of the corrupt blocks.
+    #[instrument(err, skip(self))]
+    pub async fn scrub_random(&mut self, count: u64) -> Result<Vec<u64>, HypercoreError> {
+        let length = self.tree.length;
+        let mut corrupt: Vec<u64> = Vec::new();
+        if length == 0 {
+            return Ok(corrupt);
+        }
+        let starts: Vec<u64> = (0..count).map(|_| self.rng.below(length)).collect();
+        for start in starts {
+            // Take the next present block, wrapping around to the start
+            let present = |index: &u64| *index < length;
+            let Some(index) = self
+                .bitfield
+                .index_of(true, start)
+                .filter(present)
+                .or_else(|| self.bitfield.index_of(true, 0).filter(present))
+            else {
+                break;
+            };
+            let Some(value) = self.get_stored(index).await? else {
+                continue;
+            };
+            if !self.block_matches_tree(index, &value).await? {
+                self.clear(index, index + 1).await?;
+                #[cfg(feature = "replication")]
+                {
+                    let _ = self
+                        .events
+                        .send(crate::replication::events::Corrupt { index });
+                }
+                corrupt.push(index);
+            }
         }
+        Ok(corrupt)
     }
 
-    async fn byte_range(
-        &mut self,
+    /// Start a read transaction that pins the current fork and length, so that a sequence of
+    /// gets sees a consistent state. Mostly useful through `SharedCore::read_txn`, where other
+    /// owners may append or truncate between the gets.
+    pub fn read_txn(&self) -> ReadTxn<'_> {
+        ReadTxn {
+            fork: self.tree.fork,
+            length: self.tree.length,
+            core: self,
+        }
+    }
+
+    /// Read value at given index as of the given fork and length. Blocks below the pinned
+    /// length don't change while the fork stays the same, so this fails with an
+    /// `InvalidOperation` error only once the hypercore has been truncated to another fork,
+    /// and with a `BadArgument` error for blocks beyond the pinned length.
+    #[instrument(err, skip(self))]
+    pub async fn get_pinned(
+        &self,
+        fork: u64,
+        length: u64,
         index: u64,
-        initial_infos: Option<&[StoreInfo]>,
-    ) -> Result<NodeByteRange, HypercoreError> {
-        match self.tree.byte_range(index, initial_infos)? {
-            Either::Right(value) => Ok(value),
-            Either::Left(instructions) => {
-                let mut instructions = instructions;
-                let mut infos: Vec<StoreInfo> = vec![];
-                loop {
-                    infos.extend(self.storage.read_infos_to_vec(&instructions).await?);
-                    match self.tree.byte_range(index, Some(&infos))? {
-                        Either::Right(value) => {
-                            return Ok(value);
-                        }
-                        Either::Left(new_instructions) => {
-                            instructions = new_instructions;
-                        }
-                    }
-                }
-            }
+    ) -> Result<Option<Vec<u8>>, HypercoreError> {
+        if self.tree.fork != fork || self.tree.length < length {
+            return Err(HypercoreError::InvalidOperation {
+                context: format!(
+                    "Read transaction on fork {fork} and length {length} was invalidated, hypercore is on fork {} and length {}",
+                    self.tree.fork, self.tree.length
+                ),
+            });
         }
+        if index >= length {
+            return Err(HypercoreError::BadArgument {
+                context: format!("Block {index} is beyond the pinned length {length}"),
+            });
+        }
+        self.get(index).await
     }
 
-    async fn create_valueless_proof(
-        &mut self,
-        block: Option<RequestBlock>,
-        hash: Option<RequestBlock>,
-        seek: Option<RequestSeek>,
-        upgrade: Option<RequestUpgrade>,
-    ) -> Result<ValuelessProof, HypercoreError> {
-        match self.tree.create_valueless_proof(
-            block.as_ref(),
-            hash.as_ref(),
-            seek.as_ref(),
-            upgrade.as_ref(),
-            None,
-        )? {
-            Either::Right(value) => Ok(value),
-            Either::Left(instructions) => {
-                let mut instructions = instructions;
-                let mut infos: Vec<StoreInfo> = vec![];
-                loop {
-                    infos.extend(self.storage.read_infos_to_vec(&instructions).await?);
-                    match self.tree.create_valueless_proof(
-                        block.as_ref(),
-                        hash.as_ref(),
-                        seek.as_ref(),
-                        upgrade.as_ref(),
-                        Some(&infos),
-                    )? {
-                        Either::Right(value) => {
-                            return Ok(value);
-                        }
-                        Either::Left(new_instructions) => {
-                            instructions = new_instructions;
-                        }
-                    }
-                }
-            }
+    /// Read value at given index, if any, verifying it against the merkle tree. A block that
+    /// doesn't match its leaf hash is reported as an `InvalidChecksum` error.
+    #[instrument(err, skip(self))]
+    pub async fn get_verified(&self, index: u64) -> Result<Option<Vec<u8>>, HypercoreError> {
+        let Some(value) = self.get_stored(index).await? else {
+            return Ok(None);
+        };
+        if !self.block_matches_tree(index, &value).await? {
+            return Err(HypercoreError::InvalidChecksum {
+                context: format!("Block {index} does not match its hash in the tree"),
+            });
         }
+        #[cfg(feature = "encryption")]
+        if let Some(encryption) = &self.encryption {
+            return Ok(Some(encryption.decrypt(index, &value)?));
+        }
+        Ok(Some(value))
     }
 
-    /// Verify a proof received from a peer. Returns a changeset that should be
-    /// applied.
-    async fn verify_proof(&mut self, proof: &Proof) -> Result<MerkleTreeChangeset, HypercoreError> {
-        match self.tree.verify_proof(proof, &self.key_pair.public, None)? {
-            Either::Right(value) => Ok(value),
-            Either::Left(instructions) => {
-                let infos = self.storage.read_infos_to_vec(&instructions).await?;
-                match self
-                    .tree
-                    .verify_proof(proof, &self.key_pair.public, Some(&infos))?
-                {
-                    Either::Right(value) => Ok(value),
-                    Either::Left(_) => Err(HypercoreError::InvalidOperation {
-                        context: "Could not verify proof from tree".to_string(),
-                    }),
-                }
-            }
+    /// Verify a disclosed field of a [`Record`](crate::Record) stored as the block at the given
+    /// `index`. Only the tree is needed, so the block itself doesn't have to be present.
+    #[instrument(err, skip(self, disclosure))]
+    pub async fn verify_disclosure(
+        &self,
+        index: u64,
+        disclosure: &FieldDisclosure,
+    ) -> Result<bool, HypercoreError> {
+        if index >= self.tree.length {
+            return Ok(false);
         }
+        let node = self.tree_node(index * 2).await?;
+        Ok(disclosure.verify(&node.hash))
     }
 
-    fn should_flush_bitfield_and_tree_and_oplog(&mut self) -> bool {
-        if self.skip_flush_count == 0
-            || self.oplog.entries_byte_length >= MAX_OPLOG_ENTRIES_BYTE_SIZE
-        {
-            self.skip_flush_count = 3;
-            true
+    /// Current fork, length and tree hash, to publish so that blocks can later be proven
+    /// against it with [`Hypercore::create_inclusion_proof`].
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            fork: self.tree.fork,
+            length: self.tree.length,
+            root_hash: Hash::tree(&self.tree.roots).as_bytes()[..]
+                .try_into()
+                .expect("BLAKE2b-256 hash should be 32 bytes"),
+        }
+    }
+
+    /// Signature of the writer over the current [`Hypercore::checkpoint`], `None` for an empty
+    /// hypercore.
+    pub fn checkpoint_signature(&self) -> Option<Signature> {
+        self.tree.signature
+    }
+
+    /// Check the head that the upgrade of a proof signs without applying anything, also for
+    /// proofs of other forks: hashes the upgrade on top of the local tree at its start and
+    /// verifies the signature with the key of this hypercore. `None` if the proof has no
+    /// upgrade, starts beyond the local length or the signature doesn't verify.
+    pub async fn check_signed_head(
+        &self,
+        proof: &Proof,
+    ) -> Result<Option<SignedHead>, HypercoreError> {
+        self.limits.check_proof(proof)?;
+        let Some(upgrade) = proof.upgrade.as_ref() else {
+            return Ok(None);
+        };
+        if upgrade.length == 0 || upgrade.start > self.tree.length {
+            return Ok(None);
+        }
+        let Some(length) = upgrade.start.checked_add(upgrade.length) else {
+            return Ok(None);
+        };
+        let mut roots = vec![];
+        for root_index in root_indices(upgrade.start) {
+            roots.push(self.tree_node(root_index).await?);
+        }
+        let byte_length = roots.iter().map(|root| root.length).sum();
+        let mut changeset =
+            MerkleTreeChangeset::new(upgrade.start, byte_length, self.tree.fork, roots);
+        if verify_upgrade(proof.fork, upgrade, None, &self.verifier, &mut changeset).is_err() {
+            return Ok(None);
+        }
+        let checkpoint = Checkpoint {
+            fork: proof.fork,
+            length,
+            root_hash: changeset.hash()[..]
+                .try_into()
+                .expect("BLAKE2b-256 hash should be 32 bytes"),
+        };
+        let conflicts = if checkpoint.fork == self.tree.fork && length <= self.tree.length {
+            let mut local_roots = vec![];
+            for root_index in root_indices(length) {
+                local_roots.push(self.tree_node(root_index).await?);
+            }
+            Hash::tree(&local_roots).as_bytes() != checkpoint.root_hash
         } else {
-            self.skip_flush_count -= 1;
             false
+        };
+        Ok(Some(SignedHead {
+            checkpoint,
+            conflicts,
+        }))
+    }
+
+    /// Receipt of a pinning service storing this hypercore, signed with the key of the
+    /// service, at the current [`Hypercore::checkpoint`] and the given Unix time in seconds.
+    /// Fails unless every block up to the length is stored.
+    pub fn pin_receipt(
+        &self,
+        service: &ed25519_dalek::SigningKey,
+        timestamp: u64,
+    ) -> Result<PinReceipt, HypercoreError> {
+        if self.header.hints.contiguous_length < self.tree.length {
+            return Err(HypercoreError::InvalidOperation {
+                context: format!(
+                    "Only {} of {} blocks are stored",
+                    self.header.hints.contiguous_length, self.tree.length
+                ),
+            });
         }
+        Ok(PinReceipt::sign(
+            &self.key_pair.public.to_bytes(),
+            self.checkpoint(),
+            timestamp,
+            service,
+        ))
     }
 
-    async fn flush_bitfield_and_tree_and_oplog(
-        &mut self,
-        clear_traces: bool,
+    /// Verify that a pinning service with the given public key signed the receipt, for this
+    /// hypercore, at a checkpoint matching its tree.
+    #[instrument(err, skip_all)]
+    pub async fn verify_pin_receipt(
+        &self,
+        receipt: &PinReceipt,
+        service: &VerifyingKey,
     ) -> Result<(), HypercoreError> {
-        let infos = self.bitfield.flush();
-        self.storage.flush_infos(&infos).await?;
-        let infos = self.tree.flush();
-        self.storage.flush_infos(&infos).await?;
-        let infos = self.oplog.flush(&self.header, clear_traces)?;
-        self.storage.flush_infos(&infos).await?;
+        if receipt.key != self.key_pair.public.to_bytes() {
+            return Err(HypercoreError::BadArgument {
+                context: "Pin receipt is for another hypercore".to_string(),
+            });
+        }
+        if !receipt.verify_signature(service) {
+            return Err(HypercoreError::InvalidSignature {
+                context: "Pin receipt is not signed by the service".to_string(),
+            });
+        }
+        self.checkpoint_roots(&receipt.checkpoint).await?;
         Ok(())
     }
-}
 
-fn update_contiguous_length(
-    header: &mut Header,
-    bitfield: &Bitfield,
-    bitfield_update: &BitfieldUpdate,
-) {
-    let end = bitfield_update.start + bitfield_update.length;
-    let mut c = header.hints.contiguous_length;
-    if bitfield_update.drop {
-        if c <= end && c > bitfield_update.start {
-            c = bitfield_update.start;
+    /// Create a proof that the block at `index` was part of the hypercore at the given
+    /// checkpoint, which must be of this hypercore's current fork and at most its length.
+    /// Returns `None` if the block isn't stored locally.
+    #[instrument(err, skip(self))]
+    pub async fn create_inclusion_proof(
+        &self,
+        index: u64,
+        checkpoint: &Checkpoint,
+    ) -> Result<Option<InclusionProof>, HypercoreError> {
+        if index >= checkpoint.length {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Block {index} is beyond the checkpoint length {}",
+                    checkpoint.length
+                ),
+            });
         }
-    } else if c <= end && c >= bitfield_update.start {
-        c = end;
-        while bitfield.get(c) {
-            c += 1;
+        let root_indices = root_indices(checkpoint.length);
+        let roots = self.checkpoint_roots(checkpoint).await?;
+        let Some(value) = self.get_stored(index).await? else {
+            return Ok(None);
+        };
+
+        let mut nodes = vec![];
+        let mut node_index = index * 2;
+        while !root_indices.contains(&node_index) {
+            nodes.push(self.tree_node(flat_tree::sibling(node_index)).await?);
+            node_index = flat_tree::parent(node_index);
         }
+        Ok(Some(InclusionProof {
+            index,
+            value,
+            nodes,
+            roots,
+        }))
     }
 
-    if c != header.hints.contiguous_length {
-        header.hints.contiguous_length = c;
+    /// Create a proof that the hypercore at the `new` checkpoint extends the hypercore at the
+    /// `old` one, so that a light client holding `old` can accept `new` without the tree. Both
+    /// checkpoints must be of this hypercore's current fork and at most its length.
+    #[instrument(err, skip(self))]
+    pub async fn create_consistency_proof(
+        &self,
+        old: &Checkpoint,
+        new: &Checkpoint,
+    ) -> Result<ConsistencyProof, HypercoreError> {
+        if old.length > new.length {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Checkpoint length {} is beyond the later checkpoint length {}",
+                    old.length, new.length
+                ),
+            });
+        }
+        let old_roots = self.checkpoint_roots(old).await?;
+        let new_roots = self.checkpoint_roots(new).await?;
+        let new_root_indices = root_indices(new.length);
+        let mut known: Vec<u64> = old_roots.iter().map(|root| root.index).collect();
+        let mut nodes = vec![];
+        for old_root in &old_roots {
+            let mut index = old_root.index;
+            while !new_root_indices.contains(&index) {
+                let sibling = flat_tree::sibling(index);
+                if !known.contains(&sibling) {
+                    known.push(sibling);
+                    nodes.push(self.tree_node(sibling).await?);
+                }
+                index = flat_tree::parent(index);
+            }
+        }
+        Ok(ConsistencyProof {
+            old_roots,
+            new_roots,
+            nodes,
+        })
     }
-}
 
-#[cfg(test)]
-pub(crate) mod tests {
-    use super::*;
+    /// Roots of the tree at the checkpoint, checked against its root hash.
+    async fn checkpoint_roots(&self, checkpoint: &Checkpoint) -> Result<Vec<Node>, HypercoreError> {
+        if checkpoint.fork != self.tree.fork || checkpoint.length > self.tree.length {
+            return Err(HypercoreError::InvalidOperation {
+                context: format!(
+                    "Checkpoint on fork {} and length {} is not covered by hypercore on fork {} and length {}",
+                    checkpoint.fork, checkpoint.length, self.tree.fork, self.tree.length
+                ),
+            });
+        }
+        let mut roots = vec![];
+        for root_index in root_indices(checkpoint.length) {
+            roots.push(self.tree_node(root_index).await?);
+        }
+        if Hash::tree(&roots).as_bytes() != checkpoint.root_hash {
+            return Err(HypercoreError::InvalidOperation {
+                context: format!(
+                    "Checkpoint at length {} does not match the tree of the hypercore",
+                    checkpoint.length
+                ),
+            });
+        }
+        Ok(roots)
+    }
 
-    #[async_std::test]
-    async fn core_create_proof_block_only() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
+    async fn block_matches_tree(&self, index: u64, value: &[u8]) -> Result<bool, HypercoreError> {
+        let node = self.tree_node(index * 2).await?;
+        Ok(Hash::data(value).as_bytes() == node.hash)
+    }
 
-        let proof = hypercore
-            .create_proof(Some(RequestBlock { index: 4, nodes: 2 }), None, None, None)
-            .await?
-            .unwrap();
-        let block = proof.block.unwrap();
-        assert_eq!(proof.upgrade, None);
-        assert_eq!(proof.seek, None);
-        assert_eq!(block.index, 4);
+    /// Access the key pair.
+    pub fn key_pair(&self) -> &PartialKeypair {
+        &self.key_pair
+    }
+
+    /// Randomness the core was built with, for the jitter of retries on its behalf.
+    #[cfg(feature = "replication")]
+    pub(crate) fn rng(&self) -> &dyn Rng {
+        self.rng.as_ref()
+    }
+
+    /// Scheme and public key the tree is signed with, the public key of the key pair unless
+    /// the core was created with a [`HypercoreBuilder::signer`](crate::HypercoreBuilder::signer).
+    pub fn signer_key(&self) -> SignerKey {
+        self.header.manifest.signer.key()
+    }
+
+    /// Create a proof for given request. Works also on a partially downloaded core: blocks
+    /// that are stored locally can be served to other peers, a missing block is left out of
+    /// the proof and `None` is returned if nothing else was requested. Missing blocks are not
+    /// requested from peers, so that requests of peers can't make this core download.
+    #[instrument(err, skip_all)]
+    pub async fn create_proof(
+        &self,
+        block: Option<RequestBlock>,
+        hash: Option<RequestBlock>,
+        seek: Option<RequestSeek>,
+        upgrade: Option<RequestUpgrade>,
+    ) -> Result<Option<Proof>, HypercoreError> {
+        // Check this before building the proof, as the tree of a partial core may not have the
+        // nodes for blocks it doesn't have.
+        let block = block.filter(|block| self.bitfield.get(block.index));
+        if block.is_none() && hash.is_none() && seek.is_none() && upgrade.is_none() {
+            return Ok(None);
+        }
+        let valueless_proof = self
+            .create_valueless_proof(block, hash, seek, upgrade)
+            .await?;
+        let value: Option<Vec<u8>> = if let Some(block) = valueless_proof.block.as_ref() {
+            let value = self.get_stored(block.index).await?;
+            if value.is_none() {
+                // The data value requested in the proof can not be read, we return None here
+                // and let the party requesting figure out what to do.
+                return Ok(None);
+            }
+            value
+        } else {
+            None
+        };
+        let proof = valueless_proof.into_proof(value);
+        self.limits.check_proof(&proof)?;
+        Ok(Some(proof))
+    }
+
+    /// Verify and apply proof received from peer, returns true if changed, false if not
+    /// possible to apply.
+    #[instrument(skip_all)]
+    pub async fn verify_and_apply_proof(&mut self, proof: &Proof) -> Result<bool, HypercoreError> {
+        self.limits.check_proof(proof)?;
+        if proof.fork != self.tree.fork {
+            return Ok(false);
+        }
+        let changeset = self.verify_proof(proof).await?;
+        self.apply_changeset(proof, changeset).await
+    }
+
+    /// Entry with the blocks from `start` up to the length and the current signature, for
+    /// followers at length `start` to apply with [`Hypercore::apply_append_entry`] instead of
+    /// requesting an upgrade. `None` if `start` isn't below the length or a block is missing.
+    pub async fn append_entry(&self, start: u64) -> Result<Option<AppendEntry>, HypercoreError> {
+        let Some(signature) = self.tree.signature else {
+            return Ok(None);
+        };
+        if start >= self.tree.length {
+            return Ok(None);
+        }
+        let mut values = Vec::with_capacity((self.tree.length - start) as usize);
+        for index in start..self.tree.length {
+            if !self.bitfield.get(index) {
+                return Ok(None);
+            }
+            match self.get_stored(index).await? {
+                Some(value) => values.push(value),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(AppendEntry {
+            fork: self.tree.fork,
+            start,
+            values,
+            signature: signature.to_bytes().to_vec(),
+        }))
+    }
+
+    /// Append the blocks of an entry of [`Hypercore::append_entry`], after hashing them into
+    /// the tree and verifying the signature of the resulting length. Returns false, leaving
+    /// the core as it is, if the entry doesn't start at the length of this core's fork, in
+    /// which case it has to be upgraded with a proof instead.
+    #[instrument(skip_all)]
+    pub async fn apply_append_entry(
+        &mut self,
+        entry: &AppendEntry,
+    ) -> Result<bool, HypercoreError> {
+        if entry.fork != self.tree.fork
+            || entry.start != self.tree.length
+            || entry.values.is_empty()
+        {
+            return Ok(false);
+        }
+        self.limits.check_batch_length(entry.values.len())?;
+        let mut batch_length: usize = 0;
+        for value in &entry.values {
+            self.limits.check_value_size(value.len())?;
+            batch_length += value.len();
+        }
+        let mut changeset = self.tree.changeset();
+        let blocks: Vec<&[u8]> = entry.values.iter().map(Vec::as_slice).collect();
+        for (data, hash) in blocks.iter().zip(self.leaf_hasher.hash_leaves(&blocks)) {
+            changeset.append_hash(hash.to_vec(), data.len() as u64);
+        }
+        changeset.verify_and_set_signature(&entry.signature, &self.verifier)?;
+        self.commit_append(changeset, &blocks, batch_length).await?;
+        Ok(true)
+    }
+
+    /// Prepare verifying a proof received from a peer on a [`VerifyPool`](crate::VerifyPool), see
+    /// [`Hypercore::apply_verified_proof`]. Fails if the proof exceeds the limits.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn verify_job(&self, proof: Proof) -> Result<VerifyJob, HypercoreError> {
+        self.limits.check_proof(&proof)?;
+        Ok(VerifyJob {
+            proof,
+            verifier: self.verifier.clone(),
+            changeset: self.tree.changeset(),
+        })
+    }
+
+    /// Apply a proof verified on a [`VerifyPool`](crate::VerifyPool), like [`Hypercore::verify_and_apply_proof`].
+    /// If the tree changed since the job was created, the proof is verified again here.
+    #[cfg(not(target_arch = "wasm32"))]
+    #[instrument(skip_all)]
+    pub async fn apply_verified_proof(
+        &mut self,
+        verified: VerifiedProof,
+    ) -> Result<bool, HypercoreError> {
+        let VerifiedProof {
+            proof,
+            changeset,
+            unverified_block_root,
+        } = verified;
+        if proof.fork != self.tree.fork {
+            return Ok(false);
+        }
+        if !self.tree.commitable(&changeset) {
+            return self.verify_and_apply_proof(&proof).await;
+        }
+        if let Either::Left(instructions) = self
+            .tree
+            .verify_block_root(unverified_block_root.as_ref(), None)?
+        {
+            let infos = self.storage.read_infos_to_vec(&instructions).await?;
+            if let Either::Left(_) = self
+                .tree
+                .verify_block_root(unverified_block_root.as_ref(), Some(&infos))?
+            {
+                return Err(HypercoreError::InvalidOperation {
+                    context: "Could not verify proof from tree".to_string(),
+                });
+            }
+        }
+        self.apply_changeset(&proof, changeset).await
+    }
+
+    async fn apply_changeset(
+        &mut self,
+        proof: &Proof,
+        changeset: MerkleTreeChangeset,
+    ) -> Result<bool, HypercoreError> {
+        if !self.tree.commitable(&changeset) {
+            return Ok(false);
+        }
+
+        // In javascript there's _verifyExclusive and _verifyShared based on changeset.upgraded, but
+        // here we do only one. _verifyShared groups together many subsequent changesets into a single
+        // oplog push, and then flushes in the end only for the whole group.
+        let bitfield_update: Option<BitfieldUpdate> = if let Some(block) = &proof.block.as_ref() {
+            let byte_offset =
+                match self
+                    .tree
+                    .byte_offset_in_changeset(block.index, &changeset, None)?
+                {
+                    Either::Right(value) => value,
+                    Either::Left(instructions) => {
+                        let infos = self.storage.read_infos_to_vec(&instructions).await?;
+                        match self.tree.byte_offset_in_changeset(
+                            block.index,
+                            &changeset,
+                            Some(&infos),
+                        )? {
+                            Either::Right(value) => value,
+                            Either::Left(_) => {
+                                return Err(HypercoreError::InvalidOperation {
+                                    context: format!(
+                                        "Could not read offset for index {} from tree",
+                                        block.index
+                                    ),
+                                });
+                            }
+                        }
+                    }
+                };
+
+            // Write the value to the block store
+            self.mark_dirty().await?;
+            let info_to_flush = self.block_store.put(&block.value, byte_offset);
+            self.storage.flush_info(info_to_flush).await?;
+            let info_to_flush = self.checksum_store.put(&block.value, block.index);
+            self.storage.flush_info(info_to_flush).await?;
+
+            // Return a bitfield update for the given value
+            Some(BitfieldUpdate {
+                drop: false,
+                start: block.index,
+                length: 1,
+            })
+        } else {
+            // Only from DataBlock can there be changes to the bitfield
+            None
+        };
+
+        // Append the changeset to the Oplog
+        let outcome = self.oplog.append_changeset(
+            &changeset,
+            bitfield_update.clone(),
+            false,
+            &self.header,
+        )?;
+        self.storage.flush_infos(&outcome.infos_to_flush).await?;
+        self.header = outcome.header;
+
+        if let Some(bitfield_update) = &bitfield_update {
+            // Write to bitfield
+            self.bitfield.update(bitfield_update);
+
+            // Contiguous length is known only now
+            update_contiguous_length(&mut self.header, &self.bitfield, bitfield_update);
+
+            // Wanted ranges completed by this block are done
+            let bitfield = &self.bitfield;
+            if let Some(infos) = self.download_store.remove(|wanted| {
+                wanted.start <= bitfield_update.start
+                    && bitfield_update.start < wanted.end
+                    && bitfield
+                        .index_of(false, wanted.start)
+                        .is_none_or(|index| index >= wanted.end)
+            })? {
+                self.storage.flush_infos(&infos).await?;
+            }
+        }
+
+        // Commit changeset to in-memory tree
+        self.tree.commit(changeset)?;
+
+        // Now ready to flush
+        if self.should_flush_bitfield_and_tree_and_oplog() {
+            self.flush_bitfield_and_tree_and_oplog(false).await?;
+        }
+
+        #[cfg(feature = "replication")]
+        {
+            if proof.upgrade.is_some() {
+                // Notify replicator if we receieved an upgrade
+                let _ = self.events.send(crate::replication::events::DataUpgrade {});
+            }
+
+            // Notify replicator if we receieved a bitfield update
+            if let Some(ref bitfield) = bitfield_update {
+                let _ = self
+                    .events
+                    .send(crate::replication::events::Have::from(bitfield));
+            }
+        }
+        Ok(true)
+    }
+
+    /// Serializes the warmed merkle tree node cache, e.g. at shutdown, so that it can be
+    /// loaded with [`Hypercore::load_node_cache_snapshot`] on the next start instead of
+    /// reading the hot nodes from storage one by one. The snapshot is tied to the current
+    /// fork, length and tree hash. Contains no nodes if no node cache is configured.
+    #[cfg(feature = "cache")]
+    pub fn node_cache_snapshot(&self) -> Result<Vec<u8>, HypercoreError> {
+        let tree_hash = Hash::tree(&self.tree.roots).as_bytes().to_vec();
+        let nodes = self.tree.cached_nodes();
+        let mut state = HypercoreState::new();
+        state.0.preencode(&self.tree.fork)?;
+        state.0.preencode(&self.tree.length)?;
+        state.preencode_fixed_32()?;
+        state.preencode(&nodes)?;
+        let mut buffer = state.create_buffer();
+        state.0.encode(&self.tree.fork, &mut buffer)?;
+        state.0.encode(&self.tree.length, &mut buffer)?;
+        state.encode_fixed_32(&tree_hash, &mut buffer)?;
+        state.encode(&nodes, &mut buffer)?;
+        Ok(buffer.to_vec())
+    }
+
+    /// Loads a snapshot created with [`Hypercore::node_cache_snapshot`] into the node cache.
+    /// Returns the number of nodes loaded, which is zero if no node cache is configured or the
+    /// snapshot is stale, i.e. the hypercore has been appended to or truncated since.
+    #[cfg(feature = "cache")]
+    pub fn load_node_cache_snapshot(&mut self, snapshot: &[u8]) -> Result<usize, HypercoreError> {
+        let mut state = HypercoreState::from_buffer(snapshot);
+        let fork: u64 = state.0.decode(snapshot)?;
+        let length: u64 = state.0.decode(snapshot)?;
+        let tree_hash = state.decode_fixed_32(snapshot)?;
+        if fork != self.tree.fork
+            || length != self.tree.length
+            || *tree_hash != *Hash::tree(&self.tree.roots).as_bytes()
+        {
+            return Ok(0);
+        }
+        let nodes: Vec<Node> = state.decode(snapshot)?;
+        Ok(self.tree.warm_node_cache(nodes))
+    }
+
+    /// Upgrade request that fast-forwards this hypercore straight to the `remote_length` of a
+    /// peer that is ahead, instead of upgrading step by step. The resulting proof carries only
+    /// the nodes needed to get from the local roots to the remote roots, so catching up takes
+    /// a single round trip however far behind this hypercore is. Returns `None` if the peer is
+    /// not ahead.
+    pub fn fast_forward_request(&self, remote_length: u64) -> Option<RequestUpgrade> {
+        (remote_length > self.tree.length).then(|| RequestUpgrade {
+            start: self.tree.length,
+            length: remote_length - self.tree.length,
+        })
+    }
+
+    /// Plans the requests needed to download the blocks overlapping the given byte range, so
+    /// that file-like consumers only fetch the blocks they need a slice of. Blocks are located
+    /// with the locally stored tree nodes where possible; otherwise the plan contains a seek,
+    /// after applying whose proof planning again gets further. Returns an empty plan once all
+    /// blocks of the range are stored locally.
+    #[instrument(err, skip(self))]
+    pub async fn plan_byte_range(
+        &mut self,
+        range: Range<u64>,
+    ) -> Result<ByteRangePlan, HypercoreError> {
+        let mut plan = ByteRangePlan::default();
+        if range.is_empty() {
+            return Ok(plan);
+        }
+        if range.end > self.tree.byte_length {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Byte range {range:?} is out of bounds of byte length {}",
+                    self.tree.byte_length
+                ),
+            });
+        }
+        let start = match self.seek_local(range.start).await? {
+            LocalSeek::Block(start) => start,
+            LocalSeek::Missing(node) => {
+                self.plan_seek(&mut plan, range.start, node);
+                return Ok(plan);
+            }
+        };
+        let end = match self.seek_local(range.end - 1).await? {
+            LocalSeek::Block(end) => end,
+            LocalSeek::Missing(node) => {
+                // Fetch only the first block while locating the last one
+                self.plan_seek(&mut plan, range.end - 1, node);
+                start
+            }
+        };
+        for index in start..=end {
+            if !self.bitfield.get(index) {
+                let nodes = self.missing_nodes(index).await?;
+                plan.blocks.push(RequestBlock { index, nodes });
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Used to fill the nodes field of a `RequestBlock` during
+    /// synchronization.
+    #[instrument(err, skip(self))]
+    pub async fn missing_nodes(&self, index: u64) -> Result<u64, HypercoreError> {
+        self.missing_nodes_from_merkle_tree_index(index * 2).await
+    }
+
+    /// Get missing nodes using a merkle tree index. Advanced variant of missing_nodex
+    /// that allow for special cases of searching directly from the merkle tree.
+    #[instrument(err, skip(self))]
+    pub async fn missing_nodes_from_merkle_tree_index(
+        &self,
+        merkle_tree_index: u64,
+    ) -> Result<u64, HypercoreError> {
+        match self.tree.missing_nodes(merkle_tree_index, None)? {
+            Either::Right(value) => Ok(value),
+            Either::Left(instructions) => {
+                let mut instructions = instructions;
+                let mut infos: Vec<StoreInfo> = vec![];
+                loop {
+                    infos.extend(self.storage.read_infos_to_vec(&instructions).await?);
+                    match self.tree.missing_nodes(merkle_tree_index, Some(&infos))? {
+                        Either::Right(value) => {
+                            return Ok(value);
+                        }
+                        Either::Left(new_instructions) => {
+                            instructions = new_instructions;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Makes the hypercore read-only by deleting the secret key. Returns true if the
+    /// hypercore was changed, false if the hypercore was already read-only. This is useful
+    /// in scenarios where a hypercore should be made immutable after initial values have
+    /// been stored. A signer given to [`HypercoreBuilder::signer`](crate::HypercoreBuilder::signer)
+    /// is dropped, but not stored: giving it again makes the hypercore writable.
+    #[instrument(err, skip_all)]
+    pub async fn make_read_only(&mut self) -> Result<bool, HypercoreError> {
+        let had_signer = self.signer.take().is_some();
+        if self.key_pair.secret.is_some() {
+            self.key_pair.secret = None;
+            self.header.key_pair.secret = None;
+            // Need to flush clearing traces to make sure both oplog slots are cleared
+            self.flush_bitfield_and_tree_and_oplog(true).await?;
+            Ok(true)
+        } else {
+            Ok(had_signer)
+        }
+    }
+
+    async fn byte_range(
+        &self,
+        index: u64,
+        initial_infos: Option<&[StoreInfo]>,
+    ) -> Result<NodeByteRange, HypercoreError> {
+        match self.tree.byte_range(index, initial_infos)? {
+            Either::Right(value) => Ok(value),
+            Either::Left(instructions) => {
+                let mut instructions = instructions;
+                let mut infos: Vec<StoreInfo> = vec![];
+                loop {
+                    infos.extend(self.storage.read_infos_to_vec(&instructions).await?);
+                    match self.tree.byte_range(index, Some(&infos))? {
+                        Either::Right(value) => {
+                            return Ok(value);
+                        }
+                        Either::Left(new_instructions) => {
+                            instructions = new_instructions;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Seek for `bytes` within the locally known tree `node`. The seek is proven along the
+    /// path from the leftmost leaf of the node, which is requested as a hash.
+    fn plan_seek(&self, plan: &mut ByteRangePlan, bytes: u64, node: u64) {
+        plan.seek = Some(RequestSeek { bytes });
+        plan.hash = Some(RequestBlock {
+            index: flat_tree::left_span(node),
+            nodes: flat_tree::depth(node),
+        });
+    }
+
+    async fn seek_local(&self, bytes: u64) -> Result<LocalSeek, HypercoreError> {
+        let mut infos: Vec<StoreInfo> = Vec::new();
+        loop {
+            match self.tree.seek_local(bytes, Some(&infos))? {
+                Either::Right(index) => return Ok(index),
+                Either::Left(instructions) => {
+                    infos.extend(self.storage.read_infos_to_vec(&instructions).await?);
+                }
+            }
+        }
+    }
+
+    async fn tree_node(&self, index: u64) -> Result<Node, HypercoreError> {
+        match self.tree.get_node(index, None)? {
+            Either::Right(value) => Ok(value),
+            Either::Left(instructions) => {
+                let infos = self.storage.read_infos(&instructions).await?;
+                match self.tree.get_node(index, Some(&infos))? {
+                    Either::Right(value) => Ok(value),
+                    Either::Left(_) => Err(HypercoreError::InvalidOperation {
+                        context: format!("Could not read node {index} from tree"),
+                    }),
+                }
+            }
+        }
+    }
+
+    async fn read_checksums(
+        &mut self,
+        index: u64,
+        length: u64,
+    ) -> Result<Vec<u32>, HypercoreError> {
+        match self.checksum_store.read(index, length, None) {
+            Either::Right(value) => Ok(value),
+            Either::Left(instruction) => {
+                let info = self.storage.read_info(instruction).await?;
+                match self.checksum_store.read(index, length, Some(info)) {
+                    Either::Right(value) => Ok(value),
+                    Either::Left(_) => Err(HypercoreError::InvalidOperation {
+                        context: "Could not read checksum storage range".to_string(),
+                    }),
+                }
+            }
+        }
+    }
+
+    async fn checksum_store_length(&mut self) -> Result<u64, HypercoreError> {
+        let info = self
+            .storage
+            .read_info(StoreInfoInstruction::new_size(
+                Store::aux(CHECKSUM_STORE),
+                0,
+            ))
+            .await?;
+        Ok(info.length.unwrap_or(0))
+    }
+
+    async fn create_valueless_proof(
+        &self,
+        block: Option<RequestBlock>,
+        hash: Option<RequestBlock>,
+        seek: Option<RequestSeek>,
+        upgrade: Option<RequestUpgrade>,
+    ) -> Result<ValuelessProof, HypercoreError> {
+        match self.tree.create_valueless_proof(
+            block.as_ref(),
+            hash.as_ref(),
+            seek.as_ref(),
+            upgrade.as_ref(),
+            None,
+        )? {
+            Either::Right(value) => Ok(value),
+            Either::Left(instructions) => {
+                let mut instructions = instructions;
+                let mut infos: Vec<StoreInfo> = vec![];
+                loop {
+                    infos.extend(self.storage.read_infos_to_vec(&instructions).await?);
+                    match self.tree.create_valueless_proof(
+                        block.as_ref(),
+                        hash.as_ref(),
+                        seek.as_ref(),
+                        upgrade.as_ref(),
+                        Some(&infos),
+                    )? {
+                        Either::Right(value) => {
+                            return Ok(value);
+                        }
+                        Either::Left(new_instructions) => {
+                            instructions = new_instructions;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Verify a proof received from a peer. Returns a changeset that should be
+    /// applied.
+    async fn verify_proof(&mut self, proof: &Proof) -> Result<MerkleTreeChangeset, HypercoreError> {
+        match self.tree.verify_proof(proof, &self.verifier, None)? {
+            Either::Right(value) => Ok(value),
+            Either::Left(instructions) => {
+                let infos = self.storage.read_infos_to_vec(&instructions).await?;
+                match self
+                    .tree
+                    .verify_proof(proof, &self.verifier, Some(&infos))?
+                {
+                    Either::Right(value) => Ok(value),
+                    Either::Left(_) => Err(HypercoreError::InvalidOperation {
+                        context: "Could not verify proof from tree".to_string(),
+                    }),
+                }
+            }
+        }
+    }
+
+    fn should_flush_bitfield_and_tree_and_oplog(&mut self) -> bool {
+        if self.skip_flush_count == 0 || self.oplog.should_flush() {
+            self.skip_flush_count = 3;
+            true
+        } else {
+            self.skip_flush_count -= 1;
+            false
+        }
+    }
+
+    async fn flush_bitfield_and_tree_and_oplog(
+        &mut self,
+        clear_traces: bool,
+    ) -> Result<(), HypercoreError> {
+        let infos = self.bitfield.flush();
+        self.storage.flush_infos(&infos).await?;
+        let infos = self.tree.flush();
+        self.storage.flush_infos(&infos).await?;
+        // The flushed bitfield and tree cover all data written so far
+        self.header.dirty = false;
+        let infos = self.oplog.flush(&self.header, clear_traces)?;
+        self.storage.flush_infos(&infos).await?;
+        Ok(())
+    }
+
+    /// Marks the oplog header dirty before data is written, for the bitfield to be checked
+    /// by [`Hypercore::repair_bitfield`] on open if the core isn't flushed afterwards.
+    async fn mark_dirty(&mut self) -> Result<(), HypercoreError> {
+        if !self.header.dirty {
+            self.header.dirty = true;
+            let infos = self.oplog.write_header(&self.header)?;
+            self.storage.flush_infos(&infos).await?;
+        }
+        Ok(())
+    }
+}
+
+fn update_contiguous_length(
+    header: &mut Header,
+    bitfield: &Bitfield,
+    bitfield_update: &BitfieldUpdate,
+) {
+    let end = bitfield_update.start + bitfield_update.length;
+    let mut c = header.hints.contiguous_length;
+    if bitfield_update.drop {
+        // Dropping any block below the contiguous length ends it there
+        if c > bitfield_update.start {
+            c = bitfield_update.start;
+        }
+    } else if c <= end && c >= bitfield_update.start {
+        c = end;
+        while bitfield.get(c) {
+            c += 1;
+        }
+    }
+
+    if c != header.hints.contiguous_length {
+        header.hints.contiguous_length = c;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::crypto::generate_signing_key;
+    use crate::{KeyEncoding, Quota};
+
+    #[async_std::test]
+    async fn core_create_proof_block_only() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+
+        let proof = hypercore
+            .create_proof(Some(RequestBlock { index: 4, nodes: 2 }), None, None, None)
+            .await?
+            .unwrap();
+        let block = proof.block.unwrap();
+        assert_eq!(proof.upgrade, None);
+        assert_eq!(proof.seek, None);
+        assert_eq!(block.index, 4);
         assert_eq!(block.nodes.len(), 2);
         assert_eq!(block.nodes[0].index, 10);
         assert_eq!(block.nodes[1].index, 13);
@@ -792,7 +2938,7 @@ pub(crate) mod tests {
 
     #[async_std::test]
     async fn core_create_proof_block_and_upgrade() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
+        let hypercore = create_hypercore_with_data(10).await?;
         let proof = hypercore
             .create_proof(
                 Some(RequestBlock { index: 4, nodes: 0 }),
@@ -823,7 +2969,7 @@ pub(crate) mod tests {
 
     #[async_std::test]
     async fn core_create_proof_block_and_upgrade_and_additional() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
+        let hypercore = create_hypercore_with_data(10).await?;
         let proof = hypercore
             .create_proof(
                 Some(RequestBlock { index: 4, nodes: 0 }),
@@ -855,7 +3001,7 @@ pub(crate) mod tests {
     #[async_std::test]
     async fn core_create_proof_block_and_upgrade_from_existing_state() -> Result<(), HypercoreError>
     {
-        let mut hypercore = create_hypercore_with_data(10).await?;
+        let hypercore = create_hypercore_with_data(10).await?;
         let proof = hypercore
             .create_proof(
                 Some(RequestBlock { index: 1, nodes: 0 }),
@@ -886,7 +3032,7 @@ pub(crate) mod tests {
     #[async_std::test]
     async fn core_create_proof_block_and_upgrade_from_existing_state_with_additional(
     ) -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
+        let hypercore = create_hypercore_with_data(10).await?;
         let proof = hypercore
             .create_proof(
                 Some(RequestBlock { index: 1, nodes: 0 }),
@@ -917,7 +3063,7 @@ pub(crate) mod tests {
 
     #[async_std::test]
     async fn core_create_proof_block_and_seek_1_no_upgrade() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
+        let hypercore = create_hypercore_with_data(10).await?;
         let proof = hypercore
             .create_proof(
                 Some(RequestBlock { index: 4, nodes: 2 }),
@@ -939,7 +3085,7 @@ pub(crate) mod tests {
 
     #[async_std::test]
     async fn core_create_proof_block_and_seek_2_no_upgrade() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
+        let hypercore = create_hypercore_with_data(10).await?;
         let proof = hypercore
             .create_proof(
                 Some(RequestBlock { index: 4, nodes: 2 }),
@@ -961,7 +3107,7 @@ pub(crate) mod tests {
 
     #[async_std::test]
     async fn core_create_proof_block_and_seek_3_no_upgrade() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
+        let hypercore = create_hypercore_with_data(10).await?;
         let proof = hypercore
             .create_proof(
                 Some(RequestBlock { index: 4, nodes: 2 }),
@@ -985,7 +3131,7 @@ pub(crate) mod tests {
 
     #[async_std::test]
     async fn core_create_proof_block_and_seek_to_tree_no_upgrade() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(16).await?;
+        let hypercore = create_hypercore_with_data(16).await?;
         let proof = hypercore
             .create_proof(
                 Some(RequestBlock { index: 0, nodes: 4 }),
@@ -1010,7 +3156,7 @@ pub(crate) mod tests {
 
     #[async_std::test]
     async fn core_create_proof_block_and_seek_with_upgrade() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
+        let hypercore = create_hypercore_with_data(10).await?;
         let proof = hypercore
             .create_proof(
                 Some(RequestBlock { index: 4, nodes: 2 }),
@@ -1039,13 +3185,419 @@ pub(crate) mod tests {
     }
 
     #[async_std::test]
-    async fn core_create_proof_seek_with_upgrade() -> Result<(), HypercoreError> {
-        let mut hypercore = create_hypercore_with_data(10).await?;
-        let proof = hypercore
+    async fn core_create_proof_seek_with_upgrade() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+        let proof = hypercore
+            .create_proof(
+                None,
+                None,
+                Some(RequestSeek { bytes: 13 }),
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        let seek = proof.seek.unwrap();
+        let upgrade = proof.upgrade.unwrap();
+        assert_eq!(proof.block, None);
+        assert_eq!(seek.nodes.len(), 4);
+        assert_eq!(seek.nodes[0].index, 12);
+        assert_eq!(seek.nodes[1].index, 14);
+        assert_eq!(seek.nodes[2].index, 9);
+        assert_eq!(seek.nodes[3].index, 3);
+        assert_eq!(upgrade.nodes.len(), 1);
+        assert_eq!(upgrade.nodes[0].index, 17);
+        assert_eq!(upgrade.additional_nodes.len(), 0);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_verify_proof_invalid_signature() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+        // Invalid clone hypercore with a different public key
+        let mut hypercore_clone = create_hypercore_with_data(0).await?;
+        let proof = hypercore
+            .create_proof(
+                None,
+                Some(RequestBlock { index: 6, nodes: 0 }),
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(hypercore_clone
+            .verify_and_apply_proof(&proof)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_signer_is_selected_at_open() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(1).await?;
+        assert_eq!(main.signer_key(), SignerKey::ed25519(&main.key_pair.public));
+
+        // A separate Ed25519 signer is recorded, and required to write
+        let signer = generate_signing_key();
+        let key_pair = PartialKeypair {
+            public: main.key_pair.public,
+            secret: None,
+        };
+        let mut signed = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .key_pair(key_pair.clone())
+            .signer(Arc::new(signer.clone()))
+            .build()
+            .await?;
+        assert_eq!(signed.signer_key(), signer.key());
+        assert!(signed.info().writeable);
+        signed.append(b"#0").await?;
+        assert!(signed.make_read_only().await?);
+        assert!(signed.append(b"#1").await.is_err());
+
+        // Readers verify with the signer key, the key pair doesn't verify
+        let proof = signed
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 1,
+                }),
+            )
+            .await?
+            .unwrap();
+        let mut reader = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .key_pair(key_pair.clone())
+            .signer_key(signer.key())
+            .build()
+            .await?;
+        assert!(!reader.info().writeable);
+        assert!(reader.verify_and_apply_proof(&proof).await?);
+        let mut wrong = create_hypercore_with_data_and_key_pair(0, key_pair).await?;
+        assert!(wrong.verify_and_apply_proof(&proof).await.is_err());
+
+        // A signer must match the signer key
+        assert!(crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .signer(Arc::new(signer))
+            .signer_key(SignerKey::ed25519(&main.key_pair.public))
+            .build()
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_open_read_only_follows() -> Result<(), HypercoreError> {
+        let mut main = create_hypercore_with_data(3).await?;
+        let mut follower =
+            Hypercore::open_read_only(Storage::new_memory().await?, main.key_pair.public).await?;
+        assert!(!follower.info().writeable);
+        assert!(follower.key_pair().secret.is_none());
+        assert!(matches!(
+            follower.append(b"#0").await,
+            Err(HypercoreError::NotWritable)
+        ));
+
+        let upgrade = RequestUpgrade {
+            start: 0,
+            length: 3,
+        };
+        let proof = main.create_proof(None, None, None, Some(upgrade)).await?;
+        assert!(follower.verify_and_apply_proof(&proof.unwrap()).await?);
+        let nodes = follower.missing_nodes(1).await?;
+        let block = RequestBlock { index: 1, nodes };
+        let proof = main.create_proof(Some(block), None, None, None).await?;
+        assert!(follower.verify_and_apply_proof(&proof.unwrap()).await?);
+        assert_eq!(follower.info().length, 3);
+        assert_eq!(follower.get(1).await?.unwrap(), b"#1");
+
+        // Tampered proofs are still rejected
+        main.append(b"#3").await?;
+        let upgrade = RequestUpgrade {
+            start: 3,
+            length: 1,
+        };
+        let mut proof = main
+            .create_proof(None, None, None, Some(upgrade))
+            .await?
+            .unwrap();
+        proof.upgrade.as_mut().unwrap().signature[0] ^= 1;
+        assert!(follower.verify_and_apply_proof(&proof).await.is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "encryption")]
+    #[async_std::test]
+    async fn core_encrypts_blocks() -> Result<(), HypercoreError> {
+        use crate::ENCRYPTION_OVERHEAD;
+        let builder =
+            crate::HypercoreBuilder::new(Storage::new_memory().await?).encryption_key([9; 32]);
+        let debug = format!("{builder:?}");
+        assert!(debug.contains("<redacted>"));
+        assert!(!debug.contains("9, 9"));
+        let mut main = builder.build().await?;
+        main.append_batch([b"#0", b"#1"]).await?;
+        assert_eq!(main.get(1).await?.unwrap(), b"#1");
+        assert_eq!(main.get_verified(0).await?.unwrap(), b"#0");
+        assert_eq!(
+            main.info().byte_length,
+            2 * (2 + ENCRYPTION_OVERHEAD as u64)
+        );
+        assert!(main.simulate_append([b"#2"]).is_err());
+
+        // Peers verify and store the encrypted blocks, only those with the key can read them
+        let public_key = main.key_pair.public;
+        let reader = |key: Option<[u8; 32]>| async move {
+            let mut builder = crate::HypercoreBuilder::new(Storage::new_memory().await?).key_pair(
+                PartialKeypair {
+                    public: public_key,
+                    secret: None,
+                },
+            );
+            if let Some(key) = key {
+                builder = builder.encryption_key(key);
+            }
+            builder.build().await
+        };
+        for key in [None, Some([9; 32]), Some([8; 32])] {
+            let mut clone = reader(key).await?;
+            let upgrade = RequestUpgrade {
+                start: 0,
+                length: 2,
+            };
+            let proof = main.create_proof(None, None, None, Some(upgrade)).await?;
+            assert!(clone.verify_and_apply_proof(&proof.unwrap()).await?);
+            let nodes = clone.missing_nodes(1).await?;
+            let block = RequestBlock { index: 1, nodes };
+            let proof = main.create_proof(Some(block), None, None, None).await?;
+            assert!(clone.verify_and_apply_proof(&proof.unwrap()).await?);
+            match key {
+                None => {
+                    let block = clone.get(1).await?.unwrap();
+                    assert_eq!(block.len(), 2 + ENCRYPTION_OVERHEAD);
+                    assert!(!block.windows(2).any(|window| window == b"#1"));
+                }
+                Some([9, ..]) => assert_eq!(clone.get(1).await?.unwrap(), b"#1"),
+                Some(_) => assert!(clone.get(1).await.is_err()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Signer keeping its key out of reach, like an HSM or a remote signing service
+    #[derive(Debug)]
+    struct RemoteSigner {
+        key: ed25519_dalek::SigningKey,
+        online: std::sync::atomic::AtomicBool,
+    }
+
+    impl Signer for RemoteSigner {
+        fn key(&self) -> SignerKey {
+            SignerKey::ed25519(&self.key.verifying_key())
+        }
+
+        fn sign<'a>(&'a self, message: &'a [u8]) -> crate::SignFuture<'a> {
+            Box::pin(async move {
+                async_std::task::yield_now().await;
+                if !self.online.load(std::sync::atomic::Ordering::SeqCst) {
+                    return Err(HypercoreError::InvalidOperation {
+                        context: "Signer is offline".to_string(),
+                    });
+                }
+                Ok(crate::crypto::sign(&self.key, message))
+            })
+        }
+    }
+
+    #[async_std::test]
+    async fn core_signed_by_async_signer() -> Result<(), HypercoreError> {
+        let signer = Arc::new(RemoteSigner {
+            key: generate_signing_key(),
+            online: true.into(),
+        });
+        let mut main =
+            Hypercore::new_with_signer(Storage::new_memory().await?, signer.clone()).await?;
+        assert_eq!(main.signer_key(), signer.key());
+        assert!(main.info().writeable);
+        main.append_batch([b"#0", b"#1"]).await?;
+
+        // A failing signer fails the append, and the core stays as it was
+        signer
+            .online
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+        assert!(main.append(b"#2").await.is_err());
+        assert!(main.truncate(1, 1).await.is_err());
+        assert_eq!(main.info().length, 2);
+        signer
+            .online
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+        main.append(b"#2").await?;
+
+        let mut reader = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .key_pair(PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            })
+            .signer_key(signer.key())
+            .build()
+            .await?;
+        let upgrade = RequestUpgrade {
+            start: 0,
+            length: 3,
+        };
+        let proof = main.create_proof(None, None, None, Some(upgrade)).await?;
+        assert!(reader.verify_and_apply_proof(&proof.unwrap()).await?);
+        assert_eq!(reader.info().length, 3);
+        Ok(())
+    }
+
+    #[cfg(feature = "schnorr")]
+    #[async_std::test]
+    async fn core_signed_with_schnorr() -> Result<(), HypercoreError> {
+        let signer = crate::SchnorrSigner::from_bytes(&[7; 32])?;
+        let mut main = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .signer(Arc::new(signer.clone()))
+            .build()
+            .await?;
+        main.append_batch([b"#0", b"#1", b"#2"]).await?;
+        assert_eq!(main.signer_key().scheme, crate::SignatureScheme::Schnorr);
+
+        let mut clone = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .key_pair(PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            })
+            .signer_key(signer.key())
+            .build()
+            .await?;
+        let upgrade = RequestUpgrade {
+            start: 0,
+            length: 3,
+        };
+        let proof = main.create_proof(None, None, None, Some(upgrade)).await?;
+        assert!(clone.verify_and_apply_proof(&proof.unwrap()).await?);
+        let nodes = clone.missing_nodes(2).await?;
+        let block = RequestBlock { index: 2, nodes };
+        let proof = main.create_proof(Some(block), None, None, None).await?;
+        assert!(clone.verify_and_apply_proof(&proof.unwrap()).await?);
+        assert_eq!(clone.get(2).await?.unwrap(), b"#2");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_verify_and_apply_proof() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(10).await?;
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        let index = 6;
+        let nodes = clone.missing_nodes(index).await?;
+        let proof = main
+            .create_proof(
+                None,
+                Some(RequestBlock { index, nodes }),
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+        let main_info = main.info();
+        let clone_info = clone.info();
+        assert_eq!(main_info.byte_length, clone_info.byte_length);
+        assert_eq!(main_info.length, clone_info.length);
+        assert!(main.get(6).await?.is_some());
+        assert!(clone.get(6).await?.is_none());
+
+        // Fetch data for index 6 and verify it is found
+        let index = 6;
+        let nodes = clone.missing_nodes(index).await?;
+        let proof = main
+            .create_proof(Some(RequestBlock { index, nodes }), None, None, None)
+            .await?
+            .unwrap();
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_create_proof_from_partial_core() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(10).await?;
+        let read_only_key_pair = PartialKeypair {
+            public: main.key_pair.public,
+            secret: None,
+        };
+        let mut partial =
+            create_hypercore_with_data_and_key_pair(0, read_only_key_pair.clone()).await?;
+        let mut clone = create_hypercore_with_data_and_key_pair(0, read_only_key_pair).await?;
+
+        // Download only blocks 4 and 5 to the partial core
+        for (index, upgrade) in [(4, true), (5, false)] {
+            let nodes = partial.missing_nodes(index).await?;
+            let proof = main
+                .create_proof(
+                    Some(RequestBlock { index, nodes }),
+                    None,
+                    None,
+                    upgrade.then_some(RequestUpgrade {
+                        start: 0,
+                        length: 10,
+                    }),
+                )
+                .await?
+                .unwrap();
+            assert!(partial.verify_and_apply_proof(&proof).await?);
+        }
+
+        // The partial core can serve the blocks it has to another peer
+        for (index, upgrade) in [(5, true), (4, false)] {
+            let nodes = clone.missing_nodes(index).await?;
+            let proof = partial
+                .create_proof(
+                    Some(RequestBlock { index, nodes }),
+                    None,
+                    None,
+                    upgrade.then_some(RequestUpgrade {
+                        start: 0,
+                        length: 10,
+                    }),
+                )
+                .await?
+                .unwrap();
+            assert!(clone.verify_and_apply_proof(&proof).await?);
+        }
+        assert_eq!(clone.info().length, 10);
+        assert_eq!(clone.info().byte_length, main.info().byte_length);
+        assert_eq!(clone.get(4).await?.unwrap(), b"#4");
+        assert_eq!(clone.get(5).await?.unwrap(), b"#5");
+
+        // But not the ones it doesn't have, while still proving the rest of the request
+        #[cfg(feature = "replication")]
+        let rx = partial.event_subscribe();
+        let nodes = clone.missing_nodes(7).await?;
+        assert!(partial
+            .create_proof(Some(RequestBlock { index: 7, nodes }), None, None, None)
+            .await?
+            .is_none());
+        let proof = partial
             .create_proof(
+                Some(RequestBlock { index: 7, nodes }),
                 None,
                 None,
-                Some(RequestSeek { bytes: 13 }),
                 Some(RequestUpgrade {
                     start: 0,
                     length: 10,
@@ -1053,30 +3605,78 @@ pub(crate) mod tests {
             )
             .await?
             .unwrap();
-        let seek = proof.seek.unwrap();
-        let upgrade = proof.upgrade.unwrap();
-        assert_eq!(proof.block, None);
-        assert_eq!(seek.nodes.len(), 4);
-        assert_eq!(seek.nodes[0].index, 12);
-        assert_eq!(seek.nodes[1].index, 14);
-        assert_eq!(seek.nodes[2].index, 9);
-        assert_eq!(seek.nodes[3].index, 3);
-        assert_eq!(upgrade.nodes.len(), 1);
-        assert_eq!(upgrade.nodes[0].index, 17);
-        assert_eq!(upgrade.additional_nodes.len(), 0);
+        assert!(proof.block.is_none());
+        assert!(proof.upgrade.is_some());
+        // Nor does it download them for the peer
+        #[cfg(feature = "replication")]
+        assert!(rx.is_empty());
         Ok(())
     }
 
     #[async_std::test]
-    async fn core_verify_proof_invalid_signature() -> Result<(), HypercoreError> {
+    async fn core_scrub() -> Result<(), HypercoreError> {
         let mut hypercore = create_hypercore_with_data(10).await?;
-        // Invalid clone hypercore with a different public key
-        let mut hypercore_clone = create_hypercore_with_data(0).await?;
-        let proof = hypercore
+        assert!(hypercore.scrub().await?.is_empty());
+
+        // Corrupt the data of block 3 and the checksum of block 5
+        hypercore
+            .storage
+            .flush_info(StoreInfo::new_content(Store::Data, 6, b"XX"))
+            .await?;
+        hypercore
+            .storage
+            .flush_info(StoreInfo::new_content(
+                Store::aux(CHECKSUM_STORE),
+                5 * CHECKSUM_SIZE,
+                &[1, 2, 3, 4],
+            ))
+            .await?;
+        let progress = Progress::new(0);
+        assert_eq!(hypercore.scrub_with_progress(&progress).await?, vec![3]);
+        assert_eq!(progress.total(), 10);
+        assert_eq!(progress.completed(), 10);
+        assert_eq!(progress.bytes(), 20);
+        assert_eq!(
+            hypercore.read_checksums(5, 1).await?,
+            vec![ChecksumStore::checksum(b"#5")]
+        );
+
+        // Cleared blocks are skipped
+        hypercore.clear(3, 4).await?;
+        assert!(hypercore.scrub().await?.is_empty());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_scrub_random() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(10).await?;
+        hypercore
+            .storage
+            .flush_info(StoreInfo::new_content(Store::Data, 6, b"XX"))
+            .await?;
+        // Corrupt blocks are cleared, so found only once
+        assert_eq!(hypercore.scrub_random(1000).await?, vec![3]);
+        assert!(!hypercore.has(3));
+        assert!(hypercore.scrub_random(1000).await?.is_empty());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_limits() -> Result<(), HypercoreError> {
+        let mut main = create_hypercore_with_data(10).await?;
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        let proof = main
             .create_proof(
-                None,
                 Some(RequestBlock { index: 6, nodes: 0 }),
                 None,
+                None,
                 Some(RequestUpgrade {
                     start: 0,
                     length: 10,
@@ -1084,16 +3684,410 @@ pub(crate) mod tests {
             )
             .await?
             .unwrap();
-        assert!(hypercore_clone
-            .verify_and_apply_proof(&proof)
-            .await
-            .is_err());
+
+        clone.limits.max_proof_nodes = 2;
+        assert!(matches!(
+            clone.verify_and_apply_proof(&proof).await,
+            Err(HypercoreError::LimitExceeded { .. })
+        ));
+        clone.limits.max_proof_nodes = Limits::default().max_proof_nodes;
+        clone.limits.max_value_size = 1;
+        assert!(matches!(
+            clone.verify_and_apply_proof(&proof).await,
+            Err(HypercoreError::LimitExceeded { .. })
+        ));
+        clone.limits = Limits::default();
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+
+        main.limits.max_batch_length = 2;
+        let recorded = main.payload_stats().count();
+        assert!(matches!(
+            main.append_batch(&[b"a", b"b", b"c"]).await,
+            Err(HypercoreError::LimitExceeded { .. })
+        ));
+        assert_eq!(main.payload_stats().count(), recorded);
+        main.limits.max_value_size = 2;
+        assert!(matches!(
+            main.append(b"abc").await,
+            Err(HypercoreError::LimitExceeded { .. })
+        ));
+        assert_eq!(main.append_batch(&[b"a", b"b"]).await?.length, 12);
         Ok(())
     }
 
     #[async_std::test]
-    async fn core_verify_and_apply_proof() -> Result<(), HypercoreError> {
-        let mut main = create_hypercore_with_data(10).await?;
+    async fn core_append_entries() -> Result<(), HypercoreError> {
+        let mut main = create_hypercore_with_data(3).await?;
+        let mut follower = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair().public,
+                secret: None,
+            },
+        )
+        .await?;
+        assert!(main.append_entry(3).await?.is_none());
+
+        // The follower catches up from scratch, then append by append
+        let entry = main.append_entry(0).await?.unwrap();
+        assert_eq!((entry.start, entry.end()), (0, 3));
+        assert_eq!(AppendEntry::decode(&entry.encode()?)?, entry);
+        assert!(follower.apply_append_entry(&entry).await?);
+        main.append(b"#3").await?;
+        let entry = main.append_entry(3).await?.unwrap();
+        assert!(follower.apply_append_entry(&entry).await?);
+        assert_eq!(follower.checkpoint(), main.checkpoint());
+        assert_eq!(follower.info().contiguous_length, 4);
+        assert_eq!(follower.get(3).await?.unwrap(), b"#3");
+
+        // Entries not starting at the length are left to proofs, forged ones fail
+        assert!(!follower.apply_append_entry(&entry).await?);
+        main.append_batch([b"#4", b"#5"]).await?;
+        let mut entry = main.append_entry(4).await?.unwrap();
+        entry.values[1] = b"#x".to_vec();
+        assert!(follower.apply_append_entry(&entry).await.is_err());
+        assert_eq!(follower.info().length, 4);
+
+        // Entries stop at missing blocks
+        main.clear(4, 5).await?;
+        assert!(main.append_entry(4).await?.is_none());
+        Ok(())
+    }
+
+    #[cfg(feature = "replication")]
+    #[async_std::test]
+    async fn core_hint_prefetch() -> Result<(), HypercoreError> {
+        use crate::replication::events::{Event, Get};
+
+        let mut hypercore = create_hypercore_with_data(6).await?;
+        hypercore.clear(2, 4).await?;
+        let mut rx = hypercore.event_subscribe();
+        assert_eq!(
+            hypercore.hint_prefetch([3, 1, 2, 9], false).await?,
+            PrefetchOutcome {
+                warmed: 1,
+                requested: 0
+            }
+        );
+        assert!(rx.is_empty());
+
+        assert_eq!(
+            hypercore.hint_prefetch([3, 1, 2, 1, 9], true).await?,
+            PrefetchOutcome {
+                warmed: 1,
+                requested: 3
+            }
+        );
+        for index in [2, 3, 9] {
+            assert!(matches!(rx.try_recv(), Ok(Event::Get(Get { index: i, .. })) if i == index));
+        }
+        assert!(rx.is_empty());
+        Ok(())
+    }
+
+    #[cfg(not(feature = "replication"))]
+    #[async_std::test]
+    async fn core_hint_prefetch_without_replication() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(6).await?;
+        hypercore.clear(2, 4).await?;
+        assert_eq!(
+            hypercore.hint_prefetch([3, 1, 2, 9], false).await?,
+            PrefetchOutcome {
+                warmed: 1,
+                requested: 0
+            }
+        );
+        assert!(matches!(
+            hypercore.hint_prefetch([3, 1, 2, 9], true).await,
+            Err(HypercoreError::InvalidOperation { .. })
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "cache")]
+    #[async_std::test]
+    async fn core_node_cache_snapshot() -> Result<(), HypercoreError> {
+        async fn create_cached_hypercore(length: u64) -> Result<Hypercore, HypercoreError> {
+            let mut hypercore = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+                .node_cache_options(crate::CacheOptionsBuilder::new())
+                .build()
+                .await?;
+            for i in 0..length {
+                hypercore.append(format!("#{}", i).as_bytes()).await?;
+            }
+            Ok(hypercore)
+        }
+
+        let mut main = create_cached_hypercore(10).await?;
+        main.get(3).await?;
+        main.get(8).await?;
+        let cached = main.tree.cached_nodes();
+        assert!(!cached.is_empty());
+        let snapshot = main.node_cache_snapshot()?;
+
+        // A restarted core with the same tree loads the snapshot
+        let mut restarted = create_cached_hypercore(10).await?;
+        assert_eq!(restarted.load_node_cache_snapshot(&snapshot)?, cached.len());
+        assert_eq!(restarted.tree.cached_nodes(), cached);
+        assert_eq!(restarted.get(3).await?.unwrap(), b"#3");
+
+        // Stale snapshots are ignored
+        main.append(b"#10").await?;
+        let mut other = create_cached_hypercore(10).await?;
+        assert_eq!(
+            other.load_node_cache_snapshot(&main.node_cache_snapshot()?)?,
+            0
+        );
+
+        // Without a node cache nothing is loaded
+        let mut uncached = create_hypercore_with_data(10).await?;
+        assert_eq!(uncached.load_node_cache_snapshot(&snapshot)?, 0);
+        Ok(())
+    }
+
+    #[cfg(feature = "cache")]
+    #[async_std::test]
+    async fn core_node_cache_holds_written_nodes() -> Result<(), HypercoreError> {
+        let mut hypercore = crate::HypercoreBuilder::new(Storage::new_memory().await?)
+            .node_cache_options(crate::CacheOptionsBuilder::new())
+            .build()
+            .await?;
+        hypercore.append_batch([b"#0", b"#1", b"#2", b"#3"]).await?;
+        hypercore.flush().await?;
+        let cached = |hypercore: &Hypercore| -> Vec<u64> {
+            let nodes = hypercore.tree.cached_nodes();
+            nodes.into_iter().map(|node| node.index).collect()
+        };
+        assert_eq!(cached(&hypercore), (0..7).collect::<Vec<u64>>());
+
+        // Truncating drops the cached nodes, the ones written again are cached
+        hypercore.truncate(2, 1).await?;
+        assert!(cached(&hypercore).is_empty());
+        hypercore.append(b"#x").await?;
+        hypercore.flush().await?;
+        assert_eq!(cached(&hypercore), vec![4]);
+        assert_eq!(hypercore.get(2).await?.unwrap(), b"#x");
+        assert!(hypercore
+            .create_proof(Some(RequestBlock { index: 2, nodes: 0 }), None, None, None)
+            .await?
+            .is_some());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_read_txn() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(5).await?;
+        let txn = hypercore.read_txn();
+        assert_eq!((txn.fork(), txn.length()), (0, 5));
+        assert_eq!(txn.get(4).await?.unwrap(), b"#4");
+        assert!(txn.get(5).await.is_err());
+
+        // Appends don't affect pinned reads, a new fork invalidates them
+        hypercore.append(b"#5").await?;
+        assert_eq!(hypercore.get_pinned(0, 5, 4).await?.unwrap(), b"#4");
+        assert!(hypercore.get_pinned(0, 5, 5).await.is_err());
+        hypercore.tree.fork += 1;
+        assert!(hypercore.get_pinned(0, 5, 4).await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_is_shared_between_tasks() -> Result<(), HypercoreError> {
+        fn assert_send_sync<T: Send + Sync>(value: T) -> T {
+            value
+        }
+        fn assert_send<T: Send>(value: T) -> T {
+            value
+        }
+        let hypercore = assert_send_sync(create_hypercore_with_data(1).await?);
+        let hypercore = Arc::new(async_std::sync::RwLock::new(hypercore));
+        let writer = hypercore.clone();
+        async_std::task::spawn(async move {
+            let mut core = writer.write().await;
+            assert_send(core.append(b"#1")).await?;
+            assert_send(core.get(0)).await
+        })
+        .await?;
+
+        // Reads take &self, so readers share the read lock
+        let held = hypercore.read().await;
+        let readers: Vec<_> = (0..2)
+            .map(|index| {
+                let reader = hypercore.clone();
+                async_std::task::spawn(async move {
+                    let core = reader.read().await;
+                    assert_send(core.get(index)).await
+                })
+            })
+            .collect();
+        for (index, reader) in readers.into_iter().enumerate() {
+            assert_eq!(reader.await?.unwrap(), format!("#{index}").as_bytes());
+        }
+        assert_eq!(held.info().length, 2);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_debug_redacts_secret_key() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(1).await?;
+        let key_pair = hypercore.key_pair();
+        let secret = key_pair.secret.as_ref().unwrap().to_bytes();
+        let debug = format!("{hypercore:?}");
+        assert!(debug.contains("<redacted>"));
+        assert!(debug.contains(&key_pair.public.to_hex()));
+        assert!(!debug.contains(&secret.to_hex()));
+        assert!(!debug.contains(&format!("{secret:?}")[1..40]));
+        Ok(())
+    }
+
+    #[derive(Debug, Default)]
+    struct SlowIoLog(std::sync::Mutex<Vec<crate::SlowIo>>);
+
+    impl crate::SlowIoListener for SlowIoLog {
+        fn on_slow_io(&self, slow_io: &crate::SlowIo) {
+            self.0.lock().unwrap().push(slow_io.clone());
+        }
+    }
+
+    #[async_std::test]
+    async fn core_slow_io_watchdog() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(1).await?;
+        let clock = crate::ManualClock::new();
+        let log = Arc::new(SlowIoLog::default());
+        hypercore.set_slow_io_watchdog(Some(
+            SlowIoWatchdog::new(std::time::Duration::from_secs(1))
+                .with_clock(Arc::new(clock.clone()))
+                .with_listener(log.clone()),
+        ));
+        hypercore.append(b"#1").await?;
+        assert_eq!(hypercore.slow_io_watchdog().unwrap().slow_count(), 0);
+
+        // Every IO counts as slow with a zero threshold
+        hypercore.set_slow_io_watchdog(Some(
+            SlowIoWatchdog::new(std::time::Duration::ZERO)
+                .with_clock(Arc::new(clock))
+                .with_listener(log.clone()),
+        ));
+        hypercore.append(b"#2").await?;
+        let slow_io = log.0.lock().unwrap().clone();
+        assert_eq!(
+            hypercore.slow_io_watchdog().unwrap().slow_count(),
+            slow_io.len() as u64
+        );
+        assert!(slow_io.contains(&crate::SlowIo {
+            store: crate::Store::Data,
+            operation: crate::IoOperation::Write,
+            offset: 4,
+            length: 2,
+            elapsed: std::time::Duration::ZERO,
+        }));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_simulate_append() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(3).await?;
+        let before = hypercore.info();
+        let simulated = hypercore.simulate_append([b"#3", b"#4"])?;
+        assert_eq!(hypercore.info(), before);
+        assert_eq!((simulated.length, simulated.byte_length), (5, 10));
+
+        hypercore.append_batch([b"#3", b"#4"]).await?;
+        assert_eq!(
+            simulated.hash,
+            Hash::tree(&hypercore.tree.roots).as_bytes()[..]
+        );
+        crate::crypto::verify(
+            &hypercore.key_pair.public,
+            &simulated.signable,
+            hypercore.tree.signature.as_ref(),
+        )?;
+        assert_eq!(
+            hypercore.simulate_append::<&[u8], _>([])?.hash,
+            simulated.hash
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_truncate() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(10).await?;
+        hypercore.annotate(4, "label", b"kept").await?;
+        hypercore.annotate(7, "label", b"gone").await?;
+        hypercore.want(2, 8, 1).await?;
+        hypercore.want(6, 10, 2).await?;
+        assert!(hypercore.truncate(11, 1).await.is_err());
+        hypercore.truncate(5, 1).await?;
+        assert_eq!(hypercore.annotations(4), vec![("label", &b"kept"[..])]);
+        assert!(hypercore.annotations(7).is_empty());
+        assert_eq!(
+            hypercore
+                .download_progress()
+                .iter()
+                .map(|progress| progress.range)
+                .collect::<Vec<_>>(),
+            vec![WantedRange {
+                start: 2,
+                end: 5,
+                priority: 1
+            }]
+        );
+        let info = hypercore.info();
+        assert_eq!((info.length, info.byte_length, info.fork), (5, 10, 1));
+        assert_eq!(info.contiguous_length, 5);
+        assert!(!hypercore.has(5));
+        assert!(hypercore.truncate(5, 0).await.is_err());
+
+        hypercore.append(b"#x").await?;
+        assert_eq!(hypercore.get(4).await?.unwrap(), b"#4");
+        assert_eq!(hypercore.get(5).await?.unwrap(), b"#x");
+        assert_eq!(hypercore.info().byte_length, 12);
+
+        // The upgrade is signed over the new fork
+        let checkpoint = hypercore.checkpoint();
+        assert_eq!(checkpoint.fork, 1);
+        let signature = hypercore.checkpoint_signature().unwrap();
+        assert!(checkpoint.verify_signature(&hypercore.key_pair.public, &signature));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_flush_empties_oplog() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(2).await?;
+        hypercore.append(b"#2").await?;
+        assert!(hypercore.oplog.entries_length > 0);
+        hypercore.flush().await?;
+        assert_eq!(hypercore.oplog.entries_length, 0);
+        assert_eq!(hypercore.oplog.entries_byte_length, 0);
+        assert_eq!(hypercore.get(2).await?.unwrap(), b"#2");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_append_quota() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(2).await?;
+        hypercore.set_append_policy(Some(Arc::new(Quota {
+            max_length: Some(4),
+            max_byte_length: Some(10),
+        })));
+        hypercore.append(b"#2").await?;
+        let result = hypercore.append_batch([b"#3", b"#4"]).await;
+        assert!(matches!(result, Err(HypercoreError::QuotaExceeded { .. })));
+        assert_eq!(hypercore.info().length, 3);
+        let result = hypercore.append(b"#3 is too long").await;
+        assert!(matches!(result, Err(HypercoreError::QuotaExceeded { .. })));
+        hypercore.append(b"#3").await?;
+        assert_eq!(hypercore.info().length, 4);
+        assert_eq!(hypercore.get(3).await?.unwrap(), b"#3");
+
+        hypercore.set_append_policy(None);
+        hypercore.append(b"#4").await?;
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_fast_forward() -> Result<(), HypercoreError> {
+        let mut main = create_hypercore_with_data(3).await?;
         let mut clone = create_hypercore_with_data_and_key_pair(
             0,
             PartialKeypair {
@@ -1102,36 +4096,233 @@ pub(crate) mod tests {
             },
         )
         .await?;
-        let index = 6;
-        let nodes = clone.missing_nodes(index).await?;
         let proof = main
-            .create_proof(
-                None,
-                Some(RequestBlock { index, nodes }),
-                None,
-                Some(RequestUpgrade {
-                    start: 0,
-                    length: 10,
-                }),
-            )
+            .create_proof(None, None, None, clone.fast_forward_request(3))
             .await?
             .unwrap();
         assert!(clone.verify_and_apply_proof(&proof).await?);
-        let main_info = main.info();
-        let clone_info = clone.info();
-        assert_eq!(main_info.byte_length, clone_info.byte_length);
-        assert_eq!(main_info.length, clone_info.length);
-        assert!(main.get(6).await?.is_some());
-        assert!(clone.get(6).await?.is_none());
+        assert_eq!(clone.info().length, 3);
+        for i in 3..1000 {
+            main.append(format!("#{i}").as_bytes()).await?;
+        }
 
-        // Fetch data for index 6 and verify it is found
-        let index = 6;
-        let nodes = clone.missing_nodes(index).await?;
+        // One proof with the minimal set of nodes catches up all the way to the head
+        let upgrade = clone.fast_forward_request(1000).unwrap();
+        assert_eq!(upgrade.start, 3);
+        assert_eq!(upgrade.length, 997);
         let proof = main
-            .create_proof(Some(RequestBlock { index, nodes }), None, None, None)
+            .create_proof(None, None, None, Some(upgrade))
             .await?
             .unwrap();
+        let proof_upgrade = proof.upgrade.as_ref().unwrap();
+        assert!(proof_upgrade.nodes.len() + proof_upgrade.additional_nodes.len() <= 20);
         assert!(clone.verify_and_apply_proof(&proof).await?);
+        assert_eq!(clone.info().length, 1000);
+        assert_eq!(clone.fast_forward_request(1000), None);
+        assert_eq!(clone.fast_forward_request(10), None);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_plan_byte_range() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(10).await?;
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        let proof = main
+            .create_proof(None, None, None, clone.fast_forward_request(10))
+            .await?
+            .unwrap();
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+
+        // Blocks are two bytes each, so bytes 7..12 are in blocks 3 to 5
+        let mut rounds = 0;
+        loop {
+            let plan = clone.plan_byte_range(7..12).await?;
+            if plan.is_empty() {
+                break;
+            }
+            rounds += 1;
+            assert!(rounds < 10);
+            if plan.seek.is_some() {
+                let proof = main
+                    .create_proof(None, plan.hash, plan.seek, None)
+                    .await?
+                    .unwrap();
+                assert!(clone.verify_and_apply_proof(&proof).await?);
+            }
+            for block in plan.blocks {
+                let proof = main
+                    .create_proof(Some(block), None, None, None)
+                    .await?
+                    .unwrap();
+                assert!(clone.verify_and_apply_proof(&proof).await?);
+            }
+        }
+        assert!(rounds <= 3);
+        assert_eq!(clone.bitfield_snapshot(), vec![3..6]);
+        assert!(clone.plan_byte_range(0..0).await?.is_empty());
+        assert!(clone.plan_byte_range(19..21).await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_download_wanted_ranges() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(10).await?;
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        clone.want(4, 6, 0).await?;
+        clone.want(0, 10, 0).await?;
+
+        for (index, upgrade) in [(4, true), (5, false)] {
+            let nodes = clone.missing_nodes(index).await?;
+            let proof = main
+                .create_proof(
+                    Some(RequestBlock { index, nodes }),
+                    None,
+                    None,
+                    upgrade.then_some(RequestUpgrade {
+                        start: 0,
+                        length: 10,
+                    }),
+                )
+                .await?
+                .unwrap();
+            assert!(clone.verify_and_apply_proof(&proof).await?);
+        }
+
+        // The completed range is dropped, the other one continues from the first missing block
+        let progress = clone.download_progress();
+        assert_eq!(progress.len(), 1);
+        assert_eq!(progress[0].range.start, 0);
+        assert_eq!(progress[0].downloaded, 2);
+        assert_eq!(progress[0].next, Some(0));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_iter_available() -> Result<(), HypercoreError> {
+        use futures::TryStreamExt;
+
+        let mut hypercore = create_hypercore_with_data(10).await?;
+        hypercore.clear(3, 8).await?;
+        let available: Vec<(u64, Range<u64>)> = hypercore.iter_available().try_collect().await?;
+        assert_eq!(
+            available,
+            vec![(0, 0..2), (1, 2..4), (2, 4..6), (8, 16..18), (9, 18..20)]
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_append_stream() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(0).await?;
+        let payloads = stream::iter([&b"hello"[..], b" ", b"world"]);
+        let outcome = hypercore
+            .append_stream(payloads, Chunking::Fixed(4))
+            .await?;
+        assert_eq!(outcome.length, 3);
+        assert_eq!(hypercore.get(0).await?.unwrap(), b"hell");
+        assert_eq!(hypercore.get(1).await?.unwrap(), b"o wo");
+        assert_eq!(hypercore.get(2).await?.unwrap(), b"rld");
+        assert_eq!(hypercore.payload_stats().count(), 3);
+
+        let payloads = stream::iter([&b"a"[..], b"bc"]);
+        let outcome = hypercore.append_stream(payloads, Chunking::Payload).await?;
+        assert_eq!(outcome.length, 5);
+        assert_eq!(hypercore.get(4).await?.unwrap(), b"bc");
+
+        // Tiny payloads so far, so blocks are sized by the proof overhead
+        assert_eq!(hypercore.suggested_chunking(), Chunking::Fixed(4096));
+        let payloads = stream::iter(vec![vec![1; 1000]; 5]);
+        let outcome = hypercore.append_stream(payloads, Chunking::Auto).await?;
+        assert_eq!(outcome.length, 7);
+        assert_eq!(hypercore.get(5).await?.unwrap().len(), 4096);
+        assert_eq!(hypercore.get(6).await?.unwrap().len(), 904);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_append_stream_payload_over_batch_limit() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(0).await?;
+        hypercore.limits.max_batch_length = 8;
+        // One payload of 20 full blocks and a partial one, more than two batches
+        let payloads = stream::iter([vec![7; 8 * 16 * 2 + 4 * 16 + 3]]);
+        let outcome = hypercore
+            .append_stream(payloads, Chunking::Fixed(16))
+            .await?;
+        assert_eq!(outcome.length, 21);
+        assert_eq!(outcome.byte_length, 20 * 16 + 3);
+        assert_eq!(hypercore.get(19).await?.unwrap(), vec![7; 16]);
+        assert_eq!(hypercore.get(20).await?.unwrap(), vec![7; 3]);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn core_diff() -> Result<(), HypercoreError> {
+        use futures::TryStreamExt;
+
+        let mut hypercore = create_hypercore_with_data(6).await?;
+        let from = hypercore.checkpoint();
+        for i in 6..10 {
+            hypercore.append(format!("#{i}").as_bytes()).await?;
+        }
+        hypercore.clear(7, 8).await?;
+        let diff: Vec<(u64, Option<Vec<u8>>)> =
+            hypercore.diff(&from, 9).await?.try_collect().await?;
+        assert_eq!(
+            diff,
+            vec![
+                (6, Some(b"#6".to_vec())),
+                (7, None),
+                (8, Some(b"#8".to_vec()))
+            ]
+        );
+        let head = hypercore.checkpoint();
+        assert_eq!(
+            hypercore
+                .diff(&head, 10)
+                .await?
+                .try_collect::<Vec<_>>()
+                .await?,
+            vec![]
+        );
+        assert!(hypercore.diff(&from, 11).await.is_err());
+        assert!(hypercore.diff(&from, 4).await.is_err());
+
+        // Same lengths, but the core diverged from the checkpoint
+        let tampered = Checkpoint {
+            root_hash: [0; 32],
+            ..from
+        };
+        assert!(hypercore.diff(&tampered, 9).await.is_err());
+        hypercore.truncate(4, 1).await?;
+        for i in 4..8 {
+            hypercore.append(format!("#{i}").as_bytes()).await?;
+        }
+        assert!(hypercore.diff(&from, 8).await.is_err());
+        let from = hypercore.checkpoint();
+        hypercore.append(b"#8").await?;
+        assert_eq!(
+            hypercore
+                .diff(&from, 9)
+                .await?
+                .try_collect::<Vec<_>>()
+                .await?
+                .len(),
+            1
+        );
         Ok(())
     }
 
@@ -1158,7 +4349,18 @@ pub(crate) mod tests {
             storage,
             HypercoreOptions {
                 key_pair: Some(key_pair),
+                signer: None,
+                signer_key: None,
                 open: false,
+                tree_page_reads: false,
+                limits: Limits::default(),
+                rng: Arc::new(OsRandom),
+                append_policy: None,
+                bitfield_format: BitfieldFormat::default(),
+                #[cfg(feature = "encryption")]
+                encryption_key: None,
+                #[cfg(feature = "parallel")]
+                hash_threads: None,
                 #[cfg(feature = "cache")]
                 node_cache_options: None,
             },
@@ -0,0 +1,126 @@
+//! Encryption of the blocks of a core with XChaCha20-Poly1305, see
+//! [`crate::HypercoreBuilder::encryption_key`]. Blocks are encrypted before they are hashed
+//! into the tree, so stores, proofs and replication only ever see ciphertext, and peers
+//! without the key verify and serve the blocks all the same.
+//!
+//! A stored block is a random 24 byte nonce, followed by the ciphertext and the 16 byte tag.
+//! The cipher key is derived from the encryption key and the public key of the core, and the
+//! index of the block is authenticated, so a block can't be moved to another index or core.
+//! Unlike the XSalsa20 blocks of the Javascript implementation, encrypted blocks are
+//! [`ENCRYPTION_OVERHEAD`] bytes bigger than their values.
+//!
+//! The key of a core can't be rotated: re-encrypting its blocks would change their hashes,
+//! and with them the tree, its signatures and the proofs peers already verified.
+use blake2::digest::consts::U32;
+use blake2::digest::{FixedOutput, Mac};
+use blake2::Blake2bMac;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use ed25519_dalek::VerifyingKey;
+use std::fmt;
+
+use crate::{HypercoreError, Rng};
+
+/// Namespace of the derived cipher key.
+const BLOCK_KEY_NAMESPACE: &[u8] = b"hypercore/block-encryption";
+
+const NONCE_SIZE: usize = 24;
+
+/// Bytes an encrypted block is bigger than its value: the nonce and the tag.
+pub const ENCRYPTION_OVERHEAD: usize = NONCE_SIZE + 16;
+
+/// Encrypts and decrypts the blocks of a core.
+#[derive(Clone)]
+pub(crate) struct BlockEncryption {
+    cipher: XChaCha20Poly1305,
+}
+
+impl BlockEncryption {
+    /// Encryption of the blocks of the core with the given public key.
+    pub(crate) fn new(encryption_key: &[u8; 32], public_key: &VerifyingKey) -> Self {
+        let mut mac = <Blake2bMac<U32> as Mac>::new_from_slice(encryption_key)
+            .expect("32 byte keys should be valid");
+        Mac::update(&mut mac, BLOCK_KEY_NAMESPACE);
+        Mac::update(&mut mac, public_key.as_bytes());
+        let block_key = mac.finalize_fixed();
+        Self {
+            cipher: XChaCha20Poly1305::new(&block_key),
+        }
+    }
+
+    /// Encrypt the value of the block at the given index.
+    pub(crate) fn encrypt(&self, index: u64, value: &[u8], rng: &dyn Rng) -> Vec<u8> {
+        let mut nonce = [0; NONCE_SIZE];
+        rng.fill_bytes(&mut nonce);
+        let payload = Payload {
+            msg: value,
+            aad: &index.to_le_bytes(),
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(XNonce::from_slice(&nonce), payload)
+            .expect("Encrypting should not fail");
+        let mut block = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        block.extend_from_slice(&nonce);
+        block.extend(ciphertext);
+        block
+    }
+
+    /// Decrypt the block at the given index. Fails with an `InvalidChecksum` error if it wasn't
+    /// encrypted with the same key for the same index.
+    pub(crate) fn decrypt(&self, index: u64, block: &[u8]) -> Result<Vec<u8>, HypercoreError> {
+        let invalid = || HypercoreError::InvalidChecksum {
+            context: format!("Block {index} could not be decrypted"),
+        };
+        if block.len() < ENCRYPTION_OVERHEAD {
+            return Err(invalid());
+        }
+        let (nonce, ciphertext) = block.split_at(NONCE_SIZE);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &index.to_le_bytes(),
+        };
+        self.cipher
+            .decrypt(XNonce::from_slice(nonce), payload)
+            .map_err(|_| invalid())
+    }
+}
+
+impl fmt::Debug for BlockEncryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockEncryption").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_signing_key;
+    use crate::SeededRng;
+
+    #[test]
+    fn blocks_decrypt_only_in_place() -> Result<(), HypercoreError> {
+        let public_key = generate_signing_key().verifying_key();
+        let encryption = BlockEncryption::new(&[7; 32], &public_key);
+        let rng = SeededRng::new(1);
+        let block = encryption.encrypt(3, b"hello", &rng);
+        assert_eq!(block.len(), 5 + ENCRYPTION_OVERHEAD);
+        assert_ne!(encryption.encrypt(3, b"hello", &rng), block);
+        assert_eq!(encryption.decrypt(3, &block)?, b"hello");
+
+        // Another index, core or key, or a tampered block doesn't decrypt
+        assert!(encryption.decrypt(4, &block).is_err());
+        let other_core = generate_signing_key().verifying_key();
+        assert!(BlockEncryption::new(&[7; 32], &other_core)
+            .decrypt(3, &block)
+            .is_err());
+        assert!(BlockEncryption::new(&[8; 32], &public_key)
+            .decrypt(3, &block)
+            .is_err());
+        let mut tampered = block.clone();
+        tampered[NONCE_SIZE] ^= 1;
+        assert!(encryption.decrypt(3, &tampered).is_err());
+        assert!(encryption.decrypt(3, &block[..10]).is_err());
+        Ok(())
+    }
+}
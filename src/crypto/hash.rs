@@ -16,7 +16,12 @@ use crate::common::Node;
 const LEAF_TYPE: [u8; 1] = [0x00];
 const PARENT_TYPE: [u8; 1] = [0x01];
 const ROOT_TYPE: [u8; 1] = [0x02];
+const RECORD_FIELD_TYPE: [u8; 1] = [0x03];
 const HYPERCORE: [u8; 9] = *b"hypercore";
+// Bytes appended to the hypercore namespace hash to namespace replication capabilities, see
+// `crypto.namespace('hypercore', 6)` in caps.js
+const REPLICATE_INITIATOR: u8 = 1;
+const REPLICATE_RESPONDER: u8 = 2;
 
 // These the output of, see `hash_namespace` test below for how they are produced
 // https://github.com/holepunchto/hypercore/blob/cf08b72f14ed7d9ef6d497ebb3071ee0ae20967e/lib/caps.js#L16
@@ -84,7 +89,6 @@ impl Hash {
 
     /// Hash a public key. Useful to find the key you're looking for on a public
     /// network without leaking the key itself.
-    #[allow(dead_code)]
     pub(crate) fn for_discovery_key(public_key: VerifyingKey) -> Self {
         let mut hasher =
             Blake2bMac::<U32>::new_with_salt_and_personal(public_key.as_bytes(), &[], &[]).unwrap();
@@ -94,6 +98,32 @@ impl Hash {
         }
     }
 
+    /// Capability proving knowledge of the public key to the peer of a replication channel,
+    /// bound to the channel by the hash of its handshake.
+    // Called `caps.replicate()` in the JS implementation.
+    pub(crate) fn for_replication_capability(
+        is_initiator: bool,
+        public_key: &VerifyingKey,
+        handshake_hash: &[u8],
+    ) -> Self {
+        let mut hasher = Blake2b256::new();
+        hasher.update(HYPERCORE);
+        let mut namespace = Blake2b256::new();
+        namespace.update(hasher.finalize());
+        namespace.update([if is_initiator {
+            REPLICATE_INITIATOR
+        } else {
+            REPLICATE_RESPONDER
+        }]);
+        let mut hasher =
+            Blake2bMac::<U32>::new_with_salt_and_personal(public_key.as_bytes(), &[], &[]).unwrap();
+        blake2::digest::Update::update(&mut hasher, &namespace.finalize());
+        blake2::digest::Update::update(&mut hasher, handshake_hash);
+        Self {
+            hash: hasher.finalize_fixed(),
+        }
+    }
+
     /// Hash a vector of `Root` nodes.
     // Called `crypto.tree()` in the JS implementation.
     #[allow(dead_code)]
@@ -163,6 +193,24 @@ impl Hash {
         }
     }
 
+    /// Hash a salted field of a merklized record
+    pub(crate) fn record_field(salt: &[u8], value: &[u8]) -> Self {
+        let (mut state, mut size) = State::new_with_size(8);
+        state
+            .encode_u64(value.len() as u64, &mut size)
+            .expect("Encoding u64 should not fail");
+
+        let mut hasher = Blake2b256::new();
+        hasher.update(RECORD_FIELD_TYPE);
+        hasher.update(salt);
+        hasher.update(&size);
+        hasher.update(value);
+
+        Self {
+            hash: hasher.finalize(),
+        }
+    }
+
     /// Hash a tree
     pub(crate) fn tree(roots: &[impl AsRef<Node>]) -> Self {
         let mut hasher = Blake2b256::new();
@@ -0,0 +1,321 @@
+//! Text encodings of 32 byte keys: z-base32, as printed by the Javascript tooling, and bech32,
+//! as used by nostr for `npub` keys.
+use std::fmt;
+
+use crate::common::{decode_hex, encode_hex};
+use crate::{HypercoreError, VerifyingKey};
+
+/// Human readable part of nostr public keys.
+pub const NPUB_HRP: &str = "npub";
+
+const ZBASE32_ALPHABET: &[u8; 32] = b"ybndrfg8ejkmcpqxot1uwisza345h769";
+const BECH32_ALPHABET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+const BECH32_CHECKSUM_LENGTH: usize = 6;
+/// Maximum length of a bech32 string, from BIP-173.
+const BECH32_MAX_LENGTH: usize = 90;
+
+/// Encoding of a 32 byte key, a core public key or a discovery key, as text.
+///
+/// [`KeyEncoding::parse_key`] accepts every format, so keys printed by the Javascript tooling,
+/// in z-base32 or hex, and keys copied from nostr clients round-trip through this crate.
+pub trait KeyEncoding: Sized {
+    /// Bytes of the key.
+    fn key_bytes(&self) -> [u8; 32];
+
+    /// Key from its bytes.
+    fn from_key_bytes(bytes: [u8; 32]) -> Result<Self, HypercoreError>;
+
+    /// Encode as lowercase hex, 64 characters.
+    fn to_hex(&self) -> String {
+        self.display().to_string()
+    }
+
+    /// Key that prints as lowercase hex with both `Display` and `Debug`, for log messages.
+    fn display(&self) -> HexKey {
+        HexKey(self.key_bytes())
+    }
+
+    /// Encode as z-base32, 52 characters.
+    fn to_zbase32(&self) -> String {
+        encode_zbase32(&self.key_bytes())
+    }
+
+    /// Decode from z-base32.
+    fn from_zbase32(encoded: &str) -> Result<Self, HypercoreError> {
+        Self::from_key_bytes(to_key_bytes(decode_zbase32(encoded)?)?)
+    }
+
+    /// Encode as bech32 with the given human readable part.
+    fn to_bech32(&self, hrp: &str) -> Result<String, HypercoreError> {
+        encode_bech32(hrp, &self.key_bytes())
+    }
+
+    /// Decode from bech32, failing if the human readable part is not `hrp`.
+    fn from_bech32(encoded: &str, hrp: &str) -> Result<Self, HypercoreError> {
+        let (actual_hrp, data) = decode_bech32(encoded)?;
+        if actual_hrp != hrp.to_ascii_lowercase() {
+            return Err(invalid_key(&format!(
+                "expected bech32 prefix {hrp}, got {actual_hrp}"
+            )));
+        }
+        Self::from_key_bytes(to_key_bytes(data)?)
+    }
+
+    /// Encode as a nostr style `npub` bech32 string.
+    fn to_npub(&self) -> String {
+        self.to_bech32(NPUB_HRP)
+            .expect("npub should be a valid human readable part")
+    }
+
+    /// Decode from a nostr style `npub` bech32 string.
+    fn from_npub(encoded: &str) -> Result<Self, HypercoreError> {
+        Self::from_bech32(encoded, NPUB_HRP)
+    }
+
+    /// Parse a key in any of the supported formats: 64 hex characters, 52 z-base32 characters
+    /// or bech32 with any human readable part.
+    fn parse_key(encoded: &str) -> Result<Self, HypercoreError> {
+        let encoded = encoded.trim();
+        if let Some(bytes) = decode_hex(encoded) {
+            return Self::from_key_bytes(bytes);
+        }
+        let bytes = if encoded.len() == 52 {
+            decode_zbase32(encoded)?
+        } else {
+            decode_bech32(encoded)?.1
+        };
+        Self::from_key_bytes(to_key_bytes(bytes)?)
+    }
+}
+
+impl KeyEncoding for [u8; 32] {
+    fn key_bytes(&self) -> [u8; 32] {
+        *self
+    }
+
+    fn from_key_bytes(bytes: [u8; 32]) -> Result<Self, HypercoreError> {
+        Ok(bytes)
+    }
+}
+
+impl KeyEncoding for VerifyingKey {
+    fn key_bytes(&self) -> [u8; 32] {
+        self.to_bytes()
+    }
+
+    fn from_key_bytes(bytes: [u8; 32]) -> Result<Self, HypercoreError> {
+        VerifyingKey::from_bytes(&bytes).map_err(|_| invalid_key("not an Ed25519 public key"))
+    }
+}
+
+/// Key printed as lowercase hex, see [`KeyEncoding::display`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HexKey(pub [u8; 32]);
+
+impl fmt::Display for HexKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&encode_hex(&self.0))
+    }
+}
+
+impl fmt::Debug for HexKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+fn encode_zbase32(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ZBASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ZBASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+fn decode_zbase32(encoded: &str) -> Result<Vec<u8>, HypercoreError> {
+    let values = encoded
+        .bytes()
+        .map(|c| {
+            ZBASE32_ALPHABET
+                .iter()
+                .position(|a| *a == c.to_ascii_lowercase())
+                .map(|value| value as u8)
+                .ok_or_else(|| invalid_key(&format!("invalid z-base32 character {}", c as char)))
+        })
+        .collect::<Result<Vec<u8>, HypercoreError>>()?;
+    convert_bits(&values, 5, 8).ok_or_else(|| invalid_key("z-base32 has non-zero padding"))
+}
+
+fn encode_bech32(hrp: &str, bytes: &[u8]) -> Result<String, HypercoreError> {
+    if hrp.is_empty() || !hrp.bytes().all(|c| (33..=126).contains(&c)) {
+        return Err(invalid_key(&format!("invalid bech32 prefix {hrp}")));
+    }
+    let hrp = hrp.to_ascii_lowercase();
+    let data = convert_bits(bytes, 8, 5).expect("Padding to 5 bits should not fail");
+    let mut values = hrp_expand(&hrp);
+    values.extend(&data);
+    values.extend([0; BECH32_CHECKSUM_LENGTH]);
+    let polymod = bech32_polymod(&values) ^ 1;
+    let mut encoded = hrp;
+    encoded.push('1');
+    let checksum = (0..BECH32_CHECKSUM_LENGTH).map(|i| ((polymod >> (5 * (5 - i))) & 31) as u8);
+    for value in data.into_iter().chain(checksum) {
+        encoded.push(BECH32_ALPHABET[value as usize] as char);
+    }
+    if encoded.len() > BECH32_MAX_LENGTH {
+        return Err(invalid_key("bech32 string is too long"));
+    }
+    Ok(encoded)
+}
+
+fn decode_bech32(encoded: &str) -> Result<(String, Vec<u8>), HypercoreError> {
+    if encoded.len() > BECH32_MAX_LENGTH {
+        return Err(invalid_key("bech32 string is too long"));
+    }
+    if encoded.bytes().any(|c| c.is_ascii_lowercase())
+        && encoded.bytes().any(|c| c.is_ascii_uppercase())
+    {
+        return Err(invalid_key("bech32 string has mixed case"));
+    }
+    let encoded = encoded.to_ascii_lowercase();
+    let Some((hrp, data)) = encoded.rsplit_once('1') else {
+        return Err(invalid_key("bech32 string has no separator"));
+    };
+    if hrp.is_empty() || data.len() < BECH32_CHECKSUM_LENGTH {
+        return Err(invalid_key("bech32 string is too short"));
+    }
+    let values = data
+        .bytes()
+        .map(|c| {
+            BECH32_ALPHABET
+                .iter()
+                .position(|a| *a == c)
+                .map(|value| value as u8)
+                .ok_or_else(|| invalid_key(&format!("invalid bech32 character {}", c as char)))
+        })
+        .collect::<Result<Vec<u8>, HypercoreError>>()?;
+    let mut checked = hrp_expand(hrp);
+    checked.extend(&values);
+    if bech32_polymod(&checked) != 1 {
+        return Err(invalid_key("invalid bech32 checksum"));
+    }
+    let data = convert_bits(&values[..values.len() - BECH32_CHECKSUM_LENGTH], 5, 8)
+        .ok_or_else(|| invalid_key("bech32 has non-zero padding"))?;
+    Ok((hrp.to_string(), data))
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut values: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    values.push(0);
+    values.extend(hrp.bytes().map(|c| c & 31));
+    values
+}
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    let mut checksum: u32 = 1;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ffffff) << 5) ^ *value as u32;
+        for (i, generator) in BECH32_GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+/// Regroup bits, padding with zeros when splitting into smaller groups. Returns `None` when
+/// joining into bigger groups leaves more than padding, or non-zero padding bits.
+fn convert_bits(values: &[u8], from: u32, to: u32) -> Option<Vec<u8>> {
+    let mut converted = Vec::with_capacity(values.len() * from as usize / to as usize + 1);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    let max = (1 << to) - 1;
+    for value in values {
+        buffer = (buffer << from) | *value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            converted.push(((buffer >> bits) & max) as u8);
+        }
+    }
+    if to < from {
+        if bits > 0 {
+            converted.push(((buffer << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || (buffer << (to - bits)) & max != 0 {
+        return None;
+    }
+    Some(converted)
+}
+
+fn to_key_bytes(bytes: Vec<u8>) -> Result<[u8; 32], HypercoreError> {
+    let length = bytes.len();
+    bytes
+        .try_into()
+        .map_err(|_| invalid_key(&format!("expected 32 bytes, got {length}")))
+}
+
+fn invalid_key(reason: &str) -> HypercoreError {
+    HypercoreError::BadArgument {
+        context: format!("Invalid key, {reason}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{discovery_key, generate_signing_key};
+
+    #[test]
+    fn key_encodings_round_trip() -> Result<(), HypercoreError> {
+        // Vector from NIP-19
+        let hex = "3bf0c63fcb93463407af97a5e5ee64fa883d107ef9e558472c4eb9aaaefa459d";
+        let npub = "npub180cvv07tjdrrgpa0j7j7tmnyl2yr6yr7l8j4s3evf6u64th6gkwsyjh6w6";
+        let key = <[u8; 32]>::parse_key(hex)?;
+        assert_eq!(key.to_npub(), npub);
+        assert_eq!(key.to_hex(), hex);
+        assert_eq!(format!("{:?}", key.display()), hex);
+        assert_eq!(<[u8; 32]>::from_npub(npub)?, key);
+        assert_eq!(<[u8; 32]>::parse_key(&npub.to_uppercase())?, key);
+        // Vector from the z-base32 specification
+        assert_eq!(encode_zbase32(&[0xf0, 0xbf, 0xc7]), "6n9hq");
+
+        let public_key = generate_signing_key().verifying_key();
+        let zbase32 = public_key.to_zbase32();
+        assert_eq!(zbase32.len(), 52);
+        assert_eq!(VerifyingKey::from_zbase32(&zbase32)?, public_key);
+        assert_eq!(VerifyingKey::parse_key(&zbase32)?, public_key);
+        let bech32 = public_key.to_bech32("hypercore")?;
+        assert!(bech32.starts_with("hypercore1"));
+        assert_eq!(VerifyingKey::from_bech32(&bech32, "hypercore")?, public_key);
+        assert_eq!(VerifyingKey::parse_key(&bech32)?, public_key);
+        assert!(VerifyingKey::from_npub(&bech32).is_err());
+
+        let discovery = discovery_key(&public_key);
+        assert_eq!(<[u8; 32]>::parse_key(&discovery.to_zbase32())?, discovery);
+
+        // Corrupted strings are rejected
+        let mut corrupted = npub.to_string();
+        corrupted.replace_range(10..11, "q");
+        assert!(<[u8; 32]>::from_npub(&corrupted).is_err());
+        assert!(<[u8; 32]>::from_zbase32(&zbase32[..51]).is_err());
+        assert!(<[u8; 32]>::parse_key(
+            "Npub180cvv07tjdrrgpa0j7j7tmnyl2yr6yr7l8j4s3evf6u64th6gkwsyjh6w6"
+        )
+        .is_err());
+        Ok(())
+    }
+}
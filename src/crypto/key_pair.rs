@@ -1,12 +1,16 @@
 //! Generate an `Ed25519` keypair.
 
-use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
-use rand::rngs::OsRng;
+use std::fmt;
 
-use crate::HypercoreError;
+use super::Hash;
+use crate::{HypercoreError, KeyEncoding, OsRandom, Rng};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 
 /// Key pair where for read-only hypercores the secret key can also be missing.
-#[derive(Debug, Clone)]
+///
+/// `Debug` prints the public key as hex and never the secret key, so key pairs, and the
+/// hypercores holding them, are safe to log.
+#[derive(Clone)]
 pub struct PartialKeypair {
     /// Public key
     pub public: VerifyingKey,
@@ -14,10 +18,82 @@ pub struct PartialKeypair {
     pub secret: Option<SigningKey>,
 }
 
+impl fmt::Debug for PartialKeypair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PartialKeypair")
+            .field("public", &self.public.display())
+            .field("secret", &self.secret.as_ref().map(|_| Redacted))
+            .finish()
+    }
+}
+
+/// Placeholder printed instead of secret key material.
+struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
 /// Generate a new `Ed25519` key pair.
 pub fn generate() -> SigningKey {
-    let mut csprng = OsRng;
-    SigningKey::generate(&mut csprng)
+    generate_with(&OsRandom)
+}
+
+/// Generate a new `Ed25519` key pair from the given source of randomness.
+pub fn generate_with(rng: &dyn Rng) -> SigningKey {
+    let mut secret = [0; 32];
+    rng.fill_bytes(&mut secret);
+    SigningKey::from_bytes(&secret)
+}
+
+/// Discovery key of the core with the given public key. Peers find each other by the
+/// discovery key, so the public key isn't leaked to the network.
+pub fn discovery_key(public_key: &VerifyingKey) -> [u8; 32] {
+    Hash::for_discovery_key(*public_key)
+        .as_bytes()
+        .try_into()
+        .expect("BLAKE2b-256 hash should be 32 bytes")
+}
+
+/// Capability sent when opening the replication channel of a core, proving the sender knows
+/// the public key of the core without revealing it. It is bound to the connection by the hash
+/// of its handshake, and differs for its initiator and responder so it can't be echoed back.
+pub fn replication_capability(
+    is_initiator: bool,
+    public_key: &VerifyingKey,
+    handshake_hash: &[u8],
+) -> [u8; 32] {
+    Hash::for_replication_capability(is_initiator, public_key, handshake_hash)
+        .as_bytes()
+        .try_into()
+        .expect("BLAKE2b-256 hash should be 32 bytes")
+}
+
+/// Verify the capability sent by the peer of a connection, where `is_initiator` is whether we
+/// initiated it.
+pub fn verify_replication_capability(
+    is_initiator: bool,
+    public_key: &VerifyingKey,
+    handshake_hash: &[u8],
+    capability: &[u8],
+) -> Result<(), HypercoreError> {
+    let expected = replication_capability(!is_initiator, public_key, handshake_hash);
+    // Compared in constant time, the capability is a secret of the core
+    let matches = capability.len() == expected.len()
+        && capability
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0;
+    if matches {
+        Ok(())
+    } else {
+        Err(HypercoreError::InvalidSignature {
+            context: "Remote sent an invalid replication capability".to_string(),
+        })
+    }
 }
 
 /// Sign a byte slice using a keypair's private key.
@@ -55,3 +131,21 @@ fn can_verify_messages() {
     verify(&signing_key.verifying_key(), from, Some(&sig)).unwrap();
     verify(&signing_key.verifying_key(), b"oops", Some(&sig)).unwrap_err();
 }
+
+#[test]
+fn can_verify_replication_capabilities() {
+    let public_key = generate().verifying_key();
+    let handshake_hash = [7; 64];
+    let initiator = replication_capability(true, &public_key, &handshake_hash);
+    let responder = replication_capability(false, &public_key, &handshake_hash);
+    assert_ne!(initiator, responder);
+    verify_replication_capability(false, &public_key, &handshake_hash, &initiator).unwrap();
+    verify_replication_capability(true, &public_key, &handshake_hash, &responder).unwrap();
+    // Echoed back, for another connection or another core, it is rejected
+    verify_replication_capability(true, &public_key, &handshake_hash, &initiator).unwrap_err();
+    verify_replication_capability(false, &public_key, &[8; 64], &initiator).unwrap_err();
+    let other_key = generate().verifying_key();
+    verify_replication_capability(false, &other_key, &handshake_hash, &initiator).unwrap_err();
+    verify_replication_capability(false, &public_key, &handshake_hash, &initiator[1..])
+        .unwrap_err();
+}
@@ -1,3 +1,5 @@
+use super::{SignatureScheme, SignerKey};
+
 // These the output of the following link:
 // https://github.com/holepunchto/hypercore/blob/cf08b72f14ed7d9ef6d497ebb3071ee0ae20967e/lib/caps.js#L16
 
@@ -26,7 +28,7 @@ pub(crate) struct Manifest {
 
 #[derive(Debug, Clone)]
 pub(crate) struct ManifestSigner {
-    pub(crate) signature: String,
+    pub(crate) scheme: SignatureScheme,
     pub(crate) namespace: [u8; 32],
     pub(crate) public_key: [u8; 32],
 }
@@ -35,9 +37,19 @@ pub(crate) fn default_signer_manifest(public_key: [u8; 32]) -> Manifest {
     Manifest {
         hash: "blake2b".to_string(),
         signer: ManifestSigner {
-            signature: "ed25519".to_string(),
+            scheme: SignatureScheme::Ed25519,
             namespace: DEFAULT_NAMESPACE,
             public_key,
         },
     }
 }
+
+impl ManifestSigner {
+    /// Key the tree signatures are verified with.
+    pub(crate) fn key(&self) -> SignerKey {
+        SignerKey {
+            scheme: self.scheme,
+            public_key: self.public_key,
+        }
+    }
+}
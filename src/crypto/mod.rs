@@ -1,9 +1,27 @@
 //! Cryptographic functions.
 
+#[cfg(feature = "encryption")]
+mod encryption;
 mod hash;
+mod key_encoding;
 mod key_pair;
 mod manifest;
+mod signer;
 
+#[cfg(feature = "encryption")]
+pub(crate) use encryption::BlockEncryption;
+#[cfg(feature = "encryption")]
+pub use encryption::ENCRYPTION_OVERHEAD;
 pub(crate) use hash::{signable_tree, Hash};
-pub use key_pair::{generate as generate_signing_key, sign, verify, PartialKeypair};
+pub use key_encoding::{HexKey, KeyEncoding, NPUB_HRP};
+pub use key_pair::{
+    discovery_key, generate as generate_signing_key, generate_with as generate_signing_key_with,
+    replication_capability, sign, verify, verify_replication_capability, PartialKeypair,
+};
 pub(crate) use manifest::{default_signer_manifest, Manifest, ManifestSigner};
+#[cfg(feature = "schnorr")]
+pub use signer::SchnorrSigner;
+pub(crate) use signer::SignatureVerifier;
+#[cfg(feature = "nostr")]
+pub(crate) use signer::{schnorr_sign, schnorr_signer_key};
+pub use signer::{CoreSigner, SignFuture, SignatureScheme, Signer, SignerKey};
@@ -0,0 +1,290 @@
+//! Signers of the tree of a core. Cores are signed with Ed25519 by the secret key of their key
+//! pair, like in the Javascript implementation. A [`CoreSigner`] given when building a core
+//! signs it instead, e.g. with BIP-340 Schnorr signatures over secp256k1 from a nostr key with
+//! the `schnorr` feature. Keys that can't be held in memory, e.g. in an HSM, an OS keychain or a
+//! remote signing service, sign through the asynchronous [`Signer`]. The scheme and public key of the signer are recorded in the manifest
+//! of the oplog header, and proofs of peers are verified against them.
+//!
+//! The Javascript implementation only knows Ed25519 manifests, it can't open cores signed with
+//! Schnorr signatures.
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+
+use super::verify;
+use crate::HypercoreError;
+
+/// Signature scheme of the signer of a core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignatureScheme {
+    /// Ed25519, the only scheme of the Javascript implementation
+    #[default]
+    Ed25519,
+    /// BIP-340 Schnorr over secp256k1 with x-only public keys, as used by nostr. Signing and
+    /// verifying needs the `schnorr` feature.
+    Schnorr,
+}
+
+impl SignatureScheme {
+    /// Id of the scheme in the manifest.
+    pub(crate) fn id(&self) -> u8 {
+        match self {
+            SignatureScheme::Ed25519 => 0,
+            SignatureScheme::Schnorr => 1,
+        }
+    }
+
+    /// Scheme of an id in the manifest.
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(SignatureScheme::Ed25519),
+            1 => Some(SignatureScheme::Schnorr),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SignatureScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureScheme::Ed25519 => write!(f, "ed25519"),
+            SignatureScheme::Schnorr => write!(f, "schnorr"),
+        }
+    }
+}
+
+/// Public key of the signer of a core, which its signatures are verified with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignerKey {
+    /// Scheme of the signatures
+    pub scheme: SignatureScheme,
+    /// Public key, x-only for [`SignatureScheme::Schnorr`]
+    pub public_key: [u8; 32],
+}
+
+impl SignerKey {
+    /// Key of a core signed by the secret key of its key pair, the default.
+    pub fn ed25519(public_key: &VerifyingKey) -> Self {
+        Self {
+            scheme: SignatureScheme::Ed25519,
+            public_key: public_key.to_bytes(),
+        }
+    }
+}
+
+/// Signs the tree of a writable core, see [`crate::HypercoreBuilder::signer`].
+pub trait CoreSigner: fmt::Debug + Send + Sync {
+    /// Scheme and public key of the signatures.
+    fn key(&self) -> SignerKey;
+
+    /// Sign the message, the 64 bytes of the signature are stored as a [`Signature`] whatever
+    /// the scheme.
+    fn sign(&self, message: &[u8]) -> Signature;
+}
+
+/// Signature being created by a [`Signer`].
+pub type SignFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Signature, HypercoreError>> + Send + 'a>>;
+
+/// Signs the tree of a writable core asynchronously, see [`crate::Hypercore::new_with_signer`].
+/// Every [`CoreSigner`] is a `Signer`.
+pub trait Signer: fmt::Debug + Send + Sync {
+    /// Scheme and public key of the signatures, known without asking the signer.
+    fn key(&self) -> SignerKey;
+
+    /// Sign the message. An error fails the append or truncate being signed, the length of the
+    /// core stays the same.
+    fn sign<'a>(&'a self, message: &'a [u8]) -> SignFuture<'a>;
+}
+
+impl<T: CoreSigner + ?Sized> Signer for T {
+    fn key(&self) -> SignerKey {
+        CoreSigner::key(self)
+    }
+
+    fn sign<'a>(&'a self, message: &'a [u8]) -> SignFuture<'a> {
+        Box::pin(async move { Ok(CoreSigner::sign(self, message)) })
+    }
+}
+
+impl CoreSigner for SigningKey {
+    fn key(&self) -> SignerKey {
+        SignerKey::ed25519(&self.verifying_key())
+    }
+
+    fn sign(&self, message: &[u8]) -> Signature {
+        ed25519_dalek::Signer::sign(self, message)
+    }
+}
+
+/// Secp256k1 key signing cores with BIP-340 Schnorr signatures.
+#[cfg(feature = "schnorr")]
+#[derive(Clone)]
+pub struct SchnorrSigner {
+    signing_key: k256::schnorr::SigningKey,
+}
+
+#[cfg(feature = "schnorr")]
+impl SchnorrSigner {
+    /// Generate a new key.
+    pub fn generate() -> Self {
+        Self::generate_with(&crate::OsRandom)
+    }
+
+    /// Generate a new key from the given source of randomness.
+    pub fn generate_with(rng: &dyn crate::Rng) -> Self {
+        loop {
+            let mut secret = [0; 32];
+            rng.fill_bytes(&mut secret);
+            if let Ok(signer) = Self::from_bytes(&secret) {
+                return signer;
+            }
+        }
+    }
+
+    /// Signer of the given secret key, e.g. that of a nostr `nsec`.
+    pub fn from_bytes(secret: &[u8; 32]) -> Result<Self, HypercoreError> {
+        let signing_key = k256::schnorr::SigningKey::from_bytes(secret).map_err(|_| {
+            HypercoreError::BadArgument {
+                context: "Invalid secp256k1 secret key".to_string(),
+            }
+        })?;
+        Ok(Self { signing_key })
+    }
+
+    /// The secret key.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.signing_key.to_bytes().into()
+    }
+}
+
+#[cfg(feature = "schnorr")]
+impl CoreSigner for SchnorrSigner {
+    fn key(&self) -> SignerKey {
+        schnorr_signer_key(&self.signing_key)
+    }
+
+    fn sign(&self, message: &[u8]) -> Signature {
+        schnorr_sign(&self.signing_key, message)
+    }
+}
+
+#[cfg(feature = "schnorr")]
+impl fmt::Debug for SchnorrSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SchnorrSigner")
+            .field("key", &CoreSigner::key(self))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Key of a secp256k1 signer.
+#[cfg(feature = "schnorr")]
+pub(crate) fn schnorr_signer_key(signing_key: &k256::schnorr::SigningKey) -> SignerKey {
+    SignerKey {
+        scheme: SignatureScheme::Schnorr,
+        public_key: signing_key.verifying_key().to_bytes().into(),
+    }
+}
+
+/// Sign the message with a BIP-340 Schnorr signature, with fresh auxiliary randomness.
+#[cfg(feature = "schnorr")]
+pub(crate) fn schnorr_sign(signing_key: &k256::schnorr::SigningKey, message: &[u8]) -> Signature {
+    let mut aux_rand = [0; 32];
+    rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut aux_rand);
+    let signature = signing_key
+        .sign_raw(message, &aux_rand)
+        .expect("Schnorr signing should not fail");
+    Signature::from_bytes(&signature.to_bytes())
+}
+
+/// Verifies the signatures of a [`SignerKey`], with its public key parsed once.
+#[derive(Debug, Clone)]
+pub(crate) enum SignatureVerifier {
+    Ed25519(VerifyingKey),
+    #[cfg(feature = "schnorr")]
+    Schnorr(k256::schnorr::VerifyingKey),
+    /// Scheme this build can't verify, failing every verification
+    #[cfg_attr(feature = "schnorr", allow(dead_code))]
+    Unsupported(SignatureScheme),
+}
+
+impl SignatureVerifier {
+    /// Verifier of the signer key. Fails if the public key is invalid.
+    pub(crate) fn new(key: &SignerKey) -> Result<Self, HypercoreError> {
+        let invalid = || HypercoreError::BadArgument {
+            context: format!("Invalid {} public key of the signer", key.scheme),
+        };
+        match key.scheme {
+            SignatureScheme::Ed25519 => Ok(SignatureVerifier::Ed25519(
+                VerifyingKey::from_bytes(&key.public_key).map_err(|_| invalid())?,
+            )),
+            #[cfg(feature = "schnorr")]
+            SignatureScheme::Schnorr => Ok(SignatureVerifier::Schnorr(
+                k256::schnorr::VerifyingKey::from_bytes(&key.public_key).map_err(|_| invalid())?,
+            )),
+            #[cfg(not(feature = "schnorr"))]
+            SignatureScheme::Schnorr => Ok(SignatureVerifier::Unsupported(key.scheme)),
+        }
+    }
+
+    /// Verify a signature of the message.
+    pub(crate) fn verify(
+        &self,
+        message: &[u8],
+        signature: &Signature,
+    ) -> Result<(), HypercoreError> {
+        match self {
+            SignatureVerifier::Ed25519(public_key) => verify(public_key, message, Some(signature)),
+            #[cfg(feature = "schnorr")]
+            SignatureVerifier::Schnorr(public_key) => {
+                let signature = k256::schnorr::Signature::try_from(&signature.to_bytes()[..])
+                    .map_err(|_| HypercoreError::InvalidSignature {
+                        context: "Could not parse Schnorr signature".to_string(),
+                    })?;
+                public_key.verify_raw(message, &signature).map_err(|_| {
+                    HypercoreError::InvalidSignature {
+                        context: "Signature could not be verified.".to_string(),
+                    }
+                })
+            }
+            SignatureVerifier::Unsupported(scheme) => Err(HypercoreError::InvalidSignature {
+                context: format!("Can not verify {scheme} signatures without its feature"),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_signing_key;
+
+    #[test]
+    fn signers_sign_verifiably() -> Result<(), HypercoreError> {
+        #[cfg_attr(not(feature = "schnorr"), allow(unused_mut))]
+        let mut signers: Vec<Box<dyn CoreSigner>> = vec![Box::new(generate_signing_key())];
+        #[cfg(feature = "schnorr")]
+        signers.push(Box::new(SchnorrSigner::from_bytes(&[3; 32])?));
+        for signer in signers {
+            let verifier = SignatureVerifier::new(&signer.key())?;
+            let signature = signer.sign(b"hello");
+            verifier.verify(b"hello", &signature)?;
+            assert!(verifier.verify(b"oops", &signature).is_err());
+        }
+        let schnorr = SignerKey {
+            scheme: SignatureScheme::Schnorr,
+            public_key: [0; 32],
+        };
+        assert_eq!(
+            SignatureScheme::from_id(schnorr.scheme.id()),
+            Some(schnorr.scheme)
+        );
+        #[cfg(not(feature = "schnorr"))]
+        assert!(SignatureVerifier::new(&schnorr)?
+            .verify(b"hello", &Signature::from_bytes(&[0; 64]))
+            .is_err());
+        Ok(())
+    }
+}
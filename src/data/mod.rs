@@ -43,4 +43,9 @@ impl BlockStore {
     pub(crate) fn clear(&mut self, start: u64, length: u64) -> StoreInfo {
         StoreInfo::new_delete(Store::Data, start, length)
     }
+
+    /// Truncates to the given byte length, returns info to write to storage.
+    pub(crate) fn truncate(&mut self, byte_length: u64) -> StoreInfo {
+        StoreInfo::new_truncate(Store::Data, byte_length)
+    }
 }
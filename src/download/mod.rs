@@ -0,0 +1,177 @@
+use compact_encoding::{CompactEncoding, State};
+use futures::future::Either;
+
+use crate::common::{HypercoreError, Store, StoreInfo, StoreInfoInstruction, StoreInfoType};
+
+/// Name of the auxiliary store of the wanted ranges, see [`crate::Storage::aux`].
+pub(crate) const DOWNLOAD_STORE: &str = "download";
+
+/// Version of the download store format.
+const DOWNLOAD_STORE_VERSION: u8 = 1;
+
+/// Range of blocks wanted from peers, `start` inclusive and `end` exclusive. Ranges with a
+/// higher `priority` should be downloaded first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WantedRange {
+    /// First wanted block
+    pub start: u64,
+    /// End of the range, exclusive
+    pub end: u64,
+    /// Download priority, higher is more urgent
+    pub priority: u64,
+}
+
+/// Download progress of a wanted range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadProgress {
+    /// The wanted range
+    pub range: WantedRange,
+    /// Number of blocks of the range present locally
+    pub downloaded: u64,
+    /// First block of the range not present locally, `None` if the range is complete
+    pub next: Option<u64>,
+}
+
+/// Download store. Holds the wanted ranges so that an interrupted download resumes after a
+/// restart. Progress isn't stored here: it is derived from the bitfield, which is persisted
+/// anyway.
+#[derive(Debug)]
+pub(crate) struct DownloadStore {
+    ranges: Vec<WantedRange>,
+}
+
+impl DownloadStore {
+    pub(crate) fn open(
+        info: Option<StoreInfo>,
+    ) -> Result<Either<StoreInfoInstruction, Self>, HypercoreError> {
+        match info {
+            None => Ok(Either::Left(StoreInfoInstruction::new_size(
+                Store::aux(DOWNLOAD_STORE),
+                0,
+            ))),
+            Some(info) => {
+                if info.info_type == StoreInfoType::Size {
+                    let length = info.length.unwrap();
+                    if length == 0 {
+                        return Ok(Either::Right(Self { ranges: vec![] }));
+                    }
+                    return Ok(Either::Left(StoreInfoInstruction::new_content(
+                        Store::aux(DOWNLOAD_STORE),
+                        0,
+                        length,
+                    )));
+                }
+                let data = info.data.expect("Did not receive download store content");
+                Ok(Either::Right(Self {
+                    ranges: decode_ranges(&data)?,
+                }))
+            }
+        }
+    }
+
+    /// Wanted ranges, most urgent first.
+    pub(crate) fn ranges(&self) -> &[WantedRange] {
+        &self.ranges
+    }
+
+    /// Adds a wanted range, or changes the priority of an already wanted range.
+    pub(crate) fn want(&mut self, range: WantedRange) -> Result<Box<[StoreInfo]>, HypercoreError> {
+        self.ranges
+            .retain(|wanted| wanted.start != range.start || wanted.end != range.end);
+        let position = self
+            .ranges
+            .iter()
+            .position(|wanted| wanted.priority < range.priority)
+            .unwrap_or(self.ranges.len());
+        self.ranges.insert(position, range);
+        self.flush()
+    }
+
+    /// Removes ranges for which `remove` returns true, returns infos to flush if anything
+    /// was removed.
+    pub(crate) fn remove<F: FnMut(&WantedRange) -> bool>(
+        &mut self,
+        mut remove: F,
+    ) -> Result<Option<Box<[StoreInfo]>>, HypercoreError> {
+        let length = self.ranges.len();
+        self.ranges.retain(|wanted| !remove(wanted));
+        if self.ranges.len() == length {
+            return Ok(None);
+        }
+        Ok(Some(self.flush()?))
+    }
+
+    /// Clips the ranges to the blocks before `length`, as the rest were truncated away.
+    /// Returns infos to flush if anything changed.
+    pub(crate) fn truncate(
+        &mut self,
+        length: u64,
+    ) -> Result<Option<Box<[StoreInfo]>>, HypercoreError> {
+        if self.ranges.iter().all(|wanted| wanted.end <= length) {
+            return Ok(None);
+        }
+        self.ranges.retain(|wanted| wanted.start < length);
+        for wanted in self.ranges.iter_mut() {
+            wanted.end = wanted.end.min(length);
+        }
+        Ok(Some(self.flush()?))
+    }
+
+    fn flush(&self) -> Result<Box<[StoreInfo]>, HypercoreError> {
+        if self.ranges.is_empty() {
+            return Ok(
+                vec![StoreInfo::new_truncate(Store::aux(DOWNLOAD_STORE), 0)].into_boxed_slice()
+            );
+        }
+        let buffer = encode_ranges(&self.ranges)?;
+        Ok(vec![
+            StoreInfo::new_content(Store::aux(DOWNLOAD_STORE), 0, &buffer),
+            StoreInfo::new_truncate(Store::aux(DOWNLOAD_STORE), buffer.len() as u64),
+        ]
+        .into_boxed_slice())
+    }
+}
+
+fn encode_ranges(ranges: &[WantedRange]) -> Result<Box<[u8]>, HypercoreError> {
+    let mut state = State::new();
+    state.add_end(1)?; // Version
+    state.preencode(&(ranges.len() as u64))?;
+    for range in ranges {
+        state.preencode(&range.start)?;
+        state.preencode(&range.end)?;
+        state.preencode(&range.priority)?;
+    }
+    let mut buffer = state.create_buffer();
+    state.set_byte_to_buffer(DOWNLOAD_STORE_VERSION, &mut buffer)?;
+    state.encode(&(ranges.len() as u64), &mut buffer)?;
+    for range in ranges {
+        state.encode(&range.start, &mut buffer)?;
+        state.encode(&range.end, &mut buffer)?;
+        state.encode(&range.priority, &mut buffer)?;
+    }
+    Ok(buffer)
+}
+
+fn decode_ranges(buffer: &[u8]) -> Result<Vec<WantedRange>, HypercoreError> {
+    let mut state = State::from_buffer(buffer);
+    let version = state.decode_u8(buffer)?;
+    if version != DOWNLOAD_STORE_VERSION {
+        return Err(HypercoreError::CorruptStorage {
+            store: Store::aux(DOWNLOAD_STORE),
+            context: Some(format!("Unknown download store version {version}")),
+        });
+    }
+    let length: u64 = state.decode(buffer)?;
+    let mut ranges = Vec::with_capacity(length as usize);
+    for _ in 0..length {
+        let start: u64 = state.decode(buffer)?;
+        let end: u64 = state.decode(buffer)?;
+        let priority: u64 = state.decode(buffer)?;
+        ranges.push(WantedRange {
+            start,
+            end,
+            priority,
+        });
+    }
+    Ok(ranges)
+}
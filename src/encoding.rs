@@ -3,9 +3,20 @@ pub use compact_encoding::{CompactEncoding, EncodingError, EncodingErrorKind, St
 use std::convert::TryInto;
 use std::ops::{Deref, DerefMut};
 
+use crate::protocol::message::{
+    Bitfield, Cancel, CoreHandshake, Data, Extension, Message, NoData, Range, Request, Synchronize,
+    Unwant, Want,
+};
+use crate::protocol::mux::MuxFrame;
+#[cfg(feature = "libp2p")]
+use crate::replication::libp2p::ReplicationRequest;
+#[cfg(feature = "replication")]
+use crate::replication::{CloseCode, CloseReason};
+use crate::verifier::{VerifierRequest, VerifierResponse};
 use crate::{
-    crypto::{Manifest, ManifestSigner},
-    DataBlock, DataHash, DataSeek, DataUpgrade, Node, RequestBlock, RequestSeek, RequestUpgrade,
+    crypto::{Manifest, ManifestSigner, SignatureScheme},
+    AppendEntry, DataBlock, DataHash, DataSeek, DataUpgrade, Node, Proof, RequestBlock,
+    RequestSeek, RequestUpgrade,
 };
 
 #[derive(Debug, Clone)]
@@ -98,6 +109,14 @@ impl CompactEncoding<Vec<Node>> for HypercoreState {
 
     fn decode(&mut self, buffer: &[u8]) -> Result<Vec<Node>, EncodingError> {
         let len: usize = self.0.decode(buffer)?;
+        // Every node takes at least 34 bytes, so a hostile length can't allocate more than
+        // the buffer could hold
+        if len > buffer.len().saturating_sub(self.start()) / 34 {
+            return Err(EncodingError::new(
+                EncodingErrorKind::OutOfBounds,
+                &format!("Node count {len} exceeds the remaining buffer"),
+            ));
+        }
         let mut value = Vec::with_capacity(len);
         for _ in 0..len {
             value.push(self.decode(buffer)?);
@@ -255,6 +274,899 @@ impl CompactEncoding<DataUpgrade> for HypercoreState {
     }
 }
 
+impl CompactEncoding<Proof> for HypercoreState {
+    fn preencode(&mut self, value: &Proof) -> Result<usize, EncodingError> {
+        self.0.preencode(&value.fork)?;
+        self.0.add_end(1)?; // Flags
+        if let Some(block) = &value.block {
+            self.preencode(block)?;
+        }
+        if let Some(hash) = &value.hash {
+            self.preencode(hash)?;
+        }
+        if let Some(seek) = &value.seek {
+            self.preencode(seek)?;
+        }
+        if let Some(upgrade) = &value.upgrade {
+            self.preencode(upgrade)?;
+        }
+        Ok(self.end())
+    }
+
+    fn encode(&mut self, value: &Proof, buffer: &mut [u8]) -> Result<usize, EncodingError> {
+        self.0.encode(&value.fork, buffer)?;
+        let flags: u8 = value.block.is_some() as u8
+            | (value.hash.is_some() as u8) << 1
+            | (value.seek.is_some() as u8) << 2
+            | (value.upgrade.is_some() as u8) << 3;
+        self.0.set_byte_to_buffer(flags, buffer)?;
+        if let Some(block) = &value.block {
+            self.encode(block, buffer)?;
+        }
+        if let Some(hash) = &value.hash {
+            self.encode(hash, buffer)?;
+        }
+        if let Some(seek) = &value.seek {
+            self.encode(seek, buffer)?;
+        }
+        if let Some(upgrade) = &value.upgrade {
+            self.encode(upgrade, buffer)?;
+        }
+        Ok(self.start())
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> Result<Proof, EncodingError> {
+        let fork: u64 = self.0.decode(buffer)?;
+        let flags = self.0.decode_u8(buffer)?;
+        let block: Option<DataBlock> = if flags & 1 != 0 {
+            Some(self.decode(buffer)?)
+        } else {
+            None
+        };
+        let hash: Option<DataHash> = if flags & 2 != 0 {
+            Some(self.decode(buffer)?)
+        } else {
+            None
+        };
+        let seek: Option<DataSeek> = if flags & 4 != 0 {
+            Some(self.decode(buffer)?)
+        } else {
+            None
+        };
+        let upgrade: Option<DataUpgrade> = if flags & 8 != 0 {
+            Some(self.decode(buffer)?)
+        } else {
+            None
+        };
+        Ok(Proof {
+            fork,
+            block,
+            hash,
+            seek,
+            upgrade,
+        })
+    }
+}
+
+impl CompactEncoding<AppendEntry> for HypercoreState {
+    fn preencode(&mut self, value: &AppendEntry) -> Result<usize, EncodingError> {
+        self.0.preencode(&value.fork)?;
+        self.0.preencode(&value.start)?;
+        self.0.preencode(&value.values.len())?;
+        for data in &value.values {
+            self.0.preencode(data)?;
+        }
+        self.0.preencode(&value.signature)
+    }
+
+    fn encode(&mut self, value: &AppendEntry, buffer: &mut [u8]) -> Result<usize, EncodingError> {
+        self.0.encode(&value.fork, buffer)?;
+        self.0.encode(&value.start, buffer)?;
+        self.0.encode(&value.values.len(), buffer)?;
+        for data in &value.values {
+            self.0.encode(data, buffer)?;
+        }
+        self.0.encode(&value.signature, buffer)
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> Result<AppendEntry, EncodingError> {
+        let fork: u64 = self.0.decode(buffer)?;
+        let start: u64 = self.0.decode(buffer)?;
+        let len: usize = self.0.decode(buffer)?;
+        // Every value takes at least a byte for its length
+        if len > buffer.len().saturating_sub(self.start()) {
+            return Err(EncodingError::new(
+                EncodingErrorKind::OutOfBounds,
+                &format!("Value count {len} exceeds the remaining buffer"),
+            ));
+        }
+        let mut values = Vec::with_capacity(len);
+        for _ in 0..len {
+            values.push(self.0.decode(buffer)?);
+        }
+        let signature: Vec<u8> = self.0.decode(buffer)?;
+        Ok(AppendEntry {
+            fork,
+            start,
+            values,
+            signature,
+        })
+    }
+}
+
+#[cfg(feature = "libp2p")]
+impl CompactEncoding<ReplicationRequest> for HypercoreState {
+    fn preencode(&mut self, value: &ReplicationRequest) -> Result<usize, EncodingError> {
+        self.0.add_end(32)?; // Discovery key
+        self.0.preencode(&value.fork)?;
+        self.0.add_end(1)?; // Flags
+        if let Some(block) = &value.block {
+            self.preencode(block)?;
+        }
+        if let Some(hash) = &value.hash {
+            self.preencode(hash)?;
+        }
+        if let Some(seek) = &value.seek {
+            self.preencode(seek)?;
+        }
+        if let Some(upgrade) = &value.upgrade {
+            self.preencode(upgrade)?;
+        }
+        Ok(self.end())
+    }
+
+    fn encode(
+        &mut self,
+        value: &ReplicationRequest,
+        buffer: &mut [u8],
+    ) -> Result<usize, EncodingError> {
+        self.0.set_slice_to_buffer(&value.discovery_key, buffer)?;
+        self.0.encode(&value.fork, buffer)?;
+        let flags: u8 = value.block.is_some() as u8
+            | (value.hash.is_some() as u8) << 1
+            | (value.seek.is_some() as u8) << 2
+            | (value.upgrade.is_some() as u8) << 3;
+        self.0.set_byte_to_buffer(flags, buffer)?;
+        if let Some(block) = &value.block {
+            self.encode(block, buffer)?;
+        }
+        if let Some(hash) = &value.hash {
+            self.encode(hash, buffer)?;
+        }
+        if let Some(seek) = &value.seek {
+            self.encode(seek, buffer)?;
+        }
+        if let Some(upgrade) = &value.upgrade {
+            self.encode(upgrade, buffer)?;
+        }
+        Ok(self.start())
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> Result<ReplicationRequest, EncodingError> {
+        let discovery_key: [u8; 32] = self.0.decode_fixed_32(buffer)?[..]
+            .try_into()
+            .expect("Fixed 32 should be 32 bytes");
+        let fork: u64 = self.0.decode(buffer)?;
+        let flags = self.0.decode_u8(buffer)?;
+        let block: Option<RequestBlock> = if flags & 1 != 0 {
+            Some(self.decode(buffer)?)
+        } else {
+            None
+        };
+        let hash: Option<RequestBlock> = if flags & 2 != 0 {
+            Some(self.decode(buffer)?)
+        } else {
+            None
+        };
+        let seek: Option<RequestSeek> = if flags & 4 != 0 {
+            Some(self.decode(buffer)?)
+        } else {
+            None
+        };
+        let upgrade: Option<RequestUpgrade> = if flags & 8 != 0 {
+            Some(self.decode(buffer)?)
+        } else {
+            None
+        };
+        Ok(ReplicationRequest {
+            discovery_key,
+            fork,
+            block,
+            hash,
+            seek,
+            upgrade,
+        })
+    }
+}
+
+impl CompactEncoding<VerifierRequest> for HypercoreState {
+    fn preencode(&mut self, value: &VerifierRequest) -> Result<usize, EncodingError> {
+        self.0.add_end(1)?; // Type
+        match value {
+            VerifierRequest::VerifyUpgrade { proof, .. }
+            | VerifierRequest::VerifyBlock { proof, .. } => {
+                self.0.add_end(32)?; // Public key
+                self.preencode(proof)
+            }
+            VerifierRequest::ComputeHash { data } => self.0.preencode(data),
+        }
+    }
+
+    fn encode(
+        &mut self,
+        value: &VerifierRequest,
+        buffer: &mut [u8],
+    ) -> Result<usize, EncodingError> {
+        match value {
+            VerifierRequest::VerifyUpgrade { public_key, proof }
+            | VerifierRequest::VerifyBlock { public_key, proof } => {
+                let kind = match value {
+                    VerifierRequest::VerifyUpgrade { .. } => 0,
+                    _ => 1,
+                };
+                self.0.set_byte_to_buffer(kind, buffer)?;
+                self.0.set_slice_to_buffer(public_key, buffer)?;
+                self.encode(proof, buffer)
+            }
+            VerifierRequest::ComputeHash { data } => {
+                self.0.set_byte_to_buffer(2, buffer)?;
+                self.0.encode(data, buffer)
+            }
+        }
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> Result<VerifierRequest, EncodingError> {
+        match self.0.decode_u8(buffer)? {
+            kind @ (0 | 1) => {
+                let public_key: [u8; 32] = self.0.decode_fixed_32(buffer)?[..]
+                    .try_into()
+                    .expect("Fixed 32 should be 32 bytes");
+                let proof: Proof = self.decode(buffer)?;
+                Ok(if kind == 0 {
+                    VerifierRequest::VerifyUpgrade { public_key, proof }
+                } else {
+                    VerifierRequest::VerifyBlock { public_key, proof }
+                })
+            }
+            2 => Ok(VerifierRequest::ComputeHash {
+                data: self.0.decode(buffer)?,
+            }),
+            kind => Err(EncodingError::new(
+                EncodingErrorKind::InvalidData,
+                &format!("Unknown verifier request type {kind}"),
+            )),
+        }
+    }
+}
+
+impl CompactEncoding<VerifierResponse> for HypercoreState {
+    fn preencode(&mut self, value: &VerifierResponse) -> Result<usize, EncodingError> {
+        self.0.add_end(1)?; // Type
+        match value {
+            VerifierResponse::Verified { fork, length } => {
+                self.0.preencode(fork)?;
+                self.0.preencode(length)
+            }
+            VerifierResponse::Rejected { reason } => self.0.preencode(reason),
+            VerifierResponse::Hash(_) => self.0.add_end(32),
+        }
+    }
+
+    fn encode(
+        &mut self,
+        value: &VerifierResponse,
+        buffer: &mut [u8],
+    ) -> Result<usize, EncodingError> {
+        match value {
+            VerifierResponse::Verified { fork, length } => {
+                self.0.set_byte_to_buffer(0, buffer)?;
+                self.0.encode(fork, buffer)?;
+                self.0.encode(length, buffer)
+            }
+            VerifierResponse::Rejected { reason } => {
+                self.0.set_byte_to_buffer(1, buffer)?;
+                self.0.encode(reason, buffer)
+            }
+            VerifierResponse::Hash(hash) => {
+                self.0.set_byte_to_buffer(2, buffer)?;
+                self.0.set_slice_to_buffer(hash, buffer)
+            }
+        }
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> Result<VerifierResponse, EncodingError> {
+        match self.0.decode_u8(buffer)? {
+            0 => Ok(VerifierResponse::Verified {
+                fork: self.0.decode(buffer)?,
+                length: self.0.decode(buffer)?,
+            }),
+            1 => Ok(VerifierResponse::Rejected {
+                reason: self.0.decode(buffer)?,
+            }),
+            2 => Ok(VerifierResponse::Hash(
+                self.0.decode_fixed_32(buffer)?[..]
+                    .try_into()
+                    .expect("Fixed 32 should be 32 bytes"),
+            )),
+            kind => Err(EncodingError::new(
+                EncodingErrorKind::InvalidData,
+                &format!("Unknown verifier response type {kind}"),
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "replication")]
+impl CompactEncoding<CloseReason> for HypercoreState {
+    fn preencode(&mut self, value: &CloseReason) -> Result<usize, EncodingError> {
+        self.0.preencode(&value.code.code())?;
+        self.0.preencode(&value.message)
+    }
+
+    fn encode(&mut self, value: &CloseReason, buffer: &mut [u8]) -> Result<usize, EncodingError> {
+        self.0.encode(&value.code.code(), buffer)?;
+        self.0.encode(&value.message, buffer)
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> Result<CloseReason, EncodingError> {
+        let code: u64 = self.0.decode(buffer)?;
+        let message: String = self.0.decode(buffer)?;
+        Ok(CloseReason {
+            code: CloseCode::from_code(code),
+            message,
+        })
+    }
+}
+
+impl CompactEncoding<CoreHandshake> for HypercoreState {
+    fn preencode(&mut self, _value: &CoreHandshake) -> Result<usize, EncodingError> {
+        self.0.add_end(1)?; // Flags
+        self.0.preencode_fixed_32()
+    }
+
+    fn encode(&mut self, value: &CoreHandshake, buffer: &mut [u8]) -> Result<usize, EncodingError> {
+        self.0.set_byte_to_buffer(value.seeks as u8, buffer)?;
+        self.0.encode_fixed_32(&value.capability, buffer)
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> Result<CoreHandshake, EncodingError> {
+        let flags: u64 = self.0.decode(buffer)?;
+        let capability = self.0.decode_fixed_32(buffer)?;
+        Ok(CoreHandshake {
+            seeks: flags & 1 != 0,
+            capability: capability
+                .as_ref()
+                .try_into()
+                .expect("Fixed 32 should be 32 bytes"),
+        })
+    }
+}
+
+impl CompactEncoding<Synchronize> for HypercoreState {
+    fn preencode(&mut self, value: &Synchronize) -> Result<usize, EncodingError> {
+        self.0.add_end(1)?; // Flags
+        self.0.preencode(&value.fork)?;
+        self.0.preencode(&value.length)?;
+        self.0.preencode(&value.remote_length)
+    }
+
+    fn encode(&mut self, value: &Synchronize, buffer: &mut [u8]) -> Result<usize, EncodingError> {
+        let flags: u8 =
+            value.can_upgrade as u8 | (value.uploading as u8) << 1 | (value.downloading as u8) << 2;
+        self.0.set_byte_to_buffer(flags, buffer)?;
+        self.0.encode(&value.fork, buffer)?;
+        self.0.encode(&value.length, buffer)?;
+        self.0.encode(&value.remote_length, buffer)
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> Result<Synchronize, EncodingError> {
+        let flags: u64 = self.0.decode(buffer)?;
+        let fork: u64 = self.0.decode(buffer)?;
+        let length: u64 = self.0.decode(buffer)?;
+        let remote_length: u64 = self.0.decode(buffer)?;
+        Ok(Synchronize {
+            fork,
+            length,
+            remote_length,
+            downloading: flags & 4 != 0,
+            uploading: flags & 2 != 0,
+            can_upgrade: flags & 1 != 0,
+        })
+    }
+}
+
+impl CompactEncoding<Request> for HypercoreState {
+    fn preencode(&mut self, value: &Request) -> Result<usize, EncodingError> {
+        self.0.add_end(1)?; // Flags
+        self.0.preencode(&value.id)?;
+        self.0.preencode(&value.fork)?;
+        if let Some(block) = &value.block {
+            self.preencode(block)?;
+        }
+        if let Some(hash) = &value.hash {
+            self.preencode(hash)?;
+        }
+        if let Some(seek) = &value.seek {
+            self.preencode(seek)?;
+        }
+        if let Some(upgrade) = &value.upgrade {
+            self.preencode(upgrade)?;
+        }
+        Ok(self.end())
+    }
+
+    fn encode(&mut self, value: &Request, buffer: &mut [u8]) -> Result<usize, EncodingError> {
+        let flags: u8 = value.block.is_some() as u8
+            | (value.hash.is_some() as u8) << 1
+            | (value.seek.is_some() as u8) << 2
+            | (value.upgrade.is_some() as u8) << 3;
+        self.0.set_byte_to_buffer(flags, buffer)?;
+        self.0.encode(&value.id, buffer)?;
+        self.0.encode(&value.fork, buffer)?;
+        if let Some(block) = &value.block {
+            self.encode(block, buffer)?;
+        }
+        if let Some(hash) = &value.hash {
+            self.encode(hash, buffer)?;
+        }
+        if let Some(seek) = &value.seek {
+            self.encode(seek, buffer)?;
+        }
+        if let Some(upgrade) = &value.upgrade {
+            self.encode(upgrade, buffer)?;
+        }
+        Ok(self.start())
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> Result<Request, EncodingError> {
+        let flags: u64 = self.0.decode(buffer)?;
+        let id: u64 = self.0.decode(buffer)?;
+        let fork: u64 = self.0.decode(buffer)?;
+        let block: Option<RequestBlock> = if flags & 1 != 0 {
+            Some(self.decode(buffer)?)
+        } else {
+            None
+        };
+        let hash: Option<RequestBlock> = if flags & 2 != 0 {
+            Some(self.decode(buffer)?)
+        } else {
+            None
+        };
+        let seek: Option<RequestSeek> = if flags & 4 != 0 {
+            Some(self.decode(buffer)?)
+        } else {
+            None
+        };
+        let upgrade: Option<RequestUpgrade> = if flags & 8 != 0 {
+            Some(self.decode(buffer)?)
+        } else {
+            None
+        };
+        Ok(Request {
+            id,
+            fork,
+            block,
+            hash,
+            seek,
+            upgrade,
+        })
+    }
+}
+
+impl CompactEncoding<Data> for HypercoreState {
+    fn preencode(&mut self, value: &Data) -> Result<usize, EncodingError> {
+        self.0.add_end(1)?; // Flags
+        self.0.preencode(&value.request)?;
+        self.0.preencode(&value.fork)?;
+        if let Some(block) = &value.block {
+            self.preencode(block)?;
+        }
+        if let Some(hash) = &value.hash {
+            self.preencode(hash)?;
+        }
+        if let Some(seek) = &value.seek {
+            self.preencode(seek)?;
+        }
+        if let Some(upgrade) = &value.upgrade {
+            self.preencode(upgrade)?;
+        }
+        Ok(self.end())
+    }
+
+    fn encode(&mut self, value: &Data, buffer: &mut [u8]) -> Result<usize, EncodingError> {
+        let flags: u8 = value.block.is_some() as u8
+            | (value.hash.is_some() as u8) << 1
+            | (value.seek.is_some() as u8) << 2
+            | (value.upgrade.is_some() as u8) << 3;
+        self.0.set_byte_to_buffer(flags, buffer)?;
+        self.0.encode(&value.request, buffer)?;
+        self.0.encode(&value.fork, buffer)?;
+        if let Some(block) = &value.block {
+            self.encode(block, buffer)?;
+        }
+        if let Some(hash) = &value.hash {
+            self.encode(hash, buffer)?;
+        }
+        if let Some(seek) = &value.seek {
+            self.encode(seek, buffer)?;
+        }
+        if let Some(upgrade) = &value.upgrade {
+            self.encode(upgrade, buffer)?;
+        }
+        Ok(self.start())
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> Result<Data, EncodingError> {
+        let flags: u64 = self.0.decode(buffer)?;
+        let request: u64 = self.0.decode(buffer)?;
+        let fork: u64 = self.0.decode(buffer)?;
+        let block: Option<DataBlock> = if flags & 1 != 0 {
+            Some(self.decode(buffer)?)
+        } else {
+            None
+        };
+        let hash: Option<DataHash> = if flags & 2 != 0 {
+            Some(self.decode(buffer)?)
+        } else {
+            None
+        };
+        let seek: Option<DataSeek> = if flags & 4 != 0 {
+            Some(self.decode(buffer)?)
+        } else {
+            None
+        };
+        let upgrade: Option<DataUpgrade> = if flags & 8 != 0 {
+            Some(self.decode(buffer)?)
+        } else {
+            None
+        };
+        Ok(Data {
+            request,
+            fork,
+            block,
+            hash,
+            seek,
+            upgrade,
+        })
+    }
+}
+
+impl CompactEncoding<Bitfield> for HypercoreState {
+    fn preencode(&mut self, value: &Bitfield) -> Result<usize, EncodingError> {
+        self.0.preencode(&value.start)?;
+        self.0.preencode_u32_array(&value.bitfield)
+    }
+
+    fn encode(&mut self, value: &Bitfield, buffer: &mut [u8]) -> Result<usize, EncodingError> {
+        self.0.encode(&value.start, buffer)?;
+        self.0.encode_u32_array(&value.bitfield, buffer)
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> Result<Bitfield, EncodingError> {
+        let start: u64 = self.0.decode(buffer)?;
+        // Peek the word count to refuse a hostile length before it is allocated
+        let words_start = self.start();
+        let len: usize = self.0.decode(buffer)?;
+        if len > buffer.len().saturating_sub(self.start()) / 4 {
+            return Err(EncodingError::new(
+                EncodingErrorKind::OutOfBounds,
+                &format!("Bitfield word count {len} exceeds the remaining buffer"),
+            ));
+        }
+        self.0.set_start(words_start)?;
+        let bitfield = self.0.decode_u32_array(buffer)?;
+        Ok(Bitfield { start, bitfield })
+    }
+}
+
+impl CompactEncoding<Range> for HypercoreState {
+    fn preencode(&mut self, value: &Range) -> Result<usize, EncodingError> {
+        self.0.add_end(1)?; // Flags
+        self.0.preencode(&value.start)?;
+        if value.length != 1 {
+            self.0.preencode(&value.length)?;
+        }
+        Ok(self.end())
+    }
+
+    fn encode(&mut self, value: &Range, buffer: &mut [u8]) -> Result<usize, EncodingError> {
+        let flags: u8 = value.drop as u8 | ((value.length == 1) as u8) << 1;
+        self.0.set_byte_to_buffer(flags, buffer)?;
+        self.0.encode(&value.start, buffer)?;
+        if value.length != 1 {
+            self.0.encode(&value.length, buffer)?;
+        }
+        Ok(self.start())
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> Result<Range, EncodingError> {
+        let flags: u64 = self.0.decode(buffer)?;
+        let start: u64 = self.0.decode(buffer)?;
+        let length: u64 = if flags & 2 != 0 {
+            1
+        } else {
+            self.0.decode(buffer)?
+        };
+        Ok(Range {
+            drop: flags & 1 != 0,
+            start,
+            length,
+        })
+    }
+}
+
+impl CompactEncoding<Extension> for HypercoreState {
+    fn preencode(&mut self, value: &Extension) -> Result<usize, EncodingError> {
+        self.0.preencode_str(&value.name)?;
+        self.0.preencode_raw_buffer(&value.message)
+    }
+
+    fn encode(&mut self, value: &Extension, buffer: &mut [u8]) -> Result<usize, EncodingError> {
+        self.0.encode_str(&value.name, buffer)?;
+        self.0.encode_raw_buffer(&value.message, buffer)
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> Result<Extension, EncodingError> {
+        let name = self.0.decode_string(buffer)?;
+        let message = decode_rest(&mut self.0, buffer)?;
+        Ok(Extension { name, message })
+    }
+}
+
+/// Encoding of messages made of one uint request id.
+macro_rules! impl_request_id_encoding {
+    ($message:ident) => {
+        impl CompactEncoding<$message> for HypercoreState {
+            fn preencode(&mut self, value: &$message) -> Result<usize, EncodingError> {
+                self.0.preencode(&value.request)
+            }
+
+            fn encode(
+                &mut self,
+                value: &$message,
+                buffer: &mut [u8],
+            ) -> Result<usize, EncodingError> {
+                self.0.encode(&value.request, buffer)
+            }
+
+            fn decode(&mut self, buffer: &[u8]) -> Result<$message, EncodingError> {
+                Ok($message {
+                    request: self.0.decode(buffer)?,
+                })
+            }
+        }
+    };
+}
+
+/// Encoding of messages made of a uint start and length.
+macro_rules! impl_range_encoding {
+    ($message:ident) => {
+        impl CompactEncoding<$message> for HypercoreState {
+            fn preencode(&mut self, value: &$message) -> Result<usize, EncodingError> {
+                self.0.preencode(&value.start)?;
+                self.0.preencode(&value.length)
+            }
+
+            fn encode(
+                &mut self,
+                value: &$message,
+                buffer: &mut [u8],
+            ) -> Result<usize, EncodingError> {
+                self.0.encode(&value.start, buffer)?;
+                self.0.encode(&value.length, buffer)
+            }
+
+            fn decode(&mut self, buffer: &[u8]) -> Result<$message, EncodingError> {
+                Ok($message {
+                    start: self.0.decode(buffer)?,
+                    length: self.0.decode(buffer)?,
+                })
+            }
+        }
+    };
+}
+
+impl_request_id_encoding!(Cancel);
+impl_request_id_encoding!(NoData);
+impl_range_encoding!(Want);
+impl_range_encoding!(Unwant);
+
+impl HypercoreState {
+    /// Preencode a message without its type.
+    pub(crate) fn preencode_message_body(
+        &mut self,
+        value: &Message,
+    ) -> Result<usize, EncodingError> {
+        match value {
+            Message::Synchronize(message) => self.preencode(message),
+            Message::Request(message) => self.preencode(message),
+            Message::Cancel(message) => self.preencode(message),
+            Message::Data(message) => self.preencode(message),
+            Message::NoData(message) => self.preencode(message),
+            Message::Want(message) => self.preencode(message),
+            Message::Unwant(message) => self.preencode(message),
+            Message::Bitfield(message) => self.preencode(message),
+            Message::Range(message) => self.preencode(message),
+            Message::Extension(message) => self.preencode(message),
+        }
+    }
+
+    /// Encode a message without its type.
+    pub(crate) fn encode_message_body(
+        &mut self,
+        value: &Message,
+        buffer: &mut [u8],
+    ) -> Result<usize, EncodingError> {
+        match value {
+            Message::Synchronize(message) => self.encode(message, buffer),
+            Message::Request(message) => self.encode(message, buffer),
+            Message::Cancel(message) => self.encode(message, buffer),
+            Message::Data(message) => self.encode(message, buffer),
+            Message::NoData(message) => self.encode(message, buffer),
+            Message::Want(message) => self.encode(message, buffer),
+            Message::Unwant(message) => self.encode(message, buffer),
+            Message::Bitfield(message) => self.encode(message, buffer),
+            Message::Range(message) => self.encode(message, buffer),
+            Message::Extension(message) => self.encode(message, buffer),
+        }
+    }
+
+    /// Decode the body of a message of the given type.
+    pub(crate) fn decode_message_body(
+        &mut self,
+        type_id: u64,
+        buffer: &[u8],
+    ) -> Result<Message, EncodingError> {
+        Ok(match type_id {
+            0 => Message::Synchronize(self.decode(buffer)?),
+            1 => Message::Request(self.decode(buffer)?),
+            2 => Message::Cancel(self.decode(buffer)?),
+            3 => Message::Data(self.decode(buffer)?),
+            4 => Message::NoData(self.decode(buffer)?),
+            5 => Message::Want(self.decode(buffer)?),
+            6 => Message::Unwant(self.decode(buffer)?),
+            7 => Message::Bitfield(self.decode(buffer)?),
+            8 => Message::Range(self.decode(buffer)?),
+            9 => Message::Extension(self.decode(buffer)?),
+            type_id => {
+                return Err(EncodingError::new(
+                    EncodingErrorKind::InvalidData,
+                    &format!("Unknown message type {type_id}"),
+                ))
+            }
+        })
+    }
+}
+
+impl CompactEncoding<Message> for HypercoreState {
+    fn preencode(&mut self, value: &Message) -> Result<usize, EncodingError> {
+        self.0.preencode(&value.type_id())?;
+        self.preencode_message_body(value)
+    }
+
+    fn encode(&mut self, value: &Message, buffer: &mut [u8]) -> Result<usize, EncodingError> {
+        self.0.encode(&value.type_id(), buffer)?;
+        self.encode_message_body(value, buffer)
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> Result<Message, EncodingError> {
+        let type_id: u64 = self.0.decode(buffer)?;
+        self.decode_message_body(type_id, buffer)
+    }
+}
+
+impl CompactEncoding<MuxFrame> for HypercoreState {
+    fn preencode(&mut self, value: &MuxFrame) -> Result<usize, EncodingError> {
+        match value {
+            MuxFrame::Open {
+                channel,
+                protocol,
+                id,
+                handshake,
+            } => {
+                self.0.add_end(2)?; // Control channel and type
+                self.0.preencode(channel)?;
+                self.0.preencode_str(protocol)?;
+                self.0.preencode_buffer(id)?;
+                self.0.preencode_raw_buffer(handshake)
+            }
+            MuxFrame::Reject { channel } | MuxFrame::Close { channel } => {
+                self.0.add_end(2)?; // Control channel and type
+                self.0.preencode(channel)
+            }
+            MuxFrame::Message {
+                channel,
+                message_type,
+                message,
+            } => {
+                self.0.preencode(channel)?;
+                self.0.preencode(message_type)?;
+                self.0.preencode_raw_buffer(message)
+            }
+        }
+    }
+
+    fn encode(&mut self, value: &MuxFrame, buffer: &mut [u8]) -> Result<usize, EncodingError> {
+        match value {
+            MuxFrame::Open {
+                channel,
+                protocol,
+                id,
+                handshake,
+            } => {
+                self.0.set_byte_to_buffer(0, buffer)?;
+                self.0.set_byte_to_buffer(1, buffer)?;
+                self.0.encode(channel, buffer)?;
+                self.0.encode_str(protocol, buffer)?;
+                self.0.encode_buffer(id, buffer)?;
+                self.0.encode_raw_buffer(handshake, buffer)
+            }
+            MuxFrame::Reject { channel } | MuxFrame::Close { channel } => {
+                let kind = match value {
+                    MuxFrame::Reject { .. } => 2,
+                    _ => 3,
+                };
+                self.0.set_byte_to_buffer(0, buffer)?;
+                self.0.set_byte_to_buffer(kind, buffer)?;
+                self.0.encode(channel, buffer)
+            }
+            MuxFrame::Message {
+                channel,
+                message_type,
+                message,
+            } => {
+                if *channel == 0 {
+                    return Err(EncodingError::new(
+                        EncodingErrorKind::InvalidData,
+                        "Channel 0 is the control channel",
+                    ));
+                }
+                self.0.encode(channel, buffer)?;
+                self.0.encode(message_type, buffer)?;
+                self.0.encode_raw_buffer(message, buffer)
+            }
+        }
+    }
+
+    fn decode(&mut self, buffer: &[u8]) -> Result<MuxFrame, EncodingError> {
+        let channel: u64 = self.0.decode(buffer)?;
+        if channel != 0 {
+            let message_type: u64 = self.0.decode(buffer)?;
+            let message = decode_rest(&mut self.0, buffer)?;
+            return Ok(MuxFrame::Message {
+                channel,
+                message_type,
+                message,
+            });
+        }
+        let kind: u64 = self.0.decode(buffer)?;
+        match kind {
+            1 => {
+                let channel: u64 = self.0.decode(buffer)?;
+                let protocol = self.0.decode_string(buffer)?;
+                let id = self.0.decode_buffer_vec(buffer)?;
+                let handshake = decode_rest(&mut self.0, buffer)?;
+                Ok(MuxFrame::Open {
+                    channel,
+                    protocol,
+                    id,
+                    handshake,
+                })
+            }
+            2 => Ok(MuxFrame::Reject {
+                channel: self.0.decode(buffer)?,
+            }),
+            3 => Ok(MuxFrame::Close {
+                channel: self.0.decode(buffer)?,
+            }),
+            kind => Err(EncodingError::new(
+                EncodingErrorKind::InvalidData,
+                &format!("Unsupported control message type {kind}"),
+            )),
+        }
+    }
+}
+
 impl CompactEncoding<Manifest> for State {
     fn preencode(&mut self, value: &Manifest) -> Result<usize, EncodingError> {
         self.add_end(1)?; // Version
@@ -318,28 +1230,19 @@ impl CompactEncoding<ManifestSigner> for State {
         value: &ManifestSigner,
         buffer: &mut [u8],
     ) -> Result<usize, EncodingError> {
-        if &value.signature == "ed25519" {
-            self.set_byte_to_buffer(0, buffer)?;
-        } else {
-            return Err(EncodingError::new(
-                EncodingErrorKind::InvalidData,
-                &format!("Unknown signature type: {}", &value.signature),
-            ));
-        }
+        self.set_byte_to_buffer(value.scheme.id(), buffer)?;
         self.encode_fixed_32(&value.namespace, buffer)?;
         self.encode_fixed_32(&value.public_key, buffer)
     }
 
     fn decode(&mut self, buffer: &[u8]) -> Result<ManifestSigner, EncodingError> {
         let signature_id: u8 = self.decode_u8(buffer)?;
-        let signature: String = if signature_id != 0 {
-            return Err(EncodingError::new(
+        let scheme = SignatureScheme::from_id(signature_id).ok_or_else(|| {
+            EncodingError::new(
                 EncodingErrorKind::InvalidData,
                 &format!("Unknown signature id: {signature_id}"),
-            ));
-        } else {
-            "ed25519".to_string()
-        };
+            )
+        })?;
         let namespace: [u8; 32] =
             self.decode_fixed_32(buffer)?
                 .to_vec()
@@ -362,9 +1265,18 @@ impl CompactEncoding<ManifestSigner> for State {
                 })?;
 
         Ok(ManifestSigner {
-            signature,
+            scheme,
             namespace,
             public_key,
         })
     }
 }
+
+/// Decode the rest of the buffer as raw bytes, which may be nothing.
+fn decode_rest(state: &mut State, buffer: &[u8]) -> Result<Vec<u8>, EncodingError> {
+    if state.start() < state.end() {
+        state.decode_raw_buffer(buffer)
+    } else {
+        Ok(vec![])
+    }
+}
@@ -0,0 +1,239 @@
+//! Proofs against externally published checkpoints, so that third parties can audit a core
+//! without replicating it: that a block was part of the core at a checkpoint, and that a
+//! later checkpoint only appended to an earlier one.
+use ed25519_dalek::Signature;
+use std::collections::HashMap;
+
+use crate::crypto::{signable_tree, verify};
+use crate::tree::{hash_leaf, hash_parent, hash_roots};
+use crate::{Node, VerifyingKey};
+
+/// State of a core at some length, e.g. published in a nostr event or a transparency log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Checkpoint {
+    /// Fork of the core
+    pub fork: u64,
+    /// Length of the core
+    pub length: u64,
+    /// Tree hash at the length, i.e. the hash of the roots
+    pub root_hash: [u8; 32],
+}
+
+impl Checkpoint {
+    /// Verify that the writer of the core with the given public key signed this checkpoint,
+    /// e.g. with the signature of [`crate::Hypercore::checkpoint_signature`].
+    pub fn verify_signature(&self, public_key: &VerifyingKey, signature: &Signature) -> bool {
+        verify(
+            public_key,
+            &signable_tree(&self.root_hash, self.length, self.fork),
+            Some(signature),
+        )
+        .is_ok()
+    }
+}
+
+/// Head that the upgrade of a proof signs, see [`crate::Hypercore::check_signed_head`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignedHead {
+    /// Fork, length and tree hash that the writer signed
+    pub checkpoint: Checkpoint,
+    /// The head is of the local fork and within the local length, but of another tree, i.e.
+    /// the writer signed two conflicting trees
+    pub conflicts: bool,
+}
+
+/// Proof that a block was part of a core at a [`Checkpoint`], created with
+/// [`crate::Hypercore::create_inclusion_proof`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct InclusionProof {
+    /// Index of the block
+    pub index: u64,
+    /// Value of the block
+    pub value: Vec<u8>,
+    /// Siblings of the nodes on the path from the leaf of the block to its root, bottom up
+    pub nodes: Vec<Node>,
+    /// Roots of the tree at the checkpoint length
+    pub roots: Vec<Node>,
+}
+
+impl InclusionProof {
+    /// Verify that the block was part of the core at the checkpoint. Only the hashes are
+    /// checked, verify the checkpoint itself with [`Checkpoint::verify_signature`] or against
+    /// where it was published.
+    pub fn verify(&self, checkpoint: &Checkpoint) -> bool {
+        if self.index >= checkpoint.length {
+            return false;
+        }
+        if !roots_match(&self.roots, checkpoint) {
+            return false;
+        }
+
+        let mut node = Node::new(
+            self.index * 2,
+            hash_leaf(&self.value).to_vec(),
+            self.value.len() as u64,
+        );
+        for sibling in &self.nodes {
+            if sibling.index != flat_tree::sibling(node.index) {
+                return false;
+            }
+            node = hash_parent(&node, sibling);
+        }
+        self.roots
+            .iter()
+            .any(|root| root.index == node.index && root.hash == node.hash)
+    }
+}
+
+/// Proof that the tree at a later [`Checkpoint`] extends the tree at an earlier one, i.e. that
+/// the history up to the earlier length was not rewritten, created with
+/// [`crate::Hypercore::create_consistency_proof`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsistencyProof {
+    /// Roots of the tree at the earlier length
+    pub old_roots: Vec<Node>,
+    /// Roots of the tree at the later length
+    pub new_roots: Vec<Node>,
+    /// Nodes needed, besides the earlier roots, to hash the earlier roots up to the later ones
+    pub nodes: Vec<Node>,
+}
+
+impl ConsistencyProof {
+    /// Verify that the tree at `new` extends the tree at `old`. As with
+    /// [`InclusionProof::verify`], only the hashes are checked.
+    pub fn verify(&self, old: &Checkpoint, new: &Checkpoint) -> bool {
+        if old.length > new.length
+            || !roots_match(&self.old_roots, old)
+            || !roots_match(&self.new_roots, new)
+        {
+            return false;
+        }
+        let new_roots: HashMap<u64, &Node> = self
+            .new_roots
+            .iter()
+            .map(|root| (root.index, root))
+            .collect();
+        let known: HashMap<u64, &Node> = self
+            .old_roots
+            .iter()
+            .chain(&self.nodes)
+            .map(|node| (node.index, node))
+            .collect();
+        self.old_roots.iter().all(|old_root| {
+            let mut node = old_root.clone();
+            while !new_roots.contains_key(&node.index) {
+                let Some(sibling) = known.get(&flat_tree::sibling(node.index)) else {
+                    return false;
+                };
+                node = hash_parent(&node, sibling);
+            }
+            new_roots[&node.index].hash == node.hash
+        })
+    }
+}
+
+/// Indices of the roots of the tree of the given length.
+pub(crate) fn root_indices(length: u64) -> Vec<u64> {
+    let mut roots = vec![];
+    flat_tree::full_roots(length * 2, &mut roots);
+    roots
+}
+
+/// Are the roots those of the checkpoint's length, hashing to its root hash.
+fn roots_match(roots: &[Node], checkpoint: &Checkpoint) -> bool {
+    let indices = root_indices(checkpoint.length);
+    roots.len() == indices.len()
+        && roots
+            .iter()
+            .zip(&indices)
+            .all(|(root, index)| root.index == *index)
+        && hash_roots(roots) == checkpoint.root_hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::{create_hypercore_with_data, create_hypercore_with_data_and_key_pair};
+    use crate::HypercoreError;
+
+    #[async_std::test]
+    async fn inclusion_proof_verifies_against_checkpoint() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(5).await?;
+        let checkpoint = hypercore.checkpoint();
+        let signature = hypercore.checkpoint_signature().unwrap();
+        assert!(checkpoint.verify_signature(&hypercore.key_pair().public, &signature));
+        hypercore.append_batch([b"#5", b"#6", b"#7"]).await?;
+
+        for index in 0..5 {
+            let proof = hypercore
+                .create_inclusion_proof(index, &checkpoint)
+                .await?
+                .unwrap();
+            assert_eq!(proof.value, format!("#{index}").into_bytes());
+            assert!(proof.verify(&checkpoint));
+        }
+        // Blocks appended after the checkpoint can't be proven against it
+        assert!(hypercore
+            .create_inclusion_proof(5, &checkpoint)
+            .await
+            .is_err());
+
+        let mut proof = hypercore
+            .create_inclusion_proof(3, &checkpoint)
+            .await?
+            .unwrap();
+        let later = hypercore.checkpoint();
+        assert!(!proof.verify(&later));
+        let later_signature = hypercore.checkpoint_signature().unwrap();
+        assert!(!checkpoint.verify_signature(&hypercore.key_pair().public, &later_signature));
+        proof.value = b"#x".to_vec();
+        assert!(!proof.verify(&checkpoint));
+
+        // Checkpoints of another history are rejected
+        let forged = Checkpoint {
+            root_hash: [0; 32],
+            ..checkpoint
+        };
+        assert!(hypercore.create_inclusion_proof(3, &forged).await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn consistency_proof_detects_rewrites() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(0).await?;
+        let mut checkpoints = vec![hypercore.checkpoint()];
+        for index in 0..13 {
+            hypercore.append(format!("#{index}").as_bytes()).await?;
+            checkpoints.push(hypercore.checkpoint());
+        }
+        for old in &checkpoints {
+            for new in checkpoints.iter().filter(|new| new.length >= old.length) {
+                let proof = hypercore.create_consistency_proof(old, new).await?;
+                assert!(proof.verify(old, new));
+                assert_eq!(proof.verify(new, old), old == new);
+            }
+        }
+
+        // A rewritten history is not consistent with the published checkpoints
+        let old = checkpoints[5];
+        let new = checkpoints[13];
+        let mut rewriter =
+            create_hypercore_with_data_and_key_pair(4, hypercore.key_pair().clone()).await?;
+        rewriter.append_batch([b"#x", b"#5"]).await?;
+        let rewritten = rewriter.checkpoint();
+        assert!(rewriter
+            .create_consistency_proof(&old, &rewritten)
+            .await
+            .is_err());
+        let proof = rewriter
+            .create_consistency_proof(&checkpoints[4], &rewritten)
+            .await?;
+        assert!(proof.verify(&checkpoints[4], &rewritten));
+        assert!(!proof.verify(&checkpoints[4], &new));
+
+        let mut forged = proof.clone();
+        forged.old_roots = proof.new_roots.clone();
+        assert!(!forged.verify(&old, &rewritten));
+        Ok(())
+    }
+}
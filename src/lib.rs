@@ -1,4 +1,8 @@
-#![forbid(unsafe_code, future_incompatible)]
+// Memory maps can't be read without unsafe code, which the `mmap` feature confines to the
+// module of its store
+#![cfg_attr(not(feature = "mmap"), forbid(unsafe_code))]
+#![cfg_attr(feature = "mmap", deny(unsafe_code))]
+#![forbid(future_incompatible)]
 #![forbid(rust_2018_idioms, rust_2018_compatibility)]
 #![forbid(missing_debug_implementations)]
 #![forbid(missing_docs)]
@@ -19,21 +23,78 @@
 //!
 //! ## Features
 //!
-//! ### `sparse` (default)
+//! By default, the crate is just the tree, the encodings and memory storage, for users of only
+//! the verification logic. Disk storage and replication are opt-in, e.g.
+//! `features = ["tokio", "sparse", "replication"]` for the features that used to be the
+//! default.
 //!
-//! When using disk storage, clearing values may create sparse files. On by default.
+//! ### `sparse`
 //!
-//! ### `async-std` (default)
-//!
-//! Use the async-std runtime, on by default. Either this or `tokio` is mandatory.
+//! When using disk storage, clearing values may create sparse files.
 //!
 //! ### `tokio`
 //!
-//! Use the tokio runtime. Either this or `async_std` is mandatory.
+//! Use the tokio runtime for disk storage, enables `disk`.
+//!
+//! ### `async-std`
+//!
+//! Use the async-std runtime for disk storage, enables `disk`.
+//!
+//! ### `disk`
+//!
+//! Disk storage: `Storage::new_disk`, `Settings::open_disk` and `CacheStore`. Needs `tokio` or
+//! `async-std`, one of which picks the runtime of the file IO.
+//!
+//! ### `replication`
+//!
+//! Events and helpers for replicating cores with peers, in `replication`, and the Noise
+//! handshake opening encrypted channels to them, in `protocol::handshake`.
 //!
 //! ### `cache`
 //!
-//! Use a moka cache for merkle tree nodes to speed-up reading.
+//! Use a moka cache for merkle tree nodes to speed-up reading. The warmed cache can be
+//! carried over restarts with `Hypercore::node_cache_snapshot` and
+//! `Hypercore::load_node_cache_snapshot`.
+//!
+//! ### `parallel`
+//!
+//! Hash the blocks of big appended batches in parallel with rayon, see
+//! `HypercoreBuilder::hash_threads`.
+//!
+//! ### `libp2p`
+//!
+//! Replicate cores over libp2p with the request-response behaviour in
+//! `replication::libp2p`.
+//!
+//! ### `schnorr`
+//!
+//! Sign cores with BIP-340 Schnorr signatures over secp256k1 instead of Ed25519, by giving a
+//! `SchnorrSigner` to `HypercoreBuilder::signer`. Such cores can't be opened by
+//! the Javascript implementation.
+//!
+//! ### `nostr`
+//!
+//! Announce cores on nostr relays and tunnel replication through their ephemeral events, for
+//! peers that can't connect directly, in `nostr`. Enables `schnorr`, nostr keys can sign cores.
+//! With `replication`, replication alerts can be sent to the owner of a core as encrypted
+//! direct messages.
+//!
+//! ### `encryption`
+//!
+//! Encrypt the values of blocks with XChaCha20-Poly1305, see
+//! `HypercoreBuilder::encryption_key`. Peers without the key replicate the encrypted blocks.
+//!
+//! ### `mmap`
+//!
+//! Read the tree and data stores of disk storage through memory maps, see
+//! `Storage::new_disk_mmap`. Enables `disk`, so needs `tokio` or `async-std`. Another process truncating the files of an open
+//! core then crashes the reading one.
+//!
+//! ### `unsafe_raw`
+//!
+//! Read and write the raw bytes of the stores with `Storage::read_raw` and
+//! `Storage::write_raw`, for tools like debuggers and migrators. Raw writes bypass every
+//! check of the hypercore.
 //!
 //! ## Example
 //! ```rust
@@ -41,7 +102,7 @@
 //! # tokio_test::block_on(async {
 //! # example().await;
 //! # });
-//! # #[cfg(feature = "async-std")]
+//! # #[cfg(not(feature = "tokio"))]
 //! # async_std::task::block_on(async {
 //! # example().await;
 //! # });
@@ -66,37 +127,110 @@
 //!
 //! Find more examples in the [examples] folder.
 //!
+//! ## Sharing between tasks
+//!
+//! [Hypercore] is `Send + Sync` and its futures are `Send`. Reads, like `get`, `has` and
+//! `create_proof`, take `&self` and lock the stores they read internally, so an
+//! `Arc<Hypercore>` can be read from many tasks at once. Writes take `&mut self`: share a
+//! hypercore that is also written behind an `Arc<RwLock<Hypercore>>`, where readers hold the
+//! read lock together. With the `shared-core` feature, `replication::SharedCore` wraps a
+//! hypercore in a mutex and implements the replication traits.
+//!
+//! Producers that only append can instead share an [Appender] from [append_queue], while the
+//! task owning the hypercore runs the queue.
+//!
 //! [Dat]: https://github.com/datrs
 //! [holepunch-hypercore]: https://github.com/holepunchto/hypercore
 //! [Hypercore]: crate::core::Hypercore
 //! [HypercoreBuilder]: crate::builder::HypercoreBuilder
 //! [examples]: https://github.com/datrs/hypercore/tree/master/examples
 
+#[cfg(all(feature = "disk", not(any(feature = "tokio", feature = "async-std"))))]
+compile_error!("disk storage needs a runtime for its file IO, enable `tokio` or `async-std`");
+
 pub mod encoding;
+#[cfg(feature = "nostr")]
+pub mod nostr;
 pub mod prelude;
+pub mod protocol;
 #[cfg(feature = "replication")]
 pub mod replication;
+pub mod tree;
+pub mod verifier;
 
+mod acl;
+mod annotation;
+mod append_entry;
+mod appender;
 mod bitfield;
 mod builder;
+mod bundle;
+#[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+mod cache_store;
+mod checksum;
+mod chunking;
 mod common;
 mod core;
 mod crypto;
 mod data;
+mod download;
+mod inclusion;
+mod light;
+mod merge;
 mod oplog;
+mod overflow;
+mod projection;
+mod receipt;
+mod record;
+mod settings;
 mod storage;
-mod tree;
+mod url;
+#[cfg(not(target_arch = "wasm32"))]
+mod verify_pool;
 
+pub use crate::acl::Restriction;
+pub use crate::append_entry::AppendEntry;
+pub use crate::appender::{append_queue, AppendQueue, Appender};
+pub use crate::bitfield::BitfieldFormat;
 #[cfg(feature = "cache")]
 pub use crate::builder::CacheOptionsBuilder;
 pub use crate::builder::HypercoreBuilder;
+pub use crate::bundle::{bundle_count, bundle_record, decode_bundle, encode_bundle, RecordId};
+#[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+pub use crate::cache_store::CacheStore;
+pub use crate::chunking::{Chunking, PayloadStats};
 pub use crate::common::{
-    DataBlock, DataHash, DataSeek, DataUpgrade, HypercoreError, Node, Proof, RequestBlock,
-    RequestSeek, RequestUpgrade, Store,
+    AppendGrowth, AppendPolicy, Backoff, ByteRangePlan, Clock, DataBlock, DataHash, DataSeek,
+    DataUpgrade, HypercoreError, Limits, ManualClock, Node, OsRandom, Progress, Proof, Quota,
+    RequestBlock, RequestSeek, RequestUpgrade, RetryPolicy, Rng, SeededRng, Store, SystemClock,
+};
+pub use crate::core::{
+    AppendOutcome, Hypercore, Info, PrefetchOutcome, ReadTxn, RecoveryReport, SimulatedAppend,
+};
+#[cfg(feature = "schnorr")]
+pub use crate::crypto::SchnorrSigner;
+#[cfg(feature = "encryption")]
+pub use crate::crypto::ENCRYPTION_OVERHEAD;
+pub use crate::crypto::{
+    discovery_key, generate_signing_key, generate_signing_key_with, replication_capability, sign,
+    verify, verify_replication_capability, CoreSigner, HexKey, KeyEncoding, PartialKeypair,
+    SignFuture, SignatureScheme, Signer, SignerKey, NPUB_HRP,
+};
+pub use crate::download::{DownloadProgress, WantedRange};
+pub use crate::inclusion::{Checkpoint, ConsistencyProof, InclusionProof, SignedHead};
+pub use crate::light::LightCore;
+pub use crate::merge::{merge_iterate, MergeEntry};
+pub use crate::overflow::{BlobPointer, OverflowCore, BLOB_BLOCK_SIZE};
+pub use crate::projection::{Projection, Projector};
+pub use crate::receipt::{PinReceipt, PIN_RECEIPT_SIZE};
+pub use crate::record::{parse_record_block, FieldDisclosure, Record};
+pub use crate::settings::{SettingValue, Settings};
+pub use crate::storage::{
+    AuxStore, IoOperation, SlowIo, SlowIoListener, SlowIoWatchdog, Storage, StorageTraits,
 };
-pub use crate::core::{AppendOutcome, Hypercore, Info};
-pub use crate::crypto::{generate_signing_key, sign, verify, PartialKeypair};
-pub use crate::storage::{Storage, StorageTraits};
+pub use crate::url::{Url, UrlScheme, UrlVersion};
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::verify_pool::{VerifiedProof, VerifyJob, VerifyPool};
 pub use ed25519_dalek::{
     SecretKey, Signature, SigningKey, VerifyingKey, KEYPAIR_LENGTH, PUBLIC_KEY_LENGTH,
     SECRET_KEY_LENGTH,
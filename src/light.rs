@@ -0,0 +1,562 @@
+//! Verification-only light client. Tracks the signed head of a hypercore without storing the
+//! data or the tree, and verifies proofs for single blocks on demand.
+use compact_encoding::EncodingError;
+use ed25519_dalek::Signature;
+use intmap::IntMap;
+use std::collections::HashSet;
+
+use crate::{
+    common::{HypercoreError, Proof},
+    crypto::SignatureVerifier,
+    encoding::{CompactEncoding, HypercoreState},
+    tree::{verify_tree, verify_upgrade, MerkleTreeChangeset},
+    Checkpoint, Node, SignerKey, Store, VerifyingKey,
+};
+
+/// Version of the encoding of a head, see [`LightCore::encode_head`].
+const HEAD_VERSION: u8 = 1;
+
+/// A light hypercore that keeps only the latest verified head (fork, length, roots and
+/// signature) in memory, plus the tree nodes of the proofs it has verified since.
+///
+/// Use `missing_nodes` to fill the `nodes` field of a `RequestBlock` and pass the
+/// received `Proof` to `verify_proof`. Proofs need to carry an upgrade until the light core
+/// has a head that covers the requested block.
+///
+/// To survive restarts, store the head encoded with `encode_head` and load it back with
+/// `from_head`, which checks its signature again. Retained proofs are not part of the head.
+///
+/// Cores signed by another signer than their key pair, see
+/// [`HypercoreBuilder::signer_key`](crate::HypercoreBuilder::signer_key), are followed with
+/// `new_with_signer_key` and `from_head_with_signer_key`.
+#[derive(Debug)]
+pub struct LightCore {
+    public_key: VerifyingKey,
+    signer_key: SignerKey,
+    verifier: SignatureVerifier,
+    fork: u64,
+    length: u64,
+    byte_length: u64,
+    roots: Vec<Node>,
+    hash: Option<Box<[u8]>>,
+    signature: Option<Signature>,
+    nodes: IntMap<Node>,
+    blocks: HashSet<u64>,
+}
+
+impl LightCore {
+    /// Creates a new light core for the hypercore with the given public key. The head is empty
+    /// until the first proof with an upgrade is verified.
+    pub fn new(public_key: VerifyingKey) -> Self {
+        Self {
+            public_key,
+            signer_key: SignerKey::ed25519(&public_key),
+            verifier: SignatureVerifier::Ed25519(public_key),
+            fork: 0,
+            length: 0,
+            byte_length: 0,
+            roots: vec![],
+            hash: None,
+            signature: None,
+            nodes: IntMap::new(),
+            blocks: HashSet::new(),
+        }
+    }
+
+    /// Creates a new light core for the hypercore with the given public key, signed by the
+    /// signer with `signer_key`. Fails if the public key of the signer is invalid.
+    pub fn new_with_signer_key(
+        public_key: VerifyingKey,
+        signer_key: SignerKey,
+    ) -> Result<Self, HypercoreError> {
+        let verifier = SignatureVerifier::new(&signer_key)?;
+        Ok(Self {
+            signer_key,
+            verifier,
+            ..Self::new(public_key)
+        })
+    }
+
+    /// Restores a light core from a head encoded with [`LightCore::encode_head`], verifying
+    /// its signature against `public_key`. No proofs are retained.
+    pub fn from_head(public_key: VerifyingKey, buffer: &[u8]) -> Result<Self, HypercoreError> {
+        Self::from_head_with_signer_key(public_key, SignerKey::ed25519(&public_key), buffer)
+    }
+
+    /// Restores a light core of a hypercore signed by the signer with `signer_key` from a head
+    /// encoded with [`LightCore::encode_head`], verifying its signature against `signer_key`.
+    pub fn from_head_with_signer_key(
+        public_key: VerifyingKey,
+        signer_key: SignerKey,
+        buffer: &[u8],
+    ) -> Result<Self, HypercoreError> {
+        let mut state = HypercoreState::from_buffer(buffer);
+        let version = state.decode_u8(buffer)?;
+        if version != HEAD_VERSION {
+            return Err(HypercoreError::InvalidOperation {
+                context: format!("Unknown light core head version {version}"),
+            });
+        }
+        let fork: u64 = state.0.decode(buffer)?;
+        let length: u64 = state.0.decode(buffer)?;
+        let roots: Vec<Node> = state.decode(buffer)?;
+        let signature: Vec<u8> = state.0.decode(buffer)?;
+        let mut light_core = Self::new_with_signer_key(public_key, signer_key)?;
+        if length == 0 && signature.is_empty() {
+            return Ok(light_core);
+        }
+        let mut root_indices = vec![];
+        flat_tree::full_roots(2 * length, &mut root_indices);
+        if !root_indices.iter().eq(roots.iter().map(|root| &root.index)) {
+            return Err(HypercoreError::InvalidOperation {
+                context: format!("Roots of the light core head do not match length {length}"),
+            });
+        }
+        let byte_length = roots.iter().map(|root| root.length).sum();
+        let mut changeset = MerkleTreeChangeset::new(length, byte_length, fork, roots);
+        changeset.verify_and_set_signature(&signature, &light_core.verifier)?;
+        light_core.fork = fork;
+        light_core.length = length;
+        light_core.byte_length = byte_length;
+        light_core.roots = changeset.roots;
+        light_core.hash = changeset.hash;
+        light_core.signature = changeset.signature;
+        Ok(light_core)
+    }
+
+    /// Encodes the latest verified head, to store and restore with [`LightCore::from_head`].
+    pub fn encode_head(&self) -> Result<Vec<u8>, EncodingError> {
+        let signature = self
+            .signature
+            .map_or(vec![], |signature| signature.to_bytes().to_vec());
+        let mut state = HypercoreState::new();
+        state.add_end(1)?; // Version
+        state.0.preencode(&self.fork)?;
+        state.0.preencode(&self.length)?;
+        state.preencode(&self.roots)?;
+        state.0.preencode(&signature)?;
+        let mut buffer = state.create_buffer();
+        state.set_byte_to_buffer(HEAD_VERSION, &mut buffer)?;
+        state.0.encode(&self.fork, &mut buffer)?;
+        state.0.encode(&self.length, &mut buffer)?;
+        state.encode(&self.roots, &mut buffer)?;
+        state.0.encode(&signature, &mut buffer)?;
+        Ok(buffer.to_vec())
+    }
+
+    /// Public key of the tracked hypercore.
+    pub fn public_key(&self) -> &VerifyingKey {
+        &self.public_key
+    }
+
+    /// Scheme and public key of the signer of the tracked hypercore.
+    pub fn signer_key(&self) -> SignerKey {
+        self.signer_key
+    }
+
+    /// Fork of the latest verified head.
+    pub fn fork(&self) -> u64 {
+        self.fork
+    }
+
+    /// Length of the latest verified head.
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// Byte length of the latest verified head.
+    pub fn byte_length(&self) -> u64 {
+        self.byte_length
+    }
+
+    /// Root hash of the latest verified head, if any.
+    pub fn hash(&self) -> Option<&[u8]> {
+        self.hash.as_deref()
+    }
+
+    /// The latest verified head as a [`Checkpoint`], e.g. to check a
+    /// [`crate::ConsistencyProof`] before moving to a newer head. `None` before the first
+    /// upgrade.
+    pub fn checkpoint(&self) -> Option<Checkpoint> {
+        Some(Checkpoint {
+            fork: self.fork,
+            length: self.length,
+            root_hash: self.hash.as_deref()?.try_into().ok()?,
+        })
+    }
+
+    /// Signature of the latest verified head, if any.
+    pub fn signature(&self) -> Option<&Signature> {
+        self.signature.as_ref()
+    }
+
+    /// Check if a proof for the block at the given `index` has been verified and is still
+    /// retained.
+    pub fn has(&self, index: u64) -> bool {
+        self.blocks.contains(&index)
+    }
+
+    /// Drops all retained proofs, keeping only the head.
+    pub fn clear_proofs(&mut self) {
+        self.nodes.clear();
+        self.blocks.clear();
+    }
+
+    /// Used to fill the nodes field of a `RequestBlock`. Counts the nodes from the block at
+    /// the given `index` upwards that are not known to the light core.
+    pub fn missing_nodes(&self, index: u64) -> u64 {
+        let head = 2 * self.length;
+        let mut iter = flat_tree::Iterator::new(2 * index);
+        let iter_right_span = iter.index() + iter.factor() / 2 - 1;
+
+        // If the index is not in the current head, we do not know how many missing nodes there are...
+        if iter_right_span >= head {
+            return 0;
+        }
+
+        let mut count: u64 = 0;
+        while !iter.contains(head) && self.known_node(iter.index()).is_none() {
+            count += 1;
+            iter.parent();
+        }
+        count
+    }
+
+    /// Verifies a proof received from a peer, returns true if verified, false if the proof is
+    /// for an older fork, or for a newer one without an upgrade. Moves the head forward if the
+    /// proof contains an upgrade.
+    ///
+    /// A proof of a newer fork, after the writer truncated, is verified from the roots at the
+    /// start of its upgrade: request the upgrade from 0, or from a length whose roots the light
+    /// core knows and both forks share. The new head replaces the old one, and the proofs
+    /// retained for the old fork are dropped.
+    pub fn verify_proof(&mut self, proof: &Proof) -> Result<bool, HypercoreError> {
+        let forked = proof.fork != self.fork;
+        let mut changeset = if !forked {
+            MerkleTreeChangeset::new(self.length, self.byte_length, self.fork, self.roots.clone())
+        } else if let (true, Some(upgrade)) = (proof.fork > self.fork, proof.upgrade.as_ref()) {
+            // Roots the old fork had at the start of the upgrade, checked by its signature
+            let roots =
+                self.roots_at(upgrade.start)
+                    .ok_or_else(|| HypercoreError::InvalidOperation {
+                        context: format!(
+                            "Could not verify fork {} from length {}, store {}, without its roots",
+                            proof.fork,
+                            upgrade.start,
+                            Store::Tree
+                        ),
+                    })?;
+            let byte_length = roots.iter().map(|root| root.length).sum();
+            MerkleTreeChangeset::new(upgrade.start, byte_length, self.fork, roots)
+        } else {
+            return Ok(false);
+        };
+
+        let mut unverified_block_root_node = verify_tree(
+            proof.block.as_ref(),
+            proof.hash.as_ref(),
+            proof.seek.as_ref(),
+            &mut changeset,
+        )?;
+        if let Some(upgrade) = proof.upgrade.as_ref() {
+            if verify_upgrade(
+                proof.fork,
+                upgrade,
+                unverified_block_root_node.as_ref(),
+                &self.verifier,
+                &mut changeset,
+            )? {
+                unverified_block_root_node = None;
+            }
+        }
+
+        if let Some(unverified_block_root_node) = unverified_block_root_node {
+            let verified_block_root_node = self
+                .known_node(unverified_block_root_node.index)
+                .filter(|_| !forked)
+                .or_else(|| {
+                    changeset
+                        .roots
+                        .iter()
+                        .find(|root| root.index == unverified_block_root_node.index)
+                })
+                .ok_or_else(|| HypercoreError::InvalidOperation {
+                    context: format!(
+                        "Could not verify node {}, store {}, without an upgrade",
+                        unverified_block_root_node.index,
+                        Store::Tree
+                    ),
+                })?;
+            if verified_block_root_node.hash != unverified_block_root_node.hash {
+                return Err(HypercoreError::InvalidChecksum {
+                    context: format!(
+                        "Invalid checksum at node {}, store {}",
+                        unverified_block_root_node.index,
+                        Store::Tree
+                    ),
+                });
+            }
+        }
+
+        if forked {
+            self.clear_proofs();
+        }
+        if changeset.upgraded {
+            self.fork = changeset.fork;
+            self.length = changeset.length;
+            self.byte_length = changeset.byte_length;
+            self.roots = changeset.roots;
+            self.hash = changeset.hash;
+            self.signature = changeset.signature;
+        }
+        for node in changeset.nodes {
+            self.nodes.insert(node.index, node);
+        }
+        if let Some(block) = proof.block.as_ref() {
+            self.blocks.insert(block.index);
+        }
+        Ok(true)
+    }
+
+    fn known_node(&self, index: u64) -> Option<&Node> {
+        self.roots
+            .iter()
+            .find(|root| root.index == index)
+            .or_else(|| self.nodes.get(index))
+    }
+
+    /// Roots of the tree at `length`, if the head and the retained proofs have them all.
+    fn roots_at(&self, length: u64) -> Option<Vec<Node>> {
+        if length > self.length {
+            return None;
+        }
+        let mut indices = vec![];
+        flat_tree::full_roots(2 * length, &mut indices);
+        indices
+            .into_iter()
+            .map(|index| self.known_node(index).cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::create_hypercore_with_data;
+    use crate::{RequestBlock, RequestUpgrade};
+
+    #[async_std::test]
+    async fn light_core_verify_upgrade_and_block() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+        let mut light_core = LightCore::new(hypercore.key_pair().public);
+        assert_eq!(light_core.missing_nodes(4), 0);
+
+        let proof = hypercore
+            .create_proof(
+                Some(RequestBlock { index: 4, nodes: 0 }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(light_core.verify_proof(&proof)?);
+        let info = hypercore.info();
+        assert_eq!(light_core.length(), info.length);
+        assert_eq!(light_core.byte_length(), info.byte_length);
+        assert_eq!(light_core.fork(), info.fork);
+        assert!(light_core.signature().is_some());
+        assert_eq!(light_core.checkpoint(), Some(hypercore.checkpoint()));
+        assert!(light_core.has(4));
+        assert!(!light_core.has(5));
+
+        // Block 5 is the sibling of the verified block 4, so no extra nodes are needed
+        assert_eq!(light_core.missing_nodes(5), 0);
+        let proof = hypercore
+            .create_proof(Some(RequestBlock { index: 5, nodes: 0 }), None, None, None)
+            .await?
+            .unwrap();
+        assert!(light_core.verify_proof(&proof)?);
+        assert!(light_core.has(5));
+
+        // Without retained proofs, the whole path to the root is requested
+        light_core.clear_proofs();
+        let nodes = light_core.missing_nodes(1);
+        assert_eq!(nodes, 3);
+        let proof = hypercore
+            .create_proof(Some(RequestBlock { index: 1, nodes }), None, None, None)
+            .await?
+            .unwrap();
+        assert!(light_core.verify_proof(&proof)?);
+        assert!(light_core.has(1));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn light_core_reject_invalid_proofs() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+        let mut light_core = LightCore::new(hypercore.key_pair().public);
+
+        // A block proof can not be verified without a head
+        let proof = hypercore
+            .create_proof(Some(RequestBlock { index: 4, nodes: 0 }), None, None, None)
+            .await?
+            .unwrap();
+        assert!(light_core.verify_proof(&proof).is_err());
+
+        // Upgrade signed by another key
+        let other_hypercore = create_hypercore_with_data(10).await?;
+        let mut other_light_core = LightCore::new(other_hypercore.key_pair().public);
+        let proof = hypercore
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(other_light_core.verify_proof(&proof).is_err());
+        assert_eq!(other_light_core.length(), 0);
+
+        // Tampered block value
+        assert!(light_core.verify_proof(&proof)?);
+        let mut proof = hypercore
+            .create_proof(Some(RequestBlock { index: 4, nodes: 3 }), None, None, None)
+            .await?
+            .unwrap();
+        proof.block.as_mut().unwrap().value = b"tampered".to_vec();
+        assert!(light_core.verify_proof(&proof).is_err());
+        assert!(!light_core.has(4));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn light_core_head_round_trips() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+        let public_key = hypercore.key_pair().public;
+        let mut light_core = LightCore::new(public_key);
+        let empty = LightCore::from_head(public_key, &light_core.encode_head()?)?;
+        assert_eq!(empty.checkpoint(), None);
+
+        let proof = hypercore
+            .create_proof(
+                Some(RequestBlock { index: 4, nodes: 0 }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(light_core.verify_proof(&proof)?);
+        let head = light_core.encode_head()?;
+        let restored = LightCore::from_head(public_key, &head)?;
+        assert_eq!(restored.checkpoint(), light_core.checkpoint());
+        assert_eq!(restored.byte_length(), light_core.byte_length());
+        assert_eq!(restored.signature(), light_core.signature());
+        assert!(!restored.has(4));
+
+        // Heads are verified again when loaded
+        let other_key = create_hypercore_with_data(0).await?.key_pair().public;
+        assert!(LightCore::from_head(other_key, &head).is_err());
+        let mut tampered = head.clone();
+        tampered[10] ^= 1;
+        assert!(LightCore::from_head(public_key, &tampered).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "schnorr")]
+    #[async_std::test]
+    async fn light_core_follows_schnorr_signed_core() -> Result<(), HypercoreError> {
+        use crate::{CoreSigner, HypercoreBuilder, SchnorrSigner, Storage};
+        use std::sync::Arc;
+
+        let signer = SchnorrSigner::from_bytes(&[7; 32])?;
+        let mut hypercore = HypercoreBuilder::new(Storage::new_memory().await?)
+            .signer(Arc::new(signer.clone()))
+            .build()
+            .await?;
+        hypercore.append_batch([b"#0", b"#1", b"#2"]).await?;
+        let public_key = hypercore.key_pair().public;
+        let proof = hypercore
+            .create_proof(
+                Some(RequestBlock { index: 1, nodes: 0 }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 3,
+                }),
+            )
+            .await?
+            .unwrap();
+
+        // The key pair doesn't verify the signatures of the signer
+        assert!(LightCore::new(public_key).verify_proof(&proof).is_err());
+        let mut light_core = LightCore::new_with_signer_key(public_key, signer.key())?;
+        assert!(light_core.verify_proof(&proof)?);
+        assert_eq!(light_core.checkpoint(), Some(hypercore.checkpoint()));
+        assert!(light_core.has(1));
+
+        let head = light_core.encode_head()?;
+        let restored = LightCore::from_head_with_signer_key(public_key, signer.key(), &head)?;
+        assert_eq!(restored.checkpoint(), light_core.checkpoint());
+        assert_eq!(restored.signer_key(), signer.key());
+        assert!(LightCore::from_head(public_key, &head).is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn light_core_follows_newer_fork() -> Result<(), HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(10).await?;
+        let mut light_core = LightCore::new(hypercore.key_pair().public);
+        let upgrade = |start, length| RequestUpgrade { start, length };
+        let proof = hypercore
+            .create_proof(None, None, None, Some(upgrade(0, 10)))
+            .await?
+            .unwrap();
+        assert!(light_core.verify_proof(&proof)?);
+
+        hypercore.truncate(5, 1).await?;
+        hypercore.append_batch([b"x", b"y", b"z"]).await?;
+        let block = |index| RequestBlock { index, nodes: 0 };
+
+        // A newer fork needs an upgrade, and one from roots the light core doesn't know fails
+        let proof = hypercore
+            .create_proof(Some(block(6)), None, None, None)
+            .await?
+            .unwrap();
+        assert!(!light_core.verify_proof(&proof)?);
+        let proof = hypercore
+            .create_proof(None, None, None, Some(upgrade(4, 4)))
+            .await?
+            .unwrap();
+        assert!(light_core.verify_proof(&proof).is_err());
+        assert_eq!(light_core.fork(), 0);
+
+        let proof = hypercore
+            .create_proof(Some(block(6)), None, None, Some(upgrade(0, 8)))
+            .await?
+            .unwrap();
+        assert!(light_core.verify_proof(&proof)?);
+        assert_eq!(light_core.fork(), 1);
+        assert_eq!(light_core.length(), 8);
+        assert_eq!(light_core.checkpoint(), Some(hypercore.checkpoint()));
+        assert!(light_core.has(6));
+
+        // Proofs of the old fork are no longer accepted
+        let mut old = proof.clone();
+        old.fork = 0;
+        assert!(!light_core.verify_proof(&old)?);
+        Ok(())
+    }
+}
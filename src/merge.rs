@@ -0,0 +1,166 @@
+//! Ordered iteration over the blocks of many cores, e.g. to show the feeds of many authors as
+//! one timeline.
+use futures::stream::{self, Stream};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use crate::{Hypercore, HypercoreError};
+
+/// Block yielded by [`merge_iterate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeEntry {
+    /// Position of the core in the slice given to [`merge_iterate`]
+    pub core: usize,
+    /// Index of the block in its core
+    pub index: u64,
+    /// Value of the block
+    pub value: Vec<u8>,
+}
+
+/// Next block of a core, ordered by key and then by core position.
+struct Head<K> {
+    key: K,
+    entry: MergeEntry,
+}
+
+impl<K: Ord> Ord for Head<K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key
+            .cmp(&other.key)
+            .then(self.entry.core.cmp(&other.entry.core))
+    }
+}
+
+impl<K: Ord> PartialOrd for Head<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord> PartialEq for Head<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K: Ord> Eq for Head<K> {}
+
+struct MergeState<'a, K, F> {
+    cores: &'a mut [Hypercore],
+    key_fn: F,
+    /// Index from which to look for the next present block of each core
+    positions: Vec<u64>,
+    heads: BinaryHeap<Reverse<Head<K>>>,
+    started: bool,
+}
+
+impl<K: Ord, F: FnMut(&[u8]) -> K> MergeState<'_, K, F> {
+    /// Reads the next present block of the core into the heap.
+    async fn advance(&mut self, core: usize) -> Result<(), HypercoreError> {
+        let hypercore = &mut self.cores[core];
+        let Some(index) = hypercore
+            .bitfield
+            .index_of(true, self.positions[core])
+            .filter(|index| *index < hypercore.tree.length)
+        else {
+            return Ok(());
+        };
+        self.positions[core] = index + 1;
+        let value = hypercore
+            .get_verified(index)
+            .await?
+            .expect("Present block should have a value");
+        self.heads.push(Reverse(Head {
+            key: (self.key_fn)(&value),
+            entry: MergeEntry { core, index, value },
+        }));
+        Ok(())
+    }
+}
+
+/// Merges the locally present blocks of many cores into one stream ordered by the key that
+/// `key_fn` derives from each value, e.g. the creation time of an event. The blocks of every
+/// core must already be in key order, as they are when an author appends events as they are
+/// created. Blocks with equal keys are yielded in the order of their cores in `cores`.
+///
+/// Only one block per core is held in memory at a time, and every block is verified against
+/// its core's merkle tree before it is yielded. Missing blocks are skipped.
+pub fn merge_iterate<'a, K, F>(
+    cores: &'a mut [Hypercore],
+    key_fn: F,
+) -> impl Stream<Item = Result<MergeEntry, HypercoreError>> + 'a
+where
+    K: Ord + 'a,
+    F: FnMut(&[u8]) -> K + 'a,
+{
+    let state = MergeState {
+        positions: vec![0; cores.len()],
+        heads: BinaryHeap::with_capacity(cores.len()),
+        cores,
+        key_fn,
+        started: false,
+    };
+    stream::try_unfold(state, |mut state| async move {
+        if !state.started {
+            state.started = true;
+            for core in 0..state.cores.len() {
+                state.advance(core).await?;
+            }
+        }
+        let Some(Reverse(head)) = state.heads.pop() else {
+            return Ok(None);
+        };
+        state.advance(head.entry.core).await?;
+        Ok(Some((head.entry, state)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::create_hypercore_with_data;
+    use futures::TryStreamExt;
+
+    async fn create_feed(times: &[u8]) -> Result<Hypercore, HypercoreError> {
+        let mut hypercore = create_hypercore_with_data(0).await?;
+        for time in times {
+            hypercore.append(&[*time]).await?;
+        }
+        Ok(hypercore)
+    }
+
+    #[async_std::test]
+    async fn merge_iterate_orders_blocks_across_cores() -> Result<(), HypercoreError> {
+        let mut cores = vec![
+            create_feed(&[1, 4, 7]).await?,
+            create_feed(&[2, 4, 9, 10]).await?,
+            create_feed(&[]).await?,
+            create_feed(&[0, 3, 5]).await?,
+        ];
+        cores[1].clear(2, 3).await?;
+
+        let merged: Vec<MergeEntry> = merge_iterate(&mut cores, |value| value[0])
+            .try_collect()
+            .await?;
+        let order: Vec<(usize, u8)> = merged
+            .iter()
+            .map(|entry| (entry.core, entry.value[0]))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                (3, 0),
+                (0, 1),
+                (1, 2),
+                (3, 3),
+                (0, 4),
+                (1, 4),
+                (3, 5),
+                (0, 7),
+                (1, 10)
+            ]
+        );
+        assert_eq!(merged[8].index, 3);
+        Ok(())
+    }
+}
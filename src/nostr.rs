@@ -0,0 +1,1704 @@
+//! Nostr relays as a rendezvous and transport for replication, for peers that can't accept
+//! direct connections. A core is announced with an [`Announcement`], an addressable event
+//! replaced by every new announcement of the same core, and peers then exchange the bytes of a
+//! replication connection, e.g. of a
+//! [`handshake`](crate::protocol::handshake::handshake) and its encrypted channel, through
+//! ephemeral events of a [`NostrTunnel`]. Writers publish the [`Head`] of their core after
+//! appending, so followers learn about updates through their usual subscriptions.
+//!
+//! Like [`crate::protocol::Mux`] this module is sans-IO: it produces the client messages of
+//! NIP-01 as JSON text, to send to each configured relay over its websocket, and parses the
+//! messages the relays send back. Events are signed with BIP-340 Schnorr signatures by
+//! [`NostrKeys`].
+//!
+//! Relays don't store ephemeral events, so tunnelled bytes only reach a peer subscribed at the
+//! time, and a chunk lost by every relay stalls the stream: send through several relays.
+//! Tunnelled bytes are public, which is why they should be those of an encrypted channel.
+//!
+//! With the `replication` feature, [`NostrAlertNotifier`] sends the
+//! [`Alert`](crate::replication::Alert)s of replication as private direct messages of NIP-17,
+//! encrypted with NIP-44, to the owner of a core.
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use ed25519_dalek::{Signature, VerifyingKey};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use k256::{ecdh, schnorr};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::common::{decode_hex, encode_hex};
+use crate::crypto::{discovery_key, schnorr_sign, schnorr_signer_key};
+use crate::{
+    Checkpoint, Clock, CoreSigner, Hypercore, HypercoreError, KeyEncoding, OsRandom, RetryPolicy,
+    Rng, SignerKey, SystemClock,
+};
+
+/// Kind of [`Announcement`] events, an addressable kind keyed by the discovery key
+pub const ANNOUNCEMENT_KIND: u32 = 32_117;
+
+/// Kind of [`Head`] events, an addressable kind keyed by the discovery key
+pub const HEAD_KIND: u32 = 32_118;
+
+/// Kind of [`NostrTunnel`] events, an ephemeral kind
+pub const TUNNEL_KIND: u32 = 22_117;
+
+/// Kind of the chat message inside a private direct message, see NIP-17
+pub const DIRECT_MESSAGE_KIND: u32 = 14;
+
+/// Kind of the seal of a private direct message, signed by the sender, see NIP-59
+pub const SEAL_KIND: u32 = 13;
+
+/// Kind of the gift wrap of a private direct message, signed by a one-time key, see NIP-59
+pub const GIFT_WRAP_KIND: u32 = 1059;
+
+/// Seals and gift wraps are backdated by up to this many seconds, so their time doesn't tell
+/// when the message was sent.
+const MAX_BACKDATE_SECS: u64 = 2 * 24 * 60 * 60;
+
+/// Maximum bytes tunnelled per event, before base64 encoding.
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Chunks received ahead of a missing one kept per tunnel, before the tunnel fails.
+const MAX_PENDING_CHUNKS: usize = 1024;
+
+/// Secp256k1 key pair signing nostr events.
+#[derive(Clone)]
+pub struct NostrKeys {
+    signing_key: schnorr::SigningKey,
+}
+
+impl NostrKeys {
+    /// Generate a new key pair.
+    pub fn generate() -> Self {
+        Self::generate_with(&OsRandom)
+    }
+
+    /// Generate a new key pair from the given source of randomness.
+    pub fn generate_with(rng: &dyn Rng) -> Self {
+        loop {
+            let mut secret = [0; 32];
+            rng.fill_bytes(&mut secret);
+            if let Ok(keys) = Self::from_secret(&secret) {
+                return keys;
+            }
+        }
+    }
+
+    /// Key pair of the given secret key.
+    pub fn from_secret(secret: &[u8; 32]) -> Result<Self, HypercoreError> {
+        let signing_key =
+            schnorr::SigningKey::from_bytes(secret).map_err(|_| HypercoreError::BadArgument {
+                context: "Invalid nostr secret key".to_string(),
+            })?;
+        Ok(Self { signing_key })
+    }
+
+    /// X-only public key, as in events and `npub`s.
+    pub fn public_key(&self) -> [u8; 32] {
+        self.signing_key.verifying_key().to_bytes().into()
+    }
+}
+
+/// Nostr keys can sign cores, see [`crate::HypercoreBuilder::signer`].
+impl CoreSigner for NostrKeys {
+    fn key(&self) -> SignerKey {
+        schnorr_signer_key(&self.signing_key)
+    }
+
+    fn sign(&self, message: &[u8]) -> Signature {
+        schnorr_sign(&self.signing_key, message)
+    }
+}
+
+impl fmt::Debug for NostrKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NostrKeys")
+            .field("public_key", &self.public_key().display())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Signed nostr event, see NIP-01.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// SHA-256 of the serialized event
+    pub id: [u8; 32],
+    /// Public key of the author
+    pub pubkey: [u8; 32],
+    /// Unix time in seconds
+    pub created_at: u64,
+    /// Kind of the event
+    pub kind: u32,
+    /// Tags, each a name followed by values
+    pub tags: Vec<Vec<String>>,
+    /// Content
+    pub content: String,
+    /// Schnorr signature of the id
+    pub sig: [u8; 64],
+}
+
+impl Event {
+    /// Create and sign an event.
+    pub fn sign(
+        keys: &NostrKeys,
+        created_at: u64,
+        kind: u32,
+        tags: Vec<Vec<String>>,
+        content: String,
+    ) -> Self {
+        Self::sign_with(keys, created_at, kind, tags, content, &OsRandom)
+    }
+
+    /// Create and sign an event, drawing the auxiliary randomness of the signature from `rng`.
+    pub fn sign_with(
+        keys: &NostrKeys,
+        created_at: u64,
+        kind: u32,
+        tags: Vec<Vec<String>>,
+        content: String,
+        rng: &dyn Rng,
+    ) -> Self {
+        let pubkey = keys.public_key();
+        let id = event_id(&pubkey, created_at, kind, &tags, &content);
+        let mut aux_rand = [0; 32];
+        rng.fill_bytes(&mut aux_rand);
+        let sig = keys
+            .signing_key
+            .sign_raw(&id, &aux_rand)
+            .expect("Signing a 32 byte id should not fail");
+        Self {
+            id,
+            pubkey,
+            created_at,
+            kind,
+            tags,
+            content,
+            sig: sig.to_bytes(),
+        }
+    }
+
+    /// Check the id and the signature of the event.
+    pub fn verify(&self) -> Result<(), HypercoreError> {
+        let id = event_id(
+            &self.pubkey,
+            self.created_at,
+            self.kind,
+            &self.tags,
+            &self.content,
+        );
+        if id != self.id {
+            return Err(HypercoreError::InvalidChecksum {
+                context: format!("Event id {} does not match its content", self.id.to_hex()),
+            });
+        }
+        let invalid = |reason: &str| HypercoreError::InvalidSignature {
+            context: format!("Event {} has {reason}", self.id.to_hex()),
+        };
+        let key = schnorr::VerifyingKey::from_bytes(&self.pubkey)
+            .map_err(|_| invalid("an invalid public key"))?;
+        let sig = schnorr::Signature::try_from(&self.sig[..])
+            .map_err(|_| invalid("a malformed signature"))?;
+        key.verify_raw(&id, &sig)
+            .map_err(|_| invalid("an invalid signature"))
+    }
+
+    /// Create a private direct message to `recipient`, see NIP-17. The message is sealed with
+    /// the signature of `keys` and wrapped in an event of a one-time key, each layer encrypted
+    /// with NIP-44, so relays only see the recipient and a randomized time.
+    pub fn direct_message(
+        keys: &NostrKeys,
+        recipient: &[u8; 32],
+        text: &str,
+        created_at: u64,
+    ) -> Result<Self, HypercoreError> {
+        Self::direct_message_with(keys, recipient, text, created_at, &OsRandom)
+    }
+
+    /// Create a private direct message, drawing the one-time key, the nonces, the randomized
+    /// times and the auxiliary randomness of the signatures from `rng`.
+    pub fn direct_message_with(
+        keys: &NostrKeys,
+        recipient: &[u8; 32],
+        text: &str,
+        created_at: u64,
+        rng: &dyn Rng,
+    ) -> Result<Self, HypercoreError> {
+        let rumor = Rumor {
+            pubkey: keys.public_key(),
+            created_at,
+            kind: DIRECT_MESSAGE_KIND,
+            tags: vec![vec!["p".to_string(), recipient.to_hex()]],
+            content: text.to_string(),
+        };
+        let seal = Self::sign_with(
+            keys,
+            backdate(created_at, rng),
+            SEAL_KIND,
+            vec![],
+            nip44_encrypt(
+                &conversation_key(keys, recipient)?,
+                &rumor.to_json().to_string(),
+                rng,
+            )?,
+            rng,
+        );
+        let wrap_keys = NostrKeys::generate_with(rng);
+        let content = nip44_encrypt(
+            &conversation_key(&wrap_keys, recipient)?,
+            &seal.to_json().to_string(),
+            rng,
+        )?;
+        let tags = vec![vec!["p".to_string(), recipient.to_hex()]];
+        Ok(Self::sign_with(
+            &wrap_keys,
+            backdate(created_at, rng),
+            GIFT_WRAP_KIND,
+            tags,
+            content,
+            rng,
+        ))
+    }
+
+    /// Unwrap a private direct message to the owner of `keys`, verifying the signatures of the
+    /// gift wrap and of the seal of the sender.
+    pub fn decrypt_direct_message(
+        &self,
+        keys: &NostrKeys,
+    ) -> Result<DirectMessage, HypercoreError> {
+        if self.kind != GIFT_WRAP_KIND {
+            return Err(invalid_message(&format!(
+                "kind {} is not a gift wrap",
+                self.kind
+            )));
+        }
+        self.verify()?;
+        let seal = Event::from_json(&parse_json(&nip44_decrypt(
+            &conversation_key(keys, &self.pubkey)?,
+            &self.content,
+        )?)?)?;
+        if seal.kind != SEAL_KIND {
+            return Err(invalid_message(&format!(
+                "kind {} is not a seal",
+                seal.kind
+            )));
+        }
+        seal.verify()?;
+        let rumor = Rumor::from_json(&parse_json(&nip44_decrypt(
+            &conversation_key(keys, &seal.pubkey)?,
+            &seal.content,
+        )?)?)?;
+        if rumor.kind != DIRECT_MESSAGE_KIND {
+            return Err(invalid_message(&format!(
+                "kind {} is not a direct message",
+                rumor.kind
+            )));
+        }
+        // Only the seal is signed, so its key must be the one of the author
+        if rumor.pubkey != seal.pubkey {
+            return Err(HypercoreError::InvalidSignature {
+                context: format!(
+                    "Direct message of {} sealed by {}",
+                    rumor.pubkey.to_hex(),
+                    seal.pubkey.to_hex()
+                ),
+            });
+        }
+        Ok(DirectMessage {
+            sender: seal.pubkey,
+            created_at: rumor.created_at,
+            text: rumor.content,
+        })
+    }
+
+    /// First value of the first tag with the given name.
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.first().is_some_and(|first| first == name))
+            .and_then(|tag| tag.get(1))
+            .map(String::as_str)
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "id": self.id.to_hex(),
+            "pubkey": self.pubkey.to_hex(),
+            "created_at": self.created_at,
+            "kind": self.kind,
+            "tags": self.tags,
+            "content": self.content,
+            "sig": encode_hex(&self.sig),
+        })
+    }
+
+    fn from_json(value: &Value) -> Result<Self, HypercoreError> {
+        let rumor = Rumor::from_json(value)?;
+        Ok(Self {
+            id: hex_field(json_string(value, "id")?)?,
+            pubkey: rumor.pubkey,
+            created_at: rumor.created_at,
+            kind: rumor.kind,
+            tags: rumor.tags,
+            content: rumor.content,
+            sig: hex_field(json_string(value, "sig")?)?,
+        })
+    }
+}
+
+/// Private direct message, see [`Event::decrypt_direct_message`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectMessage {
+    /// Public key of the sender
+    pub sender: [u8; 32],
+    /// Unix time in seconds the message was written at
+    pub created_at: u64,
+    /// Text of the message
+    pub text: String,
+}
+
+/// Unsigned event, the message inside a seal, see NIP-59.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rumor {
+    pubkey: [u8; 32],
+    created_at: u64,
+    kind: u32,
+    tags: Vec<Vec<String>>,
+    content: String,
+}
+
+impl Rumor {
+    fn to_json(&self) -> Value {
+        let id = event_id(
+            &self.pubkey,
+            self.created_at,
+            self.kind,
+            &self.tags,
+            &self.content,
+        );
+        json!({
+            "id": id.to_hex(),
+            "pubkey": self.pubkey.to_hex(),
+            "created_at": self.created_at,
+            "kind": self.kind,
+            "tags": self.tags,
+            "content": self.content,
+        })
+    }
+
+    fn from_json(value: &Value) -> Result<Self, HypercoreError> {
+        let tags = json_field(value, "tags")?
+            .as_array()
+            .ok_or_else(|| invalid_message("event tags are not an array"))?
+            .iter()
+            .map(|tag| {
+                tag.as_array()
+                    .and_then(|values| {
+                        values
+                            .iter()
+                            .map(|value| value.as_str().map(str::to_string))
+                            .collect()
+                    })
+                    .ok_or_else(|| invalid_message("event tag is not an array of strings"))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            pubkey: hex_field(json_string(value, "pubkey")?)?,
+            created_at: json_number(value, "created_at")?,
+            kind: u32::try_from(json_number(value, "kind")?)
+                .map_err(|_| invalid_message("event kind is too big"))?,
+            tags,
+            content: json_string(value, "content")?.to_string(),
+        })
+    }
+}
+
+/// Subscription filter, see NIP-01. Empty lists match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    /// Kinds of the events
+    pub kinds: Vec<u32>,
+    /// Authors of the events
+    pub authors: Vec<[u8; 32]>,
+    /// Single letter tags, and the values one of which the events must have
+    pub tags: Vec<(char, Vec<String>)>,
+}
+
+impl Filter {
+    fn to_json(&self) -> Value {
+        let mut filter = serde_json::Map::new();
+        if !self.kinds.is_empty() {
+            filter.insert("kinds".to_string(), json!(self.kinds));
+        }
+        if !self.authors.is_empty() {
+            let authors: Vec<String> = self.authors.iter().map(KeyEncoding::to_hex).collect();
+            filter.insert("authors".to_string(), json!(authors));
+        }
+        for (name, values) in &self.tags {
+            filter.insert(format!("#{name}"), json!(values));
+        }
+        Value::Object(filter)
+    }
+}
+
+/// Message from a client to a relay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientMessage {
+    /// Publish an event
+    Event(Event),
+    /// Subscribe to the events matching any of the filters
+    Req {
+        /// Id of the subscription
+        subscription: String,
+        /// Filters of the subscription
+        filters: Vec<Filter>,
+    },
+    /// End a subscription
+    Close {
+        /// Id of the subscription
+        subscription: String,
+    },
+}
+
+impl ClientMessage {
+    /// The message as JSON text, to send to a relay.
+    pub fn to_json(&self) -> String {
+        let message = match self {
+            ClientMessage::Event(event) => json!(["EVENT", event.to_json()]),
+            ClientMessage::Req {
+                subscription,
+                filters,
+            } => {
+                let mut message = vec![json!("REQ"), json!(subscription)];
+                message.extend(filters.iter().map(Filter::to_json));
+                Value::Array(message)
+            }
+            ClientMessage::Close { subscription } => json!(["CLOSE", subscription]),
+        };
+        message.to_string()
+    }
+}
+
+/// Message from a relay to a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelayMessage {
+    /// Event matching a subscription, not verified yet
+    Event {
+        /// Id of the subscription
+        subscription: String,
+        /// The event
+        event: Event,
+    },
+    /// Outcome of publishing an event
+    Ok {
+        /// Id of the event
+        event_id: [u8; 32],
+        /// The event was accepted
+        accepted: bool,
+        /// Reason given by the relay
+        message: String,
+    },
+    /// All stored events of the subscription were sent
+    Eose {
+        /// Id of the subscription
+        subscription: String,
+    },
+    /// The relay ended the subscription
+    Closed {
+        /// Id of the subscription
+        subscription: String,
+        /// Reason given by the relay
+        message: String,
+    },
+    /// Human readable notice
+    Notice(String),
+}
+
+impl RelayMessage {
+    /// Parse the JSON text of a message received from a relay.
+    pub fn parse(text: &str) -> Result<Self, HypercoreError> {
+        let value = parse_json(text)?;
+        let values = value
+            .as_array()
+            .ok_or_else(|| invalid_message("not an array"))?;
+        let string = |index: usize| {
+            values
+                .get(index)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| invalid_message(&format!("no string at {index}")))
+        };
+        Ok(match string(0)?.as_str() {
+            "EVENT" => RelayMessage::Event {
+                subscription: string(1)?,
+                event: Event::from_json(
+                    values
+                        .get(2)
+                        .ok_or_else(|| invalid_message("EVENT without event"))?,
+                )?,
+            },
+            "OK" => RelayMessage::Ok {
+                event_id: hex_field(&string(1)?)?,
+                accepted: values
+                    .get(2)
+                    .and_then(Value::as_bool)
+                    .ok_or_else(|| invalid_message("OK without status"))?,
+                message: string(3).unwrap_or_default(),
+            },
+            "EOSE" => RelayMessage::Eose {
+                subscription: string(1)?,
+            },
+            "CLOSED" => RelayMessage::Closed {
+                subscription: string(1)?,
+                message: string(2).unwrap_or_default(),
+            },
+            "NOTICE" => RelayMessage::Notice(string(1)?),
+            other => return Err(invalid_message(&format!("unknown type {other}"))),
+        })
+    }
+}
+
+/// Core announced to nostr relays, so peers can find its public key and the relays to tunnel
+/// replication through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    /// Public key of the core
+    pub public_key: VerifyingKey,
+    /// Length of the core when announced
+    pub length: u64,
+    /// Relays the announcer tunnels replication through
+    pub relays: Vec<String>,
+}
+
+impl Announcement {
+    /// Announcement of the core, reachable through the given relays.
+    pub fn new(core: &Hypercore, relays: Vec<String>) -> Self {
+        Self {
+            public_key: core.key_pair().public,
+            length: core.info().length,
+            relays,
+        }
+    }
+
+    /// Discovery key of the core, which keys the announcement.
+    pub fn discovery_key(&self) -> [u8; 32] {
+        discovery_key(&self.public_key)
+    }
+
+    /// Signed event of the announcement.
+    pub fn to_event(&self, keys: &NostrKeys, created_at: u64) -> Event {
+        self.to_event_with(keys, created_at, &OsRandom)
+    }
+
+    /// Signed event of the announcement, drawing the auxiliary randomness of the signature from
+    /// `rng`.
+    pub fn to_event_with(&self, keys: &NostrKeys, created_at: u64, rng: &dyn Rng) -> Event {
+        let mut tags = vec![
+            vec!["d".to_string(), self.discovery_key().to_hex()],
+            vec!["key".to_string(), self.public_key.to_hex()],
+            vec!["length".to_string(), self.length.to_string()],
+        ];
+        tags.extend(
+            self.relays
+                .iter()
+                .map(|relay| vec!["relay".to_string(), relay.clone()]),
+        );
+        Event::sign_with(
+            keys,
+            created_at,
+            ANNOUNCEMENT_KIND,
+            tags,
+            String::new(),
+            rng,
+        )
+    }
+
+    /// Announcement of a received event, verifying it and that its public key matches the
+    /// discovery key it is announced under.
+    pub fn from_event(event: &Event) -> Result<Self, HypercoreError> {
+        if event.kind != ANNOUNCEMENT_KIND {
+            return Err(invalid_message(&format!(
+                "kind {} is not an announcement",
+                event.kind
+            )));
+        }
+        event.verify()?;
+        let tag = |name: &str| {
+            event
+                .tag(name)
+                .ok_or_else(|| invalid_message(&format!("announcement without {name}")))
+        };
+        let public_key = VerifyingKey::parse_key(tag("key")?)?;
+        if discovery_key(&public_key).to_hex() != tag("d")? {
+            return Err(invalid_message(
+                "announced key does not match the discovery key",
+            ));
+        }
+        let length = tag("length")?
+            .parse()
+            .map_err(|_| invalid_message("announced length is not a number"))?;
+        let relays = event
+            .tags
+            .iter()
+            .filter(|tag| tag.first().is_some_and(|name| name == "relay"))
+            .filter_map(|tag| tag.get(1).cloned())
+            .collect();
+        Ok(Self {
+            public_key,
+            length,
+            relays,
+        })
+    }
+
+    /// Filter of the announcements of the core with the given discovery key.
+    pub fn filter(discovery_key: &[u8; 32]) -> Filter {
+        Filter {
+            kinds: vec![ANNOUNCEMENT_KIND],
+            authors: vec![],
+            tags: vec![('d', vec![discovery_key.to_hex()])],
+        }
+    }
+}
+
+/// Latest state of a core, signed by its writer, published so followers learn about updates
+/// without replicating. The event may be signed by any nostr identity: followers trust the
+/// signature of the writer over the [`Checkpoint`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Head {
+    /// Public key of the core
+    pub public_key: VerifyingKey,
+    /// Fork, length and tree hash of the core
+    pub checkpoint: Checkpoint,
+    /// Signature of the writer over the checkpoint, `None` for an empty core
+    pub signature: Option<Signature>,
+}
+
+impl Head {
+    /// Current head of the core.
+    pub fn new(core: &Hypercore) -> Self {
+        Self {
+            public_key: core.key_pair().public,
+            checkpoint: core.checkpoint(),
+            signature: core.checkpoint_signature(),
+        }
+    }
+
+    /// Signed event of the head.
+    pub fn to_event(&self, keys: &NostrKeys, created_at: u64) -> Event {
+        self.to_event_with(keys, created_at, &OsRandom)
+    }
+
+    /// Signed event of the head, drawing the auxiliary randomness of the signature from `rng`.
+    pub fn to_event_with(&self, keys: &NostrKeys, created_at: u64, rng: &dyn Rng) -> Event {
+        let checkpoint = &self.checkpoint;
+        let mut tags = vec![
+            vec!["d".to_string(), discovery_key(&self.public_key).to_hex()],
+            vec!["key".to_string(), self.public_key.to_hex()],
+            vec!["fork".to_string(), checkpoint.fork.to_string()],
+            vec!["length".to_string(), checkpoint.length.to_string()],
+            vec!["root".to_string(), checkpoint.root_hash.to_hex()],
+        ];
+        if let Some(signature) = &self.signature {
+            tags.push(vec!["sig".to_string(), encode_hex(&signature.to_bytes())]);
+        }
+        Event::sign_with(keys, created_at, HEAD_KIND, tags, String::new(), rng)
+    }
+
+    /// Head of a received event, verifying it and the signature of the writer of the core.
+    pub fn from_event(event: &Event) -> Result<Self, HypercoreError> {
+        if event.kind != HEAD_KIND {
+            return Err(invalid_message(&format!(
+                "kind {} is not a head",
+                event.kind
+            )));
+        }
+        event.verify()?;
+        let tag = |name: &str| {
+            event
+                .tag(name)
+                .ok_or_else(|| invalid_message(&format!("head without {name}")))
+        };
+        let number = |name: &str| {
+            tag(name)?
+                .parse::<u64>()
+                .map_err(|_| invalid_message(&format!("head {name} is not a number")))
+        };
+        let public_key = VerifyingKey::parse_key(tag("key")?)?;
+        if discovery_key(&public_key).to_hex() != tag("d")? {
+            return Err(invalid_message("head key does not match the discovery key"));
+        }
+        let checkpoint = Checkpoint {
+            fork: number("fork")?,
+            length: number("length")?,
+            root_hash: hex_field(tag("root")?)?,
+        };
+        let signature = event
+            .tag("sig")
+            .map(|sig| hex_field(sig).map(|bytes| Signature::from_bytes(&bytes)))
+            .transpose()?;
+        let signed = match &signature {
+            Some(signature) => checkpoint.verify_signature(&public_key, signature),
+            None => checkpoint.length == 0,
+        };
+        if !signed {
+            return Err(HypercoreError::InvalidSignature {
+                context: format!(
+                    "Head of {} is not signed by its writer",
+                    public_key.display()
+                ),
+            });
+        }
+        Ok(Self {
+            public_key,
+            checkpoint,
+            signature,
+        })
+    }
+
+    /// Filter of the heads of the core with the given discovery key.
+    pub fn filter(discovery_key: &[u8; 32]) -> Filter {
+        Filter {
+            kinds: vec![HEAD_KIND],
+            authors: vec![],
+            tags: vec![('d', vec![discovery_key.to_hex()])],
+        }
+    }
+}
+
+/// Publish the current head of the core: the message to send to each of the relays.
+pub fn publish_head(
+    core: &Hypercore,
+    keys: &NostrKeys,
+    relays: &[String],
+) -> Vec<(String, ClientMessage)> {
+    publish_head_with(core, keys, relays, &SystemClock, &OsRandom)
+}
+
+/// Same as [`publish_head`], but timestamps the event with `clock` and draws the auxiliary
+/// randomness of its signature from `rng`.
+pub fn publish_head_with(
+    core: &Hypercore,
+    keys: &NostrKeys,
+    relays: &[String],
+    clock: &dyn Clock,
+    rng: &dyn Rng,
+) -> Vec<(String, ClientMessage)> {
+    let created_at = clock.unix_time().as_secs();
+    let message = ClientMessage::Event(Head::new(core).to_event_with(keys, created_at, rng));
+    relays
+        .iter()
+        .map(|relay| (relay.clone(), message.clone()))
+        .collect()
+}
+
+/// Byte stream with one peer, tunnelled through ephemeral events. Both peers use the same
+/// session, e.g. the hex discovery key of the core they replicate, and each numbers its
+/// chunks so the other reassembles them in order, whatever order and however many times the
+/// relays deliver them. Chunks a relay rejects, e.g. when rate limiting, are sent again by
+/// the [`RetryPolicy`] of the tunnel, see [`NostrTunnel::resend`].
+///
+/// Chunks are public unless encrypted with NIP-44, see [`NostrTunnel::with_encryption`].
+///
+/// The subscription id, the signatures of the chunks and the jitter of retries are drawn
+/// from [`OsRandom`], or from the [`Rng`] given to [`NostrTunnel::with_rng`]. Chunks are
+/// timestamped by [`SystemClock`], or by the [`Clock`] given to [`NostrTunnel::with_clock`].
+#[derive(Debug)]
+pub struct NostrTunnel {
+    keys: NostrKeys,
+    peer: [u8; 32],
+    session: String,
+    subscription: String,
+    /// Sequence number of the next chunk sent
+    send_seq: u64,
+    /// Sequence number of the next chunk to receive
+    receive_seq: u64,
+    /// Chunks received ahead of `receive_seq`
+    pending: BTreeMap<u64, Vec<u8>>,
+    /// Last chunks sent, with the times relays rejected them
+    sent: VecDeque<(Event, u32)>,
+    retry: RetryPolicy,
+    /// Conversation key encrypting the chunks with NIP-44, if encrypted
+    conversation_key: Option<[u8; 32]>,
+    rng: Arc<dyn Rng>,
+    clock: Arc<dyn Clock>,
+}
+
+impl NostrTunnel {
+    /// Tunnel with the peer with the given public key, in the given session.
+    pub fn new(keys: NostrKeys, peer: [u8; 32], session: &str) -> Self {
+        let rng: Arc<dyn Rng> = Arc::new(OsRandom);
+        Self {
+            keys,
+            peer,
+            session: session.to_string(),
+            subscription: subscription_id(rng.as_ref()),
+            send_seq: 0,
+            receive_seq: 0,
+            pending: BTreeMap::new(),
+            sent: VecDeque::new(),
+            retry: RetryPolicy::default(),
+            conversation_key: None,
+            rng,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Encrypt the chunks to the peer with NIP-44, so relays only see who tunnels to whom and
+    /// how much. Both peers must encrypt. Fails if the public key of the peer is invalid.
+    pub fn with_encryption(mut self) -> Result<Self, HypercoreError> {
+        self.conversation_key = Some(conversation_key(&self.keys, &self.peer)?);
+        Ok(self)
+    }
+
+    /// Timestamp the chunks with `clock` instead.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Draw the randomness of the tunnel from `rng` instead, starting with a new subscription
+    /// id. Use a [`SeededRng`](crate::SeededRng) for reproducible runs in tests.
+    pub fn with_rng(mut self, rng: Arc<dyn Rng>) -> Self {
+        self.subscription = subscription_id(rng.as_ref());
+        self.rng = rng;
+        self
+    }
+
+    /// Send chunks relays rejected again by the given policy.
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    /// Subscription to the chunks of the peer, to send to every relay first.
+    pub fn subscribe(&self) -> ClientMessage {
+        ClientMessage::Req {
+            subscription: self.subscription.clone(),
+            filters: vec![Filter {
+                kinds: vec![TUNNEL_KIND],
+                authors: vec![self.peer],
+                tags: vec![
+                    ('p', vec![self.keys.public_key().to_hex()]),
+                    ('s', vec![self.session.clone()]),
+                ],
+            }],
+        }
+    }
+
+    /// Events carrying the bytes to the peer, to send to every relay.
+    pub fn send(&mut self, bytes: &[u8]) -> Vec<ClientMessage> {
+        let created_at = self.clock.unix_time().as_secs();
+        bytes
+            .chunks(MAX_CHUNK_SIZE)
+            .map(|chunk| {
+                let mut content = BASE64.encode(chunk);
+                if let Some(conversation_key) = &self.conversation_key {
+                    content = nip44_encrypt(conversation_key, &content, self.rng.as_ref())
+                        .expect("Base64 of a chunk should be at most 65535 bytes");
+                }
+                let tags = vec![
+                    vec!["p".to_string(), self.peer.to_hex()],
+                    vec!["s".to_string(), self.session.clone()],
+                    vec!["seq".to_string(), self.send_seq.to_string()],
+                ];
+                self.send_seq += 1;
+                let event = Event::sign_with(
+                    &self.keys,
+                    created_at,
+                    TUNNEL_KIND,
+                    tags,
+                    content,
+                    self.rng.as_ref(),
+                );
+                if self.sent.len() >= MAX_PENDING_CHUNKS {
+                    self.sent.pop_front();
+                }
+                self.sent.push_back((event.clone(), 0));
+                ClientMessage::Event(event)
+            })
+            .collect()
+    }
+
+    /// Handle the outcome of publishing a chunk, returning the chunk to send again to the
+    /// relay that rejected it, after the delay, if the retry policy allows. Only the last
+    /// chunks sent are kept for this.
+    pub fn resend(&mut self, message: &RelayMessage) -> Option<(ClientMessage, Duration)> {
+        let RelayMessage::Ok {
+            event_id,
+            accepted: false,
+            message,
+        } = message
+        else {
+            return None;
+        };
+        let (event, rejections) = self
+            .sent
+            .iter_mut()
+            .find(|(event, _)| event.id == *event_id)?;
+        *rejections += 1;
+        let Some(delay) = self.retry.delay(*rejections, self.rng.as_ref()) else {
+            tracing::warn!("Tunnel chunk was rejected {rejections} times, giving up: {message}");
+            return None;
+        };
+        Some((ClientMessage::Event(event.clone()), delay))
+    }
+
+    /// Handle a message of a relay, returning the bytes of the peer it completes, in order,
+    /// if any. Messages of other subscriptions and events not from the peer are ignored.
+    pub fn receive(&mut self, message: &RelayMessage) -> Result<Vec<u8>, HypercoreError> {
+        let RelayMessage::Event {
+            subscription,
+            event,
+        } = message
+        else {
+            return Ok(vec![]);
+        };
+        if *subscription != self.subscription
+            || event.kind != TUNNEL_KIND
+            || event.pubkey != self.peer
+            || event.tag("s") != Some(&self.session)
+        {
+            return Ok(vec![]);
+        }
+        event.verify()?;
+        let seq: u64 = event
+            .tag("seq")
+            .and_then(|seq| seq.parse().ok())
+            .ok_or_else(|| invalid_message("tunnel event without sequence number"))?;
+        if seq >= self.receive_seq && !self.pending.contains_key(&seq) {
+            if self.pending.len() >= MAX_PENDING_CHUNKS {
+                return Err(HypercoreError::InvalidOperation {
+                    context: format!("Tunnel chunk {} was never received", self.receive_seq),
+                });
+            }
+            let content = match &self.conversation_key {
+                Some(conversation_key) => nip44_decrypt(conversation_key, &event.content)?,
+                None => event.content.clone(),
+            };
+            let chunk = BASE64
+                .decode(content)
+                .map_err(|_| invalid_message("tunnel event is not base64"))?;
+            self.pending.insert(seq, chunk);
+        }
+        let mut bytes = vec![];
+        while let Some(chunk) = self.pending.remove(&self.receive_seq) {
+            bytes.extend(chunk);
+            self.receive_seq += 1;
+        }
+        Ok(bytes)
+    }
+
+    /// Message ending the subscription, to send to every relay when done.
+    pub fn close(&self) -> ClientMessage {
+        ClientMessage::Close {
+            subscription: self.subscription.clone(),
+        }
+    }
+}
+
+/// Sends [`Alert`](crate::replication::Alert)s to the owner of a core as private direct
+/// messages, see [`Event::direct_message`], wrap a core in an
+/// [`AlertingCore`](crate::replication::AlertingCore) with it. Like the rest of this module it
+/// does no IO: `send` gets the message for each relay.
+///
+/// Messages are timestamped by [`SystemClock`] and encrypted and signed with randomness of
+/// [`OsRandom`], or of the sources given to [`NostrAlertNotifier::with_clock`] and
+/// [`NostrAlertNotifier::with_rng`].
+#[cfg(feature = "replication")]
+pub struct NostrAlertNotifier<F> {
+    keys: NostrKeys,
+    recipient: [u8; 32],
+    relays: Vec<String>,
+    send: F,
+    clock: Arc<dyn Clock>,
+    rng: Arc<dyn Rng>,
+}
+
+#[cfg(feature = "replication")]
+impl<F: Fn(Vec<(String, ClientMessage)>) + Send + Sync> NostrAlertNotifier<F> {
+    /// Notifier messaging `recipient` from `keys` through the given relays.
+    pub fn new(keys: NostrKeys, recipient: [u8; 32], relays: Vec<String>, send: F) -> Self {
+        Self {
+            keys,
+            recipient,
+            relays,
+            send,
+            clock: Arc::new(SystemClock),
+            rng: Arc::new(OsRandom),
+        }
+    }
+
+    /// Timestamp the messages with `clock` instead.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Draw the randomness of the messages from `rng` instead. Use a
+    /// [`SeededRng`](crate::SeededRng) for reproducible runs in tests.
+    pub fn with_rng(mut self, rng: Arc<dyn Rng>) -> Self {
+        self.rng = rng;
+        self
+    }
+}
+
+#[cfg(feature = "replication")]
+impl<F: Fn(Vec<(String, ClientMessage)>) + Send + Sync> crate::replication::AlertNotifier
+    for NostrAlertNotifier<F>
+{
+    async fn notify(&self, public_key: &VerifyingKey, alert: crate::replication::Alert) {
+        let text = format!("Hypercore {}: {alert}", public_key.display());
+        let Ok(event) = Event::direct_message_with(
+            &self.keys,
+            &self.recipient,
+            &text,
+            self.clock.unix_time().as_secs(),
+            self.rng.as_ref(),
+        ) else {
+            // The recipient isn't a valid public key, nothing to send
+            return;
+        };
+        let message = ClientMessage::Event(event);
+        (self.send)(
+            self.relays
+                .iter()
+                .map(|relay| (relay.clone(), message.clone()))
+                .collect(),
+        );
+    }
+}
+
+#[cfg(feature = "replication")]
+impl<F> fmt::Debug for NostrAlertNotifier<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NostrAlertNotifier")
+            .field("keys", &self.keys)
+            .field("recipient", &self.recipient.to_hex())
+            .field("relays", &self.relays)
+            .field("clock", &self.clock)
+            .field("rng", &self.rng)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Version byte of the NIP-44 payloads.
+const NIP44_VERSION: u8 = 2;
+
+/// Encrypt a text as a NIP-44 payload of the conversation, with a nonce drawn from `rng`.
+fn nip44_encrypt(
+    conversation_key: &[u8; 32],
+    text: &str,
+    rng: &dyn Rng,
+) -> Result<String, HypercoreError> {
+    let mut nonce = [0; 32];
+    rng.fill_bytes(&mut nonce);
+    nip44_encrypt_with_nonce(conversation_key, text, &nonce)
+}
+
+fn nip44_encrypt_with_nonce(
+    conversation_key: &[u8; 32],
+    text: &str,
+    nonce: &[u8; 32],
+) -> Result<String, HypercoreError> {
+    let length = u16::try_from(text.len())
+        .ok()
+        .filter(|length| *length > 0)
+        .ok_or_else(|| HypercoreError::BadArgument {
+            context: format!("Can not encrypt {} bytes, only 1 to 65535", text.len()),
+        })?;
+    let (key, chacha_nonce, hmac_key) = nip44_message_keys(conversation_key, nonce);
+    let mut padded = Vec::with_capacity(2 + nip44_padded_len(text.len()));
+    padded.extend(length.to_be_bytes());
+    padded.extend(text.as_bytes());
+    padded.resize(2 + nip44_padded_len(text.len()), 0);
+    ChaCha20::new(&key.into(), &chacha_nonce.into()).apply_keystream(&mut padded);
+    let mut payload = Vec::with_capacity(1 + 32 + padded.len() + 32);
+    payload.push(NIP44_VERSION);
+    payload.extend(nonce);
+    payload.extend(&padded);
+    payload.extend(nip44_mac(&hmac_key, nonce, &padded).finalize().into_bytes());
+    Ok(BASE64.encode(payload))
+}
+
+/// Text of a NIP-44 payload of the conversation, after checking its MAC.
+fn nip44_decrypt(conversation_key: &[u8; 32], payload: &str) -> Result<String, HypercoreError> {
+    if payload.starts_with('#') {
+        return Err(invalid_message("unsupported encryption version"));
+    }
+    if !(132..=87_472).contains(&payload.len()) {
+        return Err(invalid_message("encrypted payload has an invalid length"));
+    }
+    let payload = BASE64
+        .decode(payload)
+        .map_err(|_| invalid_message("encrypted payload is not base64"))?;
+    if !(99..=65_603).contains(&payload.len()) || payload[0] != NIP44_VERSION {
+        return Err(invalid_message("unsupported encryption version"));
+    }
+    let nonce: [u8; 32] = payload[1..33].try_into().expect("Should be 32 bytes");
+    let (ciphertext, mac) = payload[33..].split_at(payload.len() - 33 - 32);
+    let (key, chacha_nonce, hmac_key) = nip44_message_keys(conversation_key, &nonce);
+    nip44_mac(&hmac_key, &nonce, ciphertext)
+        .verify_slice(mac)
+        .map_err(|_| HypercoreError::InvalidChecksum {
+            context: "Encrypted payload has an invalid MAC".to_string(),
+        })?;
+    let mut padded = ciphertext.to_vec();
+    ChaCha20::new(&key.into(), &chacha_nonce.into()).apply_keystream(&mut padded);
+    let length = usize::from(u16::from_be_bytes([padded[0], padded[1]]));
+    if length == 0 || padded.len() != 2 + nip44_padded_len(length) {
+        return Err(invalid_message("encrypted payload has invalid padding"));
+    }
+    padded.truncate(2 + length);
+    String::from_utf8(padded.split_off(2))
+        .map_err(|_| invalid_message("encrypted payload is not UTF-8"))
+}
+
+/// Conversation key of NIP-44, the same both ways between two keys.
+fn conversation_key(keys: &NostrKeys, other: &[u8; 32]) -> Result<[u8; 32], HypercoreError> {
+    let (conversation_key, _) =
+        Hkdf::<Sha256>::extract(Some(b"nip44-v2"), &shared_secret(keys, other)?);
+    Ok(conversation_key.into())
+}
+
+/// ChaCha20 key and nonce and HMAC key of a NIP-44 message.
+fn nip44_message_keys(
+    conversation_key: &[u8; 32],
+    nonce: &[u8; 32],
+) -> ([u8; 32], [u8; 12], [u8; 32]) {
+    let mut keys = [0; 76];
+    Hkdf::<Sha256>::from_prk(conversation_key)
+        .expect("Conversation key should be a valid PRK")
+        .expand(nonce, &mut keys)
+        .expect("76 bytes should be a valid length");
+    (
+        keys[..32].try_into().expect("Should be 32 bytes"),
+        keys[32..44].try_into().expect("Should be 12 bytes"),
+        keys[44..].try_into().expect("Should be 32 bytes"),
+    )
+}
+
+fn nip44_mac(hmac_key: &[u8; 32], nonce: &[u8; 32], ciphertext: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(hmac_key).expect("HMAC takes keys of any size");
+    mac.update(nonce);
+    mac.update(ciphertext);
+    mac
+}
+
+/// Length texts are padded to by NIP-44, hiding their exact length.
+fn nip44_padded_len(length: usize) -> usize {
+    if length <= 32 {
+        return 32;
+    }
+    let next_power = 1 << (usize::BITS - (length - 1).leading_zeros());
+    let chunk = if next_power <= 256 {
+        32
+    } else {
+        next_power / 8
+    };
+    chunk * ((length - 1) / chunk + 1)
+}
+
+/// Time of a seal or gift wrap, up to two days before the message.
+fn backdate(created_at: u64, rng: &dyn Rng) -> u64 {
+    created_at.saturating_sub(rng.below(MAX_BACKDATE_SECS))
+}
+
+/// Shared secret of NIP-44: the x coordinate of the ECDH point with the x-only public key of
+/// the other side.
+fn shared_secret(keys: &NostrKeys, other: &[u8; 32]) -> Result<[u8; 32], HypercoreError> {
+    let mut sec1 = [2; 33];
+    sec1[1..].copy_from_slice(other);
+    let other =
+        k256::PublicKey::from_sec1_bytes(&sec1).map_err(|_| HypercoreError::BadArgument {
+            context: "Invalid nostr public key".to_string(),
+        })?;
+    let shared = ecdh::diffie_hellman(keys.signing_key.as_nonzero_scalar(), other.as_affine());
+    Ok((*shared.raw_secret_bytes()).into())
+}
+
+fn event_id(
+    pubkey: &[u8; 32],
+    created_at: u64,
+    kind: u32,
+    tags: &[Vec<String>],
+    content: &str,
+) -> [u8; 32] {
+    let serialized = json!([0, pubkey.to_hex(), created_at, kind, tags, content]).to_string();
+    Sha256::digest(serialized.as_bytes()).into()
+}
+
+fn parse_json(text: &str) -> Result<Value, HypercoreError> {
+    serde_json::from_str(text).map_err(|err| invalid_message(&format!("invalid JSON: {err}")))
+}
+
+fn json_field<'a>(value: &'a Value, name: &str) -> Result<&'a Value, HypercoreError> {
+    value
+        .get(name)
+        .ok_or_else(|| invalid_message(&format!("event without {name}")))
+}
+
+fn json_string<'a>(value: &'a Value, name: &str) -> Result<&'a str, HypercoreError> {
+    json_field(value, name)?
+        .as_str()
+        .ok_or_else(|| invalid_message(&format!("event {name} is not a string")))
+}
+
+fn json_number(value: &Value, name: &str) -> Result<u64, HypercoreError> {
+    json_field(value, name)?
+        .as_u64()
+        .ok_or_else(|| invalid_message(&format!("event {name} is not a number")))
+}
+
+fn hex_field<const N: usize>(encoded: &str) -> Result<[u8; N], HypercoreError> {
+    decode_hex(encoded).ok_or_else(|| invalid_message(&format!("expected {N} hex bytes")))
+}
+
+/// Random id of a subscription.
+fn subscription_id(rng: &dyn Rng) -> String {
+    let mut subscription = [0; 8];
+    rng.fill_bytes(&mut subscription);
+    encode_hex(&subscription)
+}
+
+fn invalid_message(reason: &str) -> HypercoreError {
+    HypercoreError::BadArgument {
+        context: format!("Invalid nostr message, {reason}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::create_hypercore_with_data;
+    use crate::{ManualClock, SeededRng};
+
+    /// Deliver a client message the way a relay would, to the subscriber of `subscription`.
+    fn relay(message: &ClientMessage, subscription: &str) -> RelayMessage {
+        let ClientMessage::Event(event) = message else {
+            panic!("Only events are relayed");
+        };
+        let text = json!(["EVENT", subscription, event.to_json()]).to_string();
+        RelayMessage::parse(&text).unwrap()
+    }
+
+    fn subscription(message: &ClientMessage) -> String {
+        let value: Value = serde_json::from_str(&message.to_json()).unwrap();
+        value[1].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn events_verify_and_round_trip() -> Result<(), HypercoreError> {
+        let keys = NostrKeys::generate();
+        let event = Event::sign(
+            &keys,
+            1_700_000_000,
+            1,
+            vec![vec!["t".to_string(), "hypercore".to_string()]],
+            "hello \"nostr\"\n".to_string(),
+        );
+        event.verify()?;
+        let message = relay(&ClientMessage::Event(event.clone()), "sub");
+        assert_eq!(
+            message,
+            RelayMessage::Event {
+                subscription: "sub".to_string(),
+                event: event.clone(),
+            }
+        );
+
+        let mut tampered = event.clone();
+        tampered.content.push('!');
+        assert!(tampered.verify().is_err());
+        let mut forged = Event::sign(&NostrKeys::generate(), 1, 1, vec![], String::new());
+        forged.pubkey = event.pubkey;
+        forged.id = event_id(&forged.pubkey, 1, 1, &[], "");
+        assert!(forged.verify().is_err());
+
+        assert_eq!(
+            RelayMessage::parse(&format!(
+                r#"["OK","{}",false,"blocked"]"#,
+                event.id.to_hex()
+            ))?,
+            RelayMessage::Ok {
+                event_id: event.id,
+                accepted: false,
+                message: "blocked".to_string(),
+            }
+        );
+        assert_eq!(
+            RelayMessage::parse(r#"["EOSE","sub"]"#)?,
+            RelayMessage::Eose {
+                subscription: "sub".to_string()
+            }
+        );
+        assert!(RelayMessage::parse(r#"["AUTH","challenge"]"#).is_err());
+        assert!(RelayMessage::parse("{}").is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn announcements_match_their_core() -> Result<(), HypercoreError> {
+        let core = create_hypercore_with_data(3).await?;
+        let keys = NostrKeys::generate();
+        let announcement = Announcement::new(&core, vec!["wss://relay.example".to_string()]);
+        let event = announcement.to_event(&keys, 1_700_000_000);
+        assert_eq!(Announcement::from_event(&event)?, announcement);
+        assert_eq!(announcement.length, 3);
+        let discovery_key = discovery_key(&core.key_pair().public);
+        assert_eq!(
+            ClientMessage::Req {
+                subscription: "a".to_string(),
+                filters: vec![Announcement::filter(&discovery_key)],
+            }
+            .to_json(),
+            format!(
+                r##"["REQ","a",{{"#d":["{}"],"kinds":[{ANNOUNCEMENT_KIND}]}}]"##,
+                discovery_key.to_hex()
+            )
+        );
+
+        // A key announced under another core's discovery key is rejected
+        let mut tags = event.tags.clone();
+        tags[0][1] = [0; 32].to_hex();
+        let event = Event::sign(&keys, 1, ANNOUNCEMENT_KIND, tags, String::new());
+        assert!(Announcement::from_event(&event).is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn heads_are_signed_by_the_writer() -> Result<(), HypercoreError> {
+        let mut core = create_hypercore_with_data(0).await?;
+        let keys = NostrKeys::generate();
+        let empty = Head::from_event(&Head::new(&core).to_event(&keys, 1))?;
+        assert_eq!(empty.checkpoint.length, 0);
+        core.append(b"#0").await?;
+
+        let relays = vec!["wss://a.example".to_string(), "wss://b.example".to_string()];
+        let messages = publish_head(&core, &keys, &relays);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].0, relays[1]);
+        let ClientMessage::Event(event) = &messages[0].1 else {
+            panic!("Head should be published as an event");
+        };
+        let head = Head::from_event(event)?;
+        assert_eq!(head.checkpoint, core.checkpoint());
+        assert_eq!(head.signature, core.checkpoint_signature());
+
+        // A head re-signed by another nostr identity is only trusted with the writer's signature
+        let mut forged = head.clone();
+        forged.checkpoint.length = 2;
+        assert!(Head::from_event(&forged.to_event(&keys, 2)).is_err());
+        forged.signature = None;
+        assert!(Head::from_event(&forged.to_event(&keys, 2)).is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn seeded_events_are_reproducible() -> Result<(), HypercoreError> {
+        let core = create_hypercore_with_data(2).await?;
+        let keys = NostrKeys::generate();
+        let relays = vec!["wss://a.example".to_string()];
+        let clock = ManualClock::at_unix_time(Duration::from_secs(1_700_000_000));
+        let run = |seed| {
+            let rng = SeededRng::new(seed);
+            let announcement =
+                Announcement::new(&core, relays.clone()).to_event_with(&keys, 1_700_000_000, &rng);
+            let head = publish_head_with(&core, &keys, &relays, &clock, &rng);
+            (announcement, head)
+        };
+        let (announcement, head) = run(1);
+        assert_eq!((announcement.clone(), head.clone()), run(1));
+        assert_ne!(announcement.sig, run(2).0.sig);
+        let ClientMessage::Event(event) = &head[0].1 else {
+            panic!("Head should be published as an event");
+        };
+        assert_eq!(event.created_at, 1_700_000_000);
+        Head::from_event(event)?;
+        Ok(())
+    }
+
+    #[test]
+    fn tunnel_reassembles_chunks_in_order() -> Result<(), HypercoreError> {
+        let (a_keys, b_keys) = (NostrKeys::generate(), NostrKeys::generate());
+        let mut a = NostrTunnel::new(a_keys.clone(), b_keys.public_key(), "session");
+        let mut b = NostrTunnel::new(b_keys.clone(), a_keys.public_key(), "session");
+        let b_subscription = subscription(&b.subscribe());
+
+        let bytes: Vec<u8> = (0..MAX_CHUNK_SIZE * 2 + 10).map(|i| i as u8).collect();
+        let mut events = a.send(&bytes);
+        events.extend(a.send(b"more"));
+        assert_eq!(events.len(), 4);
+
+        // Out of order, duplicated by a second relay, and mixed with strangers' events
+        let stranger = Event::sign(
+            &NostrKeys::generate(),
+            1,
+            TUNNEL_KIND,
+            vec![vec!["s".to_string(), "session".to_string()]],
+            String::new(),
+        );
+        assert!(b.receive(&relay(&events[1], &b_subscription))?.is_empty());
+        let mut received = vec![];
+        for index in [0, 1, 3, 2, 0] {
+            received.extend(b.receive(&relay(&events[index], &b_subscription))?);
+            let stranger = relay(&ClientMessage::Event(stranger.clone()), &b_subscription);
+            assert!(b.receive(&stranger)?.is_empty());
+        }
+        let mut expected = bytes;
+        expected.extend(b"more");
+        assert_eq!(received, expected);
+
+        // The peer's own subscription doesn't see its events
+        let a_subscription = subscription(&a.subscribe());
+        assert!(a.receive(&relay(&events[0], &a_subscription))?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn encrypted_tunnels_hide_their_chunks() -> Result<(), HypercoreError> {
+        let (a_keys, b_keys) = (NostrKeys::generate(), NostrKeys::generate());
+        let clock = Arc::new(ManualClock::at_unix_time(Duration::from_secs(42)));
+        let mut a = NostrTunnel::new(a_keys.clone(), b_keys.public_key(), "session")
+            .with_encryption()?
+            .with_clock(clock);
+        let mut b = NostrTunnel::new(b_keys, a_keys.public_key(), "session").with_encryption()?;
+        let mut plain = NostrTunnel::new(NostrKeys::generate(), a_keys.public_key(), "session");
+        let b_subscription = subscription(&b.subscribe());
+
+        let bytes = vec![7; MAX_CHUNK_SIZE + 1];
+        let events = a.send(&bytes);
+        let ClientMessage::Event(event) = &events[0] else {
+            panic!("Should be an event");
+        };
+        assert_eq!(event.created_at, 42);
+        assert_eq!(BASE64.decode(&event.content).unwrap()[0], NIP44_VERSION);
+        let mut received = vec![];
+        for event in &events {
+            received.extend(b.receive(&relay(event, &b_subscription))?);
+        }
+        assert_eq!(received, bytes);
+
+        // Unencrypted chunks are rejected by an encrypted tunnel
+        let events = plain.send(b"hello");
+        let mut a_from_plain =
+            NostrTunnel::new(a_keys, plain.keys.public_key(), "session").with_encryption()?;
+        let a_subscription = subscription(&a_from_plain.subscribe());
+        assert!(a_from_plain
+            .receive(&relay(&events[0], &a_subscription))
+            .is_err());
+        assert!(NostrTunnel::new(NostrKeys::generate(), [0; 32], "session")
+            .with_encryption()
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn tunnel_resends_rejected_chunks() {
+        let mut tunnel = NostrTunnel::new(NostrKeys::generate(), [1; 32], "session");
+        tunnel.set_retry_policy(RetryPolicy {
+            max_attempts: 2,
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        });
+        let events = tunnel.send(b"hello");
+        let ClientMessage::Event(event) = &events[0] else {
+            panic!("Should be an event");
+        };
+        let outcome = |accepted| RelayMessage::Ok {
+            event_id: event.id,
+            accepted,
+            message: "rate-limited: slow down".to_string(),
+        };
+        assert_eq!(tunnel.resend(&outcome(true)), None);
+        assert_eq!(
+            tunnel.resend(&outcome(false)),
+            Some((events[0].clone(), Duration::from_millis(100)))
+        );
+        // Out of attempts, and unknown events are left alone
+        assert_eq!(tunnel.resend(&outcome(false)), None);
+        let unknown = RelayMessage::Ok {
+            event_id: [0; 32],
+            accepted: false,
+            message: String::new(),
+        };
+        assert_eq!(tunnel.resend(&unknown), None);
+    }
+
+    #[test]
+    fn seeded_tunnels_are_reproducible() {
+        let keys = NostrKeys::generate();
+        let run = |seed| {
+            let mut tunnel = NostrTunnel::new(keys.clone(), [1; 32], "session")
+                .with_rng(Arc::new(SeededRng::new(seed)));
+            tunnel.set_retry_policy(RetryPolicy {
+                max_attempts: 4,
+                jitter: 0.5,
+                ..RetryPolicy::default()
+            });
+            let events = tunnel.send(b"hello");
+            let ClientMessage::Event(event) = &events[0] else {
+                panic!("Should be an event");
+            };
+            let rejected = RelayMessage::Ok {
+                event_id: event.id,
+                accepted: false,
+                message: "rate-limited: slow down".to_string(),
+            };
+            let delays: Vec<_> = (0..4)
+                .map(|_| tunnel.resend(&rejected).map(|(_, delay)| delay))
+                .collect();
+            (subscription(&tunnel.subscribe()), event.sig, delays)
+        };
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1).2, run(2).2);
+    }
+
+    #[test]
+    fn nip44_matches_the_reference_vectors() -> Result<(), HypercoreError> {
+        let mut secret = [0; 32];
+        secret[31] = 1;
+        let keys = NostrKeys::from_secret(&secret)?;
+        secret[31] = 2;
+        let other = NostrKeys::from_secret(&secret)?;
+        assert_eq!(
+            conversation_key(&keys, &other.public_key())?.to_hex(),
+            "c41c775356fd92eadc63ff5a0dc1da211b268cbea22316767095b2871ea1412d"
+        );
+        assert_eq!(
+            conversation_key(&other, &keys.public_key())?,
+            conversation_key(&keys, &other.public_key())?
+        );
+        let mut nonce = [0; 32];
+        nonce[31] = 1;
+        let key = conversation_key(&keys, &other.public_key())?;
+        let payload = nip44_encrypt_with_nonce(&key, "a", &nonce)?;
+        assert_eq!(
+            payload,
+            "AgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAABee0G5VSK0/9YypIObAtDKfYEAjD35uVkHyB0F4DwrcNaCXlCWZKaArsGrY6M9wnuTMxWfp1RTN9Xga8no+kF5Vsb"
+        );
+        let other_key = conversation_key(&other, &keys.public_key())?;
+        assert_eq!(nip44_decrypt(&other_key, &payload)?, "a");
+
+        for (length, padded) in [
+            (16, 32),
+            (33, 64),
+            (65, 96),
+            (100, 128),
+            (250, 256),
+            (320, 320),
+            (515, 640),
+            (900, 1024),
+            (65535, 65536),
+        ] {
+            assert_eq!(nip44_padded_len(length), padded);
+        }
+        assert!(nip44_encrypt(&key, "", &OsRandom).is_err());
+
+        // A flipped bit fails the MAC
+        let mut tampered = BASE64.decode(&payload).unwrap();
+        tampered[40] ^= 1;
+        assert!(matches!(
+            nip44_decrypt(&other_key, &BASE64.encode(tampered)),
+            Err(HypercoreError::InvalidChecksum { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn direct_messages_are_read_by_the_recipient_only() -> Result<(), HypercoreError> {
+        let sender = NostrKeys::generate();
+        let recipient = NostrKeys::generate();
+        let event = Event::direct_message(&sender, &recipient.public_key(), "key compromised?", 1)?;
+        assert_eq!(event.kind, GIFT_WRAP_KIND);
+        assert_ne!(event.pubkey, sender.public_key());
+        assert_eq!(
+            event.tag("p"),
+            Some(recipient.public_key().to_hex().as_str())
+        );
+        assert_eq!(
+            event.decrypt_direct_message(&recipient)?,
+            DirectMessage {
+                sender: sender.public_key(),
+                created_at: 1,
+                text: "key compromised?".to_string(),
+            }
+        );
+        assert!(event.decrypt_direct_message(&sender).is_err());
+        assert!(event
+            .decrypt_direct_message(&NostrKeys::generate())
+            .is_err());
+
+        // A seal of another key than the author of the message is rejected
+        let rumor = Rumor {
+            pubkey: NostrKeys::generate().public_key(),
+            created_at: 1,
+            kind: DIRECT_MESSAGE_KIND,
+            tags: vec![],
+            content: "I am someone else".to_string(),
+        };
+        let key = conversation_key(&sender, &recipient.public_key())?;
+        let content = nip44_encrypt(&key, &rumor.to_json().to_string(), &OsRandom)?;
+        let seal = Event::sign(&sender, 1, SEAL_KIND, vec![], content);
+        let wrap_keys = NostrKeys::generate();
+        let key = conversation_key(&wrap_keys, &recipient.public_key())?;
+        let content = nip44_encrypt(&key, &seal.to_json().to_string(), &OsRandom)?;
+        let wrap = Event::sign(&wrap_keys, 1, GIFT_WRAP_KIND, vec![], content);
+        assert!(matches!(
+            wrap.decrypt_direct_message(&recipient),
+            Err(HypercoreError::InvalidSignature { .. })
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "replication")]
+    #[async_std::test]
+    async fn alerts_are_sent_as_direct_messages() -> Result<(), HypercoreError> {
+        use crate::replication::{Alert, AlertNotifier};
+        use std::sync::Mutex;
+
+        let owner = NostrKeys::generate();
+        let keys = NostrKeys::generate();
+        let sent = Mutex::new(vec![]);
+        let notifier = NostrAlertNotifier::new(
+            keys.clone(),
+            owner.public_key(),
+            vec!["wss://a.example".to_string()],
+            |messages| sent.lock().unwrap().extend(messages),
+        )
+        .with_clock(Arc::new(ManualClock::at_unix_time(Duration::from_secs(
+            1_700_000_000,
+        ))))
+        .with_rng(Arc::new(SeededRng::new(1)));
+        let core = create_hypercore_with_data(0).await?;
+        let alert = Alert::UnexpectedTruncation {
+            local_fork: 0,
+            remote_fork: 1,
+        };
+        notifier
+            .notify(&core.key_pair().public, alert.clone())
+            .await;
+
+        let sent = sent.into_inner().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, "wss://a.example");
+        let ClientMessage::Event(event) = &sent[0].1 else {
+            panic!("Alert should be sent as an event");
+        };
+        let message = event.decrypt_direct_message(&owner)?;
+        assert_eq!(message.sender, keys.public_key());
+        assert_eq!(message.created_at, 1_700_000_000);
+        assert!(event.created_at <= message.created_at);
+        assert!(message
+            .text
+            .contains(&core.key_pair().public.display().to_string()));
+        assert!(message.text.ends_with(&alert.to_string()));
+        Ok(())
+    }
+}
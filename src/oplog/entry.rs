@@ -76,8 +76,9 @@ impl CompactEncoding<BitfieldUpdate> for HypercoreState {
 /// Oplog Entry
 #[derive(Debug)]
 pub struct Entry {
-    // TODO: This is a keyValueArray in JS
-    pub(crate) user_data: Vec<String>,
+    /// User data set by the entry, a keyValue in JS. `None` as value removes the key, it is
+    /// encoded as an empty buffer like JS encodes null.
+    pub(crate) user_data: Option<(String, Option<Vec<u8>>)>,
     pub(crate) tree_nodes: Vec<Node>,
     pub(crate) tree_upgrade: Option<EntryTreeUpgrade>,
     pub(crate) bitfield: Option<BitfieldUpdate>,
@@ -86,8 +87,10 @@ pub struct Entry {
 impl CompactEncoding<Entry> for HypercoreState {
     fn preencode(&mut self, value: &Entry) -> Result<usize, EncodingError> {
         self.0.add_end(1)?; // flags
-        if !value.user_data.is_empty() {
-            self.0.preencode(&value.user_data)?;
+        if let Some((key, value)) = &value.user_data {
+            self.0.preencode_str(key)?;
+            self.0
+                .preencode_buffer(value.as_deref().unwrap_or_default())?;
         }
         if !value.tree_nodes.is_empty() {
             self.preencode(&value.tree_nodes)?;
@@ -105,9 +108,11 @@ impl CompactEncoding<Entry> for HypercoreState {
         let start = self.0.start();
         self.0.add_start(1)?;
         let mut flags: u8 = 0;
-        if !value.user_data.is_empty() {
+        if let Some((key, value)) = &value.user_data {
             flags |= 1;
-            self.0.encode(&value.user_data, buffer)?;
+            self.0.encode_str(key, buffer)?;
+            self.0
+                .encode_buffer(value.as_deref().unwrap_or_default(), buffer)?;
         }
         if !value.tree_nodes.is_empty() {
             flags |= 2;
@@ -128,10 +133,12 @@ impl CompactEncoding<Entry> for HypercoreState {
 
     fn decode(&mut self, buffer: &[u8]) -> Result<Entry, EncodingError> {
         let flags = self.0.decode_u8(buffer)?;
-        let user_data: Vec<String> = if flags & 1 != 0 {
-            self.0.decode(buffer)?
+        let user_data = if flags & 1 != 0 {
+            let key = self.0.decode_string(buffer)?;
+            let value = self.0.decode_buffer_vec(buffer)?;
+            Some((key, (!value.is_empty()).then_some(value)))
         } else {
-            vec![]
+            None
         };
 
         let tree_nodes: Vec<Node> = if flags & 2 != 0 {
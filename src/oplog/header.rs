@@ -5,9 +5,17 @@ use std::convert::TryInto;
 
 use crate::crypto::default_signer_manifest;
 use crate::crypto::Manifest;
+use crate::BitfieldFormat;
 use crate::PartialKeypair;
 use crate::VerifyingKey;
 
+/// Header flag set when a non-default bitfield format follows the hints.
+const BITFIELD_FORMAT_FLAG: u8 = 8;
+/// Header flag set while data may have been written that the bitfield and tree don't cover yet.
+const DIRTY_FLAG: u8 = 16;
+/// Header flags this implementation reads: manifest, key pair, bitfield format and dirty.
+const KNOWN_FLAGS: u8 = 2 | 4 | BITFIELD_FORMAT_FLAG | DIRTY_FLAG;
+
 /// Oplog header.
 #[derive(Debug, Clone)]
 pub(crate) struct Header {
@@ -18,10 +26,20 @@ pub(crate) struct Header {
     pub(crate) key: [u8; 32],
     pub(crate) manifest: Manifest,
     pub(crate) key_pair: PartialKeypair,
-    // TODO: This is a keyValueArray in JS
-    pub(crate) user_data: Vec<String>,
+    /// Key/value pairs of the application, a keyValueArray in JS
+    pub(crate) user_data: Vec<(String, Vec<u8>)>,
     pub(crate) tree: HeaderTree,
     pub(crate) hints: HeaderHints,
+    /// Not in the Javascript header, only stored when not the default.
+    pub(crate) bitfield_format: BitfieldFormat,
+    /// Not in the Javascript header, set before data is written and cleared when the bitfield
+    /// and tree are flushed, only a dirty core needs its bitfield checked on open.
+    pub(crate) dirty: bool,
+    /// Flags of fields this implementation doesn't know, e.g. written by a newer Javascript
+    /// implementation, kept to be written back.
+    pub(crate) unknown_flags: u8,
+    /// Raw bytes of the unknown fields, which follow the known ones.
+    pub(crate) unknown_fields: Vec<u8>,
 }
 
 impl Header {
@@ -39,6 +57,10 @@ impl Header {
                 reorgs: vec![],
                 contiguous_length: 0,
             },
+            bitfield_format: BitfieldFormat::default(),
+            dirty: false,
+            unknown_flags: 0,
+            unknown_fields: vec![],
         }
         // Javascript side, initial header
         // header = {
@@ -59,6 +81,33 @@ impl Header {
         //    }
         //  }
     }
+
+    /// Value of the user data under the given key.
+    pub(crate) fn user_data(&self, key: &str) -> Option<&[u8]> {
+        self.user_data
+            .iter()
+            .find(|(existing, _)| existing == key)
+            .map(|(_, value)| &value[..])
+    }
+
+    /// Set the user data under the given key, or remove it with `None`. Keys are kept sorted,
+    /// like `updateUserData` of the Javascript implementation does.
+    pub(crate) fn set_user_data(&mut self, key: &str, value: Option<&[u8]>) {
+        match (
+            self.user_data
+                .binary_search_by(|(existing, _)| existing.as_str().cmp(key)),
+            value,
+        ) {
+            (Ok(position), Some(value)) => self.user_data[position].1 = value.to_vec(),
+            (Ok(position), None) => {
+                self.user_data.remove(position);
+            }
+            (Err(position), Some(value)) => self
+                .user_data
+                .insert(position, (key.to_string(), value.to_vec())),
+            (Err(_), None) => {}
+        }
+    }
 }
 
 /// Oplog header tree
@@ -197,21 +246,48 @@ impl CompactEncoding<Header> for State {
         self.preencode_fixed_32()?; // key
         self.preencode(&value.manifest)?;
         self.preencode(&value.key_pair)?;
-        self.preencode(&value.user_data)?;
+        self.preencode(&value.user_data.len())?;
+        for (key, value) in &value.user_data {
+            self.preencode_str(key)?;
+            self.preencode_buffer(value)?;
+        }
         self.preencode(&value.tree)?;
-        self.preencode(&value.hints)
+        self.preencode(&value.hints)?;
+        if !value.bitfield_format.is_default() {
+            self.add_end(1)?; // Bitfield format version
+            self.preencode(&(value.bitfield_format.page_size() as u32))?;
+        }
+        self.add_end(value.unknown_fields.len())
     }
 
     fn encode(&mut self, value: &Header, buffer: &mut [u8]) -> Result<usize, EncodingError> {
         self.set_byte_to_buffer(1, buffer)?; // Version
-        let flags: u8 = 2 | 4; // Manifest and key pair, TODO: external=1
+        let mut flags: u8 = 2 | 4 | value.unknown_flags; // Manifest and key pair, TODO: external=1
+        if !value.bitfield_format.is_default() {
+            flags |= BITFIELD_FORMAT_FLAG;
+        }
+        if value.dirty {
+            flags |= DIRTY_FLAG;
+        }
         self.set_byte_to_buffer(flags, buffer)?;
         self.encode_fixed_32(&value.key, buffer)?;
         self.encode(&value.manifest, buffer)?;
         self.encode(&value.key_pair, buffer)?;
-        self.encode(&value.user_data, buffer)?;
+        self.encode(&value.user_data.len(), buffer)?;
+        for (key, value) in &value.user_data {
+            self.encode_str(key, buffer)?;
+            self.encode_buffer(value, buffer)?;
+        }
         self.encode(&value.tree, buffer)?;
-        self.encode(&value.hints, buffer)
+        let mut end = self.encode(&value.hints, buffer)?;
+        if !value.bitfield_format.is_default() {
+            self.set_byte_to_buffer(BitfieldFormat::VERSION, buffer)?;
+            end = self.encode(&(value.bitfield_format.page_size() as u32), buffer)?;
+        }
+        if !value.unknown_fields.is_empty() {
+            end = self.set_slice_to_buffer(&value.unknown_fields, buffer)?;
+        }
+        Ok(end)
     }
 
     fn decode(&mut self, buffer: &[u8]) -> Result<Header, EncodingError> {
@@ -219,7 +295,7 @@ impl CompactEncoding<Header> for State {
         if version != 1 {
             panic!("Unknown oplog version {}", version);
         }
-        let _flags: u8 = self.decode_u8(buffer)?;
+        let flags: u8 = self.decode_u8(buffer)?;
         let key: [u8; 32] = self
             .decode_fixed_32(buffer)?
             .to_vec()
@@ -232,9 +308,37 @@ impl CompactEncoding<Header> for State {
             })?;
         let manifest: Manifest = self.decode(buffer)?;
         let key_pair: PartialKeypair = self.decode(buffer)?;
-        let user_data: Vec<String> = self.decode(buffer)?;
+        let user_data_length: usize = self.decode(buffer)?;
+        let mut user_data = Vec::with_capacity(user_data_length.min(1024));
+        for _ in 0..user_data_length {
+            let key = self.decode_string(buffer)?;
+            let value = self.decode_buffer_vec(buffer)?;
+            user_data.push((key, value));
+        }
         let tree: HeaderTree = self.decode(buffer)?;
         let hints: HeaderHints = self.decode(buffer)?;
+        let bitfield_format = if flags & BITFIELD_FORMAT_FLAG != 0 {
+            let format_version: u8 = self.decode_u8(buffer)?;
+            if format_version != BitfieldFormat::VERSION {
+                return Err(EncodingError::new(
+                    EncodingErrorKind::InvalidData,
+                    &format!("Unknown bitfield format version {format_version}"),
+                ));
+            }
+            let page_size: u32 = self.decode(buffer)?;
+            BitfieldFormat::new(page_size as usize).map_err(|_| {
+                EncodingError::new(
+                    EncodingErrorKind::InvalidData,
+                    &format!("Invalid bitfield page size {page_size}"),
+                )
+            })?
+        } else {
+            BitfieldFormat::default()
+        };
+        let dirty = flags & DIRTY_FLAG != 0;
+        let unknown_flags = flags & !KNOWN_FLAGS;
+        let unknown_fields = buffer[self.start()..self.end()].to_vec();
+        self.set_start(self.end())?;
 
         Ok(Header {
             key,
@@ -243,6 +347,10 @@ impl CompactEncoding<Header> for State {
             user_data,
             tree,
             hints,
+            bitfield_format,
+            dirty,
+            unknown_flags,
+            unknown_fields,
         })
     }
 }
@@ -251,7 +359,7 @@ impl CompactEncoding<Header> for State {
 mod tests {
     use super::*;
 
-    use crate::crypto::generate_signing_key;
+    use crate::crypto::{generate_signing_key, SignatureScheme};
 
     #[test]
     fn encode_partial_key_pair() -> Result<(), EncodingError> {
@@ -317,9 +425,112 @@ mod tests {
             header_ret.manifest.signer.public_key
         );
         assert_eq!(
-            header.manifest.signer.signature,
-            header_ret.manifest.signer.signature
+            header.manifest.signer.scheme,
+            header_ret.manifest.signer.scheme
+        );
+        assert!(header_ret.bitfield_format.is_default());
+
+        // A non-default bitfield format is appended and flagged
+        let mut header = header;
+        header.bitfield_format = BitfieldFormat::new(1024).unwrap();
+        let mut enc_state = State::new();
+        enc_state.preencode(&header)?;
+        let mut format_buffer = enc_state.create_buffer();
+        enc_state.encode(&header, &mut format_buffer)?;
+        assert_eq!(format_buffer.len(), buffer.len() + 1 + 3);
+        assert_eq!(format_buffer[1], 2 | 4 | BITFIELD_FORMAT_FLAG);
+        let mut dec_state = State::from_buffer(&format_buffer);
+        let header_ret: Header = dec_state.decode(&format_buffer)?;
+        assert_eq!(header_ret.bitfield_format.page_size(), 1024);
+
+        // The signature scheme of the signer is in the manifest
+        header.manifest.signer.scheme = SignatureScheme::Schnorr;
+        let mut enc_state = State::new();
+        enc_state.preencode(&header)?;
+        let mut schnorr_buffer = enc_state.create_buffer();
+        enc_state.encode(&header, &mut schnorr_buffer)?;
+        let mut dec_state = State::from_buffer(&schnorr_buffer);
+        let header_ret: Header = dec_state.decode(&schnorr_buffer)?;
+        assert_eq!(header_ret.manifest.signer.scheme, SignatureScheme::Schnorr);
+
+        // A dirty header is only flagged, without a field
+        let mut header = Header::new(header.key_pair.clone());
+        header.dirty = true;
+        let mut enc_state = State::new();
+        enc_state.preencode(&header)?;
+        let mut dirty_buffer = enc_state.create_buffer();
+        enc_state.encode(&header, &mut dirty_buffer)?;
+        assert_eq!(dirty_buffer.len(), buffer.len());
+        assert_eq!(dirty_buffer[1], 2 | 4 | DIRTY_FLAG);
+        let mut dec_state = State::from_buffer(&dirty_buffer);
+        let header_ret: Header = dec_state.decode(&dirty_buffer)?;
+        assert!(header_ret.dirty);
+        assert_eq!(header_ret.unknown_flags, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn encode_header_round_trips_bytes() -> Result<(), EncodingError> {
+        let signing_key = generate_signing_key();
+        let mut header = Header::new(PartialKeypair {
+            public: signing_key.verifying_key(),
+            secret: Some(signing_key),
+        });
+        header.set_user_data("name", Some(b"gnostr"));
+        header.tree.fork = 2;
+        header.tree.length = 10;
+        header.hints.contiguous_length = 7;
+        let encode = |header: &Header| -> Result<Box<[u8]>, EncodingError> {
+            let mut enc_state = State::new();
+            enc_state.preencode(header)?;
+            let mut buffer = enc_state.create_buffer();
+            enc_state.encode(header, &mut buffer)?;
+            Ok(buffer)
+        };
+        let buffer = encode(&header)?;
+        let header_ret: Header = State::from_buffer(&buffer).decode(&buffer)?;
+        assert_eq!(encode(&header_ret)?, buffer);
+        assert!(header_ret.unknown_fields.is_empty());
+
+        // Fields of a newer implementation survive decoding and encoding again
+        let mut newer = buffer.to_vec();
+        newer[1] |= 64;
+        newer.extend_from_slice(&[3, 1, 2, 3]);
+        let header_ret: Header = State::from_buffer(&newer).decode(&newer)?;
+        assert_eq!(header_ret.unknown_flags, 64);
+        assert_eq!(header_ret.unknown_fields, vec![3, 1, 2, 3]);
+        assert_eq!(header_ret.tree.fork, 2);
+        assert_eq!(header_ret.user_data("name"), Some(&b"gnostr"[..]));
+        assert_eq!(&encode(&header_ret)?[..], &newer[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn encode_header_user_data() -> Result<(), EncodingError> {
+        let signing_key = generate_signing_key();
+        let mut header = Header::new(PartialKeypair {
+            public: signing_key.verifying_key(),
+            secret: None,
+        });
+        header.set_user_data("name", Some(b"gnostr"));
+        header.set_user_data("manifest", Some(&[1, 2, 3]));
+        header.set_user_data("gone", Some(b"soon"));
+        header.set_user_data("gone", None);
+        header.set_user_data("name", Some(b"gnostr-core"));
+        let mut enc_state = State::new();
+        enc_state.preencode(&header)?;
+        let mut buffer = enc_state.create_buffer();
+        enc_state.encode(&header, &mut buffer)?;
+        let mut dec_state = State::from_buffer(&buffer);
+        let header_ret: Header = dec_state.decode(&buffer)?;
+        assert_eq!(
+            header_ret.user_data,
+            vec![
+                ("manifest".to_string(), vec![1, 2, 3]),
+                ("name".to_string(), b"gnostr-core".to_vec())
+            ]
         );
+        assert_eq!(header_ret.user_data("gone"), None);
         Ok(())
     }
 }
@@ -2,9 +2,10 @@ use futures::future::Either;
 use std::convert::{TryFrom, TryInto};
 
 use crate::common::{BitfieldUpdate, Store, StoreInfo, StoreInfoInstruction};
+use crate::crypto::SignerKey;
 use crate::encoding::{CompactEncoding, HypercoreState};
 use crate::tree::MerkleTreeChangeset;
-use crate::{HypercoreError, Node, PartialKeypair};
+use crate::{BitfieldFormat, HypercoreError, Node, PartialKeypair};
 
 mod entry;
 mod header;
@@ -12,7 +13,7 @@ mod header;
 pub(crate) use entry::{Entry, EntryTreeUpgrade};
 pub(crate) use header::{Header, HeaderTree};
 
-pub(crate) const MAX_OPLOG_ENTRIES_BYTE_SIZE: u64 = 65536;
+const MAX_OPLOG_ENTRIES_BYTE_SIZE: u64 = 65536;
 const HEADER_SIZE: usize = 4096;
 
 /// Oplog.
@@ -41,6 +42,12 @@ pub(crate) struct OplogOpenOutcome {
     pub(crate) header: Header,
     pub(crate) infos_to_flush: Box<[StoreInfo]>,
     pub(crate) entries: Option<Box<[Entry]>>,
+    /// Header slot the header was read from, `None` for a fresh oplog
+    pub(crate) header_slot: Option<u8>,
+    /// Trailing entries of an unfinished atomic batch that were dropped
+    pub(crate) discarded_entries: u64,
+    /// Bytes after the last valid entry, e.g. of an entry torn by a crash
+    pub(crate) discarded_bytes: u64,
 }
 
 impl OplogOpenOutcome {
@@ -50,6 +57,9 @@ impl OplogOpenOutcome {
             header,
             infos_to_flush,
             entries: None,
+            header_slot: None,
+            discarded_entries: 0,
+            discarded_bytes: 0,
         }
     }
     pub(crate) fn from_create_header_outcome(
@@ -61,6 +71,9 @@ impl OplogOpenOutcome {
             header: create_header_outcome.header,
             infos_to_flush: create_header_outcome.infos_to_flush,
             entries: None,
+            header_slot: None,
+            discarded_entries: 0,
+            discarded_bytes: 0,
         }
     }
 }
@@ -84,9 +97,12 @@ struct ValidateLeaderOutcome {
 const INITIAL_HEADER_BITS: [bool; 2] = [true, false];
 
 impl Oplog {
-    /// Opens an existing Oplog from existing byte buffer or creates a new one.
+    /// Opens an existing Oplog from existing byte buffer or creates a new one. The bitfield
+    /// format and signer are only used when creating, existing oplogs have theirs in the header.
     pub(crate) fn open(
         key_pair: &Option<PartialKeypair>,
+        bitfield_format: BitfieldFormat,
+        signer: Option<SignerKey>,
         info: Option<StoreInfo>,
     ) -> Result<Either<StoreInfoInstruction, OplogOpenOutcome>, HypercoreError> {
         match info {
@@ -103,19 +119,19 @@ impl Oplog {
                 // Depending on what is stored, the state needs to be set accordingly.
                 // See `get_next_header_oplog_slot_and_bit_value` for details on header_bits.
                 let mut outcome: OplogOpenOutcome = if let Some(mut h1_outcome) = h1_outcome {
-                    let (header, header_bits): (Header, [bool; 2]) =
+                    let (header, header_bits, header_slot): (Header, [bool; 2], u8) =
                         if let Some(mut h2_outcome) = h2_outcome {
                             let header_bits = [h1_outcome.header_bit, h2_outcome.header_bit];
-                            let header: Header = if header_bits[0] == header_bits[1] {
-                                (*h1_outcome.state).decode(&existing)?
+                            if header_bits[0] == header_bits[1] {
+                                ((*h1_outcome.state).decode(&existing)?, header_bits, 0)
                             } else {
-                                (*h2_outcome.state).decode(&existing)?
-                            };
-                            (header, header_bits)
+                                ((*h2_outcome.state).decode(&existing)?, header_bits, 1)
+                            }
                         } else {
                             (
                                 (*h1_outcome.state).decode(&existing)?,
                                 [h1_outcome.header_bit, h1_outcome.header_bit],
+                                0,
                             )
                         };
                     let oplog = Oplog {
@@ -123,7 +139,9 @@ impl Oplog {
                         entries_length: 0,
                         entries_byte_length: 0,
                     };
-                    OplogOpenOutcome::new(oplog, header, Box::new([]))
+                    let mut outcome = OplogOpenOutcome::new(oplog, header, Box::new([]));
+                    outcome.header_slot = Some(header_slot);
+                    outcome
                 } else if let Some(mut h2_outcome) = h2_outcome {
                     // This shouldn't happen because the first header is saved to the first slot
                     // but Javascript supports this so we should too.
@@ -133,14 +151,16 @@ impl Oplog {
                         entries_length: 0,
                         entries_byte_length: 0,
                     };
-                    OplogOpenOutcome::new(
+                    let mut outcome = OplogOpenOutcome::new(
                         oplog,
                         (*h2_outcome.state).decode(&existing)?,
                         Box::new([]),
-                    )
+                    );
+                    outcome.header_slot = Some(1);
+                    outcome
                 } else if let Some(key_pair) = key_pair {
                     // There is nothing in the oplog, start from fresh given key pair.
-                    Self::fresh(key_pair.clone())?
+                    Self::fresh(key_pair.clone(), bitfield_format, signer)?
                 } else {
                     // The storage is empty and no key pair given, erroring
                     return Err(HypercoreError::EmptyStorage {
@@ -153,6 +173,7 @@ impl Oplog {
                     let mut entry_offset = OplogSlot::Entries as usize;
                     let mut entries: Vec<Entry> = Vec::new();
                     let mut partials: Vec<bool> = Vec::new();
+                    let mut entry_ends: Vec<usize> = Vec::new();
                     while let Some(mut entry_outcome) =
                         Self::validate_leader(entry_offset, &existing)?
                     {
@@ -160,12 +181,22 @@ impl Oplog {
                         entries.push(entry);
                         partials.push(entry_outcome.partial_bit);
                         entry_offset = (*entry_outcome.state).end();
+                        entry_ends.push(entry_offset);
                     }
 
                     // Remove all trailing partial entries
-                    while !partials.is_empty() && partials[partials.len() - 1] {
+                    while partials.pop() == Some(true) {
                         entries.pop();
+                        entry_ends.pop();
+                        outcome.discarded_entries += 1;
                     }
+                    outcome.discarded_bytes = (existing.len() - entry_offset) as u64;
+
+                    // New entries go after the kept ones, which stay until the next flush
+                    outcome.oplog.entries_length = entries.len() as u64;
+                    outcome.oplog.entries_byte_length = entry_ends
+                        .last()
+                        .map_or(0, |end| (end - OplogSlot::Entries as usize) as u64);
                     outcome.entries = Some(entries.into_boxed_slice());
                 }
                 Ok(Either::Right(outcome))
@@ -206,12 +237,13 @@ impl Oplog {
                 .signature
                 .expect("Upgraded changeset must be signed before appended");
             let signature: Box<[u8]> = signature.to_bytes().into();
+            header.tree.fork = changeset.fork;
             header.tree.root_hash = hash.clone();
             header.tree.signature = signature.clone();
             header.tree.length = changeset.length;
 
             Entry {
-                user_data: vec![],
+                user_data: None,
                 tree_nodes,
                 tree_upgrade: Some(EntryTreeUpgrade {
                     fork: changeset.fork,
@@ -223,7 +255,7 @@ impl Oplog {
             }
         } else {
             Entry {
-                user_data: vec![],
+                user_data: None,
                 tree_nodes,
                 tree_upgrade: None,
                 bitfield: bitfield_update,
@@ -239,7 +271,7 @@ impl Oplog {
         end: u64,
     ) -> Result<Box<[StoreInfo]>, HypercoreError> {
         let entry: Entry = Entry {
-            user_data: vec![],
+            user_data: None,
             tree_nodes: vec![],
             tree_upgrade: None,
             bitfield: Some(BitfieldUpdate {
@@ -251,6 +283,26 @@ impl Oplog {
         self.append_entries(&[entry], false)
     }
 
+    /// Sets or removes user data, returns infos to write to storage.
+    pub(crate) fn set_user_data(
+        &mut self,
+        key: &str,
+        value: Option<&[u8]>,
+    ) -> Result<Box<[StoreInfo]>, HypercoreError> {
+        let entry: Entry = Entry {
+            user_data: Some((key.to_string(), value.map(|value| value.to_vec()))),
+            tree_nodes: vec![],
+            tree_upgrade: None,
+            bitfield: None,
+        };
+        self.append_entries(&[entry], false)
+    }
+
+    /// Have the entries grown big enough that they should be flushed into the other stores.
+    pub(crate) fn should_flush(&self) -> bool {
+        self.entries_byte_length >= MAX_OPLOG_ENTRIES_BYTE_SIZE
+    }
+
     /// Flushes pending changes, returns infos to write to storage.
     pub(crate) fn flush(
         &mut self,
@@ -264,7 +316,7 @@ impl Oplog {
             let (new_header_bits, infos_to_flush) =
                 Self::insert_header(header, 0, self.header_bits, clear_traces)?;
             let mut combined_infos_to_flush: Vec<StoreInfo> =
-                infos_to_flush.into_vec().drain(0..1).into_iter().collect();
+                infos_to_flush.into_vec().drain(0..1).collect();
             let (new_header_bits, infos_to_flush) =
                 Self::insert_header(header, 0, new_header_bits, clear_traces)?;
             combined_infos_to_flush.extend(infos_to_flush.into_vec());
@@ -278,6 +330,18 @@ impl Oplog {
         Ok(infos_to_flush)
     }
 
+    /// Writes the header keeping the entries, e.g. to mark it dirty before data is written,
+    /// returns infos to write to storage.
+    pub(crate) fn write_header(
+        &mut self,
+        header: &Header,
+    ) -> Result<Box<[StoreInfo]>, HypercoreError> {
+        let (new_header_bits, infos_to_flush) =
+            Self::insert_header(header, self.entries_byte_length, self.header_bits, false)?;
+        self.header_bits = new_header_bits;
+        Ok(infos_to_flush)
+    }
+
     /// Appends a batch of entries to the Oplog.
     fn append_entries(
         &mut self,
@@ -315,10 +379,19 @@ impl Oplog {
         Ok(vec![StoreInfo::new_content(Store::Oplog, index, &buffer)].into_boxed_slice())
     }
 
-    fn fresh(key_pair: PartialKeypair) -> Result<OplogOpenOutcome, HypercoreError> {
+    fn fresh(
+        key_pair: PartialKeypair,
+        bitfield_format: BitfieldFormat,
+        signer: Option<SignerKey>,
+    ) -> Result<OplogOpenOutcome, HypercoreError> {
         let entries_length: u64 = 0;
         let entries_byte_length: u64 = 0;
-        let header = Header::new(key_pair);
+        let mut header = Header::new(key_pair);
+        header.bitfield_format = bitfield_format;
+        if let Some(signer) = signer {
+            header.manifest.signer.scheme = signer.scheme;
+            header.manifest.signer.public_key = signer.public_key;
+        }
         let (header_bits, infos_to_flush) =
             Self::insert_header(&header, entries_byte_length, INITIAL_HEADER_BITS, false)?;
         let oplog = Oplog {
@@ -0,0 +1,302 @@
+//! Primary core whose large values overflow into a companion blobs core, so that the primary
+//! feed stays light while supporting big attachments.
+//!
+//! Every value of the primary core starts with a tag byte. Values up to the threshold are
+//! stored inline after a 0 tag. Larger values are appended to the blobs core in blocks of
+//! [`BLOB_BLOCK_SIZE`] bytes, and the primary core gets a pointer after a 1 tag: the index of
+//! the first blob block, the number of blob blocks and the byte length of the value, as
+//! little-endian u64s.
+use std::ops::Range;
+
+use crate::{AppendOutcome, Hypercore, HypercoreError};
+
+/// Byte size of the blocks values are split into in the blobs core.
+pub const BLOB_BLOCK_SIZE: usize = 64 * 1024;
+
+const INLINE_TAG: u8 = 0;
+const POINTER_TAG: u8 = 1;
+const POINTER_SIZE: usize = 1 + 3 * 8;
+
+/// Location of a value in the blobs core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlobPointer {
+    /// Index of the first block in the blobs core
+    pub start: u64,
+    /// Number of blocks in the blobs core
+    pub blocks: u64,
+    /// Byte length of the value
+    pub byte_length: u64,
+}
+
+/// Pair of a primary core and its companion blobs core. Values above the threshold are moved
+/// to the blobs core on [`OverflowCore::append`] and resolved on [`OverflowCore::get`].
+#[derive(Debug)]
+pub struct OverflowCore {
+    primary: Hypercore,
+    blobs: Hypercore,
+    threshold: usize,
+}
+
+impl OverflowCore {
+    /// Pair a primary core with a blobs core, overflowing values bigger than `threshold`
+    /// bytes.
+    pub fn new(primary: Hypercore, blobs: Hypercore, threshold: usize) -> Self {
+        Self {
+            primary,
+            blobs,
+            threshold,
+        }
+    }
+
+    /// Byte length above which values overflow into the blobs core.
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// The primary core.
+    pub fn primary(&mut self) -> &mut Hypercore {
+        &mut self.primary
+    }
+
+    /// The blobs core.
+    pub fn blobs(&mut self) -> &mut Hypercore {
+        &mut self.blobs
+    }
+
+    /// Split into the primary and the blobs core.
+    pub fn into_inner(self) -> (Hypercore, Hypercore) {
+        (self.primary, self.blobs)
+    }
+
+    /// Append a value to the primary core, moving it to the blobs core if it is bigger than
+    /// the threshold. Returns the outcome of the append to the primary core.
+    pub async fn append(&mut self, value: &[u8]) -> Result<AppendOutcome, HypercoreError> {
+        if value.len() <= self.threshold {
+            let mut record = Vec::with_capacity(1 + value.len());
+            record.push(INLINE_TAG);
+            record.extend_from_slice(value);
+            return self.primary.append(&record).await;
+        }
+        let chunks: Vec<&[u8]> = value.chunks(BLOB_BLOCK_SIZE).collect();
+        let start = self.blobs.info().length;
+        self.blobs.append_batch(&chunks).await?;
+        let pointer = BlobPointer {
+            start,
+            blocks: chunks.len() as u64,
+            byte_length: value.len() as u64,
+        };
+        self.primary.append(&encode_pointer(&pointer)).await
+    }
+
+    /// Get the value at the given index of the primary core, reading it from the blobs core if
+    /// it overflowed. Returns `None` if the block or any of its blob blocks is missing; the
+    /// missing blocks are requested like with [`Hypercore::get`].
+    pub async fn get(&mut self, index: u64) -> Result<Option<Vec<u8>>, HypercoreError> {
+        let Some(record) = self.primary.get(index).await? else {
+            return Ok(None);
+        };
+        let pointer = match parse_record(&record)? {
+            Record::Inline(value) => return Ok(Some(value.to_vec())),
+            Record::Pointer(pointer) => pointer,
+        };
+        let blob_indices = self.check_pointer(index, &pointer)?;
+        let mut value = Vec::with_capacity(pointer.byte_length as usize);
+        let mut complete = true;
+        for blob_index in blob_indices {
+            // Keep going past missing blocks, so that all of them get requested
+            match self.blobs.get(blob_index).await? {
+                Some(block) if complete => value.extend_from_slice(&block),
+                Some(_) => {}
+                None => complete = false,
+            }
+        }
+        if !complete {
+            return Ok(None);
+        }
+        if value.len() as u64 != pointer.byte_length {
+            return Err(HypercoreError::InvalidOperation {
+                context: format!(
+                    "Blob of block {index} has {} bytes, expected {}",
+                    value.len(),
+                    pointer.byte_length
+                ),
+            });
+        }
+        Ok(Some(value))
+    }
+
+    /// Pointer of the value at the given index of the primary core, `None` if the block is
+    /// missing or the value is inline.
+    pub async fn blob_pointer(
+        &mut self,
+        index: u64,
+    ) -> Result<Option<BlobPointer>, HypercoreError> {
+        let Some(record) = self.primary.get(index).await? else {
+            return Ok(None);
+        };
+        match parse_record(&record)? {
+            Record::Inline(_) => Ok(None),
+            Record::Pointer(pointer) => Ok(Some(pointer)),
+        }
+    }
+
+    /// Check a pointer read from the primary core, which may come from a peer, before
+    /// allocating for or reading its value: its blocks must fit the value and lie within the
+    /// blobs core. Returns the indices of the blob blocks.
+    fn check_pointer(
+        &self,
+        index: u64,
+        pointer: &BlobPointer,
+    ) -> Result<Range<u64>, HypercoreError> {
+        let invalid = |reason: String| HypercoreError::InvalidOperation {
+            context: format!("Invalid blob pointer in block {index}, {reason}"),
+        };
+        if pointer.blocks != pointer.byte_length.div_ceil(BLOB_BLOCK_SIZE as u64) {
+            return Err(invalid(format!(
+                "{} blocks can't hold {} bytes",
+                pointer.blocks, pointer.byte_length
+            )));
+        }
+        let info = self.blobs.info();
+        let end = pointer
+            .start
+            .checked_add(pointer.blocks)
+            .filter(|end| *end <= info.length)
+            .ok_or_else(|| {
+                invalid(format!(
+                    "blocks from {} on are beyond the blobs core length {}",
+                    pointer.start, info.length
+                ))
+            })?;
+        if pointer.byte_length > info.byte_length {
+            return Err(invalid(format!(
+                "{} bytes are more than the blobs core has",
+                pointer.byte_length
+            )));
+        }
+        Ok(pointer.start..end)
+    }
+}
+
+enum Record<'a> {
+    Inline(&'a [u8]),
+    Pointer(BlobPointer),
+}
+
+fn encode_pointer(pointer: &BlobPointer) -> Vec<u8> {
+    let mut record = Vec::with_capacity(POINTER_SIZE);
+    record.push(POINTER_TAG);
+    record.extend(pointer.start.to_le_bytes());
+    record.extend(pointer.blocks.to_le_bytes());
+    record.extend(pointer.byte_length.to_le_bytes());
+    record
+}
+
+fn parse_record(record: &[u8]) -> Result<Record<'_>, HypercoreError> {
+    let read_u64 = |position: usize| {
+        u64::from_le_bytes(
+            record[position..position + 8]
+                .try_into()
+                .expect("Slice should be 8 bytes"),
+        )
+    };
+    match record.first() {
+        Some(&INLINE_TAG) => Ok(Record::Inline(&record[1..])),
+        Some(&POINTER_TAG) if record.len() == POINTER_SIZE => Ok(Record::Pointer(BlobPointer {
+            start: read_u64(1),
+            blocks: read_u64(9),
+            byte_length: read_u64(17),
+        })),
+        _ => Err(HypercoreError::InvalidOperation {
+            context: "Block is not an overflow record".to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::create_hypercore_with_data;
+
+    #[async_std::test]
+    async fn overflow_core_moves_large_values_to_blobs() -> Result<(), HypercoreError> {
+        let mut core = OverflowCore::new(
+            create_hypercore_with_data(0).await?,
+            create_hypercore_with_data(0).await?,
+            1024,
+        );
+        let attachment: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+        core.append(b"hello").await?;
+        core.append(&attachment).await?;
+        core.append(&[7; 1024]).await?;
+
+        assert_eq!(core.primary().info().length, 3);
+        assert_eq!(core.blobs().info().length, 4);
+        assert!(core.primary().info().byte_length < 1100);
+        assert_eq!(core.get(0).await?.unwrap(), b"hello");
+        assert_eq!(core.get(1).await?.unwrap(), attachment);
+        assert_eq!(core.get(2).await?.unwrap(), vec![7; 1024]);
+        assert_eq!(core.get(3).await?, None);
+        assert_eq!(
+            core.blob_pointer(1).await?,
+            Some(BlobPointer {
+                start: 0,
+                blocks: 4,
+                byte_length: 200_000
+            })
+        );
+        assert_eq!(core.blob_pointer(0).await?, None);
+
+        // A missing blob block makes the value missing
+        core.blobs().clear(2, 3).await?;
+        assert_eq!(core.get(1).await?, None);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn overflow_core_rejects_invalid_pointers() -> Result<(), HypercoreError> {
+        let mut core = OverflowCore::new(
+            create_hypercore_with_data(0).await?,
+            create_hypercore_with_data(0).await?,
+            1024,
+        );
+        core.append(&[1; 100_000]).await?;
+        let invalid = [
+            // Overflowing the block range
+            BlobPointer {
+                start: u64::MAX,
+                blocks: 1,
+                byte_length: 10,
+            },
+            // Too big to allocate
+            BlobPointer {
+                start: 0,
+                blocks: u64::MAX / BLOB_BLOCK_SIZE as u64,
+                byte_length: u64::MAX / BLOB_BLOCK_SIZE as u64 * BLOB_BLOCK_SIZE as u64,
+            },
+            // Blocks not matching the byte length
+            BlobPointer {
+                start: 0,
+                blocks: 1,
+                byte_length: 100_000,
+            },
+            // Beyond the blobs core
+            BlobPointer {
+                start: 1,
+                blocks: 2,
+                byte_length: 100_000,
+            },
+        ];
+        for pointer in &invalid {
+            core.primary().append(&encode_pointer(pointer)).await?;
+        }
+        assert_eq!(core.get(0).await?.unwrap(), vec![1; 100_000]);
+        for index in 1..=invalid.len() as u64 {
+            assert!(matches!(
+                core.get(index).await,
+                Err(HypercoreError::InvalidOperation { .. })
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+//! Projections folding the entries of a core into application state, the read model of apps
+//! built on feeds. The state is snapshotted to the user data of the core every so many
+//! entries, so a reopened projection resumes from its last snapshot instead of the first
+//! entry.
+//!
+//! A snapshot is stored under `projection/<name>` as the fork and the number of entries folded
+//! into it, both little-endian u64s, followed by the snapshot of the state. Snapshots live in
+//! the oplog header, so they should stay small.
+use crate::{Hypercore, HypercoreError};
+
+/// Byte size of the fork and length preceding a snapshot.
+const SNAPSHOT_HEADER_SIZE: usize = 16;
+
+/// Application state built from the entries of a core, see [`Projector`].
+pub trait Projection {
+    /// Fold the entry at `index` into the state. Entries are applied in order, each once.
+    fn apply(&mut self, index: u64, entry: &[u8]) -> Result<(), HypercoreError>;
+
+    /// Serialize the state.
+    fn snapshot(&self) -> Vec<u8>;
+
+    /// Replace the state with a serialized one.
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), HypercoreError>;
+}
+
+/// Keeps a [`Projection`] up to date with a core.
+#[derive(Debug)]
+pub struct Projector<P> {
+    projection: P,
+    key: String,
+    /// Entries folded into the projection
+    length: u64,
+    /// Fork of the core the entries were folded from
+    fork: u64,
+    snapshot_interval: u64,
+    snapshot_length: u64,
+}
+
+impl<P: Projection> Projector<P> {
+    /// Default number of entries folded between snapshots.
+    pub const DEFAULT_SNAPSHOT_INTERVAL: u64 = 1024;
+
+    /// Open the projection with the given name, given in its initial state. It is restored
+    /// from its last snapshot, unless the core was truncated since.
+    pub fn open(core: &Hypercore, name: &str, mut projection: P) -> Result<Self, HypercoreError> {
+        let key = format!("projection/{name}");
+        let info = core.info();
+        let mut length = 0;
+        if let Some(value) = core.get_user_data(&key) {
+            let (fork, snapshot_length, snapshot) = decode_snapshot(value)?;
+            if fork == info.fork && snapshot_length <= info.length {
+                projection.restore(snapshot)?;
+                length = snapshot_length;
+            } else {
+                tracing::debug!("Discarded snapshot of {key} from fork {fork}");
+            }
+        }
+        Ok(Self {
+            projection,
+            key,
+            length,
+            fork: info.fork,
+            snapshot_interval: Self::DEFAULT_SNAPSHOT_INTERVAL,
+            snapshot_length: length,
+        })
+    }
+
+    /// Snapshot after every `interval` entries folded instead of the default, at least 1.
+    pub fn with_snapshot_interval(mut self, interval: u64) -> Self {
+        self.snapshot_interval = interval.max(1);
+        self
+    }
+
+    /// The projection.
+    pub fn projection(&self) -> &P {
+        &self.projection
+    }
+
+    /// The projection, consuming the projector.
+    pub fn into_inner(self) -> P {
+        self.projection
+    }
+
+    /// Number of entries folded into the projection.
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// Fold the entries appended since the last update, up to the contiguous length of the
+    /// core, snapshotting every interval. Returns the number of entries folded. Fails if the
+    /// core was truncated since the projection was opened: reopen it from its initial state.
+    pub async fn update(&mut self, core: &mut Hypercore) -> Result<u64, HypercoreError> {
+        let info = core.info();
+        if info.fork != self.fork || info.length < self.length {
+            return Err(HypercoreError::InvalidOperation {
+                context: format!("Core was truncated under {}", self.key),
+            });
+        }
+        let start = self.length;
+        while self.length < info.contiguous_length {
+            let entry = core
+                .get(self.length)
+                .await?
+                .expect("Contiguous entries should be present");
+            self.projection.apply(self.length, &entry)?;
+            self.length += 1;
+            if self.length - self.snapshot_length >= self.snapshot_interval {
+                self.snapshot(core).await?;
+            }
+        }
+        Ok(self.length - start)
+    }
+
+    /// Store a snapshot of the projection now.
+    pub async fn snapshot(&mut self, core: &mut Hypercore) -> Result<(), HypercoreError> {
+        let snapshot = self.projection.snapshot();
+        let mut value = Vec::with_capacity(SNAPSHOT_HEADER_SIZE + snapshot.len());
+        value.extend(self.fork.to_le_bytes());
+        value.extend(self.length.to_le_bytes());
+        value.extend(snapshot);
+        core.set_user_data(&self.key, Some(&value)).await?;
+        self.snapshot_length = self.length;
+        Ok(())
+    }
+}
+
+fn decode_snapshot(value: &[u8]) -> Result<(u64, u64, &[u8]), HypercoreError> {
+    if value.len() < SNAPSHOT_HEADER_SIZE {
+        return Err(HypercoreError::InvalidOperation {
+            context: "Projection snapshot is too short".to_string(),
+        });
+    }
+    let fork = u64::from_le_bytes(value[..8].try_into().expect("Should be 8 bytes"));
+    let length = u64::from_le_bytes(value[8..16].try_into().expect("Should be 8 bytes"));
+    Ok((fork, length, &value[SNAPSHOT_HEADER_SIZE..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::create_hypercore_with_data;
+
+    /// Sums the numbers of the "#i" entries, counting the entries applied.
+    #[derive(Debug, Default)]
+    struct Sum {
+        sum: u64,
+        applied: u64,
+    }
+
+    impl Projection for Sum {
+        fn apply(&mut self, _index: u64, entry: &[u8]) -> Result<(), HypercoreError> {
+            self.sum += std::str::from_utf8(&entry[1..])
+                .unwrap()
+                .parse::<u64>()
+                .unwrap();
+            self.applied += 1;
+            Ok(())
+        }
+
+        fn snapshot(&self) -> Vec<u8> {
+            self.sum.to_le_bytes().to_vec()
+        }
+
+        fn restore(&mut self, snapshot: &[u8]) -> Result<(), HypercoreError> {
+            self.sum = u64::from_le_bytes(snapshot.try_into().unwrap());
+            Ok(())
+        }
+    }
+
+    #[async_std::test]
+    async fn projection_resumes_from_snapshot() -> Result<(), HypercoreError> {
+        let mut core = create_hypercore_with_data(10).await?;
+        let mut projector =
+            Projector::open(&core, "sum", Sum::default())?.with_snapshot_interval(4);
+        assert_eq!(projector.update(&mut core).await?, 10);
+        assert_eq!(projector.projection().sum, 45);
+        assert_eq!(projector.update(&mut core).await?, 0);
+
+        // Resumed from the snapshot at 8, only the entries after it are applied
+        let mut projector = Projector::open(&core, "sum", Sum::default())?;
+        assert_eq!(projector.length(), 8);
+        assert_eq!(projector.update(&mut core).await?, 2);
+        assert_eq!(projector.projection().sum, 45);
+        assert_eq!(projector.projection().applied, 2);
+        core.append(b"#10").await?;
+        projector.update(&mut core).await?;
+        projector.snapshot(&mut core).await?;
+        assert_eq!(Projector::open(&core, "sum", Sum::default())?.length(), 11);
+
+        // Snapshots of a truncated history are discarded
+        core.truncate(5, 1).await?;
+        assert!(projector.update(&mut core).await.is_err());
+        let mut projector = Projector::open(&core, "sum", Sum::default())?;
+        assert_eq!(projector.length(), 0);
+        projector.update(&mut core).await?;
+        assert_eq!(projector.projection().sum, 10);
+        Ok(())
+    }
+}
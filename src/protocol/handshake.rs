@@ -0,0 +1,656 @@
+//! Noise handshake authenticating two peers and keying the channel between them, with the
+//! `Noise_XX_Ed25519_ChaChaPoly_BLAKE2b` protocol of hypercore peers: both peers prove they own
+//! an Ed25519 key pair, and Diffie-Hellman is done on the Edwards curve.
+//!
+//! [`Handshake`] is the sans-IO state machine. [`handshake`] runs it over a transport and
+//! returns an [`EncryptedChannel`]. On the transport every message is a frame: its byte length
+//! as a little-endian u24 followed by the message, encrypted with ChaCha20-Poly1305 and a
+//! counter nonce. Javascript peers use a libsodium secretstream instead, so only peers of this
+//! crate understand each other.
+use blake2::{Blake2b512, Digest};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use curve25519_dalek::traits::IsIdentity;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use hmac::{Mac, SimpleHmac};
+use sha2::Sha512;
+use std::fmt;
+
+use crate::crypto::generate_signing_key_with;
+use crate::{HypercoreError, OsRandom, Rng, SigningKey, VerifyingKey};
+
+/// Name of the Noise protocol, the initial handshake hash
+const PROTOCOL_NAME: &[u8] = b"Noise_XX_Ed25519_ChaChaPoly_BLAKE2b";
+const HASH_LEN: usize = 64;
+const PUBLIC_KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+
+/// Maximum byte size of a message, handshake or encrypted
+pub const MAX_MESSAGE_SIZE: usize = 65535;
+
+/// Cipher of one direction of a channel: ChaCha20-Poly1305 with a counter nonce.
+pub struct CipherState {
+    cipher: ChaCha20Poly1305,
+    nonce: u64,
+}
+
+impl fmt::Debug for CipherState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CipherState")
+            .field("nonce", &self.nonce)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CipherState {
+    fn new(key: &[u8]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new_from_slice(&key[..32]).expect("Key should be 32 bytes"),
+            nonce: 0,
+        }
+    }
+
+    /// Number of messages encrypted or decrypted so far.
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    /// Encrypt a message, authenticating the associated data with it.
+    pub fn encrypt(&mut self, ad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, HypercoreError> {
+        let nonce = self.next_nonce()?;
+        self.cipher
+            .encrypt(
+                &nonce.into(),
+                Payload {
+                    msg: plaintext,
+                    aad: ad,
+                },
+            )
+            .map_err(|_| HypercoreError::InvalidOperation {
+                context: "Could not encrypt message".to_string(),
+            })
+    }
+
+    /// Decrypt a message encrypted with the same associated data. Fails if the message was
+    /// tampered with, or if it isn't the next message of the other side.
+    pub fn decrypt(&mut self, ad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, HypercoreError> {
+        let nonce = self.next_nonce()?;
+        self.cipher
+            .decrypt(
+                &nonce.into(),
+                Payload {
+                    msg: ciphertext,
+                    aad: ad,
+                },
+            )
+            .map_err(|_| HypercoreError::InvalidSignature {
+                context: "Message failed authentication".to_string(),
+            })
+    }
+
+    fn next_nonce(&mut self) -> Result<[u8; 12], HypercoreError> {
+        // The last nonce is reserved by Noise
+        if self.nonce == u64::MAX {
+            return Err(HypercoreError::LimitExceeded {
+                context: "Cipher nonces exhausted".to_string(),
+            });
+        }
+        let mut nonce = [0; 12];
+        nonce[4..].copy_from_slice(&self.nonce.to_le_bytes());
+        self.nonce += 1;
+        Ok(nonce)
+    }
+}
+
+/// Chaining key and handshake hash, and the cipher keyed from them.
+struct SymmetricState {
+    chaining_key: [u8; HASH_LEN],
+    hash: [u8; HASH_LEN],
+    cipher: Option<CipherState>,
+}
+
+impl SymmetricState {
+    fn new() -> Self {
+        let mut hash = [0; HASH_LEN];
+        hash[..PROTOCOL_NAME.len()].copy_from_slice(PROTOCOL_NAME);
+        let mut state = Self {
+            chaining_key: hash,
+            hash,
+            cipher: None,
+        };
+        // Empty prologue
+        state.mix_hash(&[]);
+        state
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        self.hash = Blake2b512::new()
+            .chain_update(self.hash)
+            .chain_update(data)
+            .finalize()
+            .into();
+    }
+
+    fn mix_key(&mut self, input: &[u8]) {
+        let (chaining_key, key) = hkdf(&self.chaining_key, input);
+        self.chaining_key = chaining_key;
+        self.cipher = Some(CipherState::new(&key));
+    }
+
+    fn encrypt_and_hash(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, HypercoreError> {
+        let ciphertext = match self.cipher.as_mut() {
+            Some(cipher) => cipher.encrypt(&self.hash, plaintext)?,
+            None => plaintext.to_vec(),
+        };
+        self.mix_hash(&ciphertext);
+        Ok(ciphertext)
+    }
+
+    fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, HypercoreError> {
+        let plaintext = match self.cipher.as_mut() {
+            Some(cipher) => cipher.decrypt(&self.hash, ciphertext)?,
+            None => ciphertext.to_vec(),
+        };
+        self.mix_hash(ciphertext);
+        Ok(plaintext)
+    }
+
+    fn split(&self) -> (CipherState, CipherState) {
+        let (initiator, responder) = hkdf(&self.chaining_key, &[]);
+        (CipherState::new(&initiator), CipherState::new(&responder))
+    }
+}
+
+/// Outcome of a finished [`Handshake`].
+#[derive(Debug)]
+pub struct HandshakeResult {
+    /// This peer initiated the handshake
+    pub is_initiator: bool,
+    /// Static public key of the remote peer
+    pub remote_public_key: VerifyingKey,
+    /// Hash of the whole handshake, the same for both peers, to bind other messages to the
+    /// channel
+    pub handshake_hash: [u8; HASH_LEN],
+    /// Cipher of the messages sent
+    pub send: CipherState,
+    /// Cipher of the messages received
+    pub receive: CipherState,
+}
+
+/// Noise XX handshake of one peer. The initiator writes the first and third message, the
+/// responder the second, and each message can carry a payload. A handshake that failed can't
+/// be resumed.
+pub struct Handshake {
+    is_initiator: bool,
+    symmetric: SymmetricState,
+    static_key: SigningKey,
+    ephemeral_key: SigningKey,
+    remote_static_key: Option<[u8; PUBLIC_KEY_LEN]>,
+    remote_ephemeral_key: Option<[u8; PUBLIC_KEY_LEN]>,
+    /// Messages written and read so far
+    step: usize,
+}
+
+impl fmt::Debug for Handshake {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handshake")
+            .field("is_initiator", &self.is_initiator)
+            .field("step", &self.step)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Handshake {
+    /// Start a handshake authenticating this peer with the given static key pair.
+    pub fn new(is_initiator: bool, static_key: SigningKey) -> Self {
+        Self::new_with(is_initiator, static_key, &OsRandom)
+    }
+
+    /// Start a handshake authenticating this peer with the given static key pair, with the
+    /// ephemeral key drawn from `rng`.
+    pub fn new_with(is_initiator: bool, static_key: SigningKey, rng: &dyn Rng) -> Self {
+        Self {
+            is_initiator,
+            symmetric: SymmetricState::new(),
+            static_key,
+            ephemeral_key: generate_signing_key_with(rng),
+            remote_static_key: None,
+            remote_ephemeral_key: None,
+            step: 0,
+        }
+    }
+
+    /// Is this the initiator of the handshake.
+    pub fn is_initiator(&self) -> bool {
+        self.is_initiator
+    }
+
+    /// Is it this peer's turn to write a message.
+    pub fn is_write_turn(&self) -> bool {
+        !self.is_finished() && self.step.is_multiple_of(2) == self.is_initiator
+    }
+
+    /// Have all three messages been exchanged.
+    pub fn is_finished(&self) -> bool {
+        self.step == 3
+    }
+
+    /// Write the next message of the handshake, carrying the payload, which is encrypted
+    /// except in the first message.
+    pub fn write_message(&mut self, payload: &[u8]) -> Result<Vec<u8>, HypercoreError> {
+        if !self.is_write_turn() {
+            return Err(HypercoreError::InvalidOperation {
+                context: "Not this peer's turn to write a handshake message".to_string(),
+            });
+        }
+        let mut message = vec![];
+        match self.step {
+            0 => {
+                // -> e
+                let ephemeral = self.ephemeral_key.verifying_key().to_bytes();
+                self.symmetric.mix_hash(&ephemeral);
+                message.extend_from_slice(&ephemeral);
+            }
+            1 => {
+                // <- e, ee, s, es
+                let ephemeral = self.ephemeral_key.verifying_key().to_bytes();
+                self.symmetric.mix_hash(&ephemeral);
+                message.extend_from_slice(&ephemeral);
+                let remote_ephemeral = self.remote_ephemeral()?;
+                self.symmetric
+                    .mix_key(&dh(&self.ephemeral_key, &remote_ephemeral)?);
+                let public_key = self.static_key.verifying_key().to_bytes();
+                message.extend(self.symmetric.encrypt_and_hash(&public_key)?);
+                self.symmetric
+                    .mix_key(&dh(&self.static_key, &remote_ephemeral)?);
+            }
+            _ => {
+                // -> s, se
+                let public_key = self.static_key.verifying_key().to_bytes();
+                message.extend(self.symmetric.encrypt_and_hash(&public_key)?);
+                self.symmetric
+                    .mix_key(&dh(&self.static_key, &self.remote_ephemeral()?)?);
+            }
+        }
+        message.extend(self.symmetric.encrypt_and_hash(payload)?);
+        if message.len() > MAX_MESSAGE_SIZE {
+            return Err(HypercoreError::BadArgument {
+                context: format!("Handshake payload of {} bytes is too big", payload.len()),
+            });
+        }
+        self.step += 1;
+        Ok(message)
+    }
+
+    /// Read the next message of the handshake, returning its payload.
+    pub fn read_message(&mut self, message: &[u8]) -> Result<Vec<u8>, HypercoreError> {
+        if self.is_finished() || self.is_write_turn() {
+            return Err(HypercoreError::InvalidOperation {
+                context: "Not this peer's turn to read a handshake message".to_string(),
+            });
+        }
+        let mut rest = message;
+        match self.step {
+            0 => {
+                // -> e
+                let remote_ephemeral = take_public_key(&mut rest)?;
+                self.symmetric.mix_hash(&remote_ephemeral);
+                self.remote_ephemeral_key = Some(remote_ephemeral);
+            }
+            1 => {
+                // <- e, ee, s, es
+                let remote_ephemeral = take_public_key(&mut rest)?;
+                self.symmetric.mix_hash(&remote_ephemeral);
+                self.remote_ephemeral_key = Some(remote_ephemeral);
+                self.symmetric
+                    .mix_key(&dh(&self.ephemeral_key, &remote_ephemeral)?);
+                let remote_static = self.read_static_key(&mut rest)?;
+                self.symmetric
+                    .mix_key(&dh(&self.ephemeral_key, &remote_static)?);
+            }
+            _ => {
+                // -> s, se
+                let remote_static = self.read_static_key(&mut rest)?;
+                self.symmetric
+                    .mix_key(&dh(&self.ephemeral_key, &remote_static)?);
+            }
+        }
+        let payload = self.symmetric.decrypt_and_hash(rest)?;
+        self.step += 1;
+        Ok(payload)
+    }
+
+    /// Hash of the handshake so far.
+    pub fn handshake_hash(&self) -> &[u8; HASH_LEN] {
+        &self.symmetric.hash
+    }
+
+    /// Finish the handshake, returning the authenticated remote key and the ciphers of the
+    /// channel.
+    pub fn finish(self) -> Result<HandshakeResult, HypercoreError> {
+        if !self.is_finished() {
+            return Err(HypercoreError::InvalidOperation {
+                context: "Handshake is not finished".to_string(),
+            });
+        }
+        let remote_static_key = self
+            .remote_static_key
+            .expect("Finished handshake should have the remote static key");
+        let remote_public_key = VerifyingKey::from_bytes(&remote_static_key).map_err(|err| {
+            HypercoreError::InvalidSignature {
+                context: format!("Invalid remote public key: {err}"),
+            }
+        })?;
+        let (initiator, responder) = self.symmetric.split();
+        let (send, receive) = if self.is_initiator {
+            (initiator, responder)
+        } else {
+            (responder, initiator)
+        };
+        Ok(HandshakeResult {
+            is_initiator: self.is_initiator,
+            remote_public_key,
+            handshake_hash: self.symmetric.hash,
+            send,
+            receive,
+        })
+    }
+
+    fn remote_ephemeral(&self) -> Result<[u8; PUBLIC_KEY_LEN], HypercoreError> {
+        self.remote_ephemeral_key
+            .ok_or_else(|| HypercoreError::InvalidOperation {
+                context: "Remote ephemeral key not received".to_string(),
+            })
+    }
+
+    fn read_static_key(
+        &mut self,
+        rest: &mut &[u8],
+    ) -> Result<[u8; PUBLIC_KEY_LEN], HypercoreError> {
+        let remote_static: [u8; PUBLIC_KEY_LEN] = self
+            .symmetric
+            .decrypt_and_hash(take(rest, PUBLIC_KEY_LEN + TAG_LEN)?)?
+            .try_into()
+            .expect("Decrypted key should be 32 bytes");
+        self.remote_static_key = Some(remote_static);
+        Ok(remote_static)
+    }
+}
+
+/// Channel encrypted with the keys of a finished handshake. Messages are authenticated and
+/// must be received in the order they were sent.
+#[derive(Debug)]
+pub struct EncryptedChannel<T> {
+    io: T,
+    send: CipherState,
+    receive: CipherState,
+    is_initiator: bool,
+    remote_public_key: VerifyingKey,
+    handshake_hash: [u8; HASH_LEN],
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> EncryptedChannel<T> {
+    /// Channel over the transport, with the ciphers of the handshake.
+    pub fn new(io: T, handshake: HandshakeResult) -> Self {
+        Self {
+            io,
+            send: handshake.send,
+            receive: handshake.receive,
+            is_initiator: handshake.is_initiator,
+            remote_public_key: handshake.remote_public_key,
+            handshake_hash: handshake.handshake_hash,
+        }
+    }
+
+    /// Did this peer initiate the handshake.
+    pub fn is_initiator(&self) -> bool {
+        self.is_initiator
+    }
+
+    /// Authenticated static public key of the remote peer.
+    pub fn remote_public_key(&self) -> &VerifyingKey {
+        &self.remote_public_key
+    }
+
+    /// Hash of the handshake, see [`HandshakeResult::handshake_hash`].
+    pub fn handshake_hash(&self) -> &[u8; HASH_LEN] {
+        &self.handshake_hash
+    }
+
+    /// Encrypt and send a message of at most [`MAX_MESSAGE_SIZE`] minus 16 bytes.
+    pub async fn send(&mut self, message: &[u8]) -> Result<(), HypercoreError> {
+        if message.len() + TAG_LEN > MAX_MESSAGE_SIZE {
+            return Err(HypercoreError::BadArgument {
+                context: format!("Message of {} bytes is too big", message.len()),
+            });
+        }
+        let ciphertext = self.send.encrypt(&[], message)?;
+        write_frame(&mut self.io, &ciphertext).await
+    }
+
+    /// Receive and decrypt the next message.
+    pub async fn receive(&mut self) -> Result<Vec<u8>, HypercoreError> {
+        let ciphertext = read_frame(&mut self.io).await?;
+        self.receive.decrypt(&[], &ciphertext)
+    }
+
+    /// The underlying transport.
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+}
+
+/// Run the handshake over the transport, with empty payloads, and open an encrypted channel
+/// on it.
+pub async fn handshake<T: AsyncRead + AsyncWrite + Unpin>(
+    mut io: T,
+    is_initiator: bool,
+    static_key: &SigningKey,
+) -> Result<EncryptedChannel<T>, HypercoreError> {
+    let mut handshake = Handshake::new(is_initiator, static_key.clone());
+    while !handshake.is_finished() {
+        if handshake.is_write_turn() {
+            let message = handshake.write_message(&[])?;
+            write_frame(&mut io, &message).await?;
+        } else {
+            let message = read_frame(&mut io).await?;
+            handshake.read_message(&message)?;
+        }
+    }
+    Ok(EncryptedChannel::new(io, handshake.finish()?))
+}
+
+async fn write_frame<T: AsyncWrite + Unpin>(
+    io: &mut T,
+    frame: &[u8],
+) -> Result<(), HypercoreError> {
+    let length = (frame.len() as u32).to_le_bytes();
+    io.write_all(&length[..3]).await?;
+    io.write_all(frame).await?;
+    io.flush().await?;
+    Ok(())
+}
+
+async fn read_frame<T: AsyncRead + Unpin>(io: &mut T) -> Result<Vec<u8>, HypercoreError> {
+    let mut length = [0; 4];
+    io.read_exact(&mut length[..3]).await?;
+    let length = u32::from_le_bytes(length) as usize;
+    if length > MAX_MESSAGE_SIZE {
+        return Err(HypercoreError::LimitExceeded {
+            context: format!("Frame of {length} bytes exceeds {MAX_MESSAGE_SIZE}"),
+        });
+    }
+    let mut frame = vec![0; length];
+    io.read_exact(&mut frame).await?;
+    Ok(frame)
+}
+
+/// Diffie-Hellman on the Edwards curve, as libsodium's `crypto_scalarmult_ed25519` with the
+/// scalar of the Ed25519 secret key: the remote key must be a canonical point of the prime
+/// order subgroup.
+fn dh(
+    secret_key: &SigningKey,
+    public_key: &[u8; PUBLIC_KEY_LEN],
+) -> Result<[u8; PUBLIC_KEY_LEN], HypercoreError> {
+    let invalid = || HypercoreError::InvalidSignature {
+        context: "Invalid remote handshake key".to_string(),
+    };
+    let point = CompressedEdwardsY(*public_key)
+        .decompress()
+        .filter(|point| {
+            point.compress().as_bytes() == public_key
+                && !point.is_small_order()
+                && point.is_torsion_free()
+        })
+        .ok_or_else(invalid)?;
+    let scalar: [u8; 32] = Sha512::digest(secret_key.to_bytes())[..32]
+        .try_into()
+        .expect("SHA-512 should be 64 bytes");
+    let shared = point.mul_clamped(scalar);
+    if shared.is_identity() {
+        return Err(invalid());
+    }
+    Ok(shared.compress().to_bytes())
+}
+
+/// HKDF of Noise with two outputs, on HMAC-BLAKE2b.
+fn hkdf(chaining_key: &[u8], input: &[u8]) -> ([u8; HASH_LEN], [u8; HASH_LEN]) {
+    let temp_key = hmac(chaining_key, &[input]);
+    let first = hmac(&temp_key, &[&[1]]);
+    let second = hmac(&temp_key, &[&first, &[2]]);
+    (first, second)
+}
+
+fn hmac(key: &[u8], data: &[&[u8]]) -> [u8; HASH_LEN] {
+    let mut mac = <SimpleHmac<Blake2b512> as Mac>::new_from_slice(key)
+        .expect("HMAC should take keys of any size");
+    for data in data {
+        mac.update(data);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+fn take<'a>(rest: &mut &'a [u8], length: usize) -> Result<&'a [u8], HypercoreError> {
+    if rest.len() < length {
+        return Err(HypercoreError::InvalidOperation {
+            context: "Handshake message is too short".to_string(),
+        });
+    }
+    let (taken, remaining) = rest.split_at(length);
+    *rest = remaining;
+    Ok(taken)
+}
+
+fn take_public_key(rest: &mut &[u8]) -> Result<[u8; PUBLIC_KEY_LEN], HypercoreError> {
+    Ok(take(rest, PUBLIC_KEY_LEN)?
+        .try_into()
+        .expect("Taken key should be 32 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::generate_signing_key;
+    use crate::SeededRng;
+
+    fn exchange(
+        initiator: &mut Handshake,
+        responder: &mut Handshake,
+    ) -> Result<Vec<Vec<u8>>, HypercoreError> {
+        Ok(vec![
+            responder.read_message(&initiator.write_message(b"one")?)?,
+            initiator.read_message(&responder.write_message(b"two")?)?,
+            responder.read_message(&initiator.write_message(b"three")?)?,
+        ])
+    }
+
+    #[test]
+    fn handshake_authenticates_both_peers() -> Result<(), HypercoreError> {
+        let initiator_key = generate_signing_key();
+        let responder_key = generate_signing_key();
+        let mut initiator = Handshake::new(true, initiator_key.clone());
+        let mut responder = Handshake::new(false, responder_key.clone());
+        assert!(responder.write_message(&[]).is_err());
+        assert!(initiator.read_message(&[0; 32]).is_err());
+
+        assert_eq!(
+            exchange(&mut initiator, &mut responder)?,
+            [b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+        );
+        assert!(initiator.is_finished() && responder.is_finished());
+        let mut initiator = initiator.finish()?;
+        let mut responder = responder.finish()?;
+        assert_eq!(initiator.remote_public_key, responder_key.verifying_key());
+        assert_eq!(responder.remote_public_key, initiator_key.verifying_key());
+        assert_eq!(initiator.handshake_hash, responder.handshake_hash);
+
+        let ciphertext = initiator.send.encrypt(&[], b"hello")?;
+        assert_eq!(responder.receive.decrypt(&[], &ciphertext)?, b"hello");
+        let ciphertext = responder.send.encrypt(&[], b"world")?;
+        assert_eq!(initiator.receive.decrypt(&[], &ciphertext)?, b"world");
+        // Replayed messages are rejected
+        assert!(initiator.receive.decrypt(&[], &ciphertext).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn handshake_rejects_tampered_messages() -> Result<(), HypercoreError> {
+        let mut initiator = Handshake::new(true, generate_signing_key());
+        let mut responder = Handshake::new(false, generate_signing_key());
+        responder.read_message(&initiator.write_message(&[])?)?;
+        let mut message = responder.write_message(b"secret")?;
+        assert!(!message.windows(6).any(|window| window == b"secret"));
+        message[40] ^= 1;
+        assert!(initiator.read_message(&message).is_err());
+
+        // Ephemeral keys of small order are refused
+        let mut responder = Handshake::new(false, generate_signing_key());
+        let mut identity = [0; PUBLIC_KEY_LEN];
+        identity[0] = 1;
+        responder.read_message(&identity)?;
+        assert!(responder.write_message(&[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn handshake_is_reproducible_with_seeded_rng() -> Result<(), HypercoreError> {
+        let initiator_key = SigningKey::from_bytes(&[1; 32]);
+        let responder_key = SigningKey::from_bytes(&[2; 32]);
+        let run = || -> Result<Vec<Vec<u8>>, HypercoreError> {
+            let mut initiator =
+                Handshake::new_with(true, initiator_key.clone(), &SeededRng::new(1));
+            let mut responder =
+                Handshake::new_with(false, responder_key.clone(), &SeededRng::new(2));
+            let first = initiator.write_message(b"one")?;
+            responder.read_message(&first)?;
+            let second = responder.write_message(b"two")?;
+            initiator.read_message(&second)?;
+            Ok(vec![first, second, initiator.write_message(b"three")?])
+        };
+        assert_eq!(run()?, run()?);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn handshake_opens_encrypted_channel() -> Result<(), HypercoreError> {
+        let (a, b) = async_std::os::unix::net::UnixStream::pair()?;
+        let a_key = generate_signing_key();
+        let b_key = generate_signing_key();
+        let (a, b) = futures::try_join!(handshake(a, true, &a_key), handshake(b, false, &b_key))?;
+        let (mut a, mut b) = (a, b);
+        assert_eq!(a.remote_public_key(), &b_key.verifying_key());
+        assert_eq!(b.remote_public_key(), &a_key.verifying_key());
+        assert_eq!(a.handshake_hash(), b.handshake_hash());
+
+        a.send(b"ping").await?;
+        a.send(&[]).await?;
+        assert_eq!(b.receive().await?, b"ping");
+        assert!(b.receive().await?.is_empty());
+        b.send(b"pong").await?;
+        assert_eq!(a.receive().await?, b"pong");
+        assert!(a.send(&vec![0; MAX_MESSAGE_SIZE]).await.is_err());
+        Ok(())
+    }
+}
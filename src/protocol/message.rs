@@ -0,0 +1,393 @@
+//! Messages of a replication channel, compact encoded as in hypercore 10. Their encodings are
+//! in [`crate::encoding`], on [`HypercoreState`].
+use crate::encoding::{CompactEncoding, EncodingError, HypercoreState};
+use crate::protocol::mux::Priority;
+use crate::{
+    DataBlock, DataHash, DataSeek, DataUpgrade, Proof, RequestBlock, RequestSeek, RequestUpgrade,
+};
+
+/// Handshake of the replication channel of a core, sent when opening it, see
+/// [`crate::protocol::Mux::open_core`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoreHandshake {
+    /// The sender answers seek requests
+    pub seeks: bool,
+    /// Proof the sender knows the public key of the core, see
+    /// [`crate::replication_capability`]
+    pub capability: [u8; 32],
+}
+
+impl CoreHandshake {
+    /// Encode the handshake.
+    pub fn encode(&self) -> Result<Vec<u8>, EncodingError> {
+        let mut state = HypercoreState::new();
+        state.preencode(self)?;
+        let mut buffer = state.create_buffer();
+        state.encode(self, &mut buffer)?;
+        Ok(buffer.to_vec())
+    }
+
+    /// Decode a handshake.
+    pub fn decode(buffer: &[u8]) -> Result<Self, EncodingError> {
+        HypercoreState::from_buffer(buffer).decode(buffer)
+    }
+}
+
+/// State of the core of the sender, sent on open and whenever it changes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Synchronize {
+    /// Fork of the core
+    pub fork: u64,
+    /// Length of the core
+    pub length: u64,
+    /// Length of the core of the receiver, as last seen by the sender
+    pub remote_length: u64,
+    /// The sender wants data
+    pub downloading: bool,
+    /// The sender serves data
+    pub uploading: bool,
+    /// The sender can upgrade the receiver to its length
+    pub can_upgrade: bool,
+}
+
+/// Request for a proof, answered with [`Data`] or [`NoData`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    /// Id of the request, echoed in the answer
+    pub id: u64,
+    /// Fork the request is for
+    pub fork: u64,
+    /// Block and the nodes of its proof
+    pub block: Option<RequestBlock>,
+    /// Hash of a tree node and the nodes of its proof
+    pub hash: Option<RequestBlock>,
+    /// Block containing a byte offset
+    pub seek: Option<RequestSeek>,
+    /// Upgrade of the length
+    pub upgrade: Option<RequestUpgrade>,
+}
+
+/// Cancels a [`Request`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancel {
+    /// Id of the request
+    pub request: u64,
+}
+
+/// Proof answering a [`Request`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Data {
+    /// Id of the request
+    pub request: u64,
+    /// Fork of the proof
+    pub fork: u64,
+    /// Block and its proof
+    pub block: Option<DataBlock>,
+    /// Tree node hash and its proof
+    pub hash: Option<DataHash>,
+    /// Proof of a seek
+    pub seek: Option<DataSeek>,
+    /// Proof of an upgrade
+    pub upgrade: Option<DataUpgrade>,
+}
+
+impl Data {
+    /// Answer to the request with the given id, e.g. with a proof of
+    /// [`crate::Hypercore::create_proof`].
+    pub fn new(request: u64, proof: Proof) -> Self {
+        Self {
+            request,
+            fork: proof.fork,
+            block: proof.block,
+            hash: proof.hash,
+            seek: proof.seek,
+            upgrade: proof.upgrade,
+        }
+    }
+
+    /// Proof to verify, e.g. with [`crate::Hypercore::verify_and_apply_proof`].
+    pub fn into_proof(self) -> Proof {
+        Proof {
+            fork: self.fork,
+            block: self.block,
+            hash: self.hash,
+            seek: self.seek,
+            upgrade: self.upgrade,
+        }
+    }
+}
+
+/// The [`Request`] can't be answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoData {
+    /// Id of the request
+    pub request: u64,
+}
+
+/// The sender wants to be told about the blocks in the range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Want {
+    /// First block of the range
+    pub start: u64,
+    /// Number of blocks
+    pub length: u64,
+}
+
+/// Revokes a [`Want`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unwant {
+    /// First block of the range
+    pub start: u64,
+    /// Number of blocks
+    pub length: u64,
+}
+
+/// Blocks the sender has, as a bitfield starting at a block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitfield {
+    /// First block covered
+    pub start: u64,
+    /// Have-bits, 32 blocks per word, least significant bit first
+    pub bitfield: Vec<u32>,
+}
+
+/// The sender now has, or dropped, the blocks in the range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    /// The blocks were dropped
+    pub drop: bool,
+    /// First block of the range
+    pub start: u64,
+    /// Number of blocks
+    pub length: u64,
+}
+
+/// Message of a protocol extension
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    /// Name of the extension
+    pub name: String,
+    /// Message, taking the rest of the frame
+    pub message: Vec<u8>,
+}
+
+/// Any message of a replication channel. Encoded as its type followed by the message, which is
+/// what follows the channel id in a frame.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    /// See [`Synchronize`]
+    Synchronize(Synchronize),
+    /// See [`Request`]
+    Request(Request),
+    /// See [`Cancel`]
+    Cancel(Cancel),
+    /// See [`Data`]
+    Data(Data),
+    /// See [`NoData`]
+    NoData(NoData),
+    /// See [`Want`]
+    Want(Want),
+    /// See [`Unwant`]
+    Unwant(Unwant),
+    /// See [`Bitfield`]
+    Bitfield(Bitfield),
+    /// See [`Range`]
+    Range(Range),
+    /// See [`Extension`]
+    Extension(Extension),
+}
+
+impl Message {
+    /// Type of the message on the wire.
+    pub fn type_id(&self) -> u64 {
+        match self {
+            Message::Synchronize(_) => 0,
+            Message::Request(_) => 1,
+            Message::Cancel(_) => 2,
+            Message::Data(_) => 3,
+            Message::NoData(_) => 4,
+            Message::Want(_) => 5,
+            Message::Unwant(_) => 6,
+            Message::Bitfield(_) => 7,
+            Message::Range(_) => 8,
+            Message::Extension(_) => 9,
+        }
+    }
+
+    /// Priority to queue the message with: block data goes last, so it doesn't delay the
+    /// messages of other channels.
+    pub fn priority(&self) -> Priority {
+        match self {
+            Message::Data(data) if data.block.is_some() => Priority::Bulk,
+            Message::Data(_) => Priority::Proof,
+            _ => Priority::Control,
+        }
+    }
+
+    /// Encode the message without its type, as sent in a [`crate::protocol::MuxFrame`].
+    pub fn encode_body(&self) -> Result<Vec<u8>, EncodingError> {
+        let mut state = HypercoreState::new();
+        state.preencode_message_body(self)?;
+        let mut buffer = state.create_buffer();
+        state.encode_message_body(self, &mut buffer)?;
+        Ok(buffer.to_vec())
+    }
+
+    /// Decode a message of the given type from its body.
+    pub fn decode_body(type_id: u64, body: &[u8]) -> Result<Self, EncodingError> {
+        HypercoreState::from_buffer(body).decode_message_body(type_id, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Node;
+
+    fn encode(message: &Message) -> Result<Vec<u8>, EncodingError> {
+        let mut state = HypercoreState::new();
+        state.preencode(message)?;
+        let mut buffer = state.create_buffer();
+        state.encode(message, &mut buffer)?;
+        Ok(buffer.to_vec())
+    }
+
+    fn decode(buffer: &[u8]) -> Result<Message, EncodingError> {
+        HypercoreState::from_buffer(buffer).decode(buffer)
+    }
+
+    #[test]
+    fn messages_round_trip() -> Result<(), EncodingError> {
+        let node = Node::new(2, vec![7; 32], 3);
+        let messages = vec![
+            Message::Synchronize(Synchronize {
+                fork: 1,
+                length: 300,
+                remote_length: 2,
+                downloading: true,
+                uploading: false,
+                can_upgrade: true,
+            }),
+            Message::Request(Request {
+                id: 4,
+                fork: 1,
+                block: Some(RequestBlock { index: 5, nodes: 2 }),
+                hash: None,
+                seek: Some(RequestSeek { bytes: 1000 }),
+                upgrade: Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            }),
+            Message::Cancel(Cancel { request: 4 }),
+            Message::Data(Data {
+                request: 4,
+                fork: 1,
+                block: Some(DataBlock {
+                    index: 5,
+                    value: b"#5".to_vec(),
+                    nodes: vec![node.clone()],
+                }),
+                hash: Some(DataHash {
+                    index: 6,
+                    nodes: vec![],
+                }),
+                seek: None,
+                upgrade: Some(DataUpgrade {
+                    start: 0,
+                    length: 10,
+                    nodes: vec![node.clone()],
+                    additional_nodes: vec![node],
+                    signature: vec![9; 64],
+                }),
+            }),
+            Message::NoData(NoData { request: 4 }),
+            Message::Want(Want {
+                start: 0,
+                length: 1024,
+            }),
+            Message::Unwant(Unwant {
+                start: 0,
+                length: 1024,
+            }),
+            Message::Bitfield(Bitfield {
+                start: 64,
+                bitfield: vec![0xffff_ffff, 1],
+            }),
+            Message::Range(Range {
+                drop: false,
+                start: 3,
+                length: 1,
+            }),
+            Message::Range(Range {
+                drop: true,
+                start: 3,
+                length: 7,
+            }),
+            Message::Extension(Extension {
+                name: "nostr".to_string(),
+                message: b"hello".to_vec(),
+            }),
+            Message::Extension(Extension {
+                name: "nostr".to_string(),
+                message: vec![],
+            }),
+        ];
+        for message in messages {
+            let encoded = encode(&message)?;
+            assert_eq!(decode(&encoded)?, message);
+            assert_eq!(
+                Message::decode_body(message.type_id(), &encoded[1..])?,
+                message
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn messages_match_wire_format() -> Result<(), EncodingError> {
+        let sync = Message::Synchronize(Synchronize {
+            fork: 0,
+            length: 300,
+            remote_length: 5,
+            downloading: true,
+            uploading: true,
+            can_upgrade: false,
+        });
+        assert_eq!(encode(&sync)?, [0, 6, 0, 0xfd, 0x2c, 0x01, 5]);
+
+        let range = Message::Range(Range {
+            drop: false,
+            start: 9,
+            length: 1,
+        });
+        assert_eq!(encode(&range)?, [8, 2, 9]);
+
+        let bitfield = Message::Bitfield(Bitfield {
+            start: 0,
+            bitfield: vec![1],
+        });
+        assert_eq!(encode(&bitfield)?, [7, 0, 1, 1, 0, 0, 0]);
+
+        let extension = Message::Extension(Extension {
+            name: "a".to_string(),
+            message: vec![1, 2],
+        });
+        assert_eq!(encode(&extension)?, [9, 1, b'a', 1, 2]);
+
+        let handshake = CoreHandshake {
+            seeks: true,
+            capability: [3; 32],
+        };
+        let encoded = handshake.encode()?;
+        assert_eq!(encoded[..2], [1, 3]);
+        assert_eq!(encoded.len(), 33);
+        assert_eq!(CoreHandshake::decode(&encoded)?, handshake);
+        assert!(CoreHandshake::decode(&encoded[..32]).is_err());
+
+        // Unknown types and hostile bitfield lengths are rejected
+        assert!(decode(&[10]).is_err());
+        assert!(decode(&[7, 0, 0xfe, 0xff, 0xff, 0xff, 0xff]).is_err());
+        Ok(())
+    }
+}
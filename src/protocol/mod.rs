@@ -0,0 +1,16 @@
+//! Hypercore replication wire protocol. The messages and the multiplexing are modelled on the
+//! Javascript implementation, but the wire is only spoken by this crate: the handshake keys a
+//! ChaCha20-Poly1305 channel with counter nonces, not the libsodium secretstream of Javascript
+//! peers, so they can't connect to each other.
+#[cfg(feature = "replication")]
+pub mod handshake;
+pub mod message;
+pub mod mux;
+
+#[cfg(feature = "replication")]
+pub use handshake::{handshake, EncryptedChannel, Handshake, HandshakeResult};
+pub use message::{
+    Bitfield, Cancel, CoreHandshake, Data, Extension, Message, NoData, Range, Request, Synchronize,
+    Unwant, Want,
+};
+pub use mux::{Mux, MuxEvent, MuxFrame, Priority, HYPERCORE_PROTOCOL};
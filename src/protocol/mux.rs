@@ -0,0 +1,555 @@
+//! Multiplexing of channels over one connection, modelled on protomux: every core, and any
+//! other protocol, gets a channel identified by its protocol name and id, e.g. the discovery
+//! key of a core, so one connection replicates many cores at once.
+//!
+//! [`Mux`] is sans-IO: its methods return the frames to send, for example with an
+//! [`EncryptedChannel`](crate::protocol::handshake::EncryptedChannel), and received frames are
+//! passed to [`Mux::receive`], which queues the resulting [`MuxEvent`]s.
+//!
+//! Messages can also be queued with a [`Priority`] by [`Mux::enqueue`], and sent in the order of
+//! [`Mux::next_frame`]: higher priorities first, and channels of the same priority in turn, so
+//! a large transfer on one channel doesn't hold up the control messages of the others.
+use std::collections::{HashMap, VecDeque};
+
+use crate::encoding::{CompactEncoding, HypercoreState};
+use crate::HypercoreError;
+
+/// Protocol name of hypercore replication channels, whose id is the discovery key of the core
+pub const HYPERCORE_PROTOCOL: &str = "hypercore/alpha";
+
+/// Frame of a multiplexed connection. Channel ids are those of the sender.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MuxFrame {
+    /// Open a channel
+    Open {
+        /// Id of the channel
+        channel: u64,
+        /// Protocol of the channel
+        protocol: String,
+        /// Id of the channel within the protocol
+        id: Vec<u8>,
+        /// Handshake of the protocol, taking the rest of the frame
+        handshake: Vec<u8>,
+    },
+    /// Reject a channel opened by the receiver
+    Reject {
+        /// Id of the channel, as sent by the receiver
+        channel: u64,
+    },
+    /// Close a channel
+    Close {
+        /// Id of the channel
+        channel: u64,
+    },
+    /// Message of an open channel
+    Message {
+        /// Id of the channel, never 0
+        channel: u64,
+        /// Type of the message within the protocol
+        message_type: u64,
+        /// Message, taking the rest of the frame
+        message: Vec<u8>,
+    },
+}
+
+/// Priority of a queued message, see [`Mux::enqueue`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// State and requests, small and latency sensitive
+    Control,
+    /// Proofs without block data
+    Proof,
+    /// Block data
+    Bulk,
+}
+
+impl Priority {
+    const ALL: [Priority; 3] = [Priority::Control, Priority::Proof, Priority::Bulk];
+}
+
+/// Event of a [`Mux`], see [`Mux::next_event`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MuxEvent {
+    /// The channel was opened on both sides
+    Opened {
+        /// Local id of the channel
+        channel: u64,
+        /// Handshake sent by the remote
+        handshake: Vec<u8>,
+    },
+    /// The remote opened a channel not opened locally: open it with [`Mux::open`] or refuse it
+    /// with [`Mux::reject`]
+    RemoteOpen {
+        /// Protocol of the channel
+        protocol: String,
+        /// Id of the channel within the protocol
+        id: Vec<u8>,
+    },
+    /// The remote rejected a channel opened locally
+    Rejected {
+        /// Local id of the channel
+        channel: u64,
+    },
+    /// The remote closed the channel
+    Closed {
+        /// Local id of the channel
+        channel: u64,
+    },
+    /// Message received on an open channel
+    Message {
+        /// Local id of the channel
+        channel: u64,
+        /// Type of the message within the protocol
+        message_type: u64,
+        /// The message
+        message: Vec<u8>,
+    },
+}
+
+#[derive(Debug)]
+struct Channel {
+    protocol: String,
+    id: Vec<u8>,
+    /// Id the remote gave the channel, once it opened it too
+    remote_channel: Option<u64>,
+}
+
+/// Remote open waiting for a local open.
+#[derive(Debug)]
+struct PendingOpen {
+    remote_channel: u64,
+    handshake: Vec<u8>,
+}
+
+/// Channels of one connection.
+#[derive(Debug, Default)]
+pub struct Mux {
+    /// Channels opened locally, by local id - 1
+    channels: Vec<Option<Channel>>,
+    /// Local ids of the open channels, by remote id
+    remote_channels: HashMap<u64, u64>,
+    /// Remote opens not yet opened locally, by protocol and id
+    pending: HashMap<(String, Vec<u8>), PendingOpen>,
+    events: VecDeque<MuxEvent>,
+    /// Queued frames, by priority and local id
+    outbound: HashMap<(Priority, u64), VecDeque<Vec<u8>>>,
+    /// Local ids with queued frames, by priority, in turn
+    turns: [VecDeque<u64>; 3],
+}
+
+impl Mux {
+    /// Create a multiplexer without channels.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a channel, returning its local id and the frame to send. The channel is open once
+    /// the remote opened it too, see [`MuxEvent::Opened`].
+    pub fn open(
+        &mut self,
+        protocol: &str,
+        id: &[u8],
+        handshake: &[u8],
+    ) -> Result<(u64, Vec<u8>), HypercoreError> {
+        if self
+            .channels
+            .iter()
+            .flatten()
+            .any(|channel| channel.protocol == protocol && channel.id == id)
+        {
+            return Err(HypercoreError::InvalidOperation {
+                context: format!("Channel {protocol} is already open"),
+            });
+        }
+        let index = match self.channels.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.channels.push(None);
+                self.channels.len() - 1
+            }
+        };
+        let channel = index as u64 + 1;
+        let mut opened = Channel {
+            protocol: protocol.to_string(),
+            id: id.to_vec(),
+            remote_channel: None,
+        };
+        if let Some(pending) = self.pending.remove(&(protocol.to_string(), id.to_vec())) {
+            opened.remote_channel = Some(pending.remote_channel);
+            self.remote_channels.insert(pending.remote_channel, channel);
+            self.events.push_back(MuxEvent::Opened {
+                channel,
+                handshake: pending.handshake,
+            });
+        }
+        self.channels[index] = Some(opened);
+        let frame = encode_frame(&MuxFrame::Open {
+            channel,
+            protocol: protocol.to_string(),
+            id: id.to_vec(),
+            handshake: handshake.to_vec(),
+        })?;
+        Ok((channel, frame))
+    }
+
+    /// Open the hypercore replication channel of the core with the given discovery key.
+    pub fn open_core(
+        &mut self,
+        discovery_key: &[u8; 32],
+        handshake: &[u8],
+    ) -> Result<(u64, Vec<u8>), HypercoreError> {
+        self.open(HYPERCORE_PROTOCOL, discovery_key, handshake)
+    }
+
+    /// Refuse a channel the remote opened, returning the frame to send, if it was pending.
+    pub fn reject(&mut self, protocol: &str, id: &[u8]) -> Result<Option<Vec<u8>>, HypercoreError> {
+        match self.pending.remove(&(protocol.to_string(), id.to_vec())) {
+            Some(pending) => Ok(Some(encode_frame(&MuxFrame::Reject {
+                channel: pending.remote_channel,
+            })?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Frame sending a message on an open channel.
+    pub fn send(
+        &self,
+        channel: u64,
+        message_type: u64,
+        message: &[u8],
+    ) -> Result<Vec<u8>, HypercoreError> {
+        if !self.is_open(channel) {
+            return Err(HypercoreError::InvalidOperation {
+                context: format!("Channel {channel} is not open"),
+            });
+        }
+        encode_frame(&MuxFrame::Message {
+            channel,
+            message_type,
+            message: message.to_vec(),
+        })
+    }
+
+    /// Queue a message on an open channel, to be sent when [`Mux::next_frame`] returns it.
+    pub fn enqueue(
+        &mut self,
+        channel: u64,
+        priority: Priority,
+        message_type: u64,
+        message: &[u8],
+    ) -> Result<(), HypercoreError> {
+        let frame = self.send(channel, message_type, message)?;
+        let queue = self.outbound.entry((priority, channel)).or_default();
+        if queue.is_empty() {
+            self.turns[priority as usize].push_back(channel);
+        }
+        queue.push_back(frame);
+        Ok(())
+    }
+
+    /// Next queued frame to send: from the highest priority with frames queued, taking one
+    /// frame of each channel in turn.
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        for priority in Priority::ALL {
+            let turns = &mut self.turns[priority as usize];
+            while let Some(channel) = turns.pop_front() {
+                let Some(queue) = self.outbound.get_mut(&(priority, channel)) else {
+                    // Closed since it was queued
+                    continue;
+                };
+                let frame = queue.pop_front();
+                if queue.is_empty() {
+                    self.outbound.remove(&(priority, channel));
+                } else {
+                    turns.push_back(channel);
+                }
+                return frame;
+            }
+        }
+        None
+    }
+
+    /// Number of queued frames.
+    pub fn queued(&self) -> usize {
+        self.outbound.values().map(VecDeque::len).sum()
+    }
+
+    /// Close a channel opened locally, returning the frame to send.
+    pub fn close(&mut self, channel: u64) -> Result<Vec<u8>, HypercoreError> {
+        let closed =
+            self.take_channel(channel)
+                .ok_or_else(|| HypercoreError::InvalidOperation {
+                    context: format!("Channel {channel} is not open"),
+                })?;
+        if let Some(remote_channel) = closed.remote_channel {
+            self.remote_channels.remove(&remote_channel);
+        }
+        encode_frame(&MuxFrame::Close { channel })
+    }
+
+    /// Is the channel open on both sides.
+    pub fn is_open(&self, channel: u64) -> bool {
+        self.channel(channel)
+            .is_some_and(|channel| channel.remote_channel.is_some())
+    }
+
+    /// Handle a received frame, queueing the events it causes.
+    pub fn receive(&mut self, frame: &[u8]) -> Result<(), HypercoreError> {
+        let frame: MuxFrame = HypercoreState::from_buffer(frame).decode(frame)?;
+        match frame {
+            MuxFrame::Open {
+                channel: remote_channel,
+                protocol,
+                id,
+                handshake,
+            } => {
+                if self.remote_channels.contains_key(&remote_channel)
+                    || self
+                        .pending
+                        .values()
+                        .any(|pending| pending.remote_channel == remote_channel)
+                {
+                    return Err(HypercoreError::InvalidOperation {
+                        context: format!("Remote channel {remote_channel} opened twice"),
+                    });
+                }
+                let local = self.channels.iter_mut().enumerate().find(|(_, channel)| {
+                    channel.as_ref().is_some_and(|channel| {
+                        channel.remote_channel.is_none()
+                            && channel.protocol == protocol
+                            && channel.id == id
+                    })
+                });
+                match local {
+                    Some((index, Some(local))) => {
+                        let channel = index as u64 + 1;
+                        local.remote_channel = Some(remote_channel);
+                        self.remote_channels.insert(remote_channel, channel);
+                        self.events
+                            .push_back(MuxEvent::Opened { channel, handshake });
+                    }
+                    _ => {
+                        self.pending.insert(
+                            (protocol.clone(), id.clone()),
+                            PendingOpen {
+                                remote_channel,
+                                handshake,
+                            },
+                        );
+                        self.events.push_back(MuxEvent::RemoteOpen { protocol, id });
+                    }
+                }
+            }
+            MuxFrame::Reject { channel } => {
+                if self
+                    .channel(channel)
+                    .is_some_and(|channel| channel.remote_channel.is_none())
+                {
+                    self.take_channel(channel);
+                    self.events.push_back(MuxEvent::Rejected { channel });
+                }
+            }
+            MuxFrame::Close {
+                channel: remote_channel,
+            } => match self.remote_channels.remove(&remote_channel) {
+                Some(channel) => {
+                    self.take_channel(channel);
+                    self.events.push_back(MuxEvent::Closed { channel });
+                }
+                None => self
+                    .pending
+                    .retain(|_, pending| pending.remote_channel != remote_channel),
+            },
+            MuxFrame::Message {
+                channel: remote_channel,
+                message_type,
+                message,
+            } => match self.remote_channels.get(&remote_channel) {
+                Some(&channel) => self.events.push_back(MuxEvent::Message {
+                    channel,
+                    message_type,
+                    message,
+                }),
+                // The channel may have been closed while the message was sent
+                None => tracing::debug!("Dropped message of unknown channel {remote_channel}"),
+            },
+        }
+        Ok(())
+    }
+
+    /// Next queued event.
+    pub fn next_event(&mut self) -> Option<MuxEvent> {
+        self.events.pop_front()
+    }
+
+    fn channel(&self, channel: u64) -> Option<&Channel> {
+        let index = usize::try_from(channel.checked_sub(1)?).ok()?;
+        self.channels.get(index)?.as_ref()
+    }
+
+    /// Take a channel out, dropping its queued frames.
+    fn take_channel(&mut self, channel: u64) -> Option<Channel> {
+        let index = usize::try_from(channel.checked_sub(1)?).ok()?;
+        let taken = self.channels.get_mut(index)?.take()?;
+        for priority in Priority::ALL {
+            self.outbound.remove(&(priority, channel));
+        }
+        Some(taken)
+    }
+}
+
+fn encode_frame(frame: &MuxFrame) -> Result<Vec<u8>, HypercoreError> {
+    let mut state = HypercoreState::new();
+    state.preencode(frame)?;
+    let mut buffer = state.create_buffer();
+    state.encode(frame, &mut buffer)?;
+    Ok(buffer.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deliver(frame: Vec<u8>, to: &mut Mux) -> Result<Vec<MuxEvent>, HypercoreError> {
+        to.receive(&frame)?;
+        Ok(std::iter::from_fn(|| to.next_event()).collect())
+    }
+
+    #[test]
+    fn mux_routes_channels() -> Result<(), HypercoreError> {
+        let mut a = Mux::new();
+        let mut b = Mux::new();
+        let (a_first, frame) = a.open_core(&[1; 32], b"a")?;
+        assert_eq!(
+            deliver(frame, &mut b)?,
+            [MuxEvent::RemoteOpen {
+                protocol: HYPERCORE_PROTOCOL.to_string(),
+                id: vec![1; 32],
+            }]
+        );
+        let (a_second, frame) = a.open_core(&[2; 32], b"a")?;
+        deliver(frame, &mut b)?;
+        let (a_user, frame) = a.open("nostr", &[], &[])?;
+        deliver(frame, &mut b)?;
+        assert!(!a.is_open(a_first));
+        assert!(a.send(a_first, 0, b"early").is_err());
+
+        // Channels are matched by protocol and id, whatever their ids on each side
+        let (b_second, frame) = b.open_core(&[2; 32], b"b")?;
+        assert_eq!(
+            b.next_event(),
+            Some(MuxEvent::Opened {
+                channel: b_second,
+                handshake: b"a".to_vec()
+            })
+        );
+        assert_eq!(
+            deliver(frame, &mut a)?,
+            [MuxEvent::Opened {
+                channel: a_second,
+                handshake: b"b".to_vec()
+            }]
+        );
+        let (b_first, frame) = b.open_core(&[1; 32], &[])?;
+        deliver(frame, &mut a)?;
+        assert_ne!(b_first, a_first);
+        b.next_event();
+
+        assert_eq!(
+            deliver(a.send(a_first, 3, b"data")?, &mut b)?,
+            [MuxEvent::Message {
+                channel: b_first,
+                message_type: 3,
+                message: b"data".to_vec()
+            }]
+        );
+        assert_eq!(
+            deliver(b.send(b_second, 0, &[])?, &mut a)?,
+            [MuxEvent::Message {
+                channel: a_second,
+                message_type: 0,
+                message: vec![],
+            }]
+        );
+
+        // Rejected and closed channels free their ids
+        let frame = b.reject("nostr", &[])?.unwrap();
+        assert_eq!(
+            deliver(frame, &mut a)?,
+            [MuxEvent::Rejected { channel: a_user }]
+        );
+        let frame = a.close(a_first)?;
+        assert_eq!(
+            deliver(frame, &mut b)?,
+            [MuxEvent::Closed { channel: b_first }]
+        );
+        assert!(!b.is_open(b_first));
+        assert_eq!(a.open("nostr", &[], &[])?.0, a_first);
+        assert!(a.is_open(a_second) && b.is_open(b_second));
+        Ok(())
+    }
+
+    #[test]
+    fn mux_queue_prioritizes_and_interleaves_channels() -> Result<(), HypercoreError> {
+        let mut a = Mux::new();
+        let mut b = Mux::new();
+        let mut open = |id: u8| -> Result<u64, HypercoreError> {
+            let (channel, frame) = a.open_core(&[id; 32], &[])?;
+            b.receive(&frame)?;
+            let (_, frame) = b.open_core(&[id; 32], &[])?;
+            a.receive(&frame)?;
+            Ok(channel)
+        };
+        let (bulk, other, closed) = (open(1)?, open(2)?, open(3)?);
+        assert!(a.enqueue(9, Priority::Control, 0, &[]).is_err());
+        for i in 0..3 {
+            a.enqueue(bulk, Priority::Bulk, 3, &[i])?;
+        }
+        a.enqueue(other, Priority::Bulk, 3, &[10])?;
+        a.enqueue(closed, Priority::Control, 0, &[20])?;
+        a.enqueue(other, Priority::Proof, 3, &[11])?;
+        a.enqueue(bulk, Priority::Control, 1, &[12])?;
+        assert_eq!(a.queued(), 7);
+        a.close(closed)?;
+        assert_eq!(a.queued(), 6);
+
+        // Control, then proofs, then bulk data with the channels taking turns
+        let frames: Vec<Vec<u8>> = std::iter::from_fn(|| a.next_frame()).collect();
+        let expected = [
+            a.send(bulk, 1, &[12])?,
+            a.send(other, 3, &[11])?,
+            a.send(bulk, 3, &[0])?,
+            a.send(other, 3, &[10])?,
+            a.send(bulk, 3, &[1])?,
+            a.send(bulk, 3, &[2])?,
+        ];
+        assert_eq!(frames, expected);
+        assert_eq!(a.queued(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn mux_frames_match_protomux() -> Result<(), HypercoreError> {
+        let open = MuxFrame::Open {
+            channel: 1,
+            protocol: "a".to_string(),
+            id: vec![7],
+            handshake: vec![9],
+        };
+        assert_eq!(encode_frame(&open)?, [0, 1, 1, 1, b'a', 1, 7, 9]);
+        assert_eq!(encode_frame(&MuxFrame::Reject { channel: 2 })?, [0, 2, 2]);
+        assert_eq!(encode_frame(&MuxFrame::Close { channel: 2 })?, [0, 3, 2]);
+        let message = MuxFrame::Message {
+            channel: 2,
+            message_type: 5,
+            message: vec![1, 2],
+        };
+        assert_eq!(encode_frame(&message)?, [2, 5, 1, 2]);
+        for frame in [open, message] {
+            let buffer = encode_frame(&frame)?;
+            let decoded: MuxFrame = HypercoreState::from_buffer(&buffer).decode(&buffer)?;
+            assert_eq!(decoded, frame);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+//! Receipts of pinning services, see [`PinReceipt`]. A service that stores cores for others
+//! signs a receipt of the state it holds of a core, so that its users can hold it to that
+//! later, e.g. by asking it for inclusion proofs of blocks up to the receipt's length.
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+
+use crate::{Checkpoint, HypercoreError};
+
+/// Namespace of the signed payload of a receipt, so that the signature can't be mistaken for
+/// one over anything else.
+const RECEIPT_NAMESPACE: &[u8] = b"hypercore/pin-receipt";
+
+/// Byte length of an encoded receipt.
+pub const PIN_RECEIPT_SIZE: usize = 32 + 8 + 8 + 32 + 8 + 32 + 64;
+
+/// Statement of a pinning service that it stores a core up to a checkpoint, signed with the
+/// key of the service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinReceipt {
+    /// Public key of the pinned core
+    pub key: [u8; 32],
+    /// State of the core the service stores
+    pub checkpoint: Checkpoint,
+    /// Seconds since the Unix epoch the service signed the receipt at
+    pub timestamp: u64,
+    /// Public key of the service
+    pub service: [u8; 32],
+    /// Signature of the service over the rest of the receipt
+    pub signature: Signature,
+}
+
+impl PinReceipt {
+    /// Sign a receipt for the core with the given public key at the checkpoint.
+    pub fn sign(
+        key: &[u8; 32],
+        checkpoint: Checkpoint,
+        timestamp: u64,
+        service: &SigningKey,
+    ) -> Self {
+        let service_key = service.verifying_key().to_bytes();
+        let signature = service.sign(&signable(key, &checkpoint, timestamp, &service_key));
+        Self {
+            key: *key,
+            checkpoint,
+            timestamp,
+            service: service_key,
+            signature,
+        }
+    }
+
+    /// Verify that the service with the given public key signed this receipt. Only the
+    /// signature is checked, verify the checkpoint against the core with
+    /// [`crate::Hypercore::verify_pin_receipt`].
+    pub fn verify_signature(&self, service: &VerifyingKey) -> bool {
+        service.as_bytes() == &self.service
+            && service
+                .verify_strict(
+                    &signable(&self.key, &self.checkpoint, self.timestamp, &self.service),
+                    &self.signature,
+                )
+                .is_ok()
+    }
+
+    /// Encode the receipt into [`PIN_RECEIPT_SIZE`] bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer =
+            signable_fields(&self.key, &self.checkpoint, self.timestamp, &self.service);
+        buffer.extend(self.signature.to_bytes());
+        buffer
+    }
+
+    /// Decode a receipt encoded with [`PinReceipt::encode`].
+    pub fn decode(buffer: &[u8]) -> Result<Self, HypercoreError> {
+        if buffer.len() != PIN_RECEIPT_SIZE {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Pin receipt should be {PIN_RECEIPT_SIZE} bytes, got {}",
+                    buffer.len()
+                ),
+            });
+        }
+        let bytes_32 = |start: usize| -> [u8; 32] {
+            buffer[start..start + 32]
+                .try_into()
+                .expect("Should be 32 bytes")
+        };
+        let u64_at = |start: usize| {
+            u64::from_le_bytes(
+                buffer[start..start + 8]
+                    .try_into()
+                    .expect("Should be 8 bytes"),
+            )
+        };
+        Ok(Self {
+            key: bytes_32(0),
+            checkpoint: Checkpoint {
+                fork: u64_at(32),
+                length: u64_at(40),
+                root_hash: bytes_32(48),
+            },
+            timestamp: u64_at(80),
+            service: bytes_32(88),
+            signature: Signature::from_bytes(buffer[120..].try_into().expect("Should be 64 bytes")),
+        })
+    }
+}
+
+fn signable_fields(
+    key: &[u8; 32],
+    checkpoint: &Checkpoint,
+    timestamp: u64,
+    service: &[u8; 32],
+) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(PIN_RECEIPT_SIZE);
+    buffer.extend(key);
+    buffer.extend(checkpoint.fork.to_le_bytes());
+    buffer.extend(checkpoint.length.to_le_bytes());
+    buffer.extend(checkpoint.root_hash);
+    buffer.extend(timestamp.to_le_bytes());
+    buffer.extend(service);
+    buffer
+}
+
+fn signable(
+    key: &[u8; 32],
+    checkpoint: &Checkpoint,
+    timestamp: u64,
+    service: &[u8; 32],
+) -> Vec<u8> {
+    let mut buffer = RECEIPT_NAMESPACE.to_vec();
+    buffer.extend(signable_fields(key, checkpoint, timestamp, service));
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::{create_hypercore_with_data, create_hypercore_with_data_and_key_pair};
+    use crate::crypto::{generate_signing_key, PartialKeypair};
+
+    #[async_std::test]
+    async fn pin_receipts_verify_against_core() -> Result<(), HypercoreError> {
+        let signing_key = generate_signing_key();
+        let key_pair = PartialKeypair {
+            public: signing_key.verifying_key(),
+            secret: Some(signing_key),
+        };
+        let key = key_pair.public.to_bytes();
+        let service = generate_signing_key();
+
+        // The service signs a receipt of its replica
+        let pinned = create_hypercore_with_data_and_key_pair(10, key_pair.clone()).await?;
+        let receipt = pinned.pin_receipt(&service, 1_700_000_000)?;
+        assert_eq!((receipt.key, receipt.checkpoint.length), (key, 10));
+        assert!(receipt.verify_signature(&service.verifying_key()));
+        assert!(!receipt.verify_signature(&generate_signing_key().verifying_key()));
+        let encoded = receipt.encode();
+        assert_eq!(encoded.len(), PIN_RECEIPT_SIZE);
+        assert_eq!(PinReceipt::decode(&encoded)?, receipt);
+        assert!(PinReceipt::decode(&encoded[1..]).is_err());
+
+        // The owner checks it against its own copy, which has grown since
+        let core = create_hypercore_with_data_and_key_pair(12, key_pair).await?;
+        core.verify_pin_receipt(&receipt, &service.verifying_key())
+            .await?;
+        assert!(core
+            .verify_pin_receipt(&receipt, &generate_signing_key().verifying_key())
+            .await
+            .is_err());
+        let mut tampered = receipt.clone();
+        tampered.timestamp += 1;
+        assert!(!tampered.verify_signature(&service.verifying_key()));
+
+        // Receipts of another core or of another state are refused
+        let other_key = generate_signing_key().verifying_key().to_bytes();
+        let other = PinReceipt::sign(&other_key, receipt.checkpoint, 1_700_000_000, &service);
+        assert!(core
+            .verify_pin_receipt(&other, &service.verifying_key())
+            .await
+            .is_err());
+        let mut forged = create_hypercore_with_data(11).await?;
+        forged.append(b"forged").await?;
+        let other = PinReceipt::sign(&key, forged.checkpoint(), 1_700_000_000, &service);
+        assert!(core
+            .verify_pin_receipt(&other, &service.verifying_key())
+            .await
+            .is_err());
+
+        // Only fully stored cores get a receipt
+        let mut sparse = create_hypercore_with_data(3).await?;
+        sparse.clear(1, 2).await?;
+        assert!(sparse.pin_receipt(&service, 1_700_000_000).is_err());
+        Ok(())
+    }
+}
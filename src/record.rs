@@ -0,0 +1,170 @@
+//! Merklized records, blocks whose fields can be disclosed one at a time.
+//!
+//! Instead of the fields themselves, the block of a [`Record`] holds a salted hash of every
+//! field. Publishing the block reveals nothing about the fields, and the owner can later prove
+//! the value of any single field with a [`FieldDisclosure`], which is checked against the leaf
+//! hash of the block in the tree.
+use crate::crypto::Hash;
+use crate::tree::hash_leaf;
+use crate::{OsRandom, Rng};
+
+/// Byte size of a salt and of a field hash.
+const FIELD_HASH_SIZE: usize = 32;
+
+/// Record of private fields, kept by its owner. The salts must be stored along with the fields
+/// to be able to disclose them later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    fields: Vec<Vec<u8>>,
+    salts: Vec<[u8; 32]>,
+}
+
+impl Record {
+    /// Create a record of the given fields, with fresh random salts.
+    pub fn new(fields: Vec<Vec<u8>>) -> Self {
+        Self::new_with(fields, &OsRandom)
+    }
+
+    /// Create a record of the given fields, with salts drawn from `rng`.
+    pub fn new_with(fields: Vec<Vec<u8>>, rng: &dyn Rng) -> Self {
+        let salts = fields
+            .iter()
+            .map(|_| {
+                let mut salt = [0; 32];
+                rng.fill_bytes(&mut salt);
+                salt
+            })
+            .collect();
+        Self { fields, salts }
+    }
+
+    /// Restore a record from its fields and their salts, returns `None` if their numbers
+    /// differ.
+    pub fn from_parts(fields: Vec<Vec<u8>>, salts: Vec<[u8; 32]>) -> Option<Self> {
+        (fields.len() == salts.len()).then_some(Self { fields, salts })
+    }
+
+    /// Values of the fields.
+    pub fn fields(&self) -> &[Vec<u8>] {
+        &self.fields
+    }
+
+    /// Salts of the fields.
+    pub fn salts(&self) -> &[[u8; 32]] {
+        &self.salts
+    }
+
+    /// Salted hashes of the fields.
+    pub fn field_hashes(&self) -> Vec<[u8; 32]> {
+        self.fields
+            .iter()
+            .zip(&self.salts)
+            .map(|(value, salt)| field_hash(salt, value))
+            .collect()
+    }
+
+    /// Block to append for this record: the field hashes, concatenated.
+    pub fn to_block(&self) -> Vec<u8> {
+        self.field_hashes().concat()
+    }
+
+    /// Disclose the field at the given position, `None` if there is no such field.
+    pub fn disclose(&self, field: usize) -> Option<FieldDisclosure> {
+        Some(FieldDisclosure {
+            field,
+            salt: *self.salts.get(field)?,
+            value: self.fields[field].clone(),
+            field_hashes: self.field_hashes(),
+        })
+    }
+}
+
+/// Value of a single field of a [`Record`], with what is needed to prove it against the leaf
+/// hash of the record's block. The other fields stay hidden behind their salted hashes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDisclosure {
+    /// Position of the disclosed field in the record
+    pub field: usize,
+    /// Salt of the disclosed field
+    pub salt: [u8; 32],
+    /// Value of the disclosed field
+    pub value: Vec<u8>,
+    /// Hashes of all fields of the record, i.e. the block
+    pub field_hashes: Vec<[u8; 32]>,
+}
+
+impl FieldDisclosure {
+    /// The record's block, rebuilt from the field hashes.
+    pub fn block(&self) -> Vec<u8> {
+        self.field_hashes.concat()
+    }
+
+    /// Verify the disclosed value against the leaf hash of the record's block, see
+    /// [`hash_leaf`]. Use [`crate::Hypercore::verify_disclosure`] to verify against a block of
+    /// a core.
+    pub fn verify(&self, block_hash: &[u8]) -> bool {
+        self.field_hashes.get(self.field) == Some(&field_hash(&self.salt, &self.value))
+            && hash_leaf(&self.block()) == block_hash
+    }
+}
+
+/// Parse a record's block into its field hashes, `None` if it isn't one.
+pub fn parse_record_block(block: &[u8]) -> Option<Vec<[u8; 32]>> {
+    if !block.len().is_multiple_of(FIELD_HASH_SIZE) {
+        return None;
+    }
+    Some(
+        block
+            .chunks_exact(FIELD_HASH_SIZE)
+            .map(|hash| hash.try_into().expect("Chunk should be 32 bytes"))
+            .collect(),
+    )
+}
+
+fn field_hash(salt: &[u8; 32], value: &[u8]) -> [u8; 32] {
+    Hash::record_field(salt, value)
+        .as_bytes()
+        .try_into()
+        .expect("BLAKE2b-256 hash should be 32 bytes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::create_hypercore_with_data;
+    use crate::HypercoreError;
+
+    #[async_std::test]
+    async fn record_discloses_single_fields() -> Result<(), HypercoreError> {
+        let record = Record::new(vec![
+            b"alice".to_vec(),
+            b"alice@example.com".to_vec(),
+            b"1990-01-01".to_vec(),
+        ]);
+        let mut hypercore = create_hypercore_with_data(2).await?;
+        hypercore.append(&record.to_block()).await?;
+        let block = hypercore.get(2).await?.unwrap();
+        assert_eq!(parse_record_block(&block).unwrap(), record.field_hashes());
+        assert!(!block.windows(5).any(|window| window == b"alice".as_slice()));
+
+        let disclosure = record.disclose(1).unwrap();
+        assert!(disclosure.verify(&hash_leaf(&block)));
+        assert!(hypercore.verify_disclosure(2, &disclosure).await?);
+        assert!(!hypercore.verify_disclosure(1, &disclosure).await?);
+
+        // Forged values and salts don't verify
+        let mut forged = disclosure.clone();
+        forged.value = b"mallory@example.com".to_vec();
+        assert!(!hypercore.verify_disclosure(2, &forged).await?);
+        let mut forged = disclosure;
+        forged.salt = [0; 32];
+        assert!(!hypercore.verify_disclosure(2, &forged).await?);
+
+        // Restored records disclose the same
+        let restored =
+            Record::from_parts(record.fields().to_vec(), record.salts().to_vec()).unwrap();
+        assert_eq!(restored.to_block(), block);
+        assert!(record.disclose(3).is_none());
+        Ok(())
+    }
+}
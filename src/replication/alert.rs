@@ -0,0 +1,294 @@
+//! Alerts about events that suggest the owner's key has been compromised, e.g. to message the
+//! owner of a feed over nostr.
+use async_broadcast::Receiver;
+use std::fmt::{self, Display};
+use std::future::Future;
+
+use super::{CoreInfo, Event, ReplicationMethods, ReplicationMethodsError};
+use crate::{
+    Info, PartialKeypair, Proof, RequestBlock, RequestSeek, RequestUpgrade, SignedHead,
+    VerifyingKey,
+};
+
+/// Suspicious event seen while replicating a core
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Alert {
+    /// A peer sent an upgrade signed with the key of the core that conflicts with the locally
+    /// verified tree of the same fork, i.e. two different trees of the core have been signed
+    Equivocation {
+        /// Fork of the conflicting upgrade
+        fork: u64,
+        /// Length of the conflicting upgrade
+        length: u64,
+    },
+    /// A peer sent an upgrade of a newer fork signed with the key of the core, i.e. the core
+    /// has been truncated elsewhere
+    UnexpectedTruncation {
+        /// Local fork
+        local_fork: u64,
+        /// Fork of the remote proof
+        remote_fork: u64,
+    },
+    /// A peer claims a different public key for the core
+    KeyMismatch {
+        /// Public key claimed by the remote peer
+        remote_key: [u8; 32],
+    },
+}
+
+impl Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Alert::Equivocation { fork, length } => write!(
+                f,
+                "Conflicting upgrade to length {length} at fork {fork}, the key may be compromised"
+            ),
+            Alert::UnexpectedTruncation {
+                local_fork,
+                remote_fork,
+            } => write!(
+                f,
+                "Unexpected truncation, remote fork {remote_fork} is newer than local fork {local_fork}"
+            ),
+            Alert::KeyMismatch { remote_key } => write!(
+                f,
+                "Remote peer claims a different key {}",
+                pretty_hash::fmt(remote_key).unwrap_or_default()
+            ),
+        }
+    }
+}
+
+/// Receives [`Alert`]s, e.g. to send them as encrypted nostr direct messages to the feed
+/// owner. Sending must not fail replication, so errors are for the notifier to handle.
+pub trait AlertNotifier {
+    /// Notify about an alert for the core with the given public key
+    fn notify(&self, public_key: &VerifyingKey, alert: Alert) -> impl Future<Output = ()> + Send;
+}
+
+/// Wraps a core and sends an [`Alert`] to the notifier when replication hits events that
+/// suggest a compromised key. Otherwise behaves exactly like the wrapped core.
+#[derive(Debug)]
+pub struct AlertingCore<C, N> {
+    core: C,
+    notifier: N,
+}
+
+impl<C: ReplicationMethods + Sync, N: AlertNotifier + Send + Sync> AlertingCore<C, N> {
+    /// Create a new alerting core
+    pub fn new(core: C, notifier: N) -> Self {
+        Self { core, notifier }
+    }
+
+    /// Access the wrapped core
+    pub fn core(&self) -> &C {
+        &self.core
+    }
+
+    /// Access the notifier
+    pub fn notifier(&self) -> &N {
+        &self.notifier
+    }
+
+    /// Check the public key announced by a remote peer, returns false and sends an alert if it
+    /// doesn't match.
+    pub async fn check_remote_key(&self, remote_key: &[u8; 32]) -> bool {
+        let public_key = self.core.key_pair().await.public;
+        if public_key.as_bytes() == remote_key {
+            return true;
+        }
+        self.notifier
+            .notify(
+                &public_key,
+                Alert::KeyMismatch {
+                    remote_key: *remote_key,
+                },
+            )
+            .await;
+        false
+    }
+
+    async fn alert(&self, alert: Alert) {
+        let public_key = self.core.key_pair().await.public;
+        self.notifier.notify(&public_key, alert).await;
+    }
+}
+
+impl<C: ReplicationMethods + Sync, N: AlertNotifier + Send + Sync> CoreInfo for AlertingCore<C, N> {
+    async fn info(&self) -> Info {
+        self.core.info().await
+    }
+
+    async fn key_pair(&self) -> PartialKeypair {
+        self.core.key_pair().await
+    }
+}
+
+impl<C: ReplicationMethods + Sync, N: AlertNotifier + Send + Sync> ReplicationMethods
+    for AlertingCore<C, N>
+{
+    async fn verify_and_apply_proof(&self, proof: &Proof) -> Result<bool, ReplicationMethodsError> {
+        let local_fork = self.core.info().await.fork;
+        let result = self.core.verify_and_apply_proof(proof).await;
+        if proof.upgrade.is_some() && !matches!(result, Ok(true)) {
+            // Anyone can send a bad proof, only heads signed with the key of the core count
+            if let Ok(Some(head)) = self.core.check_signed_head(proof).await {
+                if head.conflicts {
+                    self.alert(Alert::Equivocation {
+                        fork: head.checkpoint.fork,
+                        length: head.checkpoint.length,
+                    })
+                    .await;
+                } else if head.checkpoint.fork > local_fork {
+                    self.alert(Alert::UnexpectedTruncation {
+                        local_fork,
+                        remote_fork: head.checkpoint.fork,
+                    })
+                    .await;
+                }
+            }
+        }
+        result
+    }
+
+    async fn missing_nodes(&self, index: u64) -> Result<u64, ReplicationMethodsError> {
+        self.core.missing_nodes(index).await
+    }
+
+    async fn create_proof(
+        &self,
+        block: Option<RequestBlock>,
+        hash: Option<RequestBlock>,
+        seek: Option<RequestSeek>,
+        upgrade: Option<RequestUpgrade>,
+    ) -> Result<Option<Proof>, ReplicationMethodsError> {
+        self.core.create_proof(block, hash, seek, upgrade).await
+    }
+
+    async fn event_subscribe(&self) -> Receiver<Event> {
+        self.core.event_subscribe().await
+    }
+
+    async fn check_signed_head(
+        &self,
+        proof: &Proof,
+    ) -> Result<Option<SignedHead>, ReplicationMethodsError> {
+        self.core.check_signed_head(proof).await
+    }
+}
+
+#[cfg(all(test, feature = "shared-core"))]
+mod tests {
+    use super::*;
+    use crate::core::tests::{create_hypercore_with_data, create_hypercore_with_data_and_key_pair};
+    use crate::replication::SharedCore;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct TestNotifier {
+        alerts: Mutex<Vec<Alert>>,
+    }
+
+    impl AlertNotifier for TestNotifier {
+        async fn notify(&self, _public_key: &VerifyingKey, alert: Alert) {
+            self.alerts.lock().unwrap().push(alert);
+        }
+    }
+
+    #[async_std::test]
+    async fn alerting_core_reports_suspicious_proofs() -> Result<(), ReplicationMethodsError> {
+        let mut main = create_hypercore_with_data(10).await?;
+        let other = create_hypercore_with_data(12).await?;
+        let public = main.key_pair.public;
+        let mut forked = create_hypercore_with_data_and_key_pair(0, main.key_pair.clone()).await?;
+        for i in 0..10 {
+            forked.append(format!("forked #{i}").as_bytes()).await?;
+        }
+        let clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public,
+                secret: None,
+            },
+        )
+        .await?;
+        let clone = AlertingCore::new(SharedCore::from(clone), TestNotifier::default());
+        let upgrade = Some(RequestUpgrade {
+            start: 0,
+            length: 10,
+        });
+        let mut proof = main
+            .create_proof(None, None, None, upgrade.clone())
+            .await?
+            .unwrap();
+
+        // Valid proof, no alerts
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+
+        // Proof claiming a newer fork that wasn't signed, no alert
+        proof.fork = 1;
+        assert!(!clone.verify_and_apply_proof(&proof).await?);
+
+        // Upgrade signed with another key, no alert
+        let other_proof = other
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 10,
+                    length: 2,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(clone.verify_and_apply_proof(&other_proof).await.is_err());
+
+        // Conflicting tree signed with the key of the core
+        let forked_proof = forked
+            .create_proof(None, None, None, upgrade.clone())
+            .await?
+            .unwrap();
+        assert!(!matches!(
+            clone.verify_and_apply_proof(&forked_proof).await,
+            Ok(true)
+        ));
+
+        // Truncated and signed with the key of the core
+        main.truncate(8, 1).await?;
+        main.append(b"truncated").await?;
+        let truncated_proof = main
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 9,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(!clone.verify_and_apply_proof(&truncated_proof).await?);
+
+        assert!(clone.check_remote_key(public.as_bytes()).await);
+        assert!(!clone.check_remote_key(&[0; 32]).await);
+        assert_eq!(
+            *clone.notifier().alerts.lock().unwrap(),
+            vec![
+                Alert::Equivocation {
+                    fork: 0,
+                    length: 10
+                },
+                Alert::UnexpectedTruncation {
+                    local_fork: 0,
+                    remote_fork: 1
+                },
+                Alert::KeyMismatch {
+                    remote_key: [0; 32]
+                },
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+//! Reasons for closing a replication session, sent to the remote peer before disconnecting
+use std::fmt::{self, Display};
+
+use super::{CoreMethodsError, ReplicationMethodsError};
+use crate::encoding::{CompactEncoding, EncodingError, HypercoreState};
+use crate::HypercoreError;
+
+/// Reason code of a [`CloseReason`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseCode {
+    /// Session closed without an error
+    Normal,
+    /// Received proof did not verify
+    InvalidProof,
+    /// Received signature did not verify
+    InvalidSignature,
+    /// Received request or message was malformed or out of bounds
+    InvalidRequest,
+    /// Local failure unrelated to the remote peer
+    Internal,
+    /// Code not known to this implementation
+    Other(u64),
+}
+
+impl CloseCode {
+    /// Numeric code sent on the wire
+    pub fn code(&self) -> u64 {
+        match self {
+            CloseCode::Normal => 0,
+            CloseCode::InvalidProof => 1,
+            CloseCode::InvalidSignature => 2,
+            CloseCode::InvalidRequest => 3,
+            CloseCode::Internal => 4,
+            CloseCode::Other(code) => *code,
+        }
+    }
+
+    /// Reason code from the numeric code received on the wire
+    pub fn from_code(code: u64) -> Self {
+        match code {
+            0 => CloseCode::Normal,
+            1 => CloseCode::InvalidProof,
+            2 => CloseCode::InvalidSignature,
+            3 => CloseCode::InvalidRequest,
+            4 => CloseCode::Internal,
+            code => CloseCode::Other(code),
+        }
+    }
+}
+
+impl Display for CloseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloseCode::Normal => write!(f, "normal"),
+            CloseCode::InvalidProof => write!(f, "invalid proof"),
+            CloseCode::InvalidSignature => write!(f, "invalid signature"),
+            CloseCode::InvalidRequest => write!(f, "invalid request"),
+            CloseCode::Internal => write!(f, "internal error"),
+            CloseCode::Other(code) => write!(f, "unknown code {code}"),
+        }
+    }
+}
+
+/// Reason for closing a replication session. Sent to the remote peer before disconnecting on
+/// protocol violations, and given to the application when received from the remote peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseReason {
+    /// Reason code
+    pub code: CloseCode,
+    /// Human readable details
+    pub message: String,
+}
+
+impl CloseReason {
+    /// Create a new close reason
+    pub fn new(code: CloseCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Close reason for a session closed without an error
+    pub fn normal() -> Self {
+        Self::new(CloseCode::Normal, "")
+    }
+
+    /// Is this reason caused by an error
+    pub fn is_error(&self) -> bool {
+        self.code != CloseCode::Normal
+    }
+
+    /// Encode the reason.
+    pub fn encode(&self) -> Result<Vec<u8>, EncodingError> {
+        let mut state = HypercoreState::new();
+        state.preencode(self)?;
+        let mut buffer = state.create_buffer();
+        state.encode(self, &mut buffer)?;
+        Ok(buffer.to_vec())
+    }
+
+    /// Decode a reason encoded with [`CloseReason::encode`].
+    pub fn decode(buffer: &[u8]) -> Result<Self, EncodingError> {
+        HypercoreState::from_buffer(buffer).decode(buffer)
+    }
+}
+
+impl Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "Closed: {}", self.code)
+        } else {
+            write!(f, "Closed: {}. {}", self.code, self.message)
+        }
+    }
+}
+
+impl From<&HypercoreError> for CloseReason {
+    fn from(err: &HypercoreError) -> Self {
+        match err {
+            HypercoreError::InvalidSignature { .. } => {
+                Self::new(CloseCode::InvalidSignature, err.to_string())
+            }
+            HypercoreError::InvalidChecksum { .. } => {
+                Self::new(CloseCode::InvalidProof, err.to_string())
+            }
+            HypercoreError::BadArgument { .. }
+            | HypercoreError::InvalidOperation { .. }
+            | HypercoreError::LimitExceeded { .. } => {
+                Self::new(CloseCode::InvalidRequest, err.to_string())
+            }
+            // Details of local failures are not the remote peer's business
+            HypercoreError::NotWritable
+            | HypercoreError::QuotaExceeded { .. }
+            | HypercoreError::EmptyStorage { .. }
+            | HypercoreError::CorruptStorage { .. }
+            | HypercoreError::ExternallyModified { .. }
+            | HypercoreError::IO { .. } => Self::new(CloseCode::Internal, ""),
+        }
+    }
+}
+
+impl From<&CoreMethodsError> for CloseReason {
+    fn from(err: &CoreMethodsError) -> Self {
+        match err {
+            CoreMethodsError::HypercoreError(err) => err.into(),
+        }
+    }
+}
+
+impl From<&ReplicationMethodsError> for CloseReason {
+    fn from(err: &ReplicationMethodsError) -> Self {
+        match err {
+            ReplicationMethodsError::HypercoreError(err) => err.into(),
+            ReplicationMethodsError::CoreMethodsError(err) => err.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn close_reason_codes() {
+        for code in 0..6 {
+            assert_eq!(CloseCode::from_code(code).code(), code);
+        }
+        assert_eq!(CloseCode::from_code(42), CloseCode::Other(42));
+
+        let err = HypercoreError::InvalidChecksum {
+            context: "Invalid checksum at node 3, store tree".to_string(),
+        };
+        let reason = CloseReason::from(&err);
+        assert_eq!(reason.code, CloseCode::InvalidProof);
+        assert!(reason.is_error());
+        assert!(!CloseReason::normal().is_error());
+
+        let reason = CloseReason::from(&ReplicationMethodsError::from(HypercoreError::IO {
+            context: Some("/secret/path".to_string()),
+            source: std::io::Error::other("disk full"),
+        }));
+        assert_eq!(reason, CloseReason::new(CloseCode::Internal, ""));
+    }
+
+    #[test]
+    fn close_reason_encoding() -> Result<(), HypercoreError> {
+        let reason = CloseReason::new(CloseCode::InvalidRequest, "Block 12 out of bounds");
+        let mut enc_state = HypercoreState::new();
+        enc_state.preencode(&reason)?;
+        let mut buffer = enc_state.create_buffer();
+        enc_state.encode(&reason, &mut buffer)?;
+        let mut dec_state = HypercoreState::from_buffer(&buffer);
+        let decoded: CloseReason = dec_state.decode(&buffer)?;
+        assert_eq!(decoded, reason);
+        assert_eq!(CloseReason::decode(&reason.encode()?)?, reason);
+        Ok(())
+    }
+}
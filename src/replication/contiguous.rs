@@ -0,0 +1,176 @@
+//! Download that keeps the contiguous prefix of a core growing first, for streaming apps such
+//! as media players that must play from the start.
+use async_broadcast::{broadcast, InactiveReceiver, Receiver, Sender};
+use std::collections::BTreeSet;
+
+use crate::{Hypercore, HypercoreError, Proof, RequestBlock};
+
+/// Plans requests so that the first missing block, the one holding back the contiguous length,
+/// is always requested, even if it is already in flight, and the following blocks up to
+/// `readahead` blocks past the contiguous length are requested once each.
+///
+/// Drive it with the core: send the requests of [`ContiguousDownload::next_requests`] to peers
+/// and apply the received proofs with [`ContiguousDownload::apply_proof`]. Subscribe to
+/// [`ContiguousDownload::watch`] to learn when the contiguous length grows, e.g. to resume
+/// playback.
+#[derive(Debug)]
+pub struct ContiguousDownload {
+    readahead: u64,
+    in_flight: BTreeSet<u64>,
+    contiguous_length: u64,
+    watch: Sender<u64>,
+    /// Kept around so `ContiguousDownload::watch` stays open.
+    _receiver: InactiveReceiver<u64>,
+}
+
+impl ContiguousDownload {
+    /// Create a download requesting up to `readahead` blocks past the contiguous length.
+    pub fn new(readahead: u64) -> Self {
+        let (mut watch, receiver) = broadcast(1);
+        watch.set_await_active(false);
+        // Receivers only care about the latest length
+        watch.set_overflow(true);
+        Self {
+            readahead: readahead.max(1),
+            in_flight: BTreeSet::new(),
+            contiguous_length: 0,
+            watch,
+            _receiver: receiver.deactivate(),
+        }
+    }
+
+    /// Contiguous length of the core when last seen.
+    pub fn contiguous_length(&self) -> u64 {
+        self.contiguous_length
+    }
+
+    /// Receiver of the contiguous length every time it changes. Lagging receivers skip to the
+    /// latest length.
+    pub fn watch(&self) -> Receiver<u64> {
+        self.watch.new_receiver()
+    }
+
+    /// Requests for up to `max` missing blocks, starting with the first missing one.
+    pub async fn next_requests(
+        &mut self,
+        core: &mut Hypercore,
+        max: usize,
+    ) -> Result<Vec<RequestBlock>, HypercoreError> {
+        self.refresh(core);
+        let end = core
+            .info()
+            .length
+            .min(self.contiguous_length.saturating_add(self.readahead));
+        let mut requests = vec![];
+        let mut position = self.contiguous_length;
+        while requests.len() < max {
+            let Some(index) = core
+                .bitfield
+                .index_of(false, position)
+                .filter(|index| *index < end)
+            else {
+                break;
+            };
+            // The first missing block is requested again and again until it arrives
+            if index == self.contiguous_length || self.in_flight.insert(index) {
+                let nodes = core.missing_nodes(index).await?;
+                requests.push(RequestBlock { index, nodes });
+            }
+            position = index + 1;
+        }
+        Ok(requests)
+    }
+
+    /// Verify and apply a proof received for a request, and update the contiguous length.
+    pub async fn apply_proof(
+        &mut self,
+        core: &mut Hypercore,
+        proof: &Proof,
+    ) -> Result<bool, HypercoreError> {
+        let applied = core.verify_and_apply_proof(proof).await?;
+        if let Some(block) = proof.block.as_ref() {
+            self.in_flight.remove(&block.index);
+        }
+        self.refresh(core);
+        Ok(applied)
+    }
+
+    /// Forget that the block at `index` was requested, e.g. because the peer went away, so
+    /// that it is requested again.
+    pub fn cancel(&mut self, index: u64) -> bool {
+        self.in_flight.remove(&index)
+    }
+
+    /// Update the contiguous length from the core, e.g. after blocks were stored without
+    /// [`ContiguousDownload::apply_proof`]. Returns the contiguous length.
+    pub fn refresh(&mut self, core: &Hypercore) -> u64 {
+        let contiguous_length = core.info().contiguous_length;
+        self.in_flight
+            .retain(|index| *index >= contiguous_length && !core.has(*index));
+        if contiguous_length != self.contiguous_length {
+            self.contiguous_length = contiguous_length;
+            let _ = self.watch.try_broadcast(contiguous_length);
+        }
+        contiguous_length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::{create_hypercore_with_data, create_hypercore_with_data_and_key_pair};
+    use crate::{PartialKeypair, RequestUpgrade};
+
+    #[async_std::test]
+    async fn contiguous_download_grows_prefix_first() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(10).await?;
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair().public,
+                secret: None,
+            },
+        )
+        .await?;
+        let proof = main
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+
+        let mut download = ContiguousDownload::new(4);
+        let mut watch = download.watch();
+        let indices = |requests: &[RequestBlock]| -> Vec<u64> {
+            requests.iter().map(|request| request.index).collect()
+        };
+        let requests = download.next_requests(&mut clone, 3).await?;
+        assert_eq!(indices(&requests), vec![0, 1, 2]);
+        // Only the first missing block is requested again, within the readahead
+        let requests = download.next_requests(&mut clone, 3).await?;
+        assert_eq!(indices(&requests), vec![0, 3]);
+
+        for index in [1, 0] {
+            let nodes = clone.missing_nodes(index).await?;
+            let proof = main
+                .create_proof(Some(RequestBlock { index, nodes }), None, None, None)
+                .await?
+                .unwrap();
+            assert!(download.apply_proof(&mut clone, &proof).await?);
+        }
+        assert_eq!(download.contiguous_length(), 2);
+        assert_eq!(watch.try_recv(), Ok(2));
+
+        download.cancel(3);
+        let requests = download.next_requests(&mut clone, 10).await?;
+        assert_eq!(indices(&requests), vec![2, 3, 4, 5]);
+        Ok(())
+    }
+}
@@ -1,6 +1,10 @@
 //! events related to replication
 use crate::{common::BitfieldUpdate, HypercoreError};
 use async_broadcast::{broadcast, InactiveReceiver, Receiver, Sender};
+use intmap::IntMap;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
 static MAX_EVENT_QUEUE_CAPACITY: usize = 32;
 
@@ -45,6 +49,14 @@ impl From<&BitfieldUpdate> for Have {
     }
 }
 
+/// Emitted when a locally stored block failed verification against the tree. The block has
+/// been cleared, so it is downloaded again when requested.
+#[derive(Debug, Clone)]
+pub struct Corrupt {
+    /// Index of the corrupt block
+    pub index: u64,
+}
+
 #[derive(Debug, Clone)]
 /// Core events relevant to replication
 pub enum Event {
@@ -54,6 +66,8 @@ pub enum Event {
     DataUpgrade(DataUpgrade),
     /// Emmitted when core gets new blocks
     Have(Have),
+    /// Emitted when a corrupt block has been found and cleared
+    Corrupt(Corrupt),
 }
 
 /// Derive From<msg> for Enum where enum variant and msg have the same name
@@ -70,6 +84,23 @@ macro_rules! impl_from_for_enum_variant {
 impl_from_for_enum_variant!(Event, Get);
 impl_from_for_enum_variant!(Event, DataUpgrade);
 impl_from_for_enum_variant!(Event, Have);
+impl_from_for_enum_variant!(Event, Corrupt);
+
+/// A [`Get`] that has been emitted and not yet resolved.
+#[derive(Debug)]
+struct InflightGet {
+    get_result: Sender<()>,
+    /// Kept around so `InflightGet::get_result` stays open.
+    _receiver: InactiveReceiver<()>,
+}
+
+impl InflightGet {
+    /// The [`Get`] is only in flight as long as someone else than us holds a sender, i.e. the
+    /// event has not been dropped by the replicators or the overflowing event queue.
+    fn is_active(&self) -> bool {
+        self.get_result.sender_count() > 1
+    }
+}
 
 #[derive(Debug)]
 pub(crate) struct Events {
@@ -77,6 +108,9 @@ pub(crate) struct Events {
     pub(crate) channel: Sender<Event>,
     /// Kept around so `Events::channel` stays open.
     _receiver: InactiveReceiver<Event>,
+    /// Emitted [`Get`] events by block index, shared by everyone waiting for that block.
+    /// Locked so that concurrent reads of the core can emit them.
+    inflight_gets: Mutex<IntMap<InflightGet>>,
 }
 
 impl Events {
@@ -87,28 +121,195 @@ impl Events {
         // Message sending is best effort. Is msg queue fills up, remove old messages to make place
         // for new ones.
         _receiver.set_overflow(true);
-        Self { channel, _receiver }
+        Self {
+            channel,
+            _receiver,
+            inflight_gets: Mutex::new(IntMap::new()),
+        }
+    }
+
+    fn inflight_gets(&self) -> MutexGuard<'_, IntMap<InflightGet>> {
+        self.inflight_gets
+            .lock()
+            .expect("Inflight gets lock poisoned")
     }
 
     /// The internal channel errors on send when no replicators are subscribed,
     /// For now we don't consider that an error, but just in case, we return a Result in case
     /// we want to change this or add another fail path later.
     pub(crate) fn send<T: Into<Event>>(&self, evt: T) -> Result<(), HypercoreError> {
-        let _errs_when_no_replicators_subscribed = self.channel.try_broadcast(evt.into());
+        let evt = evt.into();
+        if let Event::Have(have) = &evt {
+            self.resolve_gets(have);
+        }
+        let _errs_when_no_replicators_subscribed = self.channel.try_broadcast(evt);
         Ok(())
     }
 
     /// Send a [`Get`] messages and return [`Receiver`] that will receive a message when block is
-    /// gotten.
+    /// gotten. If a [`Get`] for the same block is still in flight, no new message is sent and
+    /// the returned [`Receiver`] waits for the result of the earlier one.
     pub(crate) fn send_on_get(&self, index: u64) -> Receiver<()> {
+        let mut inflight_gets = self.inflight_gets();
+        inflight_gets.retain(|_, inflight| inflight.is_active());
+        if let Some(inflight) = inflight_gets.get(index) {
+            return inflight.get_result.new_receiver();
+        }
+
         let (mut tx, rx) = broadcast(1);
         tx.set_await_active(false);
-        let _ = self.send(Get {
-            index,
+        let _ = self.channel.try_broadcast(
+            Get {
+                index,
+                get_result: tx.clone(),
+            }
+            .into(),
+        );
+        let inflight = InflightGet {
             get_result: tx,
-        });
+            _receiver: rx.clone().deactivate(),
+        };
+        if inflight.is_active() {
+            inflight_gets.insert(index, inflight);
+        }
         rx
     }
+
+    /// Notify everyone waiting for blocks covered by the given [`Have`].
+    fn resolve_gets(&self, have: &Have) {
+        let mut inflight_gets = self.inflight_gets();
+        if have.drop || inflight_gets.is_empty() {
+            return;
+        }
+        let end = have.start + have.length;
+        inflight_gets.retain(|index, inflight| {
+            if index >= have.start && index < end {
+                let _ = inflight.get_result.try_broadcast(());
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+/// Coalesces [`Have`] events into merged ranges, so that a replicator can announce many blocks
+/// verified in quick succession with a few range messages instead of one message per block.
+///
+/// The batcher doesn't run a timer itself: push events as they are received, wait until
+/// [`HaveBatcher::deadline`] and then [`HaveBatcher::flush`] the merged ranges.
+#[derive(Debug)]
+pub struct HaveBatcher {
+    interval: Duration,
+    deadline: Option<Instant>,
+    /// Pending ranges of blocks gotten, start to end
+    have: BTreeMap<u64, u64>,
+    /// Pending ranges of blocks dropped, start to end
+    drop: BTreeMap<u64, u64>,
+}
+
+impl HaveBatcher {
+    /// Create a new batcher that holds events back for at most `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            deadline: None,
+            have: BTreeMap::new(),
+            drop: BTreeMap::new(),
+        }
+    }
+
+    /// Add an event received at `now`. A later event overrides earlier ones for the same
+    /// blocks.
+    pub fn push(&mut self, have: &Have, now: Instant) {
+        if have.length == 0 {
+            return;
+        }
+        let end = have.start + have.length;
+        if have.drop {
+            remove_range(&mut self.have, have.start, end);
+            insert_range(&mut self.drop, have.start, end);
+        } else {
+            remove_range(&mut self.drop, have.start, end);
+            insert_range(&mut self.have, have.start, end);
+        }
+        if self.deadline.is_none() {
+            self.deadline = Some(now + self.interval);
+        }
+    }
+
+    /// When the pending ranges should be flushed, `None` if nothing is pending.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Are there pending ranges.
+    pub fn is_empty(&self) -> bool {
+        self.deadline.is_none()
+    }
+
+    /// Take the pending ranges if the deadline has passed at `now`.
+    pub fn poll(&mut self, now: Instant) -> Option<Vec<Have>> {
+        match self.deadline {
+            Some(deadline) if deadline <= now => Some(self.flush()),
+            _ => None,
+        }
+    }
+
+    /// Take the pending ranges regardless of the deadline, dropped ranges first.
+    pub fn flush(&mut self) -> Vec<Have> {
+        self.deadline = None;
+        let drop = std::mem::take(&mut self.drop)
+            .into_iter()
+            .map(|r| (r, true));
+        let have = std::mem::take(&mut self.have)
+            .into_iter()
+            .map(|r| (r, false));
+        drop.chain(have)
+            .map(|((start, end), drop)| Have {
+                start,
+                length: end - start,
+                drop,
+            })
+            .collect()
+    }
+}
+
+/// Insert `start..end` into non-overlapping `ranges`, merging overlapping and adjacent ranges.
+fn insert_range(ranges: &mut BTreeMap<u64, u64>, mut start: u64, mut end: u64) {
+    if let Some((&prev_start, &prev_end)) = ranges.range(..=start).next_back() {
+        if prev_end >= start {
+            start = prev_start;
+            end = end.max(prev_end);
+            ranges.remove(&prev_start);
+        }
+    }
+    while let Some((&next_start, &next_end)) = ranges.range(start..).next() {
+        if next_start > end {
+            break;
+        }
+        end = end.max(next_end);
+        ranges.remove(&next_start);
+    }
+    ranges.insert(start, end);
+}
+
+/// Remove `start..end` from non-overlapping `ranges`, splitting ranges that cover it partially.
+fn remove_range(ranges: &mut BTreeMap<u64, u64>, start: u64, end: u64) {
+    if let Some((&prev_start, &prev_end)) = ranges.range(..start).next_back() {
+        if prev_end > start {
+            ranges.insert(prev_start, start);
+            if prev_end > end {
+                ranges.insert(end, prev_end);
+            }
+        }
+    }
+    while let Some((&next_start, &next_end)) = ranges.range(start..end).next() {
+        ranges.remove(&next_start);
+        if next_end > end {
+            ranges.insert(end, next_end);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -174,4 +375,70 @@ mod test {
         assert!(rx.is_empty());
         Ok(())
     }
+
+    #[async_std::test]
+    async fn test_get_deduplication() -> Result<(), CoreMethodsError> {
+        let mut core = crate::core::tests::create_hypercore_with_data(1).await?;
+        let mut rx = core.event_subscribe();
+        assert!(core.request_block(0).is_none());
+
+        // Two requests for the same missing block only emit one Get event
+        let mut first = core.request_block(1).unwrap();
+        let mut second = core.request_block(1).unwrap();
+        assert_eq!(core.get(1).await?, None);
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(Event::Get(Get { index: 1, .. }))
+        ));
+        assert!(rx.is_empty());
+
+        // Getting the block resolves all waiters
+        core.append(b"foo").await?;
+        first.recv().await.unwrap();
+        second.recv().await.unwrap();
+        assert!(matches!(rx.try_recv(), Ok(Event::DataUpgrade(_))));
+        assert!(matches!(rx.try_recv(), Ok(Event::Have(_))));
+
+        // A dropped Get event is emitted again on the next request
+        let _ = core.request_block(2).unwrap();
+        let Ok(Event::Get(get)) = rx.try_recv() else {
+            panic!("Expected a Get event");
+        };
+        drop(get);
+        let _ = core.request_block(2).unwrap();
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(Event::Get(Get { index: 2, .. }))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_have_batcher() {
+        let now = Instant::now();
+        let mut batcher = HaveBatcher::new(Duration::from_millis(50));
+        assert!(batcher.is_empty());
+        let have = |start, length, drop| Have {
+            start,
+            length,
+            drop,
+        };
+
+        for index in [4, 6, 5, 0, 1, 9] {
+            batcher.push(&have(index, 1, false), now);
+        }
+        batcher.push(&have(8, 2, true), now + Duration::from_millis(10));
+        assert_eq!(batcher.deadline(), Some(now + Duration::from_millis(50)));
+        assert!(batcher.poll(now + Duration::from_millis(49)).is_none());
+
+        let flushed: Vec<(u64, u64, bool)> = batcher
+            .poll(now + Duration::from_millis(50))
+            .unwrap()
+            .into_iter()
+            .map(|have| (have.start, have.length, have.drop))
+            .collect();
+        assert_eq!(flushed, vec![(8, 2, true), (0, 2, false), (4, 3, false)]);
+        assert!(batcher.is_empty());
+        assert!(batcher.flush().is_empty());
+    }
 }
@@ -0,0 +1,223 @@
+//! Set of followed feeds, one core per author, e.g. the follow list of a nostr user.
+use futures::stream::{self, Stream, StreamExt};
+use std::collections::BTreeMap;
+
+use super::Event;
+use crate::{Hypercore, HypercoreError, Proof, RequestBlock};
+
+/// Public key of an author, e.g. a nostr public key, which is a 32 byte x-only secp256k1 key.
+pub type AuthorKey = [u8; 32];
+
+/// Request for a missing block of a feed, see [`FeedSet::missing_requests`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedRequest {
+    /// Author of the feed
+    pub author: AuthorKey,
+    /// Missing block, with the number of tree nodes missing locally
+    pub block: RequestBlock,
+}
+
+/// Cores of the followed authors, keyed by author. Which core belongs to which author is up to
+/// the application, e.g. from a nostr event announcing the core's public key.
+#[derive(Debug, Default)]
+pub struct FeedSet {
+    feeds: BTreeMap<AuthorKey, Hypercore>,
+}
+
+impl FeedSet {
+    /// Create an empty feed set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follow an author. Returns the previous core of the author, if any.
+    pub fn add(&mut self, author: AuthorKey, core: Hypercore) -> Option<Hypercore> {
+        self.feeds.insert(author, core)
+    }
+
+    /// Unfollow an author, returns their core.
+    pub fn remove(&mut self, author: &AuthorKey) -> Option<Hypercore> {
+        self.feeds.remove(author)
+    }
+
+    /// Core of the given author.
+    pub fn get(&self, author: &AuthorKey) -> Option<&Hypercore> {
+        self.feeds.get(author)
+    }
+
+    /// Mutable core of the given author.
+    pub fn get_mut(&mut self, author: &AuthorKey) -> Option<&mut Hypercore> {
+        self.feeds.get_mut(author)
+    }
+
+    /// Whether the author is followed.
+    pub fn contains(&self, author: &AuthorKey) -> bool {
+        self.feeds.contains_key(author)
+    }
+
+    /// Followed authors, in key order.
+    pub fn authors(&self) -> impl Iterator<Item = &AuthorKey> {
+        self.feeds.keys()
+    }
+
+    /// Number of followed authors.
+    pub fn len(&self) -> usize {
+        self.feeds.len()
+    }
+
+    /// Whether no author is followed.
+    pub fn is_empty(&self) -> bool {
+        self.feeds.is_empty()
+    }
+
+    /// Subscribe to the events of all cores, each tagged with its author. Covers the authors
+    /// followed at the time of the call; subscribe again after adding authors.
+    pub fn subscribe_all(&self) -> impl Stream<Item = (AuthorKey, Event)> + Unpin {
+        stream::select_all(self.feeds.iter().map(|(author, core)| {
+            let author = *author;
+            core.event_subscribe().map(move |event| (author, event))
+        }))
+    }
+
+    /// Requests for up to `max` blocks missing from the followed cores, taking one block of
+    /// each author in turn so that no single busy author starves the others. Blocks are
+    /// missing if they are below the length of their core but not stored locally.
+    pub async fn missing_requests(
+        &mut self,
+        max: usize,
+    ) -> Result<Vec<FeedRequest>, HypercoreError> {
+        let mut cursors: Vec<(AuthorKey, u64)> =
+            self.feeds.keys().map(|author| (*author, 0)).collect();
+        let mut requests = vec![];
+        while requests.len() < max && !cursors.is_empty() {
+            let mut i = 0;
+            while i < cursors.len() && requests.len() < max {
+                let (author, position) = cursors[i];
+                let core = self
+                    .feeds
+                    .get_mut(&author)
+                    .expect("Author should be followed");
+                let length = core.tree.length;
+                match core
+                    .bitfield
+                    .index_of(false, position)
+                    .filter(|index| *index < length)
+                {
+                    Some(index) => {
+                        let nodes = core.missing_nodes(index).await?;
+                        requests.push(FeedRequest {
+                            author,
+                            block: RequestBlock { index, nodes },
+                        });
+                        cursors[i].1 = index + 1;
+                        i += 1;
+                    }
+                    None => {
+                        cursors.remove(i);
+                    }
+                }
+            }
+        }
+        Ok(requests)
+    }
+
+    /// Verify and apply a proof received for the feed of the given author.
+    pub async fn apply_proof(
+        &mut self,
+        author: &AuthorKey,
+        proof: &Proof,
+    ) -> Result<bool, HypercoreError> {
+        let Some(core) = self.feeds.get_mut(author) else {
+            return Err(HypercoreError::BadArgument {
+                context: "Proof for an author that is not followed".to_string(),
+            });
+        };
+        core.verify_and_apply_proof(proof).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::{create_hypercore_with_data, create_hypercore_with_data_and_key_pair};
+    use crate::{PartialKeypair, RequestUpgrade};
+
+    #[async_std::test]
+    async fn feed_set_downloads_missing_blocks() -> Result<(), HypercoreError> {
+        let mut mains = [
+            create_hypercore_with_data(3).await?,
+            create_hypercore_with_data(1).await?,
+        ];
+        let authors = [[1; 32], [2; 32]];
+        let mut feeds = FeedSet::new();
+        for (author, main) in authors.iter().zip(mains.iter()) {
+            let clone = create_hypercore_with_data_and_key_pair(
+                0,
+                PartialKeypair {
+                    public: main.key_pair().public,
+                    secret: None,
+                },
+            )
+            .await?;
+            assert!(feeds.add(*author, clone).is_none());
+        }
+        assert_eq!(feeds.len(), 2);
+        let mut events = feeds.subscribe_all();
+
+        // Learn the lengths of the feeds
+        for (author, main) in authors.iter().zip(mains.iter_mut()) {
+            let length = main.info().length;
+            let proof = main
+                .create_proof(None, None, None, Some(RequestUpgrade { start: 0, length }))
+                .await?
+                .unwrap();
+            assert!(feeds.apply_proof(author, &proof).await?);
+        }
+        assert_eq!(
+            events.next().await.map(|(author, _)| author),
+            Some(authors[0])
+        );
+
+        // Authors take turns
+        let requests = feeds.missing_requests(3).await?;
+        assert_eq!(
+            requests
+                .iter()
+                .map(|request| (request.author, request.block.index))
+                .collect::<Vec<_>>(),
+            vec![(authors[0], 0), (authors[1], 0), (authors[0], 1)]
+        );
+
+        loop {
+            let requests = feeds.missing_requests(10).await?;
+            if requests.is_empty() {
+                break;
+            }
+            for request in requests {
+                let main = &mut mains[authors.iter().position(|a| *a == request.author).unwrap()];
+                let proof = main
+                    .create_proof(Some(request.block), None, None, None)
+                    .await?
+                    .unwrap();
+                assert!(feeds.apply_proof(&request.author, &proof).await?);
+            }
+        }
+        assert_eq!(
+            feeds.get(&authors[0]).unwrap().bitfield_snapshot(),
+            vec![0..3]
+        );
+        assert_eq!(
+            feeds.get(&authors[1]).unwrap().bitfield_snapshot(),
+            vec![0..1]
+        );
+
+        assert!(feeds.remove(&authors[1]).is_some());
+        assert!(!feeds.contains(&authors[1]));
+        let proof = mains[1]
+            .create_proof(Some(RequestBlock { index: 0, nodes: 0 }), None, None, None)
+            .await?
+            .unwrap();
+        assert!(feeds.apply_proof(&authors[1], &proof).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,392 @@
+//! Fallback transport for networks where only HTTP(S) egress works. Wire messages are tunnelled
+//! to a gateway: outgoing messages are sent with chunked `POST` requests, incoming messages are
+//! fetched with long-polling `GET` requests that the gateway holds open until it has messages
+//! for the session or times out.
+//!
+//! Every request uses its own connection, which the caller opens, e.g. a TCP stream, wrapped in
+//! TLS for HTTPS. Bodies are sequences of frames: the byte length of a message as a
+//! little-endian u32 followed by the message. The gateway side reads requests with
+//! [`read_tunnel_request`] and answers them with [`write_send_response`] and
+//! [`write_poll_response`].
+use futures::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use std::io;
+
+use crate::common::encode_hex;
+use crate::{OsRandom, Rng};
+
+/// Maximum byte size of the head of a request or response
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// Maximum byte size of a body
+pub const MAX_BODY_SIZE: usize = 8 * 1024 * 1024;
+
+/// Client side of a tunnelled session with a gateway.
+#[derive(Debug, Clone)]
+pub struct HttpTunnel {
+    host: String,
+    path: String,
+    session: String,
+}
+
+impl HttpTunnel {
+    /// Create a tunnel to the gateway at `host` (the value of the `Host` header) serving the
+    /// tunnel under `path`, with a new random session id.
+    pub fn new(host: &str, path: &str) -> Self {
+        Self::new_with(host, path, &OsRandom)
+    }
+
+    /// Create a tunnel like [`HttpTunnel::new`], with a session id drawn from `rng`.
+    pub fn new_with(host: &str, path: &str, rng: &dyn Rng) -> Self {
+        let mut session = [0; 16];
+        rng.fill_bytes(&mut session);
+        Self {
+            host: host.to_string(),
+            path: path.trim_end_matches('/').to_string(),
+            session: encode_hex(&session),
+        }
+    }
+
+    /// Session id, which the gateway uses to route messages.
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+
+    /// Send messages to the gateway with a chunked `POST` over the given connection.
+    pub async fn send<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        io: S,
+        messages: &[Vec<u8>],
+    ) -> io::Result<()> {
+        let mut io = BufReader::new(io);
+        let head = format!(
+            "POST {}/{}/send HTTP/1.1\r\nHost: {}\r\nContent-Type: application/octet-stream\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n",
+            self.path, self.session, self.host
+        );
+        io.get_mut().write_all(head.as_bytes()).await?;
+        for message in messages {
+            let frame = frame(message)?;
+            io.get_mut()
+                .write_all(format!("{:x}\r\n", frame.len()).as_bytes())
+                .await?;
+            io.get_mut().write_all(&frame).await?;
+            io.get_mut().write_all(b"\r\n").await?;
+        }
+        io.get_mut().write_all(b"0\r\n\r\n").await?;
+        io.get_mut().flush().await?;
+
+        let head = read_head(&mut io).await?;
+        let status = response_status(&head)?;
+        if !(200..300).contains(&status) {
+            return Err(io::Error::other(format!(
+                "Gateway answered send with {status}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Wait for messages from the gateway with a long-polling `GET` over the given connection.
+    /// Returns no messages if the gateway timed out the poll.
+    pub async fn poll<S: AsyncRead + AsyncWrite + Unpin>(&self, io: S) -> io::Result<Vec<Vec<u8>>> {
+        let mut io = BufReader::new(io);
+        let head = format!(
+            "GET {}/{}/poll HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            self.path, self.session, self.host
+        );
+        io.get_mut().write_all(head.as_bytes()).await?;
+        io.get_mut().flush().await?;
+
+        let head = read_head(&mut io).await?;
+        match response_status(&head)? {
+            204 => Ok(vec![]),
+            200 => parse_frames(&read_body(&mut io, &head).await?),
+            status => Err(io::Error::other(format!(
+                "Gateway answered poll with {status}"
+            ))),
+        }
+    }
+}
+
+/// Request received by the gateway, see [`read_tunnel_request`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TunnelRequest {
+    /// Messages sent by the client of the session, answer with [`write_send_response`]
+    Send {
+        /// Session id
+        session: String,
+        /// Messages sent
+        messages: Vec<Vec<u8>>,
+    },
+    /// Poll for messages to the client of the session, answer with [`write_poll_response`]
+    Poll {
+        /// Session id
+        session: String,
+    },
+}
+
+/// Read a tunnel request on the gateway side. `path` is the path the tunnel is served under.
+pub async fn read_tunnel_request<S: AsyncRead + Unpin>(
+    io: S,
+    path: &str,
+) -> io::Result<TunnelRequest> {
+    let mut io = BufReader::new(io);
+    let head = read_head(&mut io).await?;
+    let request_line = head.lines().next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next());
+    let route = target
+        .and_then(|target| target.strip_prefix(path.trim_end_matches('/')))
+        .and_then(|route| route.strip_prefix('/'))
+        .and_then(|route| route.split_once('/'))
+        .filter(|(session, _)| !session.is_empty());
+    match (method, route) {
+        ("POST", Some((session, "send"))) => Ok(TunnelRequest::Send {
+            session: session.to_string(),
+            messages: parse_frames(&read_body(&mut io, &head).await?)?,
+        }),
+        ("GET", Some((session, "poll"))) => Ok(TunnelRequest::Poll {
+            session: session.to_string(),
+        }),
+        _ => Err(invalid_data(format!(
+            "Not a tunnel request: {request_line}"
+        ))),
+    }
+}
+
+/// Acknowledge a [`TunnelRequest::Send`].
+pub async fn write_send_response<S: AsyncWrite + Unpin>(mut io: S) -> io::Result<()> {
+    io.write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n")
+        .await?;
+    io.flush().await
+}
+
+/// Answer a [`TunnelRequest::Poll`] with the messages for the client, or with none when the
+/// poll timed out.
+pub async fn write_poll_response<S: AsyncWrite + Unpin>(
+    mut io: S,
+    messages: &[Vec<u8>],
+) -> io::Result<()> {
+    if messages.is_empty() {
+        return write_send_response(io).await;
+    }
+    let mut body = vec![];
+    for message in messages {
+        body.extend(frame(message)?);
+    }
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    io.write_all(head.as_bytes()).await?;
+    io.write_all(&body).await?;
+    io.flush().await
+}
+
+fn frame(message: &[u8]) -> io::Result<Vec<u8>> {
+    if message.len() > MAX_BODY_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Message of {} bytes exceeds maximum size {MAX_BODY_SIZE}",
+                message.len()
+            ),
+        ));
+    }
+    let mut frame = Vec::with_capacity(4 + message.len());
+    frame.extend((message.len() as u32).to_le_bytes());
+    frame.extend(message);
+    Ok(frame)
+}
+
+fn parse_frames(mut body: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let mut messages = vec![];
+    while !body.is_empty() {
+        let (length, rest) = body
+            .split_first_chunk::<4>()
+            .ok_or_else(|| invalid_data("Truncated frame length".to_string()))?;
+        let length = u32::from_le_bytes(*length) as usize;
+        if rest.len() < length {
+            return Err(invalid_data("Truncated frame".to_string()));
+        }
+        messages.push(rest[..length].to_vec());
+        body = &rest[length..];
+    }
+    Ok(messages)
+}
+
+/// Reads the head of a request or response, up to and without the empty line.
+async fn read_head<R: AsyncRead + Unpin>(io: &mut BufReader<R>) -> io::Result<String> {
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        if io.read_line(&mut line).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if head.len() + line.len() > MAX_HEAD_SIZE {
+            return Err(invalid_data("HTTP head too large".to_string()));
+        }
+        if line == "\r\n" || line == "\n" {
+            return Ok(head);
+        }
+        head.push_str(&line);
+    }
+}
+
+fn response_status(head: &str) -> io::Result<u16> {
+    head.lines()
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid_data("Invalid HTTP status line".to_string()))
+}
+
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then_some(value.trim())
+    })
+}
+
+async fn read_body<R: AsyncRead + Unpin>(io: &mut BufReader<R>, head: &str) -> io::Result<Vec<u8>> {
+    let chunked = header(head, "Transfer-Encoding")
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"));
+    if !chunked {
+        let length: usize = match header(head, "Content-Length") {
+            Some(length) => length
+                .parse()
+                .map_err(|_| invalid_data("Invalid Content-Length".to_string()))?,
+            None => 0,
+        };
+        if length > MAX_BODY_SIZE {
+            return Err(invalid_data(format!("Body of {length} bytes is too large")));
+        }
+        let mut body = vec![0; length];
+        io.read_exact(&mut body).await?;
+        return Ok(body);
+    }
+    let mut body = vec![];
+    loop {
+        let mut line = String::new();
+        io.read_line(&mut line).await?;
+        let size = line.trim_end().split(';').next().unwrap_or_default();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| invalid_data("Invalid chunk size".to_string()))?;
+        if body.len() + size > MAX_BODY_SIZE {
+            return Err(invalid_data("Chunked body is too large".to_string()));
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        io.read_exact(&mut body[start..]).await?;
+        // Line ending after the chunk, or the empty trailer after the last chunk
+        let mut line = String::new();
+        io.read_line(&mut line).await?;
+        if size == 0 {
+            return Ok(body);
+        }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// Connection that reads a canned input and records what is written.
+    struct Connection {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Connection {
+        fn new(input: Vec<u8>) -> Self {
+            Self {
+                input: Cursor::new(input),
+                output: vec![],
+            }
+        }
+    }
+
+    impl AsyncRead for Connection {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.input).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Connection {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.output.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[async_std::test]
+    async fn http_tunnel_round_trip() -> io::Result<()> {
+        let tunnel = HttpTunnel::new("gateway.example.com", "/tunnel/");
+        let messages = vec![b"hello".to_vec(), vec![], vec![7; 300]];
+
+        // Client sends, the gateway reads the chunked POST
+        let mut gateway_ack = Connection::new(vec![]);
+        write_send_response(&mut gateway_ack).await?;
+        let mut client = Connection::new(gateway_ack.output);
+        tunnel.send(&mut client, &messages).await?;
+        let request = read_tunnel_request(Cursor::new(client.output), "/tunnel").await?;
+        assert_eq!(
+            request,
+            TunnelRequest::Send {
+                session: tunnel.session().to_string(),
+                messages: messages.clone(),
+            }
+        );
+
+        // Client polls, the gateway answers with messages
+        let mut client = Connection::new(vec![]);
+        let _ = tunnel.poll(&mut client).await;
+        let request = read_tunnel_request(Cursor::new(client.output), "/tunnel").await?;
+        assert_eq!(
+            request,
+            TunnelRequest::Poll {
+                session: tunnel.session().to_string()
+            }
+        );
+        let mut gateway_answer = Connection::new(vec![]);
+        write_poll_response(&mut gateway_answer, &messages).await?;
+        let mut client = Connection::new(gateway_answer.output);
+        assert_eq!(tunnel.poll(&mut client).await?, messages);
+
+        // Timed out polls carry no messages
+        let mut gateway_answer = Connection::new(vec![]);
+        write_poll_response(&mut gateway_answer, &[]).await?;
+        let mut client = Connection::new(gateway_answer.output);
+        assert!(tunnel.poll(&mut client).await?.is_empty());
+
+        // Other requests are refused
+        let other = b"GET /index.html HTTP/1.1\r\nHost: x\r\n\r\n".to_vec();
+        assert!(read_tunnel_request(Cursor::new(other), "/tunnel")
+            .await
+            .is_err());
+        Ok(())
+    }
+}
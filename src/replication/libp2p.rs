@@ -0,0 +1,234 @@
+//! Replication of cores over libp2p, as a [`request_response`] behaviour.
+//!
+//! A peer requests proofs by sending a [`ReplicationRequest`] to another peer, addressing the
+//! core by its discovery key so that the public key never goes over the wire. The remote peer
+//! answers with [`respond`], which creates the proof from its local core.
+use ::libp2p::request_response::{self, Codec, ProtocolSupport};
+use ::libp2p::StreamProtocol;
+use async_trait::async_trait;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::io;
+
+use super::ReplicationTarget;
+use crate::encoding::{CompactEncoding, EncodingError, HypercoreState};
+use crate::{HypercoreError, Proof, RequestBlock, RequestSeek, RequestUpgrade};
+
+/// Protocol name of hypercore replication
+pub const PROTOCOL: StreamProtocol = StreamProtocol::new("/hypercore/replication/1");
+
+/// Maximum size of a single encoded request or response
+pub const MAX_MESSAGE_SIZE: usize = 8 * 1024 * 1024;
+
+/// Request for a proof from a remote core, see [`crate::Hypercore::create_proof`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplicationRequest {
+    /// Discovery key of the core, see [`discovery_key`]
+    pub discovery_key: [u8; 32],
+    /// Fork the requesting peer is on
+    pub fork: u64,
+    /// Request a block
+    pub block: Option<RequestBlock>,
+    /// Request a hash
+    pub hash: Option<RequestBlock>,
+    /// Seek to a byte offset
+    pub seek: Option<RequestSeek>,
+    /// Request an upgrade
+    pub upgrade: Option<RequestUpgrade>,
+}
+
+/// Codec of [`ReplicationRequest`]s and their proofs. Messages are compact encoded and
+/// prefixed with their length as a little-endian u32. A missing proof is sent as an empty
+/// message.
+#[derive(Debug, Clone, Default)]
+pub struct ReplicationCodec;
+
+/// libp2p behaviour replicating cores
+pub type Behaviour = request_response::Behaviour<ReplicationCodec>;
+
+/// Create a new [`Behaviour`] that both sends and answers requests.
+pub fn new_behaviour(config: request_response::Config) -> Behaviour {
+    Behaviour::new([(PROTOCOL, ProtocolSupport::Full)], config)
+}
+
+pub use crate::crypto::discovery_key;
+
+/// Answer a request from a remote peer with a proof from the local core. Returns `None` if the
+/// request is for another core or another fork, or if the core can't prove it.
+pub async fn respond<T: ReplicationTarget>(
+    core: &mut T,
+    request: ReplicationRequest,
+) -> Result<Option<Proof>, HypercoreError> {
+    if request.discovery_key != discovery_key(&core.key_pair().public)
+        || request.fork != core.info().fork
+    {
+        return Ok(None);
+    }
+    core.create_proof(request.block, request.hash, request.seek, request.upgrade)
+        .await
+}
+
+#[async_trait]
+impl Codec for ReplicationCodec {
+    type Protocol = StreamProtocol;
+    type Request = ReplicationRequest;
+    type Response = Option<Proof>;
+
+    async fn read_request<T>(
+        &mut self,
+        _protocol: &StreamProtocol,
+        io: &mut T,
+    ) -> io::Result<ReplicationRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let buffer = read_message(io).await?;
+        let mut state = HypercoreState::from_buffer(&buffer);
+        state.decode(&buffer).map_err(invalid_data)
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _protocol: &StreamProtocol,
+        io: &mut T,
+    ) -> io::Result<Option<Proof>>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let buffer = read_message(io).await?;
+        if buffer.is_empty() {
+            return Ok(None);
+        }
+        let mut state = HypercoreState::from_buffer(&buffer);
+        state.decode(&buffer).map(Some).map_err(invalid_data)
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _protocol: &StreamProtocol,
+        io: &mut T,
+        request: ReplicationRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let mut state = HypercoreState::new();
+        state.preencode(&request).map_err(invalid_data)?;
+        let mut buffer = state.create_buffer();
+        state.encode(&request, &mut buffer).map_err(invalid_data)?;
+        write_message(io, &buffer).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _protocol: &StreamProtocol,
+        io: &mut T,
+        response: Option<Proof>,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let Some(proof) = response else {
+            return write_message(io, &[]).await;
+        };
+        let mut state = HypercoreState::new();
+        state.preencode(&proof).map_err(invalid_data)?;
+        let mut buffer = state.create_buffer();
+        state.encode(&proof, &mut buffer).map_err(invalid_data)?;
+        write_message(io, &buffer).await
+    }
+}
+
+async fn read_message<T: AsyncRead + Unpin>(io: &mut T) -> io::Result<Vec<u8>> {
+    let mut length = [0; 4];
+    io.read_exact(&mut length).await?;
+    let length = u32::from_le_bytes(length) as usize;
+    if length > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Message of {length} bytes exceeds maximum size {MAX_MESSAGE_SIZE}"),
+        ));
+    }
+    let mut buffer = vec![0; length];
+    io.read_exact(&mut buffer).await?;
+    Ok(buffer)
+}
+
+async fn write_message<T: AsyncWrite + Unpin>(io: &mut T, buffer: &[u8]) -> io::Result<()> {
+    if buffer.len() > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Message of {} bytes exceeds maximum size {MAX_MESSAGE_SIZE}",
+                buffer.len()
+            ),
+        ));
+    }
+    io.write_all(&(buffer.len() as u32).to_le_bytes()).await?;
+    io.write_all(buffer).await?;
+    io.flush().await
+}
+
+fn invalid_data(err: EncodingError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::{create_hypercore_with_data, create_hypercore_with_data_and_key_pair};
+    use crate::PartialKeypair;
+    use futures::io::Cursor;
+
+    #[async_std::test]
+    async fn libp2p_codec_replicates_core() -> Result<(), HypercoreError> {
+        let mut main = create_hypercore_with_data(10).await?;
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair().public,
+                secret: None,
+            },
+        )
+        .await?;
+        let mut codec = ReplicationCodec;
+
+        let request = ReplicationRequest {
+            discovery_key: discovery_key(&main.key_pair().public),
+            fork: 0,
+            block: Some(RequestBlock { index: 6, nodes: 0 }),
+            hash: None,
+            seek: None,
+            upgrade: Some(RequestUpgrade {
+                start: 0,
+                length: 10,
+            }),
+        };
+        let mut io = Cursor::new(Vec::new());
+        codec
+            .write_request(&PROTOCOL, &mut io, request.clone())
+            .await?;
+        io.set_position(0);
+        let received = codec.read_request(&PROTOCOL, &mut io).await?;
+        assert_eq!(received, request);
+
+        let proof = respond(&mut main, received).await?;
+        assert!(proof.is_some());
+        let mut io = Cursor::new(Vec::new());
+        codec
+            .write_response(&PROTOCOL, &mut io, proof.clone())
+            .await?;
+        io.set_position(0);
+        let received = codec.read_response(&PROTOCOL, &mut io).await?;
+        assert_eq!(received, proof);
+        assert!(clone.verify_and_apply_proof(&received.unwrap()).await?);
+        assert_eq!(clone.get(6).await?.unwrap(), b"#6");
+
+        // Requests for other cores are not answered
+        let other = ReplicationRequest {
+            discovery_key: [0; 32],
+            ..request
+        };
+        assert_eq!(respond(&mut main, other).await?, None);
+        Ok(())
+    }
+}
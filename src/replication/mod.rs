@@ -1,17 +1,41 @@
 //! External interface for replication
+pub mod alert;
+pub mod close;
+pub mod contiguous;
 pub mod events;
+pub mod feed_set;
+pub mod http_tunnel;
+#[cfg(feature = "libp2p")]
+pub mod libp2p;
+pub mod peers;
+pub mod relay;
+pub mod repair;
+#[cfg(feature = "replication")]
+pub mod replicator;
 #[cfg(feature = "shared-core")]
 pub mod shared_core;
+pub mod target;
 
 #[cfg(feature = "shared-core")]
-pub use shared_core::SharedCore;
+pub use shared_core::{SharedCore, SharedReadTxn};
 
 use crate::{
     AppendOutcome, HypercoreError, Info, PartialKeypair, Proof, RequestBlock, RequestSeek,
-    RequestUpgrade,
+    RequestUpgrade, SignedHead,
 };
 
-pub use events::Event;
+pub use alert::{Alert, AlertNotifier, AlertingCore};
+pub use close::{CloseCode, CloseReason};
+pub use contiguous::ContiguousDownload;
+pub use events::{Event, HaveBatcher};
+pub use feed_set::{AuthorKey, FeedRequest, FeedSet};
+pub use http_tunnel::{HttpTunnel, TunnelRequest};
+pub use peers::{LatencyHistogram, PeerRanking, PeerStats};
+pub use relay::{Relay, RelayLimits};
+pub use repair::{ReadRepair, RepairOutcome, RepairPolicy};
+#[cfg(feature = "replication")]
+pub use replicator::{Replicator, APPEND_ENTRY_EXTENSION, CLOSE_EXTENSION};
+pub use target::ReplicationTarget;
 
 use async_broadcast::Receiver;
 use std::future::Future;
@@ -57,6 +81,11 @@ pub trait ReplicationMethods: CoreInfo + Send {
     ) -> impl Future<Output = Result<Option<Proof>, ReplicationMethodsError>> + Send;
     /// subscribe to core events
     fn event_subscribe(&self) -> impl Future<Output = Receiver<Event>>;
+    /// ref Core::check_signed_head
+    fn check_signed_head(
+        &self,
+        proof: &Proof,
+    ) -> impl Future<Output = Result<Option<SignedHead>, ReplicationMethodsError>> + Send;
 }
 
 /// Error for CoreMethods trait
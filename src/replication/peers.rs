@@ -0,0 +1,257 @@
+//! Latency and throughput of the peers replicated with, so applications can pick the peer to
+//! send interactive requests, e.g. byte range reads, to. [`crate::replication::Replicator`]
+//! measures every request it sends, other transports can record their own.
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{Clock, SystemClock};
+
+/// Distribution of round trip times. Durations are counted in power of two buckets of
+/// microseconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    count: u64,
+    total: Duration,
+    buckets: [u64; 65],
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            total: Duration::ZERO,
+            buckets: [0; 65],
+        }
+    }
+}
+
+impl LatencyHistogram {
+    /// Record a round trip time.
+    pub fn record(&mut self, rtt: Duration) {
+        self.count += 1;
+        self.total = self.total.saturating_add(rtt);
+        self.buckets[bucket(rtt)] += 1;
+    }
+
+    /// Number of recorded round trips.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Mean round trip time, `None` if nothing has been recorded.
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.total / self.count.min(u32::MAX as u64) as u32)
+    }
+
+    /// Upper bound of the time below which the given share of round trips fall, e.g. `0.5`
+    /// for the median. Rounded up to a power of two microseconds, `None` if nothing has been
+    /// recorded.
+    pub fn percentile(&self, share: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let target = ((self.count as f64 * share.clamp(0.0, 1.0)).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Some(Duration::from_micros(
+                    1u64.checked_shl(i as u32).unwrap_or(u64::MAX),
+                ));
+            }
+        }
+        unreachable!("Bucket counts should add up to count")
+    }
+
+    /// Number of round trips per bucket, bucket `i` counting times up to `2^i` microseconds.
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+}
+
+/// Index of the bucket of times in `(2^(i-1), 2^i]` microseconds.
+fn bucket(rtt: Duration) -> usize {
+    let micros = rtt.as_micros().min(u64::MAX as u128) as u64;
+    if micros <= 1 {
+        0
+    } else {
+        (u64::BITS - (micros - 1).leading_zeros()) as usize
+    }
+}
+
+/// What was measured of a peer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerStats {
+    rtt: LatencyHistogram,
+    no_data: u64,
+    bytes: u64,
+    busy: Duration,
+}
+
+impl PeerStats {
+    /// Round trip times of the requests to the peer, answered or not.
+    pub fn rtt(&self) -> &LatencyHistogram {
+        &self.rtt
+    }
+
+    /// Number of requests the peer couldn't answer.
+    pub fn no_data(&self) -> u64 {
+        self.no_data
+    }
+
+    /// Bytes of blocks received from the peer.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Time requests to the peer were in flight.
+    pub fn busy(&self) -> Duration {
+        self.busy
+    }
+
+    /// Bytes of blocks received per second of requests in flight, `None` before the peer
+    /// has been busy.
+    pub fn throughput(&self) -> Option<u64> {
+        let micros = self.busy.as_micros();
+        (micros > 0).then(|| (self.bytes as u128 * 1_000_000 / micros) as u64)
+    }
+}
+
+/// Statistics of every peer, by the static public key of its connection.
+#[derive(Debug, Clone)]
+pub struct PeerRanking {
+    peers: HashMap<[u8; 32], PeerStats>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for PeerRanking {
+    fn default() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+}
+
+impl PeerRanking {
+    /// Create a ranking without peers, timed by the system clock.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a ranking without peers, timed by the given clock.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            peers: HashMap::new(),
+            clock,
+        }
+    }
+
+    /// Current time of the clock of the ranking.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Statistics of the peer, `None` if nothing was recorded.
+    pub fn get(&self, peer: &[u8; 32]) -> Option<&PeerStats> {
+        self.peers.get(peer)
+    }
+
+    /// Forget the peer, e.g. when it went away for good.
+    pub fn remove(&mut self, peer: &[u8; 32]) -> Option<PeerStats> {
+        self.peers.remove(peer)
+    }
+
+    /// Record an answered request sent at the given time, with the bytes of blocks it got.
+    pub fn record(&mut self, peer: &[u8; 32], sent: Instant, bytes: u64) {
+        let rtt = self.now().saturating_duration_since(sent);
+        let stats = self.peers.entry(*peer).or_default();
+        stats.rtt.record(rtt);
+        stats.bytes += bytes;
+    }
+
+    /// Record a request sent at the given time that the peer couldn't answer.
+    pub fn record_no_data(&mut self, peer: &[u8; 32], sent: Instant) {
+        self.record(peer, sent, 0);
+        self.peers.entry(*peer).or_default().no_data += 1;
+    }
+
+    /// Record a time requests were in flight to the peer, see [`PeerStats::throughput`].
+    pub fn record_busy(&mut self, peer: &[u8; 32], busy: Duration) {
+        let stats = self.peers.entry(*peer).or_default();
+        stats.busy = stats.busy.saturating_add(busy);
+    }
+
+    /// Peers best first: the lowest median round trip time, then the highest throughput.
+    /// Peers without round trips come last.
+    pub fn ranked(&self) -> Vec<([u8; 32], &PeerStats)> {
+        let mut ranked: Vec<([u8; 32], &PeerStats)> = self
+            .peers
+            .iter()
+            .map(|(peer, stats)| (*peer, stats))
+            .collect();
+        ranked.sort_by(|(a_peer, a), (b_peer, b)| {
+            let a_rtt = a.rtt.percentile(0.5).unwrap_or(Duration::MAX);
+            let b_rtt = b.rtt.percentile(0.5).unwrap_or(Duration::MAX);
+            a_rtt
+                .cmp(&b_rtt)
+                .then(b.throughput().cmp(&a.throughput()))
+                .then(a_peer.cmp(b_peer))
+        });
+        ranked
+    }
+
+    /// The best peer of [`PeerRanking::ranked`].
+    pub fn best(&self) -> Option<[u8; 32]> {
+        self.ranked().first().map(|(peer, _)| *peer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ManualClock;
+
+    #[test]
+    fn peers_rank_by_latency_then_throughput() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.mean(), None);
+        for millis in [1, 2, 3, 40] {
+            histogram.record(Duration::from_millis(millis));
+        }
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.mean(), Some(Duration::from_micros(11_500)));
+        assert_eq!(histogram.percentile(0.5), Some(Duration::from_micros(2048)));
+        assert_eq!(
+            histogram.percentile(1.0),
+            Some(Duration::from_micros(65_536))
+        );
+        assert_eq!(histogram.buckets()[10..13], [1, 1, 1]);
+
+        let clock = ManualClock::new();
+        let mut peers = PeerRanking::with_clock(Arc::new(clock.clone()));
+        assert_eq!(peers.best(), None);
+        let (far, near, fast, idle) = ([1; 32], [2; 32], [3; 32], [4; 32]);
+        for _ in 0..3 {
+            let sent = peers.now();
+            clock.advance(Duration::from_millis(5));
+            peers.record(&near, sent, 100);
+            peers.record(&fast, sent, 1000);
+            clock.advance(Duration::from_millis(95));
+            peers.record(&far, sent, 10_000);
+        }
+        peers.record_no_data(&near, peers.now());
+        peers.record_busy(&near, Duration::from_millis(100));
+        peers.record_busy(&fast, Duration::from_millis(100));
+        peers.record_busy(&idle, Duration::from_millis(100));
+
+        let near_stats = peers.get(&near).unwrap();
+        assert_eq!(near_stats.rtt().count(), 4);
+        assert_eq!(near_stats.no_data(), 1);
+        assert_eq!(near_stats.throughput(), Some(3000));
+        assert_eq!(peers.get(&far).unwrap().throughput(), None);
+        let ranked: Vec<[u8; 32]> = peers.ranked().into_iter().map(|(peer, _)| peer).collect();
+        assert_eq!(ranked, [fast, near, far, idle]);
+        assert_eq!(peers.best(), Some(fast));
+        assert!(peers.remove(&fast).is_some());
+        assert_eq!(peers.best(), Some(near));
+    }
+}
@@ -0,0 +1,278 @@
+//! Relay that forwards replication requests for a core it doesn't store to an upstream peer,
+//! with rate limits. Lets lightweight nodes pass data between peers that can't connect
+//! directly.
+use async_broadcast::Receiver;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use super::{CoreInfo, Event, ReplicationMethods, ReplicationMethodsError};
+use crate::{
+    Clock, Info, PartialKeypair, Proof, RequestBlock, RequestSeek, RequestUpgrade, SignedHead,
+    SystemClock,
+};
+
+/// Byte size of a node in a proof.
+const PROOF_NODE_SIZE: u64 = 40;
+
+/// Rate limits of a [`Relay`]. Both allow bursts of up to one second worth of traffic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayLimits {
+    /// Maximum number of forwarded proof requests per second
+    pub requests_per_second: u64,
+    /// Maximum number of forwarded proof bytes per second
+    pub bytes_per_second: u64,
+}
+
+impl Default for RelayLimits {
+    fn default() -> Self {
+        Self {
+            requests_per_second: 100,
+            bytes_per_second: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.updated = now;
+    }
+
+    fn available(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= 1.0
+    }
+
+    /// Takes tokens, going into debt if there aren't enough.
+    fn take(&mut self, amount: u64, now: Instant) {
+        self.refill(now);
+        self.tokens -= amount as f64;
+    }
+}
+
+#[derive(Debug)]
+struct RelayBuckets {
+    requests: TokenBucket,
+    bytes: TokenBucket,
+}
+
+/// Forwards replication to an `upstream` that has the core, e.g. a connection to another peer,
+/// without storing anything locally. Requests over the rate limits are answered with no proof,
+/// like requests for blocks the relay doesn't have, so peers look for them elsewhere.
+#[derive(Debug)]
+pub struct Relay<U> {
+    upstream: U,
+    limits: RelayLimits,
+    buckets: Mutex<RelayBuckets>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<U: ReplicationMethods> Relay<U> {
+    /// Create a relay to the given upstream with the given rate limits.
+    pub fn new(upstream: U, limits: RelayLimits) -> Self {
+        Self::with_clock(upstream, limits, Arc::new(SystemClock))
+    }
+
+    /// Create a relay whose rate limits refill by the given clock, e.g. a
+    /// [`crate::ManualClock`] in tests.
+    pub fn with_clock(upstream: U, limits: RelayLimits, clock: Arc<dyn Clock>) -> Self {
+        let now = clock.now();
+        let buckets = Mutex::new(RelayBuckets {
+            requests: TokenBucket::new(limits.requests_per_second, now),
+            bytes: TokenBucket::new(limits.bytes_per_second, now),
+        });
+        Self {
+            upstream,
+            limits,
+            buckets,
+            clock,
+        }
+    }
+
+    /// Access the upstream.
+    pub fn upstream(&self) -> &U {
+        &self.upstream
+    }
+
+    /// Access the rate limits.
+    pub fn limits(&self) -> &RelayLimits {
+        &self.limits
+    }
+
+    /// Reserve one request, if the limits allow it.
+    fn reserve_request(&self) -> bool {
+        let now = self.clock.now();
+        let mut buckets = self.buckets.lock().expect("Relay buckets lock poisoned");
+        if !buckets.requests.available(now) || !buckets.bytes.available(now) {
+            return false;
+        }
+        buckets.requests.take(1, now);
+        true
+    }
+
+    fn record_bytes(&self, bytes: u64) {
+        let now = self.clock.now();
+        let mut buckets = self.buckets.lock().expect("Relay buckets lock poisoned");
+        buckets.bytes.take(bytes, now);
+    }
+}
+
+impl<U: ReplicationMethods + Sync> CoreInfo for Relay<U> {
+    async fn info(&self) -> Info {
+        let mut info = self.upstream.info().await;
+        info.writeable = false;
+        info
+    }
+
+    async fn key_pair(&self) -> PartialKeypair {
+        // Never hand out the secret key of the upstream
+        let key_pair = self.upstream.key_pair().await;
+        PartialKeypair {
+            public: key_pair.public,
+            secret: None,
+        }
+    }
+}
+
+impl<U: ReplicationMethods + Sync> ReplicationMethods for Relay<U> {
+    async fn verify_and_apply_proof(&self, proof: &Proof) -> Result<bool, ReplicationMethodsError> {
+        self.upstream.verify_and_apply_proof(proof).await
+    }
+
+    async fn missing_nodes(&self, index: u64) -> Result<u64, ReplicationMethodsError> {
+        self.upstream.missing_nodes(index).await
+    }
+
+    async fn create_proof(
+        &self,
+        block: Option<RequestBlock>,
+        hash: Option<RequestBlock>,
+        seek: Option<RequestSeek>,
+        upgrade: Option<RequestUpgrade>,
+    ) -> Result<Option<Proof>, ReplicationMethodsError> {
+        if !self.reserve_request() {
+            return Ok(None);
+        }
+        let proof = self
+            .upstream
+            .create_proof(block, hash, seek, upgrade)
+            .await?;
+        if let Some(proof) = proof.as_ref() {
+            self.record_bytes(proof_byte_size(proof));
+        }
+        Ok(proof)
+    }
+
+    async fn event_subscribe(&self) -> Receiver<Event> {
+        self.upstream.event_subscribe().await
+    }
+
+    async fn check_signed_head(
+        &self,
+        proof: &Proof,
+    ) -> Result<Option<SignedHead>, ReplicationMethodsError> {
+        self.upstream.check_signed_head(proof).await
+    }
+}
+
+/// Approximate byte size of a proof on the wire.
+fn proof_byte_size(proof: &Proof) -> u64 {
+    let mut nodes: usize = 0;
+    let mut bytes: u64 = 0;
+    if let Some(block) = proof.block.as_ref() {
+        bytes += block.value.len() as u64;
+        nodes += block.nodes.len();
+    }
+    if let Some(hash) = proof.hash.as_ref() {
+        nodes += hash.nodes.len();
+    }
+    if let Some(seek) = proof.seek.as_ref() {
+        nodes += seek.nodes.len();
+    }
+    if let Some(upgrade) = proof.upgrade.as_ref() {
+        nodes += upgrade.nodes.len() + upgrade.additional_nodes.len();
+        bytes += upgrade.signature.len() as u64;
+    }
+    bytes + nodes as u64 * PROOF_NODE_SIZE
+}
+
+#[cfg(all(test, feature = "shared-core"))]
+mod tests {
+    use super::*;
+    use crate::core::tests::{create_hypercore_with_data, create_hypercore_with_data_and_key_pair};
+    use crate::replication::SharedCore;
+
+    #[async_std::test]
+    async fn relay_forwards_with_rate_limits() -> Result<(), ReplicationMethodsError> {
+        let main = create_hypercore_with_data(10).await?;
+        let public = main.key_pair.public;
+        let clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public,
+                secret: None,
+            },
+        )
+        .await?;
+        let relay = Relay::new(
+            SharedCore::from(main),
+            RelayLimits {
+                requests_per_second: 2,
+                bytes_per_second: 1024,
+            },
+        );
+        let clone = SharedCore::from(clone);
+        assert!(relay.key_pair().await.secret.is_none());
+        assert!(!relay.info().await.writeable);
+
+        let index = 6;
+        let nodes = clone.missing_nodes(index).await?;
+        let proof = relay
+            .create_proof(
+                Some(RequestBlock { index, nodes }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(clone.verify_and_apply_proof(&proof).await?);
+        assert_eq!(
+            crate::replication::CoreMethods::get(&clone, 6)
+                .await?
+                .unwrap(),
+            b"#6"
+        );
+
+        // Second request fits, the third is over the request limit
+        let request = Some(RequestBlock { index: 7, nodes: 0 });
+        assert!(relay
+            .create_proof(request.clone(), None, None, None)
+            .await?
+            .is_some());
+        assert!(relay
+            .create_proof(request, None, None, None)
+            .await?
+            .is_none());
+        Ok(())
+    }
+}
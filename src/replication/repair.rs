@@ -0,0 +1,261 @@
+//! Read repair: a block that fails verification is requested again from another peer, and the
+//! peer that sent it is penalized, instead of failing the read on the first bad proof.
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::time::Duration;
+
+use crate::{Hypercore, HypercoreError, Proof, RequestBlock, RetryPolicy};
+
+/// How bad proofs are retried and punished.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RepairPolicy {
+    /// Retries of a block with bad proofs: its `max_attempts` bad proofs are tolerated before
+    /// the error is returned
+    pub retry: RetryPolicy,
+    /// Bad proofs after which a peer isn't chosen anymore, `None` to never ban
+    pub ban_after: Option<u32>,
+}
+
+impl Default for RepairPolicy {
+    fn default() -> Self {
+        Self {
+            retry: RetryPolicy::default(),
+            ban_after: Some(3),
+        }
+    }
+}
+
+/// Outcome of [`ReadRepair::apply_proof`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum RepairOutcome<P> {
+    /// The proof was verified, true if it changed the core
+    Applied(bool),
+    /// The proof failed verification: send the request to a peer not in `exclude`, e.g. one
+    /// picked with [`ReadRepair::choose_peer`]
+    Retry {
+        /// Request for the block again
+        request: RequestBlock,
+        /// Peers that sent a bad proof of the block
+        exclude: Vec<P>,
+        /// Time to wait before sending the request
+        delay: Duration,
+    },
+}
+
+/// Tracks bad proofs per block and per peer, for peers identified by `P`.
+#[derive(Debug)]
+pub struct ReadRepair<P> {
+    policy: RepairPolicy,
+    /// Peers that sent a bad proof, by block index
+    failed: HashMap<u64, Vec<P>>,
+    /// Bad proofs sent, by peer
+    penalties: HashMap<P, u32>,
+}
+
+impl<P: Clone + Eq + Hash> ReadRepair<P> {
+    /// Create a read repair with the given policy.
+    pub fn new(policy: RepairPolicy) -> Self {
+        Self {
+            policy,
+            failed: HashMap::new(),
+            penalties: HashMap::new(),
+        }
+    }
+
+    /// The retry policy.
+    pub fn policy(&self) -> &RepairPolicy {
+        &self.policy
+    }
+
+    /// Verify and apply a proof received from `peer`. A proof failing verification penalizes
+    /// the peer and asks for a retry, until the block failed the `max_attempts` of the retry
+    /// policy: then the verification error is returned. Other errors are returned right away.
+    /// Retry delays are jittered with the [`Rng`](crate::Rng) the core was built with.
+    pub async fn apply_proof(
+        &mut self,
+        core: &mut Hypercore,
+        peer: &P,
+        proof: &Proof,
+    ) -> Result<RepairOutcome<P>, HypercoreError> {
+        let index = proof.block.as_ref().map(|block| block.index);
+        match core.verify_and_apply_proof(proof).await {
+            Ok(applied) => {
+                if let Some(index) = index {
+                    self.failed.remove(&index);
+                }
+                Ok(RepairOutcome::Applied(applied))
+            }
+            Err(
+                err @ (HypercoreError::InvalidChecksum { .. }
+                | HypercoreError::InvalidSignature { .. }),
+            ) => {
+                *self.penalties.entry(peer.clone()).or_default() += 1;
+                let Some(index) = index else {
+                    return Err(err);
+                };
+                let failed = self.failed.entry(index).or_default();
+                if !failed.contains(peer) {
+                    failed.push(peer.clone());
+                }
+                let attempts = failed.len() as u32;
+                let Some(delay) = self.policy.retry.delay(attempts, core.rng()) else {
+                    self.failed.remove(&index);
+                    return Err(err);
+                };
+                let exclude = failed.clone();
+                tracing::warn!("Bad proof of block {index}, retrying ({attempts} failed): {err}");
+                let nodes = core.missing_nodes(index).await?;
+                Ok(RepairOutcome::Retry {
+                    request: RequestBlock { index, nodes },
+                    exclude,
+                    delay,
+                })
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Pick the peer to request the block at `index` from: one that didn't send a bad proof of
+    /// it and isn't banned, with the fewest penalties.
+    pub fn choose_peer<'a>(
+        &self,
+        index: u64,
+        candidates: impl IntoIterator<Item = &'a P>,
+    ) -> Option<&'a P>
+    where
+        P: 'a,
+    {
+        let failed: HashSet<&P> = self.failed.get(&index).into_iter().flatten().collect();
+        candidates
+            .into_iter()
+            .filter(|peer| !failed.contains(peer) && !self.is_banned(peer))
+            .min_by_key(|peer| self.penalty(peer))
+    }
+
+    /// Bad proofs sent by the peer.
+    pub fn penalty(&self, peer: &P) -> u32 {
+        self.penalties.get(peer).copied().unwrap_or(0)
+    }
+
+    /// Has the peer sent too many bad proofs to be chosen.
+    pub fn is_banned(&self, peer: &P) -> bool {
+        self.policy
+            .ban_after
+            .is_some_and(|ban_after| self.penalty(peer) >= ban_after)
+    }
+
+    /// Forget the penalties of a peer, e.g. when it disconnects.
+    pub fn forget_peer(&mut self, peer: &P) {
+        self.penalties.remove(peer);
+        for failed in self.failed.values_mut() {
+            failed.retain(|failed| failed != peer);
+        }
+        self.failed.retain(|_, failed| !failed.is_empty());
+    }
+}
+
+impl<P: Clone + Eq + Hash> Default for ReadRepair<P> {
+    fn default() -> Self {
+        Self::new(RepairPolicy::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::{create_hypercore_with_data, create_hypercore_with_data_and_key_pair};
+    use crate::{PartialKeypair, RequestUpgrade};
+
+    async fn block_proof(
+        main: &mut Hypercore,
+        clone: &mut Hypercore,
+        index: u64,
+        forged: bool,
+    ) -> Result<Proof, HypercoreError> {
+        let nodes = clone.missing_nodes(index).await?;
+        let mut proof = main
+            .create_proof(Some(RequestBlock { index, nodes }), None, None, None)
+            .await?
+            .unwrap();
+        if forged {
+            proof.block.as_mut().unwrap().value = b"#x".to_vec();
+        }
+        Ok(proof)
+    }
+
+    #[async_std::test]
+    async fn read_repair_retries_other_peers() -> Result<(), HypercoreError> {
+        let mut main = create_hypercore_with_data(10).await?;
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair().public,
+                secret: None,
+            },
+        )
+        .await?;
+        let upgrade = main
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        let mut repair = ReadRepair::new(RepairPolicy {
+            retry: RetryPolicy {
+                max_attempts: 2,
+                jitter: 0.0,
+                ..RetryPolicy::default()
+            },
+            ban_after: Some(2),
+        });
+        assert_eq!(
+            repair.apply_proof(&mut clone, &"a", &upgrade).await?,
+            RepairOutcome::Applied(true)
+        );
+
+        let forged = block_proof(&mut main, &mut clone, 3, true).await?;
+        let good = block_proof(&mut main, &mut clone, 3, false).await?;
+        let forged_4 = block_proof(&mut main, &mut clone, 4, true).await?;
+
+        let outcome = repair.apply_proof(&mut clone, &"a", &forged).await?;
+        let RepairOutcome::Retry {
+            request,
+            exclude,
+            delay,
+        } = outcome
+        else {
+            panic!("Forged proof should be retried");
+        };
+        assert_eq!(request.index, 3);
+        assert_eq!(delay, Duration::from_millis(100));
+        assert_eq!(exclude, vec!["a"]);
+        assert_eq!(repair.penalty(&"a"), 1);
+        assert_eq!(repair.choose_peer(3, &["a", "b"]), Some(&"b"));
+        assert_eq!(repair.choose_peer(4, &["a", "b"]), Some(&"b"));
+        assert_eq!(
+            repair.apply_proof(&mut clone, &"b", &good).await?,
+            RepairOutcome::Applied(true)
+        );
+        assert_eq!(clone.get(3).await?.unwrap(), b"#3");
+
+        // Out of attempts the error is returned, and the offender is banned
+        let outcome = repair.apply_proof(&mut clone, &"a", &forged_4).await?;
+        assert!(matches!(outcome, RepairOutcome::Retry { .. }));
+        assert!(repair.is_banned(&"a"));
+        assert!(repair
+            .apply_proof(&mut clone, &"c", &forged_4)
+            .await
+            .is_err());
+        assert_eq!(repair.choose_peer(4, &["a", "c"]), Some(&"c"));
+
+        repair.forget_peer(&"a");
+        assert_eq!(repair.choose_peer(4, &["a"]), Some(&"a"));
+        Ok(())
+    }
+}
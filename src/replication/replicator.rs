@@ -0,0 +1,1122 @@
+//! Replication of cores with a peer over an [`EncryptedChannel`]: [`Replicator`] runs the
+//! request/response loop of the [`protocol`](crate::protocol) messages for all its cores at once.
+//!
+//! Every core gets a `hypercore/alpha` channel of the [`Mux`], opened with a capability proving
+//! knowledge of its public key, bound to the connection by the handshake hash. Each peer then
+//! announces the blocks it has with [`Range`]s, followed by a [`Synchronize`] with its length.
+//! Upgrades and the blocks announced by the peer are requested a window at a time, and
+//! requests of the peer are answered with proofs. Once a peer has nothing left to download it
+//! says so with `downloading: false`, and replication ends when both peers did for every core.
+//!
+//! Replication is one-shot: blocks appended while it runs are not announced. Blocks
+//! [restricted](Hypercore::restrict) to other peers are neither announced nor sent.
+//!
+//! A replication failing on invalid messages or proofs tells the peer why before closing the
+//! channels, with a [`CloseReason`] in an extension message named [`CLOSE_EXTENSION`].
+//!
+//! The round trip time of every request and the throughput of every peer are recorded in the
+//! [`PeerRanking`] of the replicator, by the static public key of the peer. Requests the peer
+//! had no data for are sent again by the [`RetryPolicy`] of the replicator, once due when the
+//! peer next sends something.
+//!
+//! Downloaded blocks and bytes are reported to a [`Progress`] set with
+//! [`Replicator::set_progress`].
+use std::collections::{HashMap, HashSet};
+use std::ops::Range as Span;
+use std::sync::Arc;
+use std::time::Instant;
+
+use futures::io::{AsyncRead, AsyncWrite};
+
+use crate::crypto::{discovery_key, replication_capability, verify_replication_capability};
+use crate::protocol::{
+    handshake, CoreHandshake, Data, EncryptedChannel, Extension, Message, Mux, MuxEvent, NoData,
+    Range, Request, Synchronize,
+};
+use crate::replication::{CloseReason, PeerRanking};
+use crate::{
+    AppendEntry, Clock, Hypercore, HypercoreError, OsRandom, Progress, RequestBlock, RetryPolicy,
+    Rng, SigningKey,
+};
+
+/// Requests in flight per core.
+const MAX_INFLIGHT: usize = 16;
+
+/// Name of the extension messages carrying [`AppendEntry`]s.
+pub const APPEND_ENTRY_EXTENSION: &str = "append-entry";
+
+/// Name of the extension messages carrying the [`CloseReason`] of a peer ending the
+/// replication on an error, sent on every channel before closing it.
+pub const CLOSE_EXTENSION: &str = "close";
+
+/// Most blocks a peer can be behind to be pushed an [`AppendEntry`] instead of upgrading.
+const MAX_PUSHED_BLOCKS: u64 = 16;
+
+/// Blocks a remote bitfield may cover, the integers Javascript peers can count exactly.
+const MAX_BITFIELD_END: u64 = 1 << 53;
+
+/// Cores replicated together with peers, one at a time.
+#[derive(Debug)]
+pub struct Replicator {
+    cores: Vec<Hypercore>,
+    discovery_keys: Vec<[u8; 32]>,
+    peers: PeerRanking,
+    retry: RetryPolicy,
+    rng: Arc<dyn Rng>,
+    push_appends: bool,
+    close_reason: Option<CloseReason>,
+    progress: Option<Progress>,
+}
+
+impl Default for Replicator {
+    fn default() -> Self {
+        Self {
+            cores: vec![],
+            discovery_keys: vec![],
+            peers: PeerRanking::default(),
+            retry: RetryPolicy::default(),
+            rng: Arc::new(OsRandom),
+            push_appends: false,
+            close_reason: None,
+            progress: None,
+        }
+    }
+}
+
+impl Replicator {
+    /// Create a replicator without cores.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a replicator without cores, timing requests with the given clock.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            peers: PeerRanking::with_clock(clock),
+            ..Self::default()
+        }
+    }
+
+    /// Latency and throughput of the peers replicated with so far.
+    pub fn peers(&self) -> &PeerRanking {
+        &self.peers
+    }
+
+    /// Retry requests the peer had no data for by the given policy.
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    /// Policy requests the peer had no data for are retried by.
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// Draw the jitter of retry delays from `rng`, [`OsRandom`] by default. Use a
+    /// [`SeededRng`](crate::SeededRng) for reproducible retry schedules in tests.
+    pub fn set_rng(&mut self, rng: Arc<dyn Rng>) {
+        self.rng = rng;
+    }
+
+    /// Push the blocks a peer is missing at the end of a core, when only a few, as an
+    /// [`AppendEntry`] in an extension message named [`APPEND_ENTRY_EXTENSION`], and apply
+    /// entries pushed by the peer. The peer then catches up without proofs of the new roots.
+    /// Both peers need this set, peers without it upgrade through proofs as usual. Off by
+    /// default.
+    pub fn set_push_appends(&mut self, push_appends: bool) {
+        self.push_appends = push_appends;
+    }
+
+    /// Report the blocks and bytes downloaded by replications to `progress`, e.g. to render a
+    /// progress bar. Its total grows with the blocks requested from peers, and it is marked
+    /// finished once a replication is.
+    pub fn set_progress(&mut self, progress: Progress) {
+        self.progress = Some(progress);
+    }
+
+    /// Reason the peer gave for ending the last replication on an error, which then failed
+    /// with it.
+    pub fn close_reason(&self) -> Option<&CloseReason> {
+        self.close_reason.as_ref()
+    }
+
+    /// Replicate the core too. Fails if a core with the same key was added.
+    pub fn add_core(&mut self, core: Hypercore) -> Result<(), HypercoreError> {
+        let discovery_key = discovery_key(&core.key_pair().public);
+        if self.discovery_keys.contains(&discovery_key) {
+            return Err(HypercoreError::BadArgument {
+                context: "Core is already replicated".to_string(),
+            });
+        }
+        self.cores.push(core);
+        self.discovery_keys.push(discovery_key);
+        Ok(())
+    }
+
+    /// The core with the given discovery key.
+    pub fn core(&self, discovery_key: &[u8; 32]) -> Option<&Hypercore> {
+        let index = self.position(discovery_key)?;
+        Some(&self.cores[index])
+    }
+
+    /// The core with the given discovery key, mutably.
+    pub fn core_mut(&mut self, discovery_key: &[u8; 32]) -> Option<&mut Hypercore> {
+        let index = self.position(discovery_key)?;
+        Some(&mut self.cores[index])
+    }
+
+    /// The cores, consuming the replicator.
+    pub fn into_cores(self) -> Vec<Hypercore> {
+        self.cores
+    }
+
+    /// Replicate the cores with the peer of the channel until both are in sync, i.e. for every
+    /// core opened on both sides, neither peer can download anything more from the other.
+    /// Cores the peer doesn't have are skipped. Fails on I/O errors, invalid capabilities and
+    /// proofs, which end the replication: the peer is sent a [`CloseReason`] first, and a
+    /// reason sent by the peer is kept in [`Replicator::close_reason`].
+    pub async fn replicate<T: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        channel: &mut EncryptedChannel<T>,
+    ) -> Result<(), HypercoreError> {
+        self.close_reason = None;
+        let mut mux = Mux::new();
+        let mut sessions = Vec::with_capacity(self.cores.len());
+        for (index, core) in self.cores.iter().enumerate() {
+            let handshake = CoreHandshake {
+                seeks: true,
+                capability: replication_capability(
+                    channel.is_initiator(),
+                    &core.key_pair().public,
+                    channel.handshake_hash(),
+                ),
+            };
+            let (id, frame) = mux.open_core(&self.discovery_keys[index], &handshake.encode()?)?;
+            channel.send(&frame).await?;
+            sessions.push(Session::new(index, id));
+        }
+        let mut replication = Replication {
+            cores: &mut self.cores,
+            sessions,
+            mux,
+            is_initiator: channel.is_initiator(),
+            handshake_hash: *channel.handshake_hash(),
+            next_request: 1,
+            frames: vec![],
+            peer: channel.remote_public_key().to_bytes(),
+            peers: &mut self.peers,
+            busy_since: None,
+            retry: self.retry,
+            rng: self.rng.as_ref(),
+            push_appends: self.push_appends,
+            remote_close: None,
+            progress: self.progress.clone(),
+        };
+        while !replication.is_finished() {
+            let frame = channel.receive().await?;
+            if let Err(err) = replication.receive(&frame).await {
+                self.close_reason = replication.remote_close.take();
+                if self.close_reason.is_none() {
+                    if let Ok(frames) = replication.close(&CloseReason::from(&err)) {
+                        for frame in frames {
+                            // The peer may be gone already, the error is what matters
+                            let _ = channel.send(&frame).await;
+                        }
+                    }
+                }
+                return Err(err);
+            }
+            replication.update_busy();
+            for frame in replication.frames.drain(..) {
+                channel.send(&frame).await?;
+            }
+            while let Some(frame) = replication.mux.next_frame() {
+                channel.send(&frame).await?;
+            }
+        }
+        if let Some(progress) = &self.progress {
+            progress.finish();
+        }
+        Ok(())
+    }
+
+    /// Open an encrypted channel to a peer, e.g. over a connected TCP stream, and replicate
+    /// the cores with it. The peer runs [`Replicator::serve`].
+    pub async fn connect<T: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        io: T,
+        static_key: &SigningKey,
+    ) -> Result<(), HypercoreError> {
+        let mut channel = handshake(io, true, static_key).await?;
+        self.replicate(&mut channel).await
+    }
+
+    /// Accept an encrypted channel from a peer running [`Replicator::connect`], e.g. over an
+    /// accepted TCP stream, and replicate the cores with it.
+    pub async fn serve<T: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        io: T,
+        static_key: &SigningKey,
+    ) -> Result<(), HypercoreError> {
+        let mut channel = handshake(io, false, static_key).await?;
+        self.replicate(&mut channel).await
+    }
+
+    fn position(&self, discovery_key: &[u8; 32]) -> Option<usize> {
+        self.discovery_keys
+            .iter()
+            .position(|key| key == discovery_key)
+    }
+}
+
+/// A request in flight.
+#[derive(Debug, Clone, Copy)]
+enum Inflight {
+    Block(u64),
+    Upgrade,
+}
+
+/// Request the peer had no data for.
+#[derive(Debug, Clone, Copy)]
+struct Failed {
+    /// Times the peer had no data
+    attempts: u32,
+    /// When to ask again, `None` once out of attempts
+    retry_at: Option<Instant>,
+}
+
+impl Failed {
+    /// The request failed once more after the given failures.
+    fn again(failed: Option<Failed>, retry: &RetryPolicy, rng: &dyn Rng, now: Instant) -> Self {
+        let attempts = failed.map_or(1, |failed| failed.attempts + 1);
+        Self {
+            attempts,
+            retry_at: retry.delay(attempts, rng).map(|delay| now + delay),
+        }
+    }
+
+    fn is_due(&self, now: Instant) -> bool {
+        self.retry_at.is_some_and(|retry_at| now >= retry_at)
+    }
+}
+
+/// Replication state of one core with the peer.
+#[derive(Debug)]
+struct Session {
+    /// Index of the core in the replicator
+    core: usize,
+    /// Local id of the mux channel
+    channel: u64,
+    /// The peer rejected or closed the channel
+    ended: bool,
+    /// Last state of the peer
+    remote: Option<Synchronize>,
+    /// Blocks the peer announced
+    remote_has: Spans,
+    /// Requests in flight, and when they were sent
+    inflight: HashMap<u64, (Inflight, Instant)>,
+    /// Blocks the peer couldn't send
+    unavailable: HashMap<u64, Failed>,
+    /// The peer couldn't upgrade to its length
+    upgrade_failed: Option<Failed>,
+    /// Blocks before this were downloaded
+    cursor: u64,
+    /// Fork, length and downloading sent in the last synchronize
+    synced: Option<(u64, u64, bool)>,
+    /// Fork and length of the peer last pushed an append entry
+    pushed: Option<(u64, u64)>,
+    /// Nothing left to download
+    done: bool,
+}
+
+impl Session {
+    fn new(core: usize, channel: u64) -> Self {
+        Self {
+            core,
+            channel,
+            ended: false,
+            remote: None,
+            remote_has: Spans::default(),
+            inflight: HashMap::new(),
+            unavailable: HashMap::new(),
+            upgrade_failed: None,
+            cursor: 0,
+            synced: None,
+            pushed: None,
+            done: false,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.ended
+            || (self.done
+                && self
+                    .remote
+                    .as_ref()
+                    .is_some_and(|remote| !remote.downloading))
+    }
+}
+
+/// State of one [`Replicator::replicate`] call.
+struct Replication<'a> {
+    cores: &'a mut [Hypercore],
+    sessions: Vec<Session>,
+    mux: Mux,
+    is_initiator: bool,
+    handshake_hash: [u8; 64],
+    next_request: u64,
+    /// Control frames to send before the queued messages
+    frames: Vec<Vec<u8>>,
+    /// Static public key of the peer
+    peer: [u8; 32],
+    peers: &'a mut PeerRanking,
+    /// Requests have been in flight since
+    busy_since: Option<Instant>,
+    retry: RetryPolicy,
+    rng: &'a dyn Rng,
+    push_appends: bool,
+    /// Reason the peer ended the replication with
+    remote_close: Option<CloseReason>,
+    progress: Option<Progress>,
+}
+
+impl Replication<'_> {
+    fn is_finished(&self) -> bool {
+        self.sessions.iter().all(Session::is_finished)
+    }
+
+    /// Handle a received frame.
+    async fn receive(&mut self, frame: &[u8]) -> Result<(), HypercoreError> {
+        self.mux.receive(frame)?;
+        while let Some(event) = self.mux.next_event() {
+            self.on_event(event).await?;
+        }
+        Ok(())
+    }
+
+    /// Frames telling the peer why the replication ends and closing every open channel.
+    /// Queued messages are dropped.
+    fn close(&mut self, reason: &CloseReason) -> Result<Vec<Vec<u8>>, HypercoreError> {
+        let message = Message::Extension(Extension {
+            name: CLOSE_EXTENSION.to_string(),
+            message: reason.encode()?,
+        });
+        let body = message.encode_body()?;
+        let mut frames = vec![];
+        for session in &self.sessions {
+            if self.mux.is_open(session.channel) {
+                frames.push(self.mux.send(session.channel, message.type_id(), &body)?);
+                frames.push(self.mux.close(session.channel)?);
+            }
+        }
+        Ok(frames)
+    }
+
+    /// Record the time requests were in flight once none are.
+    fn update_busy(&mut self) {
+        let busy = self
+            .sessions
+            .iter()
+            .any(|session| !session.inflight.is_empty());
+        match (busy, self.busy_since) {
+            (true, None) => self.busy_since = Some(self.peers.now()),
+            (false, Some(since)) => {
+                let busy = self.peers.now().saturating_duration_since(since);
+                self.peers.record_busy(&self.peer, busy);
+                self.busy_since = None;
+            }
+            _ => {}
+        }
+    }
+
+    async fn on_event(&mut self, event: MuxEvent) -> Result<(), HypercoreError> {
+        match event {
+            MuxEvent::RemoteOpen { protocol, id } => {
+                tracing::debug!("Rejected channel {protocol} of a core not replicated");
+                if let Some(frame) = self.mux.reject(&protocol, &id)? {
+                    self.frames.push(frame);
+                }
+            }
+            MuxEvent::Opened { channel, handshake } => {
+                let index = self.session(channel)?;
+                self.on_open(index, &handshake)?;
+            }
+            MuxEvent::Rejected { channel } | MuxEvent::Closed { channel } => {
+                let index = self.session(channel)?;
+                self.sessions[index].ended = true;
+            }
+            MuxEvent::Message {
+                channel,
+                message_type,
+                message,
+            } => {
+                let index = self.session(channel)?;
+                let message = Message::decode_body(message_type, &message)?;
+                if let Some(reply) = self.on_message(index, message).await? {
+                    self.send(index, &reply)?;
+                }
+                self.advance(index).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_open(&mut self, index: usize, handshake: &[u8]) -> Result<(), HypercoreError> {
+        let core = &self.cores[self.sessions[index].core];
+        let handshake = CoreHandshake::decode(handshake)?;
+        verify_replication_capability(
+            self.is_initiator,
+            &core.key_pair().public,
+            &self.handshake_hash,
+            &handshake.capability,
+        )?;
+
+        // Announce the blocks we have before our length, so the peer knows them all once it
+        // gets the synchronize
+        let mut ranges = vec![];
+        for present in core.bitfield_snapshot() {
+            ranges.extend(core.readable_ranges(present, &self.peer));
+        }
+        for range in ranges {
+            let range = Message::Range(Range {
+                drop: false,
+                start: range.start,
+                length: range.end - range.start,
+            });
+            self.send(index, &range)?;
+        }
+        self.sync(index)
+    }
+
+    /// Handle a message of the peer, returning the reply.
+    async fn on_message(
+        &mut self,
+        index: usize,
+        message: Message,
+    ) -> Result<Option<Message>, HypercoreError> {
+        let session = &mut self.sessions[index];
+        let core = &mut self.cores[session.core];
+        match message {
+            Message::Synchronize(sync) => {
+                let info = core.info();
+                let behind = (sync.fork, sync.length);
+                let push = self.push_appends
+                    && session.pushed != Some(behind)
+                    && sync.fork == info.fork
+                    && sync.length < info.length
+                    && info.length - sync.length <= MAX_PUSHED_BLOCKS
+                    && core
+                        .readable_ranges(sync.length..info.length, &self.peer)
+                        .first()
+                        == Some(&(sync.length..info.length));
+                session.remote = Some(sync);
+                if push {
+                    if let Some(entry) = core.append_entry(behind.1).await? {
+                        session.pushed = Some(behind);
+                        return Ok(Some(Message::Extension(Extension {
+                            name: APPEND_ENTRY_EXTENSION.to_string(),
+                            message: entry.encode()?,
+                        })));
+                    }
+                }
+            }
+            Message::Range(range) => {
+                let span = range.start..range.start.saturating_add(range.length);
+                if range.drop {
+                    session.remote_has.remove(span);
+                } else {
+                    session.cursor = session.cursor.min(span.start);
+                    session.remote_has.insert(span);
+                }
+            }
+            Message::Bitfield(bitfield) => {
+                let covered = (bitfield.bitfield.len() as u64)
+                    .checked_mul(32)
+                    .and_then(|bits| bitfield.start.checked_add(bits))
+                    .is_some_and(|end| end <= MAX_BITFIELD_END);
+                if !covered {
+                    tracing::debug!("Dropped bitfield starting at block {}", bitfield.start);
+                    return Ok(None);
+                }
+                let runs = bitfield_runs(bitfield.start, &bitfield.bitfield);
+                if let Some(first) = runs.first() {
+                    session.cursor = session.cursor.min(first.start);
+                }
+                session.remote_has.extend(runs);
+            }
+            Message::Request(request) => {
+                return Ok(Some(answer(core, request, &self.peer).await));
+            }
+            Message::Data(data) => {
+                let Some((inflight, sent)) = session.inflight.remove(&data.request) else {
+                    tracing::debug!("Dropped data of unknown request {}", data.request);
+                    return Ok(None);
+                };
+                let block = data.block.as_ref().map(|block| block.index);
+                let bytes = data.block.as_ref().map_or(0, |block| block.value.len());
+                self.peers.record(&self.peer, sent, bytes as u64);
+                let upgraded = data.upgrade.as_ref().is_some_and(|upgrade| {
+                    upgrade.start.saturating_add(upgrade.length) <= core.info().length
+                });
+                if matches!(inflight, Inflight::Upgrade) && upgraded {
+                    // An append entry got here first
+                    return Ok(None);
+                }
+                core.verify_and_apply_proof(&data.into_proof()).await?;
+                if let Inflight::Block(requested) = inflight {
+                    if block == Some(requested) {
+                        if let Some(progress) = &self.progress {
+                            progress.advance(1, bytes as u64);
+                        }
+                        session.unavailable.remove(&requested);
+                        return Ok(Some(Message::Range(Range {
+                            drop: false,
+                            start: requested,
+                            length: 1,
+                        })));
+                    } else {
+                        let failed = session.unavailable.get(&requested).copied();
+                        let failed = Failed::again(failed, &self.retry, self.rng, self.peers.now());
+                        session.unavailable.insert(requested, failed);
+                    }
+                }
+            }
+            Message::NoData(NoData { request }) => {
+                let Some((inflight, sent)) = session.inflight.remove(&request) else {
+                    return Ok(None);
+                };
+                self.peers.record_no_data(&self.peer, sent);
+                match inflight {
+                    Inflight::Block(block) => {
+                        let failed = session.unavailable.get(&block).copied();
+                        let failed = Failed::again(failed, &self.retry, self.rng, self.peers.now());
+                        session.unavailable.insert(block, failed);
+                    }
+                    Inflight::Upgrade => {
+                        let failed = session.upgrade_failed;
+                        let failed = Failed::again(failed, &self.retry, self.rng, self.peers.now());
+                        session.upgrade_failed = Some(failed);
+                    }
+                }
+            }
+            Message::Extension(extension) if extension.name == CLOSE_EXTENSION => {
+                let reason = CloseReason::decode(&extension.message)?;
+                if !reason.is_error() {
+                    session.ended = true;
+                    return Ok(None);
+                }
+                let context = format!("Peer ended the replication. {reason}");
+                self.remote_close = Some(reason);
+                return Err(HypercoreError::InvalidOperation { context });
+            }
+            Message::Extension(extension)
+                if self.push_appends && extension.name == APPEND_ENTRY_EXTENSION =>
+            {
+                let entry = AppendEntry::decode(&extension.message)?;
+                if core.apply_append_entry(&entry).await? {
+                    if let Some(progress) = &self.progress {
+                        let blocks = entry.values.len() as u64;
+                        let bytes = entry.values.iter().map(|value| value.len() as u64).sum();
+                        progress.set_total(progress.total() + blocks);
+                        progress.advance(blocks, bytes);
+                    }
+                    session.upgrade_failed = None;
+                    return Ok(Some(Message::Range(Range {
+                        drop: false,
+                        start: entry.start,
+                        length: entry.end() - entry.start,
+                    })));
+                }
+            }
+            // Every block is announced and requests are answered right away
+            Message::Cancel(_) | Message::Want(_) | Message::Unwant(_) | Message::Extension(_) => {}
+        }
+        Ok(None)
+    }
+
+    /// Request what the peer can give us, and tell it when that changed.
+    async fn advance(&mut self, index: usize) -> Result<(), HypercoreError> {
+        let session = &mut self.sessions[index];
+        let core = &mut self.cores[session.core];
+        let Some(remote) = session.remote.clone() else {
+            return Ok(());
+        };
+        let info = core.info();
+        let now = self.peers.now();
+        let mut requests = vec![];
+        if remote.fork != info.fork {
+            tracing::debug!(
+                "Not downloading from fork {} of the peer, at fork {}",
+                remote.fork,
+                info.fork
+            );
+        } else {
+            let upgrading = session
+                .inflight
+                .values()
+                .any(|(inflight, _)| matches!(inflight, Inflight::Upgrade));
+            let upgrade_due = session
+                .upgrade_failed
+                .is_none_or(|failed| failed.is_due(now));
+            let upgrade = core.fast_forward_request(remote.length);
+            if upgrade.is_some() && !upgrading && upgrade_due {
+                requests.push((
+                    Inflight::Upgrade,
+                    Request {
+                        id: 0,
+                        fork: info.fork,
+                        block: None,
+                        hash: None,
+                        seek: None,
+                        upgrade,
+                    },
+                ));
+            }
+
+            let requested: HashSet<u64> = session
+                .inflight
+                .values()
+                .filter_map(|(inflight, _)| match inflight {
+                    Inflight::Block(block) => Some(*block),
+                    Inflight::Upgrade => None,
+                })
+                .collect();
+            let mut cursor = None;
+            'spans: for span in session.remote_has.spans() {
+                for block in span.start.max(session.cursor)..span.end.min(info.length) {
+                    if session.inflight.len() + requests.len() >= MAX_INFLIGHT {
+                        break 'spans;
+                    }
+                    if core.has(block) {
+                        continue;
+                    }
+                    cursor.get_or_insert(block);
+                    let due = session
+                        .unavailable
+                        .get(&block)
+                        .is_none_or(|failed| failed.is_due(now));
+                    if requested.contains(&block) || !due {
+                        continue;
+                    }
+                    let nodes = core.missing_nodes(block).await?;
+                    requests.push((
+                        Inflight::Block(block),
+                        Request {
+                            id: 0,
+                            fork: info.fork,
+                            block: Some(RequestBlock {
+                                index: block,
+                                nodes,
+                            }),
+                            hash: None,
+                            seek: None,
+                            upgrade: None,
+                        },
+                    ));
+                }
+            }
+            if requests.is_empty() {
+                session.cursor = cursor.unwrap_or(info.length);
+            }
+        }
+        session.done = requests.is_empty() && session.inflight.is_empty();
+        if let Some(progress) = &self.progress {
+            let blocks = requests
+                .iter()
+                .filter(|(inflight, _)| matches!(inflight, Inflight::Block(_)))
+                .count() as u64;
+            progress.set_total(progress.total() + blocks);
+        }
+
+        for (inflight, mut request) in requests {
+            request.id = self.next_request;
+            self.next_request += 1;
+            let sent = self.peers.now();
+            self.sessions[index]
+                .inflight
+                .insert(request.id, (inflight, sent));
+            self.send(index, &Message::Request(request))?;
+        }
+        self.sync(index)
+    }
+
+    /// Send our state if it changed since it was last sent.
+    fn sync(&mut self, index: usize) -> Result<(), HypercoreError> {
+        let session = &self.sessions[index];
+        let info = self.cores[session.core].info();
+        let state = (info.fork, info.length, !session.done);
+        if session.synced == Some(state) {
+            return Ok(());
+        }
+        let message = Message::Synchronize(Synchronize {
+            fork: info.fork,
+            length: info.length,
+            remote_length: session.remote.as_ref().map_or(0, |remote| remote.length),
+            downloading: !session.done,
+            uploading: true,
+            can_upgrade: true,
+        });
+        self.send(index, &message)?;
+        self.sessions[index].synced = Some(state);
+        Ok(())
+    }
+
+    fn send(&mut self, index: usize, message: &Message) -> Result<(), HypercoreError> {
+        self.mux.enqueue(
+            self.sessions[index].channel,
+            message.priority(),
+            message.type_id(),
+            &message.encode_body()?,
+        )
+    }
+
+    fn session(&self, channel: u64) -> Result<usize, HypercoreError> {
+        self.sessions
+            .iter()
+            .position(|session| session.channel == channel)
+            .ok_or_else(|| HypercoreError::InvalidOperation {
+                context: format!("No core on channel {channel}"),
+            })
+    }
+}
+
+/// Answer a request of the peer with a proof, or no data if it can't be proven or the block is
+/// restricted to other peers.
+async fn answer(core: &mut Hypercore, request: Request, peer: &[u8; 32]) -> Message {
+    let no_data = Message::NoData(NoData {
+        request: request.id,
+    });
+    if request.fork != core.info().fork
+        || request
+            .block
+            .as_ref()
+            .is_some_and(|block| !core.has(block.index) || !core.is_readable_by(block.index, peer))
+    {
+        return no_data;
+    }
+    match core
+        .create_proof(request.block, request.hash, request.seek, request.upgrade)
+        .await
+    {
+        Ok(Some(proof)) => Message::Data(Data::new(request.id, proof)),
+        Ok(None) => no_data,
+        Err(err) => {
+            tracing::debug!("Could not answer request {}: {err}", request.id);
+            no_data
+        }
+    }
+}
+
+/// Sorted, disjoint ranges of blocks.
+#[derive(Debug, Default)]
+struct Spans(Vec<Span<u64>>);
+
+impl Spans {
+    fn spans(&self) -> &[Span<u64>] {
+        &self.0
+    }
+
+    fn insert(&mut self, span: Span<u64>) {
+        if span.is_empty() {
+            return;
+        }
+        let mut merged = span;
+        self.0.retain(|other| {
+            let overlaps = other.start <= merged.end && merged.start <= other.end;
+            if overlaps {
+                merged = merged.start.min(other.start)..merged.end.max(other.end);
+            }
+            !overlaps
+        });
+        let index = self.0.partition_point(|other| other.start < merged.start);
+        self.0.insert(index, merged);
+    }
+
+    /// Insert many spans at once, sorting and merging in one pass rather than one insert each.
+    fn extend(&mut self, spans: impl IntoIterator<Item = Span<u64>>) {
+        self.0
+            .extend(spans.into_iter().filter(|span| !span.is_empty()));
+        self.0.sort_unstable_by_key(|span| span.start);
+        let mut merged: Vec<Span<u64>> = Vec::with_capacity(self.0.len());
+        for span in self.0.drain(..) {
+            match merged.last_mut() {
+                Some(last) if span.start <= last.end => last.end = last.end.max(span.end),
+                _ => merged.push(span),
+            }
+        }
+        self.0 = merged;
+    }
+
+    fn remove(&mut self, span: Span<u64>) {
+        let mut spans = Vec::with_capacity(self.0.len() + 1);
+        for other in self.0.drain(..) {
+            if other.end <= span.start || span.end <= other.start {
+                spans.push(other);
+                continue;
+            }
+            if other.start < span.start {
+                spans.push(other.start..span.start);
+            }
+            if span.end < other.end {
+                spans.push(span.end..other.end);
+            }
+        }
+        self.0 = spans;
+    }
+}
+
+/// Runs of set bits of a bitfield message, in order. The caller checks that the blocks
+/// covered fit in a u64.
+fn bitfield_runs(start: u64, words: &[u32]) -> Vec<Span<u64>> {
+    let mut runs: Vec<Span<u64>> = vec![];
+    for (word_index, word) in words.iter().enumerate() {
+        let offset = start + word_index as u64 * 32;
+        let mut bits = u64::from(*word);
+        let mut position = 0;
+        while bits != 0 {
+            let zeros = u64::from(bits.trailing_zeros());
+            bits >>= zeros;
+            let ones = u64::from(bits.trailing_ones());
+            bits >>= ones;
+            let run = offset + position + zeros..offset + position + zeros + ones;
+            position += zeros + ones;
+            match runs.last_mut() {
+                Some(last) if last.end == run.start => last.end = run.end,
+                _ => runs.push(run),
+            }
+        }
+    }
+    runs
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::core::tests::{create_hypercore_with_data, create_hypercore_with_data_and_key_pair};
+    use crate::crypto::generate_signing_key;
+    use crate::replication::CloseCode;
+    use crate::{PartialKeypair, SeededRng};
+    use async_std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    async fn clone_of(main: &Hypercore, length: u64) -> Result<Hypercore, HypercoreError> {
+        create_hypercore_with_data_and_key_pair(
+            length,
+            PartialKeypair {
+                public: main.key_pair().public,
+                secret: None,
+            },
+        )
+        .await
+    }
+
+    #[async_std::test]
+    async fn replicate_syncs_cores_both_ways() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(40).await?;
+        let clone = clone_of(&main, 0).await?;
+        // The writer of this core cleared blocks only its clone has
+        let mut partial = create_hypercore_with_data(6).await?;
+        let mut partial_clone = clone_of(&partial, 0).await?;
+        let upgrade = partial_clone.fast_forward_request(6);
+        let proof = partial.create_proof(None, None, None, upgrade).await?;
+        partial_clone
+            .verify_and_apply_proof(&proof.unwrap())
+            .await?;
+        for index in [1, 4] {
+            let nodes = partial_clone.missing_nodes(index).await?;
+            let block = RequestBlock { index, nodes };
+            let proof = partial.create_proof(Some(block), None, None, None).await?;
+            partial_clone
+                .verify_and_apply_proof(&proof.unwrap())
+                .await?;
+            partial.clear(index, index + 1).await?;
+        }
+        let only_local = create_hypercore_with_data(3).await?;
+
+        let mut a = Replicator::new();
+        a.add_core(main)?;
+        a.add_core(partial)?;
+        a.add_core(only_local)?;
+        let duplicate = clone_of(&a.cores[0], 0).await?;
+        assert!(a.add_core(duplicate).is_err());
+        let mut b = Replicator::new();
+        b.add_core(clone)?;
+        b.add_core(partial_clone)?;
+        let progress = Progress::new(0);
+        b.set_progress(progress.clone());
+
+        let (left, right) = UnixStream::pair()?;
+        let (a_key, b_key) = (generate_signing_key(), generate_signing_key());
+        futures::try_join!(a.connect(left, &a_key), b.serve(right, &b_key))?;
+
+        // Every request to the peer was timed
+        let a_peer = a.peers().get(&b_key.verifying_key().to_bytes()).unwrap();
+        let b_peer = b.peers().get(&a_key.verifying_key().to_bytes()).unwrap();
+        assert_eq!(a.peers().ranked().len(), 1);
+        assert_eq!(a_peer.bytes(), 4);
+        assert_eq!(b_peer.bytes(), 10 * 2 + 30 * 3 + 4 * 2);
+        assert_eq!(b_peer.rtt().count(), 40 + 4 + 1);
+        assert_eq!(progress.total(), 40 + 4);
+        assert_eq!(progress.completed(), 40 + 4);
+        assert_eq!(progress.bytes(), b_peer.bytes());
+        assert!(progress.is_finished());
+        assert!(b_peer.busy() > Duration::ZERO);
+        assert!(b_peer.throughput().is_some());
+
+        let a = a.into_cores();
+        let b = b.into_cores();
+        assert_eq!(b[0].info().contiguous_length, 40);
+        for i in 0..40 {
+            assert_eq!(b[0].get(i).await?.unwrap(), format!("#{i}").as_bytes());
+        }
+        assert_eq!(a[1].info().contiguous_length, 6);
+        assert_eq!(a[1].get(4).await?.unwrap(), b"#4");
+        assert_eq!(b[1].info().contiguous_length, 6);
+        assert_eq!(b[1].get(5).await?.unwrap(), b"#5");
+        assert_eq!(a[2].info().length, 3);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn replicate_pushes_append_entries() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(5).await?;
+        let clone = clone_of(&main, 0).await?;
+        let mut a = Replicator::new();
+        a.set_push_appends(true);
+        a.add_core(main)?;
+        let mut b = Replicator::new();
+        b.set_push_appends(true);
+        b.add_core(clone)?;
+
+        let (left, right) = UnixStream::pair()?;
+        let (a_key, b_key) = (generate_signing_key(), generate_signing_key());
+        futures::try_join!(a.connect(left, &a_key), b.serve(right, &b_key))?;
+
+        // The blocks came with the entry, not with proofs
+        let b_peer = b.peers().get(&a_key.verifying_key().to_bytes()).unwrap();
+        assert_eq!(b_peer.bytes(), 0);
+        let b = b.into_cores();
+        assert_eq!(b[0].info().contiguous_length, 5);
+        assert_eq!(b[0].get(4).await?.unwrap(), b"#4");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn replicate_sends_close_reason_on_invalid_proofs() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(10).await?;
+        let mut clone = clone_of(&main, 0).await?;
+        let upgrade = clone.fast_forward_request(10);
+        let proof = main.create_proof(None, None, None, upgrade).await?;
+        clone.verify_and_apply_proof(&proof.unwrap()).await?;
+        // Same key, other blocks
+        let mut forked = create_hypercore_with_data_and_key_pair(0, main.key_pair.clone()).await?;
+        for i in 0..12 {
+            forked.append(format!("forked #{i}").as_bytes()).await?;
+        }
+        let mut a = Replicator::new();
+        a.add_core(clone)?;
+        let mut b = Replicator::new();
+        b.add_core(forked)?;
+
+        let (left, right) = UnixStream::pair()?;
+        let (a_key, b_key) = (generate_signing_key(), generate_signing_key());
+        let (a_result, b_result) = futures::join!(a.connect(left, &a_key), b.serve(right, &b_key));
+        assert!(matches!(
+            a_result,
+            Err(HypercoreError::InvalidSignature { .. })
+        ));
+        assert!(a.close_reason().is_none());
+        assert!(b_result.is_err());
+        let reason = b.close_reason().unwrap();
+        assert_eq!(reason.code, CloseCode::InvalidSignature);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn replicate_withholds_restricted_blocks() -> Result<(), HypercoreError> {
+        let reader_key = generate_signing_key();
+        let mut main = create_hypercore_with_data(10).await?;
+        main.restrict(4..7, &[reader_key.verifying_key().to_bytes()])
+            .await?;
+        let mut server = Replicator::new();
+        server.add_core(main)?;
+        let server_key = generate_signing_key();
+
+        for (key, blocks) in [(reader_key, 10), (generate_signing_key(), 7)] {
+            let mut client = Replicator::new();
+            client.add_core(clone_of(&server.cores[0], 0).await?)?;
+            let (left, right) = UnixStream::pair()?;
+            futures::try_join!(client.connect(left, &key), server.serve(right, &server_key))?;
+            let clone = &client.cores[0];
+            assert_eq!(clone.info().length, 10);
+            assert_eq!((0..10).filter(|i| clone.has(*i)).count(), blocks);
+            assert_eq!(clone.has(5), blocks == 10);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn unavailable_requests_back_off() {
+        let retry = RetryPolicy {
+            max_attempts: 3,
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        };
+        let mut replicator = Replicator::new();
+        assert_eq!(replicator.retry_policy(), &RetryPolicy::default());
+        replicator.set_retry_policy(retry);
+        assert_eq!(replicator.retry_policy(), &retry);
+
+        let now = Instant::now();
+        let failed = Failed::again(None, &retry, &OsRandom, now);
+        assert_eq!(failed.attempts, 1);
+        assert!(!failed.is_due(now + Duration::from_millis(99)));
+        assert!(failed.is_due(now + Duration::from_millis(100)));
+        let failed = Failed::again(Some(failed), &retry, &OsRandom, now);
+        assert_eq!(failed.retry_at, Some(now + Duration::from_millis(200)));
+
+        // Out of attempts the request is never due again
+        let failed = Failed::again(Some(failed), &retry, &OsRandom, now);
+        assert_eq!(failed.retry_at, None);
+        assert!(!failed.is_due(now + Duration::from_secs(3600)));
+        assert!(!Failed::again(None, &RetryPolicy::never(), &OsRandom, now).is_due(now));
+    }
+
+    #[test]
+    fn seeded_retries_are_reproducible() {
+        let retry = RetryPolicy {
+            max_attempts: 5,
+            jitter: 0.5,
+            ..RetryPolicy::default()
+        };
+        let now = Instant::now();
+        let schedule = |seed| {
+            let mut replicator = Replicator::new();
+            replicator.set_rng(Arc::new(SeededRng::new(seed)));
+            let mut failed = None;
+            let mut schedule = vec![];
+            for _ in 0..4 {
+                let next = Failed::again(failed, &retry, replicator.rng.as_ref(), now);
+                schedule.push(next.retry_at);
+                failed = Some(next);
+            }
+            schedule
+        };
+        assert_eq!(schedule(1), schedule(1));
+        assert_ne!(schedule(1), schedule(2));
+    }
+
+    #[test]
+    fn bitfields_insert_runs_of_blocks() {
+        assert_eq!(
+            bitfield_runs(64, &[0x8000_000f, 0x1, 0, u32::MAX, 0x5]),
+            vec![64..68, 95..97, 160..193, 194..195]
+        );
+        assert_eq!(bitfield_runs(0, &[0xaaaa_aaaa]).len(), 16);
+
+        let mut spans = Spans::default();
+        spans.insert(10..20);
+        spans.extend([0..2, 19..30, 40..41, 2..4, 5..5]);
+        assert_eq!(spans.spans(), &[0..4, 10..30, 40..41]);
+    }
+}
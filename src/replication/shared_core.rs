@@ -1,12 +1,12 @@
 //! Implementation of a Hypercore that can have multiple owners. Along with implementations of all
 //! the hypercore traits.
 use crate::{
-    AppendOutcome, Hypercore, Info, PartialKeypair, Proof, RequestBlock, RequestSeek,
-    RequestUpgrade,
+    AppendOutcome, Hypercore, HypercoreError, Info, PartialKeypair, Proof, RequestBlock,
+    RequestSeek, RequestUpgrade, SignedHead,
 };
 use async_broadcast::Receiver;
 use async_lock::Mutex;
-use std::{future::Future, sync::Arc};
+use std::{future::Future, sync::Arc, time::Duration};
 
 use super::{
     CoreInfo, CoreMethods, CoreMethodsError, Event, ReplicationMethods, ReplicationMethodsError,
@@ -26,8 +26,83 @@ impl SharedCore {
     pub fn from_hypercore(core: Hypercore) -> Self {
         SharedCore(Arc::new(Mutex::new(core)))
     }
+
+    /// Request a block from peers (see: [`crate::Hypercore::request_block`]). Requests from all
+    /// owners of this core for the same block share a single [`Event::Get`].
+    pub async fn request_block(&self, index: u64) -> Option<Receiver<()>> {
+        self.0.lock().await.request_block(index)
+    }
+
+    /// Start a read transaction pinning the current fork and length, so that a sequence of
+    /// gets sees a consistent state even if other owners append or truncate meanwhile.
+    pub async fn read_txn(&self) -> SharedReadTxn {
+        let core = self.0.lock().await;
+        SharedReadTxn {
+            fork: core.tree.fork,
+            length: core.tree.length,
+            core: self.clone(),
+        }
+    }
+
+    /// Background task that verifies one random block every `interval`, see
+    /// [`crate::Hypercore::scrub_random`]. This crate has no runtime of its own, so pass the
+    /// sleep function of yours and spawn the returned future, e.g.
+    /// `tokio::spawn(core.scrubber(interval, tokio::time::sleep))`. The task ends once all
+    /// other owners of the core have been dropped, or on the first error.
+    pub fn scrubber<F, S>(
+        &self,
+        interval: Duration,
+        sleep: F,
+    ) -> impl Future<Output = Result<(), HypercoreError>> + Send + 'static
+    where
+        F: Fn(Duration) -> S + Send + 'static,
+        S: Future<Output = ()> + Send,
+    {
+        let core = Arc::downgrade(&self.0);
+        async move {
+            loop {
+                sleep(interval).await;
+                let Some(core) = core.upgrade() else {
+                    return Ok(());
+                };
+                core.lock().await.scrub_random(1).await?;
+            }
+        }
+    }
 }
 
+/// Read transaction on a [`SharedCore`], see [`SharedCore::read_txn`]. Locks the core only for
+/// the duration of each get, so other owners can keep appending.
+#[derive(Debug, Clone)]
+pub struct SharedReadTxn {
+    core: SharedCore,
+    fork: u64,
+    length: u64,
+}
+
+impl SharedReadTxn {
+    /// Pinned fork.
+    pub fn fork(&self) -> u64 {
+        self.fork
+    }
+
+    /// Pinned length.
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// Read value at given index of the pinned state, see [`Hypercore::get_pinned`].
+    pub async fn get(&self, index: u64) -> Result<Option<Vec<u8>>, HypercoreError> {
+        self.core
+            .0
+            .lock()
+            .await
+            .get_pinned(self.fork, self.length, index)
+            .await
+    }
+}
+
+#[allow(clippy::manual_async_fn)]
 impl CoreInfo for SharedCore {
     fn info(&self) -> impl Future<Output = Info> + Send {
         async move {
@@ -44,6 +119,7 @@ impl CoreInfo for SharedCore {
     }
 }
 
+#[allow(clippy::manual_async_fn)]
 impl ReplicationMethods for SharedCore {
     fn verify_and_apply_proof(
         &self,
@@ -60,7 +136,7 @@ impl ReplicationMethods for SharedCore {
         index: u64,
     ) -> impl Future<Output = Result<u64, ReplicationMethodsError>> {
         async move {
-            let mut core = self.0.lock().await;
+            let core = self.0.lock().await;
             Ok(core.missing_nodes(index).await?)
         }
     }
@@ -73,7 +149,7 @@ impl ReplicationMethods for SharedCore {
         upgrade: Option<RequestUpgrade>,
     ) -> impl Future<Output = Result<Option<Proof>, ReplicationMethodsError>> {
         async move {
-            let mut core = self.0.lock().await;
+            let core = self.0.lock().await;
             Ok(core.create_proof(block, hash, seek, upgrade).await?)
         }
     }
@@ -81,8 +157,19 @@ impl ReplicationMethods for SharedCore {
     fn event_subscribe(&self) -> impl Future<Output = Receiver<Event>> {
         async move { self.0.lock().await.event_subscribe() }
     }
+
+    fn check_signed_head(
+        &self,
+        proof: &Proof,
+    ) -> impl Future<Output = Result<Option<SignedHead>, ReplicationMethodsError>> {
+        async move {
+            let core = self.0.lock().await;
+            Ok(core.check_signed_head(proof).await?)
+        }
+    }
 }
 
+#[allow(clippy::manual_async_fn)]
 impl CoreMethods for SharedCore {
     fn has(&self, index: u64) -> impl Future<Output = bool> + Send {
         async move {
@@ -95,7 +182,7 @@ impl CoreMethods for SharedCore {
         index: u64,
     ) -> impl Future<Output = Result<Option<Vec<u8>>, CoreMethodsError>> + Send {
         async move {
-            let mut core = self.0.lock().await;
+            let core = self.0.lock().await;
             Ok(core.get(index).await?)
         }
     }
@@ -128,6 +215,7 @@ mod tests {
 
     use crate::core::tests::{create_hypercore_with_data, create_hypercore_with_data_and_key_pair};
     #[async_std::test]
+    #[allow(clippy::bool_assert_comparison)]
     async fn shared_core_methods() -> Result<(), CoreMethodsError> {
         let core = crate::core::tests::create_hypercore_with_data(0).await?;
         let core = SharedCore::from(core);
@@ -174,6 +262,19 @@ mod tests {
         Ok(())
     }
 
+    #[async_std::test]
+    async fn shared_core_read_txn() -> Result<(), HypercoreError> {
+        let core = SharedCore::from(create_hypercore_with_data(3).await?);
+        let txn = core.read_txn().await;
+        let other = core.clone();
+        other.0.lock().await.append(b"#3").await?;
+        assert_eq!(txn.length(), 3);
+        assert_eq!(txn.get(2).await?.unwrap(), b"#2");
+        assert!(txn.get(3).await.is_err());
+        assert_eq!(core.read_txn().await.length(), 4);
+        Ok(())
+    }
+
     #[async_std::test]
     async fn shared_core_replication_methods() -> Result<(), ReplicationMethodsError> {
         let main = create_hypercore_with_data(10).await?;
@@ -221,4 +322,28 @@ mod tests {
         assert!(clone.verify_and_apply_proof(&proof).await?);
         Ok(())
     }
+
+    #[async_std::test]
+    async fn shared_core_scrubber() -> Result<(), HypercoreError> {
+        use crate::common::{Store, StoreInfo};
+
+        let mut core = create_hypercore_with_data(10).await?;
+        // Corrupt the data of block 3
+        core.storage
+            .flush_info(StoreInfo::new_content(Store::Data, 6, b"XX"))
+            .await?;
+        let mut rx = core.event_subscribe();
+        let core = SharedCore::from(core);
+        let scrubber =
+            async_std::task::spawn(core.scrubber(Duration::from_millis(1), async_std::task::sleep));
+        loop {
+            if let Event::Corrupt(corrupt) = rx.recv().await.unwrap() {
+                assert_eq!(corrupt.index, 3);
+                break;
+            }
+        }
+        assert!(!core.0.lock().await.has(3));
+        drop(core);
+        scrubber.await
+    }
 }
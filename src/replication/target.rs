@@ -0,0 +1,134 @@
+//! Trait for replication protocol implementations that drive a core directly
+use async_broadcast::Receiver;
+use std::future::Future;
+use std::ops::Range;
+
+use super::Event;
+use crate::{
+    Hypercore, HypercoreError, Info, PartialKeypair, Proof, RequestBlock, RequestSeek,
+    RequestUpgrade,
+};
+
+/// Everything a replication protocol needs from a core: creating and applying proofs, knowing
+/// which blocks are present and being notified of changes. Lets third-party protocols, e.g.
+/// over libp2p or custom gossip, replicate a [`Hypercore`] without access to its internals.
+pub trait ReplicationTarget {
+    /// ref Core::info
+    fn info(&self) -> Info;
+    /// ref Core::key_pair
+    fn key_pair(&self) -> &PartialKeypair;
+    /// ref Core::create_proof
+    fn create_proof(
+        &mut self,
+        block: Option<RequestBlock>,
+        hash: Option<RequestBlock>,
+        seek: Option<RequestSeek>,
+        upgrade: Option<RequestUpgrade>,
+    ) -> impl Future<Output = Result<Option<Proof>, HypercoreError>> + Send;
+    /// ref Core::verify_and_apply_proof
+    fn verify_and_apply_proof(
+        &mut self,
+        proof: &Proof,
+    ) -> impl Future<Output = Result<bool, HypercoreError>> + Send;
+    /// ref Core::missing_nodes
+    fn missing_nodes(
+        &mut self,
+        index: u64,
+    ) -> impl Future<Output = Result<u64, HypercoreError>> + Send;
+    /// Ranges of blocks present locally, in order
+    fn bitfield_snapshot(&self) -> Vec<Range<u64>>;
+    /// Subscribe to core events, such as appends
+    fn event_subscribe(&self) -> Receiver<Event>;
+}
+
+impl ReplicationTarget for Hypercore {
+    fn info(&self) -> Info {
+        Hypercore::info(self)
+    }
+
+    fn key_pair(&self) -> &PartialKeypair {
+        Hypercore::key_pair(self)
+    }
+
+    async fn create_proof(
+        &mut self,
+        block: Option<RequestBlock>,
+        hash: Option<RequestBlock>,
+        seek: Option<RequestSeek>,
+        upgrade: Option<RequestUpgrade>,
+    ) -> Result<Option<Proof>, HypercoreError> {
+        Hypercore::create_proof(self, block, hash, seek, upgrade).await
+    }
+
+    async fn verify_and_apply_proof(&mut self, proof: &Proof) -> Result<bool, HypercoreError> {
+        Hypercore::verify_and_apply_proof(self, proof).await
+    }
+
+    async fn missing_nodes(&mut self, index: u64) -> Result<u64, HypercoreError> {
+        Hypercore::missing_nodes(self, index).await
+    }
+
+    fn bitfield_snapshot(&self) -> Vec<Range<u64>> {
+        Hypercore::bitfield_snapshot(self)
+    }
+
+    fn event_subscribe(&self) -> Receiver<Event> {
+        Hypercore::event_subscribe(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::{create_hypercore_with_data, create_hypercore_with_data_and_key_pair};
+
+    /// Replicates all blocks present in `from` to `to` using only the trait.
+    async fn replicate<A: ReplicationTarget, B: ReplicationTarget>(
+        from: &mut A,
+        to: &mut B,
+    ) -> Result<(), HypercoreError> {
+        let length = from.info().length;
+        let mut upgrade = Some(RequestUpgrade { start: 0, length });
+        for range in from.bitfield_snapshot() {
+            for index in range {
+                let nodes = to.missing_nodes(index).await?;
+                let proof = from
+                    .create_proof(
+                        Some(RequestBlock { index, nodes }),
+                        None,
+                        None,
+                        upgrade.take(),
+                    )
+                    .await?
+                    .unwrap();
+                assert!(to.verify_and_apply_proof(&proof).await?);
+            }
+        }
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn replication_target_replicates_core() -> Result<(), HypercoreError> {
+        let mut main = create_hypercore_with_data(10).await?;
+        main.clear(2, 5).await?;
+        assert_eq!(
+            ReplicationTarget::bitfield_snapshot(&main),
+            vec![0..2, 5..10]
+        );
+
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair.public,
+                secret: None,
+            },
+        )
+        .await?;
+        let mut rx = ReplicationTarget::event_subscribe(&clone);
+        replicate(&mut main, &mut clone).await?;
+        assert_eq!(clone.bitfield_snapshot(), vec![0..2, 5..10]);
+        assert_eq!(clone.get(7).await?.unwrap(), b"#7");
+        assert!(matches!(rx.try_recv(), Ok(Event::DataUpgrade(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,415 @@
+//! Persistent key/value store for library level settings, such as cache sizes, bandwidth caps
+//! and sync modes of individual cores, kept next to the cores of an application.
+use compact_encoding::{CompactEncoding, State};
+#[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+use random_access_disk::RandomAccessDisk;
+use random_access_memory::RandomAccessMemory;
+use std::collections::BTreeMap;
+#[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+use std::path::Path;
+use tracing::instrument;
+
+use crate::common::encode_hex;
+use crate::{storage::map_random_access_err, HypercoreError, StorageTraits, VerifyingKey};
+
+/// Version of the settings store format.
+const SETTINGS_VERSION: u8 = 1;
+
+/// Byte size of a slot locating the encoded settings.
+const SLOT_SIZE: u64 = 32;
+
+/// Encoded settings are stored after the two slots.
+const DATA_START: u64 = 2 * SLOT_SIZE;
+
+/// Value of a setting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SettingValue {
+    /// Boolean flag
+    Bool(bool),
+    /// Unsigned number, e.g. a size or a rate
+    U64(u64),
+    /// Text, e.g. the name of a mode
+    String(String),
+    /// Raw bytes
+    Bytes(Vec<u8>),
+}
+
+impl From<bool> for SettingValue {
+    fn from(value: bool) -> Self {
+        SettingValue::Bool(value)
+    }
+}
+
+impl From<u64> for SettingValue {
+    fn from(value: u64) -> Self {
+        SettingValue::U64(value)
+    }
+}
+
+impl From<&str> for SettingValue {
+    fn from(value: &str) -> Self {
+        SettingValue::String(value.to_string())
+    }
+}
+
+impl From<String> for SettingValue {
+    fn from(value: String) -> Self {
+        SettingValue::String(value)
+    }
+}
+
+impl From<Vec<u8>> for SettingValue {
+    fn from(value: Vec<u8>) -> Self {
+        SettingValue::Bytes(value)
+    }
+}
+
+/// Location of the encoded settings, as recorded in a slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Slot {
+    /// Flush the slot was written by, the newest valid slot wins
+    seq: u64,
+    offset: u64,
+    length: u64,
+    /// CRC32 of the encoded settings
+    checksum: u32,
+}
+
+/// Persistent settings. Every change is written to storage right away, the whole store is
+/// small enough to be rewritten each time.
+///
+/// Like the header of the oplog, the store has two checksummed slots, written alternately.
+/// A flush writes the settings where they don't overlap the current ones, syncs, and only
+/// then points the other slot at them, so a crash at any point leaves the previous settings
+/// readable.
+///
+/// Settings of a single core are namespaced with [`Settings::core_key`].
+#[derive(Debug)]
+pub struct Settings {
+    storage: Box<dyn StorageTraits + Send>,
+    values: BTreeMap<String, SettingValue>,
+    /// Index and content of the slot written last
+    current: Option<(u64, Slot)>,
+}
+
+impl Settings {
+    /// Open settings from the given storage.
+    #[instrument(err, skip_all)]
+    pub async fn open(mut storage: Box<dyn StorageTraits + Send>) -> Result<Self, HypercoreError> {
+        let length = storage.len().await.map_err(map_random_access_err)?;
+        if length < DATA_START {
+            return Ok(Self {
+                storage,
+                values: BTreeMap::new(),
+                current: None,
+            });
+        }
+        let slots = storage
+            .read(0, DATA_START)
+            .await
+            .map_err(map_random_access_err)?;
+        let mut current: Option<(u64, Slot, Vec<u8>)> = None;
+        for index in 0..2 {
+            let start = (index * SLOT_SIZE) as usize;
+            let Some(slot) = decode_slot(&slots[start..start + SLOT_SIZE as usize]) else {
+                continue;
+            };
+            if current
+                .as_ref()
+                .is_some_and(|(_, newest, _)| newest.seq > slot.seq)
+                || slot.offset < DATA_START
+                || slot.offset.saturating_add(slot.length) > length
+            {
+                continue;
+            }
+            let buffer = storage
+                .read(slot.offset, slot.length)
+                .await
+                .map_err(map_random_access_err)?;
+            if crc32fast::hash(&buffer) == slot.checksum {
+                current = Some((index, slot, buffer));
+            }
+        }
+        let (values, current) = match current {
+            Some((index, slot, buffer)) => (decode_settings(&buffer)?, Some((index, slot))),
+            // A crash during the first flush leaves both slots empty
+            None if slots.iter().all(|byte| *byte == 0) => (BTreeMap::new(), None),
+            None => {
+                return Err(HypercoreError::InvalidChecksum {
+                    context: "No valid settings slot".to_string(),
+                });
+            }
+        };
+        Ok(Self {
+            storage,
+            values,
+            current,
+        })
+    }
+
+    /// New settings backed by a `RandomAccessMemory` instance.
+    pub async fn open_memory() -> Result<Self, HypercoreError> {
+        Self::open(Box::new(RandomAccessMemory::default())).await
+    }
+
+    /// Open settings from the `settings` file in the given root directory, e.g. the directory
+    /// that holds the storage directories of the cores.
+    #[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+    pub async fn open_disk(root: &Path) -> Result<Self, HypercoreError> {
+        let storage = RandomAccessDisk::open(root.join("settings"))
+            .await
+            .map_err(map_random_access_err)?;
+        Self::open(Box::new(storage)).await
+    }
+
+    /// Key of the setting `name` of the core with the given public key.
+    pub fn core_key(public_key: &VerifyingKey, name: &str) -> String {
+        format!("core/{}/{name}", encode_hex(public_key.as_bytes()))
+    }
+
+    /// Get a setting.
+    pub fn get(&self, key: &str) -> Option<&SettingValue> {
+        self.values.get(key)
+    }
+
+    /// Get a boolean setting, `None` if missing or not a boolean.
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.values.get(key) {
+            Some(SettingValue::Bool(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Get a number setting, `None` if missing or not a number.
+    pub fn get_u64(&self, key: &str) -> Option<u64> {
+        match self.values.get(key) {
+            Some(SettingValue::U64(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Get a text setting, `None` if missing or not text.
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        match self.values.get(key) {
+            Some(SettingValue::String(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Get a bytes setting, `None` if missing or not bytes.
+    pub fn get_bytes(&self, key: &str) -> Option<&[u8]> {
+        match self.values.get(key) {
+            Some(SettingValue::Bytes(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Iterate over all settings in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &SettingValue)> {
+        self.values.iter().map(|(key, value)| (key.as_str(), value))
+    }
+
+    /// Set and persist a setting.
+    #[instrument(err, skip(self, value))]
+    pub async fn set(
+        &mut self,
+        key: &str,
+        value: impl Into<SettingValue>,
+    ) -> Result<(), HypercoreError> {
+        self.values.insert(key.to_string(), value.into());
+        self.flush().await
+    }
+
+    /// Remove and persist a setting, returns true if it was set.
+    #[instrument(err, skip(self))]
+    pub async fn remove(&mut self, key: &str) -> Result<bool, HypercoreError> {
+        if self.values.remove(key).is_none() {
+            return Ok(false);
+        }
+        self.flush().await?;
+        Ok(true)
+    }
+
+    async fn flush(&mut self) -> Result<(), HypercoreError> {
+        let buffer = encode_settings(&self.values)?;
+        let length = buffer.len() as u64;
+        let (index, seq, offset) = match self.current {
+            None => (0, 1, DATA_START),
+            // Before the current settings if they fit there, after them otherwise
+            Some((index, current)) if current.offset - DATA_START >= length => {
+                (1 - index, current.seq + 1, DATA_START)
+            }
+            Some((index, current)) => (1 - index, current.seq + 1, current.offset + current.length),
+        };
+        let slot = Slot {
+            seq,
+            offset,
+            length,
+            checksum: crc32fast::hash(&buffer),
+        };
+        self.storage
+            .write(offset, &buffer)
+            .await
+            .map_err(map_random_access_err)?;
+        self.storage
+            .sync_all()
+            .await
+            .map_err(map_random_access_err)?;
+        self.storage
+            .write(index * SLOT_SIZE, &encode_slot(&slot))
+            .await
+            .map_err(map_random_access_err)?;
+        self.storage
+            .sync_all()
+            .await
+            .map_err(map_random_access_err)?;
+        self.current = Some((index, slot));
+        if offset == DATA_START {
+            // The previous settings are no longer needed
+            self.storage
+                .truncate(offset + length)
+                .await
+                .map_err(map_random_access_err)?;
+        }
+        Ok(())
+    }
+}
+
+fn encode_slot(slot: &Slot) -> [u8; SLOT_SIZE as usize] {
+    let mut buffer = [0; SLOT_SIZE as usize];
+    buffer[4..12].copy_from_slice(&slot.seq.to_le_bytes());
+    buffer[12..20].copy_from_slice(&slot.offset.to_le_bytes());
+    buffer[20..28].copy_from_slice(&slot.length.to_le_bytes());
+    buffer[28..32].copy_from_slice(&slot.checksum.to_le_bytes());
+    let checksum = crc32fast::hash(&buffer[4..]);
+    buffer[..4].copy_from_slice(&checksum.to_le_bytes());
+    buffer
+}
+
+/// Slot from its bytes, `None` if torn or never written.
+fn decode_slot(buffer: &[u8]) -> Option<Slot> {
+    let u64_at = |start: usize| u64::from_le_bytes(buffer[start..start + 8].try_into().unwrap());
+    let u32_at = |start: usize| u32::from_le_bytes(buffer[start..start + 4].try_into().unwrap());
+    if u32_at(0) != crc32fast::hash(&buffer[4..]) {
+        return None;
+    }
+    Some(Slot {
+        seq: u64_at(4),
+        offset: u64_at(12),
+        length: u64_at(20),
+        checksum: u32_at(28),
+    })
+}
+
+fn encode_settings(values: &BTreeMap<String, SettingValue>) -> Result<Box<[u8]>, HypercoreError> {
+    let mut state = State::new();
+    state.add_end(1)?; // Version
+    state.preencode(&(values.len() as u64))?;
+    for (key, value) in values {
+        state.preencode(key)?;
+        state.add_end(1)?; // Type
+        match value {
+            SettingValue::Bool(_) => state.add_end(1)?,
+            SettingValue::U64(value) => state.preencode(value)?,
+            SettingValue::String(value) => state.preencode(value)?,
+            SettingValue::Bytes(value) => state.preencode(value)?,
+        };
+    }
+    let mut buffer = state.create_buffer();
+    state.set_byte_to_buffer(SETTINGS_VERSION, &mut buffer)?;
+    state.encode(&(values.len() as u64), &mut buffer)?;
+    for (key, value) in values {
+        state.encode(key, &mut buffer)?;
+        match value {
+            SettingValue::Bool(value) => {
+                state.set_byte_to_buffer(0, &mut buffer)?;
+                state.set_byte_to_buffer(*value as u8, &mut buffer)?
+            }
+            SettingValue::U64(value) => {
+                state.set_byte_to_buffer(1, &mut buffer)?;
+                state.encode(value, &mut buffer)?
+            }
+            SettingValue::String(value) => {
+                state.set_byte_to_buffer(2, &mut buffer)?;
+                state.encode(value, &mut buffer)?
+            }
+            SettingValue::Bytes(value) => {
+                state.set_byte_to_buffer(3, &mut buffer)?;
+                state.encode(value, &mut buffer)?
+            }
+        };
+    }
+    Ok(buffer)
+}
+
+fn decode_settings(buffer: &[u8]) -> Result<BTreeMap<String, SettingValue>, HypercoreError> {
+    let mut state = State::from_buffer(buffer);
+    let version = state.decode_u8(buffer)?;
+    if version != SETTINGS_VERSION {
+        return Err(HypercoreError::InvalidOperation {
+            context: format!("Unknown settings version {version}"),
+        });
+    }
+    let length: u64 = state.decode(buffer)?;
+    let mut values = BTreeMap::new();
+    for _ in 0..length {
+        let key: String = state.decode(buffer)?;
+        let value = match state.decode_u8(buffer)? {
+            0 => SettingValue::Bool(state.decode_u8(buffer)? != 0),
+            1 => SettingValue::U64(state.decode(buffer)?),
+            2 => SettingValue::String(state.decode(buffer)?),
+            3 => SettingValue::Bytes(state.decode(buffer)?),
+            value_type => {
+                return Err(HypercoreError::InvalidOperation {
+                    context: format!("Unknown type {value_type} of setting {key}"),
+                });
+            }
+        };
+        values.insert(key, value);
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn torn_flush_keeps_previous_settings() -> Result<(), HypercoreError> {
+        let mut settings = Settings::open_memory().await?;
+        for value in 0..10u64 {
+            settings.set("value", value).await?;
+            let (index, slot) = settings.current.expect("Settings should be flushed");
+            assert_eq!((index, slot.seq), (value % 2, value + 1));
+        }
+        let (index, slot) = settings.current.expect("Settings should be flushed");
+        assert!(settings.storage.len().await.unwrap() <= DATA_START + 3 * slot.length);
+
+        // Settings written, but the crash came before the slot pointing at them
+        settings.values.insert("value".to_string(), 10.into());
+        let buffer = encode_settings(&settings.values)?;
+        settings
+            .storage
+            .write(slot.offset + slot.length, &buffer)
+            .await
+            .unwrap();
+        let mut settings = Settings::open(settings.storage).await?;
+        assert_eq!(settings.get_u64("value"), Some(9));
+        assert_eq!(settings.current, Some((index, slot)));
+
+        // Slot torn while written
+        let mut torn = encode_slot(&Slot {
+            seq: slot.seq + 1,
+            ..slot
+        });
+        torn[8] ^= 0xff;
+        settings
+            .storage
+            .write((1 - index) * SLOT_SIZE, &torn)
+            .await
+            .unwrap();
+        let settings = Settings::open(settings.storage).await?;
+        assert_eq!(settings.get_u64("value"), Some(9));
+        Ok(())
+    }
+}
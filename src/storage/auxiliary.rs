@@ -0,0 +1,96 @@
+//! Auxiliary stores of extensions, e.g. indexes kept next to a core. They are created by the
+//! factory of the [`Storage`] like the stores of the core, as `aux-<name>` files for disk
+//! storage, and are synced and destroyed together with them.
+use crate::common::{Store, StoreInfo, StoreInfoInstruction};
+use crate::{HypercoreError, Storage};
+
+/// Maximum byte length of the name of an auxiliary store.
+const MAX_AUX_NAME_LENGTH: usize = 64;
+
+/// Fails unless the name is a valid auxiliary store name: ASCII letters, digits, `-` and `_`,
+/// so that it is a file name on every platform.
+pub(crate) fn validate_aux_name(name: &str) -> Result<(), HypercoreError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_AUX_NAME_LENGTH
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_');
+    if valid {
+        Ok(())
+    } else {
+        Err(HypercoreError::BadArgument {
+            context: format!("Invalid auxiliary store name {name:?}"),
+        })
+    }
+}
+
+/// Auxiliary store of an extension, see [`Storage::aux`]. Its IO is measured by the
+/// [`crate::SlowIoWatchdog`] of the storage like that of the other stores.
+#[derive(Debug)]
+pub struct AuxStore<'a> {
+    storage: &'a mut Storage,
+    store: Store,
+}
+
+impl<'a> AuxStore<'a> {
+    pub(crate) fn new(storage: &'a mut Storage, name: &str) -> Self {
+        Self {
+            storage,
+            store: Store::Aux(name.to_string()),
+        }
+    }
+
+    /// Name of the store.
+    pub fn name(&self) -> &str {
+        match &self.store {
+            Store::Aux(name) => name,
+            _ => unreachable!("Auxiliary stores are Store::Aux"),
+        }
+    }
+
+    /// Byte length of the store.
+    pub async fn len(&mut self) -> Result<u64, HypercoreError> {
+        let info = self
+            .storage
+            .read_info(StoreInfoInstruction::new_size(self.store.clone(), 0))
+            .await?;
+        Ok(info.length.unwrap_or(0))
+    }
+
+    /// Whether the store is empty.
+    pub async fn is_empty(&mut self) -> Result<bool, HypercoreError> {
+        Ok(self.len().await? == 0)
+    }
+
+    /// Read `length` bytes at `offset`. Fails if they are past the end of the store.
+    pub async fn read(&mut self, offset: u64, length: u64) -> Result<Vec<u8>, HypercoreError> {
+        let info = self
+            .storage
+            .read_info(StoreInfoInstruction::new_content(
+                self.store.clone(),
+                offset,
+                length,
+            ))
+            .await?;
+        match info.data {
+            Some(data) => Ok(data.into_vec()),
+            None => Err(HypercoreError::InvalidOperation {
+                context: format!("Could not read {} at {offset}", self.store),
+            }),
+        }
+    }
+
+    /// Write the bytes at `offset`, growing the store if needed.
+    pub async fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), HypercoreError> {
+        self.storage
+            .flush_info(StoreInfo::new_content(self.store.clone(), offset, data))
+            .await
+    }
+
+    /// Truncate the store to `length` bytes.
+    pub async fn truncate(&mut self, length: u64) -> Result<(), HypercoreError> {
+        self.storage
+            .flush_info(StoreInfo::new_truncate(self.store.clone(), length))
+            .await
+    }
+}
@@ -0,0 +1,149 @@
+//! Coalescing of the writes of a flush. Flushing an append writes many small slices, e.g. a
+//! tree node per write, most of them next to each other. Merging touching slices into one
+//! write saves a syscall per slice on disk, as the backends don't take vectored writes.
+//!
+//! Reads are coalesced the same way: a proof or a byte offset needs a handful of tree nodes,
+//! often stored next to each other, which are then read at once.
+use std::borrow::Cow;
+
+use crate::common::{Store, StoreInfoInstruction, StoreInfoType};
+
+/// Merge touching and overlapping writes to the same store into one write each, in offset
+/// order. Where writes overlap, the later one wins, like when writing them in order.
+pub(crate) fn coalesce<'a>(writes: &[(u64, &'a [u8])]) -> Vec<(u64, Cow<'a, [u8]>)> {
+    let mut order: Vec<usize> = (0..writes.len()).collect();
+    order.sort_by_key(|&i| writes[i].0);
+
+    // Group the writes by the contiguous spans they cover
+    let mut groups: Vec<(u64, u64, Vec<usize>)> = vec![];
+    for i in order {
+        let (offset, data) = writes[i];
+        let end = offset + data.len() as u64;
+        match groups.last_mut() {
+            Some((_, group_end, members)) if offset <= *group_end => {
+                *group_end = (*group_end).max(end);
+                members.push(i);
+            }
+            _ => groups.push((offset, end, vec![i])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(start, end, mut members)| {
+            if let [i] = members[..] {
+                return (start, Cow::Borrowed(writes[i].1));
+            }
+            // Apply in the original order so later writes win
+            members.sort_unstable();
+            let mut buffer = vec![0; (end - start) as usize];
+            for i in members {
+                let (offset, data) = writes[i];
+                let position = (offset - start) as usize;
+                buffer[position..position + data.len()].copy_from_slice(data);
+            }
+            (start, Cow::Owned(buffer))
+        })
+        .collect()
+}
+
+/// Group the instructions into reads: touching and overlapping content reads of known length
+/// from the same store form a group, in offset order, every other instruction is a group of
+/// its own. Groups hold indices of the instructions.
+pub(crate) fn coalesce_reads(instructions: &[StoreInfoInstruction]) -> Vec<Vec<usize>> {
+    let mergeable = |instruction: &StoreInfoInstruction| {
+        instruction.info_type == StoreInfoType::Content
+            && instruction.length.is_some()
+            && !instruction.allow_partial
+    };
+    let mut order: Vec<usize> = (0..instructions.len()).collect();
+    order.sort_by_key(|&i| instructions[i].index);
+
+    let mut groups: Vec<Vec<usize>> = vec![];
+    // Per store read from, the group it last read into and where that group ends
+    let mut open: Vec<(&Store, usize, u64)> = vec![];
+    for i in order {
+        let instruction = &instructions[i];
+        if !mergeable(instruction) {
+            groups.push(vec![i]);
+            continue;
+        }
+        let end = instruction.index + instruction.length.unwrap_or(0);
+        match open
+            .iter_mut()
+            .find(|(store, _, _)| **store == instruction.store)
+        {
+            Some((_, group, group_end)) if instruction.index <= *group_end => {
+                *group_end = (*group_end).max(end);
+                groups[*group].push(i);
+            }
+            Some(open) => {
+                *open = (&instruction.store, groups.len(), end);
+                groups.push(vec![i]);
+            }
+            None => {
+                open.push((&instruction.store, groups.len(), end));
+                groups.push(vec![i]);
+            }
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesce_merges_touching_writes() {
+        let writes: [(u64, &[u8]); 5] =
+            [(8, b"cd"), (20, b"x"), (6, b"ab"), (10, b"ef"), (7, b"Z")];
+        let coalesced = coalesce(&writes);
+        assert_eq!(coalesced.len(), 2);
+        assert_eq!(coalesced[0], (6, Cow::Owned::<[u8]>(b"aZcdef".to_vec())));
+        assert!(matches!(coalesced[1], (20, Cow::Borrowed(b"x"))));
+        assert!(coalesce(&[]).is_empty());
+    }
+
+    #[test]
+    fn coalesce_reads_merges_touching_reads() {
+        let instructions = [
+            StoreInfoInstruction::new_content(Store::Tree, 80, 40),
+            StoreInfoInstruction::new_content(Store::Data, 40, 40),
+            StoreInfoInstruction::new_content(Store::Tree, 40, 40),
+            StoreInfoInstruction::new_content(Store::Tree, 200, 40),
+            StoreInfoInstruction::new_size(Store::Tree, 0),
+            StoreInfoInstruction::new_content(Store::Tree, 120, 40),
+        ];
+        let mut groups = coalesce_reads(&instructions);
+        groups.sort();
+        assert_eq!(groups, vec![vec![1], vec![2, 0, 5], vec![3], vec![4]]);
+        assert!(coalesce_reads(&[]).is_empty());
+    }
+
+    #[async_std::test]
+    async fn coalesced_reads_fall_back_on_misses() -> Result<(), crate::HypercoreError> {
+        use crate::{common::StoreInfo, Storage};
+        let mut storage = Storage::new_memory().await?;
+        let data: Vec<u8> = (0..120).collect();
+        storage
+            .flush_info(StoreInfo::new_content(Store::Tree, 0, &data))
+            .await?;
+
+        let read = |index| StoreInfoInstruction::new_content_allow_miss(Store::Tree, index, 40);
+        let infos = storage
+            .read_infos_to_vec(&[read(80), read(0), read(40)])
+            .await?;
+        let starts: Vec<u8> = infos
+            .iter()
+            .map(|info| info.data.as_ref().unwrap()[0])
+            .collect();
+        assert_eq!(starts, vec![80, 0, 40]);
+
+        // The merged read is out of bounds, so each is read on its own
+        let infos = storage.read_infos_to_vec(&[read(80), read(120)]).await?;
+        assert_eq!(infos[0].data.as_deref(), Some(&data[80..]));
+        assert!(infos[1].miss);
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+//! Detection of store files modified by another process, e.g. a user copying an older core
+//! over the directory while it is open, see [`crate::Storage::set_file_watch`]. Files are
+//! compared by length and modification time with those seen after our own writes, so it is a
+//! poll, cheap enough to run before every flush, not a notification.
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::store_file_name;
+use crate::Store;
+
+/// What a store file looked like. `None` fields of a missing file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    length: Option<u64>,
+    modified: Option<SystemTime>,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> io::Result<Self> {
+        match std::fs::metadata(path) {
+            Ok(metadata) => Ok(Self {
+                length: Some(metadata.len()),
+                modified: metadata.modified().ok(),
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self {
+                length: None,
+                modified: None,
+            }),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Stamps of the store files as last written by us.
+#[derive(Debug)]
+pub(crate) struct FileWatch {
+    dir: PathBuf,
+    stamps: Vec<(Store, Stamp)>,
+}
+
+impl FileWatch {
+    /// Watch the files of the given stores in the directory, as they are now.
+    pub(crate) fn new(dir: &Path, stores: &[Store]) -> io::Result<Self> {
+        let mut watch = Self {
+            dir: dir.to_path_buf(),
+            stamps: Vec::with_capacity(stores.len()),
+        };
+        for store in stores {
+            let stamp = Stamp::of(&watch.path(store))?;
+            watch.stamps.push((store.clone(), stamp));
+        }
+        Ok(watch)
+    }
+
+    fn path(&self, store: &Store) -> PathBuf {
+        self.dir.join(store_file_name(store))
+    }
+
+    /// Also watch the file of the store, as it is now.
+    pub(crate) fn watch(&mut self, store: &Store) -> io::Result<()> {
+        let stamp = Stamp::of(&self.path(store))?;
+        self.stamps.push((store.clone(), stamp));
+        Ok(())
+    }
+
+    /// Accept the current file of the store, after writing it ourselves.
+    pub(crate) fn update(&mut self, store: &Store) -> io::Result<()> {
+        let path = self.path(store);
+        if let Some((_, stamp)) = self.stamps.iter_mut().find(|(watched, _)| watched == store) {
+            *stamp = Stamp::of(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Stores whose files changed since we last wrote them.
+    pub(crate) fn changed(&self) -> io::Result<Vec<Store>> {
+        let mut changed = vec![];
+        for (store, stamp) in &self.stamps {
+            if Stamp::of(&self.path(store))? != *stamp {
+                changed.push(store.clone());
+            }
+        }
+        Ok(changed)
+    }
+}
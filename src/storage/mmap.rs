@@ -0,0 +1,91 @@
+//! Disk store reading through a memory map, see [`crate::Storage::new_disk_mmap`]. Reads of
+//! tree nodes and blocks are small and many, a proof takes dozens of them, and serving them
+//! from a map of the file saves a syscall each. Writes go to the file as usual.
+//!
+//! Memory maps are the one place of this crate with `unsafe` code: a map is only sound while
+//! nobody shrinks the file under it. This store drops its map before truncating, but another
+//! process truncating the file makes reads of the cut off pages crash with `SIGBUS`. Watch the
+//! files with [`crate::Storage::set_file_watch`] to learn about other processes writing them.
+#![allow(unsafe_code)]
+
+use memmap2::Mmap;
+use random_access_disk::RandomAccessDisk;
+use random_access_storage::{RandomAccess, RandomAccessError};
+use std::fs::File;
+use std::path::Path;
+
+/// [`RandomAccessDisk`] reading through a memory map of its file.
+#[derive(Debug)]
+pub(crate) struct MmapDisk {
+    disk: RandomAccessDisk,
+    file: File,
+    /// Map of the file as long as it was when mapped, `None` until read or after truncating
+    map: Option<Mmap>,
+}
+
+impl MmapDisk {
+    pub(crate) async fn open(path: &Path) -> Result<Self, RandomAccessError> {
+        // Opening the disk store creates the file
+        let disk = RandomAccessDisk::open(path).await?;
+        let file = File::open(path)?;
+        Ok(Self {
+            disk,
+            file,
+            map: None,
+        })
+    }
+
+    /// Map the file again if it grew past the map.
+    async fn remap(&mut self, end: u64) -> Result<(), RandomAccessError> {
+        let mapped = self.map.as_ref().map_or(0, |map| map.len() as u64);
+        if end <= mapped || end > self.disk.len().await? {
+            return Ok(());
+        }
+        // SAFETY: the map covers the file as long as the disk store has written it, and the
+        // store only shrinks the file after dropping the map, see `truncate`.
+        self.map = Some(unsafe { Mmap::map(&self.file)? });
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl RandomAccess for MmapDisk {
+    async fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), RandomAccessError> {
+        self.disk.write(offset, data).await
+    }
+
+    async fn read(&mut self, offset: u64, length: u64) -> Result<Vec<u8>, RandomAccessError> {
+        let end = offset.saturating_add(length);
+        self.remap(end).await?;
+        match &self.map {
+            Some(map) if end <= map.len() as u64 => Ok(map[offset as usize..end as usize].to_vec()),
+            // Out of bounds, let the disk store fail
+            _ => self.disk.read(offset, length).await,
+        }
+    }
+
+    async fn del(&mut self, offset: u64, length: u64) -> Result<(), RandomAccessError> {
+        // Deleting up to the end truncates
+        if offset.saturating_add(length) >= self.disk.len().await? {
+            self.map = None;
+        }
+        self.disk.del(offset, length).await
+    }
+
+    async fn truncate(&mut self, length: u64) -> Result<(), RandomAccessError> {
+        self.map = None;
+        self.disk.truncate(length).await
+    }
+
+    async fn len(&mut self) -> Result<u64, RandomAccessError> {
+        self.disk.len().await
+    }
+
+    async fn is_empty(&mut self) -> Result<bool, RandomAccessError> {
+        self.disk.is_empty().await
+    }
+
+    async fn sync_all(&mut self) -> Result<(), RandomAccessError> {
+        self.disk.sync_all().await
+    }
+}
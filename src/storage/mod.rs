@@ -1,13 +1,20 @@
 //! Save data to a desired storage backend.
 
 use futures::future::FutureExt;
-#[cfg(not(target_arch = "wasm32"))]
+use futures::lock::{Mutex, MutexGuard};
+#[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
 use random_access_disk::RandomAccessDisk;
 use random_access_memory::RandomAccessMemory;
 use random_access_storage::{RandomAccess, RandomAccessError};
+use std::collections::BTreeMap;
 use std::fmt::Debug;
-#[cfg(not(target_arch = "wasm32"))]
+use std::future::Future;
+#[cfg(feature = "unsafe_raw")]
+use std::ops::Range;
+#[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
 use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
 use tracing::instrument;
 
 use crate::{
@@ -15,17 +22,71 @@ use crate::{
     HypercoreError,
 };
 
+mod auxiliary;
+mod coalesce;
+#[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+mod file_watch;
+#[cfg(all(feature = "mmap", not(target_arch = "wasm32")))]
+mod mmap;
+mod watchdog;
+
+use auxiliary::validate_aux_name;
+pub use auxiliary::AuxStore;
+use coalesce::{coalesce, coalesce_reads};
+#[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+use file_watch::FileWatch;
+#[cfg(all(feature = "mmap", not(target_arch = "wasm32")))]
+use mmap::MmapDisk;
+use watchdog::measure;
+pub use watchdog::{IoOperation, SlowIo, SlowIoListener, SlowIoWatchdog};
+
 /// Supertrait for Storage
 pub trait StorageTraits: RandomAccess + Debug {}
 impl<T: RandomAccess + Debug> StorageTraits for T {}
 
+/// Backend of a store. Locked on reads, which share the storage, so that reads of different
+/// stores and of concurrent readers of a [`crate::Hypercore`] don't wait for each other's IO.
+/// Writes have the storage to themselves and skip the lock.
+type Backend = Mutex<Box<dyn StorageTraits + Send>>;
+
+/// Creates the backend of a store, see [`Storage::open`].
+type CreateStore = Arc<
+    dyn Fn(
+            Store,
+        ) -> Pin<
+            Box<
+                dyn Future<Output = Result<Box<dyn StorageTraits + Send>, RandomAccessError>>
+                    + Send,
+            >,
+        > + Send
+        + Sync,
+>;
+
 /// Save data to a desired storage backend.
-#[derive(Debug)]
 pub struct Storage {
-    tree: Box<dyn StorageTraits + Send>,
-    data: Box<dyn StorageTraits + Send>,
-    bitfield: Box<dyn StorageTraits + Send>,
-    oplog: Box<dyn StorageTraits + Send>,
+    tree: Backend,
+    data: Backend,
+    bitfield: Backend,
+    oplog: Backend,
+    /// Auxiliary stores opened so far, by name
+    aux: BTreeMap<String, Backend>,
+    create: CreateStore,
+    overwrite: bool,
+    slow_io: Option<Arc<SlowIoWatchdog>>,
+    /// Directory of the store files, for disk storage
+    #[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+    dir: Option<PathBuf>,
+    /// Watch of the store files for changes of other processes
+    #[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+    file_watch: Option<FileWatch>,
+}
+
+impl Debug for Storage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The backends would print their contents, in memory that includes the oplog header
+        // with the secret key
+        f.debug_struct("Storage").finish_non_exhaustive()
+    }
 }
 
 pub(crate) fn map_random_access_err(err: RandomAccessError) -> HypercoreError {
@@ -52,19 +113,37 @@ pub(crate) fn map_random_access_err(err: RandomAccessError) -> HypercoreError {
     }
 }
 
+/// All stores of the core, data first so that it is synced before what refers to it, and the
+/// oplog last.
+const STORES: [Store; 4] = [Store::Data, Store::Tree, Store::Bitfield, Store::Oplog];
+
+/// Name of the file of a store in the directory of disk storage.
+#[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+fn store_file_name(store: &Store) -> String {
+    match store {
+        Store::Tree => "tree".to_string(),
+        Store::Data => "data".to_string(),
+        Store::Bitfield => "bitfield".to_string(),
+        Store::Oplog => "oplog".to_string(),
+        Store::Aux(name) => format!("aux-{name}"),
+    }
+}
+
 impl Storage {
     /// Create a new instance. Takes a callback to create new storage instances and overwrite flag.
+    /// The callback is kept to create the auxiliary stores opened later, see [`Storage::aux`].
     pub async fn open<Cb>(create: Cb, overwrite: bool) -> Result<Self, HypercoreError>
     where
         Cb: Fn(
-            Store,
-        ) -> std::pin::Pin<
-            Box<
-                dyn std::future::Future<
-                        Output = Result<Box<dyn StorageTraits + Send>, RandomAccessError>,
-                    > + Send,
-            >,
-        >,
+                Store,
+            ) -> Pin<
+                Box<
+                    dyn Future<Output = Result<Box<dyn StorageTraits + Send>, RandomAccessError>>
+                        + Send,
+                >,
+            > + Send
+            + Sync
+            + 'static,
     {
         let mut tree = create(Store::Tree).await.map_err(map_random_access_err)?;
         let mut data = create(Store::Data).await.map_err(map_random_access_err)?;
@@ -89,18 +168,134 @@ impl Storage {
         }
 
         let instance = Self {
-            tree,
-            data,
-            bitfield,
-            oplog,
+            tree: Mutex::new(tree),
+            data: Mutex::new(data),
+            bitfield: Mutex::new(bitfield),
+            oplog: Mutex::new(oplog),
+            aux: BTreeMap::new(),
+            create: Arc::new(create),
+            overwrite,
+            slow_io: None,
+            #[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+            dir: None,
+            #[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+            file_watch: None,
         };
 
         Ok(instance)
     }
 
+    /// Measure the latency of every IO with the given watchdog, or stop measuring with `None`.
+    pub fn set_slow_io_watchdog(&mut self, watchdog: Option<SlowIoWatchdog>) {
+        self.slow_io = watchdog.map(Arc::new);
+    }
+
+    /// The watchdog measuring IO latency, if any.
+    pub fn slow_io_watchdog(&self) -> Option<&SlowIoWatchdog> {
+        self.slow_io.as_deref()
+    }
+
+    /// Watch the files of the stores of the core for changes made by another process, e.g. a
+    /// user copying files around while the core is open, or stop watching. While watching,
+    /// every flush first checks the files, and fails with
+    /// [`HypercoreError::ExternallyModified`] instead of writing over such changes. Files are
+    /// compared by length and modification time, so a change keeping both is missed. Fails for
+    /// storage that isn't on disk.
+    #[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+    pub fn set_file_watch(&mut self, watch: bool) -> Result<(), HypercoreError> {
+        self.file_watch = match (&self.dir, watch) {
+            (_, false) => None,
+            (Some(dir), true) => Some(FileWatch::new(dir, &self.stores())?),
+            (None, true) => {
+                return Err(HypercoreError::BadArgument {
+                    context: "Only files of disk storage can be watched".to_string(),
+                })
+            }
+        };
+        Ok(())
+    }
+
+    /// Are the store files watched, see [`Storage::set_file_watch`].
+    #[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+    pub fn is_file_watched(&self) -> bool {
+        self.file_watch.is_some()
+    }
+
+    /// Fail with [`HypercoreError::ExternallyModified`] if another process changed the
+    /// watched store files, see [`Storage::set_file_watch`].
+    pub fn check_files(&self) -> Result<(), HypercoreError> {
+        #[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+        if let Some(file_watch) = &self.file_watch {
+            let stores = file_watch.changed()?;
+            if !stores.is_empty() {
+                return Err(HypercoreError::ExternallyModified { stores });
+            }
+        }
+        Ok(())
+    }
+
+    /// Auxiliary store of an extension with the given name, e.g. an index of the blocks,
+    /// created with the callback of [`Storage::open`] on first use. Names are ASCII letters,
+    /// digits, `-` and `_`, and should be prefixed with the name of the extension. The store is
+    /// synced and destroyed with the core. The core keeps its block checksums, wanted ranges
+    /// and annotations in the stores `checksum`, `download` and `annotations`.
+    pub async fn aux(&mut self, name: &str) -> Result<AuxStore<'_>, HypercoreError> {
+        validate_aux_name(name)?;
+        if !self.aux.contains_key(name) {
+            let mut storage = (self.create)(Store::Aux(name.to_string()))
+                .await
+                .map_err(map_random_access_err)?;
+            if self.overwrite && storage.len().await.map_err(map_random_access_err)? > 0 {
+                storage.truncate(0).await.map_err(map_random_access_err)?;
+            }
+            self.aux.insert(name.to_string(), Mutex::new(storage));
+            #[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+            if let Some(file_watch) = &mut self.file_watch {
+                file_watch.watch(&Store::aux(name))?;
+            }
+        }
+        Ok(AuxStore::new(self, name))
+    }
+
+    /// Names of the auxiliary stores opened so far.
+    pub fn aux_names(&self) -> impl Iterator<Item = &str> {
+        self.aux.keys().map(String::as_str)
+    }
+
+    /// Delete the auxiliary store with the given name, e.g. to rebuild an index. For disk
+    /// storage, its file is removed.
+    #[instrument(err, skip(self))]
+    pub async fn remove_aux(&mut self, name: &str) -> Result<(), HypercoreError> {
+        self.aux(name).await?.truncate(0).await?;
+        let mut storage = self
+            .aux
+            .remove(name)
+            .expect("Auxiliary store was just opened")
+            .into_inner();
+        storage.sync_all().await.map_err(map_random_access_err)?;
+        drop(storage);
+        #[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+        if let Some(dir) = &self.dir {
+            let path = dir.join(store_file_name(&Store::Aux(name.to_string())));
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// All open stores, in the order they are synced: the oplog, which refers to the others,
+    /// goes last.
+    fn stores(&self) -> Vec<Store> {
+        let mut stores = STORES[..STORES.len() - 1].to_vec();
+        stores.extend(self.aux.keys().cloned().map(Store::Aux));
+        stores.push(Store::Oplog);
+        stores
+    }
+
     /// Read info from store based on given instruction. Convenience method to `read_infos`.
     pub(crate) async fn read_info(
-        &mut self,
+        &self,
         info_instruction: StoreInfoInstruction,
     ) -> Result<StoreInfo, HypercoreError> {
         let mut infos = self.read_infos_to_vec(&[info_instruction]).await?;
@@ -111,75 +306,181 @@ impl Storage {
 
     /// Read infos from stores based on given instructions
     pub(crate) async fn read_infos(
-        &mut self,
+        &self,
         info_instructions: &[StoreInfoInstruction],
     ) -> Result<Box<[StoreInfo]>, HypercoreError> {
         let infos = self.read_infos_to_vec(info_instructions).await?;
         Ok(infos.into_boxed_slice())
     }
 
-    /// Reads infos but retains them as a Vec
+    /// Reads infos but retains them as a Vec. Touching reads from the same store are read
+    /// at once, see [`coalesce_reads`].
     pub(crate) async fn read_infos_to_vec(
-        &mut self,
+        &self,
         info_instructions: &[StoreInfoInstruction],
     ) -> Result<Vec<StoreInfo>, HypercoreError> {
-        if info_instructions.is_empty() {
-            return Ok(vec![]);
-        }
-        let mut current_store: Store = info_instructions[0].store.clone();
-        let mut storage = self.get_random_access(&current_store);
-        let mut infos: Vec<StoreInfo> = Vec::with_capacity(info_instructions.len());
-        for instruction in info_instructions.iter() {
-            if instruction.store != current_store {
-                current_store = instruction.store.clone();
-                storage = self.get_random_access(&current_store);
+        let mut infos: Vec<Option<StoreInfo>> = info_instructions.iter().map(|_| None).collect();
+        for group in coalesce_reads(info_instructions) {
+            if let [i] = group[..] {
+                infos[i] = Some(self.read_instruction(&info_instructions[i]).await?);
+                continue;
             }
-            match instruction.info_type {
-                StoreInfoType::Content => {
-                    let read_length = match instruction.length {
-                        Some(length) => length,
-                        None => storage.len().await.map_err(map_random_access_err)?,
-                    };
-                    let read_result = storage.read(instruction.index, read_length).await;
-                    let info: StoreInfo = match read_result {
-                        Ok(buf) => Ok(StoreInfo::new_content(
-                            instruction.store.clone(),
-                            instruction.index,
-                            &buf,
-                        )),
-                        Err(RandomAccessError::OutOfBounds { length, .. }) => {
-                            if instruction.allow_miss {
-                                Ok(StoreInfo::new_content_miss(
-                                    instruction.store.clone(),
-                                    instruction.index,
-                                ))
-                            } else {
-                                Err(HypercoreError::InvalidOperation {
-                                    context: format!(
-                                        "Could not read from store {}, index {} / length {} is out of bounds for store length {}",
-                                        current_store,
-                                        instruction.index,
-                                        read_length,
-                                        length
-                                    ),
-                                })
-                            }
-                        }
-                        Err(e) => Err(map_random_access_err(e)),
-                    }?;
-                    infos.push(info);
+            match self.read_group(info_instructions, &group).await? {
+                Some(read) => {
+                    for (i, info) in group.into_iter().zip(read) {
+                        infos[i] = Some(info);
+                    }
                 }
-                StoreInfoType::Size => {
-                    let length = storage.len().await.map_err(map_random_access_err)?;
-                    infos.push(StoreInfo::new_size(
+                // Some read is out of bounds, which the instructions may allow
+                None => {
+                    for i in group {
+                        infos[i] = Some(self.read_instruction(&info_instructions[i]).await?);
+                    }
+                }
+            }
+        }
+        Ok(infos
+            .into_iter()
+            .map(|info| info.expect("Every instruction should have been read"))
+            .collect())
+    }
+
+    /// Read touching content instructions of one store at once. `None` if the read is out of
+    /// bounds.
+    async fn read_group(
+        &self,
+        info_instructions: &[StoreInfoInstruction],
+        group: &[usize],
+    ) -> Result<Option<Vec<StoreInfo>>, HypercoreError> {
+        let watchdog = self.slow_io.clone();
+        let store = &info_instructions[group[0]].store;
+        let span = |i: &usize| {
+            let instruction = &info_instructions[*i];
+            let length = instruction
+                .length
+                .expect("Coalesced reads should have a length");
+            (instruction.index, instruction.index + length)
+        };
+        let start = group.iter().map(|i| span(i).0).min().unwrap_or(0);
+        let end = group.iter().map(|i| span(i).1).max().unwrap_or(0);
+        let mut storage = self.lock_random_access(store).await;
+        let buf = match measure(
+            watchdog.as_deref(),
+            store,
+            IoOperation::Read,
+            start,
+            end - start,
+            storage.read(start, end - start),
+        )
+        .await
+        {
+            Ok(buf) => buf,
+            Err(RandomAccessError::OutOfBounds { .. }) => return Ok(None),
+            Err(e) => return Err(map_random_access_err(e)),
+        };
+        Ok(Some(
+            group
+                .iter()
+                .map(|i| {
+                    let (from, to) = span(i);
+                    StoreInfo::new_content(
+                        store.clone(),
+                        from,
+                        &buf[(from - start) as usize..(to - start) as usize],
+                    )
+                })
+                .collect(),
+        ))
+    }
+
+    /// Read the info of one instruction.
+    async fn read_instruction(
+        &self,
+        instruction: &StoreInfoInstruction,
+    ) -> Result<StoreInfo, HypercoreError> {
+        let watchdog = self.slow_io.as_deref();
+        let current_store = &instruction.store;
+        let mut storage = self.lock_random_access(current_store).await;
+        match instruction.info_type {
+            StoreInfoType::Content => {
+                let read_length = match instruction.length {
+                    Some(length) => length,
+                    None => measure(
+                        watchdog,
+                        current_store,
+                        IoOperation::Length,
+                        0,
+                        0,
+                        storage.len(),
+                    )
+                    .await
+                    .map_err(map_random_access_err)?,
+                };
+                let read_result = measure(
+                    watchdog,
+                    current_store,
+                    IoOperation::Read,
+                    instruction.index,
+                    read_length,
+                    storage.read(instruction.index, read_length),
+                )
+                .await;
+                match read_result {
+                    Ok(buf) => Ok(StoreInfo::new_content(
                         instruction.store.clone(),
                         instruction.index,
-                        length - instruction.index,
-                    ));
+                        &buf,
+                    )),
+                    Err(RandomAccessError::OutOfBounds { length, .. }) => {
+                        let store_length = storage.len().await.map_err(map_random_access_err)?;
+                        if instruction.allow_partial && instruction.index < store_length {
+                            let buf = storage
+                                .read(instruction.index, store_length - instruction.index)
+                                .await
+                                .map_err(map_random_access_err)?;
+                            Ok(StoreInfo::new_content(
+                                instruction.store.clone(),
+                                instruction.index,
+                                &buf,
+                            ))
+                        } else if instruction.allow_miss {
+                            Ok(StoreInfo::new_content_miss(
+                                instruction.store.clone(),
+                                instruction.index,
+                            ))
+                        } else {
+                            Err(HypercoreError::InvalidOperation {
+                                context: format!(
+                                    "Could not read from store {}, index {} / length {} is out of bounds for store length {}",
+                                    current_store,
+                                    instruction.index,
+                                    read_length,
+                                    length
+                                ),
+                            })
+                        }
+                    }
+                    Err(e) => Err(map_random_access_err(e)),
                 }
             }
+            StoreInfoType::Size => {
+                let length = measure(
+                    watchdog,
+                    current_store,
+                    IoOperation::Length,
+                    0,
+                    0,
+                    storage.len(),
+                )
+                .await
+                .map_err(map_random_access_err)?;
+                Ok(StoreInfo::new_size(
+                    instruction.store.clone(),
+                    instruction.index,
+                    length - instruction.index,
+                ))
+            }
         }
-        Ok(infos)
     }
 
     /// Flush info to storage. Convenience method to `flush_infos`.
@@ -192,38 +493,89 @@ impl Storage {
         if infos.is_empty() {
             return Ok(());
         }
+        self.check_files()?;
+        self.write_infos(infos).await?;
+        #[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+        if let Some(file_watch) = &mut self.file_watch {
+            let mut written: Vec<&Store> = infos.iter().map(|info| &info.store).collect();
+            written.dedup();
+            for store in written {
+                file_watch.update(store)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_infos(&mut self, infos: &[StoreInfo]) -> Result<(), HypercoreError> {
+        let watchdog = self.slow_io.clone();
+        let watchdog = watchdog.as_deref();
         let mut current_store: Store = infos[0].store.clone();
         let mut storage = self.get_random_access(&current_store);
-        for info in infos.iter() {
+        let mut position = 0;
+        while position < infos.len() {
+            let info = &infos[position];
             if info.store != current_store {
                 current_store = info.store.clone();
                 storage = self.get_random_access(&current_store);
             }
+            // Consecutive writes to the same store are coalesced
+            let writes: Vec<(u64, &[u8])> = infos[position..]
+                .iter()
+                .map_while(|info| match (&info.info_type, &info.data) {
+                    (StoreInfoType::Content, Some(data))
+                        if !info.miss && info.store == current_store =>
+                    {
+                        Some((info.index, &data[..]))
+                    }
+                    _ => None,
+                })
+                .collect();
+            if !writes.is_empty() {
+                position += writes.len();
+                for (index, data) in coalesce(&writes) {
+                    measure(
+                        watchdog,
+                        &current_store,
+                        IoOperation::Write,
+                        index,
+                        data.len() as u64,
+                        storage.write(index, &data),
+                    )
+                    .await
+                    .map_err(map_random_access_err)?;
+                }
+                continue;
+            }
+            position += 1;
             match info.info_type {
                 StoreInfoType::Content => {
-                    if !info.miss {
-                        if let Some(data) = &info.data {
-                            storage
-                                .write(info.index, data)
-                                .await
-                                .map_err(map_random_access_err)?;
-                        }
-                    } else {
-                        storage
-                            .del(
-                                info.index,
-                                info.length.expect("When deleting, length must be given"),
-                            )
-                            .await
-                            .map_err(map_random_access_err)?;
+                    // Content without data has nothing to write
+                    if info.miss {
+                        let length = info.length.expect("When deleting, length must be given");
+                        measure(
+                            watchdog,
+                            &current_store,
+                            IoOperation::Delete,
+                            info.index,
+                            length,
+                            storage.del(info.index, length),
+                        )
+                        .await
+                        .map_err(map_random_access_err)?;
                     }
                 }
                 StoreInfoType::Size => {
                     if info.miss {
-                        storage
-                            .truncate(info.index)
-                            .await
-                            .map_err(map_random_access_err)?;
+                        measure(
+                            watchdog,
+                            &current_store,
+                            IoOperation::Truncate,
+                            info.index,
+                            0,
+                            storage.truncate(info.index),
+                        )
+                        .await
+                        .map_err(map_random_access_err)?;
                     } else {
                         panic!("Flushing a size that isn't miss, is not supported");
                     }
@@ -233,12 +585,100 @@ impl Storage {
         Ok(())
     }
 
+    /// Sync all stores to disk. Data is synced before the tree, bitfield and oplog that
+    /// refer to it.
+    pub(crate) async fn sync_all(&mut self) -> Result<(), HypercoreError> {
+        let watchdog = self.slow_io.clone();
+        for store in self.stores() {
+            let storage = self.get_random_access(&store);
+            measure(
+                watchdog.as_deref(),
+                &store,
+                IoOperation::Sync,
+                0,
+                0,
+                storage.sync_all(),
+            )
+            .await
+            .map_err(map_random_access_err)?;
+        }
+        Ok(())
+    }
+
+    /// Read the raw bytes of a store in the given byte range, bypassing the hypercore. For tools
+    /// like debuggers and migrators: the layout of the stores is not a stable API.
+    #[cfg(feature = "unsafe_raw")]
+    pub async fn read_raw(
+        &mut self,
+        store: Store,
+        range: Range<u64>,
+    ) -> Result<Vec<u8>, HypercoreError> {
+        if let Store::Aux(name) = &store {
+            self.aux(name).await?;
+        }
+        if range.end < range.start {
+            return Err(HypercoreError::BadArgument {
+                context: format!("Invalid range {range:?}"),
+            });
+        }
+        let info = self
+            .read_info(StoreInfoInstruction::new_content(
+                store,
+                range.start,
+                range.end - range.start,
+            ))
+            .await?;
+        Ok(info.data.expect("Content info should have data").into_vec())
+    }
+
+    /// Write raw bytes to a store at the given byte offset, bypassing the hypercore. Writes that
+    /// don't keep the stores consistent corrupt the core, see [`Storage::read_raw`].
+    #[cfg(feature = "unsafe_raw")]
+    pub async fn write_raw(
+        &mut self,
+        store: Store,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), HypercoreError> {
+        if let Store::Aux(name) = &store {
+            self.aux(name).await?;
+        }
+        self.flush_info(StoreInfo::new_content(store, offset, data))
+            .await
+    }
+
+    fn backend(&self, store: &Store) -> &Backend {
+        match store {
+            Store::Tree => &self.tree,
+            Store::Data => &self.data,
+            Store::Bitfield => &self.bitfield,
+            Store::Oplog => &self.oplog,
+            Store::Aux(name) => self
+                .aux
+                .get(name)
+                .expect("Auxiliary store should be opened with Storage::aux"),
+        }
+    }
+
+    /// Lock the backend of a store for reading.
+    async fn lock_random_access(
+        &self,
+        store: &Store,
+    ) -> MutexGuard<'_, Box<dyn StorageTraits + Send>> {
+        self.backend(store).lock().await
+    }
+
     fn get_random_access(&mut self, store: &Store) -> &mut Box<dyn StorageTraits + Send> {
         match store {
-            Store::Tree => &mut self.tree,
-            Store::Data => &mut self.data,
-            Store::Bitfield => &mut self.bitfield,
-            Store::Oplog => &mut self.oplog,
+            Store::Tree => self.tree.get_mut(),
+            Store::Data => self.data.get_mut(),
+            Store::Bitfield => self.bitfield.get_mut(),
+            Store::Oplog => self.oplog.get_mut(),
+            Store::Aux(name) => self
+                .aux
+                .get_mut(name)
+                .expect("Auxiliary store should be opened with Storage::aux")
+                .get_mut(),
         }
     }
 
@@ -254,25 +694,118 @@ impl Storage {
     }
 
     /// New storage backed by a `RandomAccessDisk` instance.
-    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
     #[instrument(err)]
     pub async fn new_disk(dir: &PathBuf, overwrite: bool) -> Result<Self, HypercoreError> {
-        let storage = |store: Store| {
-            let dir = dir.clone();
+        let store_dir = dir.clone();
+        let storage = move |store: Store| {
+            let dir = store_dir.clone();
             async move {
-                let name = match store {
-                    Store::Tree => "tree",
-                    Store::Data => "data",
-                    Store::Bitfield => "bitfield",
-                    Store::Oplog => "oplog",
-                };
-                Ok(
-                    Box::new(RandomAccessDisk::open(dir.as_path().join(name)).await?)
-                        as Box<dyn StorageTraits + Send>,
-                )
+                Ok(Box::new(
+                    RandomAccessDisk::open(dir.as_path().join(store_file_name(&store))).await?,
+                ) as Box<dyn StorageTraits + Send>)
+            }
+            .boxed()
+        };
+        let mut instance = Self::open(storage, overwrite).await?;
+        instance.dir = Some(dir.clone());
+        Ok(instance)
+    }
+
+    /// New storage backed by `RandomAccessDisk` instances, reading the tree and data stores
+    /// through memory maps. Reading blocks and proofs then copies from the page cache without
+    /// a syscall per node. The file of a mapped store must not be truncated by another process
+    /// while open, see [`Storage::set_file_watch`].
+    #[cfg(all(feature = "mmap", not(target_arch = "wasm32")))]
+    #[instrument(err)]
+    pub async fn new_disk_mmap(dir: &PathBuf, overwrite: bool) -> Result<Self, HypercoreError> {
+        let store_dir = dir.clone();
+        let storage = move |store: Store| {
+            let dir = store_dir.clone();
+            async move {
+                let path = dir.as_path().join(store_file_name(&store));
+                Ok(match store {
+                    Store::Tree | Store::Data => {
+                        Box::new(MmapDisk::open(&path).await?) as Box<dyn StorageTraits + Send>
+                    }
+                    _ => Box::new(RandomAccessDisk::open(path).await?),
+                })
             }
             .boxed()
         };
-        Self::open(storage, overwrite).await
+        let mut instance = Self::open(storage, overwrite).await?;
+        instance.dir = Some(dir.clone());
+        Ok(instance)
+    }
+
+    /// Delete everything in the stores and close them. With `shred`, the oplog, which holds
+    /// the key pair, is first overwritten with zeros and synced, so that the secret key doesn't
+    /// linger in the freed blocks of the backend.
+    ///
+    /// For disk storage, the store files are removed, including those of auxiliary stores not
+    /// opened, and so is the directory if nothing else is left in it. Files not belonging to
+    /// the stores are never removed.
+    #[instrument(err, skip(self))]
+    pub async fn destroy(mut self, shred: bool) -> Result<(), HypercoreError> {
+        let watchdog = self.slow_io.clone();
+        if shred {
+            let oplog = self.oplog.get_mut();
+            let length = oplog.len().await.map_err(map_random_access_err)?;
+            if length > 0 {
+                measure(
+                    watchdog.as_deref(),
+                    &Store::Oplog,
+                    IoOperation::Write,
+                    0,
+                    length,
+                    oplog.write(0, &vec![0; length as usize]),
+                )
+                .await
+                .map_err(map_random_access_err)?;
+                oplog.sync_all().await.map_err(map_random_access_err)?;
+            }
+        }
+        for store in self.stores() {
+            let storage = self.get_random_access(&store);
+            if storage.len().await.map_err(map_random_access_err)? > 0 {
+                measure(
+                    watchdog.as_deref(),
+                    &store,
+                    IoOperation::Truncate,
+                    0,
+                    0,
+                    storage.truncate(0),
+                )
+                .await
+                .map_err(map_random_access_err)?;
+            }
+        }
+        self.sync_all().await?;
+
+        #[cfg(all(feature = "disk", not(target_arch = "wasm32")))]
+        if let Some(dir) = self.dir.take() {
+            // Close the files before removing them
+            drop(self);
+            for store in STORES {
+                let path = dir.join(store_file_name(&store));
+                if path.exists() {
+                    std::fs::remove_file(path)?;
+                }
+            }
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let name = entry.file_name();
+                let aux_name = name.to_str().and_then(|name| name.strip_prefix("aux-"));
+                if aux_name.is_some_and(|name| validate_aux_name(name).is_ok())
+                    && entry.file_type()?.is_file()
+                {
+                    std::fs::remove_file(entry.path())?;
+                }
+            }
+            if dir.exists() && std::fs::read_dir(&dir)?.next().is_none() {
+                std::fs::remove_dir(&dir)?;
+            }
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,148 @@
+//! Watchdog measuring the latency of storage IO, to spot failing disks or overloaded network
+//! backends before errors and timeouts cascade.
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{Clock, Store, SystemClock};
+
+/// Kind of storage IO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoOperation {
+    /// Read of a byte range
+    Read,
+    /// Write of a byte range
+    Write,
+    /// Delete of a byte range
+    Delete,
+    /// Truncate to an offset
+    Truncate,
+    /// Read of the store length
+    Length,
+    /// Sync to disk
+    Sync,
+}
+
+/// IO that took at least the threshold of the [`SlowIoWatchdog`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowIo {
+    /// Store the IO went to
+    pub store: Store,
+    /// Kind of IO
+    pub operation: IoOperation,
+    /// Byte offset of the IO
+    pub offset: u64,
+    /// Byte length of the IO, 0 if it has none
+    pub length: u64,
+    /// How long the IO took
+    pub elapsed: Duration,
+}
+
+/// Listener for slow IO, see [`SlowIoWatchdog::with_listener`].
+pub trait SlowIoListener: Debug + Send + Sync {
+    /// Called after every slow IO, on the task doing the IO, so it should return quickly.
+    fn on_slow_io(&self, slow_io: &SlowIo);
+}
+
+/// Measures every IO of a [`crate::Storage`] and reports those taking at least `threshold`:
+/// they are logged as warnings, passed to the listener and counted.
+#[derive(Debug)]
+pub struct SlowIoWatchdog {
+    threshold: Duration,
+    clock: Arc<dyn Clock>,
+    listener: Option<Arc<dyn SlowIoListener>>,
+    slow_count: AtomicU64,
+    /// Longest IO seen, in nanoseconds
+    max_elapsed: AtomicU64,
+}
+
+impl SlowIoWatchdog {
+    /// Create a watchdog reporting IO that takes at least `threshold`.
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            clock: Arc::new(SystemClock),
+            listener: None,
+            slow_count: AtomicU64::new(0),
+            max_elapsed: AtomicU64::new(0),
+        }
+    }
+
+    /// Call the listener for every slow IO.
+    pub fn with_listener(mut self, listener: Arc<dyn SlowIoListener>) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Measure with the given clock instead of the OS clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Latency at which IO counts as slow.
+    pub fn threshold(&self) -> Duration {
+        self.threshold
+    }
+
+    /// Number of slow IOs seen.
+    pub fn slow_count(&self) -> u64 {
+        self.slow_count.load(Ordering::Relaxed)
+    }
+
+    /// Latency of the slowest IO seen, slow or not.
+    pub fn max_elapsed(&self) -> Duration {
+        Duration::from_nanos(self.max_elapsed.load(Ordering::Relaxed))
+    }
+
+    /// Run the IO, reporting it if it takes at least the threshold.
+    pub(crate) async fn measure<F: Future>(
+        &self,
+        store: &Store,
+        operation: IoOperation,
+        offset: u64,
+        length: u64,
+        io: F,
+    ) -> F::Output {
+        let start = self.clock.now();
+        let output = io.await;
+        let elapsed = self.clock.now().saturating_duration_since(start);
+        self.max_elapsed.fetch_max(
+            elapsed.as_nanos().try_into().unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+        if elapsed >= self.threshold {
+            self.slow_count.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!(
+                "Slow {operation:?} on {store} store at {offset} for {length} bytes took {elapsed:?}"
+            );
+            if let Some(listener) = self.listener.as_ref() {
+                listener.on_slow_io(&SlowIo {
+                    store: store.clone(),
+                    operation,
+                    offset,
+                    length,
+                    elapsed,
+                });
+            }
+        }
+        output
+    }
+}
+
+/// Run the IO, measured by the watchdog if there is one.
+pub(crate) async fn measure<F: Future>(
+    watchdog: Option<&SlowIoWatchdog>,
+    store: &Store,
+    operation: IoOperation,
+    offset: u64,
+    length: u64,
+    io: F,
+) -> F::Output {
+    match watchdog {
+        Some(watchdog) => watchdog.measure(store, operation, offset, length, io).await,
+        None => io.await,
+    }
+}
@@ -0,0 +1,178 @@
+#[cfg(feature = "parallel")]
+use crate::HypercoreError;
+use crate::{crypto::Hash, Node};
+
+/// Smallest batch whose leaves are hashed in parallel, below that the hand-off to the thread
+/// pool costs more than it saves.
+#[cfg(feature = "parallel")]
+const PARALLEL_MIN_BATCH_LENGTH: usize = 32;
+
+/// Hash of a block, i.e. the hash of its leaf node in the merkle tree.
+pub fn hash_leaf(data: &[u8]) -> [u8; 32] {
+    to_array(&Hash::data(data))
+}
+
+/// Parent node of two sibling nodes, in either order.
+pub fn hash_parent(left: &Node, right: &Node) -> Node {
+    Node::new(
+        flat_tree::parent(left.index),
+        Hash::parent(left, right).as_bytes().to_vec(),
+        left.length + right.length,
+    )
+}
+
+/// Hash of the tree with the given roots, which is what gets signed for a length.
+pub fn hash_roots(roots: &[Node]) -> [u8; 32] {
+    to_array(&Hash::tree(roots))
+}
+
+/// Calculates the roots of a merkle tree block by block, without storing the tree. Produces
+/// the same roots and tree hash as appending the same blocks to an empty hypercore.
+#[derive(Debug, Clone, Default)]
+pub struct RootAccumulator {
+    roots: Vec<Node>,
+    length: u64,
+    byte_length: u64,
+}
+
+impl RootAccumulator {
+    /// Create an empty accumulator.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the next block.
+    pub fn push(&mut self, data: &[u8]) {
+        self.push_hash(hash_leaf(data), data.len() as u64);
+    }
+
+    /// Add the next block by its [`hash_leaf`] hash and byte length, e.g. when the hashes have
+    /// been calculated elsewhere.
+    pub fn push_hash(&mut self, hash: [u8; 32], byte_length: u64) {
+        let mut node = Node::new(self.length * 2, hash.to_vec(), byte_length);
+        while let Some(last) = self.roots.last() {
+            if flat_tree::sibling(node.index) != last.index {
+                break;
+            }
+            let left = self.roots.pop().expect("Root should exist");
+            node = hash_parent(&left, &node);
+        }
+        self.roots.push(node);
+        self.length += 1;
+        self.byte_length += byte_length;
+    }
+
+    /// Number of blocks added.
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// Total byte length of the blocks added.
+    pub fn byte_length(&self) -> u64 {
+        self.byte_length
+    }
+
+    /// Current roots of the tree.
+    pub fn roots(&self) -> &[Node] {
+        &self.roots
+    }
+
+    /// Current tree hash, see [`hash_roots`].
+    pub fn hash(&self) -> [u8; 32] {
+        hash_roots(&self.roots)
+    }
+}
+
+/// Hashes the leaves of appended batches. With the `parallel` feature, big batches are hashed
+/// on a thread pool: the global rayon pool by default, or a dedicated pool with the configured
+/// number of threads.
+#[derive(Debug, Default)]
+pub(crate) struct LeafHasher {
+    #[cfg(feature = "parallel")]
+    pool: Option<rayon::ThreadPool>,
+    #[cfg(feature = "parallel")]
+    sequential: bool,
+}
+
+impl LeafHasher {
+    /// Create a leaf hasher using the given number of threads, `None` for the global pool.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn new(threads: Option<usize>) -> Result<Self, HypercoreError> {
+        let Some(threads) = threads else {
+            return Ok(Self::default());
+        };
+        if threads <= 1 {
+            return Ok(Self {
+                pool: None,
+                sequential: true,
+            });
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("hypercore-hash-{i}"))
+            .build()
+            .map_err(|err| HypercoreError::InvalidOperation {
+                context: format!("Could not create hashing thread pool: {err}"),
+            })?;
+        Ok(Self {
+            pool: Some(pool),
+            sequential: false,
+        })
+    }
+
+    /// Leaf hashes of the given blocks, in order.
+    pub(crate) fn hash_leaves(&self, batch: &[&[u8]]) -> Vec<[u8; 32]> {
+        #[cfg(feature = "parallel")]
+        if !self.sequential && batch.len() >= PARALLEL_MIN_BATCH_LENGTH {
+            use rayon::prelude::*;
+            let hash = || batch.par_iter().map(|data| hash_leaf(data)).collect();
+            return match &self.pool {
+                Some(pool) => pool.install(hash),
+                None => hash(),
+            };
+        }
+        batch.iter().map(|data| hash_leaf(data)).collect()
+    }
+}
+
+fn to_array(hash: &Hash) -> [u8; 32] {
+    hash.as_bytes()
+        .try_into()
+        .expect("BLAKE2b-256 hash should be 32 bytes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::create_hypercore_with_data;
+    use crate::HypercoreError;
+
+    #[async_std::test]
+    async fn root_accumulator_matches_tree() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(11).await?;
+        let mut accumulator = RootAccumulator::new();
+        for i in 0..11 {
+            accumulator.push(format!("#{i}").as_bytes());
+        }
+        assert_eq!(accumulator.length(), hypercore.tree.length);
+        assert_eq!(accumulator.byte_length(), hypercore.tree.byte_length);
+        assert_eq!(accumulator.roots(), &hypercore.tree.roots[..]);
+        assert_eq!(
+            &accumulator.hash()[..],
+            Hash::tree(&hypercore.tree.roots).as_bytes()
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn leaf_hasher_matches_sequential_hashing() -> Result<(), HypercoreError> {
+        let blocks: Vec<Vec<u8>> = (0..100).map(|i| format!("#{i}").into_bytes()).collect();
+        let batch: Vec<&[u8]> = blocks.iter().map(|block| block.as_slice()).collect();
+        let expected: Vec<[u8; 32]> = batch.iter().map(|data| hash_leaf(data)).collect();
+        for threads in [None, Some(1), Some(4)] {
+            assert_eq!(LeafHasher::new(threads)?.hash_leaves(&batch), expected);
+        }
+        Ok(())
+    }
+}
@@ -5,15 +5,16 @@ use intmap::IntMap;
 #[cfg(feature = "cache")]
 use moka::sync::Cache;
 use std::convert::TryFrom;
+use std::ops::Range;
 
 #[cfg(feature = "cache")]
 use crate::common::cache::CacheOptions;
 use crate::common::{HypercoreError, NodeByteRange, Proof, ValuelessProof};
-use crate::crypto::Hash;
+use crate::crypto::{Hash, SignatureVerifier};
 use crate::oplog::HeaderTree;
 use crate::{
     common::{StoreInfo, StoreInfoInstruction},
-    Node, VerifyingKey,
+    Node,
 };
 use crate::{
     DataBlock, DataHash, DataSeek, DataUpgrade, RequestBlock, RequestSeek, RequestUpgrade, Store,
@@ -33,11 +34,25 @@ pub(crate) struct MerkleTree {
     unflushed: IntMap<Node>,
     truncated: bool,
     truncate_to: u64,
+    /// Read nodes from storage in pages of `TREE_PAGE_NODES` instead of one by one.
+    pub(crate) page_reads: bool,
     #[cfg(feature = "cache")]
     node_cache: Option<Cache<u64, Node>>,
 }
 
 const NODE_SIZE: u64 = 40;
+/// Number of nodes that fit into a 4KiB page, read as a unit when `page_reads` is set. The
+/// on-disk layout is not affected, pages are just consecutive groups of nodes.
+const TREE_PAGE_NODES: u64 = 4096 / NODE_SIZE;
+
+/// Result of [`MerkleTree::seek_local`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LocalSeek {
+    /// Index of the block containing the byte offset
+    Block(u64),
+    /// The children of this node, which contains the byte offset, are not stored locally
+    Missing(u64),
+}
 
 impl MerkleTree {
     /// Opens MerkleTree, based on read infos.
@@ -92,7 +107,7 @@ impl MerkleTree {
                 if length > 0 {
                     length /= 2;
                 }
-                let signature: Option<Signature> = if header_tree.signature.len() > 0 {
+                let signature: Option<Signature> = if !header_tree.signature.is_empty() {
                     Some(
                         Signature::try_from(&*header_tree.signature).map_err(|_err| {
                             HypercoreError::InvalidSignature {
@@ -116,6 +131,7 @@ impl MerkleTree {
                     unflushed: IntMap::new(),
                     truncated: false,
                     truncate_to: 0,
+                    page_reads: false,
                     signature,
                 }))
             }
@@ -164,9 +180,44 @@ impl MerkleTree {
         infos_to_flush.into_boxed_slice()
     }
 
+    /// Nodes currently in the node cache, in index order.
+    #[cfg(feature = "cache")]
+    pub(crate) fn cached_nodes(&self) -> Vec<Node> {
+        let Some(node_cache) = &self.node_cache else {
+            return vec![];
+        };
+        let mut nodes: Vec<Node> = node_cache.iter().map(|(_, node)| node).collect();
+        nodes.sort_by_key(|node| node.index);
+        nodes
+    }
+
+    /// Drop every node from the node cache, e.g. when the tree store was changed underneath.
+    #[cfg(feature = "cache")]
+    pub(crate) fn clear_node_cache(&self) {
+        if let Some(node_cache) = &self.node_cache {
+            node_cache.invalidate_all();
+        }
+    }
+
+    /// Insert nodes of this tree into the node cache, returns the number of nodes inserted.
+    #[cfg(feature = "cache")]
+    pub(crate) fn warm_node_cache(&self, nodes: Vec<Node>) -> usize {
+        let Some(node_cache) = &self.node_cache else {
+            return 0;
+        };
+        let mut inserted = 0;
+        for node in nodes {
+            if node.index < 2 * self.length {
+                node_cache.insert(node.index, node);
+                inserted += 1;
+            }
+        }
+        inserted
+    }
+
     /// Get storage byte range of given hypercore index
     pub(crate) fn byte_range(
-        &mut self,
+        &self,
         hypercore_index: u64,
         infos: Option<&[StoreInfo]>,
     ) -> Result<Either<Box<[StoreInfoInstruction]>, NodeByteRange>, HypercoreError> {
@@ -212,9 +263,55 @@ impl MerkleTree {
         }
     }
 
+    /// Locate the block containing given byte offset using only locally stored nodes.
+    pub(crate) fn seek_local(
+        &self,
+        bytes: u64,
+        infos: Option<&[StoreInfo]>,
+    ) -> Result<Either<Box<[StoreInfoInstruction]>, LocalSeek>, HypercoreError> {
+        if bytes >= self.byte_length {
+            return Err(HypercoreError::BadArgument {
+                context: format!(
+                    "Byte offset {bytes} is out of bounds of byte length {}",
+                    self.byte_length
+                ),
+            });
+        }
+        let nodes: IntMap<Option<Node>> = self.infos_to_nodes(infos)?;
+        let mut bytes = bytes;
+        for root in &self.roots {
+            if bytes >= root.length {
+                bytes -= root.length;
+                continue;
+            }
+            let mut index = root.index;
+            // Descend until the leaf containing the offset
+            while index % 2 != 0 {
+                let (left, right) =
+                    flat_tree::children(index).expect("Non-leaf node should have children");
+                match self.optional_node(left, &nodes)? {
+                    Either::Left(instruction) => {
+                        return Ok(Either::Left(vec![instruction].into_boxed_slice()));
+                    }
+                    Either::Right(None) => return Ok(Either::Right(LocalSeek::Missing(index))),
+                    Either::Right(Some(left_node)) => {
+                        if bytes < left_node.length {
+                            index = left;
+                        } else {
+                            bytes -= left_node.length;
+                            index = right;
+                        }
+                    }
+                }
+            }
+            return Ok(Either::Right(LocalSeek::Block(index / 2)));
+        }
+        unreachable!("Roots should cover the byte length")
+    }
+
     /// Get the byte offset given hypercore index
     pub(crate) fn byte_offset(
-        &mut self,
+        &self,
         hypercore_index: u64,
         infos: Option<&[StoreInfo]>,
     ) -> Result<Either<Box<[StoreInfoInstruction]>, u64>, HypercoreError> {
@@ -331,7 +428,7 @@ impl MerkleTree {
     /// https://github.com/holepunchto/hypercore/blob/9ce03363cb8938dbab53baba7d7cc9dde0508a7e/lib/merkle-tree.js#L1181
     /// The implementation should be rewritten to make it clearer.
     pub(crate) fn create_valueless_proof(
-        &mut self,
+        &self,
         block: Option<&RequestBlock>,
         hash: Option<&RequestBlock>,
         seek: Option<&RequestSeek>,
@@ -479,11 +576,7 @@ impl MerkleTree {
                     start: upgrade.start,
                     length: upgrade.length,
                     nodes: p.upgrade.expect("nodes need to be set"),
-                    additional_nodes: if let Some(additional_upgrade) = p.additional_upgrade {
-                        additional_upgrade
-                    } else {
-                        vec![]
-                    },
+                    additional_nodes: p.additional_upgrade.unwrap_or_default(),
                     signature: signature
                         .expect("signature needs to be set")
                         .to_bytes()
@@ -509,31 +602,26 @@ impl MerkleTree {
     pub(crate) fn verify_proof(
         &mut self,
         proof: &Proof,
-        public_key: &VerifyingKey,
+        verifier: &SignatureVerifier,
         infos: Option<&[StoreInfo]>,
     ) -> Result<Either<Box<[StoreInfoInstruction]>, MerkleTreeChangeset>, HypercoreError> {
-        let nodes: IntMap<Option<Node>> = self.infos_to_nodes(infos)?;
-        let mut instructions: Vec<StoreInfoInstruction> = Vec::new();
-        let mut changeset = self.changeset();
-
-        let mut unverified_block_root_node = verify_tree(
-            proof.block.as_ref(),
-            proof.hash.as_ref(),
-            proof.seek.as_ref(),
-            &mut changeset,
-        )?;
-        if let Some(upgrade) = proof.upgrade.as_ref() {
-            if verify_upgrade(
-                proof.fork,
-                upgrade,
-                unverified_block_root_node.as_ref(),
-                public_key,
-                &mut changeset,
-            )? {
-                unverified_block_root_node = None;
-            }
+        let (changeset, unverified_block_root_node) =
+            verify_proof_detached(proof, verifier, self.changeset())?;
+        match self.verify_block_root(unverified_block_root_node.as_ref(), infos)? {
+            Either::Left(instructions) => Ok(Either::Left(instructions)),
+            Either::Right(()) => Ok(Either::Right(changeset)),
         }
+    }
 
+    /// Second half of [`MerkleTree::verify_proof`]: checks the root of the proven block, that
+    /// the upgrade of the proof didn't cover, against the tree.
+    pub(crate) fn verify_block_root(
+        &mut self,
+        unverified_block_root_node: Option<&Node>,
+        infos: Option<&[StoreInfo]>,
+    ) -> Result<Either<Box<[StoreInfoInstruction]>, ()>, HypercoreError> {
+        let nodes: IntMap<Option<Node>> = self.infos_to_nodes(infos)?;
+        let mut instructions: Vec<StoreInfoInstruction> = Vec::new();
         if let Some(unverified_block_root_node) = unverified_block_root_node {
             let node_or_instruction =
                 self.required_node(unverified_block_root_node.index, &nodes)?;
@@ -556,7 +644,7 @@ impl MerkleTree {
         }
 
         if instructions.is_empty() {
-            Ok(Either::Right(changeset))
+            Ok(Either::Right(()))
         } else {
             Ok(Either::Left(instructions.into_boxed_slice()))
         }
@@ -564,7 +652,7 @@ impl MerkleTree {
 
     /// Attempts to get missing nodes from given index. NB: must be called in a loop.
     pub(crate) fn missing_nodes(
-        &mut self,
+        &self,
         index: u64,
         infos: Option<&[StoreInfo]>,
     ) -> Result<Either<Box<[StoreInfoInstruction]>, u64>, HypercoreError> {
@@ -597,6 +685,47 @@ impl MerkleTree {
         Ok(Either::Right(count))
     }
 
+    /// Instruction reading the leaf nodes of the blocks from the tree store, as needed by
+    /// [`MerkleTree::missing_leaves`].
+    pub(crate) fn leaves_instruction(blocks: &Range<u64>) -> StoreInfoInstruction {
+        StoreInfoInstruction::new_content_allow_partial(
+            Store::Tree,
+            NODE_SIZE * 2 * blocks.start,
+            NODE_SIZE * (2 * (blocks.end - blocks.start)).saturating_sub(1),
+        )
+    }
+
+    /// Blocks of the range whose leaf node is neither unflushed nor stored in `info`, the read
+    /// of [`MerkleTree::leaves_instruction`].
+    pub(crate) fn missing_leaves(&self, blocks: Range<u64>, info: &StoreInfo) -> Vec<u64> {
+        let data: &[u8] = info.data.as_deref().unwrap_or_default();
+        let first = 2 * blocks.start;
+        blocks
+            .filter(|block| {
+                let index = 2 * block;
+                if let Some(node) = self.unflushed.get(index) {
+                    return node.blank || (self.truncated && index >= 2 * self.truncate_to);
+                }
+                let start = ((index - first) * NODE_SIZE) as usize;
+                data.get(start + 8..start + NODE_SIZE as usize)
+                    .is_none_or(|hash| hash.iter().all(|byte| *byte == 0))
+            })
+            .collect()
+    }
+
+    /// Gets the node at given merkle tree index.
+    pub(crate) fn get_node(
+        &self,
+        index: u64,
+        infos: Option<&[StoreInfo]>,
+    ) -> Result<Either<Box<[StoreInfoInstruction]>, Node>, HypercoreError> {
+        let nodes: IntMap<Option<Node>> = self.infos_to_nodes(infos)?;
+        match self.required_node(index, &nodes)? {
+            Either::Left(instruction) => Ok(Either::Left(vec![instruction].into_boxed_slice())),
+            Either::Right(node) => Ok(Either::Right(node)),
+        }
+    }
+
     /// Is the changeset commitable to given tree
     pub(crate) fn commitable(&self, changeset: &MerkleTreeChangeset) -> bool {
         let correct_length: bool = if changeset.upgraded {
@@ -632,6 +761,11 @@ impl MerkleTree {
             };
 
             self.truncated = true;
+            // Cached nodes past the new head, and their parents, are gone
+            #[cfg(feature = "cache")]
+            if let Some(node_cache) = &self.node_cache {
+                node_cache.invalidate_all();
+            }
             let mut unflushed_indices_to_delete: Vec<u64> = Vec::new();
             for node in self.unflushed.iter() {
                 if *node.0 >= 2 * changeset.ancestors {
@@ -658,6 +792,15 @@ impl MerkleTree {
     pub(crate) fn flush_nodes(&mut self) -> Vec<StoreInfo> {
         let mut infos_to_flush: Vec<StoreInfo> = Vec::with_capacity(self.unflushed.len());
         for (_, node) in self.unflushed.drain() {
+            // Written nodes are read back soon, e.g. for the proof of the next append
+            #[cfg(feature = "cache")]
+            if let Some(node_cache) = &self.node_cache {
+                if node.blank {
+                    node_cache.invalidate(&node.index);
+                } else {
+                    node_cache.insert(node.index, node.clone());
+                }
+            }
             let (mut state, mut buffer) = State::new_with_size(40);
             state
                 .encode_u64(node.length, &mut buffer)
@@ -695,7 +838,7 @@ impl MerkleTree {
     }
 
     fn byte_offset_from_index(
-        &mut self,
+        &self,
         index: u64,
         infos: Option<&[StoreInfo]>,
     ) -> Result<Either<Box<[StoreInfoInstruction]>, u64>, HypercoreError> {
@@ -856,6 +999,16 @@ impl MerkleTree {
         }
 
         // If not, return an instruction
+        if self.page_reads {
+            let page_start = index - index % TREE_PAGE_NODES;
+            return Ok(Either::Left(
+                StoreInfoInstruction::new_content_allow_partial(
+                    Store::Tree,
+                    NODE_SIZE * page_start,
+                    NODE_SIZE * TREE_PAGE_NODES,
+                ),
+            ));
+        }
         let offset = 40 * index;
         let length = 40;
         let info = if allow_miss {
@@ -1298,7 +1451,7 @@ impl MerkleTree {
     }
 
     fn infos_to_nodes(
-        &mut self,
+        &self,
         infos: Option<&[StoreInfo]>,
     ) -> Result<IntMap<Option<Node>>, HypercoreError> {
         match infos {
@@ -1306,7 +1459,9 @@ impl MerkleTree {
                 let mut nodes: IntMap<Option<Node>> = IntMap::with_capacity(infos.len());
                 for info in infos {
                     let index = index_from_info(info);
-                    if !info.miss {
+                    if self.page_reads {
+                        self.page_to_nodes(index, info, &mut nodes)?;
+                    } else if !info.miss {
                         let node = node_from_bytes(&index, info.data.as_ref().unwrap())?;
                         #[cfg(feature = "cache")]
                         if !node.blank {
@@ -1324,6 +1479,34 @@ impl MerkleTree {
             None => Ok(IntMap::new()),
         }
     }
+
+    /// Splits a page read from storage into nodes. Nodes of the page past the end of the
+    /// store are marked missing.
+    fn page_to_nodes(
+        &self,
+        page_start: u64,
+        info: &StoreInfo,
+        nodes: &mut IntMap<Option<Node>>,
+    ) -> Result<(), HypercoreError> {
+        let data: &[u8] = info.data.as_deref().unwrap_or_default();
+        for (i, index) in (page_start..page_start + TREE_PAGE_NODES).enumerate() {
+            let start = i * NODE_SIZE as usize;
+            let end = start + NODE_SIZE as usize;
+            if info.miss || end > data.len() {
+                nodes.insert(index, None);
+                continue;
+            }
+            let node = node_from_bytes(&index, &data[start..end])?;
+            #[cfg(feature = "cache")]
+            if !node.blank {
+                if let Some(node_cache) = &self.node_cache {
+                    node_cache.insert(node.index, node.clone())
+                }
+            }
+            nodes.insert(index, Some(node));
+        }
+        Ok(())
+    }
 }
 
 /// Converts a hypercore index into a merkle tree index. In the flat tree
@@ -1334,7 +1517,36 @@ fn hypercore_index_into_merkle_tree_index(hypercore_index: u64) -> u64 {
     2 * hypercore_index
 }
 
-fn verify_tree(
+/// First half of [`MerkleTree::verify_proof`], which needs no access to the tree or storage so
+/// it can run on another thread: verifies the hashes and signature of a proof into a changeset
+/// of the tree. Returns the changeset and the root of the proven block if the upgrade of the
+/// proof didn't cover it.
+pub(crate) fn verify_proof_detached(
+    proof: &Proof,
+    verifier: &SignatureVerifier,
+    mut changeset: MerkleTreeChangeset,
+) -> Result<(MerkleTreeChangeset, Option<Node>), HypercoreError> {
+    let mut unverified_block_root_node = verify_tree(
+        proof.block.as_ref(),
+        proof.hash.as_ref(),
+        proof.seek.as_ref(),
+        &mut changeset,
+    )?;
+    if let Some(upgrade) = proof.upgrade.as_ref() {
+        if verify_upgrade(
+            proof.fork,
+            upgrade,
+            unverified_block_root_node.as_ref(),
+            verifier,
+            &mut changeset,
+        )? {
+            unverified_block_root_node = None;
+        }
+    }
+    Ok((changeset, unverified_block_root_node))
+}
+
+pub(crate) fn verify_tree(
     block: Option<&DataBlock>,
     hash: Option<&DataHash>,
     seek: Option<&DataSeek>,
@@ -1396,11 +1608,11 @@ fn verify_tree(
     Ok(root)
 }
 
-fn verify_upgrade(
+pub(crate) fn verify_upgrade(
     fork: u64,
     upgrade: &DataUpgrade,
     block_root: Option<&Node>,
-    public_key: &VerifyingKey,
+    verifier: &SignatureVerifier,
     changeset: &mut MerkleTreeChangeset,
 ) -> Result<bool, HypercoreError> {
     let mut q = if let Some(block_root) = block_root {
@@ -1458,7 +1670,7 @@ fn verify_upgrade(
         iter.sibling();
     }
     changeset.fork = fork;
-    changeset.verify_and_set_signature(&upgrade.signature, public_key)?;
+    changeset.verify_and_set_signature(&upgrade.signature, verifier)?;
     Ok(q.extra.is_none())
 }
 
@@ -1564,7 +1776,7 @@ fn parent_node(index: u64, left: &Node, right: &Node) -> Node {
     )
 }
 
-fn block_node(index: u64, value: &Vec<u8>) -> Node {
+fn block_node(index: u64, value: &[u8]) -> Node {
     Node::new(
         index,
         Hash::data(value).as_bytes().to_vec(),
@@ -1,9 +1,9 @@
-use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use ed25519_dalek::Signature;
 use std::convert::TryFrom;
 
 use crate::{
-    crypto::{signable_tree, verify, Hash},
-    sign, HypercoreError, Node,
+    crypto::{signable_tree, Hash, SignatureVerifier, Signer},
+    HypercoreError, Node,
 };
 
 /// Changeset for a `MerkleTree`. This allows to incrementally change a `MerkleTree` in two steps:
@@ -55,12 +55,17 @@ impl MerkleTreeChangeset {
 
     pub(crate) fn append(&mut self, data: &[u8]) -> usize {
         let len = data.len();
+        self.append_hash(Hash::data(data).as_bytes().to_vec(), len as u64);
+        len
+    }
+
+    /// Append a block by its already calculated leaf hash and byte length.
+    pub(crate) fn append_hash(&mut self, hash: Vec<u8>, byte_length: u64) {
         let head = self.length * 2;
         let mut iter = flat_tree::Iterator::new(head);
-        let node = Node::new(head, Hash::data(data).as_bytes().to_vec(), len as u64);
+        let node = Node::new(head, hash, byte_length);
         self.append_root(node, &mut iter);
         self.batch_length += 1;
-        len
     }
 
     pub(crate) fn append_root(&mut self, node: Node, iter: &mut flat_tree::Iterator) {
@@ -91,19 +96,23 @@ impl MerkleTreeChangeset {
     }
 
     /// Hashes and signs the changeset
-    pub(crate) fn hash_and_sign(&mut self, signing_key: &SigningKey) {
+    pub(crate) async fn hash_and_sign(
+        &mut self,
+        signer: &dyn Signer,
+    ) -> Result<(), HypercoreError> {
         let hash = self.hash();
         let signable = self.signable(&hash);
-        let signature = sign(signing_key, &signable);
+        let signature = signer.sign(&signable).await?;
         self.hash = Some(hash);
         self.signature = Some(signature);
+        Ok(())
     }
 
-    /// Verify and set signature with given public key
+    /// Verify and set signature of the signer of the core
     pub(crate) fn verify_and_set_signature(
         &mut self,
         signature: &[u8],
-        public_key: &VerifyingKey,
+        verifier: &SignatureVerifier,
     ) -> Result<(), HypercoreError> {
         // Verify that the received signature matches the public key
         let signature =
@@ -111,7 +120,7 @@ impl MerkleTreeChangeset {
                 context: "Could not parse signature".to_string(),
             })?;
         let hash = self.hash();
-        verify(public_key, &self.signable(&hash), Some(&signature))?;
+        verifier.verify(&self.signable(&hash), &signature)?;
 
         // Set values to changeset
         self.hash = Some(hash);
@@ -1,5 +1,12 @@
+//! Merkle tree of a hypercore, and hashing functions to calculate tree hashes outside of it.
+mod hashing;
 mod merkle_tree;
 mod merkle_tree_changeset;
 
-pub(crate) use merkle_tree::MerkleTree;
+pub use hashing::{hash_leaf, hash_parent, hash_roots, RootAccumulator};
+
+pub(crate) use hashing::LeafHasher;
+pub(crate) use merkle_tree::{
+    verify_proof_detached, verify_tree, verify_upgrade, LocalSeek, MerkleTree,
+};
 pub(crate) use merkle_tree_changeset::MerkleTreeChangeset;
@@ -0,0 +1,200 @@
+//! Links to cores, `hyper://<key>[/path][?fork=<fork>&length=<length>]` and the same with the
+//! `gnostr://` scheme, so applications can share links instead of raw hex keys.
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{HypercoreError, KeyEncoding};
+
+/// Scheme of a [`Url`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UrlScheme {
+    /// `hyper://`, keys are printed in z-base32 like the Javascript tooling does
+    Hyper,
+    /// `gnostr://`, keys are printed as `npub` bech32 like nostr clients do
+    Gnostr,
+}
+
+impl UrlScheme {
+    /// Scheme name, without `://`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UrlScheme::Hyper => "hyper",
+            UrlScheme::Gnostr => "gnostr",
+        }
+    }
+}
+
+/// Version of a core a [`Url`] is pinned to, see [`crate::Hypercore::get_pinned`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UrlVersion {
+    /// Fork of the core
+    pub fork: u64,
+    /// Length of the core
+    pub length: u64,
+}
+
+/// Parsed link to a core.
+///
+/// The key can be given in any format [`KeyEncoding::parse_key`] accepts. The path addresses an
+/// entry in a key-value store on top of the core, e.g. a hyperbee, and is kept as is. The
+/// version pins the link to a fork and length; the fork defaults to 0 when only the length is
+/// given. Unknown query parameters and fragments are ignored.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Url {
+    /// Scheme of the link
+    pub scheme: UrlScheme,
+    /// Public key of the core
+    pub key: [u8; 32],
+    /// Path in the core, starting with `/`
+    pub path: Option<String>,
+    /// Version the link is pinned to
+    pub version: Option<UrlVersion>,
+}
+
+impl Url {
+    /// Link to the latest version of a core.
+    pub fn new(scheme: UrlScheme, key: [u8; 32]) -> Self {
+        Self {
+            scheme,
+            key,
+            path: None,
+            version: None,
+        }
+    }
+
+    /// Parse a link.
+    pub fn parse(url: &str) -> Result<Self, HypercoreError> {
+        let url = url.trim();
+        let (scheme, rest) = url
+            .split_once("://")
+            .ok_or_else(|| invalid_url(url, "missing scheme"))?;
+        let scheme = match scheme.to_ascii_lowercase().as_str() {
+            "hyper" => UrlScheme::Hyper,
+            "gnostr" => UrlScheme::Gnostr,
+            _ => return Err(invalid_url(url, "unknown scheme")),
+        };
+        let rest = rest.split_once('#').map_or(rest, |(rest, _)| rest);
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, query)) => (rest, Some(query)),
+            None => (rest, None),
+        };
+        let (key, path) = match rest.find('/') {
+            Some(position) => (&rest[..position], Some(&rest[position..])),
+            None => (rest, None),
+        };
+        let key = <[u8; 32]>::parse_key(key).map_err(|err| invalid_url(url, &err.to_string()))?;
+        let path = path.filter(|path| *path != "/").map(str::to_string);
+
+        let mut fork: Option<u64> = None;
+        let mut length: Option<u64> = None;
+        for parameter in query.into_iter().flat_map(|query| query.split('&')) {
+            let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            let target = match name {
+                "fork" => &mut fork,
+                "length" => &mut length,
+                _ => continue,
+            };
+            *target = Some(
+                value
+                    .parse()
+                    .map_err(|_| invalid_url(url, &format!("invalid {name} {value:?}")))?,
+            );
+        }
+        let version = match (fork, length) {
+            (_, Some(length)) => Some(UrlVersion {
+                fork: fork.unwrap_or(0),
+                length,
+            }),
+            (Some(_), None) => return Err(invalid_url(url, "fork without length")),
+            (None, None) => None,
+        };
+        Ok(Self {
+            scheme,
+            key,
+            path,
+            version,
+        })
+    }
+}
+
+impl FromStr for Url {
+    type Err = HypercoreError;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        Self::parse(url)
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let key = match self.scheme {
+            UrlScheme::Hyper => self.key.to_zbase32(),
+            UrlScheme::Gnostr => self.key.to_npub(),
+        };
+        write!(f, "{}://{key}", self.scheme.as_str())?;
+        if let Some(path) = self.path.as_ref() {
+            write!(f, "{path}")?;
+        }
+        if let Some(version) = self.version.as_ref() {
+            write!(f, "?fork={}&length={}", version.fork, version.length)?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid_url(url: &str, reason: &str) -> HypercoreError {
+    HypercoreError::BadArgument {
+        context: format!("Invalid url {url}, {reason}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_parses_and_prints_links() -> Result<(), HypercoreError> {
+        let hex = "3bf0c63fcb93463407af97a5e5ee64fa883d107ef9e558472c4eb9aaaefa459d";
+        let key = <[u8; 32]>::parse_key(hex)?;
+
+        let url: Url = format!("hyper://{hex}/posts/1?length=10&foo#top").parse()?;
+        assert_eq!(
+            url,
+            Url {
+                scheme: UrlScheme::Hyper,
+                key,
+                path: Some("/posts/1".to_string()),
+                version: Some(UrlVersion {
+                    fork: 0,
+                    length: 10
+                }),
+            }
+        );
+        let printed = url.to_string();
+        assert_eq!(
+            printed,
+            format!("hyper://{}/posts/1?fork=0&length=10", key.to_zbase32())
+        );
+        assert_eq!(Url::parse(&printed)?, url);
+
+        let url = Url::parse(
+            "gnostr://npub180cvv07tjdrrgpa0j7j7tmnyl2yr6yr7l8j4s3evf6u64th6gkwsyjh6w6/?fork=2&length=5",
+        )?;
+        assert_eq!(url.scheme, UrlScheme::Gnostr);
+        assert_eq!(url.key, key);
+        assert_eq!(url.path, None);
+        assert_eq!(url.version, Some(UrlVersion { fork: 2, length: 5 }));
+        assert_eq!(Url::parse(&url.to_string())?, url);
+        assert_eq!(
+            Url::new(UrlScheme::Gnostr, key).to_string(),
+            "gnostr://npub180cvv07tjdrrgpa0j7j7tmnyl2yr6yr7l8j4s3evf6u64th6gkwsyjh6w6"
+        );
+
+        assert!(Url::parse(hex).is_err());
+        assert!(Url::parse(&format!("http://{hex}")).is_err());
+        assert!(Url::parse("hyper://notakey").is_err());
+        assert!(Url::parse(&format!("hyper://{hex}?fork=1")).is_err());
+        assert!(Url::parse(&format!("hyper://{hex}?length=x")).is_err());
+        Ok(())
+    }
+}
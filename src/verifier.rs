@@ -0,0 +1,283 @@
+//! Verifier co-process protocol. Lets programs in other languages delegate all verification to
+//! a sandboxed `hypercore-verifier` subprocess, speaking to it over its stdin and stdout.
+//!
+//! Every message is a frame: its byte length as a little-endian u32 followed by the compact
+//! encoded [`VerifierRequest`] or [`VerifierResponse`]. Each request gets exactly one
+//! response, in order. The verifier keeps a [`LightCore`] per public key, so an upgrade
+//! verified once covers the blocks verified after it.
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use crate::encoding::{CompactEncoding, EncodingError, HypercoreState};
+use crate::tree::hash_leaf;
+use crate::{LightCore, Proof, VerifyingKey};
+
+/// Maximum byte size of a frame
+pub const MAX_FRAME_SIZE: usize = 8 * 1024 * 1024;
+
+/// Request to the verifier
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifierRequest {
+    /// Verify a proof that upgrades the head of the core with the given public key
+    VerifyUpgrade {
+        /// Public key of the core
+        public_key: [u8; 32],
+        /// Proof containing an upgrade
+        proof: Proof,
+    },
+    /// Verify a proof of a block of the core with the given public key, against its head
+    VerifyBlock {
+        /// Public key of the core
+        public_key: [u8; 32],
+        /// Proof containing a block
+        proof: Proof,
+    },
+    /// Compute the leaf hash of a block
+    ComputeHash {
+        /// Value of the block
+        data: Vec<u8>,
+    },
+}
+
+/// Response of the verifier
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifierResponse {
+    /// The proof is valid. Carries the head of the core after applying it.
+    Verified {
+        /// Fork of the head
+        fork: u64,
+        /// Length of the head
+        length: u64,
+    },
+    /// The proof or request is invalid
+    Rejected {
+        /// Why the proof was rejected
+        reason: String,
+    },
+    /// Leaf hash of the block
+    Hash([u8; 32]),
+}
+
+/// Verification state of the co-process.
+#[derive(Debug, Default)]
+pub struct Verifier {
+    cores: HashMap<[u8; 32], LightCore>,
+}
+
+impl Verifier {
+    /// Create a verifier that knows no cores.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle a single request.
+    pub fn handle(&mut self, request: VerifierRequest) -> VerifierResponse {
+        let (public_key, proof) = match request {
+            VerifierRequest::ComputeHash { data } => {
+                return VerifierResponse::Hash(hash_leaf(&data));
+            }
+            VerifierRequest::VerifyUpgrade { public_key, proof } => {
+                if proof.upgrade.is_none() {
+                    return rejected("Proof has no upgrade");
+                }
+                (public_key, proof)
+            }
+            VerifierRequest::VerifyBlock { public_key, proof } => {
+                if proof.block.is_none() {
+                    return rejected("Proof has no block");
+                }
+                (public_key, proof)
+            }
+        };
+        let core = match self.cores.get_mut(&public_key) {
+            Some(core) => core,
+            None => {
+                let Ok(key) = VerifyingKey::from_bytes(&public_key) else {
+                    return rejected("Invalid public key");
+                };
+                self.cores.entry(public_key).or_insert(LightCore::new(key))
+            }
+        };
+        match core.verify_proof(&proof) {
+            Ok(true) => VerifierResponse::Verified {
+                fork: core.fork(),
+                length: core.length(),
+            },
+            Ok(false) => rejected(&format!("Proof is not for fork {}", core.fork())),
+            Err(err) => rejected(&err.to_string()),
+        }
+    }
+}
+
+/// Serve requests read from `reader`, e.g. stdin, writing the responses to `writer`, e.g.
+/// stdout, until `reader` is closed. Frames that can't be decoded are answered with
+/// [`VerifierResponse::Rejected`].
+pub fn serve<R: Read, W: Write>(mut reader: R, mut writer: W) -> io::Result<()> {
+    let mut verifier = Verifier::new();
+    while let Some(frame) = read_frame(&mut reader)? {
+        let response = match decode_request(&frame) {
+            Ok(request) => verifier.handle(request),
+            Err(err) => rejected(&err.to_string()),
+        };
+        write_frame(
+            &mut writer,
+            &encode_response(&response).map_err(invalid_data)?,
+        )?;
+    }
+    Ok(())
+}
+
+/// Read a frame, `None` if the reader is closed before it.
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let length = u32::from_le_bytes(length) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Frame of {length} bytes exceeds maximum size {MAX_FRAME_SIZE}"),
+        ));
+    }
+    let mut frame = vec![0; length];
+    reader.read_exact(&mut frame)?;
+    Ok(Some(frame))
+}
+
+/// Write a frame and flush the writer.
+pub fn write_frame<W: Write>(writer: &mut W, frame: &[u8]) -> io::Result<()> {
+    if frame.len() > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Frame of {} bytes exceeds maximum size {MAX_FRAME_SIZE}",
+                frame.len()
+            ),
+        ));
+    }
+    writer.write_all(&(frame.len() as u32).to_le_bytes())?;
+    writer.write_all(frame)?;
+    writer.flush()
+}
+
+/// Encode a request into a frame.
+pub fn encode_request(request: &VerifierRequest) -> Result<Vec<u8>, EncodingError> {
+    let mut state = HypercoreState::new();
+    state.preencode(request)?;
+    let mut buffer = state.create_buffer();
+    state.encode(request, &mut buffer)?;
+    Ok(buffer.to_vec())
+}
+
+/// Decode a request from a frame.
+pub fn decode_request(frame: &[u8]) -> Result<VerifierRequest, EncodingError> {
+    HypercoreState::from_buffer(frame).decode(frame)
+}
+
+/// Encode a response into a frame.
+pub fn encode_response(response: &VerifierResponse) -> Result<Vec<u8>, EncodingError> {
+    let mut state = HypercoreState::new();
+    state.preencode(response)?;
+    let mut buffer = state.create_buffer();
+    state.encode(response, &mut buffer)?;
+    Ok(buffer.to_vec())
+}
+
+/// Decode a response from a frame.
+pub fn decode_response(frame: &[u8]) -> Result<VerifierResponse, EncodingError> {
+    HypercoreState::from_buffer(frame).decode(frame)
+}
+
+fn rejected(reason: &str) -> VerifierResponse {
+    VerifierResponse::Rejected {
+        reason: reason.to_string(),
+    }
+}
+
+fn invalid_data(err: EncodingError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::create_hypercore_with_data;
+    use crate::{HypercoreError, RequestBlock, RequestUpgrade};
+    use std::io::Cursor;
+
+    #[async_std::test]
+    async fn verifier_serves_framed_requests() -> Result<(), HypercoreError> {
+        let hypercore = create_hypercore_with_data(10).await?;
+        let public_key = hypercore.key_pair().public.to_bytes();
+        let upgrade = hypercore
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        let block = hypercore
+            .create_proof(Some(RequestBlock { index: 3, nodes: 3 }), None, None, None)
+            .await?
+            .unwrap();
+        let mut forged = block.clone();
+        forged.block.as_mut().unwrap().value = b"#x".to_vec();
+
+        let requests = [
+            VerifierRequest::VerifyBlock {
+                public_key,
+                proof: block.clone(),
+            },
+            VerifierRequest::VerifyUpgrade {
+                public_key,
+                proof: upgrade,
+            },
+            VerifierRequest::VerifyBlock {
+                public_key,
+                proof: block,
+            },
+            VerifierRequest::VerifyBlock {
+                public_key,
+                proof: forged,
+            },
+            VerifierRequest::ComputeHash {
+                data: b"#3".to_vec(),
+            },
+        ];
+        let mut input = vec![];
+        for request in &requests {
+            write_frame(&mut input, &encode_request(request)?)?;
+        }
+        // Garbage is rejected without ending the session
+        write_frame(&mut input, &[0xff])?;
+
+        let mut output = vec![];
+        serve(Cursor::new(input), &mut output)?;
+        let mut output = Cursor::new(output);
+        let mut responses = vec![];
+        while let Some(frame) = read_frame(&mut output)? {
+            responses.push(decode_response(&frame)?);
+        }
+        assert_eq!(responses.len(), 6);
+        // Blocks can't be verified before the head is known
+        assert!(matches!(responses[0], VerifierResponse::Rejected { .. }));
+        let verified = VerifierResponse::Verified {
+            fork: 0,
+            length: 10,
+        };
+        assert_eq!(responses[1], verified);
+        assert_eq!(responses[2], verified);
+        assert!(matches!(responses[3], VerifierResponse::Rejected { .. }));
+        assert_eq!(responses[4], VerifierResponse::Hash(hash_leaf(b"#3")));
+        assert!(matches!(responses[5], VerifierResponse::Rejected { .. }));
+        Ok(())
+    }
+}
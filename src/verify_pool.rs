@@ -0,0 +1,251 @@
+//! Pool of threads verifying the hashes and signatures of received proofs, so that BLAKE2b and
+//! Ed25519 don't run on the async executor and starve IO tasks under load.
+//!
+//! Verifying a proof is split in two: [`crate::Hypercore::verify_job`] snapshots what the
+//! verification needs, the pool verifies it, and [`crate::Hypercore::apply_verified_proof`]
+//! checks the result against the tree and stores it.
+use futures::channel::oneshot;
+use std::collections::VecDeque;
+use std::future::{poll_fn, Future};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Poll, Waker};
+use std::thread::JoinHandle;
+
+use crate::crypto::SignatureVerifier;
+use crate::tree::{verify_proof_detached, MerkleTreeChangeset};
+use crate::{HypercoreError, Node, Proof};
+
+/// Proof waiting for verification, see [`crate::Hypercore::verify_job`].
+#[derive(Debug)]
+pub struct VerifyJob {
+    pub(crate) proof: Proof,
+    pub(crate) verifier: SignatureVerifier,
+    pub(crate) changeset: MerkleTreeChangeset,
+}
+
+impl VerifyJob {
+    /// Verify on the current thread.
+    pub fn run(self) -> Result<VerifiedProof, HypercoreError> {
+        let (changeset, unverified_block_root) =
+            verify_proof_detached(&self.proof, &self.verifier, self.changeset)?;
+        Ok(VerifiedProof {
+            proof: self.proof,
+            changeset,
+            unverified_block_root,
+        })
+    }
+}
+
+/// Proof whose hashes and signature have been verified, to be applied with
+/// [`crate::Hypercore::apply_verified_proof`].
+#[derive(Debug)]
+pub struct VerifiedProof {
+    pub(crate) proof: Proof,
+    pub(crate) changeset: MerkleTreeChangeset,
+    pub(crate) unverified_block_root: Option<Node>,
+}
+
+impl VerifiedProof {
+    /// The verified proof.
+    pub fn proof(&self) -> &Proof {
+        &self.proof
+    }
+}
+
+type Task = (
+    VerifyJob,
+    oneshot::Sender<Result<VerifiedProof, HypercoreError>>,
+);
+
+#[derive(Debug, Default)]
+struct Queue {
+    tasks: VecDeque<Task>,
+    /// Submitters waiting for room in the queue
+    waiters: Vec<Waker>,
+    closed: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    queue: Mutex<Queue>,
+    work: Condvar,
+    capacity: usize,
+}
+
+/// Bounded pool of verification threads.
+///
+/// At most `capacity` jobs wait in the queue. When it is full, [`VerifyPool::verify`] waits
+/// for room and [`VerifyPool::try_verify`] hands the job back, which lets the network layer
+/// stop reading from peers until the pool catches up.
+#[derive(Debug)]
+pub struct VerifyPool {
+    shared: Arc<Shared>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl VerifyPool {
+    /// Start a pool of `threads` threads with room for `capacity` waiting jobs.
+    pub fn new(threads: usize, capacity: usize) -> Result<Self, HypercoreError> {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue::default()),
+            work: Condvar::new(),
+            capacity: capacity.max(1),
+        });
+        let threads = (0..threads.max(1))
+            .map(|i| {
+                let shared = shared.clone();
+                std::thread::Builder::new()
+                    .name(format!("hypercore-verify-{i}"))
+                    .spawn(move || work(&shared))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { shared, threads })
+    }
+
+    /// Number of jobs waiting for a thread.
+    pub fn queued(&self) -> usize {
+        self.lock().tasks.len()
+    }
+
+    /// Verify a job on the pool, waiting for room in the queue if it is full.
+    pub async fn verify(&self, job: VerifyJob) -> Result<VerifiedProof, HypercoreError> {
+        let (sender, receiver) = oneshot::channel();
+        let mut task = Some((job, sender));
+        poll_fn(|cx| {
+            let mut queue = self.lock();
+            if queue.tasks.len() >= self.shared.capacity {
+                queue.waiters.push(cx.waker().clone());
+                return Poll::Pending;
+            }
+            queue
+                .tasks
+                .push_back(task.take().expect("Task should be queued once"));
+            self.shared.work.notify_one();
+            Poll::Ready(())
+        })
+        .await;
+        receive(receiver).await
+    }
+
+    /// Verify a job on the pool if there is room in the queue, otherwise return the job.
+    pub fn try_verify(
+        &self,
+        job: VerifyJob,
+    ) -> Result<impl Future<Output = Result<VerifiedProof, HypercoreError>>, Box<VerifyJob>> {
+        let mut queue = self.lock();
+        if queue.tasks.len() >= self.shared.capacity {
+            return Err(Box::new(job));
+        }
+        let (sender, receiver) = oneshot::channel();
+        queue.tasks.push_back((job, sender));
+        self.shared.work.notify_one();
+        Ok(receive(receiver))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Queue> {
+        self.shared.queue.lock().expect("Verify pool lock poisoned")
+    }
+}
+
+impl Drop for VerifyPool {
+    fn drop(&mut self) {
+        self.lock().closed = true;
+        self.shared.work.notify_all();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+async fn receive(
+    receiver: oneshot::Receiver<Result<VerifiedProof, HypercoreError>>,
+) -> Result<VerifiedProof, HypercoreError> {
+    receiver
+        .await
+        .map_err(|_| HypercoreError::InvalidOperation {
+            context: "Verify pool was dropped".to_string(),
+        })?
+}
+
+/// Runs jobs until the pool is dropped and the queue is empty.
+fn work(shared: &Shared) {
+    loop {
+        let (job, sender) = {
+            let mut queue = shared.queue.lock().expect("Verify pool lock poisoned");
+            loop {
+                if let Some(task) = queue.tasks.pop_front() {
+                    for waiter in queue.waiters.drain(..) {
+                        waiter.wake();
+                    }
+                    break task;
+                }
+                if queue.closed {
+                    return;
+                }
+                queue = shared.work.wait(queue).expect("Verify pool lock poisoned");
+            }
+        };
+        let _ = sender.send(job.run());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::tests::{create_hypercore_with_data, create_hypercore_with_data_and_key_pair};
+    use crate::{PartialKeypair, RequestBlock, RequestUpgrade};
+
+    #[async_std::test]
+    async fn verify_pool_verifies_proofs_off_thread() -> Result<(), HypercoreError> {
+        let main = create_hypercore_with_data(10).await?;
+        let mut clone = create_hypercore_with_data_and_key_pair(
+            0,
+            PartialKeypair {
+                public: main.key_pair().public,
+                secret: None,
+            },
+        )
+        .await?;
+        let pool = VerifyPool::new(2, 1)?;
+
+        let upgrade = main
+            .create_proof(
+                None,
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 10,
+                }),
+            )
+            .await?
+            .unwrap();
+        let verified = pool.verify(clone.verify_job(upgrade)?).await?;
+        assert!(clone.apply_verified_proof(verified).await?);
+        assert_eq!(clone.info().length, 10);
+
+        let mut jobs = vec![];
+        for index in 0..10 {
+            let nodes = clone.missing_nodes(index).await?;
+            let proof = main
+                .create_proof(Some(RequestBlock { index, nodes }), None, None, None)
+                .await?
+                .unwrap();
+            jobs.push(pool.verify(clone.verify_job(proof)?));
+        }
+        for verified in futures::future::join_all(jobs).await {
+            assert!(clone.apply_verified_proof(verified?).await?);
+        }
+        assert_eq!(clone.get(7).await?.unwrap(), b"#7");
+
+        // Forged blocks don't match the tree
+        let mut forged = main
+            .create_proof(Some(RequestBlock { index: 3, nodes: 3 }), None, None, None)
+            .await?
+            .unwrap();
+        forged.block.as_mut().unwrap().value = b"#x".to_vec();
+        let verified = pool.try_verify(clone.verify_job(forged)?).unwrap().await?;
+        assert!(clone.apply_verified_proof(verified).await.is_err());
+        Ok(())
+    }
+}
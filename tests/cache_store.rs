@@ -0,0 +1,137 @@
+use anyhow::Result;
+use hypercore::{
+    generate_signing_key, CacheStore, HypercoreBuilder, PartialKeypair, RequestBlock,
+    RequestUpgrade, Storage,
+};
+use std::time::Duration;
+use tempfile::Builder;
+use test_log::test;
+
+#[cfg(feature = "async-std")]
+use async_std::test as async_test;
+#[cfg(feature = "tokio")]
+use tokio::test as async_test;
+
+#[test(async_test)]
+async fn cache_store_evicts_least_recently_used() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("cache_store_evicts_least_recently_used")
+        .tempdir()
+        .unwrap();
+    let mut keys = vec![];
+    let mut sources = vec![];
+    for _ in 0..3 {
+        let signing_key = generate_signing_key();
+        keys.push(signing_key.verifying_key());
+        let mut source = HypercoreBuilder::new(Storage::new_memory().await?)
+            .key_pair(PartialKeypair {
+                public: signing_key.verifying_key(),
+                secret: Some(signing_key),
+            })
+            .build()
+            .await?;
+        source.append(&[0; 4096]).await?;
+        sources.push(source);
+    }
+
+    let mut cache = CacheStore::open(dir.path(), 40_000).await?;
+    for (public_key, source) in keys.iter().zip(sources.iter_mut()) {
+        let core = cache.get(public_key).await?;
+        let nodes = core.missing_nodes(0).await?;
+        let proof = source
+            .create_proof(
+                Some(RequestBlock { index: 0, nodes }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 1,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(core.verify_and_apply_proof(&proof).await?);
+        cache.evict().await?;
+    }
+    // Only two cores fit into the budget, so the first one was evicted
+    assert_eq!(cache.keys(), vec![keys[1], keys[2]]);
+    assert!(cache.disk_usage()? <= cache.budget());
+
+    // Using the second core makes the third the least recently used
+    cache.get(&keys[1]).await?;
+    drop(cache);
+    let mut cache = CacheStore::open(dir.path(), 40_000).await?;
+    assert_eq!(cache.keys(), vec![keys[2], keys[1]]);
+    assert_eq!(
+        cache.get(&keys[1]).await?.get(0).await?.unwrap(),
+        vec![0; 4096]
+    );
+    assert!(cache.remove(&keys[2]).await?);
+    assert!(!cache.remove(&keys[2]).await?);
+    assert_eq!(cache.keys(), vec![keys[1]]);
+    Ok(())
+}
+
+#[test(async_test)]
+async fn cache_store_commits_writes_of_many_cores_together() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("cache_store_commits_writes_of_many_cores_together")
+        .tempdir()
+        .unwrap();
+    let mut cache = CacheStore::open(dir.path(), u64::MAX).await?;
+    cache.set_commit_window(Duration::from_secs(3600));
+    let mut keys = vec![];
+    for _ in 0..3 {
+        let signing_key = generate_signing_key();
+        let public_key = signing_key.verifying_key();
+        let mut source = HypercoreBuilder::new(Storage::new_memory().await?)
+            .key_pair(PartialKeypair {
+                public: public_key,
+                secret: Some(signing_key),
+            })
+            .build()
+            .await?;
+        source.append(b"hello").await?;
+        let proof = source
+            .create_proof(
+                Some(RequestBlock { index: 0, nodes: 0 }),
+                None,
+                None,
+                Some(RequestUpgrade {
+                    start: 0,
+                    length: 1,
+                }),
+            )
+            .await?
+            .unwrap();
+        assert!(
+            cache
+                .get(&public_key)
+                .await?
+                .verify_and_apply_proof(&proof)
+                .await?
+        );
+        cache.mark_written(&public_key);
+        keys.push(public_key);
+    }
+    cache.mark_written(&keys[0]);
+    assert_eq!(cache.uncommitted(), 3);
+    assert!(cache.commit_deadline().is_some());
+
+    // Nothing is synced before the window has passed
+    assert_eq!(cache.commit_if_due().await?, 0);
+    cache.set_commit_window(Duration::ZERO);
+    assert_eq!(cache.commit_if_due().await?, 3);
+    assert_eq!(cache.uncommitted(), 0);
+    assert_eq!(cache.commit_deadline(), None);
+
+    // Removed cores are no longer committed
+    cache.mark_written(&keys[1]);
+    assert!(cache.remove(&keys[1]).await?);
+    assert_eq!(cache.commit().await?, 0);
+
+    drop(cache);
+    let mut cache = CacheStore::open(dir.path(), u64::MAX).await?;
+    assert_eq!(cache.get(&keys[2]).await?.get(0).await?.unwrap(), b"hello");
+    Ok(())
+}
@@ -1,8 +1,16 @@
 pub mod common;
 
 use anyhow::Result;
-use common::{create_hypercore, get_test_key_pair, open_hypercore, storage_contains_data};
-use hypercore::{HypercoreBuilder, Storage};
+use common::{
+    create_hypercore, create_hypercore_hash, get_test_key_pair, open_hypercore,
+    storage_contains_data,
+};
+use hypercore::{
+    generate_signing_key, BitfieldFormat, DownloadProgress, Hypercore, HypercoreBuilder,
+    HypercoreError, Progress, Proof, RequestBlock, RequestSeek, RequestUpgrade, Storage, Store,
+    WantedRange,
+};
+use std::time::Duration;
 use tempfile::Builder;
 use test_log::test;
 
@@ -72,8 +80,708 @@ async fn hypercore_make_read_only() -> Result<()> {
         &write_key_pair.secret.as_ref().unwrap().to_bytes()[16..],
     ));
 
-    let mut hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    let hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
     assert_eq!(&hypercore.get(0).await?.unwrap(), b"Hello");
     assert_eq!(&hypercore.get(1).await?.unwrap(), b"World!");
     Ok(())
 }
+
+#[test(async_test)]
+async fn hypercore_create_from_iter() -> Result<()> {
+    // The 1st, 5th, 9th, ... append flush the tree and bitfield, so with 101 appends both
+    // hypercores should end up with identical stores. 101 * 65536 bytes also exceeds the
+    // write buffer of create_from_iter.
+    let blocks: Vec<Vec<u8>> = (0..101u8).map(|i| vec![i; 65536]).collect();
+    let append_dir = Builder::new()
+        .prefix("hypercore_create_from_iter_append")
+        .tempdir()
+        .unwrap();
+    let iter_dir = Builder::new()
+        .prefix("hypercore_create_from_iter_iter")
+        .tempdir()
+        .unwrap();
+
+    let mut appended = create_hypercore(&append_dir.path().to_string_lossy()).await?;
+    for block in blocks.iter() {
+        appended.append(block).await?;
+    }
+    let storage = Storage::new_disk(&iter_dir.path().to_path_buf(), true).await?;
+    let created = Hypercore::create_from_iter(storage, get_test_key_pair(), blocks.iter()).await?;
+
+    assert_eq!(appended.info(), created.info());
+    assert_eq!(created.get(100).await?.unwrap(), blocks[100]);
+    let appended_hash = create_hypercore_hash(&append_dir.path().to_string_lossy());
+    let created_hash = create_hypercore_hash(&iter_dir.path().to_string_lossy());
+    assert_eq!(appended_hash.tree, created_hash.tree);
+    assert_eq!(appended_hash.data, created_hash.data);
+    assert_eq!(appended_hash.bitfield, created_hash.bitfield);
+
+    // Reopening gives the same state
+    drop(created);
+    let reopened = open_hypercore(&iter_dir.path().to_string_lossy()).await?;
+    assert_eq!(appended.info(), reopened.info());
+    assert_eq!(reopened.get(42).await?.unwrap(), blocks[42]);
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_create_from_iter_with_progress() -> Result<()> {
+    let blocks: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i; 100]).collect();
+    let progress = Progress::new(blocks.len() as u64);
+    assert_eq!(progress.eta(), None);
+    let storage = Storage::new_memory().await?;
+    let hypercore = Hypercore::create_from_iter_with_progress(
+        storage,
+        get_test_key_pair(),
+        blocks.iter(),
+        &progress.clone(),
+    )
+    .await?;
+    assert_eq!(hypercore.info().length, 10);
+    assert!(progress.is_finished());
+    assert_eq!(progress.total(), 10);
+    assert_eq!(progress.completed(), 10);
+    assert_eq!(progress.bytes(), 1000);
+    assert_eq!(progress.eta(), Some(Duration::ZERO));
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_tree_page_reads() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_tree_page_reads")
+        .tempdir()
+        .unwrap();
+    let mut hypercore = create_hypercore(&dir.path().to_string_lossy()).await?;
+    for i in 0..300u64 {
+        hypercore.append(format!("#{i}").as_bytes()).await?;
+    }
+    drop(hypercore);
+
+    let mut hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    let proofs = create_test_proofs(&mut hypercore).await?;
+    assert!(proofs.iter().all(Option::is_some));
+    drop(hypercore);
+
+    let storage = Storage::new_disk(&dir.path().to_path_buf(), false).await?;
+    let mut hypercore = HypercoreBuilder::new(storage)
+        .open(true)
+        .tree_page_reads(true)
+        .build()
+        .await?;
+    assert_eq!(hypercore.info().length, 300);
+    assert_eq!(hypercore.get(299).await?.unwrap(), b"#299");
+    assert_eq!(create_test_proofs(&mut hypercore).await?, proofs);
+    Ok(())
+}
+
+async fn create_test_proofs(hypercore: &mut Hypercore) -> Result<Vec<Option<Proof>>> {
+    let mut proofs = vec![];
+    for index in [0, 101, 150, 299] {
+        proofs.push(
+            hypercore
+                .create_proof(
+                    Some(RequestBlock { index, nodes: 0 }),
+                    None,
+                    None,
+                    Some(RequestUpgrade {
+                        start: 0,
+                        length: 300,
+                    }),
+                )
+                .await?,
+        );
+        proofs.push(
+            hypercore
+                .create_proof(None, None, Some(RequestSeek { bytes: index * 4 }), None)
+                .await?,
+        );
+    }
+    Ok(proofs)
+}
+
+#[test(async_test)]
+async fn hypercore_download_state_persists() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_download_state_persists")
+        .tempdir()
+        .unwrap();
+    let mut hypercore = create_hypercore(&dir.path().to_string_lossy()).await?;
+    for i in 0..5 {
+        hypercore.append(format!("#{i}").as_bytes()).await?;
+    }
+    hypercore.clear(2, 3).await?;
+    hypercore.want(0, 10, 1).await?;
+    hypercore.want(20, 30, 5).await?;
+    hypercore.want(40, 50, 0).await?;
+    assert!(hypercore.unwant(40, 50).await?);
+    assert!(!hypercore.unwant(40, 50).await?);
+    assert!(hypercore.want(50, 50, 0).await.is_err());
+    let expected = vec![
+        DownloadProgress {
+            range: WantedRange {
+                start: 20,
+                end: 30,
+                priority: 5,
+            },
+            downloaded: 0,
+            next: Some(20),
+        },
+        DownloadProgress {
+            range: WantedRange {
+                start: 0,
+                end: 10,
+                priority: 1,
+            },
+            downloaded: 4,
+            next: Some(2),
+        },
+    ];
+    assert_eq!(hypercore.download_progress(), expected);
+    drop(hypercore);
+
+    let hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    assert_eq!(hypercore.download_progress(), expected);
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_annotations_persist() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_annotations_persist")
+        .tempdir()
+        .unwrap();
+    let mut hypercore = create_hypercore(&dir.path().to_string_lossy()).await?;
+    let hash = {
+        hypercore.append(b"Hello").await?;
+        hypercore.append(b"spam").await?;
+        create_hypercore_hash(&dir.path().to_string_lossy())
+    };
+    hypercore.annotate(1, "spam", &[1]).await?;
+    hypercore.annotate(1, "label", b"ad").await?;
+    hypercore.annotate(0, "seen_at", b"1700000000").await?;
+    hypercore.annotate(0, "seen_at", b"1700000001").await?;
+    assert!(hypercore.remove_annotation(1, "label").await?);
+    assert!(!hypercore.remove_annotation(1, "label").await?);
+    assert!(hypercore.annotate(2, "spam", &[1]).await.is_err());
+    drop(hypercore);
+
+    // Annotating doesn't touch the log
+    assert_eq!(create_hypercore_hash(&dir.path().to_string_lossy()), hash);
+
+    // A record torn by a crash is ignored
+    let annotations_path = dir.path().join("aux-annotations");
+    let mut annotations = std::fs::read(&annotations_path)?;
+    annotations.extend_from_slice(&[20, 1]);
+    std::fs::write(&annotations_path, annotations)?;
+
+    let mut hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    assert_eq!(
+        hypercore.annotations(0),
+        vec![("seen_at", &b"1700000001"[..])]
+    );
+    assert_eq!(hypercore.annotations(1), vec![("spam", &[1u8][..])]);
+    assert!(hypercore.annotations(2).is_empty());
+    hypercore.annotate(0, "label", b"greeting").await?;
+    drop(hypercore);
+
+    let hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    assert_eq!(
+        hypercore.annotations(0),
+        vec![("label", &b"greeting"[..]), ("seen_at", &b"1700000001"[..])]
+    );
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_truncate_clips_local_state() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_truncate_clips_local_state")
+        .tempdir()
+        .unwrap();
+    let mut hypercore = create_hypercore(&dir.path().to_string_lossy()).await?;
+    for i in 0..6 {
+        hypercore.append(format!("#{i}").as_bytes()).await?;
+    }
+    hypercore.annotate(1, "label", b"kept").await?;
+    hypercore.annotate(4, "label", b"gone").await?;
+    hypercore.want(0, 10, 1).await?;
+    hypercore.truncate(3, 1).await?;
+    drop(hypercore);
+
+    let hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    assert_eq!(hypercore.annotations(1), vec![("label", &b"kept"[..])]);
+    assert!(hypercore.annotations(4).is_empty());
+    assert_eq!(
+        hypercore.download_progress(),
+        vec![DownloadProgress {
+            range: WantedRange {
+                start: 0,
+                end: 3,
+                priority: 1,
+            },
+            downloaded: 3,
+            next: None,
+        }]
+    );
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_bitfield_format_persists() -> Result<()> {
+    for (page_size, length) in [(1024, 10_000), (4096, 33_000)] {
+        let dir = Builder::new()
+            .prefix("hypercore_bitfield_format_persists")
+            .tempdir()
+            .unwrap();
+        let format = BitfieldFormat::new(page_size)?;
+        let storage = Storage::new_disk(&dir.path().to_owned(), true).await?;
+        let mut hypercore = HypercoreBuilder::new(storage)
+            .key_pair(get_test_key_pair())
+            .bitfield_format(format)
+            .build()
+            .await?;
+        let blocks: Vec<Vec<u8>> = (0..length).map(|i| format!("#{i}").into_bytes()).collect();
+        hypercore.append_batch(&blocks).await?;
+        hypercore.clear(10, 20).await?;
+        drop(hypercore);
+
+        let pages = (length as usize).div_ceil(page_size * 8);
+        assert_eq!(
+            std::fs::metadata(dir.path().join("bitfield"))?.len(),
+            (pages * page_size) as u64
+        );
+
+        // The format is read from the header, whatever the builder says
+        let storage = Storage::new_disk(&dir.path().to_owned(), false).await?;
+        let hypercore = HypercoreBuilder::new(storage)
+            .open(true)
+            .bitfield_format(BitfieldFormat::new(65536)?)
+            .build()
+            .await?;
+        assert_eq!(hypercore.bitfield_format(), format);
+        assert!(hypercore.has(9));
+        assert!(!hypercore.has(10));
+        assert!(!hypercore.has(19));
+        for index in [20, page_size as u64 * 8, length - 1] {
+            assert!(hypercore.has(index));
+        }
+        assert_eq!(
+            hypercore.get(length - 1).await?,
+            Some(format!("#{}", length - 1).into_bytes())
+        );
+        assert!(!hypercore.has(length));
+    }
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_destroy() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_destroy")
+        .tempdir()
+        .unwrap();
+    let core_dir = dir.path().join("core");
+    let mut hypercore = create_hypercore(&core_dir.to_string_lossy()).await?;
+    hypercore.append_batch([b"#0", b"#1", b"#2"]).await?;
+    assert!(core_dir.join("oplog").exists());
+    hypercore.destroy(true).await?;
+    assert!(!core_dir.exists());
+
+    // Files that aren't stores are kept, and so is their directory
+    let mut hypercore = create_hypercore(&core_dir.to_string_lossy()).await?;
+    hypercore.append(b"#0").await?;
+    std::fs::write(core_dir.join("notes.txt"), b"keep")?;
+    hypercore.destroy(false).await?;
+    let mut left: Vec<_> = std::fs::read_dir(&core_dir)?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<_, _>>()?;
+    left.sort();
+    assert_eq!(left, vec!["notes.txt"]);
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_truncate_persists() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_truncate_persists")
+        .tempdir()
+        .unwrap();
+    let mut hypercore = create_hypercore(&dir.path().to_string_lossy()).await?;
+    let blocks: Vec<Vec<u8>> = (0..20).map(|i| format!("#{i}").into_bytes()).collect();
+    hypercore.append_batch(&blocks).await?;
+    hypercore.truncate(7, 1).await?;
+    hypercore.append(b"#x").await?;
+    drop(hypercore);
+
+    let hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    let info = hypercore.info();
+    assert_eq!((info.length, info.fork), (8, 1));
+    assert_eq!(hypercore.get(6).await?, Some(b"#6".to_vec()));
+    assert_eq!(hypercore.get(7).await?, Some(b"#x".to_vec()));
+    assert!(!hypercore.has(8));
+    assert_eq!(
+        std::fs::metadata(dir.path().join("data"))?.len(),
+        info.byte_length
+    );
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_repairs_bitfield_of_lost_data() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_repairs_bitfield_of_lost_data")
+        .tempdir()
+        .unwrap();
+    let mut hypercore = create_hypercore(&dir.path().to_string_lossy()).await?;
+    let blocks: Vec<Vec<u8>> = (0..10).map(|i| format!("#{i}").into_bytes()).collect();
+    hypercore.append_batch(&blocks[..7]).await?;
+    hypercore.append_batch(&blocks[7..]).await?;
+    drop(hypercore);
+
+    // The writes of the last three blocks were lost in a crash, but the oplog wasn't
+    let data = std::fs::OpenOptions::new()
+        .write(true)
+        .open(dir.path().join("data"))?;
+    data.set_len(15)?;
+    drop(data);
+
+    let hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    assert_eq!(hypercore.recovery_report().cleared_blocks, vec![7..10]);
+    assert!(!hypercore.recovery_report().is_clean());
+    assert_eq!(hypercore.info().length, 10);
+    assert_eq!(hypercore.info().contiguous_length, 7);
+    assert!(hypercore.has(6));
+    assert!(!hypercore.has(7));
+    assert!(!hypercore.has(9));
+    assert_eq!(hypercore.get(6).await?, Some(b"#6".to_vec()));
+    drop(hypercore);
+
+    let hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    assert!(hypercore.recovery_report().is_clean());
+    assert_eq!(hypercore.info().contiguous_length, 7);
+    assert!(!hypercore.has(7));
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_repairs_bitfield_of_lost_tree_nodes() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_repairs_bitfield_of_lost_tree_nodes")
+        .tempdir()
+        .unwrap();
+    let mut hypercore = create_hypercore(&dir.path().to_string_lossy()).await?;
+    let blocks: Vec<Vec<u8>> = (0..10).map(|i| format!("#{i}").into_bytes()).collect();
+    hypercore.append_batch(&blocks[..5]).await?;
+    hypercore.append_batch(&blocks[5..]).await?;
+    drop(hypercore);
+
+    // The leaf node of block 3 was lost in a crash, but the oplog wasn't
+    let mut tree = std::fs::read(dir.path().join("tree"))?;
+    tree[6 * 40..7 * 40].fill(0);
+    std::fs::write(dir.path().join("tree"), tree)?;
+
+    let hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    assert_eq!(hypercore.recovery_report().cleared_blocks, vec![3..4]);
+    assert_eq!(hypercore.info().contiguous_length, 3);
+    assert!(hypercore.has(2));
+    assert!(!hypercore.has(3));
+    assert!(hypercore.has(4));
+    assert_eq!(hypercore.get(9).await?, Some(b"#9".to_vec()));
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_checks_bitfield_only_when_dirty() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_checks_bitfield_only_when_dirty")
+        .tempdir()
+        .unwrap();
+    let mut hypercore = create_hypercore(&dir.path().to_string_lossy()).await?;
+    let blocks: Vec<Vec<u8>> = (0..10).map(|i| format!("#{i}").into_bytes()).collect();
+    hypercore.append_batch(&blocks).await?;
+    drop(hypercore);
+
+    // The first append flushed the bitfield and tree, so opening trusts them without reading
+    // the data store
+    let data = std::fs::OpenOptions::new()
+        .write(true)
+        .open(dir.path().join("data"))?;
+    data.set_len(15)?;
+    drop(data);
+    let mut hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    assert!(hypercore.recovery_report().cleared_blocks.is_empty());
+    assert!(hypercore.has(9));
+
+    // Appending marks it dirty until the next flush, which only the first append after
+    // opening does right away
+    hypercore.append(b"#10").await?;
+    hypercore.append(b"#11").await?;
+    drop(hypercore);
+    let data = std::fs::OpenOptions::new()
+        .write(true)
+        .open(dir.path().join("data"))?;
+    data.set_len(23)?;
+    drop(data);
+    let hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    assert_eq!(hypercore.recovery_report().cleared_blocks, vec![11..12]);
+    assert!(hypercore.has(10));
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_reports_torn_oplog_entry() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_reports_torn_oplog_entry")
+        .tempdir()
+        .unwrap();
+    let mut hypercore = create_hypercore(&dir.path().to_string_lossy()).await?;
+    assert_eq!(hypercore.recovery_report().header_slot, None);
+    hypercore.append(b"Hello").await?;
+    hypercore.append(b"World").await?;
+    drop(hypercore);
+
+    let hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    let report = hypercore.recovery_report().clone();
+    assert!(report.is_clean());
+    assert!(report.header_slot.is_some());
+    assert_eq!(report.replayed_entries, 1);
+    drop(hypercore);
+
+    // The write of the last oplog entry was torn by a crash
+    let oplog = std::fs::OpenOptions::new()
+        .write(true)
+        .open(dir.path().join("oplog"))?;
+    oplog.set_len(oplog.metadata()?.len() - 3)?;
+    drop(oplog);
+
+    let hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    let report = hypercore.recovery_report();
+    assert!(!report.is_clean());
+    assert_eq!(report.replayed_entries, 0);
+    assert!(report.discarded_bytes > 0);
+    assert_eq!(hypercore.info().length, 1);
+    assert_eq!(hypercore.get(0).await?, Some(b"Hello".to_vec()));
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_detects_externally_modified_files() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_detects_externally_modified_files")
+        .tempdir()
+        .unwrap();
+    let mut hypercore = create_hypercore(&dir.path().to_string_lossy()).await?;
+    hypercore.set_file_watch(true)?;
+    for i in 0..5 {
+        hypercore.append(format!("#{i}").as_bytes()).await?;
+    }
+    hypercore.check_files()?;
+
+    // Another process appends to the data file
+    let mut data = std::fs::OpenOptions::new()
+        .append(true)
+        .open(dir.path().join("data"))?;
+    std::io::Write::write_all(&mut data, b"garbage")?;
+    drop(data);
+    assert!(matches!(
+        hypercore.check_files(),
+        Err(HypercoreError::ExternallyModified { stores }) if stores == [Store::Data]
+    ));
+    assert!(matches!(
+        hypercore.append(b"#5").await,
+        Err(HypercoreError::ExternallyModified { .. })
+    ));
+    drop(hypercore);
+
+    let mut hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    hypercore.set_file_watch(true)?;
+    hypercore.append(b"#5").await?;
+    hypercore.check_files()?;
+
+    // Auxiliary stores opened while watching are watched too
+    hypercore
+        .aux("test-index")
+        .await?
+        .write(0, b"index")
+        .await?;
+    hypercore.check_files()?;
+    std::fs::write(dir.path().join("aux-test-index"), b"garbage")?;
+    assert!(matches!(
+        hypercore.check_files(),
+        Err(HypercoreError::ExternallyModified { stores })
+            if stores == [Store::Aux("test-index".to_string())]
+    ));
+    hypercore.set_file_watch(false)?;
+
+    let mut memory = HypercoreBuilder::new(Storage::new_memory().await?)
+        .build()
+        .await?;
+    assert!(memory.set_file_watch(true).is_err());
+    memory.check_files()?;
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_user_data_persists() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_user_data_persists")
+        .tempdir()
+        .unwrap();
+    let mut hypercore = create_hypercore(&dir.path().to_string_lossy()).await?;
+    hypercore.set_user_data("manifest", Some(b"v1")).await?;
+    hypercore.append(b"Hello").await?;
+    hypercore.set_user_data("name", Some(b"feed")).await?;
+    hypercore.set_user_data("manifest", Some(b"v2")).await?;
+    hypercore.set_user_data("name", None).await?;
+    assert_eq!(hypercore.get_user_data("manifest"), Some(&b"v2"[..]));
+    assert_eq!(hypercore.get_user_data("name"), None);
+    drop(hypercore);
+
+    // Replayed from the oplog entries
+    let mut hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    assert_eq!(
+        hypercore.user_data().collect::<Vec<_>>(),
+        vec![("manifest", &b"v2"[..])]
+    );
+    hypercore.set_user_data("name", Some(b"feed")).await?;
+    // Enough appends to flush the oplog into the header
+    for i in 0..64 {
+        hypercore.append(format!("#{i}").as_bytes()).await?;
+    }
+    drop(hypercore);
+
+    let hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    assert_eq!(
+        hypercore.user_data().collect::<Vec<_>>(),
+        vec![("manifest", &b"v2"[..]), ("name", &b"feed"[..])]
+    );
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_open_read_only_from_public_key() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_open_read_only_from_public_key")
+        .tempdir()
+        .unwrap();
+    let public_key = {
+        let mut hypercore = create_hypercore(&dir.path().to_string_lossy()).await?;
+        hypercore.append(b"Hello").await?;
+        hypercore.key_pair().public
+    };
+
+    // The stored secret key isn't used
+    let storage = Storage::new_disk(&dir.path().to_path_buf(), false).await?;
+    let mut hypercore = Hypercore::open_read_only(storage, public_key).await?;
+    assert!(!hypercore.info().writeable);
+    assert!(hypercore.key_pair().secret.is_none());
+    assert!(matches!(
+        hypercore.append(b"World").await,
+        Err(HypercoreError::NotWritable)
+    ));
+    assert_eq!(hypercore.get(0).await?.unwrap(), b"Hello");
+    drop(hypercore);
+
+    let storage = Storage::new_disk(&dir.path().to_path_buf(), false).await?;
+    let other_key = generate_signing_key().verifying_key();
+    assert!(Hypercore::open_read_only(storage, other_key).await.is_err());
+    let mut hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    assert!(hypercore.info().writeable);
+    hypercore.append(b"World").await?;
+    Ok(())
+}
+
+#[test(async_test)]
+async fn hypercore_aux_stores_persist() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_aux_stores_persist")
+        .tempdir()
+        .unwrap();
+    let core_dir = dir.path().join("core");
+    let mut hypercore = create_hypercore(&core_dir.to_string_lossy()).await?;
+    assert!(hypercore.aux("../index").await.is_err());
+    assert!(hypercore.aux("").await.is_err());
+    let mut index = hypercore.aux("index").await?;
+    assert_eq!(index.name(), "index");
+    assert!(index.is_empty().await?);
+    index.write(0, b"0123456789").await?;
+    index.truncate(8).await?;
+    hypercore.aux("bloom").await?.write(4, b"bits").await?;
+    hypercore.sync().await?;
+    assert!(core_dir.join("aux-index").exists());
+    drop(hypercore);
+
+    let mut hypercore = open_hypercore(&core_dir.to_string_lossy()).await?;
+    let mut index = hypercore.aux("index").await?;
+    assert_eq!(index.len().await?, 8);
+    assert_eq!(index.read(2, 3).await?, b"234");
+    assert!(index.read(6, 4).await.is_err());
+    hypercore.remove_aux("index").await?;
+    assert!(!core_dir.join("aux-index").exists());
+    assert!(hypercore.aux("index").await?.is_empty().await?);
+
+    // Destroyed with the core, also when not opened
+    drop(hypercore);
+    let hypercore = open_hypercore(&core_dir.to_string_lossy()).await?;
+    hypercore.destroy(false).await?;
+    assert!(!core_dir.exists());
+    Ok(())
+}
+
+#[cfg(feature = "unsafe_raw")]
+#[test(async_test)]
+async fn storage_raw_access() -> Result<()> {
+    use hypercore::Store;
+    let dir = Builder::new()
+        .prefix("storage_raw_access")
+        .tempdir()
+        .unwrap();
+    let mut hypercore = create_hypercore(&dir.path().to_string_lossy()).await?;
+    hypercore.append_batch([b"Hello", b"World"]).await?;
+    drop(hypercore);
+
+    let mut storage = Storage::new_disk(&dir.path().to_path_buf(), false).await?;
+    assert_eq!(storage.read_raw(Store::Data, 0..10).await?, b"HelloWorld");
+    assert!(storage.read_raw(Store::Data, 5..11).await.is_err());
+    storage.write_raw(Store::Data, 0, b"J").await?;
+    drop(storage);
+
+    // Raw writes bypass verification
+    let hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    assert_eq!(hypercore.get(0).await?.unwrap(), b"Jello");
+    Ok(())
+}
+
+#[cfg(feature = "mmap")]
+#[test(async_test)]
+async fn hypercore_mmap_reads() -> Result<()> {
+    let dir = Builder::new()
+        .prefix("hypercore_mmap_reads")
+        .tempdir()
+        .unwrap();
+    let mut hypercore = create_hypercore(&dir.path().to_string_lossy()).await?;
+    let blocks: Vec<Vec<u8>> = (0..300).map(|i| format!("#{i}").into_bytes()).collect();
+    hypercore.append_batch(&blocks).await?;
+    let proofs = create_test_proofs(&mut hypercore).await?;
+    drop(hypercore);
+
+    let storage = Storage::new_disk_mmap(&dir.path().to_path_buf(), false).await?;
+    let mut hypercore = HypercoreBuilder::new(storage).open(true).build().await?;
+    assert_eq!(hypercore.get(299).await?.unwrap(), b"#299");
+    assert_eq!(create_test_proofs(&mut hypercore).await?, proofs);
+
+    // Growing and shrinking the files remaps them
+    hypercore.append(b"#300").await?;
+    assert_eq!(hypercore.get(300).await?.unwrap(), b"#300");
+    hypercore.truncate(10, 1).await?;
+    assert_eq!(hypercore.get(9).await?.unwrap(), b"#9");
+    hypercore.append(b"#x").await?;
+    assert_eq!(hypercore.get(10).await?.unwrap(), b"#x");
+    drop(hypercore);
+
+    let hypercore = open_hypercore(&dir.path().to_string_lossy()).await?;
+    assert_eq!(hypercore.info().length, 11);
+    assert_eq!(hypercore.get(10).await?.unwrap(), b"#x");
+    Ok(())
+}
@@ -125,3 +125,152 @@ async fn assert_implementation_matches_model(ops: Vec<Op>) -> bool {
     }
     true
 }
+
+/// Step of the interleavings of writes, truncations and reopening checked against the model.
+#[derive(Clone, Debug, Arbitrary)]
+enum Step {
+    Append {
+        #[proptest(strategy(block_strategy))]
+        data: Vec<u8>,
+    },
+    Truncate {
+        #[proptest(strategy(divisor_strategy))]
+        len_divisor: u8,
+    },
+    Fork,
+    Clear {
+        #[proptest(strategy(divisor_strategy))]
+        len_divisor_for_start: u8,
+        #[proptest(strategy(divisor_strategy))]
+        len_divisor_for_length: u8,
+    },
+    Get {
+        #[proptest(strategy(small_index_strategy))]
+        index: u64,
+    },
+    Reopen,
+}
+
+fn block_strategy() -> impl Strategy<Value = Vec<u8>> {
+    proptest::collection::vec(any::<u8>(), 1..64)
+}
+
+fn small_index_strategy() -> impl Strategy<Value = u64> {
+    0_u64..64
+}
+
+proptest! {
+  #![proptest_config(ProptestConfig {
+    cases: 64,
+    failure_persistence: Some(Box::new(FileFailurePersistence::WithSource("regressions"))),
+    ..Default::default()
+  })]
+
+  #[test]
+  #[cfg(feature = "async-std")]
+  fn interleavings_match_model(steps in proptest::collection::vec(any::<Step>(), 0..40)) {
+    async_std::task::block_on(assert_interleavings_match_model(steps));
+  }
+
+  #[test]
+  #[cfg(feature = "tokio")]
+  fn interleavings_match_model(steps in proptest::collection::vec(any::<Step>(), 0..40)) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(assert_interleavings_match_model(steps));
+  }
+}
+
+/// Blocks of the core, `None` where cleared, and its fork.
+#[derive(Debug, Default)]
+struct Model {
+    blocks: Vec<Option<Vec<u8>>>,
+    fork: u64,
+}
+
+/// Runs the steps on a disk core and on an in-memory reference core that is only appended to
+/// and truncated, and after every step checks the disk core against the model and its tree
+/// against the reference.
+async fn assert_interleavings_match_model(steps: Vec<Step>) {
+    use hypercore::{Hypercore, HypercoreBuilder, Storage};
+
+    let dir = tempfile::Builder::new()
+        .prefix("interleavings_match_model")
+        .tempdir()
+        .unwrap();
+    let work_dir = dir.path().to_string_lossy().to_string();
+    let mut hypercore = common::create_hypercore(&work_dir).await.unwrap();
+    let mut reference = HypercoreBuilder::new(Storage::new_memory().await.unwrap())
+        .key_pair(common::get_test_key_pair())
+        .build()
+        .await
+        .unwrap();
+    let mut model = Model::default();
+
+    for step in steps {
+        let length = model.blocks.len() as u64;
+        match step {
+            Step::Append { data } => {
+                hypercore.append(&data).await.unwrap();
+                reference.append(&data).await.unwrap();
+                model.blocks.push(Some(data));
+            }
+            Step::Truncate { len_divisor } => {
+                let new_length = length - length / len_divisor as u64;
+                model.fork += 1;
+                hypercore.truncate(new_length, model.fork).await.unwrap();
+                reference.truncate(new_length, model.fork).await.unwrap();
+                model.blocks.truncate(new_length as usize);
+            }
+            Step::Fork => {
+                model.fork += 1;
+                hypercore.truncate(length, model.fork).await.unwrap();
+                reference.truncate(length, model.fork).await.unwrap();
+            }
+            Step::Clear {
+                len_divisor_for_start,
+                len_divisor_for_length,
+            } => {
+                let start = length / len_divisor_for_start as u64;
+                let end = (start + length / len_divisor_for_length as u64).min(length);
+                hypercore.clear(start, end).await.unwrap();
+                model.blocks[start as usize..end as usize].fill(None);
+            }
+            Step::Get { index } => {
+                let expected = model.blocks.get(index as usize).cloned().flatten();
+                assert_eq!(hypercore.get(index).await.unwrap(), expected);
+            }
+            Step::Reopen => {
+                drop(hypercore);
+                hypercore = common::open_hypercore(&work_dir).await.unwrap();
+            }
+        }
+        assert_matches_model(&hypercore, &reference, &model);
+    }
+
+    // Whatever the steps, the core reopens to the same state
+    drop(hypercore);
+    let hypercore: Hypercore = common::open_hypercore(&work_dir).await.unwrap();
+    assert_matches_model(&hypercore, &reference, &model);
+}
+
+fn assert_matches_model(
+    hypercore: &hypercore::Hypercore,
+    reference: &hypercore::Hypercore,
+    model: &Model,
+) {
+    let info = hypercore.info();
+    let length = model.blocks.len() as u64;
+    assert_eq!((info.length, info.fork), (length, model.fork));
+    assert_eq!(info.byte_length, reference.info().byte_length);
+    assert_eq!(hypercore.checkpoint(), reference.checkpoint());
+    let contiguous = model
+        .blocks
+        .iter()
+        .take_while(|block| block.is_some())
+        .count() as u64;
+    assert_eq!(info.contiguous_length, contiguous);
+    for (index, block) in model.blocks.iter().enumerate() {
+        assert_eq!(hypercore.has(index as u64), block.is_some(), "has({index})");
+    }
+    assert!(!hypercore.has(length));
+}
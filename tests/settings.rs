@@ -0,0 +1,33 @@
+use anyhow::Result;
+use hypercore::{generate_signing_key, Settings};
+use tempfile::Builder;
+use test_log::test;
+
+#[cfg(feature = "async-std")]
+use async_std::test as async_test;
+#[cfg(feature = "tokio")]
+use tokio::test as async_test;
+
+#[test(async_test)]
+async fn settings_persist() -> Result<()> {
+    let dir = Builder::new().prefix("settings_persist").tempdir().unwrap();
+    let core_key = Settings::core_key(&generate_signing_key().verifying_key(), "sync_mode");
+    {
+        let mut settings = Settings::open_disk(dir.path()).await?;
+        settings.set("cache.max_capacity", 1024 * 1024).await?;
+        settings.set("bandwidth.upload_limit", 64 * 1024).await?;
+        settings.set("sparse", true).await?;
+        settings.set(&core_key, "sparse").await?;
+        settings.set("bandwidth.upload_limit", 128 * 1024).await?;
+        assert!(settings.remove("sparse").await?);
+        assert!(!settings.remove("sparse").await?);
+    }
+    let settings = Settings::open_disk(dir.path()).await?;
+    assert_eq!(settings.get_u64("cache.max_capacity"), Some(1024 * 1024));
+    assert_eq!(settings.get_u64("bandwidth.upload_limit"), Some(128 * 1024));
+    assert_eq!(settings.get_bool("sparse"), None);
+    assert_eq!(settings.get_str(&core_key), Some("sparse"));
+    assert_eq!(settings.get_u64(&core_key), None);
+    assert_eq!(settings.iter().count(), 3);
+    Ok(())
+}